@@ -127,7 +127,14 @@ if [[ "$SENTRY_COUNT" -eq 3 ]]; then
   copy_bin "sentry-blue"
 fi
 
-"$BIN_DIR/sentry-omega" build --bins-dir "$BIN_DIR" --releases-dir "$RELEASES_DIR" --release-id "${OMEGA_RELEASE_ID:-omega-dev}"
+# Record the git commit as build provenance when this checkout has one; builds from a plain
+# source snapshot simply leave it out.
+PROVENANCE_ARGS=()
+if SOURCE_REV="$(git -C "$ROOT_DIR" rev-parse HEAD 2>/dev/null)"; then
+  PROVENANCE_ARGS+=(--source-rev "$SOURCE_REV")
+fi
+
+"$BIN_DIR/sentry-omega" build --bins-dir "$BIN_DIR" --releases-dir "$RELEASES_DIR" --release-id "${OMEGA_RELEASE_ID:-auto}" ${PROVENANCE_ARGS[@]+"${PROVENANCE_ARGS[@]}"}
 
 echo "Binaries staged in $BIN_DIR"
 echo "Release artifacts updated in $RELEASES_DIR"
@@ -0,0 +1,302 @@
+//! The log forwarder: queue lines in, batched Discord messages out.
+//!
+//! Bard's Python modules (and, with `--watch`, other bots) append lines to a `gateway_queue.log`.
+//! Sending each line to Discord on its own would hit the rate limit quickly and bury the logging
+//! channel, so the forwarder works in three steps:
+//!
+//! 1. **Parse.** `parse_line` reads `level|source|message`, for example
+//!    `warn|squire|Spool is 80% full`. Lines from Bard's Python logger look like
+//!    `[2026-10-16T09:30:00Z] ERROR: message` and are understood too. Anything else is kept
+//!    whole as an `info` line from the file's owner, so nothing is dropped for being unusual.
+//! 2. **Filter.** Lines below `BARD_MIN_LEVEL` (`debug`, `info`, `warn`, `error`; default `info`)
+//!    are skipped.
+//! 3. **Batch.** A `Batcher` collects up to `MAX_BATCH_MESSAGES` (10) messages and sends them
+//!    together once it is full or `MAX_BATCH_WAIT` (5 seconds) after the first one arrived,
+//!    whichever comes first. The same line arriving again right after itself is not added twice:
+//!    the earlier one gets a count instead and is shown as `... (x3)`.
+//!
+//! A `Batch` becomes one line in the outbox (`Discovery/bard_outbox.log`), shaped like Squire's
+//! `OutboundMessage`: `{"channel_id":"123...","body":"{\"content\":\"...\"}"}`. The channel comes
+//! from `BARD_LOG_CHANNEL`; without it `channel_id` is `null` and the gateway that flushes the
+//! outbox picks its own logging channel. Bard itself never opens a network connection.
+//!
+//! Time comes in as an `Instant` argument, so a `runtime::ManualClock` can play through the
+//! five-second limit without waiting.
+
+use std::time::{Duration, Instant};
+
+use crate::log::Level;
+
+/// Most messages in one batch.
+pub const MAX_BATCH_MESSAGES: usize = 10;
+/// Longest a message waits for others to join its batch.
+pub const MAX_BATCH_WAIT: Duration = Duration::from_secs(5);
+/// Longest `content` Discord accepts, in characters.
+pub const MAX_CONTENT_CHARS: usize = 2000;
+/// Environment variable with the lowest level that is forwarded.
+pub const MIN_LEVEL_ENV: &str = "BARD_MIN_LEVEL";
+/// Environment variable with the Discord channel id for the outbox lines.
+pub const CHANNEL_ENV: &str = "BARD_LOG_CHANNEL";
+
+/// One queue line, understood.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub level: Level,
+    /// Who wrote it: the middle field of `level|source|message`, or the owner of the queue file.
+    pub source: String,
+    pub message: String,
+}
+
+/// Read one queue line. `default_source` names the file's owner and is used when the line does
+/// not say who wrote it. Never fails: an unstructured line becomes an `info` line as it is.
+pub fn parse_line(line: &str, default_source: &str) -> LogLine {
+    let line = line.trim();
+    // `level|source|message`; the message itself may hold more `|`.
+    let mut parts = line.splitn(3, '|');
+    if let (Some(level), Some(source), Some(message)) = (parts.next(), parts.next(), parts.next()) {
+        if let Some(level) = Level::parse(level) {
+            let source = if source.trim().is_empty() { default_source } else { source.trim() };
+            return LogLine { level, source: source.to_string(), message: message.trim().to_string() };
+        }
+    }
+    // `[timestamp] LEVEL: message` from `python/core/logger.py`.
+    if let Some((level, message)) = line.strip_prefix('[').and_then(|rest| rest.split_once("] ")).and_then(|(_, rest)| rest.split_once(": ")) {
+        if let Some(level) = Level::parse(level) {
+            return LogLine { level, source: default_source.to_string(), message: message.trim().to_string() };
+        }
+    }
+    LogLine { level: Level::Info, source: default_source.to_string(), message: line.to_string() }
+}
+
+/// `BARD_MIN_LEVEL` as a level; `None` (unset or empty) means `info`.
+pub fn min_level(raw: Option<&str>) -> Result<Level, String> {
+    match raw.map(str::trim).filter(|raw| !raw.is_empty()) {
+        None => Ok(Level::Info),
+        Some(raw) => Level::parse(raw).ok_or_else(|| format!("{MIN_LEVEL_ENV} must be debug, info, warn, or error, got {raw:?}")),
+    }
+}
+
+/// A line waiting in a batch, with how many times it arrived in a row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchEntry {
+    pub line: LogLine,
+    pub count: u32,
+}
+
+/// Messages sent together as one Discord message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    pub entries: Vec<BatchEntry>,
+}
+
+impl Batch {
+    /// The message text: one line per entry, such as `[warn] squire: Spool is 80% full (x3)`.
+    /// Cut to Discord's 2000 characters, with a note saying how much was left out.
+    pub fn content(&self) -> String {
+        let text = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let repeats = if entry.count > 1 { format!(" (x{})", entry.count) } else { String::new() };
+                format!("[{}] {}: {}{}", entry.line.level.as_str(), entry.line.source, entry.line.message, repeats)
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let total = text.chars().count();
+        if total <= MAX_CONTENT_CHARS {
+            return text;
+        }
+        // Leave room for the note; 40 characters fit `\n...[cut <any count> chars]`.
+        let keep = MAX_CONTENT_CHARS - 40;
+        format!("{}\n...[cut {} chars]", text.chars().take(keep).collect::<String>(), total - keep)
+    }
+
+    /// The outbox line: `{"channel_id":...,"body":"{\"content\":...}"}`.
+    pub fn to_outbox_json(&self, channel_id: Option<&str>) -> String {
+        let body = format!("{{\"content\":\"{}\"}}", json_escape(&self.content()));
+        let channel = match channel_id {
+            Some(channel_id) => format!("\"{}\"", json_escape(channel_id)),
+            None => "null".to_string(),
+        };
+        format!("{{\"channel_id\":{},\"body\":\"{}\"}}", channel, json_escape(&body))
+    }
+}
+
+/// Collects lines into batches by count and by time, folding repeats together.
+#[derive(Debug, Clone)]
+pub struct Batcher {
+    max_messages: usize,
+    max_wait: Duration,
+    pending: Vec<BatchEntry>,
+    /// When the first pending line arrived.
+    started: Option<Instant>,
+}
+
+impl Default for Batcher {
+    fn default() -> Self {
+        Self::new(MAX_BATCH_MESSAGES, MAX_BATCH_WAIT)
+    }
+}
+
+impl Batcher {
+    /// At most `max_messages` per batch (at least 1), sent no later than `max_wait` after the first.
+    pub fn new(max_messages: usize, max_wait: Duration) -> Self {
+        Self { max_messages: max_messages.max(1), max_wait, pending: Vec::new(), started: None }
+    }
+
+    /// Lines waiting to be sent; a repeated line counts once.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Add a line that arrived at `now`. Returns the batch when this line filled it.
+    pub fn push(&mut self, line: LogLine, now: Instant) -> Option<Batch> {
+        if let Some(last) = self.pending.last_mut().filter(|last| last.line == line) {
+            last.count = last.count.saturating_add(1);
+            return None;
+        }
+        self.started.get_or_insert(now);
+        self.pending.push(BatchEntry { line, count: 1 });
+        if self.pending.len() >= self.max_messages {
+            return self.flush();
+        }
+        None
+    }
+
+    /// The batch, once its first line has waited `max_wait`.
+    pub fn due(&mut self, now: Instant) -> Option<Batch> {
+        let started = self.started?;
+        if now.saturating_duration_since(started) >= self.max_wait {
+            return self.flush();
+        }
+        None
+    }
+
+    /// Whatever is pending, right now (used when stopping).
+    pub fn flush(&mut self) -> Option<Batch> {
+        self.started = None;
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(Batch { entries: std::mem::take(&mut self.pending) })
+    }
+}
+
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Clock, ManualClock};
+
+    fn line(level: Level, message: &str) -> LogLine {
+        LogLine { level, source: "squire".to_string(), message: message.to_string() }
+    }
+
+    #[test]
+    fn structured_python_and_unstructured_lines() {
+        assert_eq!(parse_line("warn|squire|Spool is 80% full", "bard"), line(Level::Warn, "Spool is 80% full"));
+        assert_eq!(parse_line(" ERROR | squire | a|b|c ", "bard"), line(Level::Error, "a|b|c"), "the message keeps its own |");
+        assert_eq!(parse_line("info||no source given", "bard").source, "bard");
+        let python = parse_line("[2026-10-16T09:30:00Z] WARNING: disk almost full", "bard");
+        assert_eq!((python.level, python.source.as_str(), python.message.as_str()), (Level::Warn, "bard", "disk almost full"));
+
+        for raw in ["just some text", "loud|squire|not a level", "[yesterday] NOTICE: odd level"] {
+            let parsed = parse_line(raw, "bard");
+            assert_eq!((parsed.level, parsed.source.as_str(), parsed.message.as_str()), (Level::Info, "bard", raw), "{raw}");
+        }
+    }
+
+    #[test]
+    fn the_minimum_level_defaults_to_info() {
+        assert_eq!(min_level(None), Ok(Level::Info));
+        assert_eq!(min_level(Some("  ")), Ok(Level::Info));
+        assert_eq!(min_level(Some("WARN")), Ok(Level::Warn));
+        assert_eq!(min_level(Some("debug")), Ok(Level::Debug));
+        assert!(min_level(Some("loud")).unwrap_err().starts_with("BARD_MIN_LEVEL must be debug, info, warn, or error"));
+        assert!(Level::Debug < Level::Info && Level::Warn < Level::Error);
+    }
+
+    #[test]
+    fn a_batch_leaves_when_full() {
+        let clock = ManualClock::new(0);
+        let mut batcher = Batcher::new(3, MAX_BATCH_WAIT);
+        assert_eq!(batcher.push(line(Level::Info, "one"), clock.instant()), None);
+        assert_eq!(batcher.push(line(Level::Info, "two"), clock.instant()), None);
+        let batch = batcher.push(line(Level::Info, "three"), clock.instant()).unwrap();
+        assert_eq!(batch.entries.len(), 3);
+        assert_eq!(batcher.pending(), 0);
+        assert_eq!(batcher.due(clock.instant()), None, "a sent batch does not leave twice");
+        assert_eq!(Batcher::default().max_messages, MAX_BATCH_MESSAGES);
+    }
+
+    #[test]
+    fn a_batch_leaves_once_its_first_line_has_waited() {
+        let clock = ManualClock::new(0);
+        let mut batcher = Batcher::default();
+        assert_eq!(batcher.due(clock.instant()), None, "nothing pending");
+        batcher.push(line(Level::Info, "first"), clock.instant());
+        clock.advance(Duration::from_secs(3));
+        batcher.push(line(Level::Info, "second"), clock.instant());
+        clock.advance(Duration::from_millis(1_999));
+        assert_eq!(batcher.due(clock.instant()), None);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(batcher.due(clock.instant()).unwrap().entries.len(), 2, "five seconds after the first, not the second");
+
+        // The next batch starts its own wait.
+        batcher.push(line(Level::Info, "third"), clock.instant());
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(batcher.due(clock.instant()), None);
+        assert_eq!(batcher.flush().unwrap().entries.len(), 1);
+        assert_eq!(batcher.flush(), None);
+    }
+
+    #[test]
+    fn repeats_in_a_row_are_counted_not_added() {
+        let clock = ManualClock::new(0);
+        let mut batcher = Batcher::default();
+        for _ in 0..4 {
+            assert_eq!(batcher.push(line(Level::Warn, "spool full"), clock.instant()), None);
+        }
+        batcher.push(line(Level::Info, "other"), clock.instant());
+        batcher.push(line(Level::Warn, "spool full"), clock.instant());
+        assert_eq!(batcher.pending(), 3);
+        let batch = batcher.flush().unwrap();
+        let counts: Vec<u32> = batch.entries.iter().map(|entry| entry.count).collect();
+        assert_eq!(counts, [4, 1, 1], "only consecutive repeats fold");
+        assert_eq!(batch.content(), "[warn] squire: spool full (x4)\n[info] squire: other\n[warn] squire: spool full");
+        // Same text at another level is a different line.
+        batcher.push(line(Level::Warn, "x"), clock.instant());
+        batcher.push(line(Level::Error, "x"), clock.instant());
+        assert_eq!(batcher.pending(), 2);
+    }
+
+    #[test]
+    fn content_is_cut_to_discords_limit_and_escaped_for_the_outbox() {
+        let long = Batch { entries: (0..30).map(|n| BatchEntry { line: line(Level::Info, &format!("{n:03} {}", "x".repeat(100))), count: 1 }).collect() };
+        let content = long.content();
+        assert!(content.chars().count() <= MAX_CONTENT_CHARS);
+        assert!(content.ends_with(" chars]") && content.contains("\n...[cut "));
+
+        let batch = Batch { entries: vec![BatchEntry { line: line(Level::Error, "said \"no\"\tthen left"), count: 2 }] };
+        assert_eq!(
+            batch.to_outbox_json(Some("123")),
+            r#"{"channel_id":"123","body":"{\"content\":\"[error] squire: said \\\"no\\\"\\tthen left (x2)\"}"}"#
+        );
+        assert!(batch.to_outbox_json(None).starts_with("{\"channel_id\":null,"));
+    }
+}
@@ -0,0 +1,17 @@
+//! Bard's Rust side as a library.
+//!
+//! `forwarder` turns queue lines into batched Discord messages: it parses `level|source|message`
+//! lines, filters them by level, folds repeats into `(xN)`, and batches by count and time.
+//! `service` wires that to the files in Bard's `Discovery/` folder: the queues it reads, the
+//! cursors that remember its place, and the outbox the batches go to. The `bard-gateway` binary
+//! in `src/main.rs` runs it in a loop.
+//!
+//! `log`, `atomic`, `lockfile`, `queue_file`, and `runtime` are re-exported from `ecosystem-common`,
+//! shared with the hub: the shared logger, whole-file replacement, the lock files
+//! every writer of a `Discovery/` file takes, line caps and rotation for queue files, and the
+//! clock and sleeping behind traits so the loop can run on a `runtime::ManualClock`.
+
+pub mod forwarder;
+pub mod service;
+
+pub use ecosystem_common::{atomic, lockfile, log, queue_file, runtime};
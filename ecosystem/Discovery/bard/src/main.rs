@@ -1,26 +1,97 @@
-//! Bard gateway placeholder binary.
-//! It mirrors Squire’s Cargo target so offline builds produce a logging-ready stub even before
-//! the real gateway lands.
+//! Bard gateway: forwards log lines from `Discovery/gateway_queue.log` (and any `--watch` queues)
+//! to `Discovery/bard_outbox.log` as batched Discord messages.
+//!
+//! It runs until the stop file appears (by default `Discovery/bard.stop`); `--once` reads
+//! everything new, writes it out, and exits, which suits cron jobs and quick checks. Sending the
+//! outbox to Discord is left to a gateway that holds a bot token; Bard never opens a socket.
 
-use std::fs;
+use std::env;
 use std::path::PathBuf;
+use std::process;
+
+use bard_gateway::forwarder::{self, CHANNEL_ENV, MIN_LEVEL_ENV};
+use bard_gateway::log::Logger;
+use bard_gateway::runtime::{EnvSource, ProcessEnv, Runtime};
+use bard_gateway::service::{self, BardLayout, Forwarder, DEFAULT_STOP_FILE_NAME};
+
+const USAGE: &str = "usage: bard-gateway [--root <bard folder>] [--watch <queue file>]... [--once] [--stop-file <path>]";
+
+const LOG: Logger = Logger::new("bard");
+
+/// Parsed command line.
+struct Options {
+    root: PathBuf,
+    watch: Vec<PathBuf>,
+    once: bool,
+    stop_file: Option<PathBuf>,
+}
 
 fn main() {
-    let working_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-    let discovery_path = working_dir.join("Discovery");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_options(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            process::exit(1);
+        }
+    };
 
-    if let Err(error) = fs::create_dir_all(&discovery_path) {
-        eprintln!("Bard could not prepare Discovery/: {error}");
-        std::process::exit(1);
-    }
+    let min_level = match forwarder::min_level(ProcessEnv.var(MIN_LEVEL_ENV).as_deref()) {
+        Ok(level) => level,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+    // Discord channel ids are numbers; anything else would only be refused later.
+    let channel_id = ProcessEnv.var(CHANNEL_ENV).map(|raw| raw.trim().to_string()).filter(|raw| !raw.is_empty());
+    let channel_id = match channel_id {
+        Some(id) if !id.chars().all(|ch| ch.is_ascii_digit()) => {
+            LOG.warn("Ignoring a channel id that is not a number; outbox lines carry no channel", &[("variable", CHANNEL_ENV)]);
+            None
+        }
+        other => other,
+    };
 
-    let log_path = discovery_path.join("gateway_queue.log");
-    let note = "Bard gateway placeholder initialized for offline Cargo builds.\n";
-    if let Err(error) = fs::write(&log_path, note) {
-        eprintln!("Bard could not write gateway queue: {error}");
-        std::process::exit(1);
-    }
+    let layout = BardLayout::of(&options.root);
+    let stop_file = options.stop_file.clone().unwrap_or_else(|| layout.discovery.join(DEFAULT_STOP_FILE_NAME));
+    let mut forwarder = Forwarder::new(&layout, &options.watch, min_level, channel_id);
+    LOG.info(
+        "Bard forwarding logs",
+        &[
+            ("queues", &(options.watch.len() + 1).to_string()),
+            ("min_level", min_level.as_str()),
+            ("outbox", &layout.outbox_file.display().to_string()),
+        ],
+    );
+    let summary = service::run(&mut forwarder, &stop_file, options.once, Runtime::system());
+    LOG.info(
+        "Bard stopped",
+        &[
+            ("polls", &summary.polls.to_string()),
+            ("read", &summary.read.to_string()),
+            ("filtered", &summary.filtered.to_string()),
+            ("batches", &summary.batches.to_string()),
+        ],
+    );
+}
 
-    println!("Bard gateway stub ready at {:?}", log_path);
-    println!("Use this as a teaching aid while keeping Discord communication anchored in Rust.");
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    // The Bard folder is the current directory unless `--root` says otherwise, like the hub.
+    let current_dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let absolute = |path: PathBuf| if path.is_absolute() { path } else { current_dir.join(path) };
+    let mut options = Options { root: current_dir.clone(), watch: Vec::new(), once: false, stop_file: None };
+
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        let mut value = |name: &str| iter.next().cloned().ok_or_else(|| format!("{name} needs a value"));
+        match flag.as_str() {
+            "--root" => options.root = absolute(PathBuf::from(value("--root")?)),
+            "--watch" => options.watch.push(absolute(PathBuf::from(value("--watch")?))),
+            "--once" => options.once = true,
+            "--stop-file" => options.stop_file = Some(absolute(PathBuf::from(value("--stop-file")?))),
+            other => return Err(format!("Unknown argument {other:?}")),
+        }
+    }
+    Ok(options)
 }
@@ -0,0 +1,354 @@
+//! The files around the forwarder: which queues Bard reads, where it remembers its place, and
+//! where the batches go.
+//!
+//! Everything lives in Bard's own `Discovery/` folder:
+//! - `gateway_queue.log` is Bard's queue, always watched. Bard is its only reader of log lines, so
+//!   it also rotates the file when it is full (see `queue_file`). Lines starting with `to=` are
+//!   messages for other bots and are left for the hub's router.
+//! - `--watch <path>` adds other bots' queues. Bard only reads them; their owners rotate them.
+//! - `bard_cursors/` holds one `QueueCursor` per watched file, so a restart continues where the
+//!   last run stopped instead of forwarding everything again.
+//! - `bard_outbox.log` receives one line per batch (see `forwarder::Batch::to_outbox_json`).
+//!
+//! Cursors are saved only when no line is waiting in the batcher, so every line that was read is
+//! in the outbox by the time its cursor moves. A crash in between forwards the last few seconds
+//! again on the next start rather than losing them.
+//!
+//! `run` polls every `POLL_INTERVAL` until the stop file appears (by default
+//! `Discovery/bard.stop`, the same convention as the hub's `hub.stop`), then writes what is
+//! still pending and removes the stop file.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::atomic::atomic_write;
+use crate::forwarder::{parse_line, Batch, Batcher};
+use crate::log::{Level, Logger};
+use crate::queue_file::{QueueCursor, QueueFile};
+use crate::runtime::Runtime;
+
+/// Bard's queue inside `Discovery/`.
+pub const QUEUE_FILE_NAME: &str = "gateway_queue.log";
+/// Where batches are written, inside `Discovery/`.
+pub const OUTBOX_FILE_NAME: &str = "bard_outbox.log";
+/// Folder for the read positions, inside `Discovery/`.
+pub const CURSOR_DIR_NAME: &str = "bard_cursors";
+/// Stop file inside `Discovery/`, used when `--stop-file` is not given.
+pub const DEFAULT_STOP_FILE_NAME: &str = "bard.stop";
+/// Time between reads of the queues. Short next to `MAX_BATCH_WAIT`, so a batch leaves on time.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+const LOG: Logger = Logger::new("bard");
+
+/// Where Bard's files are, derived from the Bard folder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BardLayout {
+    /// `<bard>/Discovery`.
+    pub discovery: PathBuf,
+    pub queue_file: PathBuf,
+    pub outbox_file: PathBuf,
+    pub cursor_dir: PathBuf,
+}
+
+impl BardLayout {
+    pub fn of(bard_root: &Path) -> Self {
+        let discovery = bard_root.join("Discovery");
+        Self {
+            queue_file: discovery.join(QUEUE_FILE_NAME),
+            outbox_file: discovery.join(OUTBOX_FILE_NAME),
+            cursor_dir: discovery.join(CURSOR_DIR_NAME),
+            discovery,
+        }
+    }
+}
+
+/// One queue file being read.
+#[derive(Debug, Clone)]
+struct Source {
+    queue: QueueFile,
+    /// Who wrote lines that do not say so: `bard`, or the folder holding the watched file's
+    /// `Discovery/` (for `squire/Discovery/gateway_queue.log`, `squire`).
+    owner: String,
+    /// Bard rotates only its own queue.
+    owned: bool,
+    cursor_file: PathBuf,
+    /// Where the last read stopped; saved to `cursor_file` when nothing is pending.
+    cursor: QueueCursor,
+    saved: QueueCursor,
+}
+
+/// What one `poll` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PollReport {
+    /// New lines read from all queues.
+    pub read: usize,
+    /// Lines below the minimum level, skipped.
+    pub filtered: usize,
+    /// Batches written to the outbox.
+    pub batches: usize,
+}
+
+/// Reads the queues, batches their lines, and writes the outbox.
+#[derive(Debug)]
+pub struct Forwarder {
+    sources: Vec<Source>,
+    batcher: Batcher,
+    min_level: Level,
+    channel_id: Option<String>,
+    outbox: QueueFile,
+}
+
+impl Forwarder {
+    /// Bard's own queue plus every path in `watch`. Cursors from an earlier run are picked up.
+    pub fn new(layout: &BardLayout, watch: &[PathBuf], min_level: Level, channel_id: Option<String>) -> Self {
+        let mut sources = vec![Source::open(layout, layout.queue_file.clone(), "bard".to_string(), true)];
+        for path in watch {
+            sources.push(Source::open(layout, path.clone(), owner_of(path), false));
+        }
+        Self { sources, batcher: Batcher::default(), min_level, channel_id, outbox: QueueFile::new(layout.outbox_file.clone()) }
+    }
+
+    /// Swap the batcher, for other batch sizes or waits.
+    pub fn with_batcher(mut self, batcher: Batcher) -> Self {
+        self.batcher = batcher;
+        self
+    }
+
+    /// Read new lines from every queue and write every batch that is full or has waited long
+    /// enough at `now`.
+    pub fn poll(&mut self, now: Instant) -> PollReport {
+        let mut report = PollReport::default();
+        let mut batches = Vec::new();
+        for source in &mut self.sources {
+            let batch = match source.queue.read_from(source.cursor) {
+                // A queue that does not exist yet reads as empty.
+                Ok(batch) => batch,
+                Err(err) => {
+                    LOG.warn("Could not read queue", &[("file", &source.queue.path().display().to_string()), ("error", &err.to_string())]);
+                    continue;
+                }
+            };
+            source.cursor = batch.cursor;
+            for raw in &batch.lines {
+                let raw = raw.trim();
+                if raw.is_empty() || raw.starts_with("to=") {
+                    continue;
+                }
+                report.read += 1;
+                let line = parse_line(raw, &source.owner);
+                if line.level < self.min_level {
+                    report.filtered += 1;
+                    continue;
+                }
+                batches.extend(self.batcher.push(line, now));
+            }
+        }
+        batches.extend(self.batcher.due(now));
+        report.batches = self.write(batches);
+        report
+    }
+
+    /// Write whatever is pending, for shutdown.
+    pub fn flush(&mut self) -> usize {
+        let batch = self.batcher.flush();
+        self.write(batch.into_iter().collect())
+    }
+
+    /// Append the batches to the outbox, then save cursors if nothing is left pending.
+    fn write(&mut self, batches: Vec<Batch>) -> usize {
+        let mut written = 0;
+        for batch in &batches {
+            match self.outbox.append(&batch.to_outbox_json(self.channel_id.as_deref())) {
+                Ok(()) => written += 1,
+                Err(err) => {
+                    // Keep the cursors where they are, so these lines are read again next start.
+                    LOG.error("Could not write to the outbox", &[("file", &self.outbox.path().display().to_string()), ("error", &err.to_string())]);
+                    return written;
+                }
+            }
+        }
+        if self.batcher.pending() == 0 {
+            for source in &mut self.sources {
+                source.save();
+            }
+        }
+        if let Err(err) = self.outbox.rotate_if_full() {
+            LOG.warn("Could not rotate the outbox", &[("error", &err.to_string())]);
+        }
+        written
+    }
+}
+
+/// Totals over a whole `run`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    pub polls: u64,
+    pub read: usize,
+    pub filtered: usize,
+    pub batches: usize,
+}
+
+/// Poll until `stop_file` appears (or once, with `once`), then write what is pending. Time and
+/// waiting come from `rt`.
+pub fn run(forwarder: &mut Forwarder, stop_file: &Path, once: bool, rt: Runtime<'_>) -> RunSummary {
+    let mut summary = RunSummary::default();
+    loop {
+        let report = forwarder.poll(rt.clock.instant());
+        summary.polls += 1;
+        summary.read += report.read;
+        summary.filtered += report.filtered;
+        summary.batches += report.batches;
+        if once || stop_requested(stop_file) {
+            break;
+        }
+        rt.sleeper.sleep(POLL_INTERVAL);
+    }
+    summary.batches += forwarder.flush();
+    summary
+}
+
+/// Check for the stop file and remove it so the next start is not stopped straight away.
+fn stop_requested(stop_file: &Path) -> bool {
+    if stop_file.exists() {
+        let _ = fs::remove_file(stop_file);
+        LOG.info("Stop file found", &[("path", &stop_file.display().to_string())]);
+        return true;
+    }
+    false
+}
+
+impl Source {
+    fn open(layout: &BardLayout, path: PathBuf, owner: String, owned: bool) -> Self {
+        let cursor_file = layout.cursor_dir.join(format!("{}.offset", cursor_name(&path)));
+        let cursor = fs::read_to_string(&cursor_file).ok().and_then(|raw| QueueCursor::parse(&raw)).unwrap_or_default();
+        Self { queue: QueueFile::new(path), owner, owned, cursor_file, saved: cursor, cursor }
+    }
+
+    fn save(&mut self) {
+        if self.cursor != self.saved {
+            let saved = fs::create_dir_all(self.cursor_file.parent().unwrap_or(Path::new(".")))
+                .and_then(|_| atomic_write(&self.cursor_file, format!("{}\n", self.cursor.to_state()).as_bytes()));
+            match saved {
+                Ok(()) => self.saved = self.cursor,
+                Err(err) => LOG.error("Could not save queue position", &[("file", &self.cursor_file.display().to_string()), ("error", &err.to_string())]),
+            }
+        }
+        if self.owned {
+            if let Err(err) = self.queue.rotate_if_full() {
+                LOG.warn("Could not rotate the queue", &[("error", &err.to_string())]);
+            }
+        }
+    }
+}
+
+/// The bot a watched queue belongs to: the folder above its `Discovery/`, else the file's stem.
+fn owner_of(path: &Path) -> String {
+    let parent = path.parent();
+    let bot = match parent.and_then(Path::file_name) {
+        Some(name) if name == "Discovery" => parent.and_then(Path::parent).and_then(Path::file_name),
+        _ => None,
+    };
+    bot.or_else(|| path.file_stem()).map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| "unknown".to_string())
+}
+
+/// A file name for a queue's cursor: the path with everything but letters, digits, `.`, `-`, and
+/// `_` replaced by `_`.
+fn cursor_name(path: &Path) -> String {
+    path.to_string_lossy()
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || matches!(ch, '.' | '-' | '_') { ch } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forwarder::MAX_BATCH_WAIT;
+    use crate::runtime::{Clock, ManualClock, MapEnv};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bard-service-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn append(path: &Path, lines: &[&str]) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut text = fs::read_to_string(path).unwrap_or_default();
+        for line in lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+        fs::write(path, text).unwrap();
+    }
+
+    fn outbox(layout: &BardLayout) -> Vec<String> {
+        fs::read_to_string(&layout.outbox_file).unwrap_or_default().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn polling_filters_batches_and_skips_routed_lines() {
+        let root = temp_dir("poll");
+        let layout = BardLayout::of(&root);
+        let clock = ManualClock::new(0);
+        let mut forwarder = Forwarder::new(&layout, &[], Level::Warn, Some("42".to_string()));
+        assert_eq!(forwarder.poll(clock.instant()), PollReport::default(), "a missing queue reads as empty");
+
+        append(&layout.queue_file, &["debug|bard|noise", "info|bard|chatter", "to=squire hello", "", "warn|bard|disk 90%", "error|bard|disk full"]);
+        assert_eq!(forwarder.poll(clock.instant()), PollReport { read: 4, filtered: 2, batches: 0 });
+        assert!(outbox(&layout).is_empty(), "waiting for more lines");
+
+        clock.advance(MAX_BATCH_WAIT);
+        assert_eq!(forwarder.poll(clock.instant()).batches, 1);
+        let lines = outbox(&layout);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("{\"channel_id\":\"42\",") && lines[0].contains("[warn] bard: disk 90%\\\\n[error] bard: disk full"), "{}", lines[0]);
+    }
+
+    #[test]
+    fn cursors_let_a_restart_continue_where_it_stopped() {
+        let root = temp_dir("cursors");
+        let layout = BardLayout::of(&root);
+        let watched = root.join("squire/Discovery/gateway_queue.log");
+        let clock = ManualClock::new(0);
+        append(&layout.queue_file, &["info|bard|one"]);
+        append(&watched, &["unstructured squire line"]);
+
+        let mut first = Forwarder::new(&layout, std::slice::from_ref(&watched), Level::Info, None);
+        assert_eq!(first.poll(clock.instant()).read, 2);
+        assert_eq!(first.flush(), 1);
+        assert!(outbox(&layout)[0].contains("[info] squire: unstructured squire line"), "owner from the folder above Discovery/");
+        assert_eq!(fs::read_dir(&layout.cursor_dir).unwrap().count(), 2);
+
+        append(&layout.queue_file, &["info|bard|two"]);
+        let mut second = Forwarder::new(&layout, &[watched], Level::Info, None);
+        assert_eq!(second.poll(clock.instant()).read, 1, "only the new line");
+        assert_eq!(second.flush(), 1);
+        assert!(outbox(&layout)[1].contains("[info] bard: two"));
+        assert_eq!(owner_of(Path::new("/srv/notes.log")), "notes");
+    }
+
+    #[test]
+    fn run_polls_until_the_stop_file_and_flushes() {
+        let root = temp_dir("run");
+        let layout = BardLayout::of(&root);
+        let stop_file = layout.discovery.join(DEFAULT_STOP_FILE_NAME);
+        append(&layout.queue_file, &["warn|bard|left pending"]);
+        let clock = ManualClock::new(0);
+        let env = MapEnv::new();
+        let rt = Runtime { clock: &clock, sleeper: &clock, env: &env };
+
+        let mut forwarder = Forwarder::new(&layout, &[], Level::Info, None);
+        assert_eq!(run(&mut forwarder, &stop_file, true, rt), RunSummary { polls: 1, read: 1, filtered: 0, batches: 1 });
+        assert!(clock.slept().is_empty(), "--once never sleeps");
+
+        append(&layout.queue_file, &["warn|bard|later"]);
+        fs::write(&stop_file, "").unwrap();
+        let summary = run(&mut forwarder, &stop_file, false, rt);
+        assert_eq!((summary.polls, summary.read, summary.batches), (1, 1, 1));
+        assert!(!stop_file.exists(), "the stop file is removed");
+        assert_eq!(outbox(&layout).len(), 2);
+    }
+}
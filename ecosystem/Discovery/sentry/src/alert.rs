@@ -0,0 +1,512 @@
+//! Alerts: tell someone when the daemon finds a problem, instead of only printing it.
+//!
+//! A mismatch that only appears on stdout goes unnoticed when nobody is reading the output. With
+//! `--alert-file` or `--alert-command`, the daemon sends an alert whenever the state changes:
+//! - `verification-failed`: a confirmed mismatch after clean passes (or on the first pass);
+//! - `recovered`: every entry matches again after a failure;
+//! - `manifest-reload-failed`: a new manifest could not be read, so the old one stays in use;
+//! - `clock-skew`: the peer's last `SKEW_ALERT_AFTER` reports were all off from our clock by more
+//!   than `--max-clock-skew-secs` (see `clock_skew`).
+//!
+//! With several `--bins-dir` slots (see `slots`), each slot fails and recovers on its own, and its
+//! alerts carry `"slot":"<label>"`, so a tampered canary alerts while the other slots are clean.
+//!
+//! Passes that only repeat the current state send nothing, and the same alert (same kind, release,
+//! entries, and error) is sent at most once per `DEDUP_WINDOW`. A file flapping between good and
+//! bad therefore alerts once, not every few seconds.
+//!
+//! Where an alert goes is an `AlertSink`. Sentry ships two, both without network code:
+//! - `FileSink` appends one JSON line per alert to a file, for a log shipper to pick up;
+//! - `CommandSink` runs a program with the alert JSON on its stdin. The program can send mail,
+//!   post a webhook, or page someone; Sentry does not need to know. It gets `timeout` to finish
+//!   and is killed after that.
+//!
+//! A sink that fails is logged and skipped. Alerting must never stop verification.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::log::Logger;
+use crate::Mode;
+
+const LOG: Logger = Logger::new("sentry-alert");
+
+/// The same alert is not sent again within this time (ten minutes).
+pub const DEDUP_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// How long `--alert-command` may run before it is killed, unless `--alert-timeout-seconds` says otherwise.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often `CommandSink` checks whether the program has finished.
+const POLL_EVERY: Duration = Duration::from_millis(20);
+
+/// What happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlertKind {
+    VerificationFailed,
+    Recovered,
+    ManifestReloadFailed,
+    ClockSkew,
+}
+
+impl AlertKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlertKind::VerificationFailed => "verification-failed",
+            AlertKind::Recovered => "recovered",
+            AlertKind::ManifestReloadFailed => "manifest-reload-failed",
+            AlertKind::ClockSkew => "clock-skew",
+        }
+    }
+}
+
+/// One alert, as sinks receive it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AlertEvent {
+    pub kind: AlertKind,
+    pub mode: Mode,
+    pub release_id: String,
+    /// Entries that did not match (`verification-failed` only).
+    pub entries: Vec<String>,
+    /// The `--bins-dir` label the alert is about, when the daemon checks several slots.
+    pub slot: Option<String>,
+    /// Why the manifest could not be reloaded (`manifest-reload-failed`), or how far the clocks
+    /// are apart (`clock-skew`).
+    pub error: Option<String>,
+    pub at_unix_millis: u128,
+}
+
+impl AlertEvent {
+    /// One line of JSON, e.g.
+    /// `{"action":"alert","kind":"recovered","mode":"red","release_id":"r1","at_unix_millis":...,"entries":[]}`.
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self.entries.iter().map(|entry| format!("\"{}\"", crate::json_escape(entry))).collect();
+        let mut json = format!(
+            "{{\"action\":\"alert\",\"kind\":\"{}\",\"mode\":\"{}\",\"release_id\":\"{}\",\"at_unix_millis\":{},\"entries\":[{}]",
+            self.kind.as_str(),
+            self.mode.as_str(),
+            crate::json_escape(&self.release_id),
+            self.at_unix_millis,
+            entries.join(",")
+        );
+        if let Some(slot) = &self.slot {
+            json.push_str(&format!(",\"slot\":\"{}\"", crate::json_escape(slot)));
+        }
+        if let Some(error) = &self.error {
+            json.push_str(&format!(",\"error\":\"{}\"", crate::json_escape(error)));
+        }
+        json.push('}');
+        json
+    }
+
+    /// Two alerts with the same key are "identical" for `DEDUP_WINDOW`. The time is left out.
+    fn dedup_key(&self) -> String {
+        format!(
+            "{}|{}|{}|{}|{}",
+            self.kind.as_str(),
+            self.release_id,
+            self.slot.as_deref().unwrap_or(""),
+            self.entries.join(","),
+            self.error.as_deref().unwrap_or("")
+        )
+    }
+}
+
+/// Why a sink could not deliver an alert.
+#[derive(Debug)]
+pub enum AlertError {
+    /// The alert file could not be opened or written.
+    Write { path: PathBuf, message: String },
+    /// The alert program could not be started, or did not take its input.
+    Spawn { program: String, message: String },
+    /// The alert program ran longer than its timeout and was killed.
+    TimedOut { program: String, after: Duration },
+    /// The alert program finished with a non-zero exit status.
+    Failed { program: String, status: String },
+}
+
+impl fmt::Display for AlertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertError::Write { path, message } => write!(f, "Unable to append to alert file {path:?}: {message}"),
+            AlertError::Spawn { program, message } => write!(f, "Unable to run alert command {program:?}: {message}"),
+            AlertError::TimedOut { program, after } => write!(f, "Alert command {program:?} was killed after {} ms", after.as_millis()),
+            AlertError::Failed { program, status } => write!(f, "Alert command {program:?} failed: {status}"),
+        }
+    }
+}
+
+impl Error for AlertError {}
+
+/// Somewhere alerts go.
+pub trait AlertSink {
+    /// Deliver one alert. An error is logged by the caller; it never stops the daemon.
+    fn notify(&self, event: &AlertEvent) -> Result<(), AlertError>;
+}
+
+/// Appends each alert as one JSON line (`--alert-file`).
+#[derive(Clone, Debug)]
+pub struct FileSink {
+    pub path: PathBuf,
+}
+
+impl AlertSink for FileSink {
+    fn notify(&self, event: &AlertEvent) -> Result<(), AlertError> {
+        let failed = |err: std::io::Error| AlertError::Write { path: self.path.clone(), message: err.to_string() };
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path).map_err(failed)?;
+        // One `write_all` per line, so two Sentries sharing a file do not interleave halves.
+        file.write_all(format!("{}\n", event.to_json()).as_bytes()).map_err(failed)
+    }
+}
+
+/// Runs a program with the alert JSON on stdin (`--alert-command`).
+///
+/// `program` is run directly, not through a shell, and gets no arguments; wrap anything more
+/// complicated in a script. Its output is discarded.
+#[derive(Clone, Debug)]
+pub struct CommandSink {
+    pub program: String,
+    pub timeout: Duration,
+}
+
+impl AlertSink for CommandSink {
+    fn notify(&self, event: &AlertEvent) -> Result<(), AlertError> {
+        let spawn_failed = |err: std::io::Error| AlertError::Spawn { program: self.program.clone(), message: err.to_string() };
+        let mut child = Command::new(&self.program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(spawn_failed)?;
+        // Dropping stdin closes the pipe, so a program reading to the end of its input finishes.
+        // A program that exits without reading makes the write fail; its exit status says more.
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(format!("{}\n", event.to_json()).as_bytes());
+        }
+        let started = Instant::now();
+        loop {
+            match child.try_wait().map_err(spawn_failed)? {
+                Some(status) if status.success() => return Ok(()),
+                Some(status) => return Err(AlertError::Failed { program: self.program.clone(), status: status.to_string() }),
+                None if started.elapsed() >= self.timeout => {
+                    // Kill and reap it, so no zombie process is left behind.
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(AlertError::TimedOut { program: self.program.clone(), after: self.timeout });
+                }
+                None => thread::sleep(POLL_EVERY),
+            }
+        }
+    }
+}
+
+/// What `Alerter` did with one observation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AlertOutcome {
+    /// The state did not change, so there was nothing to say.
+    Unchanged,
+    /// The same alert went out less than `DEDUP_WINDOW` ago.
+    Suppressed,
+    /// Handed to every sink; `failed` holds one message per sink that could not deliver it.
+    Sent { failed: Vec<String> },
+}
+
+/// Turns daemon passes into alerts: spots state changes, drops repeats, and calls the sinks.
+///
+/// The caller passes the time in (`now_millis`), so the dedup window can be driven by a
+/// `ManualClock` instead of waiting ten minutes.
+pub struct Alerter {
+    sinks: Vec<Box<dyn AlertSink>>,
+    window: Duration,
+    /// The slots whose last observed pass failed (`""` without slots). Sentry starts by assuming
+    /// all is well, so a failure on the very first pass alerts and a clean first pass does not.
+    failing: BTreeSet<String>,
+    /// When each alert (by `dedup_key`) was last sent.
+    last_sent: BTreeMap<String, u128>,
+}
+
+impl Alerter {
+    pub fn new(sinks: Vec<Box<dyn AlertSink>>) -> Self {
+        Self::with_window(sinks, DEDUP_WINDOW)
+    }
+
+    pub fn with_window(sinks: Vec<Box<dyn AlertSink>>, window: Duration) -> Self {
+        Self { sinks, window, failing: BTreeSet::new(), last_sent: BTreeMap::new() }
+    }
+
+    /// No sinks: every call is a no-op. The daemon uses this when no alert flag is given.
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Record one confirmed pass. `failing_entries` lists the entries that did not match; empty
+    /// means the pass was clean.
+    pub fn observe_pass(&mut self, mode: Mode, release_id: &str, failing_entries: &[String], now_millis: u128) -> AlertOutcome {
+        self.observe_slot(mode, release_id, None, failing_entries, now_millis)
+    }
+
+    /// `observe_pass` for one `--bins-dir` slot, tracked apart from the others. `None` is the
+    /// daemon's only folder.
+    pub fn observe_slot(&mut self, mode: Mode, release_id: &str, slot: Option<&str>, failing_entries: &[String], now_millis: u128) -> AlertOutcome {
+        let failing = !failing_entries.is_empty();
+        let key = slot.unwrap_or("").to_string();
+        if failing == self.failing.contains(&key) {
+            return AlertOutcome::Unchanged;
+        }
+        if failing {
+            self.failing.insert(key);
+        } else {
+            self.failing.remove(&key);
+        }
+        let kind = if failing { AlertKind::VerificationFailed } else { AlertKind::Recovered };
+        let event = AlertEvent {
+            kind,
+            mode,
+            release_id: release_id.to_string(),
+            entries: failing_entries.to_vec(),
+            slot: slot.map(str::to_string),
+            error: None,
+            at_unix_millis: now_millis,
+        };
+        self.send(&event)
+    }
+
+    /// Record a manifest that could not be reloaded. Every failed reload is a change worth
+    /// reporting, so only the dedup window holds repeats back.
+    pub fn manifest_reload_failed(&mut self, mode: Mode, release_id: &str, error: &str, now_millis: u128) -> AlertOutcome {
+        let event = AlertEvent {
+            kind: AlertKind::ManifestReloadFailed,
+            mode,
+            release_id: release_id.to_string(),
+            entries: Vec::new(),
+            slot: None,
+            error: Some(error.to_string()),
+            at_unix_millis: now_millis,
+        };
+        self.send(&event)
+    }
+
+    /// Record that the peer's clock stayed skewed. `SkewTracker` already decides when that is
+    /// worth saying, so this always sends (subject to the dedup window).
+    pub fn clock_skew(&mut self, mode: Mode, release_id: &str, detail: &str, now_millis: u128) -> AlertOutcome {
+        let event = AlertEvent {
+            kind: AlertKind::ClockSkew,
+            mode,
+            release_id: release_id.to_string(),
+            entries: Vec::new(),
+            slot: None,
+            error: Some(detail.to_string()),
+            at_unix_millis: now_millis,
+        };
+        self.send(&event)
+    }
+
+    fn send(&mut self, event: &AlertEvent) -> AlertOutcome {
+        if self.sinks.is_empty() {
+            return AlertOutcome::Unchanged;
+        }
+        let key = event.dedup_key();
+        let window = self.window.as_millis();
+        if self.last_sent.get(&key).is_some_and(|sent| event.at_unix_millis.saturating_sub(*sent) < window) {
+            LOG.info("Alert suppressed; the same one was sent recently", &[("kind", event.kind.as_str())]);
+            return AlertOutcome::Suppressed;
+        }
+        // Forget alerts older than the window, so the map does not grow forever.
+        self.last_sent.retain(|_, sent| event.at_unix_millis.saturating_sub(*sent) < window);
+        self.last_sent.insert(key, event.at_unix_millis);
+
+        let mut failed = Vec::new();
+        for sink in &self.sinks {
+            if let Err(err) = sink.notify(event) {
+                LOG.warn("Alert not delivered", &[("kind", event.kind.as_str()), ("error", &err.to_string())]);
+                failed.push(err.to_string());
+            }
+        }
+        if failed.len() < self.sinks.len() {
+            LOG.info("Alert sent", &[("kind", event.kind.as_str()), ("release_id", &event.release_id)]);
+        }
+        AlertOutcome::Sent { failed }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Clock, ManualClock};
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sentry-alert-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Keeps every alert it is handed; clones share the list.
+    #[derive(Clone, Default)]
+    struct Recorder(Rc<RefCell<Vec<AlertEvent>>>);
+
+    impl AlertSink for Recorder {
+        fn notify(&self, event: &AlertEvent) -> Result<(), AlertError> {
+            self.0.borrow_mut().push(event.clone());
+            Ok(())
+        }
+    }
+
+    impl Recorder {
+        fn kinds(&self) -> Vec<AlertKind> {
+            self.0.borrow().iter().map(|event| event.kind).collect()
+        }
+    }
+
+    fn failing(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|entry| entry.to_string()).collect()
+    }
+
+    /// An executable script under `dir`, run with `/bin/sh`.
+    #[cfg(unix)]
+    fn script(dir: &std::path::Path, name: &str, body: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn event(kind: AlertKind) -> AlertEvent {
+        AlertEvent { kind, mode: Mode::Red, release_id: "r1".to_string(), entries: failing(&["squire"]), slot: None, error: None, at_unix_millis: 5 }
+    }
+
+    #[test]
+    fn only_changes_of_state_alert() {
+        let recorder = Recorder::default();
+        let mut alerter = Alerter::new(vec![Box::new(recorder.clone())]);
+        let clock = ManualClock::new(1_700_000_000_000);
+        let mut pass = |entries: &[&str]| {
+            clock.advance(Duration::from_secs(60));
+            alerter.observe_pass(Mode::Red, "r1", &failing(entries), clock.now_millis())
+        };
+        assert_eq!(pass(&[]), AlertOutcome::Unchanged, "a clean first pass says nothing");
+        assert_eq!(pass(&["squire"]), AlertOutcome::Sent { failed: Vec::new() });
+        assert_eq!(pass(&["squire"]), AlertOutcome::Unchanged);
+        assert_eq!(pass(&["squire", "bard"]), AlertOutcome::Unchanged, "still failing is not a change");
+        assert_eq!(pass(&[]), AlertOutcome::Sent { failed: Vec::new() });
+        assert_eq!(pass(&[]), AlertOutcome::Unchanged);
+        assert_eq!(recorder.kinds(), [AlertKind::VerificationFailed, AlertKind::Recovered]);
+        assert_eq!(recorder.0.borrow()[0].entries, ["squire"]);
+        assert!(Alerter::new(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn slots_fail_and_recover_on_their_own() {
+        let recorder = Recorder::default();
+        let mut alerter = Alerter::new(vec![Box::new(recorder.clone())]);
+        let none: Vec<String> = Vec::new();
+        assert!(matches!(alerter.observe_slot(Mode::Red, "r1", Some("canary"), &failing(&["squire"]), 1), AlertOutcome::Sent { .. }));
+        assert_eq!(alerter.observe_slot(Mode::Red, "r1", Some("main"), &none, 2), AlertOutcome::Unchanged);
+        assert!(matches!(alerter.observe_slot(Mode::Red, "r1", Some("main"), &failing(&["bard"]), 3), AlertOutcome::Sent { .. }));
+        assert!(matches!(alerter.observe_slot(Mode::Red, "r1", Some("canary"), &none, 4), AlertOutcome::Sent { .. }));
+        let slots: Vec<Option<String>> = recorder.0.borrow().iter().map(|event| event.slot.clone()).collect();
+        assert_eq!(slots, [Some("canary".to_string()), Some("main".to_string()), Some("canary".to_string())]);
+        assert!(recorder.0.borrow()[0].to_json().ends_with(",\"slot\":\"canary\"}"));
+    }
+
+    #[test]
+    fn the_same_alert_is_sent_once_per_window() {
+        let recorder = Recorder::default();
+        let mut alerter = Alerter::new(vec![Box::new(recorder.clone())]);
+        let clock = ManualClock::new(1_700_000_000_000);
+        let none: Vec<String> = Vec::new();
+
+        // Flapping inside the window: the second failure and recovery repeat the first ones.
+        assert!(matches!(alerter.observe_pass(Mode::Red, "r1", &failing(&["squire"]), clock.now_millis()), AlertOutcome::Sent { .. }));
+        clock.advance(Duration::from_secs(30));
+        assert!(matches!(alerter.observe_pass(Mode::Red, "r1", &none, clock.now_millis()), AlertOutcome::Sent { .. }));
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(alerter.observe_pass(Mode::Red, "r1", &failing(&["squire"]), clock.now_millis()), AlertOutcome::Suppressed);
+        assert_eq!(alerter.observe_pass(Mode::Red, "r1", &none, clock.now_millis()), AlertOutcome::Suppressed);
+        // Another entry is another alert.
+        assert!(matches!(alerter.observe_pass(Mode::Red, "r1", &failing(&["bard"]), clock.now_millis()), AlertOutcome::Sent { .. }));
+
+        assert!(matches!(alerter.manifest_reload_failed(Mode::Red, "r1", "line 2", clock.now_millis()), AlertOutcome::Sent { .. }));
+        clock.advance(DEDUP_WINDOW - Duration::from_millis(1));
+        assert_eq!(alerter.manifest_reload_failed(Mode::Red, "r1", "line 2", clock.now_millis()), AlertOutcome::Suppressed);
+        assert!(matches!(alerter.manifest_reload_failed(Mode::Red, "r1", "line 3", clock.now_millis()), AlertOutcome::Sent { .. }));
+        clock.advance(Duration::from_millis(1));
+        assert!(matches!(alerter.manifest_reload_failed(Mode::Red, "r1", "line 2", clock.now_millis()), AlertOutcome::Sent { .. }));
+        assert!(matches!(alerter.clock_skew(Mode::Red, "r1", "peer is 90 s ahead", clock.now_millis()), AlertOutcome::Sent { .. }));
+        assert_eq!(
+            recorder.kinds(),
+            [
+                AlertKind::VerificationFailed,
+                AlertKind::Recovered,
+                AlertKind::VerificationFailed,
+                AlertKind::ManifestReloadFailed,
+                AlertKind::ManifestReloadFailed,
+                AlertKind::ManifestReloadFailed,
+                AlertKind::ClockSkew,
+            ]
+        );
+    }
+
+    #[test]
+    fn the_file_sink_appends_one_json_line_per_alert() {
+        let dir = temp_dir("file");
+        let sink = FileSink { path: dir.join("alerts.jsonl") };
+        sink.notify(&event(AlertKind::VerificationFailed)).unwrap();
+        sink.notify(&AlertEvent { error: Some("bad \"line\"".to_string()), ..event(AlertKind::ManifestReloadFailed) }).unwrap();
+        let text = fs::read_to_string(dir.join("alerts.jsonl")).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "{\"action\":\"alert\",\"kind\":\"verification-failed\",\"mode\":\"red\",\"release_id\":\"r1\",\"at_unix_millis\":5,\"entries\":[\"squire\"]}");
+        assert!(lines[1].ends_with(",\"error\":\"bad \\\"line\\\"\"}"));
+
+        let unwritable = FileSink { path: dir.join("missing").join("alerts.jsonl") };
+        assert!(matches!(unwritable.notify(&event(AlertKind::Recovered)), Err(AlertError::Write { .. })));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_command_sink_pipes_the_alert_into_the_program() {
+        let dir = temp_dir("command");
+        let out = dir.join("received.json");
+        let program = script(&dir, "deliver.sh", &format!("cat > '{}'", out.display()));
+        let sink = CommandSink { program, timeout: DEFAULT_COMMAND_TIMEOUT };
+        sink.notify(&event(AlertKind::VerificationFailed)).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), format!("{}\n", event(AlertKind::VerificationFailed).to_json()));
+
+        let failing = CommandSink { program: script(&dir, "fail.sh", "cat > /dev/null\nexit 3"), timeout: DEFAULT_COMMAND_TIMEOUT };
+        let err = failing.notify(&event(AlertKind::Recovered)).unwrap_err();
+        assert!(matches!(err, AlertError::Failed { .. }) && err.to_string().contains('3'), "{err}");
+
+        let missing = CommandSink { program: dir.join("no-such-program").to_string_lossy().into_owned(), timeout: DEFAULT_COMMAND_TIMEOUT };
+        assert!(matches!(missing.notify(&event(AlertKind::Recovered)), Err(AlertError::Spawn { .. })));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_slow_command_is_killed_and_the_other_sinks_still_get_the_alert() {
+        let dir = temp_dir("timeout");
+        let slow = CommandSink { program: script(&dir, "slow.sh", "sleep 5"), timeout: Duration::from_millis(100) };
+        let started = Instant::now();
+        let err = slow.notify(&event(AlertKind::VerificationFailed)).unwrap_err();
+        assert!(matches!(err, AlertError::TimedOut { after, .. } if after == Duration::from_millis(100)), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(4), "the program was not waited for");
+        assert!(err.to_string().ends_with("was killed after 100 ms"));
+
+        let recorder = Recorder::default();
+        let mut alerter = Alerter::new(vec![Box::new(slow), Box::new(recorder.clone())]);
+        let outcome = alerter.observe_pass(Mode::Red, "r1", &failing(&["squire"]), 1);
+        let AlertOutcome::Sent { failed } = outcome else { panic!("not sent: {outcome:?}") };
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].contains("was killed after"));
+        assert_eq!(recorder.kinds(), [AlertKind::VerificationFailed]);
+    }
+}
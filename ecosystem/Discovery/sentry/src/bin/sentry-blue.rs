@@ -1,11 +1,15 @@
 //! Wrapper binary that defaults to blue mode.
 //! Blue is the air-gapped builder responsible for reproducible release bundles.
 
+use sentry_omega::log::Logger;
 use sentry_omega::{run_cli, Mode};
 
 fn main() {
-    if let Err(error) = run_cli(Mode::Blue) {
-        eprintln!("sentry-blue failed: {error}");
-        std::process::exit(1);
+    match run_cli(Mode::Blue) {
+        Ok(outcome) => std::process::exit(outcome.code()),
+        Err(error) => {
+            Logger::new("sentry-blue").error("Run failed", &[("error", &error.chain())]);
+            std::process::exit(error.exit_code());
+        }
     }
 }
@@ -2,11 +2,15 @@
 //! Defaults to yellow mode so it can run inside the ecosystem host unless a caller overrides
 //! `--mode`.
 
+use sentry_omega::log::Logger;
 use sentry_omega::{run_cli, Mode};
 
 fn main() {
-    if let Err(error) = run_cli(Mode::Yellow) {
-        eprintln!("sentry-omega failed: {error}");
-        std::process::exit(1);
+    match run_cli(Mode::Yellow) {
+        Ok(outcome) => std::process::exit(outcome.code()),
+        Err(error) => {
+            Logger::new("sentry-omega").error("Run failed", &[("error", &error.chain())]);
+            std::process::exit(error.exit_code());
+        }
     }
 }
@@ -1,11 +1,15 @@
 //! Wrapper binary that defaults to red mode.
 //! Red runs on a separate host and double-checks Yellow’s summaries for disagreement alerts.
 
+use sentry_omega::log::Logger;
 use sentry_omega::{run_cli, Mode};
 
 fn main() {
-    if let Err(error) = run_cli(Mode::Red) {
-        eprintln!("sentry-red failed: {error}");
-        std::process::exit(1);
+    match run_cli(Mode::Red) {
+        Ok(outcome) => std::process::exit(outcome.code()),
+        Err(error) => {
+            Logger::new("sentry-red").error("Run failed", &[("error", &error.chain())]);
+            std::process::exit(error.exit_code());
+        }
     }
 }
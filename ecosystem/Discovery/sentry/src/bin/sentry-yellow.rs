@@ -1,11 +1,15 @@
 //! Wrapper binary that pins Sentry Omega to yellow mode by default.
 //! Yellow runs alongside the ecosystem hub and verifies local bots before trusting builds.
 
+use sentry_omega::log::Logger;
 use sentry_omega::{run_cli, Mode};
 
 fn main() {
-    if let Err(error) = run_cli(Mode::Yellow) {
-        eprintln!("sentry-yellow failed: {error}");
-        std::process::exit(1);
+    match run_cli(Mode::Yellow) {
+        Ok(outcome) => std::process::exit(outcome.code()),
+        Err(error) => {
+            Logger::new("sentry-yellow").error("Run failed", &[("error", &error.chain())]);
+            std::process::exit(error.exit_code());
+        }
     }
 }
@@ -0,0 +1,299 @@
+//! Release bundles: one `.tar` file holding a release's binaries and its manifest.
+//!
+//! Operators used to pack a tarball by hand to carry a release to Red and Blue, and nothing tied
+//! that tarball to the manifest. `build --bundle` now writes it, and `unbundle` unpacks it and
+//! verifies the files against the manifest inside it in the same step.
+//!
+//! The archive is plain ustar, the POSIX tar format every `tar` understands, written by the code
+//! below rather than a crate. It is not gzipped: compression would need a deflate
+//! implementation, and binaries are shipped over links where size is not the problem. Pipe it
+//! through `gzip -n` by hand if you want it smaller (`-n` keeps the output reproducible).
+//!
+//! Two builds of the same files give byte-identical archives, so a bundle's hash can be compared
+//! across hosts. To get there every varying detail is pinned:
+//! - members are sorted by path (`manifest.txt` and the signature files first);
+//! - every modification time is the same fixed value (`--source-date-epoch`);
+//! - owner and group are numeric 0/0 with empty names;
+//! - a member's mode comes from the manifest, not from whatever the disk says today.
+//!
+//! Inside the archive the manifest and signature files sit at the top and the binaries under
+//! `bin/`, named as in the manifest.
+//!
+//! A ustar name field holds 100 bytes. Longer paths are split at a `/` into a `prefix` field (up
+//! to 155 bytes) and the name (up to 100); a path that cannot be split that way is refused
+//! rather than silently cut short. Likewise a member of 8 GiB or more, or a `--source-date-epoch`
+//! past the year 2242, does not fit ustar's eleven octal digits and is refused.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Every tar header and data block is this long.
+const BLOCK: usize = 512;
+const NAME_LEN: usize = 100;
+const PREFIX_LEN: usize = 155;
+
+/// One file inside a bundle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    /// Relative path with `/` separators, e.g. `build/bin/squire`.
+    pub path: String,
+    /// Permission bits, e.g. `0o755`.
+    pub mode: u32,
+    pub data: Vec<u8>,
+}
+
+/// Build a ustar archive from `members`, in the order given, with `mtime` on every member.
+pub fn write_tar(members: &[Member], mtime: u64) -> Result<Vec<u8>, String> {
+    let mut archive = Vec::new();
+    for member in members {
+        check_relative(&member.path)?;
+        let (prefix, name) = split_path(&member.path)?;
+        let mut header = [0u8; BLOCK];
+        put_str(&mut header[0..100], name);
+        let field = |what: &'static str| move |err: String| format!("{}: {what} {err}", member.path);
+        put_octal(&mut header[100..108], u64::from(member.mode & 0o7777)).map_err(field("mode"))?;
+        put_octal(&mut header[108..116], 0).map_err(field("uid"))?;
+        put_octal(&mut header[116..124], 0).map_err(field("gid"))?;
+        // Eleven octal digits hold sizes below 8 GiB and times before March 2242.
+        put_octal(&mut header[124..136], member.data.len() as u64).map_err(field("size"))?;
+        put_octal(&mut header[136..148], mtime).map_err(field("modification time (--source-date-epoch)"))?;
+        header[156] = b'0'; // regular file
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        // uname and gname stay empty, so readers use the numeric ids above.
+        put_str(&mut header[345..500], prefix);
+        let checksum = header_checksum(&header);
+        // The checksum field is six octal digits, a NUL, and a space.
+        put_octal(&mut header[148..155], checksum).map_err(field("checksum"))?;
+        header[155] = b' ';
+
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(&member.data);
+        archive.resize(archive.len().next_multiple_of(BLOCK), 0);
+    }
+    // Two empty blocks mark the end of the archive.
+    archive.resize(archive.len() + 2 * BLOCK, 0);
+    Ok(archive)
+}
+
+/// Read every regular file out of a ustar archive. Folder entries, which `tar -c` adds when an
+/// operator repacks a bundle by hand, are skipped; links and devices are an error, because a
+/// release bundle only ever holds plain files.
+pub fn read_tar(archive: &[u8]) -> Result<Vec<Member>, String> {
+    let mut members = Vec::new();
+    let mut offset = 0;
+    while offset + BLOCK <= archive.len() {
+        let header = &archive[offset..offset + BLOCK];
+        if header.iter().all(|byte| *byte == 0) {
+            return Ok(members);
+        }
+        let recorded = read_octal(&header[148..156]).ok_or("Bundle header has an unreadable checksum")?;
+        if recorded != header_checksum(header) {
+            return Err(format!("Bundle header at byte {offset} is damaged (checksum mismatch)"));
+        }
+        let name = read_str(&header[0..100]);
+        let prefix = read_str(&header[345..500]);
+        let path = if prefix.is_empty() { name } else { format!("{prefix}/{name}") };
+        let is_folder = header[156] == b'5';
+        if !is_folder && !matches!(header[156], b'0' | 0) {
+            return Err(format!("Bundle member {path:?} is not a regular file"));
+        }
+        check_relative(path.trim_end_matches('/'))?;
+        let mode = read_octal(&header[100..108]).ok_or_else(|| format!("Bundle member {path:?} has an unreadable mode"))?;
+        let size = read_octal(&header[124..136]).ok_or_else(|| format!("Bundle member {path:?} has an unreadable size"))?;
+        let start = offset + BLOCK;
+        let end = start
+            .checked_add(size as usize)
+            .filter(|end| *end <= archive.len())
+            .ok_or_else(|| format!("Bundle member {path:?} is cut short"))?;
+        if !is_folder {
+            members.push(Member { path, mode: mode as u32 & 0o7777, data: archive[start..end].to_vec() });
+        }
+        offset = end.next_multiple_of(BLOCK);
+    }
+    Err("Bundle ends without its closing empty blocks".to_string())
+}
+
+/// Write every member under `dest`, creating folders as needed and restoring modes on Unix.
+pub fn extract(members: &[Member], dest: &Path) -> Result<(), String> {
+    for member in members {
+        let target = dest.join(&member.path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|err| format!("Unable to create {:?}: {err}", parent))?;
+        }
+        fs::write(&target, &member.data).map_err(|err| format!("Unable to write {:?}: {err}", target))?;
+        set_mode(&target, member.mode)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .map_err(|err| format!("Unable to set the mode of {:?}: {err}", path))
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> Result<(), String> {
+    // Windows only has "read-only": set it when nobody may write.
+    if mode & 0o222 == 0 {
+        let mut permissions = fs::metadata(path).map_err(|err| format!("Unable to read {:?}: {err}", path))?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(path, permissions).map_err(|err| format!("Unable to set the mode of {:?}: {err}", path))?;
+    }
+    Ok(())
+}
+
+/// A bundle member path as stored in the archive: relative, `/`-separated.
+pub fn member_path(path: &Path) -> Result<String, String> {
+    let parts: Vec<String> = path
+        .components()
+        .map(|component| match component {
+            Component::Normal(part) => Ok(part.to_string_lossy().into_owned()),
+            Component::CurDir => Ok(String::new()),
+            _ => Err(format!("{:?} must be a relative path without `..` to go into a bundle", path)),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(parts.into_iter().filter(|part| !part.is_empty()).collect::<Vec<_>>().join("/"))
+}
+
+/// Refuse absolute paths and `..`, so extracting can never write outside the destination.
+fn check_relative(path: &str) -> Result<(), String> {
+    let unsafe_path = path.is_empty()
+        || path.starts_with('/')
+        || path.contains('\\')
+        || path.split('/').any(|part| part.is_empty() || part == "." || part == "..")
+        || PathBuf::from(path).is_absolute();
+    if unsafe_path {
+        return Err(format!("Bundle member path {path:?} is not a plain relative path"));
+    }
+    Ok(())
+}
+
+/// Split `path` into the ustar `prefix` and `name` fields.
+fn split_path(path: &str) -> Result<(&str, &str), String> {
+    if path.len() <= NAME_LEN {
+        return Ok(("", path));
+    }
+    // Use the first `/` that leaves a short enough name, keeping the prefix as short as possible.
+    path.match_indices('/')
+        .map(|(index, _)| (&path[..index], &path[index + 1..]))
+        .find(|(prefix, name)| prefix.len() <= PREFIX_LEN && !name.is_empty() && name.len() <= NAME_LEN)
+        .ok_or_else(|| format!("{path:?} is too long for a tar header (100-byte name plus 155-byte prefix)"))
+}
+
+/// Sum of all header bytes, counting the checksum field itself as eight spaces.
+fn header_checksum(header: &[u8]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| if (148..156).contains(&index) { u64::from(b' ') } else { u64::from(*byte) })
+        .sum()
+}
+
+fn put_str(field: &mut [u8], value: &str) {
+    field[..value.len()].copy_from_slice(value.as_bytes());
+}
+
+/// Zero-padded octal digits followed by a NUL, filling the field. A value with more digits than
+/// the field holds is an error rather than a header that other readers would misread.
+fn put_octal(field: &mut [u8], value: u64) -> Result<(), String> {
+    let width = field.len() - 1;
+    let digits = format!("{:0width$o}", value);
+    if digits.len() > width {
+        return Err(format!("{value} does not fit in a tar header ({width} octal digits, at most {})", (1u64 << (3 * width)) - 1));
+    }
+    field[..width].copy_from_slice(digits.as_bytes());
+    field[width] = 0;
+    Ok(())
+}
+
+fn read_str(field: &[u8]) -> String {
+    let end = field.iter().position(|byte| *byte == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn read_octal(field: &[u8]) -> Option<u64> {
+    let text = read_str(field);
+    let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(path: &str, mode: u32, data: &[u8]) -> Member {
+        Member { path: path.to_string(), mode, data: data.to_vec() }
+    }
+
+    #[test]
+    fn archives_round_trip_and_are_byte_identical() {
+        let members = vec![member("manifest.txt", 0o644, b"release_id=r1\n"), member("bin/squire", 0o755, &[7; 1300]), member("bin/empty", 0o600, b"")];
+        let archive = write_tar(&members, 1_700_000_000).unwrap();
+        assert_eq!(archive, write_tar(&members, 1_700_000_000).unwrap());
+        assert_ne!(archive, write_tar(&members, 1_700_000_001).unwrap());
+        // Three headers, 1 + 3 + 0 data blocks, and the two closing blocks.
+        assert_eq!(archive.len(), (3 + 4 + 2) * BLOCK);
+        assert_eq!(&archive[257..263], b"ustar\0");
+        assert_eq!(read_str(&archive[108..116]), "0000000", "uid 0");
+        assert_eq!(read_str(&archive[265..297]), "", "no owner name");
+        assert_eq!(read_tar(&archive).unwrap(), members);
+    }
+
+    #[test]
+    fn long_paths_use_the_prefix_field() {
+        let at_limit = "a".repeat(NAME_LEN);
+        let long = format!("bin/{}/{}", "d".repeat(120), "n".repeat(90));
+        let members = vec![member(&at_limit, 0o644, b"1"), member(&long, 0o755, b"2")];
+        let archive = write_tar(&members, 0).unwrap();
+        assert_eq!(read_str(&archive[345..500]), "");
+        let second = &archive[2 * BLOCK..3 * BLOCK];
+        assert_eq!(read_str(&second[0..100]), "n".repeat(90));
+        assert_eq!(read_str(&second[345..500]), format!("bin/{}", "d".repeat(120)));
+        assert_eq!(read_tar(&archive).unwrap(), members);
+
+        // A file name over 100 bytes cannot be split at any `/`, and neither can a huge folder.
+        for path in [format!("bin/{}", "n".repeat(101)), format!("{}/name", "d".repeat(PREFIX_LEN + 1))] {
+            assert!(write_tar(&[member(&path, 0o644, b"")], 0).unwrap_err().contains("too long"), "{path}");
+        }
+    }
+
+    #[test]
+    fn paths_that_could_escape_are_refused() {
+        for path in ["/etc/passwd", "../outside", "bin/../../x", "bin//x", "bin\\x", ""] {
+            assert!(write_tar(&[member(path, 0o644, b"")], 0).is_err(), "{path:?}");
+        }
+        // A tampered archive is caught by the header checksum, and a forged one by the path check.
+        let mut archive = write_tar(&[member("bin/ok", 0o644, b"x")], 0).unwrap();
+        archive[0] = b'X';
+        assert!(read_tar(&archive).unwrap_err().contains("checksum mismatch"));
+        archive[..6].copy_from_slice(b"../ok\0");
+        let checksum = header_checksum(&archive[..BLOCK]);
+        put_octal(&mut archive[148..155], checksum).unwrap();
+        assert!(read_tar(&archive).unwrap_err().contains("not a plain relative path"));
+        assert!(read_tar(&archive[..BLOCK]).is_err(), "missing closing blocks");
+        assert_eq!(member_path(Path::new("./tools/squire")).unwrap(), "tools/squire");
+        assert!(member_path(Path::new("../squire")).is_err());
+    }
+
+    #[test]
+    fn numbers_too_big_for_their_header_field_are_refused() {
+        let members = [member("bin/squire", 0o755, b"x")];
+        let last_second = (1u64 << 33) - 1;
+        let archive = write_tar(&members, last_second).unwrap();
+        assert_eq!(read_str(&archive[136..148]), "77777777777");
+        assert_eq!(read_tar(&archive).unwrap(), members);
+        let err = write_tar(&members, last_second + 1).unwrap_err();
+        assert_eq!(err, "bin/squire: modification time (--source-date-epoch) 8589934592 does not fit in a tar header (11 octal digits, at most 8589934591)");
+        assert!(write_tar(&members, 99_999_999_999).is_err());
+
+        let mut size = [0u8; 12];
+        assert!(put_octal(&mut size, 8 << 30).unwrap_err().starts_with("8589934592 does not fit"), "an 8 GiB member");
+        assert_eq!(size, [0; 12], "nothing written on error");
+    }
+}
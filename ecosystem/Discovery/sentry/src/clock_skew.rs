@@ -0,0 +1,205 @@
+//! Clock skew: notice when another host's clock disagrees with ours.
+//!
+//! Comparing notes between hosts quietly assumes their clocks agree. When Red's clock is 20
+//! minutes off, presence TTLs, waiver expiries, and "is this report fresh?" all go wrong, and the
+//! symptoms point everywhere except at the clock. So every status document says when it was
+//! written (`generated_at_unix_ms`, the writer's clock in milliseconds), and a reader compares that
+//! with its own clock:
+//! - `cross-check` looks at the `--theirs` document;
+//! - `daemon --peer-status <file>` looks at the peer's latest document on every pass.
+//!
+//! A difference larger than `--max-clock-skew-secs` (default 120) becomes a line in the report's
+//! `warnings`. It is never a failure on its own: the hashes are still right, only times are
+//! suspect. A report that simply sat around for a while looks the same as a clock running behind,
+//! so compare fresh reports; that is why the daemon only counts a peer report once, when it is new.
+//!
+//! One odd report can be a slow copy. Three new reports in a row that are all skewed
+//! (`SKEW_ALERT_AFTER`) make the daemon send one `clock-skew` alert. It alerts again only after a
+//! report within the limit has been seen.
+
+use std::time::Duration;
+
+/// `--max-clock-skew-secs` when not given: two minutes, the same slack presence files get.
+pub const DEFAULT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(120);
+/// How many new, skewed peer reports in a row the daemon waits for before alerting.
+pub const SKEW_ALERT_AFTER: u32 = 3;
+
+/// One comparison of a peer's timestamp with our clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SkewCheck {
+    /// `generated_at_unix_ms` from the peer's document.
+    pub peer_ms: u128,
+    /// Our clock when we read it.
+    pub local_ms: u128,
+    pub max_skew: Duration,
+}
+
+impl SkewCheck {
+    pub fn new(peer_ms: u128, local_ms: u128, max_skew: Duration) -> Self {
+        Self { peer_ms, local_ms, max_skew }
+    }
+
+    /// How far the peer's clock is ahead of ours, in milliseconds. Negative means behind.
+    pub fn skew_ms(&self) -> i128 {
+        self.peer_ms as i128 - self.local_ms as i128
+    }
+
+    /// More than `max_skew` apart. Exactly `max_skew` still counts as in sync.
+    pub fn exceeded(&self) -> bool {
+        self.skew_ms().unsigned_abs() > self.max_skew.as_millis()
+    }
+
+    /// The `warnings` line for a skewed report, or `None` when the clocks agree closely enough.
+    pub fn warning(&self, who: &str) -> Option<String> {
+        if !self.exceeded() {
+            return None;
+        }
+        let skew = self.skew_ms();
+        Some(format!(
+            "{who}'s clock is {}s {} ours (report generated_at_unix_ms={}, local {}); allowed skew is {}s",
+            skew.unsigned_abs() / 1000,
+            if skew > 0 { "ahead of" } else { "behind" },
+            self.peer_ms,
+            self.local_ms,
+            self.max_skew.as_secs()
+        ))
+    }
+
+    /// Render as a JSON object, e.g. `{"peer_ms":...,"local_ms":...,"skew_ms":-1200000,"max_skew_secs":120,"status":"skewed"}`.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"peer_ms\":{},\"local_ms\":{},\"skew_ms\":{},\"max_skew_secs\":{},\"status\":\"{}\"}}",
+            self.peer_ms,
+            self.local_ms,
+            self.skew_ms(),
+            self.max_skew.as_secs(),
+            if self.exceeded() { "skewed" } else { "ok" }
+        )
+    }
+}
+
+/// Counts skewed peer reports in a row so the daemon alerts once per episode, not every pass.
+#[derive(Clone, Debug, Default)]
+pub struct SkewTracker {
+    /// `generated_at_unix_ms` of the last report counted; the same report read again is skipped.
+    last_seen: Option<u128>,
+    consecutive: u32,
+    alerted: bool,
+}
+
+impl SkewTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `peer_ms` belongs to a report not counted yet.
+    pub fn is_new(&self, peer_ms: u128) -> bool {
+        self.last_seen != Some(peer_ms)
+    }
+
+    /// How many new reports in a row were skewed.
+    pub fn consecutive(&self) -> u32 {
+        self.consecutive
+    }
+
+    /// Count one report. Returns `true` exactly once per run of skewed reports: on the
+    /// `SKEW_ALERT_AFTER`-th in a row. A report already counted changes nothing and returns `false`.
+    pub fn observe(&mut self, check: &SkewCheck) -> bool {
+        if !self.is_new(check.peer_ms) {
+            return false;
+        }
+        self.last_seen = Some(check.peer_ms);
+        if !check.exceeded() {
+            self.consecutive = 0;
+            self.alerted = false;
+            return false;
+        }
+        self.consecutive = self.consecutive.saturating_add(1);
+        if self.consecutive >= SKEW_ALERT_AFTER && !self.alerted {
+            self.alerted = true;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Clock, ManualClock};
+
+    const LOCAL: u128 = 1_700_000_000_000;
+
+    #[test]
+    fn exactly_the_limit_is_in_sync_and_one_millisecond_more_is_not() {
+        let at_limit = SkewCheck::new(LOCAL + 120_000, LOCAL, DEFAULT_MAX_CLOCK_SKEW);
+        assert_eq!(at_limit.skew_ms(), 120_000);
+        assert!(!at_limit.exceeded() && at_limit.warning("peer").is_none());
+        assert!(SkewCheck::new(LOCAL + 120_001, LOCAL, DEFAULT_MAX_CLOCK_SKEW).exceeded());
+        assert!(!SkewCheck::new(LOCAL - 120_000, LOCAL, DEFAULT_MAX_CLOCK_SKEW).exceeded());
+        assert!(SkewCheck::new(LOCAL - 120_001, LOCAL, DEFAULT_MAX_CLOCK_SKEW).exceeded());
+        assert!(!SkewCheck::new(LOCAL, LOCAL, Duration::ZERO).exceeded());
+    }
+
+    #[test]
+    fn warnings_say_which_way_the_clock_is_off() {
+        let ahead = SkewCheck::new(LOCAL + 1_200_000, LOCAL, DEFAULT_MAX_CLOCK_SKEW).warning("red").unwrap();
+        assert_eq!(ahead, format!("red's clock is 1200s ahead of ours (report generated_at_unix_ms={}, local {LOCAL}); allowed skew is 120s", LOCAL + 1_200_000));
+        let behind = SkewCheck::new(LOCAL - 300_500, LOCAL, DEFAULT_MAX_CLOCK_SKEW).warning("red").unwrap();
+        assert!(behind.starts_with("red's clock is 300s behind ours"), "{behind}");
+    }
+
+    #[test]
+    fn json_carries_the_signed_skew_and_status() {
+        let skewed = SkewCheck::new(LOCAL - 1_200_000, LOCAL, DEFAULT_MAX_CLOCK_SKEW).to_json();
+        assert_eq!(skewed, format!("{{\"peer_ms\":{},\"local_ms\":{LOCAL},\"skew_ms\":-1200000,\"max_skew_secs\":120,\"status\":\"skewed\"}}", LOCAL - 1_200_000));
+        assert!(SkewCheck::new(LOCAL + 5, LOCAL, DEFAULT_MAX_CLOCK_SKEW).to_json().ends_with("\"skew_ms\":5,\"max_skew_secs\":120,\"status\":\"ok\"}"));
+    }
+
+    /// A report a peer writes now, with its clock `offset_ms` ahead of `clock`.
+    fn peer_report(clock: &ManualClock, offset_ms: u128) -> SkewCheck {
+        let local = clock.now_millis();
+        SkewCheck::new(local + offset_ms, local, DEFAULT_MAX_CLOCK_SKEW)
+    }
+
+    #[test]
+    fn the_tracker_alerts_once_per_run_of_new_skewed_reports() {
+        let clock = ManualClock::new(LOCAL);
+        let mut tracker = SkewTracker::new();
+        let mut alerts = Vec::new();
+        for _ in 0..5 {
+            let check = peer_report(&clock, 1_200_000);
+            alerts.push(tracker.observe(&check));
+            // Reading the same report again on the next pass is not a new data point.
+            assert!(!tracker.observe(&check));
+            clock.advance(Duration::from_secs(60));
+        }
+        assert_eq!(alerts, [false, false, true, false, false]);
+        assert_eq!(tracker.consecutive(), 5);
+
+        assert!(!tracker.observe(&peer_report(&clock, 0)), "an in-sync report never alerts");
+        assert_eq!(tracker.consecutive(), 0);
+        let mut again = Vec::new();
+        for _ in 0..3 {
+            clock.advance(Duration::from_secs(60));
+            again.push(tracker.observe(&peer_report(&clock, 1_200_000)));
+        }
+        assert_eq!(again, [false, false, true], "a fresh episode alerts again");
+    }
+
+    #[test]
+    fn a_stale_report_seen_twice_is_counted_once() {
+        let clock = ManualClock::new(LOCAL);
+        let mut tracker = SkewTracker::new();
+        let check = peer_report(&clock, 1_200_000);
+        assert!(tracker.is_new(check.peer_ms));
+        tracker.observe(&check);
+        assert!(!tracker.is_new(check.peer_ms));
+        clock.advance(Duration::from_secs(600));
+        let reread = SkewCheck::new(check.peer_ms, clock.now_millis(), DEFAULT_MAX_CLOCK_SKEW);
+        for _ in 0..3 {
+            assert!(!tracker.observe(&reread));
+        }
+        assert_eq!(tracker.consecutive(), 1);
+    }
+}
@@ -0,0 +1,289 @@
+//! Compare two saved status documents (Yellow's and Red's, for example) and list disagreements.
+//!
+//! Each role verifies the same release on its own host and saves the result with `--output`.
+//! If both hosts are honest and healthy they computed the same hashes. A difference means one
+//! host saw different bytes, which is exactly what Red exists to catch.
+//!
+//! The other host's `generated_at_unix_ms` is also compared with this host's clock (see
+//! `clock_skew`). Skew shows up in `warnings` and never changes the exit status. So do documents
+//! written by different major versions of Sentry (`tool_version`, see `version`).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::clock_skew::SkewCheck;
+use crate::json::{self, JsonValue};
+use crate::version::Version;
+use crate::{json_escape, Mode, STATUS_FORMAT_VERSION};
+
+/// The parts of a status document that cross-check compares.
+#[derive(Clone, Debug)]
+pub struct StatusSummary {
+    pub release_id: String,
+    /// Entry key (relative path, or name in older documents) to hash. Taken from `observed` (what
+    /// the host computed) when the document has it, otherwise from `entries` (what the manifest
+    /// recorded, e.g. a `build` document).
+    pub hashes: BTreeMap<String, String>,
+    /// When the writer's clock rendered the document. `None` for documents from before the field.
+    pub generated_at_unix_ms: Option<u128>,
+    /// The Sentry that wrote the document. Documents from before the field count as `0.0.0`.
+    pub tool_version: Version,
+}
+
+/// One disagreement between the two documents.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Conflict {
+    ReleaseId { mine: String, theirs: String },
+    Hash { name: String, mine: String, theirs: String },
+    OnlyMine { name: String },
+    OnlyTheirs { name: String },
+}
+
+impl Conflict {
+    fn to_json(&self) -> String {
+        match self {
+            Conflict::ReleaseId { mine, theirs } => format!(
+                "{{\"kind\":\"release_id\",\"mine\":\"{}\",\"theirs\":\"{}\"}}",
+                json_escape(mine),
+                json_escape(theirs)
+            ),
+            Conflict::Hash { name, mine, theirs } => format!(
+                "{{\"kind\":\"hash\",\"name\":\"{}\",\"mine\":\"{}\",\"theirs\":\"{}\"}}",
+                json_escape(name),
+                json_escape(mine),
+                json_escape(theirs)
+            ),
+            Conflict::OnlyMine { name } => format!("{{\"kind\":\"only_mine\",\"name\":\"{}\"}}", json_escape(name)),
+            Conflict::OnlyTheirs { name } => format!("{{\"kind\":\"only_theirs\",\"name\":\"{}\"}}", json_escape(name)),
+        }
+    }
+}
+
+/// Read and validate one status document.
+pub fn load_status(path: &Path) -> Result<StatusSummary, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("Unable to read {:?}: {err}", path))?;
+    let document = json::parse(&text).map_err(|err| format!("{:?} is not valid JSON: {err}", path))?;
+
+    // Check the version first so an old or new layout fails with a clear message instead of a
+    // confusing "missing field" further down.
+    let Some(version) = document.get("format_version").and_then(JsonValue::as_f64) else {
+        return Err(format!(
+            "{:?} has no format_version, so it was written by an older Sentry; regenerate it with `verify --output`",
+            path
+        ));
+    };
+    if version != f64::from(STATUS_FORMAT_VERSION) {
+        return Err(format!(
+            "{:?} uses status format {}, but this Sentry reads format {}",
+            path, version, STATUS_FORMAT_VERSION
+        ));
+    }
+
+    let release_id = document
+        .get("release_id")
+        .and_then(JsonValue::as_str)
+        .ok_or_else(|| format!("{:?} has no release_id", path))?
+        .to_string();
+
+    if matches!(document.get("observed"), Some(JsonValue::Object(_))) {
+        return Err(format!("{:?} covers several --bins-dir slots; cross-check a document from one slot", path));
+    }
+    let list = document
+        .get("observed")
+        .or_else(|| document.get("entries"))
+        .and_then(JsonValue::as_array)
+        .ok_or_else(|| format!("{:?} has neither observed nor entries", path))?;
+
+    let mut hashes = BTreeMap::new();
+    for item in list {
+        // Keyed like `results`: by relative path, or by name in documents from before it existed.
+        let name = item.get("rel_path").or_else(|| item.get("name")).and_then(JsonValue::as_str);
+        let hash = item.get("hash").and_then(JsonValue::as_str);
+        let (Some(name), Some(hash)) = (name, hash) else {
+            return Err(format!("{:?} has an entry without a name or hash", path));
+        };
+        hashes.insert(name.to_string(), hash.to_string());
+    }
+
+    let generated_at_unix_ms = document
+        .get("generated_at_unix_ms")
+        .and_then(JsonValue::as_f64)
+        .filter(|millis| *millis >= 0.0)
+        .map(|millis| millis as u128);
+
+    let tool_version = match document.get("tool_version").and_then(JsonValue::as_str) {
+        Some(text) => Version::parse(text).ok_or_else(|| format!("{:?} has an invalid tool_version {:?}", path, text))?,
+        None => Version::LEGACY,
+    };
+
+    Ok(StatusSummary { release_id, hashes, generated_at_unix_ms, tool_version })
+}
+
+/// List every disagreement, release id first, then entries in name order.
+pub fn compare(mine: &StatusSummary, theirs: &StatusSummary) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+
+    if mine.release_id != theirs.release_id {
+        conflicts.push(Conflict::ReleaseId { mine: mine.release_id.clone(), theirs: theirs.release_id.clone() });
+    }
+
+    for (name, my_hash) in &mine.hashes {
+        match theirs.hashes.get(name) {
+            Some(their_hash) if their_hash != my_hash => conflicts.push(Conflict::Hash {
+                name: name.clone(),
+                mine: my_hash.clone(),
+                theirs: their_hash.clone(),
+            }),
+            Some(_) => {}
+            None => conflicts.push(Conflict::OnlyMine { name: name.clone() }),
+        }
+    }
+    for name in theirs.hashes.keys() {
+        if !mine.hashes.contains_key(name) {
+            conflicts.push(Conflict::OnlyTheirs { name: name.clone() });
+        }
+    }
+
+    conflicts
+}
+
+/// Compare `theirs.generated_at_unix_ms` with `local_ms`. Returns the check (when the document
+/// has a timestamp) and the warnings to report: one for too much skew, or one saying the document
+/// is too old to carry a timestamp.
+pub fn clock_warnings(theirs: &StatusSummary, local_ms: u128, max_skew: Duration) -> (Option<SkewCheck>, Vec<String>) {
+    match theirs.generated_at_unix_ms {
+        Some(peer_ms) => {
+            let check = SkewCheck::new(peer_ms, local_ms, max_skew);
+            (Some(check), check.warning("their host").into_iter().collect())
+        }
+        None => (None, vec!["their document has no generated_at_unix_ms (older Sentry), so clock skew was not checked".to_string()]),
+    }
+}
+
+/// A warning when the two documents come from different major versions of Sentry, whose hashes
+/// and fields may not mean the same thing.
+pub fn version_warning(mine: &StatusSummary, theirs: &StatusSummary) -> Option<String> {
+    (mine.tool_version.major != theirs.tool_version.major).then(|| {
+        format!(
+            "the documents come from different major Sentry versions (mine {}, theirs {}); upgrade both hosts before trusting the comparison",
+            mine.tool_version, theirs.tool_version
+        )
+    })
+}
+
+/// The `"action":"cross-check"` report. `warnings` never change `status`.
+pub fn render_report(mode: Mode, mine: &StatusSummary, theirs: &StatusSummary, conflicts: &[Conflict], skew: Option<&SkewCheck>, warnings: &[String]) -> String {
+    let conflict_list = conflicts.iter().map(Conflict::to_json).collect::<Vec<_>>().join(",");
+    let warning_list = warnings.iter().map(|warning| format!("\"{}\"", json_escape(warning))).collect::<Vec<_>>().join(",");
+    format!(
+        "{{\"format_version\":{},\"action\":\"cross-check\",\"mode\":\"{}\",\"mine_release_id\":\"{}\",\"theirs_release_id\":\"{}\",\"status\":\"{}\",\"conflicts\":[{}],\"clock_skew\":{},\"warnings\":[{}]}}",
+        STATUS_FORMAT_VERSION,
+        mode.as_str(),
+        json_escape(&mine.release_id),
+        json_escape(&theirs.release_id),
+        if conflicts.is_empty() { "agree" } else { "disagree" },
+        conflict_list,
+        skew.map(SkewCheck::to_json).unwrap_or_else(|| "null".to_string()),
+        warning_list
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn write_status(name: &str, body: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sentry-cross-check-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{name}.json"));
+        fs::write(&path, body).unwrap();
+        path
+    }
+
+    /// A one-slot `verify` document for `release_id` with `(rel_path, hash)` observations.
+    fn status(name: &str, release_id: &str, observed: &[(&str, &str)]) -> StatusSummary {
+        let observed = observed.iter().map(|(rel_path, hash)| format!("{{\"rel_path\":\"{rel_path}\",\"hash\":\"{hash}\"}}")).collect::<Vec<_>>().join(",");
+        let body = format!("{{\"format_version\":{STATUS_FORMAT_VERSION},\"release_id\":\"{release_id}\",\"observed\":[{observed}]}}");
+        load_status(&write_status(name, &body)).unwrap()
+    }
+
+    #[test]
+    fn agreeing_reports_have_no_conflicts() {
+        let mine = status("agree-mine", "r1", &[("squire", "aa"), ("tools/hub", "bb")]);
+        let theirs = status("agree-theirs", "r1", &[("tools/hub", "bb"), ("squire", "aa")]);
+        assert!(compare(&mine, &theirs).is_empty());
+        assert!(render_report(Mode::Red, &mine, &theirs, &[], None, &[]).contains("\"status\":\"agree\""));
+    }
+
+    #[test]
+    fn one_differing_hash_is_named() {
+        let mine = status("hash-mine", "r1", &[("squire", "aa"), ("bard", "cc")]);
+        let theirs = status("hash-theirs", "r1", &[("squire", "ab"), ("bard", "cc")]);
+        let conflicts = compare(&mine, &theirs);
+        assert_eq!(conflicts, [Conflict::Hash { name: "squire".to_string(), mine: "aa".to_string(), theirs: "ab".to_string() }]);
+        let report = render_report(Mode::Red, &mine, &theirs, &conflicts, None, &[]);
+        assert!(report.contains("\"status\":\"disagree\"") && report.contains("{\"kind\":\"hash\",\"name\":\"squire\",\"mine\":\"aa\",\"theirs\":\"ab\"}"), "{report}");
+    }
+
+    #[test]
+    fn different_releases_and_missing_entries_are_conflicts() {
+        let mine = status("release-mine", "r1", &[("squire", "aa"), ("old", "dd")]);
+        let theirs = status("release-theirs", "r2", &[("squire", "aa"), ("new", "ee")]);
+        assert_eq!(
+            compare(&mine, &theirs),
+            [
+                Conflict::ReleaseId { mine: "r1".to_string(), theirs: "r2".to_string() },
+                Conflict::OnlyMine { name: "old".to_string() },
+                Conflict::OnlyTheirs { name: "new".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn unusable_documents_are_refused_with_a_reason() {
+        let old = write_status("old-format", "{\"release_id\":\"r1\",\"observed\":[]}");
+        assert!(load_status(&old).unwrap_err().contains("has no format_version"));
+        let multi = write_status("multi", &format!("{{\"format_version\":{STATUS_FORMAT_VERSION},\"release_id\":\"r1\",\"observed\":{{\"a\":[]}}}}"));
+        assert!(load_status(&multi).unwrap_err().contains("several --bins-dir slots"));
+        let nameless = write_status("nameless", &format!("{{\"format_version\":{STATUS_FORMAT_VERSION},\"release_id\":\"r1\",\"entries\":[{{\"hash\":\"aa\"}}]}}"));
+        assert!(load_status(&nameless).unwrap_err().contains("without a name or hash"));
+    }
+
+    #[test]
+    fn clock_warnings_flag_skew_and_missing_timestamps_without_conflicts() {
+        let body = |generated: &str| format!("{{\"format_version\":{STATUS_FORMAT_VERSION},\"release_id\":\"r1\"{generated},\"observed\":[]}}");
+        let local = 1_700_000_000_000u128;
+        let skewed = load_status(&write_status("skew-far", &body(&format!(",\"generated_at_unix_ms\":{}", local - 1_200_000)))).unwrap();
+        let (check, warnings) = clock_warnings(&skewed, local, Duration::from_secs(120));
+        assert_eq!(check.map(|check| check.skew_ms()), Some(-1_200_000));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("their host's clock is 1200s behind ours"), "{warnings:?}");
+        let close = load_status(&write_status("skew-close", &body(&format!(",\"generated_at_unix_ms\":{}", local + 120_000)))).unwrap();
+        assert!(clock_warnings(&close, local, Duration::from_secs(120)).1.is_empty());
+        let undated = load_status(&write_status("skew-none", &body(""))).unwrap();
+        let (check, warnings) = clock_warnings(&undated, local, Duration::from_secs(120));
+        assert!(check.is_none() && warnings[0].contains("no generated_at_unix_ms"));
+
+        let report = render_report(Mode::Red, &close, &skewed, &[], check.as_ref(), &warnings);
+        assert!(report.contains("\"status\":\"agree\"") && report.contains("\"clock_skew\":null") && report.contains("older Sentry"), "{report}");
+    }
+
+    #[test]
+    fn different_major_versions_are_a_warning() {
+        let body = |version: &str| format!("{{\"format_version\":{STATUS_FORMAT_VERSION},\"release_id\":\"r1\"{version},\"observed\":[]}}");
+        let one = load_status(&write_status("version-one", &body(",\"tool_version\":\"1.4.2\""))).unwrap();
+        let one_later = load_status(&write_status("version-one-later", &body(",\"tool_version\":\"1.9.0-beta\""))).unwrap();
+        let two = load_status(&write_status("version-two", &body(",\"tool_version\":\"2.0.0\""))).unwrap();
+        let legacy = load_status(&write_status("version-none", &body(""))).unwrap();
+        assert_eq!(legacy.tool_version, Version::LEGACY);
+        assert_eq!(version_warning(&one, &one_later), None);
+        let warning = version_warning(&one, &two).unwrap();
+        assert!(warning.contains("(mine 1.4.2, theirs 2.0.0)"), "{warning}");
+        assert!(version_warning(&legacy, &one).is_some());
+        let broken = write_status("version-broken", &body(",\"tool_version\":\"new\""));
+        assert!(load_status(&broken).unwrap_err().contains("invalid tool_version \"new\""));
+    }
+}
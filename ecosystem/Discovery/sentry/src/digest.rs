@@ -0,0 +1,119 @@
+//! Extra digests recorded next to each manifest entry's hash (`build --digests`).
+//!
+//! The manifest's own `hash` field is what verification has always compared, but nobody outside
+//! Sentry can reproduce it. Vendors publish SHA-256 or SHA-512 sums instead, so `build` can also
+//! record those: `--digests sha256,sha512` adds `hash_sha256=<hex>` and `hash_sha512=<hex>` fields
+//! to every entry line. `sha256` alone is the default.
+//!
+//! Each file is read once, through a fixed buffer. `MultiHasher` feeds every chunk into every
+//! chosen algorithm side by side, so asking for two digests costs one read, not two. `verify` recomputes every digest an
+//! entry carries and names the algorithm that no longer matches.
+
+use crate::sha256::{self, Sha256};
+use crate::sha512::Sha512;
+
+/// Algorithms `--digests` accepts. The order here is the order fields appear in a manifest line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DigestAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+/// What `build` records when `--digests` is not given.
+pub const DEFAULT_DIGESTS: &[DigestAlgorithm] = &[DigestAlgorithm::Sha256];
+
+impl DigestAlgorithm {
+    pub const ALL: [DigestAlgorithm; 2] = [DigestAlgorithm::Sha256, DigestAlgorithm::Sha512];
+
+    /// Name used on the command line and in JSON: `sha256` or `sha512`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha256 => "sha256",
+            DigestAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    /// Key of the manifest field: `hash_sha256` or `hash_sha512`.
+    pub fn field(self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha256 => "hash_sha256",
+            DigestAlgorithm::Sha512 => "hash_sha512",
+        }
+    }
+
+    /// The algorithm whose manifest field is `field`, if any.
+    pub fn from_field(field: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|algorithm| algorithm.field() == field)
+    }
+}
+
+/// Parse a `--digests` value such as `sha256,sha512`. Names are case-insensitive and may repeat;
+/// the result is sorted and without duplicates. An unknown or empty name is an error, so a typo
+/// stops the build before anything is hashed.
+pub fn parse_list(text: &str) -> Result<Vec<DigestAlgorithm>, String> {
+    let mut algorithms = Vec::new();
+    for name in text.split(',').map(str::trim) {
+        let algorithm = DigestAlgorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.as_str().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                let known: Vec<&str> = DigestAlgorithm::ALL.iter().map(|algorithm| algorithm.as_str()).collect();
+                format!("Unknown digest {:?} in --digests; expected a comma-separated list of {}", name, known.join(", "))
+            })?;
+        algorithms.push(algorithm);
+    }
+    algorithms.sort();
+    algorithms.dedup();
+    Ok(algorithms)
+}
+
+/// Several hashers fed the same bytes in one pass.
+pub struct MultiHasher {
+    sha256: Option<Sha256>,
+    sha512: Option<Sha512>,
+}
+
+impl MultiHasher {
+    /// Start one hasher for each algorithm in `algorithms`.
+    pub fn new(algorithms: &[DigestAlgorithm]) -> Self {
+        Self {
+            sha256: algorithms.contains(&DigestAlgorithm::Sha256).then(Sha256::new),
+            sha512: algorithms.contains(&DigestAlgorithm::Sha512).then(Sha512::new),
+        }
+    }
+
+    /// Feed the next piece of the file to every hasher.
+    pub fn update(&mut self, data: &[u8]) {
+        if let Some(hasher) = &mut self.sha256 {
+            hasher.update(data);
+        }
+        if let Some(hasher) = &mut self.sha512 {
+            hasher.update(data);
+        }
+    }
+
+    /// `(algorithm, lowercase hex)` for every hasher, in field order.
+    pub fn finalize(self) -> Vec<(DigestAlgorithm, String)> {
+        let mut digests = Vec::new();
+        if let Some(hasher) = self.sha256 {
+            digests.push((DigestAlgorithm::Sha256, sha256::to_hex(&hasher.finalize())));
+        }
+        if let Some(hasher) = self.sha512 {
+            digests.push((DigestAlgorithm::Sha512, sha256::to_hex(&hasher.finalize())));
+        }
+        digests
+    }
+}
+
+/// Every digest in `algorithms` of bytes already read from a file.
+pub fn digests_of(data: &[u8], algorithms: &[DigestAlgorithm]) -> Vec<(DigestAlgorithm, String)> {
+    let mut hasher = MultiHasher::new(algorithms);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// `{"sha256":"...","sha512":"..."}` for status documents.
+pub fn digests_json(digests: &[(DigestAlgorithm, String)]) -> String {
+    let fields: Vec<String> = digests.iter().map(|(algorithm, hex)| format!("\"{}\":\"{}\"", algorithm.as_str(), hex)).collect();
+    format!("{{{}}}", fields.join(","))
+}
@@ -0,0 +1,218 @@
+//! Disk space: check there is room before writing, instead of failing halfway.
+//!
+//! On a nearly full disk `build` used to get as far as half a release folder and then stop with a
+//! bare "No space left on device". Now it adds up what it is about to write (`required_for_build`)
+//! and compares that with what the filesystem has left before writing anything. Too little room
+//! is `SentryError::InsufficientSpace`, which names both numbers; `--skip-space-check` turns the
+//! check off for a filesystem that reports nonsense.
+//!
+//! The daemon's cache, log, and heartbeat files grow slowly and forever, so each pass it looks at
+//! the folders holding them and adds a `low-disk` line to the document's `warnings` when one has
+//! less than `--min-free-mb` (default 256) left. Like clock skew, that is a warning only.
+//!
+//! Asking the operating system goes through the `SpaceProvider` trait. `SystemSpace` is the real
+//! one; a stand-in that returns a fixed number shows what `build` and the daemon do on a full
+//! disk without filling one (pass it to `run_cli_with_space`).
+//!
+//! `SystemSpace` calls `statvfs` on Linux and macOS and `GetDiskFreeSpaceExW` on Windows. These two
+//! small blocks are the only `unsafe` code in Sentry: the standard library has no call for free
+//! space, and the rule is no outside crates. Elsewhere it reports `Unsupported`, and the checks are
+//! skipped with a log line rather than refusing every build.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// `--min-free-mb` when not given.
+pub const DEFAULT_MIN_FREE_MB: u64 = 256;
+/// What a build without `--bundle` is assumed to need: the manifest and its `.sig` files are
+/// small text files, so one megabyte leaves plenty of slack.
+pub const MANIFEST_ONLY_ESTIMATE: u64 = 1024 * 1024;
+
+/// Tells how many bytes can still be written on the filesystem holding a path.
+pub trait SpaceProvider {
+    /// Bytes available to this process (not counting space reserved for root) on the filesystem
+    /// that holds `path`. `path` itself must exist; see `existing_ancestor`.
+    fn available_bytes(&self, path: &Path) -> io::Result<u64>;
+}
+
+/// Asks the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemSpace;
+
+impl SpaceProvider for SystemSpace {
+    fn available_bytes(&self, path: &Path) -> io::Result<u64> {
+        platform::available_bytes(path)
+    }
+}
+
+/// A fixed answer for every path, for trying the refusal and the `low-disk` warning by hand.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedSpace(pub u64);
+
+impl SpaceProvider for FixedSpace {
+    fn available_bytes(&self, _path: &Path) -> io::Result<u64> {
+        Ok(self.0)
+    }
+}
+
+/// The closest folder at or above `path` that exists. A releases folder that `build` has not
+/// created yet lives on the same filesystem as its parent, so that is the one to ask about.
+pub fn existing_ancestor(path: &Path) -> PathBuf {
+    let mut current = if path.as_os_str().is_empty() { Path::new(".") } else { path };
+    loop {
+        if current.exists() {
+            return current.to_path_buf();
+        }
+        match current.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => current = parent,
+            _ => return PathBuf::from("."),
+        }
+    }
+}
+
+/// Bytes `build` is about to write: every binary's size again when it also writes a bundle
+/// (plus the manifest estimate for the rest of the tar), or just the estimate otherwise.
+pub fn required_for_build(entry_sizes: impl IntoIterator<Item = u64>, bundle: bool) -> u64 {
+    if bundle {
+        entry_sizes.into_iter().fold(MANIFEST_ONLY_ESTIMATE, u64::saturating_add)
+    } else {
+        MANIFEST_ONLY_ESTIMATE
+    }
+}
+
+/// The daemon's `low-disk` warning for one folder, or `None` when it has at least `min_free_mb`
+/// left. An error means the free space could not be read.
+pub fn low_disk_warning(space: &dyn SpaceProvider, folder: &Path, min_free_mb: u64) -> io::Result<Option<String>> {
+    let available = space.available_bytes(&existing_ancestor(folder))?;
+    let minimum = min_free_mb.saturating_mul(1024 * 1024);
+    if available >= minimum {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "low-disk: {} has {} MB free ({} bytes), below --min-free-mb {}",
+        folder.display(),
+        available / (1024 * 1024),
+        available,
+        min_free_mb
+    )))
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod platform {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::raw::{c_char, c_int, c_ulong};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    /// Block counts are 64-bit on Linux and 32-bit on macOS.
+    #[cfg(target_os = "linux")]
+    type BlockCount = u64;
+    #[cfg(target_os = "macos")]
+    type BlockCount = u32;
+
+    /// The start of C's `struct statvfs`: the fields we read, in the order the C headers declare
+    /// them. `_rest` stands in for the fields after them and is larger than any of them need, so
+    /// `statvfs` never writes past the end of this struct.
+    #[repr(C)]
+    struct StatVfs {
+        f_bsize: c_ulong,
+        f_frsize: c_ulong,
+        _f_blocks: BlockCount,
+        _f_bfree: BlockCount,
+        f_bavail: BlockCount,
+        _rest: [u64; 16],
+    }
+
+    extern "C" {
+        fn statvfs(path: *const c_char, buf: *mut StatVfs) -> c_int;
+    }
+
+    // The conversions to u64 do nothing on Linux but are needed for macOS's 32-bit counts.
+    #[allow(clippy::useless_conversion, clippy::unnecessary_cast)]
+    pub fn available_bytes(path: &Path) -> io::Result<u64> {
+        let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+        let mut stats = StatVfs { f_bsize: 0, f_frsize: 0, _f_blocks: 0, _f_bfree: 0, f_bavail: 0, _rest: [0; 16] };
+        // SAFETY: `c_path` is a NUL-terminated string that outlives the call, and `stats` is a
+        // writable buffer at least as large as the C struct.
+        let result = unsafe { statvfs(c_path.as_ptr(), &mut stats) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // `f_bavail` counts fragments of `f_frsize` bytes; old systems leave that 0 and mean `f_bsize`.
+        let unit = if stats.f_frsize != 0 { stats.f_frsize } else { stats.f_bsize };
+        Ok(u64::from(stats.f_bavail).saturating_mul(unit as u64))
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(directory: *const u16, free_to_caller: *mut u64, total: *mut u64, total_free: *mut u64) -> i32;
+    }
+
+    pub fn available_bytes(path: &Path) -> io::Result<u64> {
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+        let mut free_to_caller = 0u64;
+        // SAFETY: `wide` is NUL-terminated and outlives the call; the totals we do not need may be null.
+        let ok = unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut free_to_caller, std::ptr::null_mut(), std::ptr::null_mut()) };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(free_to_caller)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    pub fn available_bytes(_path: &Path) -> io::Result<u64> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "free space is not known on this platform"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_estimate_counts_binaries_only_when_bundling() {
+        assert_eq!(required_for_build([10, 20], false), MANIFEST_ONLY_ESTIMATE);
+        assert_eq!(required_for_build([10, 20], true), MANIFEST_ONLY_ESTIMATE + 30);
+        assert_eq!(required_for_build([u64::MAX, 1], true), u64::MAX, "saturates instead of wrapping");
+    }
+
+    #[test]
+    fn low_disk_warns_only_below_the_minimum() {
+        let folder = Path::new("state");
+        let mb = 1024 * 1024;
+        assert_eq!(low_disk_warning(&FixedSpace(256 * mb), folder, 256).unwrap(), None);
+        let warning = low_disk_warning(&FixedSpace(256 * mb - 1), folder, 256).unwrap().unwrap();
+        assert_eq!(warning, format!("low-disk: state has 255 MB free ({} bytes), below --min-free-mb 256", 256 * mb - 1));
+        assert_eq!(low_disk_warning(&FixedSpace(0), folder, 0).unwrap(), None, "0 turns the warning off");
+    }
+
+    #[test]
+    fn the_nearest_existing_folder_is_asked() {
+        let base = std::env::temp_dir().join(format!("sentry-disk-space-{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        assert_eq!(existing_ancestor(&base.join("releases/omega-r1")), base);
+        assert_eq!(existing_ancestor(&base), base);
+        assert_eq!(existing_ancestor(Path::new("")), PathBuf::from("."));
+        assert_eq!(existing_ancestor(Path::new("no-such-folder-here/deeper")), PathBuf::from("."));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    #[test]
+    fn the_system_reports_space_for_a_real_folder() {
+        assert!(SystemSpace.available_bytes(&std::env::temp_dir()).unwrap() > 0);
+        assert!(SystemSpace.available_bytes(Path::new("/no/such/folder/anywhere")).is_err());
+    }
+}
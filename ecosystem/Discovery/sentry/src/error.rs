@@ -0,0 +1,223 @@
+//! `SentryError`: what the library functions return when they fail.
+//!
+//! The functions other crates call (`build_manifest`, `load_manifest`, `persist_manifest`,
+//! `verify_bins`, and `Verifier`) return this enum, so a caller can tell "the bins folder is not
+//! there" from "the manifest is broken on line 7" from "this entry cannot be read" without reading
+//! the message. Every variant about a file carries that file's path.
+//!
+//! It implements `std::error::Error`. `Display` describes only this error; the cause (an
+//! `io::Error`, for instance) comes from `source()`, as the standard library recommends.
+//! `chain()` joins both into one line, `Unable to read manifest "m.txt": No such file or directory`,
+//! which is what the CLI prints.
+//!
+//! `run_cli` returns a `SentryError` too. Problems that are not about the manifest or the files
+//! (a bad flag, an unreachable peer) arrive as `Message`, and `exit_code()` picks the process
+//! exit code from the variant. `From<String>` and `From<SentryError> for String` let code on
+//! either side use `?`.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::safe_path::PathProblem;
+use crate::version::Version;
+
+/// Exit code for a `--bins-dir` that does not exist.
+pub const EXIT_BINS_DIR_MISSING: i32 = 5;
+/// Exit code for a manifest that cannot be read, parsed, or trusted.
+pub const EXIT_MANIFEST_UNUSABLE: i32 = 6;
+/// Exit code for a listed file that cannot be read. 7 is left out: it is the self-check's code.
+pub const EXIT_ENTRY_UNREADABLE: i32 = 8;
+/// Exit code for a `build` refused because the disk is too full to hold the release.
+pub const EXIT_INSUFFICIENT_SPACE: i32 = 9;
+
+#[derive(Debug)]
+pub enum SentryError {
+    /// The bins directory given to `build_manifest` or `verify_bins` does not exist.
+    BinsDirMissing(PathBuf),
+    /// The manifest file could not be read.
+    ManifestRead { path: PathBuf, source: io::Error },
+    /// A manifest line is damaged. `line` counts from 1; it is 0 when the problem is the manifest
+    /// as a whole, such as a missing `release_id=`.
+    ManifestParse { path: PathBuf, line: usize, reason: String },
+    /// The manifest's `min_reader_version=` is newer than this Sentry (see `version`), so it was
+    /// not read any further.
+    ManifestTooNew { path: PathBuf, required: Version, current: Version },
+    /// A file the manifest lists (or `build` found) could not be read. `entry` is its relative
+    /// path in the manifest and `path` where Sentry looked.
+    EntryUnreadable { entry: String, path: PathBuf, source: io::Error },
+    /// Another read or write failed. `context` says which file and what for.
+    Io { context: String, source: io::Error },
+    /// A value such as a release id is not in the expected format.
+    Parse(String),
+    /// The files do not allow the request: no binaries to record, two entries with one path, or a
+    /// release folder that already holds different binaries.
+    Verification(String),
+    /// A manifest entry names a file outside the bins directory (see `safe_path`). `entry` is the
+    /// entry's name and `path` the value as written; the field is shown as `rel=...` when that
+    /// is the one at fault.
+    UnsafePath { entry: String, path: String, problem: PathProblem },
+    /// `build` needs `required` bytes but the filesystem holding `path` has only `available`
+    /// (see `disk_space`). Nothing was written.
+    InsufficientSpace { path: PathBuf, required: u64, available: u64 },
+    /// Anything else the CLI reports, already worded for a person (a bad flag, a refused push).
+    Message(String),
+}
+
+impl SentryError {
+    /// An `Io` error; `context` reads like "Unable to write \"releases/x/manifest.txt\"".
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        SentryError::Io { context: context.into(), source }
+    }
+
+    /// This error and every `source()` below it, joined with `: `.
+    pub fn chain(&self) -> String {
+        let mut text = self.to_string();
+        let mut cause = self.source();
+        while let Some(err) = cause {
+            text.push_str(&format!(": {err}"));
+            cause = err.source();
+        }
+        text
+    }
+
+    /// The process exit code for this error: `EXIT_BINS_DIR_MISSING`, `EXIT_MANIFEST_UNUSABLE`,
+    /// `EXIT_ENTRY_UNREADABLE`, `EXIT_INSUFFICIENT_SPACE`, or 1 for everything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SentryError::BinsDirMissing(_) => EXIT_BINS_DIR_MISSING,
+            SentryError::ManifestRead { .. }
+            | SentryError::ManifestParse { .. }
+            | SentryError::ManifestTooNew { .. }
+            | SentryError::UnsafePath { .. } => EXIT_MANIFEST_UNUSABLE,
+            SentryError::EntryUnreadable { .. } => EXIT_ENTRY_UNREADABLE,
+            SentryError::InsufficientSpace { .. } => EXIT_INSUFFICIENT_SPACE,
+            SentryError::Io { .. } | SentryError::Parse(_) | SentryError::Verification(_) | SentryError::Message(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for SentryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SentryError::BinsDirMissing(path) => write!(f, "Binary directory {path:?} not found"),
+            SentryError::ManifestRead { path, .. } => write!(f, "Unable to read manifest {path:?}"),
+            SentryError::ManifestParse { path, line: 0, reason } => write!(f, "Manifest {path:?}: {reason}"),
+            SentryError::ManifestParse { path, line, reason } => write!(f, "Manifest {path:?} line {line}: {reason}"),
+            SentryError::ManifestTooNew { path, required, current } => {
+                write!(f, "Manifest {path:?} requires sentry >= {required}, this is {current}; upgrade Sentry on this host to read it")
+            }
+            SentryError::EntryUnreadable { entry, path, .. } => write!(f, "Unable to read entry {entry:?} at {path:?}"),
+            SentryError::Io { context, .. } => f.write_str(context),
+            SentryError::InsufficientSpace { path, required, available } => write!(
+                f,
+                "Not enough disk space for the release: {required} bytes needed, {available} bytes available on the filesystem holding {path:?} (pass --skip-space-check to try anyway)"
+            ),
+            SentryError::Parse(message) | SentryError::Verification(message) | SentryError::Message(message) => f.write_str(message),
+            SentryError::UnsafePath { entry, path, problem } => {
+                write!(f, "Manifest entry {entry:?} names {path:?}, which {problem}; Sentry only reads files inside the bins directory")?;
+                if *problem == PathProblem::Absolute {
+                    f.write_str(" (pass --trust-absolute-paths for a trusted manifest with absolute file paths)")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for SentryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SentryError::ManifestRead { source, .. } | SentryError::EntryUnreadable { source, .. } | SentryError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<SentryError> for String {
+    fn from(err: SentryError) -> Self {
+        err.chain()
+    }
+}
+
+impl From<String> for SentryError {
+    fn from(message: String) -> Self {
+        SentryError::Message(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn not_found() -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, "No such file or directory")
+    }
+
+    #[test]
+    fn display_and_chain_are_stable_for_the_cli() {
+        let cases = [
+            (SentryError::BinsDirMissing(PathBuf::from("bins")), "Binary directory \"bins\" not found"),
+            (SentryError::ManifestRead { path: PathBuf::from("m.txt"), source: not_found() }, "Unable to read manifest \"m.txt\": No such file or directory"),
+            (SentryError::ManifestParse { path: PathBuf::from("m.txt"), line: 7, reason: "invalid mode \"purple\"".to_string() }, "Manifest \"m.txt\" line 7: invalid mode \"purple\""),
+            (SentryError::ManifestParse { path: PathBuf::from("m.txt"), line: 0, reason: "missing release_id".to_string() }, "Manifest \"m.txt\": missing release_id"),
+            (
+                SentryError::ManifestTooNew { path: PathBuf::from("m.txt"), required: Version::parse("9.0.0").unwrap(), current: Version::parse("1.2.3").unwrap() },
+                "Manifest \"m.txt\" requires sentry >= 9.0.0, this is 1.2.3; upgrade Sentry on this host to read it",
+            ),
+            (
+                SentryError::EntryUnreadable { entry: "tools/bard".to_string(), path: PathBuf::from("bins/tools/bard"), source: not_found() },
+                "Unable to read entry \"tools/bard\" at \"bins/tools/bard\": No such file or directory",
+            ),
+            (SentryError::io("Unable to write \"out.json\"", not_found()), "Unable to write \"out.json\": No such file or directory"),
+            (SentryError::Parse("bad id".to_string()), "bad id"),
+            (SentryError::Verification("two entries".to_string()), "two entries"),
+            (SentryError::Message("--to is required".to_string()), "--to is required"),
+            (
+                SentryError::UnsafePath { entry: "squire".to_string(), path: "../squire".to_string(), problem: PathProblem::Escapes },
+                "Manifest entry \"squire\" names \"../squire\", which climbs out of its folder with ..; Sentry only reads files inside the bins directory",
+            ),
+            (
+                SentryError::InsufficientSpace { path: PathBuf::from("releases"), required: 10, available: 3 },
+                "Not enough disk space for the release: 10 bytes needed, 3 bytes available on the filesystem holding \"releases\" (pass --skip-space-check to try anyway)",
+            ),
+        ];
+        for (err, expected) in cases {
+            assert_eq!(err.chain(), expected);
+            assert_eq!(String::from(err), expected);
+        }
+        let absolute = SentryError::UnsafePath { entry: "squire".to_string(), path: "/bin/sh".to_string(), problem: PathProblem::Absolute };
+        assert!(absolute.to_string().ends_with("inside the bins directory (pass --trust-absolute-paths for a trusted manifest with absolute file paths)"));
+    }
+
+    #[test]
+    fn display_leaves_the_cause_to_source() {
+        let err = SentryError::ManifestRead { path: PathBuf::from("m.txt"), source: not_found() };
+        assert_eq!(err.to_string(), "Unable to read manifest \"m.txt\"");
+        let source = err.source().and_then(|source| source.downcast_ref::<io::Error>()).unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::NotFound);
+        assert!(SentryError::BinsDirMissing(PathBuf::from("bins")).source().is_none());
+        assert!(SentryError::io("x", not_found()).source().is_some());
+    }
+
+    #[test]
+    fn each_variant_has_its_exit_code() {
+        let codes = [
+            (SentryError::BinsDirMissing(PathBuf::new()), EXIT_BINS_DIR_MISSING),
+            (SentryError::ManifestRead { path: PathBuf::new(), source: not_found() }, EXIT_MANIFEST_UNUSABLE),
+            (SentryError::ManifestParse { path: PathBuf::new(), line: 1, reason: String::new() }, EXIT_MANIFEST_UNUSABLE),
+            (SentryError::ManifestTooNew { path: PathBuf::new(), required: Version::parse("2").unwrap(), current: Version::parse("1").unwrap() }, EXIT_MANIFEST_UNUSABLE),
+            (SentryError::UnsafePath { entry: String::new(), path: String::new(), problem: PathProblem::Empty }, EXIT_MANIFEST_UNUSABLE),
+            (SentryError::EntryUnreadable { entry: String::new(), path: PathBuf::new(), source: not_found() }, EXIT_ENTRY_UNREADABLE),
+            (SentryError::InsufficientSpace { path: PathBuf::new(), required: 2, available: 1 }, EXIT_INSUFFICIENT_SPACE),
+            (SentryError::io("x", not_found()), 1),
+            (SentryError::Parse(String::new()), 1),
+            (SentryError::Verification(String::new()), 1),
+            (SentryError::from("flag".to_string()), 1),
+        ];
+        for (err, code) in codes {
+            assert_eq!(err.exit_code(), code, "{err:?}");
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! `verify --explain`: what kind of change a mismatching file went through.
+//!
+//! "mismatch" says a file changed, not how. Before escalating, an operator wants to know whether
+//! it is a one-byte patch, something glued onto the end, or a different file altogether. The
+//! manifest only holds hashes of the whole file, so the answer is pieced together from what can be
+//! checked:
+//! - the recorded size against the size on disk;
+//! - for a file that grew, whether its first `expected_size` bytes still hash to the recorded
+//!   values (then the old file is intact and something was added after it);
+//! - whether `filetype` still finds the same kind of file.
+//!
+//! That gives one of four kinds:
+//! - `appended`: larger, and the first `expected_size` bytes are the original file;
+//! - `truncated`: smaller, and still the same kind of file. The missing bytes are gone, so whether
+//!   the rest is a true prefix cannot be checked;
+//! - `modified`: the same size and the same kind of file, but other bytes;
+//! - `replaced`: larger without the original at the start, or a different kind of file.
+//!
+//! The report also names the first 4 KiB block (`BLOCK_SIZE`) known to differ. Without hashes per
+//! block that is only known when the size changed: for `appended` it is the block where the new
+//! bytes start, and for `truncated` the block where the file now ends. For the other kinds it is
+//! `null`. Only `verify` reads files again for this; the daemon does not.
+
+use std::fs;
+use std::path::Path;
+
+use crate::digest::{self, DigestAlgorithm};
+use crate::{filetype, hash_bytes, json_escape, BinCheck, ManifestEntry, SentryError};
+
+/// The block size used for `first_differing_block_offset`.
+pub const BLOCK_SIZE: u64 = 4096;
+
+/// How a mismatching file differs from the one recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MismatchKind {
+    Truncated,
+    Appended,
+    Modified,
+    Replaced,
+}
+
+impl MismatchKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MismatchKind::Truncated => "truncated",
+            MismatchKind::Appended => "appended",
+            MismatchKind::Modified => "modified",
+            MismatchKind::Replaced => "replaced",
+        }
+    }
+}
+
+/// One mismatching entry, explained.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Explanation {
+    /// The entry's key, as in `results`.
+    pub rel_path: String,
+    /// The plain status from `results`, e.g. `mismatch` or `type-changed`.
+    pub status: String,
+    /// The size the manifest recorded and the size on disk now.
+    pub expected_size: u64,
+    pub actual_size: u64,
+    /// For a larger file: whether its first `expected_size` bytes match the recorded hashes.
+    /// `None` when the file did not grow, since then there is nothing to compare.
+    pub prefix_matches: Option<bool>,
+    /// Whether the file is now another kind of file (see `filetype`).
+    pub type_changed: bool,
+    pub kind: MismatchKind,
+}
+
+impl Explanation {
+    /// Sort the facts into a `MismatchKind` and keep them for the report.
+    pub fn new(rel_path: &str, status: &str, expected_size: u64, actual_size: u64, prefix_matches: Option<bool>, type_changed: bool) -> Self {
+        let kind = if type_changed {
+            MismatchKind::Replaced
+        } else if actual_size > expected_size {
+            if prefix_matches == Some(true) { MismatchKind::Appended } else { MismatchKind::Replaced }
+        } else if actual_size < expected_size {
+            MismatchKind::Truncated
+        } else {
+            MismatchKind::Modified
+        };
+        Explanation { rel_path: rel_path.to_string(), status: status.to_string(), expected_size, actual_size, prefix_matches, type_changed, kind }
+    }
+
+    /// Size on disk minus the recorded size; negative for a smaller file.
+    pub fn size_delta(&self) -> i128 {
+        i128::from(self.actual_size) - i128::from(self.expected_size)
+    }
+
+    /// Start of the first `BLOCK_SIZE` block known to differ, when it can be known (see the module notes).
+    pub fn first_differing_block_offset(&self) -> Option<u64> {
+        let block_start = |offset: u64| offset / BLOCK_SIZE * BLOCK_SIZE;
+        match self.kind {
+            MismatchKind::Appended => Some(block_start(self.expected_size)),
+            MismatchKind::Truncated => Some(block_start(self.actual_size)),
+            MismatchKind::Modified | MismatchKind::Replaced => None,
+        }
+    }
+
+    /// One object in the `explain` list, e.g.
+    /// `{"rel_path":"squire","status":"mismatch","kind":"appended","expected_size":8192,"actual_size":8200,...}`.
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        format!(
+            "{{\"rel_path\":\"{}\",\"status\":\"{}\",\"kind\":\"{}\",\"expected_size\":{},\"actual_size\":{},\"size_delta\":{},\"prefix_matches\":{},\"type_changed\":{},\"first_differing_block_offset\":{}}}",
+            json_escape(&self.rel_path),
+            json_escape(&self.status),
+            self.kind.as_str(),
+            self.expected_size,
+            self.actual_size,
+            self.size_delta(),
+            optional(self.prefix_matches.map(|matches| matches.to_string())),
+            self.type_changed,
+            optional(self.first_differing_block_offset().map(|offset| offset.to_string()))
+        )
+    }
+}
+
+/// Read the file behind a mismatching `check` again and explain it against its manifest `entry`.
+pub fn explain_entry(entry: &ManifestEntry, check: &BinCheck, full_path: &Path) -> Result<Explanation, SentryError> {
+    let data = fs::read(full_path).map_err(|source| SentryError::EntryUnreadable { entry: entry.rel_path.clone(), path: full_path.to_path_buf(), source })?;
+    let actual_size = data.len() as u64;
+    let prefix_matches = (actual_size > entry.size).then(|| {
+        // The manifest hash is short, so every recorded digest (SHA-256 by default) has to agree as well.
+        let prefix = &data[..entry.size as usize];
+        let algorithms: Vec<DigestAlgorithm> = entry.digests.iter().map(|(algorithm, _)| *algorithm).collect();
+        let digests = digest::digests_of(prefix, &algorithms);
+        hash_bytes(prefix) == entry.hash
+            && entry.digests.iter().all(|(algorithm, expected)| {
+                digests.iter().any(|(other, observed)| other == algorithm && observed.eq_ignore_ascii_case(expected))
+            })
+    });
+    // The check only sniffs when the manifest recorded a type; sniff here for the same entries.
+    let type_changed = entry.filetype.is_some_and(|expected| filetype::sniff(&data) != expected);
+    Ok(Explanation::new(&entry.rel_path, check.status(), entry.size, actual_size, prefix_matches, type_changed))
+}
+
+/// The `explain` field: a JSON list of every explanation, in `results` order.
+pub fn explanations_json(explanations: &[Explanation]) -> String {
+    format!("[{}]", explanations.iter().map(Explanation::to_json).collect::<Vec<_>>().join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_facts_sort_into_the_four_kinds() {
+        let kind = |expected, actual, prefix, type_changed| Explanation::new("squire", "mismatch", expected, actual, prefix, type_changed).kind;
+        assert_eq!(kind(100, 120, Some(true), false), MismatchKind::Appended);
+        assert_eq!(kind(100, 120, Some(false), false), MismatchKind::Replaced);
+        assert_eq!(kind(100, 80, None, false), MismatchKind::Truncated);
+        assert_eq!(kind(100, 100, None, false), MismatchKind::Modified);
+        assert_eq!(kind(100, 120, Some(true), true), MismatchKind::Replaced, "another kind of file wins");
+        assert_eq!(kind(100, 100, None, true), MismatchKind::Replaced);
+    }
+
+    #[test]
+    fn the_first_differing_block_is_known_only_when_the_size_changed() {
+        let offset = |expected, actual, prefix| Explanation::new("squire", "mismatch", expected, actual, prefix, false).first_differing_block_offset();
+        assert_eq!(offset(8_192, 8_200, Some(true)), Some(8_192));
+        assert_eq!(offset(8_191, 8_200, Some(true)), Some(4_096));
+        assert_eq!(offset(10_000, 4_095, None), Some(0));
+        assert_eq!(offset(10_000, 10_000, None), None);
+        assert_eq!(offset(10, 20, Some(false)), None);
+    }
+
+    #[test]
+    fn json_carries_the_delta_and_nulls() {
+        let truncated = Explanation::new("tools/\"bard\"", "mismatch", 5_000, 4_000, None, false);
+        assert_eq!(truncated.size_delta(), -1_000);
+        assert_eq!(
+            truncated.to_json(),
+            "{\"rel_path\":\"tools/\\\"bard\\\"\",\"status\":\"mismatch\",\"kind\":\"truncated\",\"expected_size\":5000,\"actual_size\":4000,\"size_delta\":-1000,\"prefix_matches\":null,\"type_changed\":false,\"first_differing_block_offset\":0}"
+        );
+        let appended = Explanation::new("squire", "mismatch", 10, 12, Some(true), false);
+        assert!(appended.to_json().contains("\"size_delta\":2,\"prefix_matches\":true,\"type_changed\":false,\"first_differing_block_offset\":0}"));
+        assert_eq!(explanations_json(&[]), "[]");
+        assert_eq!(explanations_json(&[appended.clone(), appended]).matches("\"kind\":\"appended\"").count(), 2);
+    }
+}
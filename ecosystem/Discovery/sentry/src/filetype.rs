@@ -0,0 +1,232 @@
+//! What kind of file each manifest entry is, judged by its first bytes (`filetype=` fields).
+//!
+//! A hash says a file did not change; it does not say the file was right to begin with. A
+//! release once shipped `squire` as a 12-byte shell script left over from debugging, and the
+//! manifest recorded its hash like any other. `build` therefore looks at the start of every file
+//! and records what it is:
+//! - `elf`: Linux and BSD programs, which start with `0x7F 'E' 'L' 'F'`;
+//! - `pe`: Windows programs, which start with `MZ`;
+//! - `macho`: macOS programs (`FE ED FA CE` / `FE ED FA CF` in either byte order, or `CA FE BA BE`
+//!   for a universal binary);
+//! - `script`: anything starting with `#!`;
+//! - `text`: other readable text;
+//! - `other`: everything else, including empty files.
+//!
+//! Only the first `SNIFF_BYTES` bytes are looked at, so this is a quick sanity check, not a parser.
+//! With `build --expect-executables`, top-level entries that are not `elf`, `pe`, or `macho`, or
+//! that are smaller than `--min-executable-size`, get a manifest warning. `verify` sniffs the file
+//! again and reports `type-changed` when the kind differs from the recorded one.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::ManifestEntry;
+
+/// How many bytes from the start of a file are enough to tell its kind.
+pub const SNIFF_BYTES: usize = 512;
+/// `--min-executable-size` when not given: real programs are practically never under 4 KiB.
+pub const DEFAULT_MIN_EXECUTABLE_SIZE: u64 = 4096;
+
+/// The kinds of file `sniff` tells apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileType {
+    Elf,
+    Pe,
+    Macho,
+    Script,
+    Text,
+    Other,
+}
+
+impl FileType {
+    /// Name used in manifests and JSON, e.g. `elf`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FileType::Elf => "elf",
+            FileType::Pe => "pe",
+            FileType::Macho => "macho",
+            FileType::Script => "script",
+            FileType::Text => "text",
+            FileType::Other => "other",
+        }
+    }
+
+    /// Read a name written by `as_str`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "elf" => Some(FileType::Elf),
+            "pe" => Some(FileType::Pe),
+            "macho" => Some(FileType::Macho),
+            "script" => Some(FileType::Script),
+            "text" => Some(FileType::Text),
+            "other" => Some(FileType::Other),
+            _ => None,
+        }
+    }
+
+    /// A compiled program for some system. Scripts do run, but they are not what a release of
+    /// compiled binaries is meant to contain.
+    pub fn is_executable(self) -> bool {
+        matches!(self, FileType::Elf | FileType::Pe | FileType::Macho)
+    }
+}
+
+/// The kind of file whose first bytes are `head` (at most `SNIFF_BYTES` are looked at).
+pub fn sniff(head: &[u8]) -> FileType {
+    let head = &head[..head.len().min(SNIFF_BYTES)];
+    const MACHO_MAGICS: [[u8; 4]; 5] = [
+        [0xFE, 0xED, 0xFA, 0xCE],
+        [0xFE, 0xED, 0xFA, 0xCF],
+        [0xCE, 0xFA, 0xED, 0xFE],
+        [0xCF, 0xFA, 0xED, 0xFE],
+        [0xCA, 0xFE, 0xBA, 0xBE],
+    ];
+    if head.starts_with(b"\x7FELF") {
+        FileType::Elf
+    } else if head.starts_with(b"MZ") {
+        FileType::Pe
+    } else if MACHO_MAGICS.iter().any(|magic| head.starts_with(magic)) {
+        FileType::Macho
+    } else if head.starts_with(b"#!") {
+        FileType::Script
+    } else if !head.is_empty() && looks_like_text(head) {
+        FileType::Text
+    } else {
+        FileType::Other
+    }
+}
+
+/// `sniff` for a file on disk, reading only its first `SNIFF_BYTES` bytes.
+pub fn sniff_file(path: &Path) -> io::Result<FileType> {
+    let mut head = Vec::with_capacity(SNIFF_BYTES);
+    File::open(path)?.take(SNIFF_BYTES as u64).read_to_end(&mut head)?;
+    Ok(sniff(&head))
+}
+
+/// UTF-8 without NUL bytes or control characters other than tabs and line breaks. A character
+/// cut in half at the end of `head` still counts, since only the start of the file was read.
+fn looks_like_text(head: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => std::str::from_utf8(&head[..err.valid_up_to()]).unwrap_or_default(),
+        Err(_) => return false,
+    };
+    valid.chars().all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r' | '\x0C'))
+}
+
+/// `build --expect-executables`: one warning per top-level entry (directly in `--bins-dir`) that
+/// is not a compiled program or is smaller than `min_size` bytes. Entries in subfolders are left
+/// alone, since those often hold data files or helper scripts on purpose.
+pub fn executable_warnings(entries: &[ManifestEntry], min_size: u64) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for entry in entries.iter().filter(|entry| !entry.rel_path.contains('/')) {
+        match entry.filetype {
+            Some(filetype) if !filetype.is_executable() => {
+                warnings.push(format!("{} is {}, not a recognized executable (elf, pe, or macho)", entry.rel_path, filetype.as_str()));
+            }
+            _ => {}
+        }
+        if entry.size < min_size {
+            warnings.push(format!("{} is only {} bytes, under the {} byte minimum for an executable", entry.rel_path, entry.size, min_size));
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// The first bytes of a 64-bit little-endian x86-64 ELF executable.
+    fn elf_header() -> Vec<u8> {
+        let mut header = b"\x7FELF\x02\x01\x01\x00".to_vec();
+        header.resize(16, 0);
+        header.extend_from_slice(&[0x02, 0x00, 0x3E, 0x00, 0x01, 0x00, 0x00, 0x00]);
+        header.resize(64, 0);
+        header
+    }
+
+    fn entry(rel_path: &str, size: u64, filetype: Option<FileType>) -> ManifestEntry {
+        ManifestEntry {
+            name: rel_path.rsplit('/').next().unwrap().to_string(),
+            rel_path: rel_path.to_string(),
+            path: rel_path.to_string(),
+            hash: "0011223344556677".to_string(),
+            size,
+            digests: Vec::new(),
+            sig: None,
+            mode: None,
+            filetype,
+            owner: None,
+            required_signer: None,
+        }
+    }
+
+    #[test]
+    fn programs_are_told_apart_by_their_magic_numbers() {
+        assert_eq!(sniff(&elf_header()), FileType::Elf);
+        assert_eq!(sniff(b"MZ\x90\x00\x03\x00\x00\x00"), FileType::Pe);
+        for magic in [[0xFE, 0xED, 0xFA, 0xCF], [0xCF, 0xFA, 0xED, 0xFE], [0xCA, 0xFE, 0xBA, 0xBE]] {
+            assert_eq!(sniff(&[&magic[..], &[0; 12]].concat()), FileType::Macho);
+        }
+        assert_eq!(sniff(b"\x7FEL"), FileType::Other, "a cut-off magic number is not enough");
+        assert!([FileType::Elf, FileType::Pe, FileType::Macho].iter().all(|filetype| filetype.is_executable()));
+    }
+
+    #[test]
+    fn shebangs_text_and_everything_else() {
+        assert_eq!(sniff(b"#!/bin/sh\necho hi\n"), FileType::Script);
+        assert_eq!(sniff(b"#!/usr/bin/env python3\n"), FileType::Script);
+        assert_eq!(sniff(b"release notes\n\tcafe\r\n"), FileType::Text);
+        assert_eq!(sniff("caf\u{e9}".as_bytes()), FileType::Text);
+        // A character cut in half at the end of the sniffed bytes still reads as text.
+        let mut long = "a".repeat(SNIFF_BYTES - 1).into_bytes();
+        long.extend_from_slice("\u{e9}".as_bytes());
+        assert_eq!(sniff(&long), FileType::Text);
+        assert_eq!(sniff(b"text with a \0 byte"), FileType::Other);
+        assert_eq!(sniff(b"\xFF\xFEbinary"), FileType::Other);
+        assert_eq!(sniff(b""), FileType::Other);
+        assert!(!FileType::Script.is_executable() && !FileType::Text.is_executable());
+
+        for filetype in [FileType::Elf, FileType::Pe, FileType::Macho, FileType::Script, FileType::Text, FileType::Other] {
+            assert_eq!(FileType::from_name(filetype.as_str()), Some(filetype));
+        }
+        assert_eq!(FileType::from_name("ELF"), None);
+    }
+
+    #[test]
+    fn files_on_disk_are_sniffed_from_their_start() {
+        let dir = std::env::temp_dir().join(format!("sentry-filetype-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut program = elf_header();
+        program.resize(10_000, 0xAB);
+        fs::write(dir.join("squire"), &program).unwrap();
+        fs::write(dir.join("hub.sh"), b"#!/bin/sh\n").unwrap();
+        assert_eq!(sniff_file(&dir.join("squire")).unwrap(), FileType::Elf);
+        assert_eq!(sniff_file(&dir.join("hub.sh")).unwrap(), FileType::Script);
+        assert!(sniff_file(&dir.join("missing")).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn top_level_entries_must_look_like_programs_of_a_believable_size() {
+        let entries = [
+            entry("squire", 9_000, Some(FileType::Elf)),
+            entry("stub", 100, Some(FileType::Elf)),
+            entry("hub", 9_000, Some(FileType::Script)),
+            entry("tools/helper.sh", 10, Some(FileType::Script)),
+            entry("legacy", 9_000, None),
+        ];
+        let warnings = executable_warnings(&entries, DEFAULT_MIN_EXECUTABLE_SIZE);
+        assert_eq!(
+            warnings,
+            [
+                "stub is only 100 bytes, under the 4096 byte minimum for an executable",
+                "hub is script, not a recognized executable (elf, pe, or macho)",
+            ]
+        );
+        assert!(executable_warnings(&entries, 0).iter().all(|warning| warning.starts_with("hub is script")));
+    }
+}
@@ -0,0 +1,286 @@
+//! `hash-dir`: list every file under a folder with its SHA-256, one JSON line per file.
+//!
+//! People comparing a deployment folder against a release used to glue `find`, `sha256sum`, and
+//! `sort` together, and every host sorted or formatted a little differently. This module does the
+//! same job in one fixed way:
+//! 1. walk the folder recursively, never following symbolic links (a link could point outside the
+//!    folder, or loop back into it, so links are listed as skipped instead);
+//! 2. leave out anything matching an `--ignore` pattern; an ignored folder is not entered at all;
+//! 3. stream each file through SHA-256 in small chunks, so a large binary never sits in memory;
+//! 4. sort by the relative path written with `/`, so Windows and Unix hosts print the same list.
+//!
+//! Each file becomes `{"path":"bin/sentry","sha256":"<hex>","size":123}`, the same three facts a
+//! manifest entry records. The last line sums everything up with a digest of digests: SHA-256
+//! over `path|sha256|size\n` for every file in order. Two folders with the same aggregate digest
+//! hold the same files with the same contents under the same names.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use crate::sha256::{self, Sha256};
+use crate::{json_escape, Mode};
+
+/// Bytes read per chunk while hashing a file.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// One hashed file, with its path relative to the walked folder.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileDigest {
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Everything `hash_dir` found.
+#[derive(Clone, Debug, Default)]
+pub struct DirListing {
+    /// Hashed files, sorted by `path`.
+    pub files: Vec<FileDigest>,
+    /// Relative paths of symbolic links that were not followed, sorted.
+    pub skipped_symlinks: Vec<String>,
+}
+
+impl DirListing {
+    /// SHA-256 over `path|sha256|size\n` for every file, in sorted order. An empty folder gives
+    /// the hash of no bytes at all.
+    pub fn aggregate_digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        for file in &self.files {
+            hasher.update(format!("{}|{}|{}\n", file.path, file.sha256, file.size).as_bytes());
+        }
+        sha256::to_hex(&hasher.finalize())
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+}
+
+/// Walk `root` and hash every regular file that no pattern in `ignore` matches.
+pub fn hash_dir(root: &Path, ignore: &[String]) -> Result<DirListing, String> {
+    if !root.is_dir() {
+        return Err(format!("Directory {:?} not found", root));
+    }
+    let mut listing = DirListing::default();
+    walk(root, "", ignore, &mut listing)?;
+    listing.files.sort_by(|a, b| a.path.cmp(&b.path));
+    listing.skipped_symlinks.sort();
+    Ok(listing)
+}
+
+/// Visit one folder. `prefix` is the folder's relative path with a trailing `/` (empty at the top).
+fn walk(folder: &Path, prefix: &str, ignore: &[String], listing: &mut DirListing) -> Result<(), String> {
+    let entries = fs::read_dir(folder).map_err(|err| format!("Unable to read directory {:?}: {err}", folder))?;
+    for entry in entries {
+        let entry = entry.map_err(|err| format!("Failed to read an entry of {:?}: {err}", folder))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let relative = format!("{prefix}{name}");
+        if is_ignored(ignore, &name, &relative) {
+            continue;
+        }
+
+        // `file_type` describes the entry itself, so a link is seen as a link, not as its target.
+        let kind = entry.file_type().map_err(|err| format!("Failed to read the type of {:?}: {err}", entry.path()))?;
+        if kind.is_symlink() {
+            listing.skipped_symlinks.push(relative);
+        } else if kind.is_dir() {
+            walk(&entry.path(), &format!("{relative}/"), ignore, listing)?;
+        } else if kind.is_file() {
+            let (sha256, size) = hash_file(&entry.path())?;
+            listing.files.push(FileDigest { path: relative, sha256, size });
+        }
+        // Sockets, pipes, and devices have no contents worth hashing; they are left out.
+    }
+    Ok(())
+}
+
+/// Hash one file chunk by chunk. Returns the lowercase hex digest and the byte count.
+pub fn hash_file(path: &Path) -> Result<(String, u64), String> {
+    let mut file = File::open(path).map_err(|err| format!("Failed to open {:?}: {err}", path))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; CHUNK_LEN];
+    let mut size = 0u64;
+    loop {
+        let read = file.read(&mut buffer).map_err(|err| format!("Failed to read {:?}: {err}", path))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((sha256::to_hex(&hasher.finalize()), size))
+}
+
+/// A pattern without `/` is compared with the entry's own name at any depth (`*.log`, `target`);
+/// a pattern with `/` is compared with the whole relative path (`build/*.tmp`, `docs/**`).
+fn is_ignored(patterns: &[String], name: &str, relative: &str) -> bool {
+    patterns.iter().any(|pattern| {
+        if pattern.contains('/') {
+            glob_match(pattern.trim_start_matches('/'), relative)
+        } else {
+            glob_match(pattern, name)
+        }
+    })
+}
+
+/// Shell-style matching: `*` matches any run of characters except `/`, `**` matches anything
+/// including `/`, and `?` matches one character other than `/`. Everything else is literal.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            // `**/` may also match no folders at all, so `docs/**/a` matches `docs/a`.
+            let rest = &pattern[2..];
+            if rest.first() == Some(&'/') && match_from(&rest[1..], text) {
+                return true;
+            }
+            (0..=text.len()).any(|skip| match_from(rest, &text[skip..]))
+        }
+        Some('*') => {
+            let rest = &pattern[1..];
+            // Try every split point up to the next `/`.
+            for skip in 0..=text.len() {
+                if match_from(rest, &text[skip..]) {
+                    return true;
+                }
+                if text.get(skip) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => text.first().is_some_and(|c| *c != '/') && match_from(&pattern[1..], &text[1..]),
+        Some(literal) => text.first() == Some(literal) && match_from(&pattern[1..], &text[1..]),
+    }
+}
+
+/// The JSON lines printed by `hash-dir`: one per file, then the summary line.
+pub fn render_lines(mode: Mode, root: &Path, listing: &DirListing) -> String {
+    let mut output = String::new();
+    for file in &listing.files {
+        output.push_str(&format!(
+            "{{\"path\":\"{}\",\"sha256\":\"{}\",\"size\":{}}}\n",
+            json_escape(&file.path),
+            file.sha256,
+            file.size
+        ));
+    }
+    let skipped = listing
+        .skipped_symlinks
+        .iter()
+        .map(|path| format!("\"{}\"", json_escape(path)))
+        .collect::<Vec<_>>()
+        .join(",");
+    output.push_str(&format!(
+        "{{\"action\":\"hash-dir\",\"mode\":\"{}\",\"root\":\"{}\",\"count\":{},\"total_size\":{},\"digest\":\"{}\",\"skipped_symlinks\":[{}]}}\n",
+        mode.as_str(),
+        json_escape(&root.to_string_lossy()),
+        listing.files.len(),
+        listing.total_size(),
+        listing.aggregate_digest(),
+        skipped
+    ));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sentry-hash-dir-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A folder under `base` holding `files` (relative path, contents).
+    fn tree(base: &Path, files: &[(&str, &[u8])]) -> PathBuf {
+        let root = base.join("tree");
+        fs::create_dir_all(&root).unwrap();
+        for (relative, contents) in files {
+            let path = root.join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    fn paths(listing: &DirListing) -> Vec<&str> {
+        listing.files.iter().map(|file| file.path.as_str()).collect()
+    }
+
+    #[test]
+    fn nested_files_are_listed_sorted_with_forward_slashes() {
+        let base = temp_dir("nested");
+        let root = tree(&base, &[("sentry", b"abc"), ("bin/tools/hub", b"hub"), ("bin/bard", b"")]);
+        let listing = hash_dir(&root, &[]).unwrap();
+        assert_eq!(paths(&listing), ["bin/bard", "bin/tools/hub", "sentry"]);
+        assert_eq!(listing.files[2].sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(listing.files[2].size, 3);
+        assert_eq!(listing.total_size(), 6);
+        assert_eq!(hash_file(&root.join("bin/bard")).unwrap(), (sha256::to_hex(&sha256::sha256(b"")), 0));
+    }
+
+    #[test]
+    fn ignored_names_and_paths_are_left_out_and_ignored_folders_not_entered() {
+        let base = temp_dir("ignore");
+        let root = tree(
+            &base,
+            &[("sentry", b"1"), ("sentry.log", b"2"), ("target/debug/x", b"3"), ("docs/a/b.md", b"4"), ("build/keep.bin", b"5"), ("build/x.tmp", b"6")],
+        );
+        let ignore: Vec<String> = ["*.log", "target", "docs/**", "build/*.tmp"].iter().map(|p| p.to_string()).collect();
+        assert_eq!(paths(&hash_dir(&root, &ignore).unwrap()), ["build/keep.bin", "sentry"]);
+
+        assert!(glob_match("docs/**/a", "docs/a"));
+        assert!(glob_match("s?ntry", "sentry") && !glob_match("a?b", "a/b"));
+        assert!(!glob_match("*.log", "logs/x.log"), "* stops at /");
+    }
+
+    #[test]
+    fn an_empty_folder_hashes_to_the_digest_of_nothing() {
+        let base = temp_dir("empty");
+        let root = tree(&base, &[]);
+        let listing = hash_dir(&root, &[]).unwrap();
+        assert!(listing.files.is_empty());
+        assert_eq!(listing.aggregate_digest(), sha256::to_hex(&sha256::sha256(b"")));
+        let summary = render_lines(Mode::Blue, &root, &listing);
+        assert!(summary.contains("\"count\":0,\"total_size\":0"), "{summary}");
+        assert!(hash_dir(&base.join("missing"), &[]).unwrap_err().contains("not found"));
+    }
+
+    #[test]
+    fn aggregate_digest_is_stable_and_changes_with_any_file() {
+        let base = temp_dir("stable");
+        let root = tree(&base, &[("a", b"one"), ("sub/b", b"two")]);
+        let first = hash_dir(&root, &[]).unwrap();
+        let second = hash_dir(&root, &[]).unwrap();
+        assert_eq!(first.aggregate_digest(), second.aggregate_digest());
+        assert_eq!(render_lines(Mode::Blue, &root, &first), render_lines(Mode::Blue, &root, &second));
+
+        fs::write(root.join("sub/b"), b"twO").unwrap();
+        assert_ne!(hash_dir(&root, &[]).unwrap().aggregate_digest(), first.aggregate_digest());
+        fs::write(root.join("sub/b"), b"two").unwrap();
+        fs::rename(root.join("a"), root.join("c")).unwrap();
+        assert_ne!(hash_dir(&root, &[]).unwrap().aggregate_digest(), first.aggregate_digest(), "a rename changes the digest");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symbolic_links_are_reported_instead_of_followed() {
+        let base = temp_dir("symlink");
+        let root = tree(&base, &[("sentry", b"1")]);
+        std::os::unix::fs::symlink(&root, root.join("loop")).unwrap();
+        let listing = hash_dir(&root, &[]).unwrap();
+        assert_eq!(paths(&listing), ["sentry"]);
+        assert_eq!(listing.skipped_symlinks, ["loop"]);
+    }
+}
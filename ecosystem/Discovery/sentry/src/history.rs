@@ -0,0 +1,267 @@
+//! Verification history: the daemon's compact cycle log and the `report` that reads it.
+//!
+//! With `daemon --log-file <path>`, every pass appends one line to that file:
+//!
+//! ```text
+//! sentry-cycle {"unix":1792142202,"release_id":"20261016-3a1f6ba04531","entries":3,"failed":["b/squire:mismatch"]}
+//! ```
+//!
+//! The line starts with a fixed prefix, then holds a small JSON object: when the pass ran, which
+//! release it checked, how many entries it looked at, and the `<rel_path>:<status>` of every entry
+//! that failed. A clean pass has `"failed":[]`. Appending one short line per pass keeps the file
+//! cheap to write and easy to `tail`.
+//!
+//! `report --log-file <path>` turns the file back into numbers a person can read: how many passes
+//! ran, how many failed, which entries failed how often, the longest run of clean passes, and when
+//! the first and last failures happened. Operators sometimes `cat` other notes into the file, so any
+//! line without the prefix (or with a broken object after it) is skipped and counted as unparsed
+//! rather than stopping the report.
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::json::{self, JsonValue};
+use crate::{json_escape, utc_date, BinCheck, Mode};
+
+/// Every cycle line starts with this.
+pub const CYCLE_PREFIX: &str = "sentry-cycle ";
+
+/// The cycle line for one daemon pass, without the trailing newline.
+pub fn cycle_line(unix: u64, release_id: &str, report: &[BinCheck]) -> String {
+    let failed: Vec<String> = report
+        .iter()
+        .filter(|check| !check.matched())
+        .map(|check| format!("\"{}:{}\"", json_escape(&check.rel_path), check.status()))
+        .collect();
+    format!(
+        "{CYCLE_PREFIX}{{\"unix\":{},\"release_id\":\"{}\",\"entries\":{},\"failed\":[{}]}}",
+        unix,
+        json_escape(release_id),
+        report.len(),
+        failed.join(",")
+    )
+}
+
+/// Add one line to the log file, creating it when needed. Appends are small single writes, so
+/// a reader never sees half a line from a finished pass.
+pub fn append_cycle(path: &Path, line: &str) -> Result<(), String> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("Unable to open {:?}: {err}", path))?;
+    file.write_all(format!("{line}\n").as_bytes())
+        .map_err(|err| format!("Unable to append to {:?}: {err}", path))
+}
+
+/// One parsed cycle line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Cycle {
+    unix: u64,
+    /// `<rel_path>:<status>` of each failed entry.
+    failed: Vec<String>,
+}
+
+/// What `report` found in the log.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HistoryReport {
+    /// Only cycles at or after this Unix time were counted (`--since`).
+    pub since_unix: Option<u64>,
+    pub cycles: u64,
+    pub failed_cycles: u64,
+    /// Entry (relative path) to the number of cycles it failed in.
+    pub failures_by_entry: BTreeMap<String, u64>,
+    /// Most clean cycles in a row, in file order.
+    pub longest_clean_streak: u64,
+    pub first_failure_unix: Option<u64>,
+    pub last_failure_unix: Option<u64>,
+    /// Lines that were not cycle lines, or whose object could not be read.
+    pub unparsed: u64,
+}
+
+/// Read the log file and summarize the cycles at or after `since_unix`.
+pub fn load_report(path: &Path, since_unix: Option<u64>) -> Result<HistoryReport, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("Unable to read {:?}: {err}", path))?;
+    Ok(summarize(&text, since_unix))
+}
+
+/// Summarize log text. Cycles before `since_unix` are left out of every count; unparsed lines are
+/// counted wherever they are, because their time is unknown.
+pub fn summarize(text: &str, since_unix: Option<u64>) -> HistoryReport {
+    let mut report = HistoryReport { since_unix, ..HistoryReport::default() };
+    let mut streak = 0u64;
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let Some(cycle) = parse_cycle(line) else {
+            report.unparsed += 1;
+            continue;
+        };
+        if since_unix.is_some_and(|since| cycle.unix < since) {
+            continue;
+        }
+        report.cycles += 1;
+        if cycle.failed.is_empty() {
+            streak += 1;
+            report.longest_clean_streak = report.longest_clean_streak.max(streak);
+            continue;
+        }
+        streak = 0;
+        report.failed_cycles += 1;
+        report.first_failure_unix = Some(report.first_failure_unix.map_or(cycle.unix, |first| first.min(cycle.unix)));
+        report.last_failure_unix = Some(report.last_failure_unix.map_or(cycle.unix, |last| last.max(cycle.unix)));
+        for failure in &cycle.failed {
+            // The status after the last `:` is dropped; a relative path may itself hold a `:`.
+            let entry = failure.rsplit_once(':').map_or(failure.as_str(), |(entry, _)| entry);
+            *report.failures_by_entry.entry(entry.to_string()).or_default() += 1;
+        }
+    }
+    report
+}
+
+fn parse_cycle(line: &str) -> Option<Cycle> {
+    let object = json::parse(line.trim_end().strip_prefix(CYCLE_PREFIX)?).ok()?;
+    let unix = object.get("unix").and_then(JsonValue::as_f64).filter(|unix| *unix >= 0.0)? as u64;
+    let failed = object
+        .get("failed")?
+        .as_array()?
+        .iter()
+        .map(|item| item.as_str().map(str::to_string))
+        .collect::<Option<Vec<_>>>()?;
+    Some(Cycle { unix, failed })
+}
+
+impl HistoryReport {
+    /// The report as a plain-text table for people.
+    pub fn render_table(&self) -> String {
+        let mut out = String::new();
+        let window = match self.since_unix {
+            Some(since) => format!("since {}", utc_time(since)),
+            None => "whole log".to_string(),
+        };
+        out.push_str(&format!("Sentry verification history ({window})\n"));
+        let rows = [
+            ("Cycles", self.cycles.to_string()),
+            ("Cycles with failures", self.failed_cycles.to_string()),
+            ("Longest clean streak", format!("{} cycles", self.longest_clean_streak)),
+            ("First failure", self.first_failure_unix.map_or("none".to_string(), utc_time)),
+            ("Last failure", self.last_failure_unix.map_or("none".to_string(), utc_time)),
+            ("Unparsed lines", self.unparsed.to_string()),
+        ];
+        for (label, value) in rows {
+            out.push_str(&format!("  {label:<22}{value}\n"));
+        }
+        if !self.failures_by_entry.is_empty() {
+            let width = self.failures_by_entry.keys().map(|entry| entry.chars().count()).max().unwrap_or(0).max(5);
+            out.push_str(&format!("\n  {:<width$}  Failed cycles\n", "Entry"));
+            for (entry, count) in &self.failures_by_entry {
+                out.push_str(&format!("  {entry:<width$}  {count}\n"));
+            }
+        }
+        out
+    }
+
+    /// The report as one JSON object for scripts.
+    pub fn to_json(&self, mode: Mode) -> String {
+        let optional = |value: Option<u64>| value.map_or("null".to_string(), |unix| unix.to_string());
+        let failures: Vec<String> = self
+            .failures_by_entry
+            .iter()
+            .map(|(entry, count)| format!("\"{}\":{}", json_escape(entry), count))
+            .collect();
+        format!(
+            "{{\"action\":\"report\",\"mode\":\"{}\",\"since_unix\":{},\"cycles\":{},\"failed_cycles\":{},\"failures_by_entry\":{{{}}},\"longest_clean_streak\":{},\"first_failure_unix\":{},\"last_failure_unix\":{},\"unparsed\":{}}}",
+            mode.as_str(),
+            optional(self.since_unix),
+            self.cycles,
+            self.failed_cycles,
+            failures.join(","),
+            self.longest_clean_streak,
+            optional(self.first_failure_unix),
+            optional(self.last_failure_unix),
+            self.unparsed
+        )
+    }
+}
+
+/// `2026-10-16 14:03:22 UTC` for a Unix time.
+fn utc_time(unix: u64) -> String {
+    let date = utc_date(unix);
+    let seconds = unix % 86_400;
+    format!(
+        "{}-{}-{} {:02}:{:02}:{:02} UTC",
+        &date[..4],
+        &date[4..6],
+        &date[6..],
+        seconds / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(unix: u64, failed: &[&str]) -> String {
+        let failed: Vec<String> = failed.iter().map(|entry| format!("\"{entry}\"")).collect();
+        format!("{CYCLE_PREFIX}{{\"unix\":{unix},\"release_id\":\"r1\",\"entries\":3,\"failed\":[{}]}}", failed.join(","))
+    }
+
+    fn sample_log() -> String {
+        [
+            line(1_000, &[]),
+            line(1_060, &["squire:mismatch"]),
+            "operator note: restarted yellow".to_string(),
+            line(1_120, &[]),
+            line(1_180, &[]),
+            line(1_240, &[]),
+            format!("{CYCLE_PREFIX}{{\"unix\":\"soon\",\"failed\":[]}}"),
+            line(1_300, &["squire:mismatch", "tools/a:b:mode-mismatch"]),
+            String::new(),
+            line(1_360, &[]),
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn mixed_clean_and_failing_cycles_are_counted() {
+        let report = summarize(&sample_log(), None);
+        assert_eq!((report.cycles, report.failed_cycles, report.longest_clean_streak), (7, 2, 3));
+        assert_eq!((report.first_failure_unix, report.last_failure_unix), (Some(1_060), Some(1_300)));
+        let failures: Vec<(&str, u64)> = report.failures_by_entry.iter().map(|(entry, count)| (entry.as_str(), *count)).collect();
+        assert_eq!(failures, [("squire", 2), ("tools/a:b", 1)]);
+        assert_eq!(report.unparsed, 2, "the note and the line with a text time");
+        assert_eq!(summarize("", None), HistoryReport::default());
+    }
+
+    #[test]
+    fn since_leaves_earlier_cycles_out_but_still_counts_unparsed_lines() {
+        let report = summarize(&sample_log(), Some(1_120));
+        assert_eq!((report.cycles, report.failed_cycles, report.longest_clean_streak), (5, 1, 3));
+        assert_eq!((report.first_failure_unix, report.last_failure_unix), (Some(1_300), Some(1_300)));
+        assert_eq!(report.failures_by_entry.get("squire"), Some(&1));
+        assert_eq!(report.unparsed, 2);
+        assert_eq!(summarize(&sample_log(), Some(2_000)).cycles, 0);
+    }
+
+    #[test]
+    fn json_and_table_carry_every_figure() {
+        let report = summarize(&sample_log(), Some(1_000));
+        let document = json::parse(&report.to_json(Mode::Yellow)).unwrap();
+        let number = |key: &str| document.get(key).and_then(JsonValue::as_f64);
+        assert_eq!(document.get("action").and_then(JsonValue::as_str), Some("report"));
+        assert_eq!(document.get("mode").and_then(JsonValue::as_str), Some("yellow"));
+        assert_eq!((number("since_unix"), number("cycles"), number("failed_cycles")), (Some(1_000.0), Some(7.0), Some(2.0)));
+        assert_eq!((number("longest_clean_streak"), number("unparsed")), (Some(3.0), Some(2.0)));
+        assert_eq!((number("first_failure_unix"), number("last_failure_unix")), (Some(1_060.0), Some(1_300.0)));
+        let failures = document.get("failures_by_entry").unwrap();
+        assert_eq!((failures.get("squire").and_then(JsonValue::as_f64), failures.get("tools/a:b").and_then(JsonValue::as_f64)), (Some(2.0), Some(1.0)));
+        assert!(HistoryReport::default().to_json(Mode::Red).contains("\"since_unix\":null,\"cycles\":0,\"failed_cycles\":0,\"failures_by_entry\":{}"));
+
+        let table = report.render_table();
+        assert!(table.starts_with("Sentry verification history (since 1970-01-01 00:16:40 UTC)\n"));
+        assert!(table.contains("  Last failure          1970-01-01 00:21:40 UTC\n"));
+        assert!(table.contains("  tools/a:b  1\n"));
+    }
+}
@@ -0,0 +1,326 @@
+//! `inspect`: read a manifest and say what is in it, without looking at any binary.
+//!
+//! When Red receives a manifest from Yellow, the first thing an operator does is read it. Most
+//! lines are hashes, so the questions that matter are hard to answer by eye. `inspect` answers
+//! them:
+//! - the release id, the mode that built it, how many entries it has, and their total size;
+//! - the largest and the smallest entry;
+//! - **duplicate hashes**: several entries with identical contents under different names. That
+//!   is usually a packaging mistake, such as one binary copied over another;
+//! - **zero-byte entries**: an empty binary is rarely what anyone meant to ship;
+//! - whether `<manifest>.sig` sits next to the manifest, and whether it is more than the
+//!   placeholder `build` writes. A manifest piped in with `--manifest -` has no folder to look
+//!   in, so that is `unknown`.
+//!
+//! It also checks that the manifest agrees with itself: every hash is hex of the right length
+//! (16 characters for the manifest hash, 64 for SHA-256 and signatures, 128 for SHA-512), a
+//! zero-byte entry carries the hashes of an empty file, and no path is listed twice. Each problem
+//! is one line in `warnings`. Warnings `build` recorded in the manifest are repeated there too.
+//! With `--strict` any warning makes the command fail (exit code 1).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::digest::{self, DigestAlgorithm};
+use crate::status::SIGNATURE_PLACEHOLDER;
+use crate::{hash_bytes, json_escape, Mode, OmegaManifest, STDIN_MANIFEST};
+
+/// Hex characters in a manifest hash (`hash_bytes`).
+pub const MANIFEST_HASH_HEX_CHARS: usize = 16;
+/// Hex characters in a per-file signature (HMAC-SHA256).
+pub const SIG_HEX_CHARS: usize = 64;
+
+/// One entry, named by its relative path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizedEntry {
+    pub rel_path: String,
+    pub size: u64,
+}
+
+/// Entries that share one manifest hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateHash {
+    pub hash: String,
+    pub rel_paths: Vec<String>,
+}
+
+/// Everything `inspect` found.
+#[derive(Clone, Debug)]
+pub struct Inspection {
+    pub manifest_path: String,
+    pub release_id: String,
+    /// The mode recorded in the manifest (who built it), not the mode `inspect` runs in.
+    pub built_in: Mode,
+    pub entries: usize,
+    pub total_bytes: u64,
+    pub largest: Option<SizedEntry>,
+    pub smallest: Option<SizedEntry>,
+    /// Non-empty entries whose contents are identical. Empty files all share one hash, so they
+    /// are listed under `zero_byte` instead.
+    pub duplicate_hashes: Vec<DuplicateHash>,
+    pub zero_byte: Vec<String>,
+    /// `signed`, `placeholder`, or `missing`, for `<manifest>.sig`; `unknown` for `--manifest -`.
+    pub signature: &'static str,
+    pub warnings: Vec<String>,
+}
+
+/// Look through `manifest`, which was read from `manifest_path`.
+pub fn inspect(manifest: &OmegaManifest, manifest_path: &Path) -> Inspection {
+    let entries = &manifest.entries;
+    let mut warnings: Vec<String> = manifest.warnings.iter().map(|warning| format!("recorded by build: {warning}")).collect();
+
+    let mut seen_paths: BTreeMap<&str, usize> = BTreeMap::new();
+    for entry in entries {
+        *seen_paths.entry(&entry.rel_path).or_insert(0) += 1;
+        warnings.extend(hex_problem(&entry.rel_path, "hash", &entry.hash, MANIFEST_HASH_HEX_CHARS));
+        for (algorithm, hex) in &entry.digests {
+            warnings.extend(hex_problem(&entry.rel_path, algorithm.field(), hex, digest_hex_chars(*algorithm)));
+        }
+        if let Some(sig) = &entry.sig {
+            warnings.extend(hex_problem(&entry.rel_path, "sig", sig, SIG_HEX_CHARS));
+        }
+        if entry.size == 0 {
+            warnings.push(format!("{}: zero-byte entry", entry.rel_path));
+            // Every empty file has the same hashes, so anything else means the size is wrong.
+            if !entry.hash.eq_ignore_ascii_case(&hash_bytes(b"")) {
+                warnings.push(format!("{}: size is 0 but hash is not the hash of an empty file", entry.rel_path));
+            }
+            for (algorithm, hex) in &entry.digests {
+                let empty = digest::digests_of(b"", &[*algorithm]).remove(0).1;
+                if !hex.eq_ignore_ascii_case(&empty) {
+                    warnings.push(format!("{}: size is 0 but {} is not the digest of an empty file", entry.rel_path, algorithm.field()));
+                }
+            }
+        }
+    }
+    for (rel_path, count) in &seen_paths {
+        if *count > 1 {
+            warnings.push(format!("{rel_path}: listed {count} times"));
+        }
+    }
+
+    let mut by_hash: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in entries.iter().filter(|entry| entry.size > 0) {
+        by_hash.entry(entry.hash.to_ascii_lowercase()).or_default().push(entry.rel_path.clone());
+    }
+    let duplicate_hashes: Vec<DuplicateHash> = by_hash
+        .into_iter()
+        .filter(|(_, rel_paths)| rel_paths.len() > 1)
+        .map(|(hash, rel_paths)| DuplicateHash { hash, rel_paths })
+        .collect();
+    for duplicate in &duplicate_hashes {
+        warnings.push(format!("{}: identical contents (hash {})", duplicate.rel_paths.join(", "), duplicate.hash));
+    }
+
+    // Ties go to the first entry in the manifest, so the answer does not change between runs.
+    let sized = |entry: &crate::ManifestEntry| SizedEntry { rel_path: entry.rel_path.clone(), size: entry.size };
+    let largest = entries.iter().rev().max_by_key(|entry| entry.size).map(sized);
+    let smallest = entries.iter().min_by_key(|entry| entry.size).map(sized);
+
+    Inspection {
+        manifest_path: manifest_path.to_string_lossy().into_owned(),
+        release_id: manifest.release_id.clone(),
+        built_in: manifest.mode,
+        entries: entries.len(),
+        total_bytes: entries.iter().map(|entry| entry.size).sum(),
+        largest,
+        smallest,
+        duplicate_hashes,
+        zero_byte: entries.iter().filter(|entry| entry.size == 0).map(|entry| entry.rel_path.clone()).collect(),
+        signature: signature_state(manifest_path),
+        warnings,
+    }
+}
+
+impl Inspection {
+    /// The findings as one JSON object.
+    pub fn to_json(&self, mode: Mode, strict: bool) -> String {
+        let sized = |entry: &Option<SizedEntry>| match entry {
+            Some(entry) => format!("{{\"rel_path\":{},\"size\":{}}}", quote(&entry.rel_path), entry.size),
+            None => "null".to_string(),
+        };
+        let duplicates: Vec<String> = self
+            .duplicate_hashes
+            .iter()
+            .map(|duplicate| format!("{{\"hash\":{},\"entries\":{}}}", quote(&duplicate.hash), quote_list(&duplicate.rel_paths)))
+            .collect();
+        format!(
+            "{{\"action\":\"inspect\",\"mode\":\"{}\",\"manifest\":{},\"release_id\":{},\"built_in\":\"{}\",\"entries\":{},\"total_bytes\":{},\"largest\":{},\"smallest\":{},\"duplicate_hashes\":[{}],\"zero_byte\":{},\"signature\":\"{}\",\"strict\":{},\"warnings\":{}}}",
+            mode.as_str(),
+            quote(&self.manifest_path),
+            quote(&self.release_id),
+            self.built_in.as_str(),
+            self.entries,
+            self.total_bytes,
+            sized(&self.largest),
+            sized(&self.smallest),
+            duplicates.join(","),
+            quote_list(&self.zero_byte),
+            self.signature,
+            strict,
+            quote_list(&self.warnings)
+        )
+    }
+
+    /// The findings as a plain-text table for people.
+    pub fn render_table(&self) -> String {
+        let sized = |entry: &Option<SizedEntry>| entry.as_ref().map_or("none".to_string(), |entry| format!("{} ({} bytes)", entry.rel_path, entry.size));
+        let mut out = format!("Sentry manifest {}\n", self.manifest_path);
+        let rows = [
+            ("Release id", self.release_id.clone()),
+            ("Built in mode", self.built_in.as_str().to_string()),
+            ("Entries", self.entries.to_string()),
+            ("Total size", format!("{} bytes", self.total_bytes)),
+            ("Largest", sized(&self.largest)),
+            ("Smallest", sized(&self.smallest)),
+            ("Duplicate hashes", self.duplicate_hashes.len().to_string()),
+            ("Zero-byte entries", self.zero_byte.len().to_string()),
+            ("Signature (.sig)", self.signature.to_string()),
+        ];
+        for (label, value) in rows {
+            out.push_str(&format!("  {label:<20}{value}\n"));
+        }
+        if self.warnings.is_empty() {
+            out.push_str("\nNo warnings.\n");
+        } else {
+            out.push_str(&format!("\nWarnings ({}):\n", self.warnings.len()));
+            for warning in &self.warnings {
+                out.push_str(&format!("  - {warning}\n"));
+            }
+        }
+        out
+    }
+}
+
+/// Hex characters in one standard digest.
+fn digest_hex_chars(algorithm: DigestAlgorithm) -> usize {
+    match algorithm {
+        DigestAlgorithm::Sha256 => 64,
+        DigestAlgorithm::Sha512 => 128,
+    }
+}
+
+/// A warning when `value` is not `expected` hex characters.
+fn hex_problem(rel_path: &str, field: &str, value: &str, expected: usize) -> Option<String> {
+    if value.len() == expected && value.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(format!("{rel_path}: {field} {value:?} is not {expected} hex characters"))
+}
+
+/// `signed`, `placeholder`, or `missing`, by the same rule `status` uses; `unknown` for stdin.
+fn signature_state(manifest_path: &Path) -> &'static str {
+    if manifest_path == Path::new(STDIN_MANIFEST) {
+        return "unknown";
+    }
+    let mut sig_path = manifest_path.as_os_str().to_owned();
+    sig_path.push(".sig");
+    match fs::read_to_string(&sig_path) {
+        Err(_) => "missing",
+        Ok(text) if text.trim().is_empty() || text.trim() == SIGNATURE_PLACEHOLDER => "placeholder",
+        Ok(_) => "signed",
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", json_escape(text))
+}
+
+fn quote_list(items: &[String]) -> String {
+    let quoted: Vec<String> = items.iter().map(|item| quote(item)).collect();
+    format!("[{}]", quoted.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_manifest_from;
+    use crate::json::{self, JsonValue};
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("sentry-inspect-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A manifest with one `name|name|hash|size` line per `(name, contents)`, plus `extra` lines.
+    fn manifest(files: &[(&str, &[u8])], extra: &[&str]) -> OmegaManifest {
+        let mut text = String::from("release_id=r1\nmode=yellow\nentries:\n");
+        for (name, contents) in files {
+            text.push_str(&format!("{name}|{name}|{}|{}\n", hash_bytes(contents), contents.len()));
+        }
+        for line in extra {
+            text.push_str(&format!("{line}\n"));
+        }
+        load_manifest_from(text.as_bytes(), Path::new("manifest.txt"), false).unwrap()
+    }
+
+    #[test]
+    fn a_clean_manifest_is_summarized_without_warnings() {
+        let found = inspect(&manifest(&[("squire", b"squire v1"), ("bard", b"bard"), ("hub", b"hub v10")], &[]), Path::new(STDIN_MANIFEST));
+        assert!(found.warnings.is_empty(), "{:?}", found.warnings);
+        assert_eq!((found.release_id.as_str(), found.built_in, found.entries, found.total_bytes), ("r1", Mode::Yellow, 3, 20));
+        assert_eq!(found.largest, Some(SizedEntry { rel_path: "squire".to_string(), size: 9 }));
+        assert_eq!(found.smallest, Some(SizedEntry { rel_path: "bard".to_string(), size: 4 }));
+        assert!(found.duplicate_hashes.is_empty() && found.zero_byte.is_empty());
+        assert_eq!(found.signature, "unknown", "a piped manifest has no folder");
+
+        let document = json::parse(&found.to_json(Mode::Red, false)).unwrap();
+        assert_eq!(document.get("built_in").and_then(JsonValue::as_str), Some("yellow"));
+        assert_eq!(document.get("total_bytes").and_then(JsonValue::as_f64), Some(20.0));
+        assert_eq!(document.get("warnings").and_then(JsonValue::as_array).map(<[JsonValue]>::len), Some(0));
+        assert!(found.render_table().ends_with("\nNo warnings.\n"));
+
+        let empty = inspect(&manifest(&[], &[]), Path::new(STDIN_MANIFEST));
+        assert_eq!((empty.largest, empty.smallest, empty.total_bytes), (None, None, 0));
+    }
+
+    #[test]
+    fn identical_contents_under_two_names_are_a_duplicate_hash() {
+        let found = inspect(&manifest(&[("squire", b"same"), ("bard", b"other"), ("squire-old", b"same")], &[]), Path::new(STDIN_MANIFEST));
+        assert_eq!(found.duplicate_hashes, [DuplicateHash { hash: hash_bytes(b"same"), rel_paths: vec!["squire".to_string(), "squire-old".to_string()] }]);
+        assert_eq!(found.warnings.len(), 1);
+        assert!(found.warnings[0].starts_with("squire, squire-old: identical contents"), "{:?}", found.warnings);
+        assert!(found.render_table().contains("Warnings (1):\n  - squire, squire-old"));
+    }
+
+    #[test]
+    fn malformed_hex_zero_byte_entries_and_repeated_paths_are_warned_about() {
+        let short_sha = format!("tools/a|tools/a|{}|3|hash_sha256=abc", hash_bytes(b"abc"));
+        let bad_sig = format!("tools/b|tools/b|{}|3|sig={}", hash_bytes(b"def"), "g".repeat(SIG_HEX_CHARS));
+        let wrong_empty = format!("stub|stub|{}|0", hash_bytes(b"not empty"));
+        let found = inspect(
+            &manifest(&[("squire", b"v1"), ("empty", b""), ("squire", b"v1")], &["odd|odd|xyz|2", &short_sha, &bad_sig, &wrong_empty]),
+            Path::new(STDIN_MANIFEST),
+        );
+        let has = |text: &str| found.warnings.iter().any(|warning| warning.contains(text));
+        assert!(has("odd: hash \"xyz\" is not 16 hex characters"), "{:?}", found.warnings);
+        assert!(has("tools/a: hash_sha256 \"abc\" is not 64 hex characters"));
+        assert!(has("tools/b: sig"));
+        assert!(has("empty: zero-byte entry") && has("stub: zero-byte entry"));
+        assert!(has("stub: size is 0 but hash is not the hash of an empty file"));
+        assert!(!has("empty: size is 0"), "the real empty hash is fine");
+        assert!(has("squire: listed 2 times"));
+        assert_eq!(found.zero_byte, ["empty", "stub"]);
+        assert!(found.duplicate_hashes.iter().all(|duplicate| !duplicate.rel_paths.contains(&"empty".to_string())));
+
+        let recorded = inspect(&manifest(&[("squire", b"v1")], &["warning=skipped a symlink"]), Path::new(STDIN_MANIFEST));
+        assert_eq!(recorded.warnings, ["recorded by build: skipped a symlink"]);
+    }
+
+    #[test]
+    fn the_signature_beside_the_manifest_is_classified() {
+        let dir = temp_dir("signature");
+        let path = dir.join("manifest.txt");
+        let found = || inspect(&manifest(&[("squire", b"v1")], &[]), &path).signature;
+        assert_eq!(found(), "missing");
+        fs::write(dir.join("manifest.txt.sig"), format!("{SIGNATURE_PLACEHOLDER}\n")).unwrap();
+        assert_eq!(found(), "placeholder");
+        fs::write(dir.join("manifest.txt.sig"), "  \n").unwrap();
+        assert_eq!(found(), "placeholder");
+        fs::write(dir.join("manifest.txt.sig"), "a1b2c3\n").unwrap();
+        assert_eq!(found(), "signed");
+    }
+}
@@ -4,15 +4,70 @@
 //! downloads. The functions here prefer descriptive printouts and simple data structures, and the
 //! CLI entrypoints in `src/bin` feed into `run_cli` with their preferred default mode.
 
-use std::collections::hash_map::DefaultHasher;
+pub mod alert;
+pub mod bundle;
+pub mod clock_skew;
+pub mod cross_check;
+pub mod digest;
+pub mod disk_space;
+pub mod error;
+pub mod explain;
+pub mod filetype;
+pub mod hash_dir;
+pub mod history;
+pub mod inspect;
+pub mod merkle;
+pub mod owners;
+pub mod policy;
+pub mod provenance;
+pub mod prune;
+pub mod publish;
+pub mod safe_path;
+pub mod schedule;
+pub mod sha512;
+pub mod slots;
+pub mod status;
+pub mod status_server;
+pub mod transfer;
+pub mod verifier;
+pub mod verify_cache;
+pub mod verify_token;
+pub mod version;
+pub mod waiver;
+#[cfg(feature = "vault-keys")]
+pub mod vault;
+
+pub use ecosystem_common::secret::SecretBytes;
+pub use ecosystem_common::{atomic, dotenv, json, log, runtime, secret, self_verify, sha256, watchdog};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
 use std::fs;
-use std::hash::{Hash, Hasher};
-use std::io::Write;
+use std::io::{self, Read};
+use std::net::TcpListener;
 use std::path::{Path, PathBuf};
-use std::thread;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
+use alert::{AlertSink, Alerter, CommandSink, FileSink};
+use clock_skew::{SkewCheck, SkewTracker};
+use digest::DigestAlgorithm;
+use disk_space::{SpaceProvider, SystemSpace};
+use filetype::FileType;
+use log::Logger;
+use runtime::{EnvSource, ProcessEnv, Runtime};
+use schedule::{Pass, Schedule, ScheduleSettings, XorShift64};
+use status_server::{SharedStatus, StatusServer};
+use verify_cache::VerifyCache;
+use watchdog::Watchdog;
+
+pub use error::SentryError;
+pub use verifier::{EntryStatus, Verifier, VerifyReport};
+
+/// Diagnostic lines go through the shared logger; `print_json_status` stays the data channel.
+const LOG: Logger = Logger::new("sentry");
+
 /// Runtime mode for Sentry Omega.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Mode {
@@ -30,55 +85,319 @@ impl Mode {
         }
     }
 
-    pub fn from_str(value: &str) -> Option<Self> {
+    /// The variable holding this role's address, e.g. `SENTRY_RED_HOST`.
+    pub fn host_env(&self) -> &'static str {
+        match self {
+            Mode::Blue => "SENTRY_BLUE_HOST",
+            Mode::Yellow => "SENTRY_YELLOW_HOST",
+            Mode::Red => "SENTRY_RED_HOST",
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
         match value {
-            "blue" => Some(Mode::Blue),
-            "yellow" => Some(Mode::Yellow),
-            "red" => Some(Mode::Red),
-            _ => None,
+            "blue" => Ok(Mode::Blue),
+            "yellow" => Ok(Mode::Yellow),
+            "red" => Ok(Mode::Red),
+            _ => Err("Mode must be blue, yellow, or red".to_string()),
+        }
+    }
+}
+
+/// Port used for `--publish` when `SENTRY_PUBLISH_PORT` is unset.
+pub const DEFAULT_PUBLISH_PORT: u16 = 7420;
+
+/// Where one role listens for published reports, read from its `SENTRY_<COLOR>_HOST` variable.
+///
+/// The variable may hold a bare host (`red.local`), a host and port (`red.local:7421`), or a URL
+/// (`https://red.local:7421`). Without a port the shared `SENTRY_PUBLISH_PORT` is used. `tls` is
+/// true for `https://` addresses. An unset or empty variable gives `configured: false`, so a
+/// status reader can tell "deliberately left out" from "set to something odd".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostConfig {
+    /// Host name or IP address, without scheme or port. Empty when not configured.
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub configured: bool,
+}
+
+impl HostConfig {
+    /// A role whose variable is not set.
+    pub fn unconfigured(default_port: u16) -> Self {
+        Self { host: String::new(), port: default_port, tls: false, configured: false }
+    }
+
+    /// Parse one variable's value. Only a bad port is an error here; a strange host name is
+    /// kept as written and reported by `OmegaEnvironment::validate`.
+    pub fn parse(raw: &str, default_port: u16) -> Result<Self, String> {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            return Ok(Self::unconfigured(default_port));
+        }
+        let lower = raw.to_ascii_lowercase();
+        let (rest, tls) = if lower.starts_with("https://") {
+            (&raw["https://".len()..], true)
+        } else if lower.starts_with("http://") {
+            (&raw["http://".len()..], false)
+        } else {
+            (raw, false)
+        };
+        let rest = rest.strip_suffix('/').unwrap_or(rest);
+        if rest.contains("://") {
+            // Some other scheme (`tcp://`, `ftp://`): kept whole so `validate` names it.
+            return Ok(Self { host: rest.to_string(), port: default_port, tls, configured: true });
+        }
+
+        // `[::1]:7420` keeps the brackets around an IPv6 address so its colons are not mistaken
+        // for the port separator. A bare IPv6 address has several colons and no port.
+        let (host, port) = if let Some(close) = rest.strip_prefix('[').and_then(|inner| inner.find(']')) {
+            let after = &rest[close + 2..];
+            match after.strip_prefix(':') {
+                Some(port) => (&rest[..close + 2], Some(port)),
+                None => (rest, None),
+            }
+        } else if rest.matches(':').count() == 1 {
+            let (host, port) = rest.split_once(':').unwrap_or((rest, ""));
+            (host, Some(port))
+        } else {
+            (rest, None)
+        };
+        let port = match port {
+            Some(text) => text
+                .parse::<u16>()
+                .ok()
+                .filter(|port| *port > 0)
+                .ok_or_else(|| format!("bad port {text:?} (expected a number from 1 to 65535)"))?,
+            None => default_port,
+        };
+        Ok(Self { host: host.to_string(), port, tls, configured: true })
+    }
+
+    /// `host:port`, the form `publish::send` connects to. A bare IPv6 address gets brackets.
+    pub fn address(&self) -> String {
+        if self.host.contains(':') && !self.host.starts_with('[') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
         }
     }
+
+    /// Why this host cannot be published to, or `None` when it can. `var` names the variable in
+    /// the message.
+    pub fn problem(&self, var: &str) -> Option<String> {
+        if !self.configured {
+            return Some(format!("{var} is not set, so nothing is published to this role"));
+        }
+        let plain = !self.host.is_empty()
+            && self.host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'));
+        let ipv6 = (self.host.starts_with('[') && self.host.ends_with(']'))
+            || (self.host.contains(':') && self.host.chars().all(|c| c.is_ascii_hexdigit() || c == ':'));
+        if !plain && !ipv6 {
+            return Some(format!("{var} holds {:?}, which is not a host name or IP address", self.host));
+        }
+        if self.tls {
+            return Some(format!(
+                "{var} asks for https, but Sentry publishes over plain TCP; put a TLS proxy in front and use its plain address"
+            ));
+        }
+        None
+    }
+
+    /// `{"host":"red.local","port":7420,"tls":false,"configured":true}`.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"host\":\"{}\",\"port\":{},\"tls\":{},\"configured\":{}}}",
+            json_escape(&self.host),
+            self.port,
+            self.tls,
+            self.configured
+        )
+    }
 }
 
 /// Runtime network settings loaded from the environment.
 #[derive(Clone, Debug)]
 pub struct OmegaEnvironment {
-    pub yellow_host: String,
-    pub red_host: String,
-    pub blue_host: String,
+    pub yellow_host: HostConfig,
+    pub red_host: HostConfig,
+    pub blue_host: HostConfig,
+    /// Port for hosts that do not name their own (`SENTRY_PUBLISH_PORT`).
+    pub publish_port: u16,
 }
 
 impl OmegaEnvironment {
-    pub fn load() -> Self {
+    /// Read the three hosts and the publish port. A malformed port is an error naming its
+    /// variable, since guessing a different port would send reports to the wrong place.
+    pub fn load() -> Result<Self, String> {
         // All runtime endpoints use hostnames from .env so operators can adjust them without
-        // rebuilding. Missing entries fall back to explicit placeholder strings to keep the
-        // output easy to read.
-        let yellow_host = env::var("SENTRY_YELLOW_HOST").unwrap_or_else(|_| "unset-yellow-host".to_string());
-        let red_host = env::var("SENTRY_RED_HOST").unwrap_or_else(|_| "unset-red-host".to_string());
-        let blue_host = env::var("SENTRY_BLUE_HOST").unwrap_or_else(|_| "unset-blue-host".to_string());
+        // rebuilding. `dotenv` copies the file into the environment first; variables that are
+        // already exported keep their value.
+        dotenv::load_default_dotenv();
+        Self::from_env(&ProcessEnv)
+    }
 
-        Self {
-            yellow_host,
-            red_host,
-            blue_host,
+    /// `load` without the `.env` step, reading the variables from `env` (a `runtime::MapEnv`
+    /// leaves the process environment alone).
+    pub fn from_env(env: &dyn EnvSource) -> Result<Self, String> {
+        let publish_port = match env.var("SENTRY_PUBLISH_PORT") {
+            Some(value) if !value.trim().is_empty() => value
+                .trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|port| *port > 0)
+                .ok_or_else(|| format!("SENTRY_PUBLISH_PORT has a bad port {value:?} (expected a number from 1 to 65535)"))?,
+            _ => DEFAULT_PUBLISH_PORT,
+        };
+        let host = |mode: Mode| {
+            let var = mode.host_env();
+            let raw = env.var(var).unwrap_or_default();
+            HostConfig::parse(&raw, publish_port).map_err(|err| format!("{var} has a {err}"))
+        };
+
+        Ok(Self {
+            yellow_host: host(Mode::Yellow)?,
+            red_host: host(Mode::Red)?,
+            blue_host: host(Mode::Blue)?,
+            publish_port,
+        })
+    }
+
+    /// The address a role listens on.
+    pub fn host_for(&self, mode: Mode) -> &HostConfig {
+        match mode {
+            Mode::Yellow => &self.yellow_host,
+            Mode::Red => &self.red_host,
+            Mode::Blue => &self.blue_host,
         }
     }
+
+    /// One warning per host that is unset, not a usable host name, or asks for TLS.
+    pub fn validate(&self) -> Vec<String> {
+        [Mode::Yellow, Mode::Red, Mode::Blue]
+            .into_iter()
+            .filter_map(|mode| self.host_for(mode).problem(mode.host_env()))
+            .collect()
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct ManifestEntry {
+    /// File name, for display. Two entries in different folders can share it.
     pub name: String,
+    /// Path under `--bins-dir` with `/` separators, e.g. `tools/squire`. This identifies the entry:
+    /// results, waivers, signature files, proofs, and the Merkle tree all use it. It is written
+    /// as `rel=` only when it differs from `name`, so flat folders and manifests from before
+    /// `build --recursive` read with `rel_path == name`.
+    pub rel_path: String,
+    /// Where the file sits under the manifest's `root`, the same as `rel_path` for manifests
+    /// `build` writes now. Older manifests hold the path as given to `build` (`bins/tools/squire`,
+    /// or an absolute one). `verify` joins it onto `--bins-dir`; `load_manifest` refuses it when
+    /// it could point outside that.
     pub path: String,
     pub hash: String,
     pub size: u64,
+    /// Standard digests from `build --digests`, written as `hash_sha256=` / `hash_sha512=` fields
+    /// in field order. Manifests from before the flag existed have none.
+    pub digests: Vec<(DigestAlgorithm, String)>,
+    /// Hex HMAC-SHA256 of the file, written by `build --per-file-sigs`. The same value sits in
+    /// `<rel_path>.sig` beside the manifest. Older manifests do not carry one.
+    pub sig: Option<String>,
+    /// Permission bits when the file was built, e.g. `0o755` (see `file_mode`). Manifests written
+    /// before modes were recorded have none, and their entries skip the mode check.
+    pub mode: Option<u32>,
+    /// What the file looked like when it was built (`filetype=`, see `filetype`). Older
+    /// manifests have none, and their entries skip the type check.
+    pub filetype: Option<FileType>,
+    /// The team that owns the file, from `build --owners-file` (see `owners`). Entries that match
+    /// no rule, and older manifests, have none.
+    pub owner: Option<String>,
+    /// Fingerprint of the key that must sign the file (`owners::key_fingerprint`). With
+    /// `--per-file-sigs`, a signature by any other key is a `signer-mismatch`.
+    pub required_signer: Option<String>,
+}
+
+/// How much of a file's mode `verify` compares with the manifest (`--check-mode`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModeCheck {
+    Off,
+    /// Only the three executable bits. The default, because group and other bits often differ
+    /// between filesystems and umasks while a lost `x` bit breaks a deploy.
+    ExecOnly,
+    /// All permission bits, including setuid, setgid, and sticky.
+    Full,
+}
+
+impl ModeCheck {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        match raw {
+            "off" => Ok(ModeCheck::Off),
+            "exec-only" => Ok(ModeCheck::ExecOnly),
+            "full" => Ok(ModeCheck::Full),
+            _ => Err(format!("--check-mode must be off, exec-only, or full, got {raw}")),
+        }
+    }
+
+    /// True when `observed` is acceptable for a file recorded with `expected`.
+    pub fn accepts(&self, expected: u32, observed: u32) -> bool {
+        let compared = match self {
+            ModeCheck::Off => 0,
+            ModeCheck::ExecOnly => 0o111,
+            ModeCheck::Full => 0o7777,
+        };
+        (expected ^ observed) & compared == 0
+    }
+}
+
+/// The permission bits of a file. Unix gives them directly. Windows only knows "read-only", so
+/// there a file counts as `0o444` when read-only and `0o644` otherwise, and never as executable.
+#[cfg(unix)]
+pub fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
 }
 
+/// Environment variable holding the key for per-file signatures: 64 hex characters (32 bytes).
+pub const SIGNING_KEY_ENV: &str = "SENTRY_SIGNING_KEY";
+/// Further signing keys, comma-separated, for entries whose `required_signer` names another key
+/// than `SENTRY_SIGNING_KEY` (see `owners`).
+pub const EXTRA_SIGNING_KEYS_ENV: &str = "SENTRY_EXTRA_SIGNING_KEYS";
+
 #[derive(Clone, Debug)]
 pub struct OmegaManifest {
     pub release_id: String,
     pub mode: Mode,
+    /// The `--bins-dir` the manifest was built from (`root=`), recorded once so entry paths can
+    /// stay relative. `verify` falls back to it when no `--bins-dir` is given. Older manifests do
+    /// not carry one.
+    pub root: Option<String>,
     pub signature_note: String,
     pub entries: Vec<ManifestEntry>,
+    /// Hex Merkle root over every entry (see `merkle`). Older manifests do not carry one.
+    pub merkle_root: Option<String>,
+    /// Seconds since 1970 when `build` wrote the manifest. Older manifests do not carry one.
+    pub created_at_unix: Option<u64>,
+    /// How the manifest was produced (see `provenance`). Older manifests do not carry one.
+    pub provenance: Option<provenance::Provenance>,
+    /// Problems `build` noticed but did not refuse, such as two entries sharing a file name.
+    /// Stored as `warning=` lines in the header.
+    pub warnings: Vec<String>,
+    /// The Sentry version that wrote the manifest (`tool_version=`, see `version`). Older
+    /// manifests do not carry one.
+    pub tool_version: Option<String>,
 }
 
 /// CLI commands supported by Sentry Omega.
@@ -88,299 +407,4851 @@ pub enum Command {
         bins_dir: PathBuf,
         releases_dir: PathBuf,
         release_id: String,
+        source_rev: Option<String>,
+        rustc_version: Option<String>,
+        /// Sign every binary and write `<name>.sig` files (`--per-file-sigs`).
+        per_file_sigs: bool,
+        /// Vault envelope holding the signing key (`--sign-key-envelope`).
+        sign_key_envelope: Option<PathBuf>,
+        /// Also write `omega-<release_id>.tar` into the release folder (`--bundle`).
+        bundle: bool,
+        /// Fixed time for the manifest and the bundle's members (`--source-date-epoch`).
+        source_date_epoch: Option<u64>,
+        /// Also hash binaries in folders below `--bins-dir` (`--recursive`).
+        recursive: bool,
+        /// Standard digests recorded for every entry (`--digests`, default `sha256`).
+        digests: Vec<DigestAlgorithm>,
+        /// Build in Blue mode even though other roles are reachable (`--allow-networked-blue`).
+        allow_networked_blue: bool,
+        /// Warn about top-level entries that are not programs or are smaller than this many bytes
+        /// (`--expect-executables`, `--min-executable-size`).
+        expect_executables: Option<u64>,
+        /// Write even when the disk looks too full (`--skip-space-check`).
+        skip_space_check: bool,
+        /// `pattern=owner[,signer]` rules annotating entries (`--owners-file`, see `owners`).
+        owners_file: Option<PathBuf>,
     },
     Verify {
-        bins_dir: PathBuf,
+        /// Every `--bins-dir`, checked against the one manifest (see `slots`).
+        bins_dirs: Vec<slots::BinsSlot>,
         manifest_path: PathBuf,
+        /// Also check each binary's `<name>.sig` (`--per-file-sigs`).
+        per_file_sigs: bool,
+        sign_key_envelope: Option<PathBuf>,
+        /// Fail when the manifest's recorded source revision differs (`--require-source-rev`).
+        require_source_rev: Option<String>,
+        /// `Some` when `--publish` or `--publish-to` was given; holds the explicit target, if any.
+        publish: Option<Option<String>>,
+        check_mode: ModeCheck,
+        /// Accept `name.exe` for `name` and the other way round (`--allow-exe-suffix`).
+        allow_exe_suffix: bool,
+        /// Accept absolute file paths in the manifest (`--trust-absolute-paths`, see `safe_path`).
+        trust_absolute_paths: bool,
+        /// Known, temporary mismatches to accept (`--waivers`).
+        waivers: Option<PathBuf>,
+        /// Say how each mismatching file changed (`--explain`, see `explain`).
+        explain: bool,
+        /// A request token from Red (`--token`, see `verify_token`).
+        token: Option<String>,
+        /// Refuse to verify without a valid `--token` (`--require-token`).
+        require_token: bool,
+        /// How long past its expiry a token is still accepted (`--max-clock-skew-secs`).
+        max_clock_skew: Duration,
     },
     Daemon {
-        bins_dir: PathBuf,
+        bins_dirs: Vec<slots::BinsSlot>,
+        manifest_path: PathBuf,
+        check_mode: ModeCheck,
+        allow_exe_suffix: bool,
+        trust_absolute_paths: bool,
+        /// Re-read every pass, so a waiver can be added or removed without a restart.
+        waivers: Option<PathBuf>,
+        /// `--interval-seconds`, `--max-interval-seconds`, `--relax-after`, and
+        /// `--interval-jitter-pct` (see `schedule`).
+        schedule: ScheduleSettings,
+        publish: Option<Option<String>>,
+        /// Address for the HTTP status endpoint (`--listen`), if requested.
+        listen: Option<String>,
+        /// Liveness file rewritten every pass (`--heartbeat`), if requested.
+        heartbeat: Option<PathBuf>,
+        /// Cycle log that gets one line per pass (`--log-file`), read back by `report`.
+        log_file: Option<PathBuf>,
+        /// Remembered hashes (`--cache-file`), unless `--no-cache` was given.
+        cache_file: Option<PathBuf>,
+        /// Every this many passes the cache is ignored (`--full-rehash-every`, at least 1).
+        full_rehash_every: u64,
+        /// File that gets one JSON line per alert (`--alert-file`), if requested.
+        alert_file: Option<PathBuf>,
+        /// Program run with each alert on stdin (`--alert-command`), if requested.
+        alert_command: Option<String>,
+        /// How long `alert_command` may run (`--alert-timeout-seconds`).
+        alert_timeout: Duration,
+        /// The peer's latest status document (`--peer-status`), checked for clock skew each pass.
+        peer_status: Option<PathBuf>,
+        /// `--max-clock-skew-secs`.
+        max_clock_skew: Duration,
+        /// Warn with `low-disk` when a folder the daemon writes to has less free (`--min-free-mb`).
+        min_free_mb: u64,
+    },
+    Prove {
+        manifest_path: PathBuf,
+        name: String,
+    },
+    CheckProof {
+        proof_path: PathBuf,
+        file_path: PathBuf,
+    },
+    CrossCheck {
+        mine_path: PathBuf,
+        theirs_path: PathBuf,
+        /// `--max-clock-skew-secs`.
+        max_clock_skew: Duration,
+    },
+    Prune {
+        releases_dir: PathBuf,
+        policy: prune::RetentionPolicy,
+        dry_run: bool,
+    },
+    IssueToken {
+        release_id: String,
+        ttl_secs: u64,
+    },
+    HashDir {
+        root: PathBuf,
+        /// Patterns from `--ignore`, already split on commas.
+        ignore: Vec<String>,
+    },
+    Unbundle {
+        bundle_path: PathBuf,
+        dest: PathBuf,
+        check_mode: ModeCheck,
+    },
+    Relocate {
+        manifest_path: PathBuf,
+        /// The new `root=`: where the binaries live now (`--root`).
+        root: PathBuf,
+        /// Re-sign every entry from the files under the new root (`--per-file-sigs`).
+        per_file_sigs: bool,
+        sign_key_envelope: Option<PathBuf>,
+    },
+    PushRelease {
+        /// `releases/omega-<id>`, holding the `omega-<id>.tar` written by `build --bundle`.
+        release_dir: PathBuf,
+        /// `host:port` of a `receive-release`.
+        target: String,
+        options: transfer::PushOptions,
+    },
+    ReceiveRelease {
+        listen: String,
+        releases_dir: PathBuf,
+        check_mode: ModeCheck,
+        /// Stop after the first transfer that finished (installed or refused), for scripts.
+        once: bool,
+    },
+    Report {
+        log_file: PathBuf,
+        /// Only count cycles from the last this many hours (`--since`).
+        since_hours: Option<u64>,
+        /// `--format json` instead of the default table.
+        json: bool,
+    },
+    Status {
+        releases_dir: PathBuf,
+        /// A `daemon --output` document to judge the last pass by.
+        state_file: Option<PathBuf>,
+        stale_after_secs: u64,
+    },
+    Inspect {
         manifest_path: PathBuf,
-        interval_seconds: u64,
+        /// `--format table` instead of the default JSON.
+        table: bool,
+        /// Fail when there is any warning (`--strict`).
+        strict: bool,
     },
+    /// `--help` was requested; holds the text to print.
+    Help(String),
 }
 
-/// Run the CLI using the provided default mode.
-pub fn run_cli(default_mode: Mode) -> Result<(), String> {
-    let args: Vec<String> = env::args().skip(1).collect();
-    let env_settings = OmegaEnvironment::load();
-    let (mode, command) = parse_args(default_mode, &args)?;
+/// How a run finished. The wrapper binaries turn this into the process exit code so scripts can
+/// react without parsing JSON (handy together with `--quiet`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CliOutcome {
+    /// Everything matched (or the command has nothing to compare).
+    Success,
+    /// At least one binary or proof did not match.
+    VerificationFailed,
+    /// The mode policy refused the command (see `policy`), e.g. `build` on Red.
+    PolicyRefused,
+    /// Nothing failed, but something needs a look, e.g. `status` found no real signature.
+    Warning,
+}
 
-    match command {
-        Command::Build { bins_dir, releases_dir, release_id } => {
-            let manifest = build_manifest(mode, &bins_dir, release_id)?;
-            persist_manifest(&manifest, &releases_dir)?;
-            print_json_status("build", mode, &env_settings, &manifest, &[]);
+impl CliOutcome {
+    /// Exit code for this outcome. Errors (bad flags, unreadable files) use 1 in the binaries.
+    pub fn code(&self) -> i32 {
+        match self {
+            CliOutcome::Success => 0,
+            CliOutcome::VerificationFailed => 2,
+            CliOutcome::PolicyRefused => 3,
+            CliOutcome::Warning => 4,
         }
-        Command::Verify { bins_dir, manifest_path } => {
-            let manifest = load_manifest(&manifest_path)?;
-            let report = verify_bins(&bins_dir, &manifest)?;
-            print_json_status("verify", mode, &env_settings, &manifest, &report);
+    }
+}
+
+/// Where and how the JSON document is written, set by the global `--output`, `--quiet`, and
+/// `--pretty` flags.
+#[derive(Clone, Debug, Default)]
+pub struct OutputOptions {
+    /// File to replace with each document; `None` means stdout (`--output -` or no flag).
+    pub path: Option<PathBuf>,
+    pub quiet: bool,
+    pub pretty: bool,
+}
+
+impl OutputOptions {
+    /// Write one document. Files go through `atomic::atomic_write`, so a crash between daemon
+    /// passes never leaves a half-written file for Red to read.
+    fn emit(&self, document: &str) -> Result<(), String> {
+        if self.quiet {
+            return Ok(());
         }
-        Command::Daemon { bins_dir, manifest_path, interval_seconds } => {
-            loop {
-                let manifest = load_manifest(&manifest_path)?;
-                let report = verify_bins(&bins_dir, &manifest)?;
-                print_json_status("daemon", mode, &env_settings, &manifest, &report);
-                thread::sleep(Duration::from_secs(interval_seconds));
+
+        let mut text = if self.pretty { pretty_json(document) } else { document.to_string() };
+        text.push('\n');
+
+        match &self.path {
+            Some(path) => Ok(write_atomic(path, text.as_bytes())?),
+            None => {
+                print!("{text}");
+                Ok(())
             }
         }
     }
+}
 
-    Ok(())
+/// Everything `parse_args` learned from the command line.
+struct Invocation {
+    mode: Mode,
+    output: OutputOptions,
+    command: Command,
 }
 
-fn parse_args(default_mode: Mode, args: &[String]) -> Result<(Mode, Command), String> {
-    let mut index = 0usize;
-    let mut mode = default_mode;
+/// Run the CLI using the provided default mode.
+pub fn run_cli(default_mode: Mode) -> Result<CliOutcome, SentryError> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    dotenv::load_default_dotenv();
+    run_cli_with(default_mode, &args, Runtime::system())
+}
 
-    if args.get(index).map(|v| v.as_str()) == Some("--mode") {
-        let Some(value) = args.get(index + 1) else {
-            return Err("--mode requires a value".to_string());
-        };
-        mode = Mode::from_str(value).ok_or_else(|| "Mode must be blue, yellow, or red".to_string())?;
-        index += 2;
-    }
+/// `run_cli` with the arguments (without the program name) and the clock, sleeper, and
+/// environment given by the caller. The `.env` file is not loaded here. With a
+/// `runtime::ManualClock` the daemon's sleeps return at once.
+pub fn run_cli_with(default_mode: Mode, args: &[String], rt: Runtime<'_>) -> Result<CliOutcome, SentryError> {
+    run_cli_with_space(default_mode, args, rt, &SystemSpace)
+}
 
-    let Some(command_name) = args.get(index) else {
-        return Err("Missing subcommand (build, verify, daemon)".to_string());
+/// `run_cli_with`, asking `space` instead of the operating system how full the disk is (see
+/// `disk_space`). A `disk_space::FixedSpace` makes `build` refuse, or the daemon warn, on demand.
+pub fn run_cli_with_space(default_mode: Mode, args: &[String], rt: Runtime<'_>, space: &dyn SpaceProvider) -> Result<CliOutcome, SentryError> {
+    let now_unix = || (rt.clock.now_millis() / 1000) as u64;
+    let env_settings = OmegaEnvironment::from_env(rt.env)?;
+    let Invocation { mode, output, command } = parse_args(default_mode, args)?;
+    if let Some(folder) = output.path.as_deref().and_then(Path::parent) {
+        // An earlier run that crashed mid-write may have left its temporary file here.
+        atomic::clean_stale_temps(folder);
+    }
+    // Each role may only run what it is for; a refusal still prints a document saying why.
+    let policy = match policy::check(mode, &command, &env_settings) {
+        Ok(decision) => decision,
+        Err(violation) => {
+            LOG.error("Refused by the mode policy", &[("mode", mode.as_str()), ("reason", &violation.to_string())]);
+            output.emit(&policy::refusal_json(mode, "build", &violation))?;
+            return Ok(CliOutcome::PolicyRefused);
+        }
     };
-    index += 1;
-
-    match command_name.as_str() {
-        "build" => {
-            let bins_dir = take_flag("--bins-dir", args, &mut index)?;
-            let releases_dir = take_flag("--releases-dir", args, &mut index)?;
-            let release_id = take_optional_flag("--release-id", args, &mut index)
-                .unwrap_or_else(|| "omega-dev".to_string());
 
-            Ok((mode, Command::Build { bins_dir: PathBuf::from(bins_dir), releases_dir: PathBuf::from(releases_dir), release_id }))
+    let outcome = match command {
+        Command::Help(text) => {
+            print!("{text}");
+            CliOutcome::Success
         }
-        "verify" => {
-            let bins_dir = take_flag("--bins-dir", args, &mut index)?;
-            let manifest_path = take_flag("--manifest", args, &mut index)?;
-            Ok((mode, Command::Verify { bins_dir: PathBuf::from(bins_dir), manifest_path: PathBuf::from(manifest_path) }))
+        Command::Build {
+            bins_dir,
+            releases_dir,
+            release_id,
+            source_rev,
+            rustc_version,
+            per_file_sigs,
+            sign_key_envelope,
+            bundle,
+            source_date_epoch,
+            recursive,
+            digests,
+            allow_networked_blue: _,
+            expect_executables,
+            skip_space_check,
+            owners_file,
+        } => {
+            let provenance = provenance::Provenance::collect(rustc_version.as_deref(), source_rev.as_deref());
+            let auto_id = release_id == AUTO_RELEASE_ID;
+            let owners = owners_file.as_deref().map(owners::OwnersFile::load).transpose()?;
+            let mut manifest = build_manifest(mode, &bins_dir, release_id, provenance, recursive, &digests, owners.as_ref())?;
+            if !per_file_sigs && manifest.entries.iter().any(|entry| entry.required_signer.is_some()) {
+                manifest.warnings.push("entries have a required_signer but the build is unsigned; add --per-file-sigs".to_string());
+            }
+            if let Some(min_size) = expect_executables {
+                // Recorded in the manifest, so Red sees them too, not just whoever ran the build.
+                manifest.warnings.extend(filetype::executable_warnings(&manifest.entries, min_size));
+            }
+            for warning in &manifest.warnings {
+                LOG.warn("Manifest warning", &[("warning", warning)]);
+            }
+            // A pinned time keeps the manifest, and so the bundle, identical between builds.
+            manifest.created_at_unix = Some(source_date_epoch.unwrap_or((rt.clock.now_millis() / 1000) as u64));
+            if auto_id {
+                // Needs the hashes and the final timestamp, so it runs after both are known.
+                manifest.release_id = auto_release_id(&manifest)?;
+            }
+            if per_file_sigs {
+                // The keys live only inside this block and are wiped when it ends.
+                let keys = load_signing_keys(sign_key_envelope.as_deref(), rt.env)?;
+                sign_entries(&mut manifest, &keys)?;
+            }
+            if skip_space_check {
+                LOG.warn("Skipping the disk space check (--skip-space-check)", &[]);
+            } else {
+                let required = disk_space::required_for_build(manifest.entries.iter().map(|entry| entry.size), bundle);
+                check_build_space(space, &releases_dir, required)?;
+            }
+            let bundle_mtime = bundle.then(|| source_date_epoch.or(manifest.created_at_unix).unwrap_or(0));
+            let (release_folder, already_present, bundle_written) = persist_release(&manifest, &releases_dir, bundle_mtime)?;
+            let mut document = render_json_status("build", mode, &env_settings, &manifest, &[], rt.clock.now_millis(), &[]);
+            let release = format!(
+                "{{\"id\":\"{}\",\"auto_id\":{},\"folder\":\"{}\",\"status\":\"{}\"}}",
+                json_escape(&manifest.release_id),
+                auto_id,
+                json_escape(&release_folder.to_string_lossy()),
+                if already_present { "unchanged" } else { "written" }
+            );
+            document = with_json_field(&document, "release", &release);
+            document = with_json_field(&document, "policy", &policy.to_json());
+            if let Some((path, bytes)) = bundle_written {
+                let summary = format!(
+                    "{{\"path\":\"{}\",\"sha256\":\"{}\",\"size\":{}}}",
+                    json_escape(&path.to_string_lossy()),
+                    sha256::sha256_hex(&bytes),
+                    bytes.len()
+                );
+                document = with_json_field(&document, "bundle", &summary);
+            }
+            output.emit(&document)?;
+            CliOutcome::Success
         }
-        "daemon" => {
-            let bins_dir = take_flag("--bins-dir", args, &mut index)?;
-            let manifest_path = take_flag("--manifest", args, &mut index)?;
-            let interval_seconds = take_optional_flag("--interval-seconds", args, &mut index)
-                .and_then(|v| v.parse::<u64>().ok())
-                .unwrap_or(60);
-            Ok((mode, Command::Daemon { bins_dir: PathBuf::from(bins_dir), manifest_path: PathBuf::from(manifest_path), interval_seconds }))
+        Command::Verify {
+            bins_dirs,
+            manifest_path,
+            per_file_sigs,
+            sign_key_envelope,
+            require_source_rev,
+            publish,
+            check_mode,
+            allow_exe_suffix,
+            trust_absolute_paths,
+            waivers,
+            explain,
+            token,
+            require_token,
+            max_clock_skew,
+        } => {
+            let verifier = Verifier::new(read_manifest_arg(&manifest_path, trust_absolute_paths)?)
+                .with_mode_check(check_mode)
+                .allow_exe_suffix(allow_exe_suffix);
+            let manifest = verifier.manifest();
+            // Without `--bins-dir` the folder the manifest was built from is checked, which only
+            // makes sense on the host that built it; the document says so.
+            let (bins_dirs, extra_warnings) = match bins_dirs.is_empty() {
+                false => (bins_dirs, Vec::new()),
+                true => {
+                    let dir = resolve_bins_dir(manifest, None)?;
+                    let slot = slots::BinsSlot { label: dir.display().to_string(), dir, labeled: false };
+                    (vec![slot], vec!["no --bins-dir given; checked the folder recorded as the manifest's root".to_string()])
+                }
+            };
+            // The token is checked before any file is read: a refused request costs nothing.
+            let token_check = match &token {
+                Some(token) => Some(check_verify_token(token, &manifest.release_id, rt.env, now_unix(), max_clock_skew)?),
+                None if require_token => {
+                    LOG.warn("Verify request refused", &[("reason", "no --token given")]);
+                    Some(Err("--require-token is set and no --token was given".to_string()))
+                }
+                None => None,
+            };
+            let token_json = token_check.as_ref().map(verify_token_json);
+            let tokens_rejected = usize::from(matches!(token_check, Some(Err(_))));
+            if require_token && tokens_rejected > 0 {
+                output.emit(&format!(
+                    "{{\"action\":\"verify\",\"mode\":\"{}\",\"status\":\"refused\",\"release_id\":\"{}\",\"token\":{},\"tokens_rejected\":{tokens_rejected}}}",
+                    mode.as_str(),
+                    json_escape(&manifest.release_id),
+                    token_json.as_deref().unwrap_or("null")
+                ))?;
+                return Ok(CliOutcome::VerificationFailed);
+            }
+            let keys = match per_file_sigs {
+                true => Some(load_signing_keys(sign_key_envelope.as_deref(), rt.env)?),
+                false => None,
+            };
+            let waiver_list = match &waivers {
+                Some(path) => Some(waiver::load_waivers(path)?),
+                None => None,
+            };
+            // Every slot goes through the same steps; the JSON parts are keyed by label afterwards.
+            let (mut reports, mut waiver_parts, mut explain_parts) = (Vec::new(), Vec::new(), Vec::new());
+            for slot in &bins_dirs {
+                let mut report = verifier.verify_dir(&slot.dir)?.checks;
+                if let Some(keys) = &keys {
+                    let sig_dir = manifest_path.parent().unwrap_or(Path::new("."));
+                    check_entry_sigs(&mut report, manifest, &slot.dir, sig_dir, keys, allow_exe_suffix)?;
+                }
+                if let Some(list) = &waiver_list {
+                    let uses = waiver::apply_waivers(&mut report, list, now_unix());
+                    waiver_parts.push((slot.label.as_str(), waiver::waivers_json(&uses)));
+                }
+                if explain {
+                    // Results come back in manifest order, so each check sits next to its entry.
+                    let mut explanations = Vec::new();
+                    for (check, entry) in report.iter().zip(&manifest.entries).filter(|(check, _)| !check.hash_matched()) {
+                        explanations.push(explain::explain_entry(entry, check, &entry_file(&slot.dir, entry, allow_exe_suffix))?);
+                    }
+                    explain_parts.push((slot.label.as_str(), explain::explanations_json(&explanations)));
+                }
+                reports.push(report);
+            }
+            let multi = slots::is_multi(&bins_dirs);
+            let document = render_json_status("verify", mode, &env_settings, manifest, single_slot_results(&bins_dirs, &reports), rt.clock.now_millis(), &extra_warnings);
+            let mut document = with_slot_results(document, &bins_dirs, &reports);
+            if waiver_list.is_some() {
+                document = with_json_field(&document, "waivers", &slots::keyed_json(multi, &waiver_parts));
+            }
+            if explain {
+                document = with_json_field(&document, "explain", &slots::keyed_json(multi, &explain_parts));
+            }
+            if let Some(token_json) = &token_json {
+                document = with_json_field(&document, "token", token_json);
+                document = with_json_field(&document, "tokens_rejected", &tokens_rejected.to_string());
+            }
+            // Any failing slot fails the run. Provenance is informational unless the caller pins a
+            // source revision.
+            let mut outcome = match reports.iter().all(|report| report_outcome(report) == CliOutcome::Success) {
+                true => CliOutcome::Success,
+                false => CliOutcome::VerificationFailed,
+            };
+            if let Some(required) = &require_source_rev {
+                let recorded = manifest.provenance.as_ref().and_then(|provenance| provenance.source_rev.as_deref());
+                let matched = recorded.is_some_and(|recorded| recorded.eq_ignore_ascii_case(required.trim()));
+                if !matched {
+                    outcome = CliOutcome::VerificationFailed;
+                }
+                let check = format!(
+                    "{{\"required\":\"{}\",\"recorded\":{},\"status\":\"{}\"}}",
+                    json_escape(required),
+                    recorded.map(|rev| format!("\"{}\"", json_escape(rev))).unwrap_or_else(|| "null".to_string()),
+                    if matched { "match" } else { "mismatch" }
+                );
+                document = with_json_field(&document, "source_rev_check", &check);
+            }
+            if let Some(target_override) = publish {
+                let published = match publish::publish_target(mode, &env_settings, target_override.as_deref()) {
+                    Ok(target) => publish::publish_once(&target, &document),
+                    Err(reason) => publish::PublishReport::not_sent(reason),
+                };
+                document = with_json_field(&document, "publish", &published.to_json());
+            }
+            output.emit(&document)?;
+            outcome
+        }
+        Command::Daemon {
+            bins_dirs,
+            manifest_path,
+            check_mode,
+            allow_exe_suffix,
+            trust_absolute_paths,
+            waivers,
+            schedule,
+            publish,
+            listen,
+            heartbeat,
+            log_file,
+            cache_file,
+            full_rehash_every,
+            alert_file,
+            alert_command,
+            alert_timeout,
+            peer_status,
+            max_clock_skew,
+            min_free_mb,
+        } => {
+            // Everything the daemon keeps writing to; without any of them, the current folder.
+            let mut written_folders: Vec<PathBuf> = [&heartbeat, &log_file, &cache_file, &alert_file, &output.path]
+                .into_iter()
+                .flatten()
+                .map(|path| path.parent().map(Path::to_path_buf).unwrap_or_default())
+                .collect();
+            written_folders.sort();
+            written_folders.dedup();
+            if written_folders.is_empty() {
+                written_folders.push(PathBuf::from("."));
+            }
+            let mut low_folders: BTreeSet<PathBuf> = BTreeSet::new();
+            let mut schedule = Schedule::new(schedule, XorShift64::from_time_and_pid(rt.clock));
+            let mut pass = Pass::Regular;
+            let status = SharedStatus::default();
+            // Keep the handle alive for the whole loop; when the loop exits with an error the
+            // handle is dropped and the server thread stops with it.
+            let _server = match &listen {
+                Some(address) => {
+                    let server = StatusServer::start(address, Arc::clone(&status))?;
+                    LOG.info("Status endpoint listening", &[("url", &format!("http://{}", server.local_addr()))]);
+                    Some(server)
+                }
+                None => None,
+            };
+            if publish.as_ref().is_some_and(Option::is_none) {
+                // Said once at startup; each pass still reports a failed publish in its JSON.
+                for warning in env_settings.validate() {
+                    LOG.warn("Host setting", &[("problem", &warning)]);
+                }
+            }
+            // A broken manifest at startup is still fatal; later ones only produce an event.
+            let mut tracker = ManifestTracker::open(&manifest_path, trust_absolute_paths)?;
+            // Same rule for the waiver file: fatal when broken at startup, afterwards the last
+            // good list stays in use.
+            let mut waiver_list = match &waivers {
+                Some(path) => waiver::load_waivers(path)?,
+                None => Vec::new(),
+            };
+            let mut previous_results: Option<BTreeMap<String, &'static str>> = None;
+            let mut heartbeat_seq = 0u64;
+            let mut cache = cache_file.as_deref().map(VerifyCache::load);
+            let mut pass_number = 0u64;
+            let mut sinks: Vec<Box<dyn AlertSink>> = Vec::new();
+            if let Some(path) = alert_file {
+                sinks.push(Box::new(FileSink { path }));
+            }
+            if let Some(program) = alert_command {
+                sinks.push(Box::new(CommandSink { program, timeout: alert_timeout }));
+            }
+            let mut alerter = Alerter::new(sinks);
+            let mut skew_tracker = SkewTracker::new();
+            // Under systemd: ready now, a ping every pass, and `STOPPING=1` when an error ends the loop.
+            let watchdog = Watchdog::from_env(rt.env);
+            notify_systemd(watchdog.ready());
+            let _stopping = watchdog.stopping_on_drop();
+            loop {
+                let started = rt.clock.instant();
+                notify_systemd(watchdog.ping());
+                if let Some(path) = &heartbeat {
+                    heartbeat_seq += 1;
+                    // A missed heartbeat only makes the hub report Sentry as stale, so a failed
+                    // write is logged rather than stopping verification.
+                    if let Err(err) = write_heartbeat(path, heartbeat_seq, rt.clock.now_millis()) {
+                        LOG.warn("Could not write heartbeat", &[("error", &err)]);
+                    }
+                }
+                if let Some(event) = tracker.refresh(mode) {
+                    output.emit(&event)?;
+                    match &tracker.reload_error {
+                        Some(err) => {
+                            alerter.manifest_reload_failed(mode, &tracker.manifest.release_id, err, rt.clock.now_millis());
+                        }
+                        // The old release's results say nothing about the new one, so the next
+                        // pass starts a fresh comparison instead of reporting every entry as changed.
+                        None => previous_results = None,
+                    }
+                }
+                let manifest = &tracker.manifest;
+                let dirs: Vec<&Path> = bins_dirs.iter().map(|slot| slot.dir.as_path()).collect();
+                let mut reports = match (&mut cache, &cache_file) {
+                    (Some(cache), Some(path)) => {
+                        // A recheck must look at the bytes: it exists to confirm a mismatch.
+                        let full_rehash = pass.is_recheck() || pass_number.is_multiple_of(full_rehash_every);
+                        let reports = verify_slots_cached(&dirs, manifest, check_mode, allow_exe_suffix, cache, full_rehash)?;
+                        if let Err(err) = cache.save(path) {
+                            LOG.warn("Could not write the verification cache", &[("path", &path.display().to_string()), ("error", &err.to_string())]);
+                        }
+                        reports
+                    }
+                    _ => dirs.iter().map(|dir| verify_bins(dir, manifest, check_mode, allow_exe_suffix)).collect::<Result<Vec<_>, _>>()?,
+                };
+                pass_number += 1;
+                if let Some(path) = &waivers {
+                    match waiver::load_waivers(path) {
+                        Ok(list) => waiver_list = list,
+                        Err(err) => LOG.warn("Waiver file unreadable; keeping the last good one", &[("error", &err)]),
+                    }
+                }
+                let waiver_parts: Vec<(&str, String)> = bins_dirs
+                    .iter()
+                    .zip(&mut reports)
+                    .map(|(slot, report)| (slot.label.as_str(), waiver::waivers_json(&waiver::apply_waivers(report, &waiver_list, now_unix()))))
+                    .collect();
+                // With several slots, entries are named `<label>/<rel_path>` in the change events
+                // and the cycle log, so each slot's changes are told apart.
+                let multi = slots::is_multi(&bins_dirs);
+                let report: Vec<BinCheck> = bins_dirs
+                    .iter()
+                    .zip(&reports)
+                    .flat_map(|(slot, report)| {
+                        report.iter().cloned().map(move |mut check| {
+                            if multi {
+                                check.rel_path = format!("{}/{}", slot.label, check.rel_path);
+                            }
+                            check
+                        })
+                    })
+                    .collect();
+                // Report when any entry's status changes between passes, including a mismatch
+                // becoming `waived` (or `waiver-expired`) once a waiver is added or runs out.
+                let results: BTreeMap<String, &'static str> = report.iter().map(|check| (check.rel_path.clone(), check.status())).collect();
+                if let Some(event) = previous_results.as_ref().and_then(|before| results_changed_event(mode, manifest, before, &results)) {
+                    output.emit(&event)?;
+                }
+                previous_results = Some(results);
+                let clean = report_outcome(&report) == CliOutcome::Success;
+                // Each slot alerts and recovers on its own. A first mismatch waits for its
+                // recheck, so a file caught mid-copy alerts nobody.
+                for (slot, slot_report) in bins_dirs.iter().zip(&reports) {
+                    let slot_clean = report_outcome(slot_report) == CliOutcome::Success;
+                    if slot_clean || pass.is_recheck() {
+                        let failing: Vec<String> = slot_report.iter().filter(|check| !check.matched()).map(|check| check.rel_path.clone()).collect();
+                        let slot_name = multi.then_some(slot.label.as_str());
+                        alerter.observe_slot(mode, &manifest.release_id, slot_name, &failing, rt.clock.now_millis());
+                    }
+                }
+                let next = schedule.record(clean, pass);
+                match (pass, clean) {
+                    (Pass::Regular, false) => LOG.warn("Mismatch found; checking again shortly", &[("after_seconds", &schedule::RECHECK_DELAY.as_secs().to_string())]),
+                    (Pass::Recheck, true) => LOG.info("Mismatch gone on the recheck; likely a file caught mid-copy", &[]),
+                    (Pass::Recheck, false) => LOG.warn("Mismatch confirmed on the recheck", &[]),
+                    (Pass::Regular, true) => {}
+                }
+                let now_millis = rt.clock.now_millis();
+                let peer_clock = peer_status.as_deref().and_then(|path| check_peer_clock(path, now_millis, max_clock_skew));
+                let mut warnings = Vec::new();
+                if let Some(check) = &peer_clock {
+                    warnings.extend(check.warning("the peer"));
+                    if skew_tracker.observe(check) {
+                        let detail = warnings.first().cloned().unwrap_or_default();
+                        LOG.warn("Peer clock skewed in consecutive reports", &[("reports", &skew_tracker.consecutive().to_string()), ("detail", &detail)]);
+                        alerter.clock_skew(mode, &manifest.release_id, &detail, now_millis);
+                    }
+                }
+                for folder in &written_folders {
+                    match disk_space::low_disk_warning(space, folder, min_free_mb) {
+                        Ok(Some(warning)) => {
+                            // Logged when a folder first runs low; the JSON repeats it every pass.
+                            if low_folders.insert(folder.clone()) {
+                                LOG.warn("Low disk space", &[("detail", &warning)]);
+                            }
+                            warnings.push(warning);
+                        }
+                        Ok(None) => {
+                            if low_folders.remove(folder) {
+                                LOG.info("Disk space recovered", &[("folder", &folder.display().to_string())]);
+                            }
+                        }
+                        Err(err) => LOG.debug("Free space unknown", &[("folder", &folder.display().to_string()), ("error", &err.to_string())]),
+                    }
+                }
+                let document = render_json_status("daemon", mode, &env_settings, manifest, single_slot_results(&bins_dirs, &reports), now_millis, &warnings);
+                let mut document = with_slot_results(document, &bins_dirs, &reports);
+                if let Some(check) = &peer_clock {
+                    document = with_json_field(&document, "peer_clock", &check.to_json());
+                }
+                if waivers.is_some() {
+                    document = with_json_field(&document, "waivers", &slots::keyed_json(multi, &waiver_parts));
+                }
+                document = with_json_field(&document, "effective_interval", &schedule.effective_interval().as_secs().to_string());
+                document = with_json_field(&document, "consecutive_clean", &schedule.consecutive_clean().to_string());
+                document = with_json_field(&document, "recheck", if pass.is_recheck() { "true" } else { "false" });
+                if let Some(cache) = &cache {
+                    let stats = cache.stats();
+                    document = with_json_field(
+                        &document,
+                        "cache",
+                        &format!("{{\"hits\":{},\"hashed\":{},\"full_rehash\":{}}}", stats.hits, stats.hashed, stats.full_rehash),
+                    );
+                }
+                let delay = schedule.delay(next);
+                if let Some(target_override) = &publish {
+                    // Retries share the wait with the sleep below, so a dead peer never
+                    // stretches the time between passes.
+                    let published = match publish::publish_target(mode, &env_settings, target_override.as_deref()) {
+                        Ok(target) => publish::publish_with_retry(&target, &document, delay),
+                        Err(reason) => publish::PublishReport::not_sent(reason),
+                    };
+                    document = with_json_field(&document, "publish", &published.to_json());
+                }
+                if let Ok(mut snapshot) = status.lock() {
+                    snapshot.document = Some(document.clone());
+                    snapshot.healthy = report_outcome(&report) == CliOutcome::Success;
+                }
+                if let Some(path) = &log_file {
+                    // History is a convenience; a full disk must not stop verification.
+                    let line = history::cycle_line(now_unix(), &manifest.release_id, &report);
+                    if let Err(err) = history::append_cycle(path, &line) {
+                        LOG.warn("Could not append to the cycle log", &[("error", &err)]);
+                    }
+                }
+                output.emit(&document)?;
+                schedule::sleep_rest(rt.clock, rt.sleeper, delay, started);
+                pass = next;
+            }
+        }
+        Command::Prove { manifest_path, name } => {
+            // The proof is a text file rather than JSON, so it bypasses `--pretty` and `--quiet`.
+            let manifest = load_manifest(&manifest_path)?;
+            let proof = render_proof(&manifest, &name)?;
+            match &output.path {
+                Some(path) => write_atomic(path, proof.as_bytes())?,
+                None => print!("{proof}"),
+            }
+            CliOutcome::Success
+        }
+        Command::CheckProof { proof_path, file_path } => {
+            let proof = load_proof(&proof_path)?;
+            let matched = check_proof(&proof, &file_path)?;
+            let document = format!(
+                "{{\"action\":\"check-proof\",\"mode\":\"{}\",\"name\":\"{}\",\"merkle_root\":\"{}\",\"status\":\"{}\"}}",
+                mode.as_str(),
+                json_escape(&proof.name),
+                merkle::to_hex(&proof.root),
+                if matched { "match" } else { "mismatch" }
+            );
+            output.emit(&document)?;
+            if matched { CliOutcome::Success } else { CliOutcome::VerificationFailed }
+        }
+        Command::CrossCheck { mine_path, theirs_path, max_clock_skew } => {
+            let mine = cross_check::load_status(&mine_path)?;
+            let theirs = cross_check::load_status(&theirs_path)?;
+            let conflicts = cross_check::compare(&mine, &theirs);
+            // Skew is a warning only: the exit status still depends on the conflicts alone.
+            let (skew, mut warnings) = cross_check::clock_warnings(&theirs, rt.clock.now_millis(), max_clock_skew);
+            warnings.extend(cross_check::version_warning(&mine, &theirs));
+            for warning in &warnings {
+                LOG.warn("Cross-check warning", &[("warning", warning)]);
+            }
+            output.emit(&cross_check::render_report(mode, &mine, &theirs, &conflicts, skew.as_ref(), &warnings))?;
+            if conflicts.is_empty() { CliOutcome::Success } else { CliOutcome::VerificationFailed }
+        }
+        Command::Prune { releases_dir, policy, dry_run } => {
+            let plan = prune::plan(&releases_dir, policy, now_unix())?;
+            if !dry_run {
+                prune::apply(&plan)?;
+            }
+            output.emit(&render_prune_report(mode, &releases_dir, &plan, dry_run))?;
+            CliOutcome::Success
+        }
+        Command::Relocate { manifest_path, root, per_file_sigs, sign_key_envelope } => {
+            // Older manifests may hold Blue's absolute paths; relocating is how they lose them.
+            let mut manifest = load_manifest_with(&manifest_path, true)?;
+            manifest.root = Some(crate::manifest_path(&root));
+            for entry in &mut manifest.entries {
+                entry.path = entry.rel_path.clone();
+            }
+            if !root.is_dir() {
+                LOG.warn("The new root is not a folder on this host", &[("root", &root.display().to_string())]);
+            }
+            if per_file_sigs {
+                sign_entries(&mut manifest, &load_signing_keys(sign_key_envelope.as_deref(), rt.env)?)?;
+            }
+            let folder = manifest_path.parent().unwrap_or(Path::new("."));
+            write_atomic(&manifest_path, render_manifest(&manifest).as_bytes())?;
+            // The manifest's signature covered the old text, so it is back to the placeholder.
+            let mut sig_path = manifest_path.clone().into_os_string();
+            sig_path.push(".sig");
+            write_atomic(Path::new(&sig_path), format!("{}\n", status::SIGNATURE_PLACEHOLDER).as_bytes())?;
+            if per_file_sigs {
+                write_entry_sigs(&manifest, folder)?;
+            }
+            let bundle_path = folder.join(format!("omega-{}.tar", manifest.release_id));
+            if bundle_path.is_file() {
+                LOG.warn("The release bundle still holds the old manifest; rebuild it with build --bundle", &[("bundle", &bundle_path.display().to_string())]);
+            }
+            output.emit(&format!(
+                "{{\"action\":\"relocate\",\"mode\":\"{}\",\"release_id\":\"{}\",\"root\":\"{}\",\"entries\":{},\"signatures\":\"{}\"}}",
+                mode.as_str(),
+                json_escape(&manifest.release_id),
+                json_escape(manifest.root.as_deref().unwrap_or_default()),
+                manifest.entries.len(),
+                if per_file_sigs { "resigned" } else { "placeholder" }
+            ))?;
+            CliOutcome::Success
+        }
+        Command::Unbundle { bundle_path, dest, check_mode } => {
+            let archive = fs::read(&bundle_path).map_err(|err| format!("Unable to read bundle {:?}: {err}", bundle_path))?;
+            let members = bundle::read_tar(&archive)?;
+            if !members.iter().any(|member| member.path == "manifest.txt") {
+                return Err(SentryError::Message(format!("{:?} holds no manifest.txt, so it is not a release bundle", bundle_path)));
+            }
+            // Mixing a bundle into files left from another release would make verification
+            // meaningless, so only a fresh or empty folder is accepted.
+            if fs::read_dir(&dest).is_ok_and(|mut entries| entries.next().is_some()) {
+                return Err(SentryError::Message(format!("Destination {:?} is not empty", dest)));
+            }
+            bundle::extract(&members, &dest)?;
+            let manifest = load_manifest(&dest.join("manifest.txt"))?;
+            let report = verify_bins(&dest, &bundled_entries(&manifest), check_mode, false)?;
+            let document = render_json_status("unbundle", mode, &env_settings, &manifest, &report, rt.clock.now_millis(), &[]);
+            output.emit(&with_json_field(&document, "dest", &format!("\"{}\"", json_escape(&dest.to_string_lossy()))))?;
+            report_outcome(&report)
+        }
+        Command::PushRelease { release_dir, target, options } => {
+            let manifest = load_manifest_with(&release_dir.join("manifest.txt"), true)?;
+            let bundle_path = release_dir.join(format!("omega-{}.tar", manifest.release_id));
+            if !bundle_path.is_file() {
+                return Err(SentryError::Message(format!("{:?} is missing; run build with --bundle first", bundle_path)));
+            }
+            let report = transfer::push(&target, &bundle_path, options)?;
+            let document = format!(
+                "{{\"action\":\"push-release\",\"mode\":\"{}\",\"release_id\":\"{}\",\"push\":{}}}",
+                mode.as_str(),
+                json_escape(&manifest.release_id),
+                report.to_json()
+            );
+            output.emit(&document)?;
+            if report.installed.is_some() { CliOutcome::Success } else { CliOutcome::VerificationFailed }
+        }
+        Command::ReceiveRelease { listen, releases_dir, check_mode, once } => {
+            fs::create_dir_all(&releases_dir).map_err(|err| format!("Unable to create {:?}: {err}", releases_dir))?;
+            let listener = TcpListener::bind(&listen).map_err(|err| format!("Unable to listen on {listen}: {err}"))?;
+            let address = listener.local_addr().map(|address| address.to_string()).unwrap_or(listen);
+            LOG.info("Waiting for release bundles", &[("address", &address), ("releases_dir", &releases_dir.display().to_string())]);
+            let install = |bundle: &transfer::ReceivedBundle| {
+                install_received_bundle(bundle, &releases_dir, check_mode, mode, &env_settings, rt.clock.now_millis())
+            };
+            // One pusher at a time: a release is small, and two uploads of one bundle must not interleave.
+            loop {
+                let mut stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        LOG.warn("Could not accept a connection", &[("error", &err.to_string())]);
+                        continue;
+                    }
+                };
+                let outcome = match transfer::receive(&mut stream, &releases_dir, &install) {
+                    transfer::Received::Installed(document) => {
+                        output.emit(&document)?;
+                        CliOutcome::Success
+                    }
+                    transfer::Received::Refused(reason) => {
+                        LOG.warn("Release transfer refused", &[("reason", &reason)]);
+                        output.emit(&format!(
+                            "{{\"action\":\"receive-release\",\"mode\":\"{}\",\"status\":\"refused\",\"error\":\"{}\"}}",
+                            mode.as_str(),
+                            json_escape(&reason)
+                        ))?;
+                        CliOutcome::VerificationFailed
+                    }
+                    transfer::Received::Incomplete(reason) => {
+                        // The acknowledged bytes wait in `.incoming` for a `--resume`.
+                        LOG.warn("Release transfer interrupted", &[("reason", &reason)]);
+                        continue;
+                    }
+                };
+                if once {
+                    break outcome;
+                }
+            }
+        }
+        Command::IssueToken { release_id, ttl_secs } => {
+            let key = load_token_key(rt.env)?;
+            let token = verify_token::VerifyRequestToken::issue_at(key.expose(), &release_id, ttl_secs, now_unix())?;
+            if !output.quiet {
+                println!("{token}");
+            }
+            CliOutcome::Success
+        }
+        Command::HashDir { root, ignore } => {
+            // JSON lines rather than one document, so `--pretty` does not apply; `--quiet` and
+            // `--output` still do.
+            let listing = hash_dir::hash_dir(&root, &ignore)?;
+            let lines = hash_dir::render_lines(mode, &root, &listing);
+            if !output.quiet {
+                match &output.path {
+                    Some(path) => write_atomic(path, lines.as_bytes())?,
+                    None => print!("{lines}"),
+                }
+            }
+            CliOutcome::Success
+        }
+        Command::Report { log_file, since_hours, json } => {
+            let since_unix = since_hours.map(|hours| now_unix().saturating_sub(hours.saturating_mul(3_600)));
+            let report = history::load_report(&log_file, since_unix)?;
+            if json {
+                output.emit(&report.to_json(mode))?;
+            } else if !output.quiet {
+                // The table is for people, so like `prove` it bypasses `--pretty`.
+                let table = report.render_table();
+                match &output.path {
+                    Some(path) => write_atomic(path, table.as_bytes())?,
+                    None => print!("{table}"),
+                }
+            }
+            CliOutcome::Success
+        }
+        Command::Status { releases_dir, state_file, stale_after_secs } => {
+            let request = status::StatusRequest { releases_dir, state_file, stale_after_secs, now_unix: now_unix() };
+            let found = status::collect(mode, &env_settings, &request);
+            output.emit(&found.to_json())?;
+            if !output.quiet {
+                // The summary goes to stderr so stdout stays one JSON document.
+                eprint!("{}", found.render_summary());
+            }
+            match found.overall() {
+                status::Health::Ok => CliOutcome::Success,
+                status::Health::Warning => CliOutcome::Warning,
+                status::Health::Failing => CliOutcome::VerificationFailed,
+            }
+        }
+        Command::Inspect { manifest_path, table, strict } => {
+            // Absolute entry paths are fine here: no file is opened through them.
+            let manifest = read_manifest_arg(&manifest_path, true)?;
+            let found = inspect::inspect(&manifest, &manifest_path);
+            if !table {
+                // Read by people first, so indented even without `--pretty`.
+                OutputOptions { pretty: true, ..output }.emit(&found.to_json(mode, strict))?;
+            } else if !output.quiet {
+                let text = found.render_table();
+                match &output.path {
+                    Some(path) => write_atomic(path, text.as_bytes())?,
+                    None => print!("{text}"),
+                }
+            }
+            if strict && !found.warnings.is_empty() {
+                return Err(SentryError::Message(format!("{} manifest warning(s) and --strict was given", found.warnings.len())));
+            }
+            CliOutcome::Success
         }
-        _ => Err("Unknown subcommand".to_string()),
-    }
-}
-
-fn take_flag(name: &str, args: &[String], index: &mut usize) -> Result<String, String> {
-    let Some(flag) = args.get(*index) else {
-        return Err(format!("Missing required flag {name}"));
     };
 
-    if flag != name {
-        return Err(format!("Expected {name} but found {flag}"));
-    }
+    Ok(outcome)
+}
 
-    let Some(value) = args.get(*index + 1) else {
-        return Err(format!("{name} requires a value"));
-    };
-    *index += 2;
-    Ok(value.clone())
+/// One flag a subcommand understands. Flags with a `value_name` take a value (`--flag value` or
+/// `--flag=value`); the rest are on/off switches.
+struct FlagSpec {
+    name: &'static str,
+    value_name: Option<&'static str>,
+    required: bool,
+    help: &'static str,
 }
 
-fn take_optional_flag(name: &str, args: &[String], index: &mut usize) -> Option<String> {
-    if args.get(*index).map(|v| v.as_str()) != Some(name) {
-        return None;
-    }
+/// Everything the parser and the `--help` text need to know about one subcommand.
+struct CommandSpec {
+    name: &'static str,
+    summary: &'static str,
+    flags: &'static [FlagSpec],
+    /// Flags that may be given more than once; every other flag may appear only once.
+    repeatable: &'static [&'static str],
+}
+
+/// Flags accepted before or after any subcommand.
+const GLOBAL_FLAGS: &[FlagSpec] = &[
+    FlagSpec { name: "--mode", value_name: Some("blue|yellow|red"), required: false, help: "Override the binary's default mode." },
+    FlagSpec { name: "--output", value_name: Some("path|-"), required: false, help: "Write the JSON document to a file (replaced atomically) or - for stdout." },
+    FlagSpec { name: "--quiet", value_name: None, required: false, help: "Print no JSON; rely on the exit code (0 match, 2 mismatch, 1 error)." },
+    FlagSpec { name: "--pretty", value_name: None, required: false, help: "Indent the JSON for humans." },
+];
+
+const COMMAND_SPECS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "build",
+        summary: "Hash every binary in --bins-dir and write a manifest into --releases-dir.",
+        flags: &[
+            FlagSpec { name: "--bins-dir", value_name: Some("dir"), required: true, help: "Directory of binaries to hash." },
+            FlagSpec { name: "--releases-dir", value_name: Some("dir"), required: true, help: "Where the manifest is written." },
+            FlagSpec { name: "--release-id", value_name: Some("id|auto"), required: false, help: "Release label (default auto: <yyyymmdd>-<content hash>)." },
+            FlagSpec { name: "--source-rev", value_name: Some("sha"), required: false, help: "Source revision recorded as provenance." },
+            FlagSpec { name: "--rustc-version", value_name: Some("text"), required: false, help: "Record this instead of running rustc --version." },
+            FlagSpec { name: "--per-file-sigs", value_name: None, required: false, help: "Write an HMAC <name>.sig per binary (needs SENTRY_SIGNING_KEY)." },
+            FlagSpec { name: "--sign-key-envelope", value_name: Some("json"), required: false, help: "Read the signing key from a vault envelope (feature vault-keys)." },
+            FlagSpec { name: "--bundle", value_name: None, required: false, help: "Also write omega-<release id>.tar with the binaries and manifest." },
+            FlagSpec { name: "--source-date-epoch", value_name: Some("secs"), required: false, help: "Fixed timestamp for the manifest and bundle (reproducible builds)." },
+            FlagSpec { name: "--recursive", value_name: None, required: false, help: "Also hash binaries in folders below --bins-dir (symlinks are skipped)." },
+            FlagSpec { name: "--digests", value_name: Some("sha256,sha512"), required: false, help: "Standard digests recorded per entry (default sha256)." },
+            FlagSpec { name: "--allow-networked-blue", value_name: None, required: false, help: "In blue mode, build even though Yellow or Red is reachable." },
+            FlagSpec { name: "--expect-executables", value_name: None, required: false, help: "Warn about top-level files that are not ELF, PE, or Mach-O programs." },
+            FlagSpec { name: "--min-executable-size", value_name: Some("bytes"), required: false, help: "Smallest believable program (default 4096; implies --expect-executables)." },
+            FlagSpec { name: "--skip-space-check", value_name: None, required: false, help: "Write even when the disk seems too full for the release." },
+            FlagSpec { name: "--owners-file", value_name: Some("file"), required: false, help: "Annotate entries with pattern=owner[,signer] rules (see README)." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "verify",
+        summary: "Compare the binaries in --bins-dir with a saved manifest.",
+        flags: &[
+            FlagSpec { name: "--bins-dir", value_name: Some("[label=]dir"), required: false, help: "Directory of binaries to check; repeat it to check several slots (default: the manifest's root)." },
+            FlagSpec { name: "--manifest", value_name: Some("file|-"), required: true, help: "Manifest produced by build, or - to read it from stdin." },
+            FlagSpec { name: "--require-source-rev", value_name: Some("sha"), required: false, help: "Fail unless the manifest records this source revision." },
+            FlagSpec { name: "--check-mode", value_name: Some("off|exec-only|full"), required: false, help: "Compare file modes too (default exec-only: just the x bits)." },
+            FlagSpec { name: "--allow-exe-suffix", value_name: None, required: false, help: "Accept name.exe for name and back (manifests from another OS)." },
+            FlagSpec { name: "--trust-absolute-paths", value_name: None, required: false, help: "Accept absolute file paths in a manifest you trust." },
+            FlagSpec { name: "--waivers", value_name: Some("file"), required: false, help: "Accept listed, unexpired mismatches as waived (see README)." },
+            FlagSpec { name: "--per-file-sigs", value_name: None, required: false, help: "Also check each binary's <name>.sig (needs SENTRY_SIGNING_KEY)." },
+            FlagSpec { name: "--sign-key-envelope", value_name: Some("json"), required: false, help: "Read the signing key from a vault envelope (feature vault-keys)." },
+            FlagSpec { name: "--publish", value_name: None, required: false, help: "Send the JSON to the next role's host." },
+            FlagSpec { name: "--publish-to", value_name: Some("host:port"), required: false, help: "Send the JSON here instead (implies --publish)." },
+            FlagSpec { name: "--explain", value_name: None, required: false, help: "Say whether each mismatch was truncated, appended, modified, or replaced." },
+            FlagSpec { name: "--token", value_name: Some("token"), required: false, help: "Request token from issue-token (needs SENTRY_VERIFY_TOKEN_KEY)." },
+            FlagSpec { name: "--require-token", value_name: None, required: false, help: "Refuse to verify without a valid --token for this release." },
+            FlagSpec { name: "--max-clock-skew-secs", value_name: Some("n"), required: false, help: "Accept a token up to n seconds past its expiry (default 120)." },
+        ],
+        repeatable: &["--bins-dir"],
+    },
+    CommandSpec {
+        name: "daemon",
+        summary: "Repeat verify forever, sleeping between passes.",
+        flags: &[
+            FlagSpec { name: "--bins-dir", value_name: Some("[label=]dir"), required: true, help: "Directory of binaries to check; repeat it to check several slots." },
+            FlagSpec { name: "--manifest", value_name: Some("file"), required: true, help: "Manifest produced by build." },
+            FlagSpec { name: "--interval-seconds", value_name: Some("n"), required: false, help: "Pause between passes (default 60)." },
+            FlagSpec { name: "--interval-jitter-pct", value_name: Some("p"), required: false, help: "Move each pause by up to +/-p% at random (default 10)." },
+            FlagSpec { name: "--relax-after", value_name: Some("n"), required: false, help: "Double the pause after n clean passes in a row (default 10, 0 = never)." },
+            FlagSpec { name: "--max-interval-seconds", value_name: Some("n"), required: false, help: "Longest pause relaxing may reach (default 600)." },
+            FlagSpec { name: "--publish", value_name: None, required: false, help: "Send each pass to the next role's host." },
+            FlagSpec { name: "--publish-to", value_name: Some("host:port"), required: false, help: "Send each pass here instead (implies --publish)." },
+            FlagSpec { name: "--listen", value_name: Some("addr:port"), required: false, help: "Serve GET /status and /healthz over HTTP." },
+            FlagSpec { name: "--check-mode", value_name: Some("off|exec-only|full"), required: false, help: "Compare file modes too (default exec-only: just the x bits)." },
+            FlagSpec { name: "--allow-exe-suffix", value_name: None, required: false, help: "Accept name.exe for name and back (manifests from another OS)." },
+            FlagSpec { name: "--trust-absolute-paths", value_name: None, required: false, help: "Accept absolute file paths in a manifest you trust." },
+            FlagSpec { name: "--waivers", value_name: Some("file"), required: false, help: "Accept listed, unexpired mismatches as waived (see README)." },
+            FlagSpec { name: "--heartbeat", value_name: Some("file"), required: false, help: "Rewrite this liveness file every pass (e.g. Discovery/heartbeat.txt)." },
+            FlagSpec { name: "--log-file", value_name: Some("file"), required: false, help: "Append one summary line per pass here (read it with report)." },
+            FlagSpec { name: "--cache-file", value_name: Some("file"), required: false, help: "Skip re-hashing files whose size and mtime are unchanged." },
+            FlagSpec { name: "--full-rehash-every", value_name: Some("n"), required: false, help: "Ignore the cache every n passes (default 10, 1 = always)." },
+            FlagSpec { name: "--no-cache", value_name: None, required: false, help: "Hash every file every pass, even with --cache-file." },
+            FlagSpec { name: "--alert-file", value_name: Some("file"), required: false, help: "Append a JSON line here when verification fails, recovers, or a reload fails." },
+            FlagSpec { name: "--alert-command", value_name: Some("prog"), required: false, help: "Run prog with each alert's JSON on stdin (no shell, no arguments)." },
+            FlagSpec { name: "--alert-timeout-seconds", value_name: Some("n"), required: false, help: "Kill --alert-command after n seconds (default 10)." },
+            FlagSpec { name: "--peer-status", value_name: Some("file"), required: false, help: "Peer's latest status JSON; warn (and alert after 3) when its clock is skewed." },
+            FlagSpec { name: "--max-clock-skew-secs", value_name: Some("n"), required: false, help: "Clock difference allowed with --peer-status (default 120)." },
+            FlagSpec { name: "--min-free-mb", value_name: Some("n"), required: false, help: "Warn low-disk when a folder written to has less free (default 256)." },
+        ],
+        repeatable: &["--bins-dir"],
+    },
+    CommandSpec {
+        name: "prove",
+        summary: "Print a Merkle inclusion proof for one manifest entry.",
+        flags: &[
+            FlagSpec { name: "--manifest", value_name: Some("file"), required: true, help: "Manifest that contains the entry." },
+            FlagSpec { name: "--name", value_name: Some("entry"), required: true, help: "Entry to prove: its relative path, or a file name only it has." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "cross-check",
+        summary: "Compare two saved status documents and list disagreements.",
+        flags: &[
+            FlagSpec { name: "--mine", value_name: Some("file"), required: true, help: "This host's status JSON (from --output)." },
+            FlagSpec { name: "--theirs", value_name: Some("file"), required: true, help: "The other host's status JSON." },
+            FlagSpec { name: "--max-clock-skew-secs", value_name: Some("n"), required: false, help: "Warn when their timestamp is further than n seconds from ours (default 120)." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "prune",
+        summary: "Delete old omega-* release folders, always keeping the newest.",
+        flags: &[
+            FlagSpec { name: "--releases-dir", value_name: Some("dir"), required: true, help: "Directory holding omega-* release folders." },
+            FlagSpec { name: "--keep", value_name: Some("n"), required: false, help: "Keep the newest N releases." },
+            FlagSpec { name: "--older-than-days", value_name: Some("d"), required: false, help: "Delete releases older than D days." },
+            FlagSpec { name: "--dry-run", value_name: None, required: false, help: "Only list what would be deleted." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "check-proof",
+        summary: "Check one file against a proof written by prove.",
+        flags: &[
+            FlagSpec { name: "--proof", value_name: Some("file"), required: true, help: "Proof file from prove." },
+            FlagSpec { name: "--file", value_name: Some("path"), required: true, help: "Binary to check." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "issue-token",
+        summary: "Print a short-lived token that lets verify --require-token check one release.",
+        flags: &[
+            FlagSpec { name: "--release-id", value_name: Some("id"), required: true, help: "Release the token is for." },
+            FlagSpec { name: "--ttl-secs", value_name: Some("n"), required: false, help: "How long the token stays valid (default 300)." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "hash-dir",
+        summary: "Print the SHA-256 of every file under a folder as JSON lines.",
+        flags: &[
+            FlagSpec { name: "--path", value_name: Some("dir"), required: true, help: "Folder to walk (symbolic links are skipped)." },
+            FlagSpec { name: "--ignore", value_name: Some("glob,..."), required: false, help: "Comma-separated patterns to leave out, e.g. *.log,target." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "unbundle",
+        summary: "Unpack a bundle written by build --bundle and verify it.",
+        flags: &[
+            FlagSpec { name: "--bundle", value_name: Some("file"), required: true, help: "The omega-<release id>.tar to unpack." },
+            FlagSpec { name: "--dest", value_name: Some("dir"), required: true, help: "Empty or new folder to unpack into." },
+            FlagSpec { name: "--check-mode", value_name: Some("off|exec-only|full"), required: false, help: "Compare file modes too (default exec-only: just the x bits)." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "relocate",
+        summary: "Rewrite a manifest's root= and make its entry paths relative.",
+        flags: &[
+            FlagSpec { name: "--manifest", value_name: Some("file"), required: true, help: "Manifest to rewrite in place." },
+            FlagSpec { name: "--root", value_name: Some("dir"), required: true, help: "Folder the binaries live in now." },
+            FlagSpec { name: "--per-file-sigs", value_name: None, required: false, help: "Re-sign every entry from the files under --root (needs SENTRY_SIGNING_KEY)." },
+            FlagSpec { name: "--sign-key-envelope", value_name: Some("json"), required: false, help: "Read the signing key from a vault envelope (feature vault-keys)." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "push-release",
+        summary: "Send a release bundle to a receive-release in checked, resumable chunks.",
+        flags: &[
+            FlagSpec { name: "--release-dir", value_name: Some("dir"), required: true, help: "Release folder holding omega-<release id>.tar (build --bundle)." },
+            FlagSpec { name: "--to", value_name: Some("host:port"), required: true, help: "Address of the receive-release to send to." },
+            FlagSpec { name: "--resume", value_name: None, required: false, help: "Continue an interrupted upload and reconnect when the link drops." },
+            FlagSpec { name: "--chunk-size", value_name: Some("bytes"), required: false, help: "Bytes per chunk (default 65536, at most 1048576)." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "receive-release",
+        summary: "Accept pushed release bundles, verify them, and move them into --releases-dir.",
+        flags: &[
+            FlagSpec { name: "--listen", value_name: Some("addr:port"), required: true, help: "Address to accept pushes on." },
+            FlagSpec { name: "--releases-dir", value_name: Some("dir"), required: true, help: "Where verified releases go (partial uploads wait in .incoming)." },
+            FlagSpec { name: "--check-mode", value_name: Some("off|exec-only|full"), required: false, help: "Compare file modes too (default exec-only: just the x bits)." },
+            FlagSpec { name: "--once", value_name: None, required: false, help: "Exit after the first finished transfer (exit 2 if it was refused)." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "report",
+        summary: "Summarize a daemon --log-file: cycles, failures, and clean streaks.",
+        flags: &[
+            FlagSpec { name: "--log-file", value_name: Some("file"), required: true, help: "Cycle log written by daemon --log-file." },
+            FlagSpec { name: "--since", value_name: Some("hours"), required: false, help: "Only count cycles from the last N hours." },
+            FlagSpec { name: "--format", value_name: Some("table|json"), required: false, help: "Output a table for people (default) or JSON." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "status",
+        summary: "Summarize the newest release, its signature, and the daemon's last report; exit 4 on warnings.",
+        flags: &[
+            FlagSpec { name: "--releases-dir", value_name: Some("dir"), required: true, help: "Folder holding the omega-* releases." },
+            FlagSpec { name: "--state-file", value_name: Some("file"), required: false, help: "JSON written by daemon --output, to judge the last pass." },
+            FlagSpec { name: "--stale-after-seconds", value_name: Some("n"), required: false, help: "Warn when --state-file is older than this (default 180)." },
+        ],
+        repeatable: &[],
+    },
+    CommandSpec {
+        name: "inspect",
+        summary: "Summarize a manifest and check that it agrees with itself; no binary is read.",
+        flags: &[
+            FlagSpec { name: "--manifest", value_name: Some("file|-"), required: true, help: "Manifest to look at, or - to read it from stdin." },
+            FlagSpec { name: "--format", value_name: Some("json|table"), required: false, help: "Output indented JSON (default) or a table for people." },
+            FlagSpec { name: "--strict", value_name: None, required: false, help: "Exit with 1 when there is any warning." },
+        ],
+        repeatable: &[],
+    },
+];
 
-    let value = args.get(*index + 1)?;
-    *index += 2;
-    Some(value.clone())
+/// Flags collected from the command line, keyed by flag name. Switches are stored with an empty
+/// value so `has` works for both kinds. Only a command's `repeatable` flags hold more than one.
+struct ParsedFlags {
+    values: HashMap<&'static str, Vec<String>>,
 }
 
-fn build_manifest(mode: Mode, bins_dir: &Path, release_id: String) -> Result<OmegaManifest, String> {
-    if !bins_dir.is_dir() {
-        return Err(format!("Binary directory {:?} not found", bins_dir));
+impl ParsedFlags {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).and_then(|values| values.first()).map(|value| value.as_str())
     }
 
-    let mut entries = Vec::new();
-    let mut dir_entries: Vec<_> = fs::read_dir(bins_dir)
-        .map_err(|err| format!("Unable to read bin directory: {err}"))?
-        .collect();
-    dir_entries.sort_by_key(|entry| entry.as_ref().ok().map(|e| e.path()));
+    /// Every value of a repeatable flag, in the order given.
+    fn all(&self, name: &str) -> &[String] {
+        self.values.get(name).map_or(&[], Vec::as_slice)
+    }
 
-    for entry in dir_entries {
-        let entry = entry.map_err(|err| format!("Failed to read file entry: {err}"))?;
-        let metadata = entry.metadata().map_err(|err| format!("Failed to read metadata: {err}"))?;
-        if !metadata.is_file() {
-            continue;
+    fn has(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    /// `--publish` and `--publish-to` fold into one setting for `verify` and `daemon`.
+    fn publish(&self) -> Option<Option<String>> {
+        match self.get("--publish-to") {
+            Some(target) => Some(Some(target.to_string())),
+            None if self.has("--publish") => Some(None),
+            None => None,
         }
+    }
 
-        let path = entry.path();
-        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
-        let content = fs::read(&path).map_err(|err| format!("Failed to read {:?}: {err}", path))?;
-        let hash = hash_bytes(&content);
+    /// `--check-mode` for `verify` and `daemon`; `exec-only` when absent.
+    fn check_mode(&self) -> Result<ModeCheck, String> {
+        self.get("--check-mode").map_or(Ok(ModeCheck::ExecOnly), ModeCheck::parse)
+    }
 
-        entries.push(ManifestEntry {
-            name,
-            path: path.to_string_lossy().to_string(),
-            hash,
-            size: metadata.len(),
-        });
+    /// Fetch a flag the spec marks as required. The parser already reported missing ones, so an
+    /// absent value here means the spec table and the command builder disagree.
+    fn required(&self, name: &str) -> Result<String, String> {
+        self.get(name)
+            .map(str::to_string)
+            .ok_or_else(|| format!("Missing required flag {name}"))
     }
+}
 
-    Ok(OmegaManifest {
-        release_id,
-        mode,
-        signature_note: "Detached signatures live alongside manifest files. Add them after signing on Sentry Blue.".to_string(),
-        entries,
-    })
+fn command_names() -> String {
+    COMMAND_SPECS.iter().map(|spec| spec.name).collect::<Vec<_>>().join(", ")
 }
 
-fn persist_manifest(manifest: &OmegaManifest, releases_dir: &Path) -> Result<(), String> {
-    if manifest.entries.is_empty() {
-        return Err("No binaries were discovered to record in the manifest".to_string());
+fn parse_args(default_mode: Mode, args: &[String]) -> Result<Invocation, String> {
+    // Step 1: find the subcommand. Global flags such as `--mode` may appear before it.
+    let mut index = 0usize;
+    let mut leading = Vec::new();
+    while let Some(arg) = args.get(index) {
+        if !arg.starts_with("--") {
+            break;
+        }
+        if arg == "--help" {
+            return Ok(Invocation { mode: default_mode, output: OutputOptions::default(), command: Command::Help(general_help()) });
+        }
+        leading.push(arg.clone());
+        // A global flag written as `--mode blue` also owns the next word.
+        if !arg.contains('=') && GLOBAL_FLAGS.iter().any(|flag| flag.name == arg && flag.value_name.is_some()) {
+            if let Some(value) = args.get(index + 1) {
+                leading.push(value.clone());
+                index += 1;
+            }
+        }
+        index += 1;
     }
 
-    let release_folder = releases_dir.join(format!("omega-{}", manifest.release_id));
-    fs::create_dir_all(&release_folder).map_err(|err| format!("Unable to create releases directory: {err}"))?;
+    let Some(command_name) = args.get(index) else {
+        return Err(format!("Missing subcommand ({})", command_names()));
+    };
+    let Some(spec) = COMMAND_SPECS.iter().find(|spec| spec.name == command_name) else {
+        return Err(format!("Unknown subcommand {command_name}; expected one of: {}", command_names()));
+    };
+
+    // Step 2: collect every flag into a map, in whatever order the user typed them.
+    let rest: Vec<String> = leading.into_iter().chain(args[index + 1..].iter().cloned()).collect();
+    let Some(flags) = collect_flags(spec, &rest)? else {
+        return Ok(Invocation { mode: default_mode, output: OutputOptions::default(), command: Command::Help(command_help(spec)) });
+    };
 
-    let manifest_path = release_folder.join("manifest.txt");
-    let mut file = fs::File::create(&manifest_path).map_err(|err| format!("Unable to create manifest: {err}"))?;
-    let contents = render_manifest(manifest);
-    file.write_all(contents.as_bytes()).map_err(|err| format!("Unable to write manifest: {err}"))?;
+    // Step 3: build the command from the validated map.
+    let mode = match flags.get("--mode") {
+        Some(value) => value.parse::<Mode>()?,
+        None => default_mode,
+    };
+    let output = OutputOptions {
+        path: flags.get("--output").filter(|value| *value != "-").map(PathBuf::from),
+        quiet: flags.has("--quiet"),
+        pretty: flags.has("--pretty"),
+    };
 
-    let signature_path = release_folder.join("manifest.txt.sig");
-    if !signature_path.exists() {
-        // Leave a friendly placeholder to remind operators to add a signed file.
-        let mut sig_file = fs::File::create(&signature_path).map_err(|err| format!("Unable to prepare signature placeholder: {err}"))?;
-        sig_file
-            .write_all(b"Add detached signature from Sentry Blue here.\n")
-            .map_err(|err| format!("Unable to write signature placeholder: {err}"))?;
+    if flags.has("--sign-key-envelope") && !flags.has("--per-file-sigs") {
+        return Err("--sign-key-envelope only makes sense together with --per-file-sigs".to_string());
     }
 
-    Ok(())
+    let command = match spec.name {
+        "build" => Command::Build {
+            bins_dir: PathBuf::from(flags.required("--bins-dir")?),
+            releases_dir: PathBuf::from(flags.required("--releases-dir")?),
+            release_id: flags.get("--release-id").unwrap_or(AUTO_RELEASE_ID).to_string(),
+            source_rev: flags.get("--source-rev").map(str::to_string),
+            rustc_version: flags.get("--rustc-version").map(str::to_string),
+            per_file_sigs: flags.has("--per-file-sigs"),
+            sign_key_envelope: flags.get("--sign-key-envelope").map(PathBuf::from),
+            bundle: flags.has("--bundle"),
+            source_date_epoch: match flags.get("--source-date-epoch") {
+                Some(value) => Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| format!("--source-date-epoch must be whole seconds since 1970, got {value}"))?,
+                ),
+                None => None,
+            },
+            recursive: flags.has("--recursive"),
+            digests: match flags.get("--digests") {
+                Some(value) => digest::parse_list(value)?,
+                None => digest::DEFAULT_DIGESTS.to_vec(),
+            },
+            allow_networked_blue: flags.has("--allow-networked-blue"),
+            expect_executables: match flags.get("--min-executable-size") {
+                Some(value) => Some(value.parse::<u64>().map_err(|_| format!("--min-executable-size must be a whole number of bytes, got {value}"))?),
+                None if flags.has("--expect-executables") => Some(filetype::DEFAULT_MIN_EXECUTABLE_SIZE),
+                None => None,
+            },
+            skip_space_check: flags.has("--skip-space-check"),
+            owners_file: flags.get("--owners-file").map(PathBuf::from),
+        },
+        "verify" => Command::Verify {
+            bins_dirs: slots::parse_slots(flags.all("--bins-dir"))?,
+            manifest_path: PathBuf::from(flags.required("--manifest")?),
+            per_file_sigs: match flags.has("--per-file-sigs") {
+                // The `.sig` files are looked up beside the manifest, and a pipe has no folder.
+                true if flags.get("--manifest") == Some(STDIN_MANIFEST) => {
+                    return Err("--per-file-sigs reads <name>.sig files beside the manifest, so it needs --manifest <file>, not -".to_string())
+                }
+                per_file_sigs => per_file_sigs,
+            },
+            sign_key_envelope: flags.get("--sign-key-envelope").map(PathBuf::from),
+            require_source_rev: flags.get("--require-source-rev").map(str::to_string),
+            publish: flags.publish(),
+            check_mode: flags.check_mode()?,
+            allow_exe_suffix: flags.has("--allow-exe-suffix"),
+            trust_absolute_paths: flags.has("--trust-absolute-paths"),
+            waivers: flags.get("--waivers").map(PathBuf::from),
+            explain: flags.has("--explain"),
+            token: flags.get("--token").map(str::to_string),
+            require_token: flags.has("--require-token"),
+            max_clock_skew: match flags.get("--max-clock-skew-secs") {
+                Some(value) => Duration::from_secs(value.parse::<u64>().map_err(|_| format!("--max-clock-skew-secs must be a whole number, got {value}"))?),
+                None => clock_skew::DEFAULT_MAX_CLOCK_SKEW,
+            },
+        },
+        "daemon" => {
+            let whole_number = |flag: &str, default: u64| -> Result<u64, String> {
+                match flags.get(flag) {
+                    Some(value) => value.parse::<u64>().map_err(|_| format!("{flag} must be a whole number, got {value}")),
+                    None => Ok(default),
+                }
+            };
+            let mut schedule = ScheduleSettings::new(Duration::from_secs(whole_number("--interval-seconds", 60)?));
+            if flags.get("--max-interval-seconds").is_some() {
+                schedule.max = Duration::from_secs(whole_number("--max-interval-seconds", 0)?);
+                if schedule.max < schedule.base {
+                    return Err("--max-interval-seconds must not be shorter than --interval-seconds".to_string());
+                }
+            }
+            schedule.relax_after = whole_number("--relax-after", schedule::DEFAULT_RELAX_AFTER)?;
+            schedule.jitter_pct = match whole_number("--interval-jitter-pct", u64::from(schedule::DEFAULT_JITTER_PCT))? {
+                pct @ 0..=100 => pct as u8,
+                pct => return Err(format!("--interval-jitter-pct must be from 0 to 100, got {pct}")),
+            };
+            let manifest_path = flags.required("--manifest")?;
+            if manifest_path == STDIN_MANIFEST {
+                // Standard input can be read once; the daemon re-reads the file to notice new releases.
+                return Err("daemon cannot read --manifest - (stdin): it re-reads the manifest every pass, so save it to a file and pass that".to_string());
+            }
+            Command::Daemon {
+                bins_dirs: slots::parse_slots(flags.all("--bins-dir"))?,
+                manifest_path: PathBuf::from(manifest_path),
+                check_mode: flags.check_mode()?,
+                allow_exe_suffix: flags.has("--allow-exe-suffix"),
+                trust_absolute_paths: flags.has("--trust-absolute-paths"),
+                waivers: flags.get("--waivers").map(PathBuf::from),
+                schedule,
+                publish: flags.publish(),
+                listen: flags.get("--listen").map(str::to_string),
+                heartbeat: flags.get("--heartbeat").map(PathBuf::from),
+                log_file: flags.get("--log-file").map(PathBuf::from),
+                cache_file: flags.get("--cache-file").filter(|_| !flags.has("--no-cache")).map(PathBuf::from),
+                full_rehash_every: match whole_number("--full-rehash-every", verify_cache::DEFAULT_FULL_REHASH_EVERY)? {
+                    0 => return Err("--full-rehash-every must be at least 1".to_string()),
+                    every => every,
+                },
+                alert_file: flags.get("--alert-file").map(PathBuf::from),
+                alert_command: flags.get("--alert-command").map(str::to_string),
+                alert_timeout: match whole_number("--alert-timeout-seconds", alert::DEFAULT_COMMAND_TIMEOUT.as_secs())? {
+                    0 => return Err("--alert-timeout-seconds must be at least 1".to_string()),
+                    seconds => Duration::from_secs(seconds),
+                },
+                peer_status: flags.get("--peer-status").map(PathBuf::from),
+                max_clock_skew: Duration::from_secs(whole_number("--max-clock-skew-secs", clock_skew::DEFAULT_MAX_CLOCK_SKEW.as_secs())?),
+                min_free_mb: whole_number("--min-free-mb", disk_space::DEFAULT_MIN_FREE_MB)?,
+            }
+        }
+        "prove" => Command::Prove {
+            manifest_path: PathBuf::from(flags.required("--manifest")?),
+            name: flags.required("--name")?,
+        },
+        "check-proof" => Command::CheckProof {
+            proof_path: PathBuf::from(flags.required("--proof")?),
+            file_path: PathBuf::from(flags.required("--file")?),
+        },
+        "cross-check" => Command::CrossCheck {
+            mine_path: PathBuf::from(flags.required("--mine")?),
+            theirs_path: PathBuf::from(flags.required("--theirs")?),
+            max_clock_skew: match flags.get("--max-clock-skew-secs") {
+                Some(value) => Duration::from_secs(value.parse::<u64>().map_err(|_| format!("--max-clock-skew-secs must be a whole number, got {value}"))?),
+                None => clock_skew::DEFAULT_MAX_CLOCK_SKEW,
+            },
+        },
+        "prune" => {
+            let keep = match flags.get("--keep") {
+                Some(value) => Some(value.parse::<usize>().map_err(|_| format!("--keep must be a whole number, got {value}"))?),
+                None => None,
+            };
+            let older_than_days = match flags.get("--older-than-days") {
+                Some(value) => Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| format!("--older-than-days must be a whole number, got {value}"))?,
+                ),
+                None => None,
+            };
+            Command::Prune {
+                releases_dir: PathBuf::from(flags.required("--releases-dir")?),
+                policy: prune::RetentionPolicy { keep, older_than_days },
+                dry_run: flags.has("--dry-run"),
+            }
+        }
+        "issue-token" => Command::IssueToken {
+            release_id: flags.required("--release-id")?.to_string(),
+            ttl_secs: match flags.get("--ttl-secs") {
+                Some(value) => value.parse::<u64>().map_err(|_| format!("--ttl-secs must be a whole number, got {value}"))?,
+                None => verify_token::DEFAULT_TTL_SECS,
+            },
+        },
+        "hash-dir" => Command::HashDir {
+            root: PathBuf::from(flags.required("--path")?),
+            ignore: flags
+                .get("--ignore")
+                .map(|patterns| patterns.split(',').map(str::trim).filter(|p| !p.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default(),
+        },
+        "unbundle" => Command::Unbundle {
+            bundle_path: PathBuf::from(flags.required("--bundle")?),
+            dest: PathBuf::from(flags.required("--dest")?),
+            check_mode: flags.check_mode()?,
+        },
+        "relocate" => Command::Relocate {
+            manifest_path: PathBuf::from(flags.required("--manifest")?),
+            root: PathBuf::from(flags.required("--root")?),
+            per_file_sigs: flags.has("--per-file-sigs"),
+            sign_key_envelope: flags.get("--sign-key-envelope").map(PathBuf::from),
+        },
+        "push-release" => Command::PushRelease {
+            release_dir: PathBuf::from(flags.required("--release-dir")?),
+            target: flags.required("--to")?,
+            options: transfer::PushOptions {
+                chunk_size: match flags.get("--chunk-size") {
+                    Some(value) => match value.parse::<usize>() {
+                        Ok(bytes @ 1..=transfer::MAX_CHUNK_SIZE) => bytes,
+                        _ => return Err(format!("--chunk-size must be from 1 to {} bytes, got {value}", transfer::MAX_CHUNK_SIZE)),
+                    },
+                    None => transfer::DEFAULT_CHUNK_SIZE,
+                },
+                resume: flags.has("--resume"),
+            },
+        },
+        "receive-release" => Command::ReceiveRelease {
+            listen: flags.required("--listen")?,
+            releases_dir: PathBuf::from(flags.required("--releases-dir")?),
+            check_mode: flags.check_mode()?,
+            once: flags.has("--once"),
+        },
+        "report" => Command::Report {
+            log_file: PathBuf::from(flags.required("--log-file")?),
+            since_hours: match flags.get("--since") {
+                Some(value) => Some(value.parse::<u64>().map_err(|_| format!("--since must be a whole number of hours, got {value}"))?),
+                None => None,
+            },
+            json: match flags.get("--format").unwrap_or("table") {
+                "table" => false,
+                "json" => true,
+                other => return Err(format!("--format must be table or json, got {other}")),
+            },
+        },
+        "status" => Command::Status {
+            releases_dir: PathBuf::from(flags.required("--releases-dir")?),
+            state_file: flags.get("--state-file").map(PathBuf::from),
+            stale_after_secs: match flags.get("--stale-after-seconds") {
+                Some(value) => value.parse::<u64>().map_err(|_| format!("--stale-after-seconds must be a whole number, got {value}"))?,
+                None => status::DEFAULT_STALE_AFTER_SECS,
+            },
+        },
+        "inspect" => Command::Inspect {
+            manifest_path: PathBuf::from(flags.required("--manifest")?),
+            table: match flags.get("--format").unwrap_or("json") {
+                "json" => false,
+                "table" => true,
+                other => return Err(format!("--format must be json or table, got {other}")),
+            },
+            strict: flags.has("--strict"),
+        },
+        other => return Err(format!("Subcommand {other} has no handler")),
+    };
+
+    Ok(Invocation { mode, output, command })
 }
 
-fn render_manifest(manifest: &OmegaManifest) -> String {
-    let mut output = String::new();
-    output.push_str(&format!("release_id={}\n", manifest.release_id));
-    output.push_str(&format!("mode={}\n", manifest.mode.as_str()));
-    output.push_str("entries:\n");
+/// Walk the arguments after the subcommand and gather them into a map. Returns `Ok(None)` when
+/// the user asked for `--help`.
+fn collect_flags(spec: &CommandSpec, args: &[String]) -> Result<Option<ParsedFlags>, String> {
+    let mut values: HashMap<&'static str, Vec<String>> = HashMap::new();
+    let mut index = 0usize;
 
-    for entry in &manifest.entries {
-        output.push_str(&format!("{}|{}|{}|{}\n", entry.name, entry.path, entry.hash, entry.size));
-    }
+    while let Some(arg) = args.get(index) {
+        index += 1;
+        if arg == "--help" {
+            return Ok(None);
+        }
+        if !arg.starts_with("--") {
+            return Err(format!("Unexpected argument {arg} for {}; flags look like --name value", spec.name));
+        }
 
-    output.push_str(&format!("signature_note={}\n", manifest.signature_note));
-    output
-}
+        // `--flag=value` carries its value inline; `--flag value` takes the next word.
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
 
-fn load_manifest(path: &Path) -> Result<OmegaManifest, String> {
-    let content = fs::read_to_string(path).map_err(|err| format!("Unable to read manifest: {err}"))?;
-    let mut release_id = String::new();
-    let mut mode = Mode::Yellow;
-    let mut entries = Vec::new();
+        let Some(flag) = spec.flags.iter().chain(GLOBAL_FLAGS).find(|flag| flag.name == name) else {
+            return Err(format!("Unknown flag {name} for {}; valid flags: {}", spec.name, valid_flag_list(spec)));
+        };
+        if values.contains_key(flag.name) && !spec.repeatable.contains(&flag.name) {
+            return Err(format!("Flag {} was given more than once", flag.name));
+        }
+
+        let value = match (flag.value_name, inline_value) {
+            (Some(_), Some(value)) => value,
+            (Some(_), None) => {
+                let Some(value) = args.get(index) else {
+                    return Err(format!("{} requires a value", flag.name));
+                };
+                index += 1;
+                value.clone()
+            }
+            (None, Some(_)) => return Err(format!("{} is a switch and does not take a value", flag.name)),
+            (None, None) => String::new(),
+        };
+        values.entry(flag.name).or_default().push(value);
+    }
+
+    // Report every missing flag at once so the user can fix the command in one go.
+    let missing: Vec<&str> = spec
+        .flags
+        .iter()
+        .filter(|flag| flag.required && !values.contains_key(flag.name))
+        .map(|flag| flag.name)
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("Missing required flags for {}: {}", spec.name, missing.join(", ")));
+    }
+
+    Ok(Some(ParsedFlags { values }))
+}
+
+fn valid_flag_list(spec: &CommandSpec) -> String {
+    spec.flags
+        .iter()
+        .chain(GLOBAL_FLAGS)
+        .map(|flag| flag.name)
+        .chain(std::iter::once("--help"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// One line per flag, e.g. `  --bins-dir <dir>   (required) Directory of binaries to hash.`
+fn flag_help_lines(flags: &[FlagSpec]) -> String {
+    let mut output = String::new();
+    for flag in flags {
+        let usage = match flag.value_name {
+            Some(value_name) => format!("{} <{}>", flag.name, value_name),
+            None => flag.name.to_string(),
+        };
+        let required = if flag.required { "(required) " } else { "" };
+        output.push_str(&format!("  {:<30} {}{}\n", usage, required, flag.help));
+    }
+    output
+}
+
+fn command_help(spec: &CommandSpec) -> String {
+    format!(
+        "Usage: sentry-omega {} [flags]\n\n{}\n\nFlags:\n{}\nGlobal flags:\n{}",
+        spec.name,
+        spec.summary,
+        flag_help_lines(spec.flags),
+        flag_help_lines(GLOBAL_FLAGS)
+    )
+}
+
+fn general_help() -> String {
+    let mut output = String::from("Usage: sentry-omega [--mode blue|yellow|red] <subcommand> [flags]\n\nSubcommands:\n");
+    for spec in COMMAND_SPECS {
+        output.push_str(&format!("  {:<12} {}\n", spec.name, spec.summary));
+    }
+    output.push_str("\nRun `sentry-omega <subcommand> --help` for the flags of one subcommand.\n");
+    output
+}
+
+/// Hash every file in `bins_dir` (and its subfolders with `recursive`) into a new manifest for
+/// `release_id`. Each entry gets the manifest hash, the `digests` asked for, its size, and its
+/// mode; the Merkle root and `warnings` are filled in as well. Nothing is written to disk: hand
+/// the result to `persist_manifest` for that.
+///
+/// Fails with `SentryError::BinsDirMissing` when the folder is missing, `SentryError::Verification`
+/// when two files would share a relative path (also when they differ only in case), and
+/// `SentryError::EntryUnreadable` (or `Io` for the folder listing) when a file cannot be read.
+pub fn build_manifest(
+    mode: Mode,
+    bins_dir: &Path,
+    release_id: String,
+    provenance: provenance::Provenance,
+    recursive: bool,
+    digests: &[DigestAlgorithm],
+    owners: Option<&owners::OwnersFile>,
+) -> Result<OmegaManifest, SentryError> {
+    if !bins_dir.is_dir() {
+        return Err(SentryError::BinsDirMissing(bins_dir.to_path_buf()));
+    }
+
+    let mut entries = Vec::new();
+    for (rel_path, path, metadata) in bin_files(bins_dir, recursive)? {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        // One pass per file: the same buffer feeds the manifest hash and every `--digests` hasher.
+        let hashed = hash_file(&path, digests).map_err(|source| SentryError::EntryUnreadable { entry: rel_path.clone(), path: path.clone(), source })?;
+        let rule = owners.and_then(|owners| owners.lookup(&rel_path));
+
+        entries.push(ManifestEntry {
+            name,
+            path: rel_path.clone(),
+            rel_path,
+            hash: hashed.hash,
+            size: metadata.len(),
+            digests: hashed.digests,
+            sig: None,
+            mode: Some(file_mode(&metadata)),
+            filetype: Some(hashed.filetype),
+            owner: rule.map(|rule| rule.owner.clone()),
+            required_signer: rule.and_then(|rule| rule.required_signer.clone()),
+        });
+    }
+
+    // The relative path is the entry's key, so two entries may never share it. Windows and macOS
+    // folders usually ignore case, so checking out a release with both `Squire` and `squire`
+    // there silently keeps only one of them; that counts as sharing it too.
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    for entry in &entries {
+        if let Some(first) = seen.insert(entry.rel_path.to_lowercase(), &entry.rel_path) {
+            if first == entry.rel_path {
+                return Err(SentryError::Verification(format!("{:?} was found twice under {:?}", first, bins_dir)));
+            }
+            return Err(SentryError::Verification(format!(
+                "{:?} and {:?} differ only in case, which breaks checkouts on Windows; rename one of them",
+                first, entry.rel_path
+            )));
+        }
+    }
+
+    // Shared file names are allowed (results use the relative path), but they make logs and
+    // `self_verify` lookups by name ambiguous, so the manifest says so.
+    let mut by_name: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for entry in &entries {
+        by_name.entry(&entry.name).or_default().push(&entry.rel_path);
+    }
+    let warnings: Vec<String> = by_name
+        .iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(name, paths)| format!("{} entries share the name {}: {}", paths.len(), name, paths.join(", ")))
+        .collect();
+
+    let merkle_root = merkle::merkle_root(&manifest_leaves(&entries)).map(|root| merkle::to_hex(&root));
+
+    Ok(OmegaManifest {
+        release_id,
+        mode,
+        root: Some(manifest_path(bins_dir)),
+        signature_note: "Detached signatures live alongside manifest files. Add them after signing on Sentry Blue.".to_string(),
+        entries,
+        merkle_root,
+        created_at_unix: Some(prune::now_unix()),
+        provenance: Some(provenance),
+        warnings,
+        tool_version: Some(version::TOOL_VERSION.to_string()),
+    })
+}
+
+/// Every regular file in `bins_dir` as `(relative path with /, full path, metadata)`, sorted by
+/// relative path. With `recursive`, folders below it are walked too. Symbolic links are skipped,
+/// like anything else that is not a plain file or folder, so a link can never pull a file from
+/// outside `bins_dir` into a release.
+fn bin_files(bins_dir: &Path, recursive: bool) -> Result<Vec<(String, PathBuf, fs::Metadata)>, SentryError> {
+    let mut files = Vec::new();
+    let mut folders = vec![(String::new(), bins_dir.to_path_buf())];
+    while let Some((prefix, folder)) = folders.pop() {
+        let listing = fs::read_dir(&folder).map_err(|err| SentryError::io(format!("Unable to read bin directory {:?}", folder), err))?;
+        for entry in listing {
+            let entry = entry.map_err(|err| SentryError::io("Failed to read file entry", err))?;
+            // `DirEntry::metadata` does not follow symbolic links.
+            let metadata = entry.metadata().map_err(|err| SentryError::io("Failed to read metadata", err))?;
+            let rel_path = format!("{prefix}{}", entry.file_name().to_string_lossy());
+            if metadata.is_file() {
+                files.push((rel_path, entry.path(), metadata));
+            } else if metadata.is_dir() && recursive {
+                folders.push((format!("{rel_path}/"), entry.path()));
+            }
+        }
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
+}
+
+/// Turn manifest entries into Merkle leaves, keeping the manifest's entry order.
+fn manifest_leaves(entries: &[ManifestEntry]) -> Vec<merkle::NodeHash> {
+    entries
+        .iter()
+        .map(|entry| merkle::leaf_hash(&entry.rel_path, &entry.hash, entry.size))
+        .collect()
+}
+
+/// `--release-id` value (and default) that asks `build` to name the release after its contents.
+pub const AUTO_RELEASE_ID: &str = "auto";
+
+/// `<yyyymmdd>-<12 hex>`: the manifest's UTC build date, then the start of its Merkle root, which
+/// covers every entry's relative path, hash, and size. The same files built on the same day (or with the
+/// same `--source-date-epoch`) always get the same id, and different files practically never do.
+fn auto_release_id(manifest: &OmegaManifest) -> Result<String, String> {
+    let root = manifest.merkle_root.as_deref().ok_or("No binaries were discovered to name the release after")?;
+    let date = utc_date(manifest.created_at_unix.unwrap_or_else(prune::now_unix));
+    Ok(format!("{}-{}", date, &root[..12]))
+}
+
+/// `yyyymmdd` for a Unix time, in UTC. Uses the usual days-to-civil-date conversion so no date
+/// crate is needed.
+fn utc_date(unix: u64) -> String {
+    let days = (unix / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+/// The facts two builds must share to count as the same release. Paths, times, and the build
+/// host may differ; the files themselves may not.
+fn same_release_content(a: &OmegaManifest, b: &OmegaManifest) -> bool {
+    let content = |manifest: &OmegaManifest| -> Vec<(String, String, u64, Option<u32>)> {
+        let mut entries: Vec<_> = manifest
+            .entries
+            .iter()
+            .map(|entry| (entry.rel_path.clone(), entry.hash.clone(), entry.size, entry.mode))
+            .collect();
+        entries.sort();
+        entries
+    };
+    content(a) == content(b)
+}
+
+/// Write the manifest and its signature files into `<releases_dir>/omega-<release_id>/` and
+/// return that folder. The bool is `true` when the folder already held a manifest for the same
+/// files; it is then left untouched. A folder holding a manifest for different files is an
+/// error rather than something to overwrite.
+///
+/// The files are written into a hidden `.omega-<release_id>.partial-<pid>` folder next to it,
+/// which is renamed into place only when everything is written. A build that fails or is killed
+/// halfway therefore never leaves a release folder that `status` would try to read.
+///
+/// Fails with `SentryError::Verification` for an empty manifest or a release folder that holds
+/// different binaries, `SentryError::Parse` for a release id Windows cannot use as a folder name
+/// or an existing manifest that does not load, and `SentryError::Io` when writing fails.
+pub fn persist_manifest(manifest: &OmegaManifest, releases_dir: &Path) -> Result<(PathBuf, bool), SentryError> {
+    persist_release(manifest, releases_dir, None).map(|(folder, already_present, _)| (folder, already_present))
+}
+
+/// A bundle `write_bundle` wrote: its path and its bytes.
+type WrittenBundle = (PathBuf, Vec<u8>);
+
+/// `persist_manifest`, also packing `omega-<release_id>.tar` when `bundle_mtime` is given (see
+/// `write_bundle`); its final path and bytes come back third. A new release gets its bundle
+/// inside the staging folder, so the rename publishes both or neither.
+fn persist_release(
+    manifest: &OmegaManifest,
+    releases_dir: &Path,
+    bundle_mtime: Option<u64>,
+) -> Result<(PathBuf, bool, Option<WrittenBundle>), SentryError> {
+    if manifest.entries.is_empty() {
+        return Err(SentryError::Verification("No binaries were discovered to record in the manifest".to_string()));
+    }
+
+    check_release_id(&manifest.release_id)?;
+    let folder_name = format!("omega-{}", manifest.release_id);
+    let release_folder = releases_dir.join(&folder_name);
+    let existing_path = release_folder.join("manifest.txt");
+    if existing_path.exists() {
+        // Only the entries' keys and hashes are compared, so absolute paths written by an earlier
+        // build from an absolute `--bins-dir` are fine here.
+        let existing = load_manifest_with(&existing_path, true)
+            .map_err(|err| SentryError::Parse(format!("{:?} already exists and cannot be compared: {}", existing_path, err.chain())))?;
+        if !same_release_content(&existing, manifest) {
+            return Err(SentryError::Verification(format!(
+                "Release {:?} already exists with different binaries; pick another --release-id or use --release-id auto",
+                manifest.release_id
+            )));
+        }
+        LOG.info(
+            "Release already exists with the same binaries; leaving it as it is",
+            &[("release_id", &manifest.release_id), ("folder", &release_folder.display().to_string())],
+        );
+        let bundle = match bundle_mtime {
+            Some(mtime) => Some(write_bundle(manifest, &release_folder, mtime)?),
+            None => None,
+        };
+        return Ok((release_folder, true, bundle));
+    }
+    fs::create_dir_all(releases_dir).map_err(|err| SentryError::io("Unable to create releases directory", err))?;
+    remove_partial_releases(releases_dir, &folder_name);
+    if release_folder.is_dir() {
+        // A release folder without a manifest is what builds before staging left when they died.
+        LOG.warn("Removing a release folder with no manifest, left by an interrupted build", &[("folder", &release_folder.display().to_string())]);
+        fs::remove_dir_all(&release_folder).map_err(|err| SentryError::io(format!("Unable to remove {:?}", release_folder), err))?;
+    }
+
+    let staging = releases_dir.join(format!(".{folder_name}.partial-{}", std::process::id()));
+    fs::create_dir_all(&staging).map_err(|err| SentryError::io(format!("Unable to create {:?}", staging), err))?;
+    let bundle = match write_release_files(manifest, &staging, bundle_mtime) {
+        Ok(bundle) => bundle,
+        Err(err) => {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(err);
+        }
+    };
+    if let Err(err) = fs::rename(&staging, &release_folder) {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(SentryError::io(format!("Unable to move {:?} to {:?}", staging, release_folder), err));
+    }
+    // The bundle was written in the staging folder; report where it lives now.
+    let bundle = bundle.map(|(path, bytes)| (release_folder.join(path.file_name().unwrap_or_default()), bytes));
+    Ok((release_folder, false, bundle))
+}
+
+/// Fill a fresh staging folder: the manifest, its signature placeholder, the per-file `.sig`
+/// files, and the bundle when asked for.
+fn write_release_files(manifest: &OmegaManifest, folder: &Path, bundle_mtime: Option<u64>) -> Result<Option<WrittenBundle>, SentryError> {
+    // Written atomically so Red never parses half a manifest after a crash.
+    write_atomic(&folder.join("manifest.txt"), render_manifest(manifest).as_bytes())?;
+    // Leave a friendly placeholder to remind operators to add a signed file.
+    write_atomic(&folder.join("manifest.txt.sig"), format!("{}\n", status::SIGNATURE_PLACEHOLDER).as_bytes())?;
+    write_entry_sigs(manifest, folder)?;
+
+    match bundle_mtime {
+        Some(mtime) => Ok(Some(write_bundle(manifest, folder, mtime)?)),
+        None => Ok(None),
+    }
+}
+
+/// Write each signed entry's `<rel_path>.sig` into `folder`, beside the manifest.
+fn write_entry_sigs(manifest: &OmegaManifest, folder: &Path) -> Result<(), SentryError> {
+    for entry in &manifest.entries {
+        if let Some(sig) = &entry.sig {
+            // Entries from subfolders keep their folder: `tools/squire.sig`.
+            let sig_path = folder.join(local_path(&format!("{}.sig", entry.rel_path)));
+            if let Some(parent) = sig_path.parent() {
+                fs::create_dir_all(parent).map_err(|err| SentryError::io(format!("Unable to create {:?}", parent), err))?;
+            }
+            write_atomic(&sig_path, format!("{sig}\n").as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Delete `.omega-<id>.partial-*` staging folders for this release left by builds that crashed.
+fn remove_partial_releases(releases_dir: &Path, folder_name: &str) {
+    let prefix = format!(".{folder_name}.partial-");
+    let Ok(entries) = fs::read_dir(releases_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) && fs::remove_dir_all(entry.path()).is_ok() {
+            LOG.info("Removed a partial release left by a crashed build", &[("folder", &entry.path().display().to_string())]);
+        }
+    }
+}
+
+/// Refuse a build the disk cannot hold. When the free space cannot be read, say so and go ahead:
+/// an unknown answer is no reason to stop every build.
+fn check_build_space(space: &dyn SpaceProvider, releases_dir: &Path, required: u64) -> Result<(), SentryError> {
+    let probe = disk_space::existing_ancestor(releases_dir);
+    match space.available_bytes(&probe) {
+        Ok(available) if available < required => {
+            Err(SentryError::InsufficientSpace { path: releases_dir.to_path_buf(), required, available })
+        }
+        Ok(_) => Ok(()),
+        Err(err) => {
+            LOG.warn("Free space unknown; building without the check", &[("folder", &probe.display().to_string()), ("error", &err.to_string())]);
+            Ok(())
+        }
+    }
+}
+
+/// Characters Windows refuses in file names. Release ids become folder names, so they are refused
+/// everywhere; a release built on Linux must still unpack on a Windows host.
+const RELEASE_ID_FORBIDDEN: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+fn check_release_id(release_id: &str) -> Result<(), SentryError> {
+    if let Some(bad) = release_id.chars().find(|c| RELEASE_ID_FORBIDDEN.contains(c) || c.is_control()) {
+        return Err(SentryError::Parse(format!(
+            "Release id {:?} contains {:?}, which Windows does not allow in folder names (avoid < > : \" | ? *)",
+            release_id, bad
+        )));
+    }
+    Ok(())
+}
+
+/// Folder inside a bundle that holds the binaries. Using the entries' relative paths under one fixed folder,
+/// rather than the paths recorded at build time, keeps the archive the same whichever folder the
+/// build ran from.
+const BUNDLE_BIN_DIR: &str = "bin";
+
+/// Pack the release into `<release_folder>/omega-<release_id>.tar` and return its path and bytes.
+/// The manifest and signature files come first, then every binary under `bin/` by relative path.
+fn write_bundle(manifest: &OmegaManifest, release_folder: &Path, mtime: u64) -> Result<WrittenBundle, String> {
+    let read = |path: &Path| fs::read(path).map_err(|err| format!("Failed to read {:?}: {err}", path));
+    let mut members = Vec::new();
+    let mut signature_files = vec!["manifest.txt".to_string(), "manifest.txt.sig".to_string()];
+    signature_files.extend(manifest.entries.iter().filter(|entry| entry.sig.is_some()).map(|entry| format!("{}.sig", entry.rel_path)));
+    for file in signature_files {
+        let data = read(&release_folder.join(local_path(&file)))?;
+        members.push(bundle::Member { path: file, mode: 0o644, data });
+    }
+
+    let mut binaries = Vec::new();
+    for entry in &manifest.entries {
+        let name = bundle::member_path(&local_path(&entry.rel_path))?;
+        binaries.push(bundle::Member {
+            path: format!("{BUNDLE_BIN_DIR}/{name}"),
+            mode: entry.mode.unwrap_or(0o644),
+            data: read(&built_file(manifest.root.as_deref(), entry))?,
+        });
+    }
+    binaries.sort_by(|a, b| a.path.cmp(&b.path));
+    if let Some(pair) = binaries.windows(2).find(|pair| pair[0].path == pair[1].path) {
+        return Err(format!("Two manifest entries would share the bundle path {:?}", pair[0].path));
+    }
+    members.extend(binaries);
+
+    let archive = bundle::write_tar(&members, mtime)?;
+    let path = release_folder.join(format!("omega-{}.tar", manifest.release_id));
+    write_atomic(&path, &archive)?;
+    Ok((path, archive))
+}
+
+/// `receive-release`: unpack a bundle that arrived whole into its staging folder, verify it against
+/// the manifest inside, and only then rename the folder to `<releases_dir>/omega-<release id>`.
+/// The `.tar` itself moves in too. Returns the document for the pusher, or why the release was refused.
+fn install_received_bundle(
+    received: &transfer::ReceivedBundle,
+    releases_dir: &Path,
+    check_mode: ModeCheck,
+    mode: Mode,
+    env_settings: &OmegaEnvironment,
+    now_millis: u128,
+) -> Result<String, String> {
+    let archive = fs::read(&received.path).map_err(|err| format!("Unable to read {:?}: {err}", received.path))?;
+    let members = bundle::read_tar(&archive)?;
+    let Some(staged_manifest) = members.iter().find(|member| member.path == "manifest.txt") else {
+        return Err(format!("{} holds no manifest.txt, so it is not a release bundle", received.name));
+    };
+    let staging = &received.staging_dir;
+    // A staging folder left by a crash belongs to nobody; start clean.
+    let _ = fs::remove_dir_all(staging);
+    bundle::extract(&members, staging)?;
+    let manifest = load_manifest(&staging.join("manifest.txt"))?;
+    check_release_id(&manifest.release_id)?;
+    let report = verify_bins(staging, &bundled_entries(&manifest), check_mode, false)?;
+    if report_outcome(&report) != CliOutcome::Success {
+        let failing: Vec<String> = report.iter().filter(|check| !check.matched()).map(|check| format!("{}:{}", check.rel_path, check.status())).collect();
+        return Err(format!("Release {} does not verify against its own manifest ({})", manifest.release_id, failing.join(", ")));
+    }
+
+    let folder = releases_dir.join(format!("omega-{}", manifest.release_id));
+    let existing = folder.join("manifest.txt");
+    let status = if existing.exists() {
+        // The same release pushed twice is fine; a different one under the same id is not.
+        let current = fs::read(&existing).map_err(|err| format!("Unable to read {:?}: {err}", existing))?;
+        if current != staged_manifest.data {
+            return Err(format!("Release {} already exists in {:?} with a different manifest", manifest.release_id, releases_dir));
+        }
+        "unchanged"
+    } else {
+        let tar_path = staging.join(format!("omega-{}.tar", manifest.release_id));
+        fs::rename(&received.path, &tar_path).map_err(|err| format!("Unable to move the bundle into {:?}: {err}", staging))?;
+        fs::rename(staging, &folder).map_err(|err| format!("Unable to move {:?} to {:?}: {err}", staging, folder))?;
+        "installed"
+    };
+    LOG.info("Release received", &[("release_id", &manifest.release_id), ("status", status)]);
+    let document = render_json_status("receive-release", mode, env_settings, &manifest, &report, now_millis, &[]);
+    let release = format!(
+        "{{\"id\":\"{}\",\"folder\":\"{}\",\"status\":\"{}\",\"sha256\":\"{}\"}}",
+        json_escape(&manifest.release_id),
+        json_escape(&folder.to_string_lossy()),
+        status,
+        received.sha256
+    );
+    Ok(with_json_field(&document, "release", &release))
+}
+
+/// The manifest as found in an unpacked bundle: the same entries, pointed at `bin/<rel_path>`.
+fn bundled_entries(manifest: &OmegaManifest) -> OmegaManifest {
+    let mut unpacked = manifest.clone();
+    for entry in &mut unpacked.entries {
+        entry.path = format!("{BUNDLE_BIN_DIR}/{}", entry.rel_path);
+    }
+    unpacked
+}
+
+fn render_manifest(manifest: &OmegaManifest) -> String {
+    let mut output = String::new();
+    // First, so a reader that is too old stops here rather than at a line it cannot parse.
+    if let Some(tool_version) = &manifest.tool_version {
+        output.push_str(&format!("tool_version={}\n", tool_version));
+    }
+    output.push_str(&format!("min_reader_version={}\n", version::MIN_READER_VERSION));
+    output.push_str(&format!("release_id={}\n", manifest.release_id));
+    output.push_str(&format!("mode={}\n", manifest.mode.as_str()));
+    if let Some(root) = &manifest.root {
+        output.push_str(&format!("root={}\n", root));
+    }
+    if let Some(root) = &manifest.merkle_root {
+        output.push_str(&format!("merkle_root={}\n", root));
+    }
+    if let Some(created_at_unix) = manifest.created_at_unix {
+        output.push_str(&format!("created_at_unix={}\n", created_at_unix));
+    }
+    if let Some(provenance) = &manifest.provenance {
+        output.push_str(&provenance.render());
+    }
+    for warning in &manifest.warnings {
+        output.push_str(&format!("warning={}\n", warning));
+    }
+    output.push_str("entries:\n");
+
+    for entry in &manifest.entries {
+        output.push_str(&format!("{}|{}|{}|{}", entry.name, entry.path, entry.hash, entry.size));
+        if entry.rel_path != entry.name {
+            output.push_str(&format!("|rel={}", entry.rel_path));
+        }
+        for (algorithm, hex) in &entry.digests {
+            output.push_str(&format!("|{}={}", algorithm.field(), hex));
+        }
+        if let Some(sig) = &entry.sig {
+            output.push_str(&format!("|sig={}", sig));
+        }
+        if let Some(mode) = entry.mode {
+            output.push_str(&format!("|mode={:04o}", mode));
+        }
+        if let Some(filetype) = entry.filetype {
+            output.push_str(&format!("|filetype={}", filetype.as_str()));
+        }
+        if let Some(owner) = &entry.owner {
+            output.push_str(&format!("|owner={}", owner));
+        }
+        if let Some(signer) = &entry.required_signer {
+            output.push_str(&format!("|required_signer={}", signer));
+        }
+        output.push('\n');
+    }
+
+    output.push_str(&format!("signature_note={}\n", manifest.signature_note));
+    output
+}
+
+/// `--manifest -`: read the manifest from standard input instead of a file (`verify`, `inspect`).
+pub const STDIN_MANIFEST: &str = "-";
+/// How errors name a manifest that came from standard input.
+const STDIN_MANIFEST_LABEL: &str = "<stdin>";
+
+/// Read a `manifest.txt` written by `persist_manifest`, including manifests from older Sentry
+/// versions (no `rel=`, digests, modes, Merkle root, or version lines). Fails with
+/// `SentryError::ManifestRead` when the file cannot be read, `SentryError::ManifestTooNew` when its
+/// `min_reader_version=` is newer than this Sentry, `SentryError::ManifestParse` (with the line number) when a line is
+/// damaged (an unknown `mode=`, a size that is not a number) or `release_id` is missing, and
+/// `SentryError::UnsafePath` when an entry's path is absolute or climbs out of the bins directory
+/// (see `safe_path`).
+pub fn load_manifest(path: &Path) -> Result<OmegaManifest, SentryError> {
+    load_manifest_with(path, false)
+}
+
+/// `load_manifest`, optionally accepting absolute file paths (`--trust-absolute-paths`). Paths
+/// with a `..` that leaves the bins directory, and absolute `rel=` keys, are refused either way.
+pub fn load_manifest_with(path: &Path, trust_absolute_paths: bool) -> Result<OmegaManifest, SentryError> {
+    let file = fs::File::open(path).map_err(|source| SentryError::ManifestRead { path: path.to_path_buf(), source })?;
+    load_manifest_from(file, path, trust_absolute_paths)
+}
+
+/// `load_manifest_with` for a manifest that arrives through any reader, such as a pipe. `path`
+/// only names the manifest in errors.
+pub fn load_manifest_from(mut reader: impl Read, path: &Path, trust_absolute_paths: bool) -> Result<OmegaManifest, SentryError> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content).map_err(|source| SentryError::ManifestRead { path: path.to_path_buf(), source })?;
+    // Line numbers count from 1, like an editor shows them.
+    let damaged = |line: usize, reason: String| SentryError::ManifestParse { path: path.to_path_buf(), line, reason };
+    let mut release_id = String::new();
+    let mut mode = Mode::Yellow;
+    let mut root = None;
+    let mut entries = Vec::new();
     let mut signature_note = String::new();
+    let mut merkle_root = None;
+    let mut created_at_unix = None;
+    let mut provenance = None;
+    let mut warnings = Vec::new();
+    let mut tool_version = None;
 
-    for line in content.lines() {
-        if let Some(rest) = line.strip_prefix("release_id=") {
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        if let Some(rest) = line.strip_prefix("min_reader_version=") {
+            let required = version::Version::parse(rest).ok_or_else(|| damaged(line_number, format!("invalid min_reader_version {rest:?}")))?;
+            let current = version::Version::current();
+            // Checked on the spot: the lines after it may use a format this Sentry cannot read.
+            if required > current {
+                return Err(SentryError::ManifestTooNew { path: path.to_path_buf(), required, current });
+            }
+        } else if let Some(rest) = line.strip_prefix("tool_version=") {
+            tool_version = Some(rest.to_string());
+        } else if let Some(rest) = line.strip_prefix("release_id=") {
             release_id = rest.to_string();
         } else if let Some(rest) = line.strip_prefix("mode=") {
-            mode = Mode::from_str(rest).unwrap_or(Mode::Yellow);
+            // Piped manifests can arrive damaged; guessing Yellow would check with the wrong rules.
+            mode = rest.parse().map_err(|_| damaged(line_number, format!("invalid mode {rest:?}")))?;
+        } else if let Some(rest) = line.strip_prefix("root=") {
+            root = Some(rest.to_string());
+        } else if let Some(rest) = line.strip_prefix("merkle_root=") {
+            merkle_root = Some(rest.to_string());
+        } else if let Some(rest) = line.strip_prefix("created_at_unix=") {
+            let value = rest.parse::<u64>().map_err(|_| damaged(line_number, format!("invalid created_at_unix {rest:?}")))?;
+            created_at_unix = Some(value);
         } else if let Some(rest) = line.strip_prefix("signature_note=") {
             signature_note = rest.to_string();
+        } else if let Some(rest) = line.strip_prefix("warning=") {
+            warnings.push(rest.to_string());
         } else if let Some(rest) = line.strip_prefix("entries:") {
             // Header line; nothing to parse here.
             let _ = rest;
+        } else if provenance::Provenance::apply_line(&mut provenance, line) {
+            // `provenance.<field>=` lines are stored by `apply_line` itself.
         } else if line.contains('|') {
+            // `name|path|hash|size`, then optional `key=value` fields: `sig=<hex>` on entries built
+            // with `--per-file-sigs`, `mode=<octal>` on entries built since modes were recorded,
+            // `rel=<path>` on entries from a subfolder, `hash_<algorithm>=<hex>` from `--digests`,
+            // `filetype=<kind>` on entries built since file types were recorded, and `owner=` and
+            // `required_signer=<fingerprint>` on entries an owners file matched.
             let parts: Vec<&str> = line.split('|').collect();
-            if parts.len() == 4 {
+            let (mut sig, mut mode, mut rel_path, mut filetype) = (None, None, None, None);
+            let (mut owner, mut required_signer) = (None, None);
+            let mut digests = Vec::new();
+            for field in parts.iter().skip(4) {
+                let digest_field = field.split_once('=').and_then(|(key, value)| Some((DigestAlgorithm::from_field(key)?, value)));
+                if let Some((algorithm, value)) = digest_field {
+                    digests.push((algorithm, value.to_ascii_lowercase()));
+                } else if let Some(value) = field.strip_prefix("rel=") {
+                    rel_path = Some(value.to_string());
+                } else if let Some(value) = field.strip_prefix("sig=") {
+                    sig = Some(value.to_string());
+                } else if let Some(value) = field.strip_prefix("mode=") {
+                    let parsed = u32::from_str_radix(value, 8)
+                        .map_err(|_| damaged(line_number, format!("entry has an invalid mode {value:?}: {line}")))?;
+                    mode = Some(parsed);
+                } else if let Some(value) = field.strip_prefix("filetype=") {
+                    let parsed = FileType::from_name(value)
+                        .ok_or_else(|| damaged(line_number, format!("entry has an unknown filetype {value:?}: {line}")))?;
+                    filetype = Some(parsed);
+                } else if let Some(value) = field.strip_prefix("owner=") {
+                    owner = Some(value.to_string());
+                } else if let Some(value) = field.strip_prefix("required_signer=") {
+                    if !owners::is_fingerprint(value) {
+                        return Err(damaged(line_number, format!("entry has an invalid required_signer {value:?}: {line}")));
+                    }
+                    required_signer = Some(value.to_ascii_lowercase());
+                } else {
+                    return Err(damaged(line_number, format!("entry has an unknown field {field:?}: {line}")));
+                }
+            }
+            if parts.len() >= 4 {
                 let name = parts[0].to_string();
-                let path = parts[1].to_string();
+                let unsafe_path = |path: &str, problem| SentryError::UnsafePath { entry: name.clone(), path: path.to_string(), problem };
+                // Older Windows builds wrote `\\`; keep the stored form uniform. Relative paths
+                // are stored in their plain form, so `./bin/x` and `bin/x` read the same file.
+                let path = match safe_path::normalize(parts[1]) {
+                    Ok(path) => path,
+                    Err(safe_path::PathProblem::Absolute) if trust_absolute_paths => parts[1].replace('\\', "/"),
+                    Err(problem) => return Err(unsafe_path(parts[1], problem)),
+                };
                 let hash = parts[2].to_string();
-                let size = parts[3].parse::<u64>().unwrap_or(0);
-                entries.push(ManifestEntry { name, path, hash, size });
+                let size = parts[3]
+                    .parse::<u64>()
+                    .map_err(|_| damaged(line_number, format!("entry has an invalid size {:?}: {line}", parts[3])))?;
+                // Older manifests have no `rel=` and were keyed by name. The key also names the
+                // `.sig` file beside the manifest, so it must stay relative even when trusted.
+                let rel_path = match safe_path::normalize(rel_path.as_deref().unwrap_or(&name)) {
+                    Ok(rel_path) => rel_path,
+                    Err(problem) => {
+                        let shown = rel_path.map(|rel| format!("rel={rel}")).unwrap_or_else(|| name.clone());
+                        return Err(unsafe_path(&shown, problem));
+                    }
+                };
+                digests.sort();
+                entries.push(ManifestEntry { name, rel_path, path, hash, size, digests, sig, mode, filetype, owner, required_signer });
             }
         }
     }
 
     if release_id.is_empty() {
-        return Err("Manifest missing release_id".to_string());
+        return Err(damaged(0, "missing release_id".to_string()));
     }
 
-    Ok(OmegaManifest { release_id, mode, signature_note, entries })
+    Ok(OmegaManifest { release_id, mode, root, signature_note, entries, merkle_root, created_at_unix, provenance, warnings, tool_version })
 }
 
-fn verify_bins(bins_dir: &Path, manifest: &OmegaManifest) -> Result<Vec<String>, String> {
-    let mut results = Vec::new();
+/// The manifest named by `--manifest`: standard input for `-`, the file otherwise.
+fn read_manifest_arg(path: &Path, trust_absolute_paths: bool) -> Result<OmegaManifest, SentryError> {
+    read_manifest_arg_from(path, trust_absolute_paths, io::stdin().lock())
+}
 
-    for entry in &manifest.entries {
-        let full_path = if Path::new(&entry.path).is_absolute() {
-            PathBuf::from(&entry.path)
-        } else {
-            bins_dir.join(&entry.path)
-        };
+/// `read_manifest_arg` with `stdin` standing in for standard input.
+fn read_manifest_arg_from(path: &Path, trust_absolute_paths: bool, stdin: impl Read) -> Result<OmegaManifest, SentryError> {
+    if path == Path::new(STDIN_MANIFEST) {
+        load_manifest_from(stdin, Path::new(STDIN_MANIFEST_LABEL), trust_absolute_paths)
+    } else {
+        load_manifest_with(path, trust_absolute_paths)
+    }
+}
 
-        let data = fs::read(&full_path)
-            .map_err(|err| format!("Failed to read {:?}: {err}", full_path))?;
-        let current_hash = hash_bytes(&data);
-        let status = if current_hash == entry.hash {
-            "match"
-        } else {
-            "mismatch"
-        };
-        results.push(format!("{}:{}", entry.name, status));
+/// The manifest a daemon verifies against, reloaded when the file changes.
+///
+/// Each pass stats the file; a new modification time or size means a new release was shipped.
+/// A good new manifest replaces the old one and yields a `manifest-reloaded` event. One that fails
+/// to parse (for example because it is being copied in) yields `manifest-reload-failed`; the daemon
+/// keeps verifying against the last good manifest and tries again on the next pass.
+struct ManifestTracker {
+    path: PathBuf,
+    manifest: OmegaManifest,
+    /// `(modified, size)` of the file `manifest` was read from.
+    stamp: Option<(std::time::SystemTime, u64)>,
+    /// `--trust-absolute-paths`, applied to every reload too.
+    trust_absolute_paths: bool,
+    /// Why the last reload failed, or `None` once a reload works (or none was needed).
+    reload_error: Option<String>,
+}
+
+impl ManifestTracker {
+    fn open(path: &Path, trust_absolute_paths: bool) -> Result<Self, String> {
+        let stamp = file_stamp(path);
+        let manifest = load_manifest_with(path, trust_absolute_paths)?;
+        Ok(Self { path: path.to_path_buf(), manifest, stamp, trust_absolute_paths, reload_error: None })
     }
 
-    Ok(results)
+    /// Reload when the file changed. Returns the JSON event to print, if anything happened.
+    fn refresh(&mut self, mode: Mode) -> Option<String> {
+        let stamp = file_stamp(&self.path);
+        if stamp == self.stamp {
+            return None;
+        }
+        let old_release = json_escape(&self.manifest.release_id);
+        match load_manifest_with(&self.path, self.trust_absolute_paths) {
+            Ok(manifest) => {
+                let event = format!(
+                    "{{\"action\":\"manifest-reloaded\",\"mode\":\"{}\",\"old_release_id\":\"{}\",\"new_release_id\":\"{}\"}}",
+                    mode.as_str(),
+                    old_release,
+                    json_escape(&manifest.release_id)
+                );
+                LOG.info("Manifest reloaded", &[("old_release_id", &self.manifest.release_id), ("new_release_id", &manifest.release_id)]);
+                self.manifest = manifest;
+                self.stamp = stamp;
+                self.reload_error = None;
+                Some(event)
+            }
+            Err(err) => {
+                // `stamp` stays as it was, so the next pass tries again.
+                let err = err.chain();
+                LOG.warn("Manifest reload failed; keeping the last good one", &[("release_id", &self.manifest.release_id), ("error", &err)]);
+                self.reload_error = Some(err.clone());
+                Some(format!(
+                    "{{\"action\":\"manifest-reload-failed\",\"mode\":\"{}\",\"release_id\":\"{}\",\"error\":\"{}\"}}",
+                    mode.as_str(),
+                    old_release,
+                    json_escape(&err)
+                ))
+            }
+        }
+    }
 }
 
-fn print_json_status(action: &str, mode: Mode, env_settings: &OmegaEnvironment, manifest: &OmegaManifest, results: &[String]) {
-    // Build a compact JSON payload by hand to avoid third-party crates.
-    let mut message = String::new();
-    message.push('{');
-    message.push_str(&format!("\"action\":\"{}\",", action));
-    message.push_str(&format!("\"mode\":\"{}\",", mode.as_str()));
-    message.push_str(&format!("\"release_id\":\"{}\",", manifest.release_id));
-    message.push_str(&format!("\"hosts\":{{\"yellow\":\"{}\",\"red\":\"{}\",\"blue\":\"{}\"}},", env_settings.yellow_host, env_settings.red_host, env_settings.blue_host));
-    message.push_str("\"entries\":[");
+/// Modification time and size, or `None` when the file cannot be read right now.
+fn file_stamp(path: &Path) -> Option<(std::time::SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
 
-    for (index, entry) in manifest.entries.iter().enumerate() {
-        if index > 0 {
-            message.push(',');
+/// An inclusion proof for one manifest entry, as written by `prove` and read by `check-proof`.
+#[derive(Clone, Debug)]
+pub struct EntryProof {
+    pub name: String,
+    pub leaf_index: usize,
+    pub leaf_count: usize,
+    pub root: merkle::NodeHash,
+    pub steps: Vec<merkle::ProofStep>,
+}
+
+/// Find an entry by relative path, or by file name when exactly one entry has that name.
+fn entry_index(manifest: &OmegaManifest, wanted: &str) -> Result<usize, String> {
+    if let Some(index) = manifest.entries.iter().position(|entry| entry.rel_path == wanted) {
+        return Ok(index);
+    }
+    let named: Vec<usize> = (0..manifest.entries.len()).filter(|index| manifest.entries[*index].name == wanted).collect();
+    match named[..] {
+        [index] => Ok(index),
+        [] => Err(format!("No manifest entry named {wanted}")),
+        _ => Err(format!("{} entries are named {wanted}; pass the relative path instead", named.len())),
+    }
+}
+
+/// Build the proof text for the entry called `name`.
+///
+/// The format mirrors the manifest: one `key=value` per line, with one `sibling=<side>:<hex>`
+/// line per tree level, listed from the leaf upward.
+fn render_proof(manifest: &OmegaManifest, name: &str) -> Result<String, String> {
+    let recorded_root = manifest
+        .merkle_root
+        .as_deref()
+        .ok_or_else(|| "Manifest has no merkle_root; rebuild it with this version of Sentry".to_string())?;
+
+    let leaves = manifest_leaves(&manifest.entries);
+    let computed_root = merkle::merkle_root(&leaves).map(|root| merkle::to_hex(&root));
+    if computed_root.as_deref() != Some(recorded_root) {
+        return Err("Manifest entries do not match its recorded merkle_root".to_string());
+    }
+
+    let index = entry_index(manifest, name)?;
+    // The leaf was built from the relative path, so the proof names the entry by it.
+    let name = &manifest.entries[index].rel_path;
+    let steps = merkle::build_proof(&leaves, index).ok_or_else(|| "Unable to build proof".to_string())?;
+
+    let mut output = String::new();
+    output.push_str(&format!("name={}\n", name));
+    output.push_str(&format!("leaf_index={}\n", index));
+    output.push_str(&format!("leaf_count={}\n", leaves.len()));
+    output.push_str(&format!("merkle_root={}\n", recorded_root));
+    for step in &steps {
+        output.push_str(&format!("sibling={}:{}\n", step.side.as_str(), merkle::to_hex(&step.hash)));
+    }
+    Ok(output)
+}
+
+/// Parse a proof file written by `prove`.
+fn load_proof(path: &Path) -> Result<EntryProof, String> {
+    let content = fs::read_to_string(path).map_err(|err| format!("Unable to read proof: {err}"))?;
+    let mut name = None;
+    let mut leaf_index = 0usize;
+    let mut leaf_count = 0usize;
+    let mut root = None;
+    let mut steps = Vec::new();
+
+    for line in content.lines() {
+        if let Some(rest) = line.strip_prefix("name=") {
+            name = Some(rest.to_string());
+        } else if let Some(rest) = line.strip_prefix("leaf_index=") {
+            leaf_index = rest.parse().map_err(|_| format!("Invalid leaf_index {rest}"))?;
+        } else if let Some(rest) = line.strip_prefix("leaf_count=") {
+            leaf_count = rest.parse().map_err(|_| format!("Invalid leaf_count {rest}"))?;
+        } else if let Some(rest) = line.strip_prefix("merkle_root=") {
+            root = Some(merkle::from_hex(rest).ok_or_else(|| format!("Invalid merkle_root {rest}"))?);
+        } else if let Some(rest) = line.strip_prefix("sibling=") {
+            let (side, hash) = rest.split_once(':').ok_or_else(|| format!("Invalid sibling line {line}"))?;
+            let side = match side {
+                "left" => merkle::Side::Left,
+                "right" => merkle::Side::Right,
+                _ => return Err(format!("Invalid sibling side {side}")),
+            };
+            let hash = merkle::from_hex(hash).ok_or_else(|| format!("Invalid sibling hash {hash}"))?;
+            steps.push(merkle::ProofStep { side, hash });
         }
-        message.push_str(&format!("{{\"name\":\"{}\",\"path\":\"{}\",\"hash\":\"{}\",\"size\":{}}}",
-            json_escape(&entry.name), json_escape(&entry.path), entry.hash, entry.size));
     }
 
-    message.push(']');
+    Ok(EntryProof {
+        name: name.ok_or_else(|| "Proof missing name".to_string())?,
+        leaf_index,
+        leaf_count,
+        root: root.ok_or_else(|| "Proof missing merkle_root".to_string())?,
+        steps,
+    })
+}
 
-    if !results.is_empty() {
-        message.push_str(",\"results\":[");
-        for (index, result) in results.iter().enumerate() {
-            if index > 0 {
-                message.push(',');
-            }
-            message.push_str(&format!("\"{}\"", json_escape(result)));
+/// Hash only `file_path`, rebuild its leaf, and fold the proof up to the recorded root.
+fn check_proof(proof: &EntryProof, file_path: &Path) -> Result<bool, String> {
+    let size = fs::metadata(file_path).map_err(|err| format!("Failed to read {:?}: {err}", file_path))?.len();
+    let hashed = hash_file(file_path, &[]).map_err(|err| format!("Failed to read {:?}: {err}", file_path))?;
+    let leaf = merkle::leaf_hash(&proof.name, &hashed.hash, size);
+    Ok(merkle::verify_proof(&leaf, &proof.steps, &proof.root))
+}
+
+/// Outcome of the per-file signature check for one entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SigCheck {
+    /// `--per-file-sigs` was not given.
+    NotChecked,
+    Valid,
+    /// The recomputed HMAC differs from `<name>.sig` or the manifest's `sig=`.
+    Mismatch,
+    /// Neither a `.sig` file nor a `sig=` field exists. Reported, but not a failure.
+    Missing,
+    /// The entry has a `required_signer`, but its signature was made with another loaded key, or
+    /// it has none at all.
+    SignerMismatch,
+}
+
+impl SigCheck {
+    /// A wrong signature or a wrong signer fails the entry; a missing one does not.
+    pub fn failed(self) -> bool {
+        matches!(self, SigCheck::Mismatch | SigCheck::SignerMismatch)
+    }
+}
+
+/// Whether a waiver (`--waivers`, see `waiver.rs`) covered an entry's hash mismatch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaiverCheck {
+    /// No waiver matched, or there was no mismatch to cover.
+    None,
+    /// An unexpired waiver accepts the observed hash.
+    Applied,
+    /// A waiver accepts the observed hash but has expired, so the mismatch counts again.
+    Expired,
+}
+
+/// What `verify_bins` found for one manifest entry.
+#[derive(Clone, Debug)]
+pub struct BinCheck {
+    /// File name, for display.
+    pub name: String,
+    /// The entry's key (see `ManifestEntry::rel_path`); `results` are listed by it.
+    pub rel_path: String,
+    /// Hash recorded in the manifest.
+    pub expected_hash: String,
+    /// Hash of the file on disk right now.
+    pub observed_hash: String,
+    /// The entry's `--digests` values as recorded, and the same algorithms recomputed now.
+    pub expected_digests: Vec<(DigestAlgorithm, String)>,
+    pub observed_digests: Vec<(DigestAlgorithm, String)>,
+    pub signature: SigCheck,
+    /// False when `--check-mode` found the file's mode differs from the manifest's. Entries
+    /// without a recorded mode, and `--check-mode off`, always pass.
+    pub mode_matched: bool,
+    /// The kind of file the manifest recorded, and the kind found now. Both are `None` for
+    /// entries without a `filetype=` field, which skip the type check.
+    pub expected_filetype: Option<FileType>,
+    pub observed_filetype: Option<FileType>,
+    pub waiver: WaiverCheck,
+    /// The observed hashes came from `--cache-file` because the file's size and modification
+    /// time had not changed (see `verify_cache`).
+    pub from_cache: bool,
+}
+
+impl BinCheck {
+    /// A changed hash, a bad signature, a changed mode, or a changed file type fails the run; a
+    /// missing signature does not. A waived hash counts as matched, but its mode must still be right.
+    pub fn matched(&self) -> bool {
+        if self.waiver == WaiverCheck::Applied {
+            return self.mode_matched;
         }
-        message.push(']');
+        self.hash_matched() && !self.signature.failed() && self.mode_matched && !self.type_changed()
     }
 
-    message.push('}');
-    println!("{}", message);
+    /// The file is a different kind of file than the manifest recorded, e.g. a script where an
+    /// `elf` program used to be. Its hash has changed too, so this is extra detail for triage.
+    pub fn type_changed(&self) -> bool {
+        matches!((self.expected_filetype, self.observed_filetype), (Some(expected), Some(observed)) if expected != observed)
+    }
+
+    /// The manifest hash and every recorded digest agree with the file.
+    pub fn hash_matched(&self) -> bool {
+        self.expected_hash == self.observed_hash && self.mismatched_digests().is_empty()
+    }
+
+    /// Algorithms whose recorded digest differs from the file's, e.g. `[Sha512]`. A changed file
+    /// usually lists all of them; only one listed points at an edited manifest line instead.
+    pub fn mismatched_digests(&self) -> Vec<DigestAlgorithm> {
+        self.expected_digests
+            .iter()
+            .filter(|(algorithm, expected)| {
+                let observed = self.observed_digests.iter().find(|(other, _)| other == algorithm);
+                !observed.is_some_and(|(_, observed)| observed.eq_ignore_ascii_case(expected))
+            })
+            .map(|(algorithm, _)| *algorithm)
+            .collect()
+    }
+
+    /// The word used in the `results` list. Without signature checks it stays `match` or
+    /// `mismatch`, as before. With them, `hash-mismatch` (the file changed) is kept apart from
+    /// `sig-mismatch` (the file is as recorded but its signature is wrong, for example a forged
+    /// or damaged `.sig`), `signer-mismatch` (signed, but not by its `required_signer`), and
+    /// `sig-missing`. A file whose contents are right but whose mode is not
+    /// (for example it lost its executable bit) is `mode-mismatch`. A file that is now another
+    /// kind of file (see `type_changed`) is `type-changed` rather than a plain hash mismatch. A
+    /// mismatch covered by a waiver is `waived`, or `waiver-expired` once the waiver has run out.
+    pub fn status(&self) -> &'static str {
+        match self.waiver {
+            WaiverCheck::Applied if !self.mode_matched => return "mode-mismatch",
+            WaiverCheck::Applied => return "waived",
+            WaiverCheck::Expired => return "waiver-expired",
+            WaiverCheck::None => {}
+        }
+        if self.type_changed() {
+            return "type-changed";
+        }
+        let hash_matched = self.hash_matched();
+        if hash_matched && !self.mode_matched && !self.signature.failed() {
+            return "mode-mismatch";
+        }
+        match (self.signature, hash_matched) {
+            (SigCheck::NotChecked, true) => "match",
+            (SigCheck::NotChecked, false) => "mismatch",
+            (_, false) => "hash-mismatch",
+            (SigCheck::Valid, true) => "match",
+            (SigCheck::Mismatch, true) => "sig-mismatch",
+            (SigCheck::Missing, true) => "sig-missing",
+            (SigCheck::SignerMismatch, true) => "signer-mismatch",
+        }
+    }
 }
 
-fn json_escape(value: &str) -> String {
-    value.replace('\\', "\\\\").replace('"', "\\\"")
+/// The folder to verify: `bins_dir` when the caller gave one, otherwise the `root` the manifest
+/// was built from, with a warning, since that path is only right on the host that built it.
+/// Manifests without a root need `bins_dir`.
+pub fn resolve_bins_dir(manifest: &OmegaManifest, bins_dir: Option<&Path>) -> Result<PathBuf, SentryError> {
+    if let Some(bins_dir) = bins_dir {
+        return Ok(bins_dir.to_path_buf());
+    }
+    let root = manifest.root.as_deref().ok_or_else(|| {
+        SentryError::Message(format!("Manifest for release {} records no root; pass --bins-dir", manifest.release_id))
+    })?;
+    LOG.warn("No --bins-dir given; verifying the folder recorded as the manifest's root", &[("root", root)]);
+    Ok(local_path(root))
 }
 
-fn hash_bytes(data: &[u8]) -> String {
-    // DefaultHasher is not cryptographic, but it is deterministic and available without extra
-    // crates. Replace this with a SHA-256 implementation from a vendored crate when you harden
-    // the manifest pipeline.
-    let mut hasher = DefaultHasher::new();
-    data.hash(&mut hasher);
-    format!("{:016x}", hasher.finish())
+/// Check every manifest entry against its file under `bins_dir` (see `entry_file` for where each
+/// one is looked for) and return one `BinCheck` per entry, in manifest order. Signatures and
+/// waivers are left for the caller (`SigCheck::NotChecked`, `WaiverCheck::None`). A missing or
+/// unreadable file fails the whole check with `SentryError::EntryUnreadable` (a missing bins folder
+/// with `SentryError::BinsDirMissing`), because a release with a file gone is not something to
+/// report entry by entry. `Verifier` wraps this for other crates.
+pub fn verify_bins(
+    bins_dir: &Path,
+    manifest: &OmegaManifest,
+    mode_check: ModeCheck,
+    allow_exe_suffix: bool,
+) -> Result<Vec<BinCheck>, SentryError> {
+    if !bins_dir.is_dir() {
+        return Err(SentryError::BinsDirMissing(bins_dir.to_path_buf()));
+    }
+    manifest
+        .entries
+        .iter()
+        .map(|entry| check_entry(entry, &entry_file(bins_dir, entry, allow_exe_suffix), mode_check))
+        .collect()
+}
+
+/// `verify_bins` with a `VerifyCache`: a file whose size and modification time match what the
+/// cache remembers is not read again, and its `BinCheck` has `from_cache` set. With
+/// `full_rehash` every file is read, and the cache is refreshed from what was found.
+pub fn verify_bins_cached(
+    bins_dir: &Path,
+    manifest: &OmegaManifest,
+    mode_check: ModeCheck,
+    allow_exe_suffix: bool,
+    cache: &mut VerifyCache,
+    full_rehash: bool,
+) -> Result<Vec<BinCheck>, SentryError> {
+    let mut reports = verify_slots_cached(&[bins_dir], manifest, mode_check, allow_exe_suffix, cache, full_rehash)?;
+    Ok(reports.remove(0))
+}
+
+/// `verify_bins_cached` for several folders in one cache pass, one report per folder in the
+/// order given. One pass matters: the cache forgets files a pass did not look at, so checking
+/// the slots one pass at a time would drop every slot's hashes but the last.
+pub fn verify_slots_cached(
+    bins_dirs: &[&Path],
+    manifest: &OmegaManifest,
+    mode_check: ModeCheck,
+    allow_exe_suffix: bool,
+    cache: &mut VerifyCache,
+    full_rehash: bool,
+) -> Result<Vec<Vec<BinCheck>>, SentryError> {
+    if let Some(missing) = bins_dirs.iter().find(|bins_dir| !bins_dir.is_dir()) {
+        return Err(SentryError::BinsDirMissing(missing.to_path_buf()));
+    }
+    cache.begin_pass(full_rehash);
+    let reports = bins_dirs
+        .iter()
+        .map(|bins_dir| {
+            manifest
+                .entries
+                .iter()
+                .map(|entry| check_entry_cached(entry, &entry_file(bins_dir, entry, allow_exe_suffix), mode_check, cache))
+                .collect()
+        })
+        .collect();
+    cache.finish_pass();
+    reports
+}
+
+/// Compare one file with one manifest entry: manifest hash, recorded digests, and mode.
+fn check_entry(entry: &ManifestEntry, full_path: &Path, mode_check: ModeCheck) -> Result<BinCheck, SentryError> {
+    // Only the algorithms this entry recorded, all fed in the same pass over the file.
+    let algorithms: Vec<DigestAlgorithm> = entry.digests.iter().map(|(algorithm, _)| *algorithm).collect();
+    let hashed = hash_file(full_path, &algorithms).map_err(|source| SentryError::EntryUnreadable { entry: entry.rel_path.clone(), path: full_path.to_path_buf(), source })?;
+    let observed_filetype = entry.filetype.map(|_| hashed.filetype);
+    entry_result(entry, full_path, mode_check, hashed.hash, hashed.digests, observed_filetype, false)
+}
+
+/// `check_entry`, answered from `cache` when the file's stamp is unchanged.
+fn check_entry_cached(entry: &ManifestEntry, full_path: &Path, mode_check: ModeCheck, cache: &mut VerifyCache) -> Result<BinCheck, SentryError> {
+    let stamp_of = |path: &Path| fs::metadata(path).map(|metadata| verify_cache::FileStamp::of(&metadata));
+    let before = stamp_of(full_path).map_err(|source| SentryError::EntryUnreadable { entry: entry.rel_path.clone(), path: full_path.to_path_buf(), source })?;
+    let key = full_path.to_string_lossy();
+    let algorithms: Vec<DigestAlgorithm> = entry.digests.iter().map(|(algorithm, _)| *algorithm).collect();
+    if let Some(cached) = cache.lookup(&key, before, &algorithms) {
+        let digests = cached.digests.into_iter().filter(|(algorithm, _)| algorithms.contains(algorithm)).collect();
+        // The cache keeps hashes only; the first bytes are cheap to read again.
+        let observed_filetype = match entry.filetype {
+            Some(_) => Some(filetype::sniff_file(full_path).map_err(|source| SentryError::EntryUnreadable { entry: entry.rel_path.clone(), path: full_path.to_path_buf(), source })?),
+            None => None,
+        };
+        return entry_result(entry, full_path, mode_check, cached.hash, digests, observed_filetype, true);
+    }
+    let hashed = hash_file(full_path, &algorithms).map_err(|source| SentryError::EntryUnreadable { entry: entry.rel_path.clone(), path: full_path.to_path_buf(), source })?;
+    let after = stamp_of(full_path).ok();
+    cache.store(&key, before, after, &hashed.hash, &hashed.digests, std::time::SystemTime::now());
+    let observed_filetype = entry.filetype.map(|_| hashed.filetype);
+    entry_result(entry, full_path, mode_check, hashed.hash, hashed.digests, observed_filetype, false)
+}
+
+/// The `BinCheck` for hashes and file type already computed (or remembered); the mode is looked up here.
+fn entry_result(
+    entry: &ManifestEntry,
+    full_path: &Path,
+    mode_check: ModeCheck,
+    observed_hash: String,
+    observed_digests: Vec<(DigestAlgorithm, String)>,
+    observed_filetype: Option<FileType>,
+    from_cache: bool,
+) -> Result<BinCheck, SentryError> {
+    let mode_matched = match entry.mode {
+        Some(expected) if mode_check != ModeCheck::Off => {
+            let metadata = fs::metadata(full_path)
+                .map_err(|source| SentryError::EntryUnreadable { entry: entry.rel_path.clone(), path: full_path.to_path_buf(), source })?;
+            mode_check.accepts(expected, file_mode(&metadata))
+        }
+        _ => true,
+    };
+    Ok(BinCheck {
+        name: entry.name.clone(),
+        rel_path: entry.rel_path.clone(),
+        expected_hash: entry.hash.clone(),
+        observed_hash,
+        expected_digests: entry.digests.clone(),
+        observed_digests,
+        signature: SigCheck::NotChecked,
+        mode_matched,
+        expected_filetype: entry.filetype,
+        observed_filetype,
+        waiver: WaiverCheck::None,
+        from_cache,
+    })
+}
+
+/// Where verification finds an entry's file: its path under `bins_dir`. Older manifests recorded
+/// the path `build` was given instead of one under the root, so for them the entry's `rel_path`
+/// under `bins_dir` is also tried: first for an absolute path (Blue's folders, which this host
+/// may not have), after the recorded one for a relative path (`build/bin/squire`).
+/// With `allow_exe_suffix`, a missing `squire` is looked for as `squire.exe` and a missing
+/// `squire.exe` as `squire`, so a manifest built on one system can check binaries from another.
+fn entry_file(bins_dir: &Path, entry: &ManifestEntry, allow_exe_suffix: bool) -> PathBuf {
+    let recorded = local_path(&entry.path);
+    let relative = bins_dir.join(local_path(&entry.rel_path));
+    let path = match recorded.is_absolute() {
+        true if relative.exists() => relative,
+        true => recorded,
+        false => {
+            let joined = bins_dir.join(recorded);
+            if !joined.exists() && relative.exists() {
+                relative
+            } else {
+                joined
+            }
+        }
+    };
+    if !allow_exe_suffix || path.exists() {
+        return path;
+    }
+    let text = path.to_string_lossy();
+    let other = match text.strip_suffix(".exe") {
+        Some(stem) => PathBuf::from(stem),
+        None => PathBuf::from(format!("{text}.exe")),
+    };
+    if other.exists() {
+        other
+    } else {
+        path
+    }
+}
+
+/// Where `build` read an entry's file: its path under the manifest's `root`. Manifests without
+/// a root recorded the whole path as given to `build`.
+fn built_file(root: Option<&str>, entry: &ManifestEntry) -> PathBuf {
+    match root {
+        Some(root) => local_path(root).join(local_path(&entry.path)),
+        None => local_path(&entry.path),
+    }
+}
+
+/// The `path` a report shows for an entry: never absolute, so a published document does not
+/// describe the build host's folders. Older manifests with an absolute path show `rel_path`.
+fn report_path(entry: &ManifestEntry) -> &str {
+    match safe_path::is_absolute(&entry.path) {
+        true => &entry.rel_path,
+        false => &entry.path,
+    }
+}
+
+/// A path as manifests store it: `/` between folders on every system, so a manifest built on
+/// Windows still reads on Linux and the other way round.
+fn manifest_path(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    if std::path::MAIN_SEPARATOR == '\\' {
+        text.replace('\\', "/")
+    } else {
+        text.into_owned()
+    }
+}
+
+/// A manifest path turned back into one for this system. Both `/` and `\\` count as separators, so
+/// manifests written before paths were normalized on Windows still work.
+fn local_path(recorded: &str) -> PathBuf {
+    PathBuf::from(recorded.replace(['/', '\\'], std::path::MAIN_SEPARATOR_STR))
+}
+
+/// The signing key: decrypted from `envelope` when given, otherwise read from
+/// `SENTRY_SIGNING_KEY`. Either way it is 32 bytes written as 64 hex characters.
+fn load_signing_key(envelope: Option<&Path>, env: &dyn EnvSource) -> Result<SecretBytes, String> {
+    let hex = match envelope {
+        Some(path) => envelope_key_text(path)?,
+        None => SecretBytes::new(
+            env.var(SIGNING_KEY_ENV)
+                .ok_or_else(|| format!("--per-file-sigs needs {SIGNING_KEY_ENV} (64 hex characters) or --sign-key-envelope"))?
+                .into_bytes(),
+        ),
+    };
+    let text = std::str::from_utf8(hex.expose()).map_err(|_| "Signing key is not text".to_string())?;
+    parse_signing_key(text)
+}
+
+/// The signing key from `load_signing_key` first, then every key in `SENTRY_EXTRA_SIGNING_KEYS`.
+/// Entries without a `required_signer` are signed and checked with the first one only.
+fn load_signing_keys(envelope: Option<&Path>, env: &dyn EnvSource) -> Result<Vec<SecretBytes>, String> {
+    let mut keys = vec![load_signing_key(envelope, env)?];
+    if let Some(extra) = env.var(EXTRA_SIGNING_KEYS_ENV).map(|text| SecretBytes::new(text.into_bytes())) {
+        let text = std::str::from_utf8(extra.expose()).map_err(|_| format!("{EXTRA_SIGNING_KEYS_ENV} is not text"))?;
+        for hex in text.split(',').filter(|hex| !hex.trim().is_empty()) {
+            keys.push(parse_signing_key(hex).map_err(|err| format!("{EXTRA_SIGNING_KEYS_ENV}: {err}"))?);
+        }
+    }
+    for key in &keys {
+        LOG.info("Signing key loaded", &[("fingerprint", &owners::key_fingerprint(key.expose()))]);
+    }
+    Ok(keys)
+}
+
+fn parse_signing_key(text: &str) -> Result<SecretBytes, String> {
+    match sha256::from_hex(text.trim()) {
+        Some(key) if key.len() == 32 => Ok(SecretBytes::new(key)),
+        _ => Err("Signing key must be 64 hex characters (32 bytes)".to_string()),
+    }
+}
+
+/// The key in `SENTRY_VERIFY_TOKEN_KEY`, shared by `issue-token` and `verify --token`.
+fn load_token_key(env: &dyn EnvSource) -> Result<SecretBytes, String> {
+    let hex = SecretBytes::new(
+        env.var(verify_token::TOKEN_KEY_ENV)
+            .ok_or_else(|| format!("Verify request tokens need {} (64 hex characters)", verify_token::TOKEN_KEY_ENV))?
+            .into_bytes(),
+    );
+    let text = std::str::from_utf8(hex.expose()).map_err(|_| format!("{} is not text", verify_token::TOKEN_KEY_ENV))?;
+    parse_signing_key(text).map_err(|err| format!("{}: {err}", verify_token::TOKEN_KEY_ENV))
+}
+
+/// Validate `--token` for the manifest's release. The outer error is a missing or bad key; the
+/// inner one is why the token itself was rejected.
+fn check_verify_token(
+    token: &str,
+    release_id: &str,
+    env: &dyn EnvSource,
+    now_unix: u64,
+    max_skew: Duration,
+) -> Result<Result<verify_token::VerifyRequestToken, String>, String> {
+    let key = load_token_key(env)?;
+    let checked = verify_token::VerifyRequestToken::validate_with_skew(key.expose(), token, now_unix, max_skew).and_then(|accepted| {
+        match accepted.release_id == release_id {
+            true => Ok(accepted),
+            false => Err(format!("token is for release {}, the manifest is {release_id}", accepted.release_id)),
+        }
+    });
+    match &checked {
+        Ok(accepted) => LOG.info(
+            "Verify request token accepted",
+            &[("release_id", &accepted.release_id), ("expiry_unix", &accepted.expiry_unix.to_string())],
+        ),
+        Err(reason) => LOG.warn("Verify request token rejected", &[("reason", reason)]),
+    }
+    Ok(checked)
+}
+
+/// The `token` field of a verify document.
+fn verify_token_json(check: &Result<verify_token::VerifyRequestToken, String>) -> String {
+    match check {
+        Ok(accepted) => format!(
+            "{{\"status\":\"accepted\",\"release_id\":\"{}\",\"expiry_unix\":{}}}",
+            json_escape(&accepted.release_id),
+            accepted.expiry_unix
+        ),
+        Err(reason) => format!("{{\"status\":\"rejected\",\"reason\":\"{}\"}}", json_escape(reason)),
+    }
+}
+
+/// The loaded key whose fingerprint is `signer`.
+fn key_for_signer<'k>(keys: &'k [SecretBytes], signer: &str) -> Option<&'k SecretBytes> {
+    keys.iter().find(|key| owners::key_fingerprint(key.expose()) == signer)
+}
+
+/// Decrypt the key text from a vault envelope with the master key in `SENTRY_VAULT_KEY`.
+#[cfg(feature = "vault-keys")]
+fn envelope_key_text(path: &Path) -> Result<SecretBytes, String> {
+    let envelope = vault::EncryptedSecret::load(path)?;
+    vault::SecretVault::from_env_var(vault::VAULT_KEY_ENV)?.decrypt(&envelope)
+}
+
+#[cfg(not(feature = "vault-keys"))]
+fn envelope_key_text(path: &Path) -> Result<SecretBytes, String> {
+    Err(format!(
+        "--sign-key-envelope {:?}: this binary was built without vault support; rebuild with --features vault-keys",
+        path
+    ))
+}
+
+/// Record an HMAC-SHA256 of every binary in its entry. `persist_manifest` writes the `.sig` files.
+/// Each file is read under the manifest's `root` (see `built_file`). An entry
+/// with a `required_signer` is signed with that key, which must be among `keys`; every other entry
+/// with the first key.
+fn sign_entries(manifest: &mut OmegaManifest, keys: &[SecretBytes]) -> Result<(), String> {
+    let root = manifest.root.clone();
+    for entry in &mut manifest.entries {
+        if entry.rel_path == "manifest.txt" {
+            // `manifest.txt.sig` already holds the manifest's own signature.
+            return Err("A binary named manifest.txt would overwrite the manifest signature file".to_string());
+        }
+        let key = match &entry.required_signer {
+            Some(signer) => key_for_signer(keys, signer).ok_or_else(|| {
+                format!("{} must be signed by key {signer}, which is not loaded; add it to {EXTRA_SIGNING_KEYS_ENV}", entry.rel_path)
+            })?,
+            None => &keys[0],
+        };
+        let path = built_file(root.as_deref(), entry);
+        let data = fs::read(&path).map_err(|err| format!("Failed to read {:?}: {err}", path))?;
+        entry.sig = Some(sha256::to_hex(&sha256::hmac_sha256(key.expose(), &data)));
+    }
+    Ok(())
+}
+
+/// Recompute each entry's HMAC and compare it with `<sig_dir>/<rel_path>.sig`, or with the manifest's
+/// `sig=` field when that file is absent. When both exist, both must agree.
+///
+/// Entries without a `required_signer` are checked with the first key. The others are checked
+/// with the key of that fingerprint, which must be loaded; a signature that only another loaded
+/// key explains, or no signature at all, is `SigCheck::SignerMismatch`.
+fn check_entry_sigs(
+    report: &mut [BinCheck],
+    manifest: &OmegaManifest,
+    bins_dir: &Path,
+    sig_dir: &Path,
+    keys: &[SecretBytes],
+    allow_exe_suffix: bool,
+) -> Result<(), String> {
+    for (check, entry) in report.iter_mut().zip(&manifest.entries) {
+        let key = match &entry.required_signer {
+            Some(signer) => key_for_signer(keys, signer).ok_or_else(|| {
+                format!("{} must be signed by key {signer}, which is not loaded; add it to {EXTRA_SIGNING_KEYS_ENV}", entry.rel_path)
+            })?,
+            None => &keys[0],
+        };
+        // `rel_path` was normalized by `load_manifest`, so the `.sig` stays inside `sig_dir`.
+        let detached = fs::read_to_string(sig_dir.join(local_path(&format!("{}.sig", entry.rel_path)))).ok();
+        let expected: Vec<&str> = detached.iter().map(|sig| sig.trim()).chain(entry.sig.as_deref()).collect();
+        if expected.is_empty() {
+            check.signature = match entry.required_signer {
+                Some(_) => SigCheck::SignerMismatch,
+                None => SigCheck::Missing,
+            };
+            continue;
+        }
+
+        let path = entry_file(bins_dir, entry, allow_exe_suffix);
+        let data = fs::read(&path).map_err(|err| format!("Failed to read {:?}: {err}", path))?;
+        let signed_by = |key: &SecretBytes| {
+            let computed = sha256::hmac_sha256(key.expose(), &data);
+            expected.iter().all(|sig| sha256::from_hex(sig).is_some_and(|recorded| sha256::constant_time_eq(&recorded, &computed)))
+        };
+        check.signature = if signed_by(key) {
+            SigCheck::Valid
+        } else if entry.required_signer.is_some() && keys.iter().any(signed_by) {
+            SigCheck::SignerMismatch
+        } else {
+            SigCheck::Mismatch
+        };
+    }
+    Ok(())
+}
+
+/// Version stamped into every status document as `format_version`. Bump it whenever a reader
+/// such as `cross-check` would misunderstand the new layout.
+pub const STATUS_FORMAT_VERSION: u32 = 1;
+
+/// The status document for `build`, `verify`, `daemon`, and `unbundle`. `generated_at_unix_ms` is
+/// this host's clock, so a reader on another host can spot clock skew (see `clock_skew`).
+/// `extra_warnings` are listed after the manifest's own warnings.
+fn render_json_status(
+    action: &str,
+    mode: Mode,
+    env_settings: &OmegaEnvironment,
+    manifest: &OmegaManifest,
+    results: &[BinCheck],
+    generated_at_unix_ms: u128,
+    extra_warnings: &[String],
+) -> String {
+    // Build a compact JSON payload by hand to avoid third-party crates.
+    let mut message = String::new();
+    message.push('{');
+    message.push_str(&format!("\"format_version\":{},", STATUS_FORMAT_VERSION));
+    message.push_str(&format!("\"generated_at_unix_ms\":{},", generated_at_unix_ms));
+    message.push_str(&format!("\"tool_version\":\"{}\",", version::TOOL_VERSION));
+    message.push_str(&format!("\"action\":\"{}\",", action));
+    message.push_str(&format!("\"mode\":\"{}\",", mode.as_str()));
+    message.push_str(&format!("\"release_id\":\"{}\",", json_escape(&manifest.release_id)));
+    message.push_str(&format!(
+        "\"hosts\":{{\"yellow\":{},\"red\":{},\"blue\":{}}},",
+        env_settings.yellow_host.to_json(),
+        env_settings.red_host.to_json(),
+        env_settings.blue_host.to_json()
+    ));
+    message.push_str("\"entries\":[");
+
+    for (index, entry) in manifest.entries.iter().enumerate() {
+        if index > 0 {
+            message.push(',');
+        }
+        message.push_str(&format!("{{\"name\":\"{}\",\"rel_path\":\"{}\",\"path\":\"{}\",\"hash\":\"{}\",\"size\":{}",
+            json_escape(&entry.name), json_escape(&entry.rel_path), json_escape(report_path(entry)), entry.hash, entry.size));
+        if !entry.digests.is_empty() {
+            message.push_str(&format!(",\"digests\":{}", digest::digests_json(&entry.digests)));
+        }
+        if let Some(sig) = &entry.sig {
+            message.push_str(&format!(",\"sig\":\"{}\"", json_escape(sig)));
+        }
+        if let Some(mode) = entry.mode {
+            message.push_str(&format!(",\"mode\":\"{:04o}\"", mode));
+        }
+        if let Some(filetype) = entry.filetype {
+            message.push_str(&format!(",\"filetype\":\"{}\"", filetype.as_str()));
+        }
+        if let Some(owner) = &entry.owner {
+            message.push_str(&format!(",\"owner\":\"{}\"", json_escape(owner)));
+        }
+        if let Some(signer) = &entry.required_signer {
+            message.push_str(&format!(",\"required_signer\":\"{}\"", signer));
+        }
+        message.push('}');
+    }
+
+    message.push(']');
+
+    if !manifest.warnings.is_empty() || !extra_warnings.is_empty() {
+        let quoted: Vec<String> = manifest.warnings.iter().chain(extra_warnings).map(|warning| format!("\"{}\"", json_escape(warning))).collect();
+        message.push_str(&format!(",\"warnings\":[{}]", quoted.join(",")));
+    }
+
+    if let Some(provenance) = &manifest.provenance {
+        // The build time and mode live elsewhere in the manifest; fold them in for readers.
+        let mut json = with_json_field(&provenance.to_json(), "builder_mode", &format!("\"{}\"", manifest.mode.as_str()));
+        if let Some(created_at_unix) = manifest.created_at_unix {
+            json = with_json_field(&json, "built_at_unix", &created_at_unix.to_string());
+        }
+        message.push_str(&format!(",\"provenance\":{}", json));
+    }
+
+    if !results.is_empty() {
+        message.push_str(&format!(",\"results\":{}", results_json(results)));
+        // The hashes this host actually computed, so another role can compare notes.
+        message.push_str(&format!(",\"observed\":{}", observed_json(results)));
+    }
+
+    message.push('}');
+    message
+}
+
+/// The `results` list: one `"rel_path:status"` string per check.
+fn results_json(results: &[BinCheck]) -> String {
+    let items: Vec<String> = results.iter().map(|result| format!("\"{}:{}\"", json_escape(&result.rel_path), result.status())).collect();
+    format!("[{}]", items.join(","))
+}
+
+/// The `observed` list: what was found on disk for each check.
+fn observed_json(results: &[BinCheck]) -> String {
+    let mut message = String::from("[");
+    for (index, result) in results.iter().enumerate() {
+        if index > 0 {
+            message.push(',');
+        }
+        message.push_str(&format!(
+            "{{\"name\":\"{}\",\"rel_path\":\"{}\",\"hash\":\"{}\"",
+            json_escape(&result.name),
+            json_escape(&result.rel_path),
+            result.observed_hash
+        ));
+        if !result.observed_digests.is_empty() {
+            message.push_str(&format!(",\"digests\":{}", digest::digests_json(&result.observed_digests)));
+        }
+        // Which algorithms disagree, so a reader can tell a changed file from an edited digest.
+        let mismatched: Vec<String> = result.mismatched_digests().iter().map(|algorithm| format!("\"{}\"", algorithm.as_str())).collect();
+        if !mismatched.is_empty() {
+            message.push_str(&format!(",\"mismatched_digests\":[{}]", mismatched.join(",")));
+        }
+        if let Some(filetype) = result.observed_filetype {
+            message.push_str(&format!(",\"filetype\":\"{}\"", filetype.as_str()));
+        }
+        if result.from_cache {
+            message.push_str(",\"from_cache\":true");
+        }
+        message.push('}');
+    }
+    message.push(']');
+    message
+}
+
+/// Finish a `render_json_status` document for several `--bins-dir` slots (see `slots`). The
+/// document was rendered with the first slot's results. With one unlabeled slot it is returned as
+/// it is; otherwise `results` and `observed` are keyed by label, and `slots` and an overall
+/// `status` are added.
+/// The checks `render_json_status` writes as `results` and `observed`: the only slot's, or none
+/// in the per-slot layout, where `with_slot_results` adds both keyed by label.
+fn single_slot_results<'a>(slots: &[slots::BinsSlot], reports: &'a [Vec<BinCheck>]) -> &'a [BinCheck] {
+    match slots::is_multi(slots) {
+        true => &[],
+        false => reports.first().map_or(&[][..], Vec::as_slice),
+    }
+}
+
+fn with_slot_results(document: String, slots: &[slots::BinsSlot], reports: &[Vec<BinCheck>]) -> String {
+    if !slots::is_multi(slots) {
+        return document;
+    }
+    let keyed = |render: fn(&[BinCheck]) -> String| {
+        let parts: Vec<(&str, String)> = slots.iter().zip(reports).map(|(slot, report)| (slot.label.as_str(), render(report))).collect();
+        slots::keyed_json(true, &parts)
+    };
+    let passed: Vec<bool> = reports.iter().map(|report| report_outcome(report) == CliOutcome::Success).collect();
+    let mut document = with_json_field(&document, "results", &keyed(results_json));
+    document = with_json_field(&document, "observed", &keyed(observed_json));
+    document = with_json_field(&document, "slots", &slots::slots_json(slots, &passed));
+    with_json_field(&document, "status", if passed.iter().all(|passed| *passed) { "\"ok\"" } else { "\"failed\"" })
+}
+
+/// Summarize a prune run. In dry-run mode `deleted` lists what *would* have been removed.
+fn render_prune_report(mode: Mode, releases_dir: &Path, plan: &prune::PrunePlan, dry_run: bool) -> String {
+    let quote_list = |paths: Vec<String>| -> String {
+        paths
+            .iter()
+            .map(|path| format!("\"{}\"", json_escape(path)))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let release_names = |releases: &[prune::ReleaseFolder]| -> Vec<String> {
+        releases.iter().map(|release| release.path.to_string_lossy().to_string()).collect()
+    };
+
+    format!(
+        "{{\"action\":\"prune\",\"mode\":\"{}\",\"releases_dir\":\"{}\",\"dry_run\":{},\"kept\":[{}],\"deleted\":[{}],\"skipped\":[{}]}}",
+        mode.as_str(),
+        json_escape(&releases_dir.to_string_lossy()),
+        dry_run,
+        quote_list(release_names(&plan.keep)),
+        quote_list(release_names(&plan.delete)),
+        quote_list(plan.skipped.iter().map(|path| path.to_string_lossy().to_string()).collect())
+    )
+}
+
+/// Log (not stop on) a message systemd did not get; it restarts the daemon if pings stay away.
+fn notify_systemd(sent: io::Result<()>) {
+    if let Err(err) = sent {
+        LOG.warn("Could not notify systemd", &[("error", &err.to_string())]);
+    }
+}
+
+/// Read the peer's status document for `daemon --peer-status` and compare its timestamp with
+/// `now_millis`. A missing, unreadable, or timestamp-less document is logged and skipped: the
+/// peer may simply not have published yet.
+fn check_peer_clock(path: &Path, now_millis: u128, max_skew: Duration) -> Option<SkewCheck> {
+    match cross_check::load_status(path) {
+        Ok(summary) => match summary.generated_at_unix_ms {
+            Some(peer_ms) => Some(SkewCheck::new(peer_ms, now_millis, max_skew)),
+            None => {
+                LOG.warn("Peer status has no generated_at_unix_ms; clock skew not checked", &[("path", &path.display().to_string())]);
+                None
+            }
+        },
+        Err(err) => {
+            LOG.warn("Peer status unreadable; clock skew not checked", &[("error", &err)]);
+            None
+        }
+    }
+}
+
+/// Append `"key":value` to a finished JSON object. `value` must already be valid JSON.
+fn with_json_field(document: &str, key: &str, value: &str) -> String {
+    let body = document.strip_suffix('}').unwrap_or(document);
+    format!("{body},\"{key}\":{value}}}")
+}
+
+/// Any mismatching binary fails the run.
+/// A `results-changed` event listing each `name:old->new` that differs from the last daemon
+/// pass, or `None` when nothing changed. Entries added or removed by a manifest reload show
+/// `none` on the missing side.
+fn results_changed_event(
+    mode: Mode,
+    manifest: &OmegaManifest,
+    before: &BTreeMap<String, &'static str>,
+    after: &BTreeMap<String, &'static str>,
+) -> Option<String> {
+    let names: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    let changes: Vec<String> = names
+        .into_iter()
+        .filter(|name| before.get(*name) != after.get(*name))
+        .map(|name| {
+            let side = |results: &BTreeMap<String, &'static str>| results.get(name).copied().unwrap_or("none");
+            format!("{}:{}->{}", name, side(before), side(after))
+        })
+        .collect();
+    if changes.is_empty() {
+        return None;
+    }
+    LOG.info("Verification results changed", &[("changes", &changes.join(","))]);
+    let quoted: Vec<String> = changes.iter().map(|change| format!("\"{}\"", json_escape(change))).collect();
+    Some(format!(
+        "{{\"action\":\"results-changed\",\"mode\":\"{}\",\"release_id\":\"{}\",\"changes\":[{}]}}",
+        mode.as_str(),
+        json_escape(&manifest.release_id),
+        quoted.join(",")
+    ))
+}
+
+fn report_outcome(results: &[BinCheck]) -> CliOutcome {
+    if results.iter().all(BinCheck::matched) {
+        CliOutcome::Success
+    } else {
+        CliOutcome::VerificationFailed
+    }
+}
+
+/// Re-indent a compact JSON document two spaces per level. It only tracks whether it is inside a
+/// string, which is all our hand-built documents need.
+fn pretty_json(compact: &str) -> String {
+    let mut output = String::new();
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut chars = compact.chars().peekable();
+
+    let newline = |output: &mut String, depth: usize| {
+        output.push('\n');
+        output.push_str(&"  ".repeat(depth));
+    };
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            output.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match c {
+            '"' => {
+                in_string = true;
+                output.push(c);
+            }
+            '{' | '[' => {
+                output.push(c);
+                // Keep empty containers on one line: `[]` rather than `[\n]`.
+                if let Some(close @ ('}' | ']')) = chars.peek().copied() {
+                    output.push(close);
+                    chars.next();
+                } else {
+                    depth += 1;
+                    newline(&mut output, depth);
+                }
+            }
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                newline(&mut output, depth);
+                output.push(c);
+            }
+            ',' => {
+                output.push(c);
+                newline(&mut output, depth);
+            }
+            ':' => output.push_str(": "),
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+/// Replace `path` without readers ever seeing a partial file (see `atomic::atomic_write`).
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), SentryError> {
+    atomic::atomic_write(path, contents).map_err(|err| SentryError::io(format!("Unable to write {:?}", path), err))
+}
+
+/// Write `pid=<pid> seq=<n> at=<unix millis>`, the same liveness line Squire's gateway keeps in
+/// its `Discovery/heartbeat.txt`. The ecosystem hub reads it to tell a running daemon from a
+/// stopped one, and a `seq` that starts over at 1 from a restart.
+fn write_heartbeat(path: &Path, seq: u64, at: u128) -> Result<(), String> {
+    Ok(write_atomic(path, format!("pid={} seq={} at={}\n", std::process::id(), seq, at).as_bytes())?)
+}
+
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            // Other control characters are not allowed raw inside JSON strings.
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The manifest hash of `data`: `self_verify::manifest_hash`, which the hub and Squire use to
+/// check themselves against a manifest at startup. Files are hashed with `hash_file` instead.
+fn hash_bytes(data: &[u8]) -> String {
+    // DefaultHasher is not cryptographic, but it is deterministic and available without extra
+    // crates. Replace this with a SHA-256 implementation from a vendored crate when you harden
+    // the manifest pipeline.
+    self_verify::manifest_hash(data)
+}
+
+/// Bytes read from a file per step when hashing it.
+const HASH_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Everything one pass over a file gives: manifest hash, the digests asked for, and file type.
+struct FileHashes {
+    hash: String,
+    digests: Vec<(DigestAlgorithm, String)>,
+    filetype: FileType,
+}
+
+/// Hash the file at `path` in one pass; see `hash_reader`.
+fn hash_file(path: &Path, algorithms: &[DigestAlgorithm]) -> io::Result<FileHashes> {
+    let file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    hash_reader(file, len, algorithms)
+}
+
+/// Read `reader` once through a fixed buffer, feeding the manifest hash and every digest in
+/// `algorithms` from each chunk, and sniff the file type from the first bytes. `len` is the size
+/// the file should have; a file that grew or shrank while it was read is an error, not a hash.
+fn hash_reader<R: Read>(mut reader: R, len: u64, algorithms: &[DigestAlgorithm]) -> io::Result<FileHashes> {
+    let mut manifest = self_verify::ManifestHasher::new(len);
+    let mut digests = digest::MultiHasher::new(algorithms);
+    let mut head = Vec::with_capacity(filetype::SNIFF_BYTES);
+    let mut buffer = vec![0u8; HASH_BUFFER_BYTES];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        let chunk = &buffer[..read];
+        manifest.update(chunk);
+        digests.update(chunk);
+        let wanted = filetype::SNIFF_BYTES - head.len();
+        head.extend_from_slice(&chunk[..read.min(wanted)]);
+    }
+    let hash = manifest.finish().ok_or_else(|| io::Error::other("the file changed size while it was read"))?;
+    Ok(FileHashes { hash, digests: digests.finalize(), filetype: filetype::sniff(&head) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out `data` a piece at a time and counts every byte and call.
+    struct CountingReader<'a> {
+        data: &'a [u8],
+        bytes_read: &'a std::cell::Cell<u64>,
+        calls: &'a std::cell::Cell<u32>,
+    }
+
+    impl Read for CountingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.calls.set(self.calls.get() + 1);
+            let n = buf.len().min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            self.bytes_read.set(self.bytes_read.get() + n as u64);
+            Ok(n)
+        }
+    }
+
+    fn sample(len: usize) -> Vec<u8> {
+        let mut data = b"\x7fELF".to_vec();
+        data.extend((0..len - 4).map(|i| (i * 31 % 251) as u8));
+        data
+    }
+
+    #[test]
+    fn hash_reader_reads_each_byte_once_and_matches_whole_buffer_hashes() {
+        let data = sample(3 * HASH_BUFFER_BYTES + 1234);
+        let (bytes_read, calls) = (std::cell::Cell::new(0), std::cell::Cell::new(0));
+        let reader = CountingReader { data: &data, bytes_read: &bytes_read, calls: &calls };
+        let algorithms = [DigestAlgorithm::Sha256, DigestAlgorithm::Sha512];
+
+        let hashed = hash_reader(reader, data.len() as u64, &algorithms).unwrap();
+
+        assert_eq!(bytes_read.get(), data.len() as u64);
+        // Four full buffers' worth of chunks plus the read that finds the end.
+        assert_eq!(calls.get(), 5);
+        assert_eq!(hashed.hash, hash_bytes(&data));
+        assert_eq!(hashed.digests, digest::digests_of(&data, &algorithms));
+        assert_eq!(hashed.filetype, filetype::sniff(&data));
+    }
+
+    #[test]
+    fn hash_reader_handles_empty_and_short_files() {
+        for len in [0, 1, filetype::SNIFF_BYTES - 1, filetype::SNIFF_BYTES + 1] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let hashed = hash_reader(data.as_slice(), len as u64, &[DigestAlgorithm::Sha256]).unwrap();
+            assert_eq!(hashed.hash, hash_bytes(&data), "len {len}");
+            assert_eq!(hashed.digests, digest::digests_of(&data, &[DigestAlgorithm::Sha256]), "len {len}");
+            assert_eq!(hashed.filetype, filetype::sniff(&data), "len {len}");
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sentry-lib-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A `bins` folder under `base` holding `files` (relative path, contents).
+    fn bins(base: &Path, files: &[(&str, &[u8])]) -> PathBuf {
+        let dir = base.join("bins");
+        for (rel_path, contents) in files {
+            let path = dir.join(rel_path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn build(bins_dir: &Path) -> OmegaManifest {
+        let provenance = provenance::Provenance::collect(Some("rustc 1.80.0"), None);
+        build_manifest(Mode::Blue, bins_dir, "r1".to_string(), provenance, true, &[DigestAlgorithm::Sha256], None).unwrap()
+    }
+
+    #[test]
+    fn proof_for_one_entry_checks_until_the_file_changes() {
+        let base = temp_dir("proof");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("bard", b"bard v1"), ("tools/hub", b"hub v1")]);
+        let manifest = build(&dir);
+        assert_eq!(build(&dir).merkle_root, manifest.merkle_root, "same files, same root");
+
+        let proof_path = base.join("hub.proof");
+        fs::write(&proof_path, render_proof(&manifest, "hub").unwrap()).unwrap();
+        let proof = load_proof(&proof_path).unwrap();
+        assert_eq!(proof.name, "tools/hub");
+        assert_eq!(proof.leaf_count, 3);
+        assert_eq!(Some(merkle::to_hex(&proof.root)), manifest.merkle_root);
+        assert_eq!(check_proof(&proof, &dir.join("tools/hub")), Ok(true));
+
+        fs::write(dir.join("tools/hub"), b"hub v2").unwrap();
+        assert_eq!(check_proof(&proof, &dir.join("tools/hub")), Ok(false));
+        // Proving against another file fails too.
+        assert_eq!(check_proof(&proof, &dir.join("bard")), Ok(false));
+    }
+
+    #[test]
+    fn proof_needs_a_recorded_root_that_still_matches() {
+        let base = temp_dir("proof-root");
+        let mut manifest = build(&bins(&base, &[("squire", b"v1")]));
+        manifest.entries[0].hash = "0000000000000000".to_string();
+        assert!(render_proof(&manifest, "squire").unwrap_err().contains("do not match"));
+        manifest.merkle_root = None;
+        assert!(render_proof(&manifest, "squire").unwrap_err().contains("no merkle_root"));
+    }
+
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    fn parse(words: &[&str]) -> Result<Invocation, String> {
+        parse_args(Mode::Blue, &args(words))
+    }
+
+    #[test]
+    fn flags_parse_in_any_order_and_in_the_equals_form() {
+        for words in [
+            &["build", "--bins-dir", "b", "--releases-dir", "r"][..],
+            &["build", "--releases-dir", "r", "--bins-dir", "b"],
+            &["build", "--releases-dir=r", "--bins-dir=b"],
+            &["--mode", "blue", "build", "--releases-dir=r", "--bins-dir", "b"],
+        ] {
+            let Invocation { mode, command, .. } = parse(words).unwrap();
+            assert_eq!(mode, Mode::Blue);
+            let Command::Build { bins_dir, releases_dir, release_id, .. } = command else { panic!("{words:?} is not a build") };
+            assert_eq!((bins_dir, releases_dir), (PathBuf::from("b"), PathBuf::from("r")), "{words:?}");
+            assert_eq!(release_id, AUTO_RELEASE_ID);
+        }
+        assert_eq!(parse(&["verify", "--manifest", "m", "--mode=red"]).unwrap().mode, Mode::Red);
+    }
+
+    #[test]
+    fn every_missing_flag_is_named_at_once() {
+        let err = parse(&["build"]).err().unwrap();
+        assert!(err.contains("--bins-dir") && err.contains("--releases-dir"), "{err}");
+    }
+
+    #[test]
+    fn unknown_duplicate_and_valueless_flags_are_errors() {
+        let err = parse(&["build", "--bins-dir", "b", "--releases-dir", "r", "--colour"]).err().unwrap();
+        assert!(err.contains("Unknown flag --colour") && err.contains("valid flags: --bins-dir"), "{err}");
+        let err = parse(&["build", "--bins-dir", "b", "--bins-dir", "c", "--releases-dir", "r"]).err().unwrap();
+        assert_eq!(err, "Flag --bins-dir was given more than once");
+        assert_eq!(parse(&["build", "--releases-dir", "r", "--bins-dir"]).err().unwrap(), "--bins-dir requires a value");
+        let err = parse(&["build", "--bins-dir", "b", "--releases-dir", "r", "--recursive=yes"]).err().unwrap();
+        assert_eq!(err, "--recursive is a switch and does not take a value");
+        assert!(parse(&["build", "stray"]).err().unwrap().contains("Unexpected argument stray"));
+        assert!(parse(&["launch"]).err().unwrap().starts_with("Unknown subcommand launch"));
+    }
+
+    #[test]
+    fn help_works_globally_and_per_command_even_with_required_flags_missing() {
+        let Command::Help(text) = parse(&["--help"]).unwrap().command else { panic!("no help") };
+        assert!(text.contains("build") && text.contains("verify"));
+        let Command::Help(text) = parse(&["build", "--help"]).unwrap().command else { panic!("no help") };
+        assert!(text.contains("--bins-dir") && text.contains("--releases-dir"));
+    }
+
+    /// Run the CLI with a manual clock at `now_millis`, `env`, and a roomy disk.
+    fn run_at(mode: Mode, words: &[&str], env: &runtime::MapEnv, now_millis: u128) -> Result<CliOutcome, SentryError> {
+        let clock = runtime::ManualClock::new(now_millis);
+        let rt = Runtime { clock: &clock, sleeper: &clock, env };
+        run_cli_with_space(mode, &args(words), rt, &disk_space::FixedSpace(u64::MAX))
+    }
+
+    fn run(mode: Mode, words: &[&str]) -> Result<CliOutcome, SentryError> {
+        run_at(mode, words, &runtime::MapEnv::new(), 1_700_000_000_000)
+    }
+
+    /// Build `bins_dir` into `<base>/releases` and return the manifest file.
+    fn persisted(base: &Path, bins_dir: &Path) -> PathBuf {
+        let (folder, _) = persist_manifest(&build(bins_dir), &base.join("releases")).unwrap();
+        folder.join("manifest.txt")
+    }
+
+    fn document(path: &Path) -> json::JsonValue {
+        json::parse(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn output_file_is_replaced_whole_and_pretty_output_still_parses() {
+        let base = temp_dir("output");
+        let dir = bins(&base, &[("squire", b"v1")]);
+        let manifest = persisted(&base, &dir);
+        let out = base.join("status.json");
+        fs::write(&out, "an older, longer document that must disappear completely").unwrap();
+        let (m, b, o) = (manifest.to_str().unwrap(), dir.to_str().unwrap(), out.to_str().unwrap());
+
+        assert_eq!(run(Mode::Yellow, &["verify", "--manifest", m, "--bins-dir", b, "--output", o]).unwrap(), CliOutcome::Success);
+        let compact = fs::read_to_string(&out).unwrap();
+        assert_eq!(compact.lines().count(), 1);
+        assert_eq!(document(&out).get("action").and_then(|v| v.as_str()), Some("verify"));
+        assert!(fs::read_dir(&base).unwrap().all(|entry| !entry.unwrap().file_name().to_string_lossy().contains(".tmp")));
+
+        assert_eq!(run(Mode::Yellow, &["verify", "--manifest", m, "--bins-dir", b, "--output", o, "--pretty"]).unwrap(), CliOutcome::Success);
+        let pretty = fs::read_to_string(&out).unwrap();
+        assert!(pretty.lines().count() > 5 && pretty.contains("\n  \"action\": \"verify\""), "{pretty}");
+        assert_eq!(document(&out).get("release_id").and_then(|v| v.as_str()), Some("r1"));
+    }
+
+    #[test]
+    fn quiet_mismatch_writes_nothing_and_still_fails() {
+        let base = temp_dir("quiet");
+        let dir = bins(&base, &[("squire", b"v1")]);
+        let manifest = persisted(&base, &dir);
+        fs::write(dir.join("squire"), b"v2").unwrap();
+        let out = base.join("status.json");
+        let words = ["verify", "--manifest", manifest.to_str().unwrap(), "--bins-dir", dir.to_str().unwrap(), "--quiet", "--output", out.to_str().unwrap()];
+        let outcome = run(Mode::Yellow, &words).unwrap();
+        assert_eq!((outcome, outcome.code()), (CliOutcome::VerificationFailed, 2));
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn pretty_json_keeps_strings_and_empty_containers_intact() {
+        let pretty = pretty_json(r#"{"a":[],"b":{"c":"x,{y}:\"z"},"d":[1,2]}"#);
+        assert_eq!(json::parse(&pretty).unwrap().get("b").and_then(|b| b.get("c")).and_then(|c| c.as_str()), Some("x,{y}:\"z"));
+        assert!(pretty.contains("\"a\": []"), "{pretty}");
+    }
+
+    #[test]
+    fn require_source_rev_passes_only_for_the_recorded_revision() {
+        let base = temp_dir("source-rev");
+        let dir = bins(&base, &[("squire", b"v1")]);
+        let mut manifest = build(&dir);
+        manifest.provenance = Some(provenance::Provenance::collect(Some("rustc 1.80.0"), Some("ABC123")));
+        let (folder, _) = persist_manifest(&manifest, &base.join("releases")).unwrap();
+        let out = base.join("status.json");
+        let verify = |extra: &[&str]| {
+            let manifest = folder.join("manifest.txt");
+            let mut words = vec!["verify", "--manifest", manifest.to_str().unwrap(), "--bins-dir", dir.to_str().unwrap(), "--output", out.to_str().unwrap()];
+            words.extend_from_slice(extra);
+            run(Mode::Yellow, &words).unwrap()
+        };
+
+        assert_eq!(verify(&[]), CliOutcome::Success);
+        assert!(document(&out).get("source_rev_check").is_none());
+        assert_eq!(verify(&["--require-source-rev", "abc123"]), CliOutcome::Success);
+        let check = document(&out).get("source_rev_check").cloned().unwrap();
+        assert_eq!(check.get("status").and_then(|v| v.as_str()), Some("match"));
+        assert_eq!(verify(&["--require-source-rev", "other"]), CliOutcome::VerificationFailed);
+    }
+
+    #[test]
+    fn manifests_without_provenance_still_verify_but_fail_a_required_revision() {
+        let base = temp_dir("legacy-provenance");
+        let dir = bins(&base, &[("squire", b"v1")]);
+        let rendered = render_manifest(&build(&dir));
+        let legacy: String = rendered.lines().filter(|line| !line.starts_with("provenance.")).map(|line| format!("{line}\n")).collect();
+        let manifest = base.join("manifest.txt");
+        fs::write(&manifest, legacy).unwrap();
+        assert!(load_manifest(&manifest).unwrap().provenance.is_none());
+        let (m, b) = (manifest.to_str().unwrap(), dir.to_str().unwrap());
+        assert_eq!(run(Mode::Yellow, &["verify", "--manifest", m, "--bins-dir", b, "--quiet"]).unwrap(), CliOutcome::Success);
+        let outcome = run(Mode::Yellow, &["verify", "--manifest", m, "--bins-dir", b, "--quiet", "--require-source-rev", "abc"]).unwrap();
+        assert_eq!(outcome, CliOutcome::VerificationFailed);
+    }
+
+    #[test]
+    fn hash_reader_rejects_a_file_that_changed_size() {
+        let data = sample(1000);
+        assert!(hash_reader(data.as_slice(), 999, &[]).is_err());
+        assert!(hash_reader(data.as_slice(), 1001, &[]).is_err());
+    }
+
+    const SIGNING_KEY_HEX: &str = "3333333333333333333333333333333333333333333333333333333333333333";
+
+    /// The `results` list of a status document, as `name:status` strings.
+    fn results(path: &Path) -> Vec<String> {
+        let document = document(path);
+        let list = document.get("results").and_then(|results| results.as_array()).unwrap();
+        list.iter().map(|result| result.as_str().unwrap().to_string()).collect()
+    }
+
+    /// Build `bins_dir` into `<base>/releases` through the CLI and return the release folder.
+    fn cli_build(base: &Path, bins_dir: &Path, extra: &[&str], env: &runtime::MapEnv) -> PathBuf {
+        let out = base.join("build.json");
+        let releases = base.join("releases");
+        let mut words = vec!["build", "--bins-dir", bins_dir.to_str().unwrap(), "--releases-dir", releases.to_str().unwrap(), "--output", out.to_str().unwrap()];
+        words.extend_from_slice(extra);
+        assert_eq!(run_at(Mode::Blue, &words, env, 1_700_000_000_000).unwrap(), CliOutcome::Success);
+        PathBuf::from(document(&out).get("release").and_then(|release| release.get("folder")).and_then(|folder| folder.as_str()).unwrap())
+    }
+
+    #[test]
+    fn per_file_sigs_round_trip_and_tell_tampering_from_corruption() {
+        let base = temp_dir("per-file-sigs");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("tools/bard", b"bard v1")]);
+        let env = runtime::MapEnv::new().with(SIGNING_KEY_ENV, SIGNING_KEY_HEX);
+        let folder = cli_build(&base, &dir, &["--release-id", "r1", "--recursive", "--per-file-sigs"], &env);
+        let key = sha256::from_hex(SIGNING_KEY_HEX).unwrap();
+        let expected_sig = sha256::to_hex(&sha256::hmac_sha256(&key, b"squire v1"));
+        assert_eq!(fs::read_to_string(folder.join("squire.sig")).unwrap(), format!("{expected_sig}\n"));
+        assert!(folder.join("tools").join("bard.sig").exists());
+        assert!(fs::read_to_string(folder.join("manifest.txt")).unwrap().contains(&format!("|sig={expected_sig}")));
+
+        let out = base.join("verify.json");
+        let manifest = folder.join("manifest.txt");
+        let verify = |env: &runtime::MapEnv| {
+            let words = ["verify", "--manifest", manifest.to_str().unwrap(), "--bins-dir", dir.to_str().unwrap(), "--per-file-sigs", "--output", out.to_str().unwrap()];
+            run_at(Mode::Yellow, &words, env, 1_700_000_000_000)
+        };
+        assert_eq!(verify(&env).unwrap(), CliOutcome::Success);
+        assert_eq!(results(&out), ["squire:match", "tools/bard:match"]);
+
+        // Someone with another key re-signed squire; bard's bytes were merely damaged.
+        let forged = sha256::to_hex(&sha256::hmac_sha256(&[0x44; 32], b"squire v1"));
+        fs::write(folder.join("squire.sig"), format!("{forged}\n")).unwrap();
+        fs::write(dir.join("tools/bard"), b"bard v1 damaged").unwrap();
+        assert_eq!(verify(&env).unwrap(), CliOutcome::VerificationFailed);
+        assert_eq!(results(&out), ["squire:sig-mismatch", "tools/bard:hash-mismatch"]);
+
+        // The key is needed to check signatures at all.
+        assert!(verify(&runtime::MapEnv::new()).unwrap_err().to_string().contains(SIGNING_KEY_ENV));
+    }
+
+    #[test]
+    fn unsigned_manifests_verify_without_the_flag_and_report_sig_missing_with_it() {
+        let base = temp_dir("per-file-sigs-missing");
+        let dir = bins(&base, &[("squire", b"squire v1")]);
+        let env = runtime::MapEnv::new().with(SIGNING_KEY_ENV, SIGNING_KEY_HEX);
+        let folder = cli_build(&base, &dir, &["--release-id", "r1"], &env);
+        assert!(!folder.join("squire.sig").exists());
+
+        let out = base.join("verify.json");
+        let (m, b, o) = (folder.join("manifest.txt"), dir.to_str().unwrap(), out.to_str().unwrap());
+        let words = ["verify", "--manifest", m.to_str().unwrap(), "--bins-dir", b, "--output", o];
+        assert_eq!(run_at(Mode::Yellow, &words, &env, 1_700_000_000_000).unwrap(), CliOutcome::Success);
+        assert_eq!(results(&out), ["squire:match"]);
+
+        let words = ["verify", "--manifest", m.to_str().unwrap(), "--bins-dir", b, "--output", o, "--per-file-sigs"];
+        assert_eq!(run_at(Mode::Yellow, &words, &env, 1_700_000_000_000).unwrap(), CliOutcome::Success);
+        assert_eq!(results(&out), ["squire:sig-missing"]);
+    }
+
+    #[test]
+    fn tracker_reloads_a_swapped_manifest_and_survives_a_broken_one() {
+        let base = temp_dir("tracker");
+        let dir = bins(&base, &[("squire", b"v1")]);
+        let path = base.join("manifest.txt");
+        fs::write(&path, render_manifest(&build(&dir))).unwrap();
+        let mut tracker = ManifestTracker::open(&path, false).unwrap();
+        assert_eq!(tracker.refresh(Mode::Yellow), None, "unchanged file");
+
+        let mut next = build(&dir);
+        next.release_id = "release-two".to_string();
+        fs::write(&path, render_manifest(&next)).unwrap();
+        let event = json::parse(&tracker.refresh(Mode::Yellow).unwrap()).unwrap();
+        assert_eq!(event.get("action").and_then(|v| v.as_str()), Some("manifest-reloaded"));
+        assert_eq!(event.get("old_release_id").and_then(|v| v.as_str()), Some("r1"));
+        assert_eq!(event.get("new_release_id").and_then(|v| v.as_str()), Some("release-two"));
+        assert_eq!(tracker.manifest.release_id, "release-two");
+        assert_eq!(tracker.refresh(Mode::Yellow), None);
+
+        fs::write(&path, "not a manifest at all\n").unwrap();
+        for _ in 0..2 {
+            // Every pass retries and reports again until the file is fixed.
+            let event = json::parse(&tracker.refresh(Mode::Yellow).unwrap()).unwrap();
+            assert_eq!(event.get("action").and_then(|v| v.as_str()), Some("manifest-reload-failed"));
+            assert_eq!(event.get("release_id").and_then(|v| v.as_str()), Some("release-two"));
+            assert!(tracker.reload_error.is_some());
+            assert_eq!(tracker.manifest.release_id, "release-two", "the last good manifest stays in use");
+            assert_eq!(verify_bins(&dir, &tracker.manifest, ModeCheck::Off, false).unwrap()[0].status(), "match");
+        }
+
+        fs::write(&path, render_manifest(&build(&dir))).unwrap();
+        assert!(tracker.refresh(Mode::Yellow).unwrap().contains("\"new_release_id\":\"r1\""));
+        assert_eq!(tracker.reload_error, None);
+    }
+
+    #[test]
+    fn host_config_reads_bare_hosts_ports_and_urls() {
+        let parse = |raw: &str| HostConfig::parse(raw, 7420).unwrap();
+        let bare = parse("red.local");
+        assert_eq!((bare.host.as_str(), bare.port, bare.tls, bare.configured), ("red.local", 7420, false, true));
+        assert_eq!((parse(" red.local:7421 ").host, parse("red.local:7421").port), ("red.local".to_string(), 7421));
+        let url = parse("https://Red.Local:8443/");
+        assert_eq!((url.host.as_str(), url.port, url.tls), ("Red.Local", 8443, true));
+        assert_eq!((parse("http://10.0.0.5").port, parse("http://10.0.0.5").tls), (7420, false));
+        assert_eq!(parse("[::1]:7500").address(), "[::1]:7500");
+        assert_eq!(parse("fe80::1").address(), "[fe80::1]:7420");
+        assert_eq!(parse(""), HostConfig::unconfigured(7420));
+
+        for bad in ["red.local:", "red.local:0", "red.local:70000", "https://red.local:http"] {
+            assert!(HostConfig::parse(bad, 7420).unwrap_err().starts_with("bad port"), "{bad}");
+        }
+    }
+
+    #[test]
+    fn environment_marks_missing_hosts_and_names_the_variable_with_a_bad_port() {
+        let env = runtime::MapEnv::new().with("SENTRY_RED_HOST", "red.local:7500").with("SENTRY_YELLOW_HOST", "https://yellow.local").with("SENTRY_PUBLISH_PORT", "7600");
+        let settings = OmegaEnvironment::from_env(&env).unwrap();
+        assert_eq!(settings.red_host.address(), "red.local:7500");
+        assert_eq!(settings.yellow_host.address(), "yellow.local:7600");
+        assert!(!settings.blue_host.configured);
+        assert_eq!(settings.blue_host.to_json(), "{\"host\":\"\",\"port\":7600,\"tls\":false,\"configured\":false}");
+        let warnings = settings.validate();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("SENTRY_YELLOW_HOST asks for https"));
+        assert!(warnings[1].starts_with("SENTRY_BLUE_HOST is not set"));
+        let odd = OmegaEnvironment::from_env(&runtime::MapEnv::new().with("SENTRY_RED_HOST", "tcp://red.local")).unwrap();
+        assert!(odd.validate().iter().any(|warning| warning.contains("\"tcp://red.local\", which is not a host name")));
+
+        let err = OmegaEnvironment::from_env(&runtime::MapEnv::new().with("SENTRY_BLUE_HOST", "blue.local:port")).unwrap_err();
+        assert!(err.starts_with("SENTRY_BLUE_HOST has a bad port \"port\""), "{err}");
+        let err = OmegaEnvironment::from_env(&runtime::MapEnv::new().with("SENTRY_PUBLISH_PORT", "-1")).unwrap_err();
+        assert!(err.starts_with("SENTRY_PUBLISH_PORT has a bad port"), "{err}");
+    }
+
+
+    #[test]
+    fn mode_checks_compare_only_the_bits_they_cover() {
+        assert!(ModeCheck::ExecOnly.accepts(0o755, 0o775), "group write is not an exec bit");
+        assert!(!ModeCheck::ExecOnly.accepts(0o755, 0o644));
+        assert!(!ModeCheck::ExecOnly.accepts(0o755, 0o754), "other lost x");
+        assert!(!ModeCheck::Full.accepts(0o755, 0o775));
+        assert!(!ModeCheck::Full.accepts(0o755, 0o4755), "setuid counts under full");
+        assert!(ModeCheck::Off.accepts(0o755, 0o000));
+        assert_eq!(ModeCheck::parse("exec-only"), Ok(ModeCheck::ExecOnly));
+        assert!(ModeCheck::parse("exec").unwrap_err().contains("--check-mode"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn verify_reports_mode_mismatch_apart_from_hash_mismatch() {
+        use std::os::unix::fs::PermissionsExt;
+        let base = temp_dir("modes");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("bard", b"bard v1")]);
+        let chmod = |name: &str, mode: u32| fs::set_permissions(dir.join(name), fs::Permissions::from_mode(mode)).unwrap();
+        chmod("squire", 0o755);
+        chmod("bard", 0o755);
+        let manifest = build(&dir);
+        assert!(manifest.entries.iter().all(|entry| entry.mode == Some(0o755)));
+        let statuses = |check: ModeCheck| -> Vec<String> {
+            verify_bins(&dir, &manifest, check, false).unwrap().iter().map(|result| format!("{}:{}", result.name, result.status())).collect()
+        };
+
+        chmod("squire", 0o644);
+        chmod("bard", 0o775);
+        assert_eq!(statuses(ModeCheck::ExecOnly), ["bard:match", "squire:mode-mismatch"]);
+        assert_eq!(statuses(ModeCheck::Full), ["bard:mode-mismatch", "squire:mode-mismatch"]);
+        assert_eq!(statuses(ModeCheck::Off), ["bard:match", "squire:match"]);
+        fs::write(dir.join("squire"), b"squire v2").unwrap();
+        assert_eq!(statuses(ModeCheck::ExecOnly), ["bard:match", "squire:mismatch"]);
+
+        // A manifest from before modes were recorded has no `mode=` field and skips the check.
+        let legacy: String = render_manifest(&manifest).lines().map(|line| strip_field(line, "mode=") + "\n").collect();
+        assert!(!legacy.contains("|mode="));
+        let path = base.join("legacy.txt");
+        fs::write(&path, legacy).unwrap();
+        let legacy = load_manifest(&path).unwrap();
+        assert!(legacy.entries.iter().all(|entry| entry.mode.is_none()));
+        fs::write(dir.join("squire"), b"squire v1").unwrap();
+        let results = verify_bins(&dir, &legacy, ModeCheck::Full, false).unwrap();
+        assert!(results.iter().all(|result| result.status() == "match"));
+    }
+
+    /// `line` without its `|<prefix>...` field.
+    fn strip_field(line: &str, prefix: &str) -> String {
+        line.split('|').enumerate().filter(|(index, field)| *index == 0 || !field.starts_with(prefix)).map(|(_, field)| field).collect::<Vec<_>>().join("|")
+    }
+
+
+    #[test]
+    fn bundles_are_reproducible_and_unbundle_verifies_them() {
+        let base = temp_dir("bundle");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("tools/bard", b"bard v1")]);
+        let env = runtime::MapEnv::new();
+        let flags = ["--release-id", "r1", "--recursive", "--bundle", "--source-date-epoch", "1700000000"];
+        let first = fs::read(cli_build(&base.join("one"), &dir, &flags, &env).join("omega-r1.tar")).unwrap();
+        let second = fs::read(cli_build(&base.join("two"), &dir, &flags, &env).join("omega-r1.tar")).unwrap();
+        assert!(first == second, "two builds of the same files give the same bundle");
+        let paths: Vec<String> = bundle::read_tar(&first).unwrap().into_iter().map(|member| member.path).collect();
+        assert_eq!(paths, ["manifest.txt", "manifest.txt.sig", "bin/squire", "bin/tools/bard"]);
+
+        let bundle_path = base.join("omega-r1.tar");
+        fs::write(&bundle_path, &first).unwrap();
+        let (dest, out) = (base.join("unpacked"), base.join("unbundle.json"));
+        let words = ["unbundle", "--bundle", bundle_path.to_str().unwrap(), "--dest", dest.to_str().unwrap(), "--output", out.to_str().unwrap()];
+        assert_eq!(run_at(Mode::Red, &words, &env, 1_700_000_000_000).unwrap(), CliOutcome::Success);
+        assert_eq!(results(&out), ["squire:match", "tools/bard:match"]);
+        assert_eq!(fs::read(dest.join("bin/tools/bard")).unwrap(), b"bard v1");
+        // The folder is no longer empty, so a second unpack is refused.
+        assert!(run_at(Mode::Red, &words, &env, 1_700_000_000_000).unwrap_err().to_string().contains("is not empty"));
+
+        // A time past what the tar header holds is an error, not a panic.
+        let releases = base.join("far-future");
+        let words = ["build", "--bins-dir", dir.to_str().unwrap(), "--releases-dir", releases.to_str().unwrap(), "--release-id", "r1", "--bundle", "--source-date-epoch", "99999999999"];
+        let err = run_at(Mode::Blue, &words, &env, 1_700_000_000_000).unwrap_err().to_string();
+        assert!(err.contains("modification time (--source-date-epoch) 99999999999 does not fit in a tar header"), "{err}");
+    }
+
+
+    #[test]
+    fn manifests_store_forward_slashes_and_refuse_case_only_collisions() {
+        let base = temp_dir("paths");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("tools/deep/bard", b"bard v1")]);
+        let manifest = build(&dir);
+        let path = base.join("manifest.txt");
+        fs::write(&path, render_manifest(&manifest)).unwrap();
+        let loaded = load_manifest(&path).unwrap();
+        let rel_paths: Vec<&str> = loaded.entries.iter().map(|entry| entry.rel_path.as_str()).collect();
+        assert_eq!(rel_paths, ["squire", "tools/deep/bard"]);
+        assert_eq!(local_path("tools/deep/bard"), Path::new("tools").join("deep").join("bard"));
+        assert_eq!(local_path("tools\\deep\\bard"), local_path("tools/deep/bard"));
+        assert!(verify_bins(&dir, &loaded, ModeCheck::ExecOnly, false).unwrap().iter().all(|check| check.status() == "match"));
+
+        let clash = bins(&base.join("clash"), &[("tools/Squire", b"a"), ("tools/squire", b"b")]);
+        let err = build_manifest(Mode::Blue, &clash, "r1".to_string(), provenance::Provenance::collect(None, None), true, &[], None).unwrap_err();
+        assert!(err.to_string().contains("differ only in case"), "{err}");
+    }
+
+    #[test]
+    fn exe_suffix_differences_need_the_flag() {
+        let base = temp_dir("exe-suffix");
+        let manifest = build(&bins(&base.join("linux"), &[("squire", b"same bytes")]));
+        let windows = bins(&base.join("windows"), &[("squire.exe", b"same bytes")]);
+        assert!(matches!(verify_bins(&windows, &manifest, ModeCheck::Off, false), Err(SentryError::EntryUnreadable { .. })));
+        assert_eq!(verify_bins(&windows, &manifest, ModeCheck::Off, true).unwrap()[0].status(), "match");
+
+        // And the other way round: a manifest built on Windows against Linux files.
+        let manifest = build(&windows);
+        let linux = base.join("linux").join("bins");
+        assert_eq!(verify_bins(&linux, &manifest, ModeCheck::Off, true).unwrap()[0].status(), "match");
+    }
+
+    #[test]
+    fn release_ids_windows_cannot_use_are_refused() {
+        let base = temp_dir("release-id");
+        let mut manifest = build(&bins(&base, &[("squire", b"v1")]));
+        for bad in ["r<1>", "2024:01", "a|b", "why?", "star*", "quote\"", "tab\t"] {
+            manifest.release_id = bad.to_string();
+            let err = persist_manifest(&manifest, &base.join("releases")).unwrap_err();
+            assert!(err.to_string().contains("Windows does not allow"), "{bad}: {err}");
+        }
+        assert!(!base.join("releases").exists() || fs::read_dir(base.join("releases")).unwrap().next().is_none());
+        manifest.release_id = "2024-01-02_r1.5".to_string();
+        assert!(persist_manifest(&manifest, &base.join("releases")).unwrap().0.ends_with("omega-2024-01-02_r1.5"));
+    }
+
+
+    #[test]
+    fn auto_release_ids_follow_the_date_and_the_contents() {
+        let base = temp_dir("auto-id");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("bard", b"bard v1")]);
+        let releases = base.join("releases");
+        let out = base.join("build.json");
+        let env = runtime::MapEnv::new();
+        let build_at = |extra: &[&str], now_millis: u128| {
+            let mut words = vec!["build", "--bins-dir", dir.to_str().unwrap(), "--releases-dir", releases.to_str().unwrap(), "--output", out.to_str().unwrap()];
+            words.extend_from_slice(extra);
+            let outcome = run_at(Mode::Blue, &words, &env, now_millis);
+            outcome.map(|_| {
+                let release = document(&out).get("release").cloned().unwrap();
+                let text = |key: &str| release.get(key).and_then(|value| value.as_str()).unwrap().to_string();
+                (text("id"), release.get("auto_id").and_then(|value| value.as_bool()).unwrap(), text("status"))
+            })
+        };
+
+        // 2023-11-14 22:13:20 UTC; `auto` is the default.
+        let (id, auto, status) = build_at(&[], 1_700_000_000_000).unwrap();
+        let root = build(&dir).merkle_root.unwrap();
+        assert_eq!((id.as_str(), auto, status.as_str()), (format!("20231114-{}", &root[..12]).as_str(), true, "written"));
+        assert_eq!(build_at(&["--release-id", "auto"], 1_700_000_000_000).unwrap(), (id.clone(), true, "unchanged".to_string()));
+        assert_eq!(build_at(&["--source-date-epoch", "0"], 1_700_000_000_000).unwrap().0, format!("19700101-{}", &root[..12]));
+
+        fs::write(dir.join("bard"), b"bard v2").unwrap();
+        let (changed, _, _) = build_at(&[], 1_700_000_000_000).unwrap();
+        assert!(changed.starts_with("20231114-") && changed != id);
+
+        // An explicit id is used as given, and reusing it for other files is refused.
+        assert_eq!(build_at(&["--release-id", "hotfix-7"], 1_700_000_000_000).unwrap(), ("hotfix-7".to_string(), false, "written".to_string()));
+        fs::write(dir.join("bard"), b"bard v3").unwrap();
+        let err = build_at(&["--release-id", "hotfix-7"], 1_700_000_000_000).unwrap_err();
+        assert!(err.to_string().contains("already exists with different binaries"), "{err}");
+        assert_eq!(utc_date(951_782_400), "20000229");
+    }
+
+
+    #[test]
+    fn waivers_downgrade_known_mismatches_until_they_expire() {
+        let base = temp_dir("waivers");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("bard", b"bard v1")]);
+        let manifest = base.join("manifest.txt");
+        fs::write(&manifest, render_manifest(&build(&dir))).unwrap();
+        fs::write(dir.join("squire"), b"squire patched").unwrap();
+        fs::write(dir.join("bard"), b"bard patched").unwrap();
+        let patched = hash_bytes(b"squire patched");
+        let waivers = base.join("waivers.txt");
+        fs::write(&waivers, format!("name=squire expected_hash={patched} expires=1700000100\nname=bard expected_hash={} expires=1700000100\n", hash_bytes(b"bard other"))).unwrap();
+
+        let out = base.join("verify.json");
+        let verify_at = |now_unix: u128| {
+            let words = ["verify", "--manifest", manifest.to_str().unwrap(), "--bins-dir", dir.to_str().unwrap(), "--waivers", waivers.to_str().unwrap(), "--output", out.to_str().unwrap()];
+            let outcome = run_at(Mode::Yellow, &words, &runtime::MapEnv::new(), now_unix * 1000).unwrap();
+            let statuses: Vec<String> = document(&out)
+                .get("waivers")
+                .and_then(|list| list.as_array())
+                .unwrap()
+                .iter()
+                .map(|waiver| format!("{}:{}", waiver.get("name").and_then(|v| v.as_str()).unwrap(), waiver.get("status").and_then(|v| v.as_str()).unwrap()))
+                .collect();
+            (outcome, results(&out), statuses)
+        };
+
+        // bard changed into something other than what its waiver accepts.
+        let (outcome, results, waived) = verify_at(1_700_000_099);
+        assert_eq!(outcome, CliOutcome::VerificationFailed);
+        assert_eq!(results, ["bard:mismatch", "squire:waived"]);
+        assert_eq!(waived, ["squire:applied", "bard:unused"]);
+
+        fs::write(dir.join("bard"), b"bard v1").unwrap();
+        let (outcome, results, _) = verify_at(1_700_000_099);
+        assert_eq!((outcome, results), (CliOutcome::Success, vec!["bard:match".to_string(), "squire:waived".to_string()]));
+
+        // At the expiry time the waiver stops applying and the mismatch fails the run again.
+        let (outcome, results, waived) = verify_at(1_700_000_100);
+        assert_eq!((outcome, results), (CliOutcome::VerificationFailed, vec!["bard:match".to_string(), "squire:waiver-expired".to_string()]));
+        assert_eq!(waived, ["squire:expired", "bard:unused"]);
+    }
+
+
+    #[test]
+    fn same_named_files_in_different_folders_verify_apart() {
+        let base = temp_dir("duplicate-names");
+        let dir = bins(&base, &[("squire", b"top"), ("tools/squire", b"tools"), ("bard", b"bard")]);
+        let manifest = build(&dir);
+        assert_eq!(manifest.warnings, ["2 entries share the name squire: squire, tools/squire"]);
+        let path = base.join("manifest.txt");
+        fs::write(&path, render_manifest(&manifest)).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains("warning=2 entries share the name squire"));
+        assert!(text.contains("squire|tools/squire|") && text.contains("|rel=tools/squire"));
+
+        fs::write(dir.join("tools/squire"), b"tools changed").unwrap();
+        let loaded = load_manifest(&path).unwrap();
+        assert_eq!(loaded.warnings, manifest.warnings);
+        let statuses: Vec<String> = verify_bins(&dir, &loaded, ModeCheck::Off, false).unwrap().iter().map(|check| format!("{}:{}", check.rel_path, check.status())).collect();
+        assert_eq!(statuses, ["bard:match", "squire:match", "tools/squire:mismatch"]);
+    }
+
+    #[test]
+    fn manifests_keyed_by_name_still_verify() {
+        let base = temp_dir("legacy-names");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("bard", b"bard v1")]);
+        // Written before `rel=`, warnings, and modes existed: `name|path|hash|size` only.
+        let legacy = format!(
+            "release_id=old\nmode=blue\nentries:\nbard|bard|{}|7\nsquire|squire|{}|9\nsignature_note=placeholder\n",
+            hash_bytes(b"bard v1"),
+            hash_bytes(b"squire v1")
+        );
+        let path = base.join("manifest.txt");
+        fs::write(&path, legacy).unwrap();
+        let loaded = load_manifest(&path).unwrap();
+        assert!(loaded.entries.iter().all(|entry| entry.rel_path == entry.name && entry.mode.is_none()));
+        assert!(loaded.warnings.is_empty());
+        let report = verify_bins(&dir, &loaded, ModeCheck::Full, false).unwrap();
+        assert_eq!(report.iter().map(|check| check.status()).collect::<Vec<_>>(), ["match", "match"]);
+    }
+
+
+    #[test]
+    fn report_windows_are_measured_from_the_injected_clock() {
+        let base = temp_dir("report");
+        let log = base.join("cycles.log");
+        let now = 1_700_000_000u64;
+        let text = format!(
+            "{}\n{}\n",
+            history::cycle_line(now - 7_200, "r1", &[]),
+            history::cycle_line(now - 600, "r1", &[])
+        );
+        fs::write(&log, text).unwrap();
+        let out = base.join("report.json");
+        let words = ["report", "--log-file", log.to_str().unwrap(), "--since", "1", "--format", "json", "--output", out.to_str().unwrap()];
+        assert_eq!(run_at(Mode::Yellow, &words, &runtime::MapEnv::new(), u128::from(now) * 1000).unwrap(), CliOutcome::Success);
+        let document = document(&out);
+        assert_eq!(document.get("since_unix").and_then(|v| v.as_f64()), Some((now - 3_600) as f64));
+        assert_eq!(document.get("cycles").and_then(|v| v.as_f64()), Some(1.0));
+    }
+
+    #[test]
+    fn build_documents_carry_the_policy_decision() {
+        let base = temp_dir("policy");
+        let dir = bins(&base, &[("squire", b"v1")]);
+        let (out, releases) = (base.join("build.json"), base.join("releases"));
+        let words = ["build", "--bins-dir", dir.to_str().unwrap(), "--releases-dir", releases.to_str().unwrap(), "--output", out.to_str().unwrap()];
+
+        assert_eq!(run(Mode::Red, &words).unwrap(), CliOutcome::PolicyRefused);
+        assert_eq!(CliOutcome::PolicyRefused.code(), 3);
+        let refused = document(&out);
+        assert_eq!(refused.get("policy").and_then(|policy| policy.get("decision")).and_then(|decision| decision.as_str()), Some("refused"));
+        assert!(!releases.exists(), "nothing was built");
+
+        for mode in [Mode::Yellow, Mode::Blue] {
+            assert_eq!(run(mode, &words).unwrap(), CliOutcome::Success);
+            let policy = document(&out).get("policy").cloned().unwrap();
+            assert_eq!(policy.get("mode").and_then(|value| value.as_str()), Some(mode.as_str()));
+            assert_eq!(policy.get("decision").and_then(|value| value.as_str()), Some("allowed"));
+        }
+    }
+
+    #[test]
+    fn status_exit_codes_follow_the_worst_piece() {
+        let base = temp_dir("status-cli");
+        let dir = bins(&base, &[("squire", b"v1")]);
+        let releases = base.join("releases");
+        fs::create_dir_all(&releases).unwrap();
+        let out = base.join("status.json");
+        let words = ["status", "--releases-dir", releases.to_str().unwrap(), "--output", out.to_str().unwrap()];
+        assert_eq!(run(Mode::Yellow, &words).unwrap(), CliOutcome::Warning, "no release yet");
+        assert_eq!(document(&out).get("overall").and_then(|overall| overall.as_str()), Some("warning"));
+
+        let manifest = persisted(&base, &dir);
+        fs::write(manifest.with_file_name("manifest.txt.sig"), "signed").unwrap();
+        assert_eq!(run(Mode::Yellow, &words).unwrap(), CliOutcome::Success);
+        let state = base.join("daemon.json");
+        fs::write(&state, r#"{"release_id":"r1","results":["squire:mismatch"]}"#).unwrap();
+        let mut with_state = words.to_vec();
+        with_state.extend(["--state-file", state.to_str().unwrap()]);
+        assert_eq!(run(Mode::Yellow, &with_state).unwrap(), CliOutcome::VerificationFailed);
+    }
+
+    #[test]
+    fn manifests_with_escaping_or_absolute_paths_are_refused_at_load() {
+        let base = temp_dir("safe-paths");
+        let outside = base.join("outside");
+        fs::write(&outside, b"secret").unwrap();
+        let hash = hash_file(&outside, &[]).unwrap().hash;
+        let manifest = base.join("manifest.txt");
+        let write = |path: &str, extra: &str| fs::write(&manifest, format!("release_id=r1\nmode=blue\nentries:\nsquire|{path}|{hash}|6{extra}\n")).unwrap();
+        let problem = |trust: bool| match load_manifest_with(&manifest, trust) {
+            Err(SentryError::UnsafePath { entry, path, problem }) => Some((entry, path, problem)),
+            Err(other) => panic!("unexpected {other}"),
+            Ok(_) => None,
+        };
+
+        for (path, expected) in [("../../etc/shadow", safe_path::PathProblem::Escapes), ("..\\..\\etc\\shadow", safe_path::PathProblem::Escapes), ("/root/.ssh/id_rsa", safe_path::PathProblem::Absolute)] {
+            write(path, "");
+            assert_eq!(problem(false), Some(("squire".to_string(), path.to_string(), expected)));
+        }
+        let err = load_manifest(&manifest).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_MANIFEST_UNUSABLE);
+        assert!(err.to_string().contains("names \"/root/.ssh/id_rsa\", which is absolute") && err.to_string().contains("--trust-absolute-paths"), "{err}");
+        // Trusting absolute paths never lets `..` through.
+        write("../outside", "");
+        assert_eq!(problem(true).map(|found| found.2), Some(safe_path::PathProblem::Escapes));
+
+        write("./sub/./squire", "");
+        assert_eq!(load_manifest(&manifest).unwrap().entries[0].path, "sub/squire");
+        // The key for `.sig` files must stay relative even in a trusted manifest.
+        write("squire", "|rel=../squire");
+        assert_eq!(problem(true), Some(("squire".to_string(), "rel=../squire".to_string(), safe_path::PathProblem::Escapes)));
+
+        // A trusted absolute path is read where it points.
+        write(outside.to_str().unwrap(), "");
+        assert!(problem(false).is_some());
+        let bins_dir = base.join("bins");
+        fs::create_dir_all(&bins_dir).unwrap();
+        let (m, b) = (manifest.to_str().unwrap(), bins_dir.to_str().unwrap());
+        assert_eq!(run(Mode::Yellow, &["verify", "--manifest", m, "--bins-dir", b, "--check-mode", "off"]).unwrap_err().exit_code(), error::EXIT_MANIFEST_UNUSABLE);
+        assert_eq!(run(Mode::Yellow, &["verify", "--manifest", m, "--bins-dir", b, "--check-mode", "off", "--trust-absolute-paths"]).unwrap(), CliOutcome::Success);
+    }
+
+    #[test]
+    fn cached_verification_skips_unchanged_files_until_a_full_pass() {
+        let base = temp_dir("verify-cache");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("tools/bard", b"bard v1")]);
+        let manifest = build(&dir);
+        // The cache only remembers files that settled a while ago.
+        let started = std::time::SystemTime::now();
+        let age = |rel: &str, secs: u64| {
+            let file = fs::File::options().write(true).open(dir.join(rel)).unwrap();
+            file.set_modified(started - std::time::Duration::from_secs(secs)).unwrap();
+        };
+        age("squire", 600);
+        age("tools/bard", 600);
+        let mut cache = VerifyCache::default();
+        let from_cache = |report: &[BinCheck]| report.iter().filter(|check| check.from_cache).count();
+
+        let first = verify_bins_cached(&dir, &manifest, ModeCheck::Off, false, &mut cache, false).unwrap();
+        assert_eq!((from_cache(&first), cache.stats().hashed, cache.len()), (0, 2, 2));
+        let second = verify_bins_cached(&dir, &manifest, ModeCheck::Off, false, &mut cache, false).unwrap();
+        assert_eq!((from_cache(&second), cache.stats().hits, cache.stats().hashed), (2, 2, 0));
+        assert!(second.iter().all(BinCheck::matched));
+
+        // A changed modification time means reading the file again.
+        age("squire", 300);
+        let third = verify_bins_cached(&dir, &manifest, ModeCheck::Off, false, &mut cache, false).unwrap();
+        assert_eq!((cache.stats().hits, cache.stats().hashed), (1, 1));
+        assert!(!third.iter().find(|check| check.rel_path == "squire").unwrap().from_cache);
+
+        // Same size, old time put back: the cache is fooled until the full pass.
+        fs::write(dir.join("tools/bard"), b"bard v2").unwrap();
+        age("tools/bard", 600);
+        let fooled = verify_bins_cached(&dir, &manifest, ModeCheck::Off, false, &mut cache, false).unwrap();
+        assert!(fooled.iter().all(BinCheck::matched));
+        let full = verify_bins_cached(&dir, &manifest, ModeCheck::Off, false, &mut cache, true).unwrap();
+        assert_eq!((cache.stats().hits, cache.stats().hashed, cache.stats().full_rehash), (0, 2, true));
+        let failed: Vec<&str> = full.iter().filter(|check| !check.matched()).map(|check| check.rel_path.as_str()).collect();
+        assert_eq!(failed, ["tools/bard"]);
+        assert!(verify_bins_cached(&dir, &manifest, ModeCheck::Off, false, &mut cache, false).unwrap().iter().any(|check| !check.matched()), "the cache now holds the new hash");
+    }
+
+    #[test]
+    fn inspect_fails_on_warnings_only_under_strict() {
+        let base = temp_dir("inspect-strict");
+        let dir = bins(&base, &[("squire", b"same"), ("squire-old", b"same")]);
+        let manifest = persisted(&base, &dir);
+        let out = base.join("inspect.json");
+        let (m, o) = (manifest.to_str().unwrap(), out.to_str().unwrap());
+
+        assert_eq!(run(Mode::Red, &["inspect", "--manifest", m, "--output", o]).unwrap(), CliOutcome::Success);
+        let found = document(&out);
+        assert_eq!(found.get("strict").and_then(json::JsonValue::as_bool), Some(false));
+        assert_eq!(found.get("warnings").and_then(json::JsonValue::as_array).map(<[json::JsonValue]>::len), Some(1));
+
+        let err = run(Mode::Red, &["inspect", "--manifest", m, "--output", o, "--strict"]).unwrap_err();
+        assert_eq!(err.exit_code(), 1);
+        assert!(err.to_string().contains("1 manifest warning(s) and --strict was given"), "{err}");
+        assert_eq!(document(&out).get("strict").and_then(json::JsonValue::as_bool), Some(true), "the report is still written");
+
+        let clean = persisted(&temp_dir("inspect-clean"), &bins(&temp_dir("inspect-clean-bins"), &[("squire", b"v1")]));
+        assert_eq!(run(Mode::Red, &["inspect", "--manifest", clean.to_str().unwrap(), "--strict", "--output", o]).unwrap(), CliOutcome::Success);
+    }
+
+    #[test]
+    fn build_records_file_types_and_verify_reports_a_type_change() {
+        let base = temp_dir("filetype");
+        let mut program = b"\x7FELF\x02\x01\x01\x00".to_vec();
+        program.resize(5_000, 0);
+        let dir = bins(&base, &[("squire", &program), ("hub", b"#!/bin/sh\nexec hub\n")]);
+        let folder = cli_build(&base, &dir, &["--release-id", "r1", "--expect-executables"], &runtime::MapEnv::new());
+        let manifest = folder.join("manifest.txt");
+        let text = fs::read_to_string(&manifest).unwrap();
+        assert!(text.contains("|filetype=elf") && text.contains("|filetype=script"), "{text}");
+        assert!(text.contains("warning=hub is script, not a recognized executable"), "{text}");
+        assert!(text.contains("warning=hub is only 19 bytes, under the 4096 byte minimum"), "{text}");
+        assert!(!text.contains("warning=squire"));
+
+        let out = base.join("verify.json");
+        let verify = || {
+            let words = ["verify", "--manifest", manifest.to_str().unwrap(), "--bins-dir", dir.to_str().unwrap(), "--output", out.to_str().unwrap()];
+            run(Mode::Yellow, &words)
+        };
+        assert_eq!(verify().unwrap(), CliOutcome::Success);
+
+        // Same kind of file, other contents: a plain mismatch.
+        program[4_000] = 1;
+        fs::write(dir.join("squire"), &program).unwrap();
+        assert_eq!(verify().unwrap(), CliOutcome::VerificationFailed);
+        assert_eq!(results(&out), ["hub:match", "squire:mismatch"]);
+
+        fs::write(dir.join("squire"), b"#!/bin/sh\necho not really squire\n").unwrap();
+        assert_eq!(verify().unwrap(), CliOutcome::VerificationFailed);
+        assert_eq!(results(&out), ["hub:match", "squire:type-changed"]);
+        let loaded = load_manifest(&manifest).unwrap();
+        let check = verify_bins(&dir, &loaded, ModeCheck::Off, false).unwrap().into_iter().find(|check| check.rel_path == "squire").unwrap();
+        assert!(check.type_changed() && !check.hash_matched());
+        assert_eq!((check.expected_filetype, check.observed_filetype), (Some(FileType::Elf), Some(FileType::Script)));
+    }
+
+    #[test]
+    fn cross_check_reports_clock_skew_as_a_warning_not_a_failure() {
+        let base = temp_dir("cross-check-skew");
+        let now = 1_700_000_000_000u128;
+        let document = |name: &str, generated: u128, hash: &str| {
+            let path = base.join(name);
+            fs::write(&path, format!("{{\"format_version\":{STATUS_FORMAT_VERSION},\"release_id\":\"r1\",\"generated_at_unix_ms\":{generated},\"observed\":[{{\"rel_path\":\"squire\",\"hash\":\"{hash}\"}}]}}")).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let mine = document("mine.json", now, "aa");
+        let out = base.join("out.json");
+        let o = out.to_str().unwrap();
+        let check = |theirs: &str, extra: &[&str]| {
+            let mut words = vec!["cross-check", "--mine", &mine, "--theirs", theirs, "--output", o];
+            words.extend_from_slice(extra);
+            let outcome = run_at(Mode::Red, &words, &runtime::MapEnv::new(), now).unwrap();
+            (outcome, json::parse(&fs::read_to_string(&out).unwrap()).unwrap())
+        };
+
+        let behind = document("behind.json", now - 1_200_000, "aa");
+        let (outcome, report) = check(&behind, &[]);
+        assert_eq!(outcome, CliOutcome::Success, "skew alone must not fail the check");
+        assert_eq!(report.get("status").and_then(json::JsonValue::as_str), Some("agree"));
+        let skew = report.get("clock_skew").unwrap();
+        assert_eq!((skew.get("skew_ms").and_then(json::JsonValue::as_f64), skew.get("status").and_then(json::JsonValue::as_str)), (Some(-1_200_000.0), Some("skewed")));
+        let warnings = report.get("warnings").and_then(json::JsonValue::as_array).unwrap();
+        assert!(warnings[0].as_str().unwrap().contains("1200s behind ours"), "{warnings:?}");
+
+        // Twenty minutes is within a 1200-second allowance, and one second over it is not.
+        let (_, report) = check(&behind, &["--max-clock-skew-secs", "1200"]);
+        assert_eq!(report.get("warnings").and_then(json::JsonValue::as_array).map(<[_]>::len), Some(0));
+        let over = document("over.json", now - 1_201_000, "aa");
+        let (_, report) = check(&over, &["--max-clock-skew-secs", "1200"]);
+        assert_eq!(report.get("warnings").and_then(json::JsonValue::as_array).map(<[_]>::len), Some(1));
+
+        // A real conflict still fails, skew or not.
+        let conflicting = document("conflict.json", now - 1_200_000, "bb");
+        assert_eq!(check(&conflicting, &[]).0, CliOutcome::VerificationFailed);
+        assert!(run_at(Mode::Red, &["cross-check", "--mine", &mine, "--theirs", &behind, "--max-clock-skew-secs", "soon"], &runtime::MapEnv::new(), now).is_err());
+    }
+
+    #[test]
+    fn push_release_installs_a_verified_release_on_the_receiver() {
+        let base = temp_dir("push-release");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("tools/bard", b"bard v1")]);
+        let folder = cli_build(&base, &dir, &["--release-id", "r1", "--recursive", "--bundle"], &runtime::MapEnv::new());
+        let yellow = base.join("yellow");
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let receiver = {
+            let (address, yellow, received) = (address.clone(), yellow.clone(), base.join("received.json"));
+            std::thread::spawn(move || {
+                let words = ["receive-release", "--listen", &address, "--releases-dir", yellow.to_str().unwrap(), "--once", "--output", received.to_str().unwrap()];
+                run(Mode::Yellow, &words).unwrap()
+            })
+        };
+        // A probe that connects and hangs up is an interrupted transfer, which the receiver skips.
+        while std::net::TcpStream::connect(&address).is_err() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let pushed = base.join("pushed.json");
+        let words = ["push-release", "--release-dir", folder.to_str().unwrap(), "--to", &address, "--chunk-size", "16", "--output", pushed.to_str().unwrap()];
+        assert_eq!(run(Mode::Blue, &words).unwrap(), CliOutcome::Success);
+        assert_eq!(receiver.join().unwrap(), CliOutcome::Success);
+        let push = document(&pushed).get("push").cloned().unwrap();
+        assert_eq!(push.get("status").and_then(|status| status.as_str()), Some("installed"));
+        let release = document(&base.join("received.json")).get("release").cloned().unwrap();
+        assert_eq!(release.get("status").and_then(|status| status.as_str()), Some("installed"));
+
+        let installed = yellow.join("omega-r1");
+        assert_eq!(fs::read(installed.join("manifest.txt")).unwrap(), fs::read(folder.join("manifest.txt")).unwrap());
+        assert!(installed.join("omega-r1.tar").is_file());
+        let (manifest, bin, out) = (installed.join("manifest.txt"), installed.join("bin"), base.join("verify.json"));
+        let words = ["verify", "--manifest", manifest.to_str().unwrap(), "--bins-dir", bin.to_str().unwrap(), "--output", out.to_str().unwrap()];
+        assert_eq!(run(Mode::Yellow, &words).unwrap(), CliOutcome::Success);
+        assert_eq!(results(&out), ["squire:match", "tools/bard:match"]);
+    }
+
+    #[test]
+    fn each_library_function_fails_with_its_own_variant() {
+        let base = temp_dir("error-variants");
+        let nowhere = base.join("nowhere");
+        let built = build_manifest(Mode::Blue, &nowhere, "r1".to_string(), provenance::Provenance::collect(None, None), false, &[], None);
+        assert!(matches!(built, Err(SentryError::BinsDirMissing(ref path)) if *path == nowhere));
+
+        let dir = bins(&base, &[("squire", b"squire v1"), ("bard", b"bard v1")]);
+        let manifest = build(&dir);
+        assert!(matches!(verify_bins(&nowhere, &manifest, ModeCheck::Off, false), Err(SentryError::BinsDirMissing(_))));
+        fs::remove_file(dir.join("bard")).unwrap();
+        match verify_bins(&dir, &manifest, ModeCheck::Off, false) {
+            Err(SentryError::EntryUnreadable { entry, path, source }) => {
+                assert_eq!((entry.as_str(), path, source.kind()), ("bard", dir.join("bard"), std::io::ErrorKind::NotFound));
+            }
+            other => panic!("expected an unreadable entry, got {other:?}"),
+        }
+
+        let missing = base.join("missing.txt");
+        assert!(matches!(load_manifest(&missing), Err(SentryError::ManifestRead { ref path, .. }) if *path == missing));
+        let path = base.join("manifest.txt");
+        let damaged = "release_id=r1\nmode=blue\nentries:\nsquire|squire|aa|1\nbard|bard|bb|many\n";
+        fs::write(&path, damaged).unwrap();
+        let err = load_manifest(&path).unwrap_err();
+        assert!(matches!(err, SentryError::ManifestParse { line: 5, .. }), "{err:?}");
+        assert_eq!(err.to_string(), format!("Manifest {path:?} line 5: entry has an invalid size \"many\": bard|bard|bb|many"));
+        fs::write(&path, "mode=blue\nentries:\n").unwrap();
+        assert!(matches!(load_manifest(&path), Err(SentryError::ManifestParse { line: 0, .. })));
+
+        let releases = base.join("releases-is-a-file");
+        fs::write(&releases, b"").unwrap();
+        assert!(matches!(persist_manifest(&manifest, &releases), Err(SentryError::Io { .. })));
+        let empty = OmegaManifest { entries: Vec::new(), ..manifest.clone() };
+        assert!(matches!(persist_manifest(&empty, &base.join("releases")), Err(SentryError::Verification(_))));
+    }
+
+    #[test]
+    fn the_cli_maps_variants_to_exit_codes() {
+        let base = temp_dir("error-exit-codes");
+        let dir = bins(&base, &[("squire", b"squire v1")]);
+        let manifest = persisted(&base, &dir);
+        let (m, b) = (manifest.to_str().unwrap(), dir.to_str().unwrap());
+        let nowhere = base.join("nowhere");
+        let code = |words: &[&str]| run(Mode::Yellow, words).unwrap_err().exit_code();
+        assert_eq!(code(&["verify", "--manifest", m, "--bins-dir", nowhere.to_str().unwrap()]), error::EXIT_BINS_DIR_MISSING);
+        assert_eq!(code(&["verify", "--manifest", nowhere.to_str().unwrap(), "--bins-dir", b]), error::EXIT_MANIFEST_UNUSABLE);
+        fs::remove_file(dir.join("squire")).unwrap();
+        let err = run(Mode::Yellow, &["verify", "--manifest", m, "--bins-dir", b]).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_ENTRY_UNREADABLE);
+        assert!(err.chain().starts_with("Unable to read entry \"squire\" at ") && err.chain().contains(": "), "{}", err.chain());
+        assert_eq!(code(&["verify", "--bins-dir", b]), 1, "a missing flag is a plain message");
+    }
+
+    #[test]
+    fn build_refuses_a_full_disk_unless_told_to_skip_the_check() {
+        let base = temp_dir("build-space");
+        let dir = bins(&base, &[("squire", b"squire v1")]);
+        let releases = base.join("releases");
+        let build = |space: u64, extra: &[&str]| {
+            let mut words = vec!["build", "--bins-dir", dir.to_str().unwrap(), "--releases-dir", releases.to_str().unwrap(), "--release-id", "r1", "--quiet"];
+            words.extend_from_slice(extra);
+            let clock = runtime::ManualClock::new(1_700_000_000_000);
+            let rt = Runtime { clock: &clock, sleeper: &clock, env: &runtime::MapEnv::new() };
+            run_cli_with_space(Mode::Blue, &args(&words), rt, &disk_space::FixedSpace(space))
+        };
+
+        let err = build(1_000, &[]).unwrap_err();
+        assert!(matches!(err, SentryError::InsufficientSpace { required: disk_space::MANIFEST_ONLY_ESTIMATE, available: 1_000, .. }), "{err:?}");
+        assert_eq!(err.exit_code(), error::EXIT_INSUFFICIENT_SPACE);
+        assert!(!releases.exists(), "nothing is written before the check");
+        let err = build(disk_space::MANIFEST_ONLY_ESTIMATE, &["--bundle"]).unwrap_err();
+        assert!(matches!(err, SentryError::InsufficientSpace { required, .. } if required == disk_space::MANIFEST_ONLY_ESTIMATE + 9));
+
+        assert_eq!(build(1_000, &["--skip-space-check"]).unwrap(), CliOutcome::Success);
+        assert!(releases.join("omega-r1").join("manifest.txt").is_file());
+    }
+
+    #[test]
+    fn a_partial_release_from_a_crashed_build_is_cleared_before_the_next() {
+        let base = temp_dir("build-partial");
+        let dir = bins(&base, &[("squire", b"squire v1")]);
+        let releases = base.join("releases");
+        let crashed = releases.join(".omega-r1.partial-99999");
+        fs::create_dir_all(&crashed).unwrap();
+        fs::write(crashed.join("manifest.txt"), "half a manifest").unwrap();
+        let other = releases.join(".omega-r2.partial-99999");
+        fs::create_dir_all(&other).unwrap();
+        // A release folder with no manifest is what a build from before staging left behind.
+        fs::create_dir_all(releases.join("omega-r1")).unwrap();
+
+        let (folder, already_present) = persist_manifest(&build(&dir), &releases).unwrap();
+        assert!(!already_present);
+        assert!(!crashed.exists() && other.exists(), "only this release's leftovers are removed");
+        assert!(folder.join("manifest.txt").is_file());
+        let names: Vec<String> = fs::read_dir(&releases).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        assert!(!names.iter().any(|name| name.starts_with(".omega-r1.partial-")), "{names:?}");
+    }
+
+    /// A sleeper for the daemon that ends the loop: after `passes` sleeps it removes `bins_dir`,
+    /// so the next pass fails with `BinsDirMissing`.
+    struct StopAfter {
+        clock: runtime::ManualClock,
+        passes: std::cell::Cell<u32>,
+        bins_dir: PathBuf,
+    }
+
+    impl runtime::Sleeper for StopAfter {
+        fn sleep(&self, duration: Duration) {
+            self.clock.advance(duration);
+            self.passes.set(self.passes.get().saturating_sub(1));
+            if self.passes.get() == 0 {
+                let _ = fs::remove_dir_all(&self.bins_dir);
+            }
+        }
+    }
+
+    #[test]
+    fn the_daemon_warns_low_disk_without_failing_the_pass() {
+        let base = temp_dir("daemon-low-disk");
+        let dir = bins(&base, &[("squire", b"squire v1")]);
+        let manifest = persisted(&base, &dir);
+        let out = base.join("state").join("daemon.json");
+        fs::create_dir_all(out.parent().unwrap()).unwrap();
+        let daemon = |space: u64| {
+            let stop = StopAfter { clock: runtime::ManualClock::new(1_700_000_000_000), passes: std::cell::Cell::new(1), bins_dir: dir.clone() };
+            let rt = Runtime { clock: &stop.clock, sleeper: &stop, env: &runtime::MapEnv::new() };
+            let words = ["daemon", "--manifest", manifest.to_str().unwrap(), "--bins-dir", dir.to_str().unwrap(), "--min-free-mb", "256", "--output", out.to_str().unwrap()];
+            let err = run_cli_with_space(Mode::Yellow, &args(&words), rt, &disk_space::FixedSpace(space)).unwrap_err();
+            assert!(matches!(err, SentryError::BinsDirMissing(_)), "{err:?}");
+            document(&out)
+        };
+
+        let low = daemon(10 * 1024 * 1024);
+        let warnings = low.get("warnings").and_then(json::JsonValue::as_array).unwrap();
+        assert_eq!(warnings.len(), 1);
+        let warning = warnings[0].as_str().unwrap();
+        assert!(warning.starts_with("low-disk: ") && warning.contains("has 10 MB free") && warning.ends_with("below --min-free-mb 256"), "{warning}");
+        assert_eq!(results(&out), ["squire:match"], "the pass itself is clean");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("squire"), b"squire v1").unwrap();
+        let roomy = daemon(1 << 40);
+        assert!(roomy.get("warnings").is_none(), "no warnings, no field");
+    }
+
+    #[test]
+    fn verify_explain_tells_appended_truncated_modified_and_replaced_apart() {
+        let base = temp_dir("explain");
+        let elf = |fill: u8| {
+            let mut bytes = b"\x7fELF".to_vec();
+            bytes.resize(10_000, fill);
+            bytes
+        };
+        let files = [("appended", elf(1)), ("truncated", elf(2)), ("modified", elf(3)), ("replaced", elf(4)), ("same", elf(5))];
+        let named: Vec<(&str, &[u8])> = files.iter().map(|(name, bytes)| (*name, bytes.as_slice())).collect();
+        let dir = bins(&base, &named);
+        let manifest = persisted(&base, &dir);
+
+        let mut appended = elf(1);
+        appended.extend_from_slice(b"payload");
+        fs::write(dir.join("appended"), appended).unwrap();
+        fs::write(dir.join("truncated"), &elf(2)[..6_000]).unwrap();
+        let mut modified = elf(3);
+        modified[5_000] ^= 0xff;
+        fs::write(dir.join("modified"), modified).unwrap();
+        fs::write(dir.join("replaced"), b"#!/bin/sh\necho not the binary\n").unwrap();
+
+        let out = base.join("explain.json");
+        let (m, b, o) = (manifest.to_str().unwrap(), dir.to_str().unwrap(), out.to_str().unwrap());
+        assert_eq!(run(Mode::Yellow, &["verify", "--manifest", m, "--bins-dir", b, "--explain", "--output", o]).unwrap(), CliOutcome::VerificationFailed);
+        let found = document(&out);
+        let mut explained: Vec<(String, String)> = found
+            .get("explain")
+            .and_then(json::JsonValue::as_array)
+            .unwrap()
+            .iter()
+            .map(|item| {
+                let field = |name: &str| item.get(name).and_then(json::JsonValue::as_str).unwrap().to_string();
+                (field("rel_path"), field("kind"))
+            })
+            .collect();
+        let expected: Vec<(String, String)> = ["appended", "modified", "replaced", "truncated"].iter().map(|kind| (kind.to_string(), kind.to_string())).collect();
+        explained.sort();
+        assert_eq!(explained, expected, "only mismatching entries are explained");
+
+        let item = |name: &str| found.get("explain").and_then(json::JsonValue::as_array).unwrap().iter().find(|item| item.get("rel_path").and_then(json::JsonValue::as_str) == Some(name)).cloned().unwrap();
+        let number = |item: &json::JsonValue, key: &str| item.get(key).and_then(json::JsonValue::as_f64);
+        let grown = item("appended");
+        assert_eq!((number(&grown, "size_delta"), number(&grown, "first_differing_block_offset")), (Some(7.0), Some(8_192.0)));
+        assert_eq!(grown.get("prefix_matches").and_then(json::JsonValue::as_bool), Some(true));
+        assert_eq!(number(&item("truncated"), "first_differing_block_offset"), Some(4_096.0));
+        assert_eq!(item("replaced").get("type_changed").and_then(json::JsonValue::as_bool), Some(true));
+
+        let plain = base.join("plain.json");
+        run(Mode::Yellow, &["verify", "--manifest", m, "--bins-dir", b, "--output", plain.to_str().unwrap()]).unwrap();
+        assert!(document(&plain).get("explain").is_none(), "only with --explain");
+    }
+
+    #[test]
+    fn manifests_carry_versions_and_refuse_readers_that_are_too_old() {
+        let base = temp_dir("versions");
+        let dir = bins(&base, &[("squire", b"squire v1")]);
+        let path = persisted(&base, &dir);
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with(&format!("tool_version={}\nmin_reader_version={}\n", version::TOOL_VERSION, version::MIN_READER_VERSION)), "{text}");
+        assert_eq!(load_manifest(&path).unwrap().tool_version.as_deref(), Some(version::TOOL_VERSION));
+
+        // A reader as new as the requirement is fine; one version short is refused by name.
+        let current = version::Version::current();
+        fs::write(&path, text.replace(&format!("min_reader_version={}", version::MIN_READER_VERSION), &format!("min_reader_version={current}"))).unwrap();
+        assert!(load_manifest(&path).is_ok());
+        let newer = version::Version::new(current.major, current.minor + 1, 0);
+        fs::write(&path, text.replace(&format!("min_reader_version={}", version::MIN_READER_VERSION), &format!("min_reader_version={newer}\nentries_v9:"))).unwrap();
+        let err = load_manifest(&path).unwrap_err();
+        assert!(matches!(err, SentryError::ManifestTooNew { required, .. } if required == newer), "{err:?}");
+        assert_eq!(err.to_string(), format!("Manifest {path:?} requires sentry >= {newer}, this is {current}; upgrade Sentry on this host to read it"));
+
+        // Manifests from before the version lines are legacy and load as before.
+        let legacy: String = text.lines().filter(|line| !line.contains("_version=")).map(|line| format!("{line}\n")).collect();
+        fs::write(&path, legacy).unwrap();
+        let loaded = load_manifest(&path).unwrap();
+        assert_eq!(loaded.tool_version, None);
+        fs::write(&path, text.replace(&format!("min_reader_version={}", version::MIN_READER_VERSION), "min_reader_version=soon")).unwrap();
+        assert!(matches!(load_manifest(&path), Err(SentryError::ManifestParse { line: 2, .. })));
+
+        let out = base.join("verify.json");
+        fs::write(&path, &text).unwrap();
+        run(Mode::Yellow, &["verify", "--manifest", path.to_str().unwrap(), "--bins-dir", dir.to_str().unwrap(), "--output", out.to_str().unwrap()]).unwrap();
+        assert_eq!(document(&out).get("tool_version").and_then(json::JsonValue::as_str), Some(version::TOOL_VERSION));
+    }
+
+    /// A pipe that breaks part-way through.
+    struct BrokenPipe;
+
+    impl Read for BrokenPipe {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "artifact store went away"))
+        }
+    }
+
+    #[test]
+    fn a_manifest_piped_to_stdin_verifies_like_the_file() {
+        let base = temp_dir("stdin-manifest");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("bard", b"bard v1")]);
+        let path = persisted(&base, &dir);
+        let text = fs::read(&path).unwrap();
+
+        let piped = read_manifest_arg_from(Path::new(STDIN_MANIFEST), false, text.as_slice()).unwrap();
+        let from_file = read_manifest_arg_from(&path, false, BrokenPipe).unwrap();
+        assert_eq!(render_manifest(&piped), render_manifest(&from_file), "a file path never touches stdin");
+        let checks = verify_bins(&dir, &piped, ModeCheck::Off, false).unwrap();
+        assert!(checks.iter().all(BinCheck::matched));
+        fs::write(dir.join("bard"), b"bard v2").unwrap();
+        let checks = verify_bins(&dir, &piped, ModeCheck::Off, false).unwrap();
+        assert_eq!(checks.iter().filter(|check| !check.matched()).map(|check| check.rel_path.as_str()).collect::<Vec<_>>(), ["bard"]);
+
+        let err = read_manifest_arg_from(Path::new(STDIN_MANIFEST), false, BrokenPipe).unwrap_err();
+        assert!(matches!(err, SentryError::ManifestRead { ref path, .. } if *path == Path::new("<stdin>")), "{err:?}");
+        let err = read_manifest_arg_from(Path::new(STDIN_MANIFEST), false, &b"release_id=r1\nmode=teal\n"[..]).unwrap_err();
+        assert_eq!(err.to_string(), "Manifest \"<stdin>\" line 2: invalid mode \"teal\"");
+    }
+
+    #[test]
+    fn damaged_modes_and_sizes_are_parse_errors_naming_the_line() {
+        let parse_error = |text: &str| match load_manifest_from(text.as_bytes(), Path::new("m.txt"), false) {
+            Err(SentryError::ManifestParse { line, reason, .. }) => (line, reason),
+            other => panic!("expected a parse error for {text:?}, got {other:?}"),
+        };
+        assert_eq!(parse_error("release_id=r1\nmode=Blue!\nentries:\n"), (2, "invalid mode \"Blue!\"".to_string()));
+        assert_eq!(parse_error("release_id=r1\nmode=\n"), (2, "invalid mode \"\"".to_string()));
+        assert_eq!(parse_error("release_id=r1\nentries:\nsquire|squire|aa|-1\n"), (3, "entry has an invalid size \"-1\": squire|squire|aa|-1".to_string()));
+        assert_eq!(parse_error("release_id=r1\nentries:\nsquire|squire|aa|1|mode=9z\n"), (3, "entry has an invalid mode \"9z\": squire|squire|aa|1|mode=9z".to_string()));
+        assert_eq!(parse_error("release_id=r1\ncreated_at_unix=yesterday\n"), (2, "invalid created_at_unix \"yesterday\"".to_string()));
+
+        let manifest = load_manifest_from(&b"release_id=r1\nmode=red\nentries:\nsquire|squire|aa|12|mode=755\n"[..], Path::new("m.txt"), false).unwrap();
+        assert_eq!((manifest.mode, manifest.entries[0].size, manifest.entries[0].mode), (Mode::Red, 12, Some(0o755)));
+    }
+
+    #[test]
+    fn commands_that_need_the_manifest_file_refuse_stdin() {
+        let err = parse(&["daemon", "--manifest", "-", "--bins-dir", "b"]).err().unwrap();
+        assert!(err.starts_with("daemon cannot read --manifest - (stdin)"), "{err}");
+        let err = parse(&["verify", "--manifest", "-", "--bins-dir", "b", "--per-file-sigs"]).err().unwrap();
+        assert!(err.starts_with("--per-file-sigs reads <name>.sig files beside the manifest"), "{err}");
+        let Command::Verify { manifest_path, .. } = parse(&["verify", "--manifest", "-", "--bins-dir", "b"]).unwrap().command else { panic!("not a verify") };
+        assert_eq!(manifest_path, Path::new(STDIN_MANIFEST));
+        assert_eq!(run(Mode::Yellow, &["daemon", "--manifest", "-", "--bins-dir", "b"]).unwrap_err().exit_code(), 1);
+    }
+
+    #[test]
+    fn verify_checks_every_slot_and_fails_when_any_slot_does() {
+        let base = temp_dir("slots-verify");
+        let blue = bins(&base.join("blue"), &[("squire", b"squire v1"), ("bard", b"bard v1")]);
+        let canary = bins(&base.join("canary"), &[("squire", b"squire v1"), ("bard", b"bard v1")]);
+        let manifest = persisted(&base, &blue);
+        let out = base.join("verify.json");
+        let (m, o) = (manifest.to_str().unwrap(), out.to_str().unwrap());
+        let (blue_slot, canary_slot) = (format!("blue={}", blue.display()), format!("canary={}", canary.display()));
+        let verify = || run(Mode::Yellow, &["verify", "--manifest", m, "--bins-dir", &blue_slot, "--bins-dir", &canary_slot, "--output", o]).unwrap();
+
+        assert_eq!(verify(), CliOutcome::Success);
+        assert_eq!(document(&out).get("status").and_then(json::JsonValue::as_str), Some("ok"));
+
+        fs::write(canary.join("bard"), b"bard v1, patched").unwrap();
+        assert_eq!(verify(), CliOutcome::VerificationFailed);
+        assert_eq!(verify().code(), 2, "any failed slot fails the run");
+        let found = document(&out);
+        assert_eq!(found.get("status").and_then(json::JsonValue::as_str), Some("failed"));
+        let slot_results = |label: &str| -> Vec<&str> {
+            found.get("results").and_then(|results| results.get(label)).and_then(json::JsonValue::as_array).unwrap().iter().filter_map(json::JsonValue::as_str).collect()
+        };
+        assert_eq!(slot_results("blue"), ["bard:match", "squire:match"]);
+        assert_eq!(slot_results("canary"), ["bard:mismatch", "squire:match"]);
+        let slots: Vec<(Option<&str>, Option<&str>)> = found
+            .get("slots")
+            .and_then(json::JsonValue::as_array)
+            .unwrap()
+            .iter()
+            .map(|slot| (slot.get("label").and_then(json::JsonValue::as_str), slot.get("status").and_then(json::JsonValue::as_str)))
+            .collect();
+        assert_eq!(slots, [(Some("blue"), Some("ok")), (Some("canary"), Some("failed"))]);
+        assert!(found.get("observed").and_then(|observed| observed.get("canary")).is_some());
+        assert_eq!(fs::read_to_string(&out).unwrap().matches("\"results\":").count(), 1, "no leftover single-slot results");
+
+        // One unlabeled folder keeps the old layout.
+        assert_eq!(run(Mode::Yellow, &["verify", "--manifest", m, "--bins-dir", blue.to_str().unwrap(), "--output", o]).unwrap(), CliOutcome::Success);
+        assert_eq!(results(&out), ["bard:match", "squire:match"]);
+        assert!(document(&out).get("slots").is_none());
+    }
+
+    #[test]
+    fn the_daemon_alerts_for_the_tampered_slot_only() {
+        let base = temp_dir("slots-daemon");
+        let main = bins(&base.join("main"), &[("squire", b"squire v1")]);
+        let canary = bins(&base.join("canary"), &[("squire", b"squire v1")]);
+        let manifest = persisted(&base, &main);
+        fs::write(canary.join("squire"), b"squire v1, tampered").unwrap();
+        let alerts = base.join("alerts.jsonl");
+        let out = base.join("daemon.json");
+        // Two passes: the mismatch, then the recheck that confirms it. Then `main` goes away.
+        let stop = StopAfter { clock: runtime::ManualClock::new(1_700_000_000_000), passes: std::cell::Cell::new(2), bins_dir: main.clone() };
+        let rt = Runtime { clock: &stop.clock, sleeper: &stop, env: &runtime::MapEnv::new() };
+        let (main_slot, canary_slot) = (format!("main={}", main.display()), format!("canary={}", canary.display()));
+        let words = [
+            "daemon", "--manifest", manifest.to_str().unwrap(), "--bins-dir", &main_slot, "--bins-dir", &canary_slot,
+            "--alert-file", alerts.to_str().unwrap(), "--output", out.to_str().unwrap(),
+        ];
+        let err = run_cli_with_space(Mode::Red, &args(&words), rt, &disk_space::FixedSpace(u64::MAX)).unwrap_err();
+        assert!(matches!(err, SentryError::BinsDirMissing(ref dir) if *dir == main), "{err:?}");
+
+        let sent: Vec<json::JsonValue> = fs::read_to_string(&alerts).unwrap().lines().map(|line| json::parse(line).unwrap()).collect();
+        assert_eq!(sent.len(), 1, "only the canary alerts, and only once it is confirmed");
+        assert_eq!(sent[0].get("kind").and_then(json::JsonValue::as_str), Some("verification-failed"));
+        assert_eq!(sent[0].get("slot").and_then(json::JsonValue::as_str), Some("canary"));
+        let entries: Vec<&str> = sent[0].get("entries").and_then(json::JsonValue::as_array).unwrap().iter().filter_map(json::JsonValue::as_str).collect();
+        assert_eq!(entries, ["squire"]);
+        assert_eq!(document(&out).get("status").and_then(json::JsonValue::as_str), Some("failed"));
+    }
+
+    #[test]
+    fn owners_annotate_entries_and_required_signers_are_enforced() {
+        const SECURITY_KEY_HEX: &str = "5555555555555555555555555555555555555555555555555555555555555555";
+        let base = temp_dir("owners");
+        let dir = bins(&base, &[("sentry-blue", b"sentry v1"), ("squire", b"squire v1"), ("tools/hasher", b"hasher v1")]);
+        let security = owners::key_fingerprint(&sha256::from_hex(SECURITY_KEY_HEX).unwrap());
+        let owners_file = base.join("OWNERS");
+        fs::write(&owners_file, format!("*=release\ntools/**=platform\nsentry-*=security,{security}\n")).unwrap();
+        let env = runtime::MapEnv::new().with(SIGNING_KEY_ENV, SIGNING_KEY_HEX).with(EXTRA_SIGNING_KEYS_ENV, SECURITY_KEY_HEX);
+        let owners_arg = owners_file.to_str().unwrap();
+
+        // The required key has to be loaded to build at all.
+        let releases = base.join("releases");
+        let words = ["build", "--bins-dir", dir.to_str().unwrap(), "--releases-dir", releases.to_str().unwrap(), "--release-id", "r0", "--recursive", "--per-file-sigs", "--owners-file", owners_arg];
+        let only_release_key = runtime::MapEnv::new().with(SIGNING_KEY_ENV, SIGNING_KEY_HEX);
+        let err = run_at(Mode::Blue, &words, &only_release_key, 1_700_000_000_000).unwrap_err();
+        assert!(err.to_string().contains(&format!("sentry-blue must be signed by key {security}, which is not loaded")), "{err}");
+
+        let folder = cli_build(&base, &dir, &["--release-id", "r1", "--recursive", "--per-file-sigs", "--owners-file", owners_arg], &env);
+        let manifest_path = folder.join("manifest.txt");
+        let text = fs::read_to_string(&manifest_path).unwrap();
+        assert!(text.lines().any(|line| line.starts_with("sentry-blue|") && line.contains("|owner=security") && line.contains(&format!("|required_signer={security}"))), "{text}");
+        assert!(text.lines().any(|line| line.starts_with("hasher|") && line.contains("|owner=platform") && !line.contains("required_signer")), "{text}");
+        let manifest = load_manifest(&manifest_path).unwrap();
+        assert_eq!(render_manifest(&manifest), text, "owners survive a render and load round trip");
+        let owner_of = |rel: &str| manifest.entries.iter().find(|entry| entry.rel_path == rel).and_then(|entry| entry.owner.clone());
+        assert_eq!((owner_of("squire").as_deref(), owner_of("tools/hasher").as_deref()), (Some("release"), Some("platform")));
+        let security_key = sha256::from_hex(SECURITY_KEY_HEX).unwrap();
+        assert_eq!(fs::read_to_string(folder.join("sentry-blue.sig")).unwrap().trim(), sha256::to_hex(&sha256::hmac_sha256(&security_key, b"sentry v1")));
+
+        let out = base.join("verify.json");
+        let verify = |env: &runtime::MapEnv| {
+            let words = ["verify", "--manifest", manifest_path.to_str().unwrap(), "--bins-dir", dir.to_str().unwrap(), "--per-file-sigs", "--output", out.to_str().unwrap()];
+            run_at(Mode::Yellow, &words, env, 1_700_000_000_000)
+        };
+        assert_eq!(verify(&env).unwrap(), CliOutcome::Success);
+
+        // Re-signed with the general release key: valid bytes, wrong signer.
+        let release_key = sha256::from_hex(SIGNING_KEY_HEX).unwrap();
+        let release_sig = sha256::to_hex(&sha256::hmac_sha256(&release_key, b"sentry v1"));
+        let unsigned: String = text
+            .lines()
+            .map(|line| format!("{}\n", line.split('|').filter(|field| !field.starts_with("sig=")).collect::<Vec<_>>().join("|")))
+            .collect();
+        fs::write(&manifest_path, unsigned).unwrap();
+        fs::write(folder.join("sentry-blue.sig"), format!("{release_sig}\n")).unwrap();
+        assert_eq!(verify(&env).unwrap(), CliOutcome::VerificationFailed);
+        assert_eq!(results(&out), ["sentry-blue:signer-mismatch", "squire:match", "tools/hasher:match"]);
+        // An entry without a required signer is still fine with the release key alone.
+        fs::remove_file(folder.join("sentry-blue.sig")).unwrap();
+        assert_eq!(verify(&env).unwrap(), CliOutcome::VerificationFailed);
+        assert_eq!(results(&out)[0], "sentry-blue:signer-mismatch", "a required signature that is missing");
+        assert!(verify(&only_release_key).unwrap_err().to_string().contains("which is not loaded"));
+    }
+
+    #[test]
+    fn verify_checks_request_tokens_against_the_injected_clock() {
+        let base = temp_dir("verify-token");
+        let dir = bins(&base, &[("squire", b"v1")]);
+        let manifest = persisted(&base, &dir);
+        let release_id = load_manifest(&manifest).unwrap().release_id;
+        let env = runtime::MapEnv::new().with(verify_token::TOKEN_KEY_ENV, SIGNING_KEY_HEX);
+        let key = sha256::from_hex(SIGNING_KEY_HEX).unwrap();
+        let now = 1_800_000_000u64;
+        let out = base.join("verify.json");
+        let verify = |extra: &[&str], env: &runtime::MapEnv, now: u64| {
+            let mut words = vec!["verify", "--manifest", manifest.to_str().unwrap(), "--bins-dir", dir.to_str().unwrap(), "--output", out.to_str().unwrap()];
+            words.extend_from_slice(extra);
+            run_at(Mode::Yellow, &words, env, u128::from(now) * 1000)
+        };
+        let token_field = |field: &str| document(&out).get("token").and_then(|token| token.get(field)).and_then(|value| value.as_str().map(str::to_string));
+        let rejected = || document(&out).get("tokens_rejected").and_then(json::JsonValue::as_f64);
+
+        let token = verify_token::VerifyRequestToken::issue_at(&key, &release_id, 60, now).unwrap();
+        assert_eq!(verify(&["--token", &token, "--require-token"], &env, now + 10).unwrap(), CliOutcome::Success);
+        assert_eq!((token_field("status").as_deref(), token_field("release_id")), (Some("accepted"), Some(release_id.clone())));
+        assert_eq!(rejected(), Some(0.0));
+        // 60 seconds of life plus the default 120 of skew, then one second too many.
+        assert_eq!(verify(&["--token", &token, "--require-token"], &env, now + 180).unwrap(), CliOutcome::Success);
+        assert_eq!(verify(&["--token", &token, "--require-token"], &env, now + 181).unwrap(), CliOutcome::VerificationFailed);
+        assert_eq!(document(&out).get("status").and_then(json::JsonValue::as_str), Some("refused"));
+        assert!(token_field("reason").unwrap().starts_with("token expired at"));
+        assert_eq!(rejected(), Some(1.0));
+        let skew = ["--token", &token, "--require-token", "--max-clock-skew-secs", "200"];
+        assert_eq!(verify(&skew, &env, now + 181).unwrap(), CliOutcome::Success);
+
+        // Without --require-token a bad token is reported but the check still runs.
+        let other = verify_token::VerifyRequestToken::issue_at(&key, "another-release", 60, now).unwrap();
+        assert_eq!(verify(&["--token", &other], &env, now).unwrap(), CliOutcome::Success);
+        assert_eq!(token_field("reason"), Some(format!("token is for release another-release, the manifest is {release_id}")));
+        assert_eq!(rejected(), Some(1.0));
+        assert!(document(&out).get("results").is_some(), "the files were checked");
+        assert_eq!(verify(&["--token", &other, "--require-token"], &env, now).unwrap(), CliOutcome::VerificationFailed);
+
+        assert_eq!(verify(&["--require-token"], &env, now).unwrap(), CliOutcome::VerificationFailed);
+        assert_eq!(token_field("reason").as_deref(), Some("--require-token is set and no --token was given"));
+        let err = verify(&["--token", &token], &runtime::MapEnv::new(), now).unwrap_err().to_string();
+        assert!(err.contains(verify_token::TOKEN_KEY_ENV), "{err}");
+    }
+
+    #[test]
+    fn manifests_record_the_root_once_and_keep_entry_paths_relative() {
+        let base = temp_dir("root-header");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("tools/bard", b"bard v1")]);
+        let manifest = build(&dir);
+        assert_eq!(manifest.root.as_deref(), Some(manifest_path(&dir).as_str()));
+        assert!(manifest.entries.iter().all(|entry| entry.path == entry.rel_path), "{:?}", manifest.entries);
+
+        let text = render_manifest(&manifest);
+        let root = manifest_path(&dir);
+        assert_eq!(text.matches(root.as_str()).count(), 1, "{text}");
+        assert!(text.contains(&format!("\nroot={root}\n")));
+        let saved = persisted(&base, &dir);
+        assert_eq!(load_manifest(&saved).unwrap().root, manifest.root, "root= survives a round trip");
+
+        let out = base.join("verify.json");
+        let words = ["verify", "--manifest", saved.to_str().unwrap(), "--bins-dir", dir.to_str().unwrap(), "--output", out.to_str().unwrap()];
+        assert_eq!(run(Mode::Yellow, &words).unwrap(), CliOutcome::Success);
+        let report = fs::read_to_string(&out).unwrap();
+        assert!(!report.contains(base.to_str().unwrap()), "the report names no folder of the build host: {report}");
+        assert!(report.contains("\"path\":\"tools/bard\""));
+    }
+
+    #[test]
+    fn verify_without_bins_dir_checks_the_recorded_root_and_says_so() {
+        let base = temp_dir("root-fallback");
+        let dir = bins(&base, &[("squire", b"squire v1")]);
+        let saved = persisted(&base, &dir);
+        let manifest = load_manifest(&saved).unwrap();
+        let elsewhere = base.join("elsewhere");
+        assert_eq!(resolve_bins_dir(&manifest, Some(&elsewhere)).unwrap(), elsewhere, "--bins-dir wins");
+        assert_eq!(resolve_bins_dir(&manifest, None).unwrap(), dir);
+
+        let out = base.join("verify.json");
+        let verify = |manifest: &Path| run(Mode::Yellow, &["verify", "--manifest", manifest.to_str().unwrap(), "--output", out.to_str().unwrap()]);
+        assert_eq!(verify(&saved).unwrap(), CliOutcome::Success);
+        let warnings = document(&out).get("warnings").and_then(|warnings| warnings.as_array().map(<[_]>::to_vec)).unwrap_or_default();
+        assert!(
+            warnings.iter().any(|warning| warning.as_str() == Some("no --bins-dir given; checked the folder recorded as the manifest's root")),
+            "{warnings:?}"
+        );
+        fs::write(dir.join("squire"), b"squire v2").unwrap();
+        assert_eq!(verify(&saved).unwrap(), CliOutcome::VerificationFailed, "the fallback really checks the files");
+
+        let rootless = base.join("rootless.txt");
+        fs::write(&rootless, render_manifest(&OmegaManifest { root: None, ..manifest.clone() })).unwrap();
+        let err = verify(&rootless).unwrap_err().to_string();
+        assert!(err.contains("records no root; pass --bins-dir"), "{err}");
+        assert!(resolve_bins_dir(&OmegaManifest { root: None, ..manifest }, None).is_err());
+    }
+
+    #[test]
+    fn older_manifests_with_build_host_paths_still_verify() {
+        let base = temp_dir("root-legacy");
+        let dir = bins(&base, &[("squire", b"squire v1"), ("tools/bard", b"bard v1")]);
+        let out = base.join("verify.json");
+        let verify = |manifest: &Path, extra: &[&str]| {
+            let mut words = vec!["verify", "--manifest", manifest.to_str().unwrap(), "--bins-dir", dir.to_str().unwrap(), "--output", out.to_str().unwrap()];
+            words.extend_from_slice(extra);
+            run(Mode::Yellow, &words)
+        };
+        let older = |path: &str| {
+            let mut manifest = build(&dir);
+            manifest.root = None;
+            for entry in &mut manifest.entries {
+                entry.path = format!("{path}/{}", entry.rel_path);
+            }
+            let file = base.join(format!("manifest-{}.txt", manifest_path(Path::new(path)).replace('/', "_")));
+            fs::write(&file, render_manifest(&manifest)).unwrap();
+            file
+        };
+
+        // Blue's absolute folders, which this host does not have.
+        let absolute = older("/srv/blue/bins");
+        assert_eq!(verify(&absolute, &["--trust-absolute-paths"]).unwrap(), CliOutcome::Success);
+        let report = fs::read_to_string(&out).unwrap();
+        assert!(!report.contains("/srv/blue"), "reports show the relative path: {report}");
+        assert!(report.contains("\"path\":\"tools/bard\""));
+        assert!(verify(&absolute, &[]).is_err(), "absolute paths still need --trust-absolute-paths");
+
+        // The folder as given to build, relative to where it ran.
+        let relative = older("build/bin");
+        assert_eq!(verify(&relative, &[]).unwrap(), CliOutcome::Success);
+        fs::write(dir.join("tools/bard"), b"bard v2").unwrap();
+        assert_eq!(verify(&relative, &[]).unwrap(), CliOutcome::VerificationFailed);
+    }
+
+    #[test]
+    fn relocate_rewrites_the_root_and_verify_follows_it() {
+        let base = temp_dir("relocate");
+        let env = runtime::MapEnv::new().with(SIGNING_KEY_ENV, SIGNING_KEY_HEX);
+        let dir = bins(&base, &[("squire", b"squire v1"), ("tools/bard", b"bard v1")]);
+        let folder = cli_build(&base, &dir, &["--release-id", "r1", "--recursive", "--per-file-sigs"], &env);
+        let manifest = folder.join("manifest.txt");
+        let moved = base.join("moved");
+        fs::rename(&dir, &moved).unwrap();
+        fs::write(folder.join("manifest.txt.sig"), "signed by an operator\n").unwrap();
+
+        let out = base.join("relocate.json");
+        let relocate = |root: &Path, extra: &[&str], env: &runtime::MapEnv| {
+            let mut words = vec!["relocate", "--manifest", manifest.to_str().unwrap(), "--root", root.to_str().unwrap(), "--output", out.to_str().unwrap()];
+            words.extend_from_slice(extra);
+            run_at(Mode::Blue, &words, env, 1_700_000_000_000)
+        };
+        assert_eq!(relocate(&moved, &[], &env).unwrap(), CliOutcome::Success);
+        let report = document(&out);
+        assert_eq!(report.get("root").and_then(json::JsonValue::as_str), Some(manifest_path(&moved).as_str()));
+        assert_eq!((report.get("entries").and_then(json::JsonValue::as_f64), report.get("signatures").and_then(json::JsonValue::as_str)), (Some(2.0), Some("placeholder")));
+        let relocated = load_manifest(&manifest).unwrap();
+        assert_eq!(relocated.root, Some(manifest_path(&moved)));
+        assert_eq!(fs::read_to_string(folder.join("manifest.txt.sig")).unwrap(), format!("{}\n", status::SIGNATURE_PLACEHOLDER), "the old signature covered the old text");
+
+        let verify_out = base.join("verify.json");
+        let verify = |extra: &[&str]| {
+            let mut words = vec!["verify", "--manifest", manifest.to_str().unwrap(), "--output", verify_out.to_str().unwrap()];
+            words.extend_from_slice(extra);
+            run_at(Mode::Yellow, &words, &env, 1_700_000_000_000)
+        };
+        assert_eq!(verify(&["--per-file-sigs"]).unwrap(), CliOutcome::Success, "entry signatures carry over");
+
+        // Re-signing reads the files under the new root.
+        fs::write(moved.join("squire"), b"squire v2").unwrap();
+        assert_eq!(relocate(&moved, &["--per-file-sigs"], &env).unwrap(), CliOutcome::Success);
+        assert_eq!(document(&out).get("signatures").and_then(json::JsonValue::as_str), Some("resigned"));
+        let key = sha256::from_hex(SIGNING_KEY_HEX).unwrap();
+        let sig = sha256::to_hex(&sha256::hmac_sha256(&key, b"squire v2"));
+        assert_eq!(fs::read_to_string(folder.join("squire.sig")).unwrap(), format!("{sig}\n"));
+        assert!(relocate(&moved, &["--per-file-sigs"], &runtime::MapEnv::new()).is_err(), "re-signing needs the key");
+
+        // An older manifest with absolute entry paths loses them.
+        let mut older = load_manifest(&manifest).unwrap();
+        older.root = None;
+        for entry in &mut older.entries {
+            entry.path = format!("/srv/blue/bins/{}", entry.rel_path);
+        }
+        fs::write(&manifest, render_manifest(&older)).unwrap();
+        assert_eq!(relocate(&moved, &[], &env).unwrap(), CliOutcome::Success);
+        let text = fs::read_to_string(&manifest).unwrap();
+        assert!(!text.contains("/srv/blue"), "{text}");
+        assert!(load_manifest(&manifest).unwrap().entries.iter().all(|entry| entry.path == entry.rel_path));
+    }
 }
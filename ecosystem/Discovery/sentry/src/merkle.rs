@@ -0,0 +1,202 @@
+//! Merkle tree helpers for cheap, partial manifest verification.
+//!
+//! A Merkle tree folds many hashes into one "root" hash. Each manifest entry becomes a leaf; pairs
+//! of leaves are hashed together into parents, pairs of parents into grandparents, and so on until
+//! one hash remains. To prove that a single entry belongs to a release you only need the handful
+//! of sibling hashes along its path to the root (about log2(entry count) of them), so a verifier
+//! can check one binary without touching any of the others.
+//!
+//! Rules used here (every verifier must follow the same ones):
+//! - Leaf hash = SHA-256(0x00 || "name|hash|size"). Parent hash = SHA-256(0x01 || left || right).
+//!   The different one-byte prefixes keep a leaf from ever being mistaken for a parent.
+//! - When a level has an odd number of nodes, the last node is paired with a copy of itself.
+
+use crate::sha256::{self, Sha256, DIGEST_LEN};
+
+/// One 32-byte node hash.
+pub type NodeHash = [u8; DIGEST_LEN];
+
+/// Domain-separation prefix for leaves.
+const LEAF_PREFIX: u8 = 0x00;
+/// Domain-separation prefix for interior nodes.
+const NODE_PREFIX: u8 = 0x01;
+
+/// Which side of the running hash a sibling sits on when folding a proof upward.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Left => "left",
+            Side::Right => "right",
+        }
+    }
+}
+
+/// One step of an inclusion proof: the sibling hash and the side it sits on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProofStep {
+    pub side: Side,
+    pub hash: NodeHash,
+}
+
+/// Hash one manifest entry into a leaf.
+pub fn leaf_hash(name: &str, content_hash: &str, size: u64) -> NodeHash {
+    let mut hasher = Sha256::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(format!("{}|{}|{}", name, content_hash, size).as_bytes());
+    hasher.finalize()
+}
+
+/// Hash two children into their parent.
+fn node_hash(left: &NodeHash, right: &NodeHash) -> NodeHash {
+    let mut hasher = Sha256::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize()
+}
+
+/// Build the next level up by pairing neighbours, duplicating the last node on odd counts.
+fn parent_level(level: &[NodeHash]) -> Vec<NodeHash> {
+    level
+        .chunks(2)
+        .map(|pair| {
+            let right = pair.get(1).unwrap_or(&pair[0]);
+            node_hash(&pair[0], right)
+        })
+        .collect()
+}
+
+/// Fold all leaves into a root. Returns `None` for an empty list because there is nothing to
+/// commit to.
+pub fn merkle_root(leaves: &[NodeHash]) -> Option<NodeHash> {
+    if leaves.is_empty() {
+        return None;
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = parent_level(&level);
+    }
+    Some(level[0])
+}
+
+/// Collect the sibling hashes needed to walk from `leaves[index]` up to the root.
+pub fn build_proof(leaves: &[NodeHash], index: usize) -> Option<Vec<ProofStep>> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut proof = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut position = index;
+
+    while level.len() > 1 {
+        let step = if position.is_multiple_of(2) {
+            // Our node is on the left; the sibling is the next node, or ourselves when we are the
+            // odd one out at the end of the level.
+            let sibling = level.get(position + 1).unwrap_or(&level[position]);
+            ProofStep { side: Side::Right, hash: *sibling }
+        } else {
+            ProofStep { side: Side::Left, hash: level[position - 1] }
+        };
+        proof.push(step);
+        level = parent_level(&level);
+        position /= 2;
+    }
+
+    Some(proof)
+}
+
+/// Recompute the root from one leaf plus its proof and compare it with the expected root.
+pub fn verify_proof(leaf: &NodeHash, proof: &[ProofStep], expected_root: &NodeHash) -> bool {
+    let mut running = *leaf;
+    for step in proof {
+        running = match step.side {
+            Side::Left => node_hash(&step.hash, &running),
+            Side::Right => node_hash(&running, &step.hash),
+        };
+    }
+    running == *expected_root
+}
+
+/// Render a node hash as hex for manifests and proof files.
+pub fn to_hex(hash: &NodeHash) -> String {
+    sha256::to_hex(hash)
+}
+
+/// Parse a hex node hash, rejecting anything that is not exactly 32 bytes.
+pub fn from_hex(text: &str) -> Option<NodeHash> {
+    let bytes = sha256::from_hex(text.trim())?;
+    bytes.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: usize) -> Vec<NodeHash> {
+        (0..count).map(|i| leaf_hash(&format!("bin-{i}"), &format!("{i:016x}"), i as u64 * 10)).collect()
+    }
+
+    #[test]
+    fn root_is_stable_and_depends_on_every_leaf() {
+        let first = merkle_root(&leaves(5)).unwrap();
+        assert_eq!(merkle_root(&leaves(5)), Some(first));
+        let mut changed = leaves(5);
+        changed[3] = leaf_hash("bin-3", "0000000000000000", 30);
+        assert_ne!(merkle_root(&changed), Some(first));
+        assert_eq!(merkle_root(&[]), None);
+        assert_eq!(merkle_root(&leaves(1)), Some(leaves(1)[0]));
+    }
+
+    #[test]
+    fn every_leaf_proves_against_the_root() {
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let root = merkle_root(&leaves).unwrap();
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = build_proof(&leaves, index).unwrap();
+                assert!(verify_proof(leaf, &proof, &root), "count {count} index {index}");
+            }
+            assert!(build_proof(&leaves, count).is_none());
+        }
+    }
+
+    #[test]
+    fn proof_fails_for_a_modified_leaf_or_a_flipped_side() {
+        let leaves = leaves(6);
+        let root = merkle_root(&leaves).unwrap();
+        let proof = build_proof(&leaves, 2).unwrap();
+        assert!(!verify_proof(&leaf_hash("bin-2", "ffffffffffffffff", 20), &proof, &root));
+
+        let mut flipped = proof.clone();
+        flipped[0].side = if flipped[0].side == Side::Left { Side::Right } else { Side::Left };
+        assert!(!verify_proof(&leaves[2], &flipped, &root));
+    }
+
+    #[test]
+    fn leaves_and_nodes_never_collide() {
+        // A leaf whose text happens to be two child hashes still hashes differently from their parent.
+        let (left, right) = (leaves(2)[0], leaves(2)[1]);
+        let mut joined = Sha256::new();
+        joined.update(&[LEAF_PREFIX]);
+        joined.update(&left);
+        joined.update(&right);
+        assert_ne!(joined.finalize(), node_hash(&left, &right));
+    }
+
+    #[test]
+    fn hex_round_trip_needs_32_bytes() {
+        let root = merkle_root(&leaves(3)).unwrap();
+        assert_eq!(from_hex(&to_hex(&root)), Some(root));
+        assert_eq!(from_hex(&format!(" {} ", to_hex(&root).to_uppercase())), Some(root));
+        assert_eq!(from_hex("abcd"), None);
+        assert_eq!(from_hex(&"zz".repeat(32)), None);
+    }
+}
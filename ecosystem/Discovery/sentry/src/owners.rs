@@ -0,0 +1,176 @@
+//! Owners files: which team owns each binary, and whose key must sign it.
+//!
+//! A release holds binaries from several teams, and some of them may only change with a build
+//! signed by a particular key (`sentry-blue` by the security key, not the general release key).
+//! `build --owners-file <path>` reads one rule per line:
+//!
+//! ```text
+//! # security owns the Sentry binaries and signs them with its own key
+//! sentry-*=security,3f9a0c1d2e4b5a69
+//! tools/**=platform
+//! ```
+//!
+//! - The pattern is matched like `hash-dir --ignore` (see `hash_dir::glob_match`): without a `/`
+//!   it is compared with the entry's file name at any depth, with a `/` with its whole relative path.
+//! - The owner is a short label, recorded as `owner=` on the entry line.
+//! - The signer is optional: the fingerprint of the key that must sign the entry (see
+//!   `key_fingerprint`), recorded as `required_signer=`. `build --per-file-sigs` signs the entry
+//!   with that key and `verify --per-file-sigs` reports `signer-mismatch` when another key did.
+//! - When several patterns match, the last one in the file wins, so put broad rules first.
+//!
+//! Entries that match no pattern get no annotation and are signed and checked as before.
+
+use std::fs;
+use std::path::Path;
+
+use crate::hash_dir::glob_match;
+use crate::sha256;
+
+/// Hex characters in a key fingerprint.
+pub const FINGERPRINT_LEN: usize = 16;
+
+/// One `pattern=owner[,signer]` line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnerRule {
+    pub pattern: String,
+    pub owner: String,
+    /// Fingerprint of the key that must sign matching entries, in lowercase hex.
+    pub required_signer: Option<String>,
+}
+
+impl OwnerRule {
+    /// A pattern with `/` is compared with the relative path, one without with the file name.
+    pub fn matches(&self, rel_path: &str) -> bool {
+        if self.pattern.contains('/') {
+            glob_match(self.pattern.trim_start_matches('/'), rel_path)
+        } else {
+            glob_match(&self.pattern, rel_path.rsplit('/').next().unwrap_or(rel_path))
+        }
+    }
+}
+
+/// The rules of an owners file, in file order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OwnersFile {
+    pub rules: Vec<OwnerRule>,
+}
+
+impl OwnersFile {
+    /// Read an owners file; errors name the file and the line.
+    pub fn load(path: &Path) -> Result<OwnersFile, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Unable to read owners file {:?}: {err}", path))?;
+        OwnersFile::parse(&text).map_err(|problem| format!("{:?} {problem}", path))
+    }
+
+    /// Blank lines and lines starting with `#` are skipped; any other line must be
+    /// `pattern=owner` or `pattern=owner,signer`.
+    pub fn parse(text: &str) -> Result<OwnersFile, String> {
+        let mut rules = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            rules.push(parse_line(line).map_err(|problem| format!("line {}: {problem}", index + 1))?);
+        }
+        Ok(OwnersFile { rules })
+    }
+
+    /// The rule for an entry: the last one whose pattern matches, if any.
+    pub fn lookup(&self, rel_path: &str) -> Option<&OwnerRule> {
+        self.rules.iter().rev().find(|rule| rule.matches(rel_path))
+    }
+}
+
+fn parse_line(line: &str) -> Result<OwnerRule, String> {
+    let (pattern, value) = line.split_once('=').ok_or("expected pattern=owner[,signer]")?;
+    let pattern = pattern.trim();
+    if pattern.is_empty() {
+        return Err("the pattern is empty".to_string());
+    }
+    let (owner, signer) = match value.split_once(',') {
+        Some((owner, signer)) => (owner.trim(), Some(signer.trim())),
+        None => (value.trim(), None),
+    };
+    // The owner lands in a `|`-separated entry line, so it must stay one plain word.
+    if owner.is_empty() || !owner.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.@".contains(&b)) {
+        return Err(format!("owner {owner:?} must be letters, digits, -, _, ., or @"));
+    }
+    let required_signer = match signer {
+        Some(signer) if is_fingerprint(signer) => Some(signer.to_ascii_lowercase()),
+        Some(signer) => return Err(format!("signer {signer:?} must be a key fingerprint ({FINGERPRINT_LEN} hex characters)")),
+        None => None,
+    };
+    Ok(OwnerRule { pattern: pattern.to_string(), owner: owner.to_string(), required_signer })
+}
+
+/// The fingerprint that names a signing key in owners files and manifests: the first 16 hex
+/// characters of the key's SHA-256. It identifies the key without revealing it.
+pub fn key_fingerprint(key: &[u8]) -> String {
+    let mut hex = sha256::sha256_hex(key);
+    hex.truncate(FINGERPRINT_LEN);
+    hex
+}
+
+/// `FINGERPRINT_LEN` hex characters, in either case.
+pub fn is_fingerprint(text: &str) -> bool {
+    text.len() == FINGERPRINT_LEN && text.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECURITY: &str = "3f9a0c1d2e4b5a69";
+
+    #[test]
+    fn rules_are_read_with_comments_signers_and_globs() {
+        let owners = OwnersFile::parse(&format!("# who owns what\n\nsentry-*=security,{}\n tools/** = platform \n/bin/?q=ops@team\n", SECURITY.to_uppercase())).unwrap();
+        assert_eq!(owners.rules.len(), 3);
+        assert_eq!(owners.rules[0], OwnerRule { pattern: "sentry-*".to_string(), owner: "security".to_string(), required_signer: Some(SECURITY.to_string()) });
+        assert_eq!((owners.rules[1].pattern.as_str(), owners.rules[1].owner.as_str(), &owners.rules[1].required_signer), ("tools/**", "platform", &None));
+
+        // Without a `/` the file name is matched at any depth; with one, the whole path.
+        assert!(owners.rules[0].matches("sentry-blue") && owners.rules[0].matches("deep/dir/sentry-red"));
+        assert!(!owners.rules[0].matches("sentry/blue"));
+        assert!(owners.rules[1].matches("tools/a/b/c") && !owners.rules[1].matches("other/tools/a"));
+        assert!(owners.rules[2].matches("bin/sq") && !owners.rules[2].matches("bin/squire"), "a leading / is the bins folder itself");
+        assert_eq!(OwnersFile::parse("").unwrap(), OwnersFile::default());
+    }
+
+    #[test]
+    fn the_last_matching_rule_wins_and_unmatched_entries_get_nothing() {
+        let owners = OwnersFile::parse(&format!("*=release\ntools/**=platform\nsentry-*=security,{SECURITY}\n")).unwrap();
+        assert_eq!(owners.lookup("sentry-blue").map(|rule| rule.owner.as_str()), Some("security"));
+        assert_eq!(owners.lookup("tools/sentry-cli").map(|rule| rule.owner.as_str()), Some("security"));
+        assert_eq!(owners.lookup("tools/hasher").map(|rule| rule.owner.as_str()), Some("platform"));
+        assert_eq!(owners.lookup("squire").map(|rule| rule.owner.as_str()), Some("release"));
+        assert_eq!(OwnersFile::parse("tools/**=platform").unwrap().lookup("squire"), None);
+    }
+
+    #[test]
+    fn bad_lines_are_errors_naming_the_line() {
+        let err = |text: &str| OwnersFile::parse(text).unwrap_err();
+        assert_eq!(err("# ok\nsquire"), "line 2: expected pattern=owner[,signer]");
+        assert_eq!(err("=security"), "line 1: the pattern is empty");
+        assert_eq!(err("squire=sec|urity"), "line 1: owner \"sec|urity\" must be letters, digits, -, _, ., or @");
+        assert_eq!(err("squire="), "line 1: owner \"\" must be letters, digits, -, _, ., or @");
+        assert_eq!(err("squire=security,3f9a"), "line 1: signer \"3f9a\" must be a key fingerprint (16 hex characters)");
+
+        let dir = std::env::temp_dir().join(format!("sentry-owners-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("OWNERS");
+        fs::write(&path, "squire=release\nbroken\n").unwrap();
+        assert_eq!(OwnersFile::load(&path).unwrap_err(), format!("{path:?} line 2: expected pattern=owner[,signer]"));
+        assert!(OwnersFile::load(&dir.join("missing")).unwrap_err().starts_with("Unable to read owners file"));
+    }
+
+    #[test]
+    fn fingerprints_name_a_key_without_revealing_it() {
+        let fingerprint = key_fingerprint(&[0x33; 32]);
+        assert_eq!(fingerprint, sha256::sha256_hex(&[0x33; 32])[..FINGERPRINT_LEN]);
+        assert!(is_fingerprint(&fingerprint) && is_fingerprint(&fingerprint.to_uppercase()));
+        assert_ne!(key_fingerprint(&[0x44; 32]), fingerprint);
+        assert!(!is_fingerprint("3f9a0c1d2e4b5a6") && !is_fingerprint("3f9a0c1d2e4b5a6g"));
+    }
+}
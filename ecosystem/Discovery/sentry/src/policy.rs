@@ -0,0 +1,280 @@
+//! Mode policy: what each role may run, checked before a command starts.
+//!
+//! The three roles are not interchangeable, so `run_cli` asks `check` first:
+//!
+//! - **Blue** is the air-gapped builder. Before `build` it looks for signs that this host is on a
+//!   network after all: a `SENTRY_YELLOW_HOST` or `SENTRY_RED_HOST` that resolves and accepts a
+//!   TCP connection. Any sign refuses the build (`PolicyViolation::NetworkedBlue`) unless
+//!   `--allow-networked-blue` is given, for example on a lab machine that is networked on
+//!   purpose. `build` has no `--publish` or `--listen` flags, so those cannot be a sign here;
+//!   publishing from Blue's `verify` stays allowed, because Blue reports back to Yellow. So does
+//!   `push-release`, the way a finished release leaves Blue.
+//! - **Red** only verifies and cross-checks, so `build` is refused outright
+//!   (`PolicyViolation::RedCannotBuild`). Building on the independent verifier would make it
+//!   check its own work.
+//! - **Yellow** may run everything, as before.
+//!
+//! Reachability goes through the `ReachabilityProbe` trait. `TcpProbe` really connects; other code
+//! can pass a probe that answers from a list, so the policy can be checked without sockets.
+//!
+//! A refused run prints `{"action":"build","mode":..,"policy":{"decision":"refused",...}}` and
+//! exits with code 3. An allowed `build` carries the same `policy` object with `"decision"` set
+//! to `allowed` or `allowed-override`.
+
+use std::error::Error;
+use std::fmt;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::{json_escape, Command, HostConfig, Mode, OmegaEnvironment};
+
+/// How long `TcpProbe` waits for each address.
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Answers "can this host reach that address right now?".
+pub trait ReachabilityProbe {
+    fn reachable(&self, host: &HostConfig) -> bool;
+}
+
+/// Resolves the host and tries a TCP connection to each address it gets, giving up on each after
+/// `timeout`. A host that does not resolve is not reachable.
+#[derive(Clone, Copy, Debug)]
+pub struct TcpProbe {
+    pub timeout: Duration,
+}
+
+impl Default for TcpProbe {
+    fn default() -> Self {
+        Self { timeout: PROBE_TIMEOUT }
+    }
+}
+
+impl ReachabilityProbe for TcpProbe {
+    fn reachable(&self, host: &HostConfig) -> bool {
+        let Ok(addresses) = host.address().to_socket_addrs() else {
+            return false;
+        };
+        addresses.into_iter().any(|address| TcpStream::connect_timeout(&address, self.timeout).is_ok())
+    }
+}
+
+/// Why a command may not run in this mode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PolicyViolation {
+    /// `build` was asked of Red.
+    RedCannotBuild,
+    /// Blue can reach other roles; `signs` says which, e.g. `SENTRY_RED_HOST red.local:7420 is reachable`.
+    NetworkedBlue { signs: Vec<String> },
+}
+
+impl PolicyViolation {
+    /// Short name for JSON: `red-cannot-build` or `networked-blue`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PolicyViolation::RedCannotBuild => "red-cannot-build",
+            PolicyViolation::NetworkedBlue { .. } => "networked-blue",
+        }
+    }
+
+    fn signs(&self) -> &[String] {
+        match self {
+            PolicyViolation::RedCannotBuild => &[],
+            PolicyViolation::NetworkedBlue { signs } => signs,
+        }
+    }
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyViolation::RedCannotBuild => {
+                f.write_str("Red only verifies and cross-checks; run build on Blue (or Yellow)")
+            }
+            PolicyViolation::NetworkedBlue { signs } => write!(
+                f,
+                "Blue is the air-gapped builder but this host looks networked ({}); disconnect it or pass --allow-networked-blue",
+                signs.join("; ")
+            ),
+        }
+    }
+}
+
+impl Error for PolicyViolation {}
+
+/// The result of a check that let the command run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PolicyDecision {
+    pub mode: Mode,
+    /// Network signs that were found but overridden with `--allow-networked-blue`.
+    pub overridden_signs: Vec<String>,
+}
+
+impl PolicyDecision {
+    /// `allowed`, or `allowed-override` when `--allow-networked-blue` waved signs through.
+    pub fn as_str(&self) -> &'static str {
+        if self.overridden_signs.is_empty() {
+            "allowed"
+        } else {
+            "allowed-override"
+        }
+    }
+
+    /// `{"mode":"blue","decision":"allowed","signs":[]}` for the build document.
+    pub fn to_json(&self) -> String {
+        policy_json(self.mode, self.as_str(), None, &self.overridden_signs)
+    }
+}
+
+/// Check `command` against the rules for `mode`, probing hosts over TCP.
+pub fn check(mode: Mode, command: &Command, env_settings: &OmegaEnvironment) -> Result<PolicyDecision, PolicyViolation> {
+    check_with(mode, command, env_settings, &TcpProbe::default())
+}
+
+/// `check` with the reachability probe supplied by the caller.
+pub fn check_with(
+    mode: Mode,
+    command: &Command,
+    env_settings: &OmegaEnvironment,
+    probe: &dyn ReachabilityProbe,
+) -> Result<PolicyDecision, PolicyViolation> {
+    let allowed = PolicyDecision { mode, overridden_signs: Vec::new() };
+    let Command::Build { allow_networked_blue, .. } = command else {
+        return Ok(allowed);
+    };
+    match mode {
+        Mode::Yellow => Ok(allowed),
+        Mode::Red => Err(PolicyViolation::RedCannotBuild),
+        Mode::Blue => {
+            let signs = network_signs(env_settings, probe);
+            if signs.is_empty() {
+                Ok(allowed)
+            } else if *allow_networked_blue {
+                Ok(PolicyDecision { mode, overridden_signs: signs })
+            } else {
+                Err(PolicyViolation::NetworkedBlue { signs })
+            }
+        }
+    }
+}
+
+/// The document printed instead of the command's own when the policy refuses it.
+pub fn refusal_json(mode: Mode, action: &str, violation: &PolicyViolation) -> String {
+    format!(
+        "{{\"action\":\"{}\",\"mode\":\"{}\",\"policy\":{}}}",
+        json_escape(action),
+        mode.as_str(),
+        policy_json(mode, "refused", Some(violation), violation.signs())
+    )
+}
+
+/// Yellow's and Red's hosts that configured Blue could reach.
+fn network_signs(env_settings: &OmegaEnvironment, probe: &dyn ReachabilityProbe) -> Vec<String> {
+    [Mode::Yellow, Mode::Red]
+        .into_iter()
+        .filter_map(|role| {
+            let host = env_settings.host_for(role);
+            (host.configured && probe.reachable(host)).then(|| format!("{} {} is reachable", role.host_env(), host.address()))
+        })
+        .collect()
+}
+
+fn policy_json(mode: Mode, decision: &str, violation: Option<&PolicyViolation>, signs: &[String]) -> String {
+    let signs: Vec<String> = signs.iter().map(|sign| format!("\"{}\"", json_escape(sign))).collect();
+    let violation = match violation {
+        Some(violation) => format!(
+            ",\"violation\":\"{}\",\"reason\":\"{}\"",
+            violation.as_str(),
+            json_escape(&violation.to_string())
+        ),
+        None => String::new(),
+    };
+    format!("{{\"mode\":\"{}\",\"decision\":\"{}\"{},\"signs\":[{}]}}", mode.as_str(), decision, violation, signs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{self, JsonValue};
+    use crate::runtime::MapEnv;
+
+    /// Reaches exactly the hosts it was given.
+    struct ListProbe(Vec<&'static str>);
+
+    impl ReachabilityProbe for ListProbe {
+        fn reachable(&self, host: &HostConfig) -> bool {
+            self.0.contains(&host.host.as_str())
+        }
+    }
+
+    fn command(words: &[&str]) -> Command {
+        let args: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        crate::parse_args(Mode::Yellow, &args).unwrap().command
+    }
+
+    fn networked() -> OmegaEnvironment {
+        OmegaEnvironment::from_env(&MapEnv::new().with("SENTRY_YELLOW_HOST", "yellow.local").with("SENTRY_RED_HOST", "red.local:7421")).unwrap()
+    }
+
+    #[test]
+    fn every_mode_and_command() {
+        let build = command(&["build", "--bins-dir", "bins", "--releases-dir", "releases"]);
+        let verify = command(&["verify", "--manifest", "m.txt", "--bins-dir", "bins", "--publish"]);
+        let push = command(&["push-release", "--release-dir", "releases/omega-r1", "--to", "yellow.local:7420"]);
+        let report = command(&["report", "--log-file", "cycles.log"]);
+        let quiet = OmegaEnvironment::from_env(&MapEnv::new()).unwrap();
+        let everything = ListProbe(vec!["yellow.local", "red.local"]);
+        let nothing = ListProbe(Vec::new());
+
+        for settings in [&quiet, &networked()] {
+            for probe in [&everything, &nothing] {
+                for other in [&verify, &push, &report] {
+                    for mode in [Mode::Blue, Mode::Yellow, Mode::Red] {
+                        assert_eq!(check_with(mode, other, settings, probe).unwrap().as_str(), "allowed");
+                    }
+                }
+                assert_eq!(check_with(Mode::Yellow, &build, settings, probe).unwrap().as_str(), "allowed");
+                assert_eq!(check_with(Mode::Red, &build, settings, probe), Err(PolicyViolation::RedCannotBuild));
+            }
+        }
+
+        // Blue refuses only when a configured host answers.
+        assert!(check_with(Mode::Blue, &build, &quiet, &everything).is_ok());
+        assert!(check_with(Mode::Blue, &build, &networked(), &nothing).is_ok());
+        let signs = match check_with(Mode::Blue, &build, &networked(), &ListProbe(vec!["red.local"])) {
+            Err(PolicyViolation::NetworkedBlue { signs }) => signs,
+            other => panic!("expected a networked-blue refusal, got {other:?}"),
+        };
+        assert_eq!(signs, ["SENTRY_RED_HOST red.local:7421 is reachable"]);
+    }
+
+    #[test]
+    fn the_override_lets_blue_build_and_keeps_the_signs() {
+        let build = command(&["build", "--bins-dir", "bins", "--releases-dir", "releases", "--allow-networked-blue"]);
+        let decision = check_with(Mode::Blue, &build, &networked(), &ListProbe(vec!["yellow.local", "red.local"])).unwrap();
+        assert_eq!(decision.as_str(), "allowed-override");
+        assert_eq!(decision.overridden_signs.len(), 2);
+        assert_eq!(check_with(Mode::Blue, &build, &networked(), &ListProbe(Vec::new())).unwrap().as_str(), "allowed");
+        assert_eq!(check_with(Mode::Red, &build, &networked(), &ListProbe(Vec::new())), Err(PolicyViolation::RedCannotBuild), "the flag is for Blue only");
+    }
+
+    #[test]
+    fn decisions_and_refusals_as_json() {
+        let decision = PolicyDecision { mode: Mode::Blue, overridden_signs: vec!["SENTRY_YELLOW_HOST yellow.local:7420 is reachable".to_string()] };
+        let document = json::parse(&decision.to_json()).unwrap();
+        assert_eq!(document.get("decision").and_then(JsonValue::as_str), Some("allowed-override"));
+        assert_eq!(document.get("signs").and_then(JsonValue::as_array).map(<[JsonValue]>::len), Some(1));
+        assert_eq!(PolicyDecision { mode: Mode::Yellow, overridden_signs: Vec::new() }.to_json(), "{\"mode\":\"yellow\",\"decision\":\"allowed\",\"signs\":[]}");
+
+        let violation = PolicyViolation::NetworkedBlue { signs: vec!["SENTRY_RED_HOST \"red\":7420 is reachable".to_string()] };
+        let document = json::parse(&refusal_json(Mode::Blue, "build", &violation)).unwrap();
+        assert_eq!((document.get("action").and_then(JsonValue::as_str), document.get("mode").and_then(JsonValue::as_str)), (Some("build"), Some("blue")));
+        let policy = document.get("policy").unwrap();
+        assert_eq!(policy.get("decision").and_then(JsonValue::as_str), Some("refused"));
+        assert_eq!(policy.get("violation").and_then(JsonValue::as_str), Some("networked-blue"));
+        assert!(policy.get("reason").and_then(JsonValue::as_str).unwrap().contains("--allow-networked-blue"));
+        assert_eq!(policy.get("signs").and_then(|signs| signs.as_array()).and_then(|signs| signs[0].as_str()), Some("SENTRY_RED_HOST \"red\":7420 is reachable"));
+
+        let red = json::parse(&refusal_json(Mode::Red, "build", &PolicyViolation::RedCannotBuild)).unwrap();
+        assert_eq!(red.get("policy").and_then(|policy| policy.get("violation")).and_then(JsonValue::as_str), Some("red-cannot-build"));
+    }
+}
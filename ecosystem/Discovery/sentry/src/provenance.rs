@@ -0,0 +1,153 @@
+//! Build provenance: a short note in the manifest about what produced it.
+//!
+//! Hashes prove *which* bytes were released; provenance records *how* they were made so an
+//! auditor can try to reproduce the build. The manifest stores it as `provenance.<field>=` lines.
+//! The build time and builder mode are not repeated here because the manifest already carries
+//! them as `created_at_unix=` and `mode=`.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+
+/// Placeholder for facts we could not discover.
+const UNKNOWN: &str = "unknown";
+
+/// What produced a manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Provenance {
+    pub hostname: String,
+    /// Output of `rustc --version`, e.g. `rustc 1.80.0 (051478957 2024-07-21)`.
+    pub rustc_version: String,
+    /// Source control revision, when the builder passed `--source-rev`.
+    pub source_rev: Option<String>,
+}
+
+impl Provenance {
+    /// Gather provenance on the build machine. `rustc_override` comes from `--rustc-version`;
+    /// without it we ask `rustc --version` (honouring `$RUSTC` the way cargo does).
+    pub fn collect(rustc_override: Option<&str>, source_rev: Option<&str>) -> Self {
+        let rustc_version = match rustc_override {
+            Some(version) => version.to_string(),
+            None => detect_rustc_version(),
+        };
+        Self {
+            hostname: detect_hostname(),
+            rustc_version,
+            source_rev: source_rev.map(str::to_string),
+        }
+    }
+
+    /// Manifest lines, each ending in a newline.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        output.push_str(&format!("provenance.hostname={}\n", self.hostname));
+        output.push_str(&format!("provenance.rustc={}\n", self.rustc_version));
+        if let Some(rev) = &self.source_rev {
+            output.push_str(&format!("provenance.source_rev={}\n", rev));
+        }
+        output
+    }
+
+    /// Apply one `provenance.<field>=value` line. Returns false when the line is not a
+    /// provenance line so the manifest loader can keep looking.
+    pub fn apply_line(slot: &mut Option<Provenance>, line: &str) -> bool {
+        let Some(rest) = line.strip_prefix("provenance.") else {
+            return false;
+        };
+        let Some((field, value)) = rest.split_once('=') else {
+            return false;
+        };
+
+        let provenance = slot.get_or_insert_with(|| Provenance {
+            hostname: UNKNOWN.to_string(),
+            rustc_version: UNKNOWN.to_string(),
+            source_rev: None,
+        });
+        match field {
+            "hostname" => provenance.hostname = value.to_string(),
+            "rustc" => provenance.rustc_version = value.to_string(),
+            "source_rev" => provenance.source_rev = Some(value.to_string()),
+            // Fields added by newer versions are ignored rather than rejected.
+            _ => {}
+        }
+        true
+    }
+
+    /// JSON object for status output.
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"hostname\":\"{}\",\"rustc\":\"{}\"",
+            crate::json_escape(&self.hostname),
+            crate::json_escape(&self.rustc_version)
+        );
+        if let Some(rev) = &self.source_rev {
+            json.push_str(&format!(",\"source_rev\":\"{}\"", crate::json_escape(rev)));
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// The standard library has no hostname call, so try the usual places in turn.
+fn detect_hostname() -> String {
+    if let Ok(name) = fs::read_to_string("/etc/hostname") {
+        let name = name.trim();
+        if !name.is_empty() {
+            return name.to_string();
+        }
+    }
+    env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .unwrap_or_else(|_| UNKNOWN.to_string())
+}
+
+fn detect_rustc_version() -> String {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|version| !version.is_empty())
+        .unwrap_or_else(|| UNKNOWN.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_back(text: &str) -> Option<Provenance> {
+        let mut slot = None;
+        for line in text.lines() {
+            assert!(Provenance::apply_line(&mut slot, line), "{line}");
+        }
+        slot
+    }
+
+    #[test]
+    fn rendered_lines_read_back_the_same() {
+        let provenance = Provenance { hostname: "blue-1".to_string(), rustc_version: "rustc 1.80.0 (051478957 2024-07-21)".to_string(), source_rev: Some("abc123".to_string()) };
+        assert_eq!(read_back(&provenance.render()), Some(provenance.clone()));
+        let without_rev = Provenance { source_rev: None, ..provenance };
+        assert_eq!(read_back(&without_rev.render()), Some(without_rev));
+    }
+
+    #[test]
+    fn other_lines_are_left_alone_and_new_fields_ignored() {
+        let mut slot = None;
+        assert!(!Provenance::apply_line(&mut slot, "release_id=r1"));
+        assert!(!Provenance::apply_line(&mut slot, "provenance.no-equals"));
+        assert_eq!(slot, None);
+        assert!(Provenance::apply_line(&mut slot, "provenance.compiler_flags=-O"));
+        assert_eq!(slot, Some(Provenance { hostname: UNKNOWN.to_string(), rustc_version: UNKNOWN.to_string(), source_rev: None }));
+    }
+
+    #[test]
+    fn override_and_json() {
+        let provenance = Provenance::collect(Some("rustc 9.9.9"), Some("deadbeef"));
+        assert_eq!(provenance.rustc_version, "rustc 9.9.9");
+        let json = Provenance { hostname: "h\"1".to_string(), ..provenance }.to_json();
+        assert_eq!(json, "{\"hostname\":\"h\\\"1\",\"rustc\":\"rustc 9.9.9\",\"source_rev\":\"deadbeef\"}");
+    }
+}
@@ -0,0 +1,199 @@
+//! Release retention: decide which `omega-*` folders under the releases directory can go.
+//!
+//! Only folders that look like ours are ever touched: the name must start with `omega-` and the
+//! folder must hold a `manifest.txt`. Anything else is reported as skipped and left alone. Folders
+//! are ordered by the manifest's `created_at_unix=` line; older manifests without that line fall
+//! back to the manifest file's modification time. The newest release is always kept, even when
+//! it is older than the age cutoff, so a quiet project never ends up with no release at all.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds in one day, used to turn `--older-than-days` into a cutoff.
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Which rules to apply. A release survives if *any* rule wants to keep it.
+#[derive(Clone, Copy, Debug)]
+pub struct RetentionPolicy {
+    /// Keep the newest N releases.
+    pub keep: Option<usize>,
+    /// Keep releases younger than this many days.
+    pub older_than_days: Option<u64>,
+}
+
+/// One release folder and when it was created.
+#[derive(Clone, Debug)]
+pub struct ReleaseFolder {
+    pub path: PathBuf,
+    pub created_at_unix: u64,
+}
+
+/// The outcome of planning a prune: what stays, what goes, and what was ignored.
+#[derive(Clone, Debug, Default)]
+pub struct PrunePlan {
+    pub keep: Vec<ReleaseFolder>,
+    pub delete: Vec<ReleaseFolder>,
+    /// Folders named `omega-*` that were skipped because they have no manifest.txt.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Current time as seconds since 1970, the format written into manifests.
+pub fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Scan `releases_dir` and sort the candidates into keep/delete/skipped without deleting.
+pub fn plan(releases_dir: &Path, policy: RetentionPolicy, now_unix: u64) -> Result<PrunePlan, String> {
+    if policy.keep.is_none() && policy.older_than_days.is_none() {
+        return Err("prune needs --keep, --older-than-days, or both".to_string());
+    }
+
+    let (releases, skipped) = releases_newest_first(releases_dir)?;
+    let mut plan = PrunePlan { skipped, ..PrunePlan::default() };
+
+    let cutoff = policy
+        .older_than_days
+        .map(|days| now_unix.saturating_sub(days.saturating_mul(SECONDS_PER_DAY)));
+
+    for (position, release) in releases.into_iter().enumerate() {
+        let is_latest = position == 0;
+        let kept_by_count = policy.keep.is_some_and(|keep| position < keep);
+        let kept_by_age = cutoff.is_some_and(|cutoff| release.created_at_unix >= cutoff);
+
+        if is_latest || kept_by_count || kept_by_age {
+            plan.keep.push(release);
+        } else {
+            plan.delete.push(release);
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Every `omega-*` folder with a manifest under `releases_dir`, newest first, plus the `omega-*`
+/// folders skipped for having none. `status` uses the first one as the current release.
+pub fn releases_newest_first(releases_dir: &Path) -> Result<(Vec<ReleaseFolder>, Vec<PathBuf>), String> {
+    let mut releases = Vec::new();
+    let mut skipped = Vec::new();
+    let dir_entries = fs::read_dir(releases_dir)
+        .map_err(|err| format!("Unable to read releases directory {:?}: {err}", releases_dir))?;
+    for entry in dir_entries {
+        let entry = entry.map_err(|err| format!("Failed to read releases entry: {err}"))?;
+        let path = entry.path();
+        let is_release_name = entry.file_name().to_string_lossy().starts_with("omega-");
+        if !path.is_dir() || !is_release_name {
+            continue;
+        }
+
+        let manifest_path = path.join("manifest.txt");
+        if !manifest_path.is_file() {
+            skipped.push(path);
+            continue;
+        }
+
+        let created_at_unix = release_timestamp(&manifest_path)?;
+        releases.push(ReleaseFolder { path, created_at_unix });
+    }
+
+    // Newest first; ties fall back to the folder name so the order is stable.
+    releases.sort_by(|a, b| b.created_at_unix.cmp(&a.created_at_unix).then_with(|| a.path.cmp(&b.path)));
+    Ok((releases, skipped))
+}
+
+/// Remove every folder the plan marked for deletion.
+pub fn apply(plan: &PrunePlan) -> Result<(), String> {
+    for release in &plan.delete {
+        fs::remove_dir_all(&release.path).map_err(|err| format!("Unable to delete {:?}: {err}", release.path))?;
+    }
+    Ok(())
+}
+
+/// Read `created_at_unix=` from the manifest, or use the file's modification time for manifests
+/// written before the field existed. No binary is read, so absolute entry paths are accepted.
+fn release_timestamp(manifest_path: &Path) -> Result<u64, String> {
+    let manifest = crate::load_manifest_with(manifest_path, true)?;
+    if let Some(created_at_unix) = manifest.created_at_unix {
+        return Ok(created_at_unix);
+    }
+
+    let modified = fs::metadata(manifest_path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|err| format!("Unable to read timestamp of {:?}: {err}", manifest_path))?;
+    Ok(modified.duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    /// A releases folder with `omega-<id>` releases created `days_ago` before `NOW`.
+    fn releases(name: &str, ages_in_days: &[(&str, u64)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sentry-prune-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (id, days_ago) in ages_in_days {
+            let folder = dir.join(format!("omega-{id}"));
+            fs::create_dir_all(&folder).unwrap();
+            let created = NOW - days_ago * SECONDS_PER_DAY;
+            fs::write(folder.join("manifest.txt"), format!("release_id={id}\nmode=blue\ncreated_at_unix={created}\nentries:\n")).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(folders: &[ReleaseFolder]) -> Vec<String> {
+        folders.iter().map(|folder| folder.path.file_name().unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn keep_n_keeps_the_newest() {
+        let dir = releases("keep", &[("a", 30), ("b", 20), ("c", 10), ("d", 1)]);
+        let plan = super::plan(&dir, RetentionPolicy { keep: Some(2), older_than_days: None }, NOW).unwrap();
+        assert_eq!(names(&plan.keep), ["omega-d", "omega-c"]);
+        assert_eq!(names(&plan.delete), ["omega-b", "omega-a"]);
+    }
+
+    #[test]
+    fn age_cutoff_keeps_young_releases_and_always_the_newest() {
+        let dir = releases("age", &[("a", 30), ("b", 20), ("c", 3)]);
+        let plan = super::plan(&dir, RetentionPolicy { keep: None, older_than_days: Some(7) }, NOW).unwrap();
+        assert_eq!((names(&plan.keep), names(&plan.delete)), (vec!["omega-c".to_string()], vec!["omega-b".to_string(), "omega-a".to_string()]));
+
+        let dir = releases("age-old", &[("a", 30), ("b", 20)]);
+        let plan = super::plan(&dir, RetentionPolicy { keep: None, older_than_days: Some(7) }, NOW).unwrap();
+        assert_eq!(names(&plan.keep), ["omega-b"]);
+    }
+
+    #[test]
+    fn dry_run_plan_deletes_nothing_until_applied() {
+        let dir = releases("apply", &[("a", 30), ("b", 1)]);
+        let plan = super::plan(&dir, RetentionPolicy { keep: Some(1), older_than_days: None }, NOW).unwrap();
+        assert!(dir.join("omega-a").is_dir());
+        apply(&plan).unwrap();
+        assert!(!dir.join("omega-a").exists());
+        assert!(dir.join("omega-b").is_dir());
+    }
+
+    #[test]
+    fn folders_without_a_manifest_or_our_prefix_are_never_touched() {
+        let dir = releases("skip", &[("a", 30), ("b", 1)]);
+        fs::create_dir_all(dir.join("omega-manual")).unwrap();
+        fs::create_dir_all(dir.join("backups")).unwrap();
+        let plan = super::plan(&dir, RetentionPolicy { keep: Some(1), older_than_days: None }, NOW).unwrap();
+        assert_eq!(plan.skipped, [dir.join("omega-manual")]);
+        assert_eq!(names(&plan.delete), ["omega-a"]);
+        apply(&plan).unwrap();
+        assert!(dir.join("omega-manual").is_dir() && dir.join("backups").is_dir());
+    }
+
+    #[test]
+    fn a_rule_is_required() {
+        let dir = releases("no-rule", &[]);
+        assert!(super::plan(&dir, RetentionPolicy { keep: None, older_than_days: None }, NOW).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! Send verification documents to the next Sentry in the chain over plain TCP.
+//!
+//! Each role forwards its findings to the next one: Yellow publishes to Red, Red to Blue, and
+//! Blue back to Yellow. The wire format is intentionally boring: one JSON document followed by a
+//! newline, then the connection closes. Nothing here may abort verification; failures are
+//! reported to the caller, which logs them and carries on.
+
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::log::Logger;
+use crate::{Mode, OmegaEnvironment};
+
+const LOG: Logger = Logger::new("sentry-publish");
+
+/// How long one connection attempt (and the write that follows) may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// First pause between daemon retries; each later pause doubles.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Result of publishing one document, rendered into the JSON output under `"publish"`.
+#[derive(Clone, Debug)]
+pub struct PublishReport {
+    pub target: String,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+impl PublishReport {
+    /// Nothing was sent because there was no usable target.
+    pub fn not_sent(reason: String) -> Self {
+        LOG.warn("Not publishing", &[("reason", &reason)]);
+        PublishReport { target: String::new(), attempts: 0, error: Some(reason) }
+    }
+
+    /// Render as a JSON object, e.g. `{"target":"red.local:7420","status":"sent","attempts":1}`.
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"target\":\"{}\",\"status\":\"{}\",\"attempts\":{}",
+            crate::json_escape(&self.target),
+            if self.error.is_none() { "sent" } else { "failed" },
+            self.attempts
+        );
+        if let Some(error) = &self.error {
+            json.push_str(&format!(",\"error\":\"{}\"", crate::json_escape(error)));
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// Pick where `mode` publishes: the explicit `--publish-to` value when given, otherwise the next
+/// role's host from the environment. Fails, without trying to connect, when that host is unset,
+/// not a usable host name, or asks for TLS (see `HostConfig::problem`).
+pub fn publish_target(mode: Mode, env_settings: &OmegaEnvironment, override_target: Option<&str>) -> Result<String, String> {
+    if let Some(target) = override_target {
+        return Ok(target.to_string());
+    }
+
+    let next = match mode {
+        Mode::Yellow => Mode::Red,
+        Mode::Red => Mode::Blue,
+        Mode::Blue => Mode::Yellow,
+    };
+    let host = env_settings.host_for(next);
+    match host.problem(next.host_env()) {
+        Some(problem) => Err(problem),
+        None => Ok(host.address()),
+    }
+}
+
+/// Make one attempt: connect, write the document and a newline, and close.
+pub fn send(target: &str, document: &str) -> Result<(), String> {
+    let addresses = target
+        .to_socket_addrs()
+        .map_err(|err| format!("Unable to resolve {target}: {err}"))?;
+
+    let mut last_error = format!("{target} did not resolve to any address");
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(mut stream) => {
+                stream
+                    .set_write_timeout(Some(CONNECT_TIMEOUT))
+                    .map_err(|err| format!("Unable to set write timeout: {err}"))?;
+                stream
+                    .write_all(format!("{document}\n").as_bytes())
+                    .and_then(|_| stream.flush())
+                    .map_err(|err| format!("Unable to send to {target}: {err}"))?;
+                return Ok(());
+            }
+            Err(err) => last_error = format!("Unable to connect to {target}: {err}"),
+        }
+    }
+    Err(last_error)
+}
+
+/// One attempt, used by `verify`.
+pub fn publish_once(target: &str, document: &str) -> PublishReport {
+    let error = send(target, document).err();
+    if let Some(message) = &error {
+        LOG.warn("Publish failed", &[("target", target), ("error", message)]);
+    }
+    PublishReport { target: target.to_string(), attempts: 1, error }
+}
+
+/// Retry with exponential backoff (1s, 2s, 4s, ...) until the send works or `budget` runs out.
+/// The daemon passes the wait before its next pass as the budget so retries never delay that pass.
+pub fn publish_with_retry(target: &str, document: &str, budget: Duration) -> PublishReport {
+    let started = Instant::now();
+    let mut delay = FIRST_RETRY_DELAY;
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        let error = match send(target, document) {
+            Ok(()) => return PublishReport { target: target.to_string(), attempts, error: None },
+            Err(error) => error,
+        };
+        LOG.warn(
+            "Publish failed",
+            &[("target", target), ("attempt", &attempts.to_string()), ("error", &error)],
+        );
+
+        let remaining = budget.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return PublishReport { target: target.to_string(), attempts, error: Some(error) };
+        }
+        thread::sleep(delay.min(remaining));
+        delay = delay.saturating_mul(2).min(budget);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::MapEnv;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn environment(env: &MapEnv) -> OmegaEnvironment {
+        OmegaEnvironment::from_env(env).unwrap()
+    }
+
+    #[test]
+    fn each_role_publishes_to_the_next_one() {
+        let env = MapEnv::new()
+            .with("SENTRY_YELLOW_HOST", "yellow.local")
+            .with("SENTRY_RED_HOST", "red.local:7500")
+            .with("SENTRY_BLUE_HOST", "blue.local");
+        let settings = environment(&env);
+        assert_eq!(publish_target(Mode::Yellow, &settings, None).unwrap(), "red.local:7500");
+        assert_eq!(publish_target(Mode::Red, &settings, None).unwrap(), "blue.local:7420");
+        assert_eq!(publish_target(Mode::Blue, &settings, None).unwrap(), "yellow.local:7420");
+        assert_eq!(publish_target(Mode::Blue, &settings, Some("elsewhere:1")).unwrap(), "elsewhere:1");
+    }
+
+    #[test]
+    fn unusable_targets_fail_before_connecting() {
+        let settings = environment(&MapEnv::new().with("SENTRY_BLUE_HOST", "https://blue.local"));
+        assert!(publish_target(Mode::Yellow, &settings, None).unwrap_err().contains("SENTRY_RED_HOST is not set"));
+        assert!(publish_target(Mode::Red, &settings, None).unwrap_err().contains("asks for https"));
+    }
+
+    #[test]
+    fn send_writes_one_line_and_closes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let reader = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+        let report = publish_once(&target, r#"{"action":"verify"}"#);
+        assert_eq!(reader.join().unwrap(), "{\"action\":\"verify\"}\n");
+        assert_eq!(report.to_json(), format!("{{\"target\":\"{target}\",\"status\":\"sent\",\"attempts\":1}}"));
+    }
+
+    #[test]
+    fn a_closed_port_is_reported_not_raised() {
+        // Bind and drop, so the port is very likely closed.
+        let target = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let report = publish_with_retry(&target, "{}", Duration::ZERO);
+        assert_eq!(report.attempts, 1);
+        assert!(report.to_json().contains("\"status\":\"failed\""), "{}", report.to_json());
+        assert!(PublishReport::not_sent("no host".to_string()).to_json().contains("\"attempts\":0"));
+    }
+}
@@ -0,0 +1,104 @@
+//! Paths from manifests and waiver files that cannot leave their folder.
+//!
+//! `verify` joins each entry's path onto `--bins-dir`, and the `.sig` lookup joins the entry's
+//! relative path onto the manifest's folder. If a manifest (damaged, or written by someone
+//! hostile) said `../../etc/shadow` or `/root/.ssh/id_rsa`, Sentry would read that file and put
+//! its hash into a report that may be published to other hosts. `normalize` stops that when the
+//! manifest is loaded:
+//!
+//! - `\` counts as `/`, so `..\..\secret` from a Windows manifest is caught too.
+//! - `.` parts and empty parts (`a//b`) are dropped: `./sub/file` becomes `sub/file`.
+//! - `..` removes the part before it: `sub/../file` becomes `file`. A `..` with nothing left to
+//!   remove would climb out of the folder, so it is refused (`PathProblem::Escapes`).
+//! - `/etc/x`, `\\server\share\x`, and `C:\x` are absolute (`PathProblem::Absolute`). Manifests
+//!   built from an absolute `--bins-dir` by older Sentry versions hold such paths;
+//!   `--trust-absolute-paths` accepts them for a manifest the operator vouches for.
+
+use std::fmt;
+
+/// Why a recorded path was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathProblem {
+    /// Nothing is left once `.` and empty parts are dropped.
+    Empty,
+    /// It starts at a root or a drive letter instead of inside the folder.
+    Absolute,
+    /// A `..` climbs above the folder it is read from.
+    Escapes,
+}
+
+impl fmt::Display for PathProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PathProblem::Empty => "is empty",
+            PathProblem::Absolute => "is absolute",
+            PathProblem::Escapes => "climbs out of its folder with ..",
+        })
+    }
+}
+
+/// True for `/x`, `\x`, and `C:x` (with any drive letter): paths that do not start inside a folder.
+pub fn is_absolute(recorded: &str) -> bool {
+    let bytes = recorded.as_bytes();
+    matches!(bytes.first(), Some(b'/' | b'\\')) || (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':')
+}
+
+/// The relative path in its plain form, parts joined with `/`, or why it is not safe to join onto
+/// a folder.
+pub fn normalize(recorded: &str) -> Result<String, PathProblem> {
+    if is_absolute(recorded) {
+        return Err(PathProblem::Absolute);
+    }
+    let mut parts: Vec<&str> = Vec::new();
+    for part in recorded.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop().ok_or(PathProblem::Escapes)?;
+            }
+            _ => parts.push(part),
+        }
+    }
+    if parts.is_empty() {
+        return Err(PathProblem::Empty);
+    }
+    Ok(parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths_come_out_plain() {
+        assert_eq!(normalize("squire").as_deref(), Ok("squire"));
+        assert_eq!(normalize("./sub/file").as_deref(), Ok("sub/file"));
+        assert_eq!(normalize("sub//./file/").as_deref(), Ok("sub/file"));
+        assert_eq!(normalize("sub/../file").as_deref(), Ok("file"), "a .. inside the folder is fine");
+        assert_eq!(normalize("tools\\win\\squire.exe").as_deref(), Ok("tools/win/squire.exe"));
+        assert_eq!(normalize("..name/x..").as_deref(), Ok("..name/x.."), "only a whole .. part climbs");
+    }
+
+    #[test]
+    fn climbing_out_is_refused_with_either_separator() {
+        for recorded in ["..", "../../etc/shadow", "sub/../../x", "..\\..\\secret", "sub\\..\\..\\x", "./../x"] {
+            assert_eq!(normalize(recorded), Err(PathProblem::Escapes), "{recorded}");
+        }
+    }
+
+    #[test]
+    fn absolute_and_empty_paths_are_refused() {
+        for recorded in ["/etc/shadow", "/root/.ssh/id_rsa", "\\\\server\\share\\x", "\\x", "C:\\x", "c:x", "Z:/bin"] {
+            assert!(is_absolute(recorded), "{recorded}");
+            assert_eq!(normalize(recorded), Err(PathProblem::Absolute), "{recorded}");
+        }
+        assert!(!is_absolute("sub/C:x") && !is_absolute("1:x") && !is_absolute(""));
+        for recorded in ["", ".", "./", "//", "sub/.."] {
+            // `//` is a root, so it is absolute before it is empty.
+            assert!(normalize(recorded).is_err(), "{recorded}");
+        }
+        assert_eq!(normalize("./."), Err(PathProblem::Empty));
+        assert_eq!(normalize("sub/.."), Err(PathProblem::Empty));
+        assert_eq!(PathProblem::Escapes.to_string(), "climbs out of its folder with ..");
+    }
+}
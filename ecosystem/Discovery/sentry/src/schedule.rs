@@ -0,0 +1,260 @@
+//! How long the daemon waits between passes.
+//!
+//! A fixed `--interval-seconds` has two problems: daemons started together (after a reboot, say)
+//! all read the disk at the same moment, and a quiet system is re-hashed every minute for
+//! nothing. `Schedule` fixes both:
+//!
+//! - **Jitter.** Each wait is moved by a random amount within ±`--interval-jitter-pct` percent
+//!   (default 10), so daemons drift apart. The randomness comes from `XorShift64`, a tiny
+//!   generator seeded from the clock and the process id. It is not suitable for secrets, only for
+//!   spreading out timers.
+//! - **Relaxing.** After `--relax-after` clean passes in a row (default 10) the interval doubles,
+//!   and doubles again after the next run of clean passes, up to `--max-interval-seconds`.
+//! - **Escalating.** Any mismatch drops straight back to the base interval and asks for one extra
+//!   pass 5 seconds later (`RECHECK_DELAY`). If the mismatch is gone by then, it was most likely a
+//!   file caught halfway through a copy; if it is still there, it is real. The extra pass never
+//!   asks for another one, so a tampered folder is checked at the base interval, not every 5
+//!   seconds.
+//!
+//! Time and waiting come from `runtime` (`Clock` and `Sleeper`). The daemon uses the real ones;
+//! with a `runtime::ManualClock` for both, a run of passes can be played through without waiting
+//! for real.
+
+use std::process;
+use std::time::{Duration, Instant};
+
+use crate::runtime::{Clock, Sleeper};
+
+/// Pause before the extra pass that follows a mismatch.
+pub const RECHECK_DELAY: Duration = Duration::from_secs(5);
+/// `--interval-jitter-pct` when absent.
+pub const DEFAULT_JITTER_PCT: u8 = 10;
+/// `--relax-after` when absent.
+pub const DEFAULT_RELAX_AFTER: u64 = 10;
+/// `--max-interval-seconds` when absent: ten minutes, or the base interval if that is longer.
+pub const DEFAULT_MAX_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Marsaglia's xorshift64: three shifts and three XORs per number. Good enough to spread timers,
+/// never good enough for keys.
+#[derive(Clone, Debug)]
+pub struct XorShift64 {
+    state: u64,
+}
+
+impl XorShift64 {
+    /// A zero state would only ever produce zeros, so it is replaced with a fixed odd constant.
+    pub fn new(seed: u64) -> Self {
+        Self { state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed } }
+    }
+
+    /// Seed from the clock's milliseconds mixed with the process id, so two daemons started in
+    /// the same millisecond still get different sequences.
+    pub fn from_time_and_pid(clock: &dyn Clock) -> Self {
+        Self::new(clock.now_millis() as u64 ^ u64::from(process::id()).rotate_left(32))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+}
+
+/// The daemon's timing flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScheduleSettings {
+    /// `--interval-seconds`: the interval to start from and to return to after a mismatch.
+    pub base: Duration,
+    /// `--max-interval-seconds`: relaxing stops here.
+    pub max: Duration,
+    /// `--relax-after`: clean passes in a row before each doubling. 0 never relaxes.
+    pub relax_after: u64,
+    /// `--interval-jitter-pct`: each wait moves by up to this share of the interval. 0 turns
+    /// jitter off.
+    pub jitter_pct: u8,
+}
+
+impl ScheduleSettings {
+    /// The defaults for a base interval; `max` is at least `base`.
+    pub fn new(base: Duration) -> Self {
+        Self { base, max: DEFAULT_MAX_INTERVAL.max(base), relax_after: DEFAULT_RELAX_AFTER, jitter_pct: DEFAULT_JITTER_PCT }
+    }
+}
+
+/// What kind of pass comes next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    /// An ordinary pass after the (jittered) effective interval.
+    Regular,
+    /// The extra pass `RECHECK_DELAY` after a mismatch.
+    Recheck,
+}
+
+impl Pass {
+    pub fn is_recheck(self) -> bool {
+        self == Pass::Recheck
+    }
+}
+
+/// The daemon's timing state between passes.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    settings: ScheduleSettings,
+    effective: Duration,
+    consecutive_clean: u64,
+    rng: XorShift64,
+}
+
+impl Schedule {
+    pub fn new(settings: ScheduleSettings, rng: XorShift64) -> Self {
+        Self { settings, effective: settings.base, consecutive_clean: 0, rng }
+    }
+
+    /// The interval between regular passes right now, before jitter.
+    pub fn effective_interval(&self) -> Duration {
+        self.effective
+    }
+
+    /// Clean passes in a row so far.
+    pub fn consecutive_clean(&self) -> u64 {
+        self.consecutive_clean
+    }
+
+    /// Update the state with the result of the pass that just ran and say which pass comes next.
+    ///
+    /// A clean pass counts towards relaxing; every `relax_after`-th one in a row doubles the
+    /// interval, up to `max`. A mismatch resets both and, unless this pass already was the
+    /// recheck, asks for a `Pass::Recheck`.
+    pub fn record(&mut self, clean: bool, pass: Pass) -> Pass {
+        if !clean {
+            self.consecutive_clean = 0;
+            self.effective = self.settings.base;
+            return if pass.is_recheck() { Pass::Regular } else { Pass::Recheck };
+        }
+        self.consecutive_clean += 1;
+        let relax_after = self.settings.relax_after;
+        if relax_after > 0 && self.consecutive_clean.is_multiple_of(relax_after) {
+            self.effective = self.effective.saturating_mul(2).min(self.settings.max.max(self.settings.base));
+        }
+        Pass::Regular
+    }
+
+    /// How long to wait before `next`: `RECHECK_DELAY` for a recheck, otherwise the effective
+    /// interval moved by a random amount within ±`jitter_pct` percent.
+    pub fn delay(&mut self, next: Pass) -> Duration {
+        if next.is_recheck() {
+            return RECHECK_DELAY;
+        }
+        let pct = u128::from(self.settings.jitter_pct.min(100));
+        if pct == 0 {
+            return self.effective;
+        }
+        // Pick a whole number of milliseconds in -spread..=spread.
+        let millis = self.effective.as_millis();
+        let spread = millis * pct / 100;
+        let offset = u128::from(self.rng.next_u64()) % (spread * 2 + 1);
+        let jittered = (millis + offset).saturating_sub(spread);
+        Duration::from_millis(u64::try_from(jittered).unwrap_or(u64::MAX))
+    }
+}
+
+/// Sleep for whatever is left of `delay` after a pass that began at `started`, so a slow pass
+/// does not push every later pass back.
+/// `started` comes from `clock.instant()`.
+pub fn sleep_rest(clock: &dyn Clock, sleeper: &dyn Sleeper, delay: Duration, started: Instant) {
+    sleeper.sleep(delay.saturating_sub(clock.instant().saturating_duration_since(started)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::ManualClock;
+
+    fn settings(base: u64, max: u64, relax_after: u64, jitter_pct: u8) -> ScheduleSettings {
+        ScheduleSettings { base: Duration::from_secs(base), max: Duration::from_secs(max), relax_after, jitter_pct }
+    }
+
+    /// Play `results` (true for a clean pass) through a schedule, sleeping on `clock` between
+    /// passes the way the daemon does; each pass takes `pass_time`.
+    fn play(schedule: &mut Schedule, clock: &ManualClock, results: &[bool], pass_time: Duration) -> Vec<(Pass, u64, u64)> {
+        let mut pass = Pass::Regular;
+        let mut seen = Vec::new();
+        for clean in results {
+            let started = clock.instant();
+            clock.advance(pass_time);
+            pass = schedule.record(*clean, pass);
+            seen.push((pass, schedule.effective_interval().as_secs(), schedule.consecutive_clean()));
+            let delay = schedule.delay(pass);
+            sleep_rest(clock, clock, delay, started);
+        }
+        seen
+    }
+
+    #[test]
+    fn clean_passes_double_the_interval_up_to_the_max() {
+        let clock = ManualClock::new(1_700_000_000_000);
+        let mut schedule = Schedule::new(settings(60, 300, 2, 0), XorShift64::new(1));
+        let seen = play(&mut schedule, &clock, &[true; 8], Duration::ZERO);
+        let intervals: Vec<u64> = seen.iter().map(|(_, interval, _)| *interval).collect();
+        assert_eq!(intervals, [60, 120, 120, 240, 240, 300, 300, 300]);
+        assert_eq!(schedule.consecutive_clean(), 8);
+        let slept: Vec<u64> = clock.slept().iter().map(Duration::as_secs).collect();
+        assert_eq!(slept, intervals, "without jitter each wait is the effective interval");
+
+        // relax_after 0 never relaxes.
+        let mut fixed = Schedule::new(settings(60, 300, 0, 0), XorShift64::new(1));
+        assert!(play(&mut fixed, &clock, &[true; 30], Duration::ZERO).iter().all(|(_, interval, _)| *interval == 60));
+    }
+
+    #[test]
+    fn a_mismatch_resets_and_asks_for_one_recheck() {
+        let clock = ManualClock::new(0);
+        let mut schedule = Schedule::new(settings(60, 600, 2, 0), XorShift64::new(7));
+        let seen = play(&mut schedule, &clock, &[true, true, true, true, false, false, false, true], Duration::ZERO);
+        assert_eq!(
+            seen,
+            [
+                (Pass::Regular, 60, 1),
+                (Pass::Regular, 120, 2),
+                (Pass::Regular, 120, 3),
+                (Pass::Regular, 240, 4),
+                (Pass::Recheck, 60, 0),
+                // The recheck failed too: back to regular passes at the base interval.
+                (Pass::Regular, 60, 0),
+                (Pass::Recheck, 60, 0),
+                (Pass::Regular, 60, 1),
+            ]
+        );
+        let slept: Vec<u64> = clock.slept().iter().map(Duration::as_secs).collect();
+        assert_eq!(slept, [60, 120, 120, 240, 5, 60, 5, 60]);
+    }
+
+    #[test]
+    fn slow_passes_shorten_the_wait() {
+        let clock = ManualClock::new(0);
+        let mut schedule = Schedule::new(settings(60, 60, 10, 0), XorShift64::new(3));
+        play(&mut schedule, &clock, &[true, true], Duration::from_secs(15));
+        assert_eq!(clock.slept(), [Duration::from_secs(45); 2]);
+        play(&mut schedule, &clock, &[true], Duration::from_secs(90));
+        assert_eq!(clock.slept().last(), Some(&Duration::ZERO), "an overlong pass starts the next one at once");
+    }
+
+    #[test]
+    fn jitter_stays_within_the_percentage_and_varies() {
+        let mut schedule = Schedule::new(settings(100, 100, 0, 10), XorShift64::new(42));
+        let delays: Vec<Duration> = (0..500).map(|_| schedule.delay(Pass::Regular)).collect();
+        assert!(delays.iter().all(|delay| (Duration::from_secs(90)..=Duration::from_secs(110)).contains(delay)));
+        assert!(delays.iter().any(|delay| *delay < Duration::from_secs(98)) && delays.iter().any(|delay| *delay > Duration::from_secs(102)));
+        assert_eq!(schedule.delay(Pass::Recheck), RECHECK_DELAY, "rechecks are never jittered");
+
+        // The same seed gives the same waits; a zero seed still produces numbers.
+        let mut again = Schedule::new(settings(100, 100, 0, 10), XorShift64::new(42));
+        assert_eq!(delays[..5], (0..5).map(|_| again.delay(Pass::Regular)).collect::<Vec<_>>()[..]);
+        assert_ne!(XorShift64::new(0).next_u64(), 0);
+        let clock = ManualClock::new(1_700_000_000_000);
+        assert_eq!(XorShift64::from_time_and_pid(&clock).next_u64(), XorShift64::from_time_and_pid(&clock).next_u64());
+    }
+}
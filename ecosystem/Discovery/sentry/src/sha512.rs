@@ -0,0 +1,177 @@
+//! SHA-512 written out by hand, like `ecosystem_common::sha256`, so the crate stays "standard library only".
+//!
+//! SHA-512 is the same design as SHA-256 with wider parts: 64-bit words instead of 32-bit, 128-byte
+//! blocks, 80 rounds, and a 128-bit length at the end of the padding. Manifests record it next to
+//! the SHA-256 when `build --digests sha256,sha512` is used, so a vendor's published SHA-512 sums
+//! can be compared with a release without hashing every file again (see `digest.rs`).
+
+/// Round constants: the first 64 bits of the fractional parts of the cube roots of the first 80
+/// primes. Fixed by FIPS 180-4.
+const ROUND_CONSTANTS: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+
+/// Starting state: the first 64 bits of the fractional parts of the square roots of the first
+/// eight primes.
+const INITIAL_STATE: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+/// Size of one SHA-512 block in bytes.
+pub const BLOCK_LEN: usize = 128;
+/// Size of a finished SHA-512 digest in bytes.
+pub const DIGEST_LEN: usize = 64;
+
+/// Incremental SHA-512 hasher. Call `update` as many times as needed, then `finalize` once.
+#[derive(Clone, Debug)]
+pub struct Sha512 {
+    state: [u64; 8],
+    /// Bytes waiting for a full 128-byte block.
+    buffer: [u8; BLOCK_LEN],
+    buffer_len: usize,
+    /// Total number of message bytes seen so far (needed for the final padding).
+    total_len: u128,
+}
+
+impl Default for Sha512 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha512 {
+    /// Start a fresh hash.
+    pub fn new() -> Self {
+        Self {
+            state: INITIAL_STATE,
+            buffer: [0u8; BLOCK_LEN],
+            buffer_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Feed more bytes into the hash. Splitting data across calls gives the same result as one
+    /// big call.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u128);
+
+        // Top up a partially filled buffer first.
+        if self.buffer_len > 0 {
+            let take = (BLOCK_LEN - self.buffer_len).min(data.len());
+            self.buffer[self.buffer_len..self.buffer_len + take].copy_from_slice(&data[..take]);
+            self.buffer_len += take;
+            data = &data[take..];
+            if self.buffer_len == BLOCK_LEN {
+                let block = self.buffer;
+                self.compress(&block);
+                self.buffer_len = 0;
+            }
+        }
+
+        // Process whole blocks straight from the input.
+        while data.len() >= BLOCK_LEN {
+            let mut block = [0u8; BLOCK_LEN];
+            block.copy_from_slice(&data[..BLOCK_LEN]);
+            self.compress(&block);
+            data = &data[BLOCK_LEN..];
+        }
+
+        // Keep the leftovers for the next call.
+        if !data.is_empty() {
+            self.buffer[..data.len()].copy_from_slice(data);
+            self.buffer_len = data.len();
+        }
+    }
+
+    /// Finish the hash and return the 64-byte digest.
+    pub fn finalize(mut self) -> [u8; DIGEST_LEN] {
+        let bit_len = self.total_len.wrapping_mul(8);
+
+        // Padding: a single 1 bit, then zeros until 16 bytes remain in the block, then the
+        // message length in bits as a big-endian 128-bit number.
+        let mut padding = vec![0x80u8];
+        let used = (self.buffer_len + 1) % BLOCK_LEN;
+        let zeros = if used <= BLOCK_LEN - 16 { BLOCK_LEN - 16 - used } else { 2 * BLOCK_LEN - 16 - used };
+        padding.extend(std::iter::repeat_n(0u8, zeros));
+        padding.extend_from_slice(&bit_len.to_be_bytes());
+
+        // `update` would also bump total_len, which no longer matters once the length is fixed.
+        self.update(&padding);
+
+        let mut digest = [0u8; DIGEST_LEN];
+        for (chunk, word) in digest.chunks_mut(8).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    /// Mix one 128-byte block into the running state.
+    fn compress(&mut self, block: &[u8; BLOCK_LEN]) {
+        // Message schedule: 16 words straight from the block, 64 more derived from them.
+        let mut w = [0u64; 80];
+        for (i, chunk) in block.chunks(8).enumerate() {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            w[i] = u64::from_be_bytes(word);
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let choose = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choose)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (slot, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *slot = slot.wrapping_add(value);
+        }
+    }
+}
+
+/// Hash a byte slice in one call.
+pub fn sha512(data: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut hasher = Sha512::new();
+    hasher.update(data);
+    hasher.finalize()
+}
@@ -0,0 +1,149 @@
+//! Bins slots: one manifest checked against several folders in one run.
+//!
+//! One host often holds the same release more than once, for example blue, green, and canary
+//! slots. Running `verify` once per folder gave three documents that had to be stitched together.
+//! Now `--bins-dir` may be repeated on `verify` and `daemon`, and every folder is checked against
+//! the same manifest, exactly as a single `--bins-dir` would be.
+//!
+//! Each `--bins-dir` may carry a label, `--bins-dir canary=/srv/canary/bin`, so reports say
+//! `canary` instead of a long path. Without one, the path as written is the label. A label is
+//! letters, digits, `.`, `_`, and `-`, so `./a=b` (with a `/` before the `=`) is still read as a
+//! plain path.
+//!
+//! With one `--bins-dir` and no label, documents look exactly as before. Otherwise the parts of
+//! the document that belong to one folder become objects keyed by label:
+//! - `results` and `observed` (and `explain` and `waivers` when asked for) hold one list per slot;
+//! - `slots` lists each slot's `label`, `bins_dir`, and `status` (`ok` or `failed`);
+//! - `status` is `ok` only when every slot passed, and the exit code follows it.
+//!
+//! The daemon keeps its change detection and alerts per slot, so a tampered canary alerts while
+//! the other slots stay clean, and recovers on its own.
+
+use std::path::PathBuf;
+
+use crate::json_escape;
+
+/// One `--bins-dir`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinsSlot {
+    /// The name used in reports: the label given, or the path as written.
+    pub label: String,
+    pub dir: PathBuf,
+    /// Whether the label was given with `label=path`.
+    pub labeled: bool,
+}
+
+impl BinsSlot {
+    /// Read one `--bins-dir` value: `label=path` or just `path`.
+    pub fn parse(value: &str) -> Result<BinsSlot, String> {
+        let labeled = value.split_once('=').filter(|(label, _)| is_label(label));
+        match labeled {
+            Some((_, "")) => Err(format!("--bins-dir {value:?} has a label but no folder after the =")),
+            Some((label, dir)) => Ok(BinsSlot { label: label.to_string(), dir: PathBuf::from(dir), labeled: true }),
+            None if value.is_empty() => Err("--bins-dir must not be empty".to_string()),
+            None => Ok(BinsSlot { label: value.to_string(), dir: PathBuf::from(value), labeled: false }),
+        }
+    }
+}
+
+/// Letters, digits, `.`, `_`, and `-`, at least one of them.
+fn is_label(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+/// Read every `--bins-dir` value, in the order given. Two slots with the same label would
+/// overwrite each other in the report, so that is an error.
+pub fn parse_slots(values: &[String]) -> Result<Vec<BinsSlot>, String> {
+    let mut slots: Vec<BinsSlot> = Vec::new();
+    for value in values {
+        let slot = BinsSlot::parse(value)?;
+        if slots.iter().any(|other| other.label == slot.label) {
+            return Err(format!("--bins-dir label {:?} is used more than once", slot.label));
+        }
+        slots.push(slot);
+    }
+    Ok(slots)
+}
+
+/// Whether documents use the per-slot layout: more than one slot, or any label.
+pub fn is_multi(slots: &[BinsSlot]) -> bool {
+    slots.len() > 1 || slots.iter().any(|slot| slot.labeled)
+}
+
+/// One field's value: the first slot's `parts` value as it is for the single-slot layout, or
+/// `{"<label>":<value>,...}` for the per-slot one. Each value must already be JSON.
+pub fn keyed_json(multi: bool, parts: &[(&str, String)]) -> String {
+    if !multi {
+        return parts.first().map(|(_, value)| value.clone()).unwrap_or_else(|| "[]".to_string());
+    }
+    let fields: Vec<String> = parts.iter().map(|(label, value)| format!("\"{}\":{}", json_escape(label), value)).collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+/// The `slots` list: `[{"label":"canary","bins_dir":"/srv/canary/bin","status":"failed"},...]`.
+pub fn slots_json(slots: &[BinsSlot], passed: &[bool]) -> String {
+    let items: Vec<String> = slots
+        .iter()
+        .zip(passed)
+        .map(|(slot, passed)| {
+            format!(
+                "{{\"label\":\"{}\",\"bins_dir\":\"{}\",\"status\":\"{}\"}}",
+                json_escape(&slot.label),
+                json_escape(&slot.dir.to_string_lossy()),
+                if *passed { "ok" } else { "failed" }
+            )
+        })
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn labels_come_before_an_equals_sign_and_paths_stay_as_written() {
+        let slot = BinsSlot::parse("canary=/srv/canary/bin").unwrap();
+        assert_eq!(slot, BinsSlot { label: "canary".to_string(), dir: PathBuf::from("/srv/canary/bin"), labeled: true });
+        let slot = BinsSlot::parse("blue-2.a_b=rel/bin=x").unwrap();
+        assert_eq!((slot.label.as_str(), slot.dir), ("blue-2.a_b", PathBuf::from("rel/bin=x")));
+        // A `/` before the `=` makes the whole value a path.
+        for plain in ["./a=b", "/srv/x=y", "bin"] {
+            let slot = BinsSlot::parse(plain).unwrap();
+            assert_eq!((slot.label.as_str(), slot.dir, slot.labeled), (plain, PathBuf::from(plain), false), "{plain}");
+        }
+        assert_eq!(BinsSlot::parse("canary=").unwrap_err(), "--bins-dir \"canary=\" has a label but no folder after the =");
+        assert_eq!(BinsSlot::parse("").unwrap_err(), "--bins-dir must not be empty");
+        assert!(!BinsSlot::parse("=/srv/bin").unwrap().labeled, "an empty label is no label");
+    }
+
+    #[test]
+    fn slots_keep_their_order_and_labels_must_differ() {
+        let slots = parse_slots(&values(&["blue=/srv/blue", "green=/srv/green", "/srv/canary"])).unwrap();
+        assert_eq!(slots.iter().map(|slot| slot.label.as_str()).collect::<Vec<_>>(), ["blue", "green", "/srv/canary"]);
+        assert_eq!(parse_slots(&values(&["a=/x", "a=/y"])).unwrap_err(), "--bins-dir label \"a\" is used more than once");
+        assert!(parse_slots(&values(&["/x", "/x"])).is_err(), "the same path twice has the same label");
+
+        assert!(!is_multi(&parse_slots(&values(&["/srv/bin"])).unwrap()));
+        assert!(is_multi(&parse_slots(&values(&["main=/srv/bin"])).unwrap()), "a label asks for the per-slot layout");
+        assert!(is_multi(&slots));
+    }
+
+    #[test]
+    fn documents_key_each_slot_by_label_only_in_the_per_slot_layout() {
+        let parts = [("blue", "[\"a:match\"]".to_string()), ("canary", "[\"a:mismatch\"]".to_string())];
+        assert_eq!(keyed_json(false, &parts[..1]), "[\"a:match\"]");
+        assert_eq!(keyed_json(false, &[]), "[]");
+        assert_eq!(keyed_json(true, &parts), "{\"blue\":[\"a:match\"],\"canary\":[\"a:mismatch\"]}");
+
+        let slots = parse_slots(&values(&["blue=/srv/blue", "/srv/\"c"])).unwrap();
+        assert_eq!(
+            slots_json(&slots, &[true, false]),
+            "[{\"label\":\"blue\",\"bins_dir\":\"/srv/blue\",\"status\":\"ok\"},{\"label\":\"/srv/\\\"c\",\"bins_dir\":\"/srv/\\\"c\",\"status\":\"failed\"}]"
+        );
+    }
+}
@@ -0,0 +1,385 @@
+//! `status`: one answer to "is this host healthy?", gathered from the files Sentry already keeps.
+//!
+//! It reads, without changing anything:
+//! - **release**: the newest `omega-*` folder under `--releases-dir` (by the manifest's
+//!   `created_at_unix=`, or the manifest file's modification time, as `prune` orders them) and its
+//!   manifest.
+//! - **signature**: that folder's `manifest.txt.sig`. `build` writes a placeholder sentence there
+//!   until an operator adds the real detached signature, so the placeholder counts as unsigned.
+//!   Only the presence of a signature is checked here, not whether it is valid.
+//! - **last_report**: the document a `daemon --output <file>` (or `verify --output`) left behind,
+//!   when `--state-file` names it. Its `"results"` say whether the last pass passed, and its age
+//!   says whether the daemon is still writing it. A document for several `--bins-dir` slots keys
+//!   `"results"` by label; its entries are then counted as `<label>/<rel_path>`.
+//! - **hosts**: the three role addresses from the environment. They are shown for information and
+//!   never change the verdict, because Blue has no hosts on purpose.
+//!
+//! A piece that cannot be read is reported as `"unknown"` with a `"reason"` instead of stopping
+//! the command. Each piece gets a `Health`; the worst one decides `"overall"` and the exit code.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::json::{self, JsonValue};
+use crate::{json_escape, load_manifest_with, prune, Mode, OmegaEnvironment};
+
+/// What `build` leaves in `manifest.txt.sig` until a real signature replaces it.
+pub const SIGNATURE_PLACEHOLDER: &str = "Add detached signature from Sentry Blue here.";
+/// `--stale-after-seconds` when absent: three of the daemon's default 60-second passes, the same
+/// allowance the hub gives heartbeats.
+pub const DEFAULT_STALE_AFTER_SECS: u64 = 180;
+
+/// How one piece looks, from best to worst. `Ord` follows that order, so `max` is the worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Health {
+    Ok,
+    /// Not broken, but someone should look: no release, no real signature, an old report.
+    Warning,
+    /// The last report found binaries that do not match.
+    Failing,
+}
+
+impl Health {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Health::Ok => "ok",
+            Health::Warning => "warning",
+            Health::Failing => "failing",
+        }
+    }
+}
+
+/// One piece of the status.
+#[derive(Clone, Debug)]
+pub struct Component {
+    /// `release`, `signature`, or `last_report`.
+    pub name: &'static str,
+    /// Short word for the JSON `"state"`, e.g. `signed`, `placeholder`, `stale`, or `unknown`.
+    pub state: &'static str,
+    pub health: Health,
+    /// Extra JSON members, already rendered as `"key":value`.
+    fields: Vec<String>,
+    /// One line for people.
+    pub summary: String,
+}
+
+impl Component {
+    fn new(name: &'static str, state: &'static str, health: Health, summary: impl Into<String>) -> Self {
+        Self { name, state, health, fields: Vec::new(), summary: summary.into() }
+    }
+
+    /// A piece that could not be read; `reason` says why.
+    fn unknown(name: &'static str, health: Health, reason: impl Into<String>) -> Self {
+        let reason = reason.into();
+        Self::new(name, "unknown", health, reason.clone()).with("reason", &quote(&reason))
+    }
+
+    fn with(mut self, key: &str, json_value: &str) -> Self {
+        self.fields.push(format!("\"{}\":{}", key, json_value));
+        self
+    }
+
+    fn to_json(&self) -> String {
+        let mut members = vec![format!("\"state\":\"{}\"", self.state), format!("\"health\":\"{}\"", self.health.as_str())];
+        members.extend(self.fields.iter().cloned());
+        format!("{{{}}}", members.join(","))
+    }
+}
+
+/// Where to look and how old a report may be.
+#[derive(Clone, Debug)]
+pub struct StatusRequest {
+    pub releases_dir: PathBuf,
+    pub state_file: Option<PathBuf>,
+    pub stale_after_secs: u64,
+    /// The current time, so an old report can be recognised.
+    pub now_unix: u64,
+}
+
+/// Everything `status` found.
+#[derive(Clone, Debug)]
+pub struct HostStatus {
+    pub mode: Mode,
+    pub components: Vec<Component>,
+    hosts_json: String,
+    host_problems: Vec<String>,
+}
+
+impl HostStatus {
+    /// The worst health of any piece.
+    pub fn overall(&self) -> Health {
+        self.components.iter().map(|component| component.health).max().unwrap_or(Health::Ok)
+    }
+
+    pub fn to_json(&self) -> String {
+        let components: Vec<String> =
+            self.components.iter().map(|component| format!("\"{}\":{}", component.name, component.to_json())).collect();
+        let problems: Vec<String> = self.host_problems.iter().map(|problem| quote(problem)).collect();
+        format!(
+            "{{\"action\":\"status\",\"mode\":\"{}\",\"overall\":\"{}\",{},\"hosts\":{},\"host_problems\":[{}]}}",
+            self.mode.as_str(),
+            self.overall().as_str(),
+            components.join(","),
+            self.hosts_json,
+            problems.join(",")
+        )
+    }
+
+    /// A few aligned lines for a terminal, ending with the verdict.
+    pub fn render_summary(&self) -> String {
+        let mut text = String::new();
+        for component in &self.components {
+            text.push_str(&format!("{:<12} {:<8} {}\n", component.name, component.health.as_str(), component.summary));
+        }
+        let hosts = match self.host_problems.len() {
+            0 => "all configured".to_string(),
+            count => format!("{count} problem(s), listed under \"host_problems\""),
+        };
+        text.push_str(&format!("{:<12} {:<8} {}\n", "hosts", "info", hosts));
+        text.push_str(&format!("overall: {}\n", self.overall().as_str()));
+        text
+    }
+}
+
+/// Gather the status. Never fails: what cannot be read becomes an `unknown` piece.
+pub fn collect(mode: Mode, env_settings: &OmegaEnvironment, request: &StatusRequest) -> HostStatus {
+    let (release, release_id, folder) = release_component(&request.releases_dir);
+    let signature = match &folder {
+        Some(folder) => signature_component(folder),
+        None => Component::unknown("signature", Health::Warning, "no release to look in"),
+    };
+    let last_report = match &request.state_file {
+        Some(path) => report_component(path, release_id.as_deref(), request),
+        None => Component::unknown("last_report", Health::Ok, "no --state-file given"),
+    };
+    let hosts_json = format!(
+        "{{\"yellow\":{},\"red\":{},\"blue\":{}}}",
+        env_settings.yellow_host.to_json(),
+        env_settings.red_host.to_json(),
+        env_settings.blue_host.to_json()
+    );
+    HostStatus { mode, components: vec![release, signature, last_report], hosts_json, host_problems: env_settings.validate() }
+}
+
+/// The newest release, its id, and its folder.
+fn release_component(releases_dir: &Path) -> (Component, Option<String>, Option<PathBuf>) {
+    let newest = match prune::releases_newest_first(releases_dir) {
+        Ok((releases, _)) => releases.into_iter().next(),
+        Err(err) => return (Component::unknown("release", Health::Warning, err), None, None),
+    };
+    let Some(newest) = newest else {
+        let reason = format!("no omega-* release with a manifest.txt in {}", releases_dir.display());
+        return (Component::unknown("release", Health::Warning, reason), None, None);
+    };
+    // Only the header and the entry count are shown, so absolute entry paths are accepted here.
+    let manifest = match load_manifest_with(&newest.path.join("manifest.txt"), true) {
+        Ok(manifest) => manifest,
+        Err(err) => return (Component::unknown("release", Health::Warning, err.chain()), None, Some(newest.path)),
+    };
+    let summary = format!("{} ({} entries) in {}", manifest.release_id, manifest.entries.len(), newest.path.display());
+    let component = Component::new("release", "found", Health::Ok, summary)
+        .with("release_id", &quote(&manifest.release_id))
+        .with("folder", &quote(&newest.path.to_string_lossy()))
+        .with("created_at_unix", &newest.created_at_unix.to_string())
+        .with("entries", &manifest.entries.len().to_string());
+    (component, Some(manifest.release_id), Some(newest.path))
+}
+
+/// `signed` when `manifest.txt.sig` holds something other than the placeholder.
+fn signature_component(folder: &Path) -> Component {
+    let path = folder.join("manifest.txt.sig");
+    let path_json = quote(&path.to_string_lossy());
+    match fs::read_to_string(&path) {
+        Err(_) => Component::new("signature", "missing", Health::Warning, format!("{} does not exist", path.display()))
+            .with("path", &path_json),
+        Ok(text) if text.trim().is_empty() || text.trim() == SIGNATURE_PLACEHOLDER => {
+            Component::new("signature", "placeholder", Health::Warning, "manifest.txt.sig still holds the placeholder text")
+                .with("path", &path_json)
+        }
+        Ok(_) => Component::new("signature", "signed", Health::Ok, "manifest.txt.sig is present (not checked here)")
+            .with("path", &path_json),
+    }
+}
+
+/// Read the daemon's last document: `failing` beats `stale` beats `passing`.
+fn report_component(path: &Path, latest_release: Option<&str>, request: &StatusRequest) -> Component {
+    let path_json = quote(&path.to_string_lossy());
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs());
+    let (Some(modified), Ok(text)) = (modified, fs::read_to_string(path)) else {
+        return Component::unknown("last_report", Health::Warning, format!("{} cannot be read", path.display())).with("path", &path_json);
+    };
+    let document = match json::parse(&text) {
+        Ok(document) => document,
+        Err(err) => {
+            return Component::unknown("last_report", Health::Warning, format!("{} is not JSON: {err}", path.display()))
+                .with("path", &path_json)
+        }
+    };
+    let results: Vec<String> = match document.get("results") {
+        Some(JsonValue::Array(items)) => items.iter().filter_map(JsonValue::as_str).map(str::to_string).collect(),
+        Some(JsonValue::Object(slots)) => slots
+            .iter()
+            .flat_map(|(label, items)| items.as_array().unwrap_or(&[]).iter().filter_map(JsonValue::as_str).map(move |item| format!("{label}/{item}")))
+            .collect(),
+        _ => {
+            let reason = format!("{} has no \"results\"; point --state-file at a verify or daemon --output file", path.display());
+            return Component::unknown("last_report", Health::Warning, reason).with("path", &path_json);
+        }
+    };
+
+    // Same rule as `BinCheck::matched`: a waived entry or a missing per-file signature still passes.
+    let failed: Vec<&str> = results
+        .iter()
+        .map(String::as_str)
+        .filter(|result| !matches!(result.rsplit_once(':'), Some((_, "match" | "waived" | "sig-missing"))))
+        .collect();
+    let age = request.now_unix.saturating_sub(modified);
+    let release_id = document.get("release_id").and_then(JsonValue::as_str).unwrap_or("");
+    let same_release = latest_release.is_none_or(|latest| latest == release_id);
+
+    let (state, health, summary) = if !failed.is_empty() {
+        ("failing", Health::Failing, format!("{} of {} entries failed: {}", failed.len(), results.len(), failed.join(", ")))
+    } else if age > request.stale_after_secs {
+        ("stale", Health::Warning, format!("passed, but written {age}s ago (limit {}s); is the daemon running?", request.stale_after_secs))
+    } else if !same_release {
+        ("other-release", Health::Warning, format!("passed for {release_id}, which is not the newest release"))
+    } else {
+        ("passing", Health::Ok, format!("all {} entries passed {age}s ago", results.len()))
+    };
+    let failed_json: Vec<String> = failed.iter().map(|result| quote(result)).collect();
+    Component::new("last_report", state, health, summary)
+        .with("path", &path_json)
+        .with("release_id", &quote(release_id))
+        .with("age_seconds", &age.to_string())
+        .with("entries", &results.len().to_string())
+        .with("failed", &format!("[{}]", failed_json.join(",")))
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", json_escape(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::runtime::MapEnv;
+    use crate::{build_manifest, persist_manifest, provenance, DigestAlgorithm};
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sentry-status-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Record `base/bins` as release `release_id` created at `created_at`, with the placeholder
+    /// signature `persist_manifest` writes; returns the release folder.
+    fn release(base: &Path, release_id: &str, created_at: u64) -> PathBuf {
+        let bins_dir = base.join("bins");
+        fs::create_dir_all(&bins_dir).unwrap();
+        fs::write(bins_dir.join("squire"), b"squire v1").unwrap();
+        let provenance = provenance::Provenance::collect(Some("rustc 1.80.0"), None);
+        let mut manifest = build_manifest(Mode::Blue, &bins_dir, release_id.to_string(), provenance, true, &[DigestAlgorithm::Sha256], None).unwrap();
+        manifest.created_at_unix = Some(created_at);
+        persist_manifest(&manifest, &base.join("releases")).unwrap().0
+    }
+
+    /// A daemon document for `release_id`, last written at `modified_unix`.
+    fn state_file(base: &Path, release_id: &str, results: &[&str], modified_unix: u64) -> PathBuf {
+        let path = base.join("daemon.json");
+        let results: Vec<String> = results.iter().map(|result| format!("\"{result}\"")).collect();
+        fs::write(&path, format!("{{\"action\":\"daemon\",\"release_id\":\"{release_id}\",\"results\":[{}]}}", results.join(","))).unwrap();
+        File::options().write(true).open(&path).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(modified_unix)).unwrap();
+        path
+    }
+
+    fn status(base: &Path, state_file: Option<PathBuf>) -> HostStatus {
+        let request = StatusRequest { releases_dir: base.join("releases"), state_file, stale_after_secs: DEFAULT_STALE_AFTER_SECS, now_unix: NOW };
+        let env_settings = OmegaEnvironment::from_env(&MapEnv::new().with("SENTRY_RED_HOST", "red.local")).unwrap();
+        collect(Mode::Yellow, &env_settings, &request)
+    }
+
+    fn states(found: &HostStatus) -> Vec<(&str, &str, Health)> {
+        found.components.iter().map(|component| (component.name, component.state, component.health)).collect()
+    }
+
+    #[test]
+    fn a_healthy_host_is_ok_everywhere() {
+        let base = temp_dir("healthy");
+        release(&base, "r0", NOW - 86_400);
+        let folder = release(&base, "r1", NOW - 3_600);
+        fs::write(folder.join("manifest.txt.sig"), "-----BEGIN SSH SIGNATURE-----\n...\n").unwrap();
+        let found = status(&base, Some(state_file(&base, "r1", &["squire:match", "bard:waived"], NOW - 30)));
+        assert_eq!(states(&found), [("release", "found", Health::Ok), ("signature", "signed", Health::Ok), ("last_report", "passing", Health::Ok)]);
+        assert_eq!(found.overall(), Health::Ok);
+
+        let document = json::parse(&found.to_json()).unwrap();
+        assert_eq!(document.get("overall").and_then(JsonValue::as_str), Some("ok"));
+        let release = document.get("release").unwrap();
+        assert_eq!(release.get("release_id").and_then(JsonValue::as_str), Some("r1"), "the newest release");
+        assert_eq!(release.get("created_at_unix").and_then(JsonValue::as_f64), Some((NOW - 3_600) as f64));
+        assert_eq!(document.get("last_report").and_then(|report| report.get("age_seconds")).and_then(JsonValue::as_f64), Some(30.0));
+        let hosts = document.get("hosts").unwrap();
+        assert_eq!(hosts.get("red").and_then(|red| red.get("host")).and_then(JsonValue::as_str), Some("red.local"));
+        assert!(found.render_summary().ends_with("overall: ok\n"));
+    }
+
+    #[test]
+    fn a_missing_or_placeholder_signature_is_a_warning() {
+        let base = temp_dir("signature");
+        let folder = release(&base, "r1", NOW - 60);
+        assert_eq!(states(&status(&base, None))[1], ("signature", "placeholder", Health::Warning));
+        fs::remove_file(folder.join("manifest.txt.sig")).unwrap();
+        let found = status(&base, None);
+        assert_eq!(states(&found)[1], ("signature", "missing", Health::Warning));
+        assert_eq!(states(&found)[2], ("last_report", "unknown", Health::Ok), "no --state-file is not a problem");
+        assert_eq!(found.overall(), Health::Warning);
+    }
+
+    #[test]
+    fn old_failing_and_unreadable_reports() {
+        let base = temp_dir("reports");
+        let folder = release(&base, "r1", NOW - 60);
+        fs::write(folder.join("manifest.txt.sig"), "signed").unwrap();
+
+        let stale = status(&base, Some(state_file(&base, "r1", &["squire:match"], NOW - DEFAULT_STALE_AFTER_SECS - 1)));
+        assert_eq!(states(&stale)[2], ("last_report", "stale", Health::Warning));
+        let fresh = status(&base, Some(state_file(&base, "r1", &["squire:match"], NOW - DEFAULT_STALE_AFTER_SECS)));
+        assert_eq!(states(&fresh)[2], ("last_report", "passing", Health::Ok));
+        let other = status(&base, Some(state_file(&base, "r0", &["squire:match"], NOW)));
+        assert_eq!(states(&other)[2], ("last_report", "other-release", Health::Warning));
+
+        // Failing wins over stale, and slots are counted as label/rel_path.
+        let path = state_file(&base, "r1", &[], NOW - 10_000);
+        fs::write(&path, r#"{"release_id":"r1","results":{"blue":["squire:match"],"green":["squire:mismatch"]}}"#).unwrap();
+        let failing = status(&base, Some(path));
+        assert_eq!(states(&failing)[2], ("last_report", "failing", Health::Failing));
+        assert_eq!(failing.overall(), Health::Failing);
+        assert!(failing.to_json().contains("\"failed\":[\"green/squire:mismatch\"]"));
+
+        fs::write(base.join("notes.txt"), "not json").unwrap();
+        assert_eq!(states(&status(&base, Some(base.join("notes.txt"))))[2], ("last_report", "unknown", Health::Warning));
+        assert_eq!(states(&status(&base, Some(base.join("absent.json"))))[2], ("last_report", "unknown", Health::Warning));
+    }
+
+    #[test]
+    fn an_empty_releases_dir_is_unknown_not_an_error() {
+        let base = temp_dir("empty");
+        fs::create_dir_all(base.join("releases/omega-no-manifest")).unwrap();
+        let found = status(&base, None);
+        assert_eq!(states(&found)[..2], [("release", "unknown", Health::Warning), ("signature", "unknown", Health::Warning)]);
+        let document = json::parse(&found.to_json()).unwrap();
+        assert!(document.get("release").and_then(|release| release.get("reason")).and_then(JsonValue::as_str).unwrap().starts_with("no omega-* release"));
+        assert_eq!(states(&status(&base.join("nowhere"), None))[0].1, "unknown");
+    }
+}
@@ -0,0 +1,211 @@
+//! A tiny HTTP/1.1 status endpoint for `daemon --listen`, written with `std::net` only.
+//!
+//! Monitoring tools scrape two paths:
+//! - `GET /status` returns the latest verification JSON (503 until the first pass finishes).
+//! - `GET /healthz` returns 200 when the last pass matched everything and 503 otherwise.
+//!
+//! Anything else gets a 404. The server runs on its own thread and answers one connection at a
+//! time; each connection has a read timeout so a client that never finishes its request cannot
+//! freeze the endpoint. Dropping the `StatusServer` handle stops the thread.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::log::Logger;
+
+const LOG: Logger = Logger::new("sentry-status");
+
+/// How long a client may take to send its request or read the reply.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+/// How often the accept loop checks whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Requests are tiny; anything larger than this is cut off.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// What the daemon last saw. The daemon writes it after every pass; the server only reads it.
+#[derive(Clone, Debug, Default)]
+pub struct StatusSnapshot {
+    /// Compact JSON of the latest pass, or `None` before the first pass completes.
+    pub document: Option<String>,
+    /// True when every entry in the latest pass matched.
+    pub healthy: bool,
+}
+
+/// The snapshot shared between the daemon loop and the server thread.
+pub type SharedStatus = Arc<Mutex<StatusSnapshot>>;
+
+/// Handle for a running server. Dropping it asks the thread to stop and waits for it.
+pub struct StatusServer {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl StatusServer {
+    /// Bind `address` (for example `127.0.0.1:9464`) and start answering on a background thread.
+    pub fn start(address: &str, status: SharedStatus) -> Result<Self, String> {
+        let listener = TcpListener::bind(address).map_err(|err| format!("Unable to listen on {address}: {err}"))?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|err| format!("Unable to read listen address: {err}"))?;
+        // Non-blocking accept lets the loop notice `stop` instead of waiting forever for a client.
+        listener
+            .set_nonblocking(true)
+            .map_err(|err| format!("Unable to configure listener: {err}"))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let handle = thread::spawn(move || serve(listener, status, thread_stop));
+
+        Ok(Self { local_addr, stop, handle: Some(handle) })
+    }
+
+    /// The address actually bound, useful when the caller asked for port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for StatusServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve(listener: TcpListener, status: SharedStatus, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(err) = handle_client(stream, &status) {
+                    LOG.warn("Status request failed", &[("error", &err.to_string())]);
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(err) => {
+                LOG.error("Status endpoint accept failed", &[("error", &err.to_string())]);
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+fn handle_client(mut stream: TcpStream, status: &SharedStatus) -> std::io::Result<()> {
+    // Accepted sockets may inherit non-blocking mode; switch back so the timeouts apply.
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let request_line = read_request_line(&mut stream)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+
+    // Copy what we need and release the lock before touching the network again.
+    let snapshot = status.lock().map(|guard| guard.clone()).unwrap_or_default();
+
+    let (code, content_type, body) = match (method, path) {
+        ("GET", "/status") => match snapshot.document {
+            Some(document) => (200, "application/json", format!("{document}\n")),
+            None => (503, "application/json", "{\"status\":\"starting\"}\n".to_string()),
+        },
+        ("GET", "/healthz") if snapshot.document.is_none() => (503, "text/plain", "starting\n".to_string()),
+        ("GET", "/healthz") if snapshot.healthy => (200, "text/plain", "ok\n".to_string()),
+        ("GET", "/healthz") => (503, "text/plain", "mismatch\n".to_string()),
+        _ => (404, "text/plain", "not found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason_phrase(code),
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+/// Read until the end of the headers (or the size cap) and return the first line.
+fn read_request_line(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while request.len() < MAX_REQUEST_BYTES && !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let text = String::from_utf8_lossy(&request);
+    Ok(text.lines().next().unwrap_or("").to_string())
+}
+
+fn reason_phrase(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        404 => "Not Found",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send one raw request and return `(status code, body)`.
+    fn get(server: &StatusServer, request: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let code = response.split_whitespace().nth(1).unwrap().parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (code, body)
+    }
+
+    fn request(path: &str) -> String {
+        format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n")
+    }
+
+    #[test]
+    fn endpoints_follow_the_latest_pass() {
+        let status = SharedStatus::default();
+        let server = StatusServer::start("127.0.0.1:0", Arc::clone(&status)).unwrap();
+
+        assert_eq!(get(&server, &request("/status")), (503, "{\"status\":\"starting\"}\n".to_string()));
+        assert_eq!(get(&server, &request("/healthz")), (503, "starting\n".to_string()));
+
+        *status.lock().unwrap() = StatusSnapshot { document: Some("{\"status\":\"ok\"}".to_string()), healthy: true };
+        assert_eq!(get(&server, &request("/status")), (200, "{\"status\":\"ok\"}\n".to_string()));
+        assert_eq!(get(&server, &request("/healthz")), (200, "ok\n".to_string()));
+
+        // A mismatch on the next pass flips the health check; the document follows it.
+        *status.lock().unwrap() = StatusSnapshot { document: Some("{\"status\":\"mismatch\"}".to_string()), healthy: false };
+        assert_eq!(get(&server, &request("/healthz")), (503, "mismatch\n".to_string()));
+        assert_eq!(get(&server, &request("/status")).1, "{\"status\":\"mismatch\"}\n");
+    }
+
+    #[test]
+    fn other_paths_and_methods_are_not_found() {
+        let server = StatusServer::start("127.0.0.1:0", SharedStatus::default()).unwrap();
+        assert_eq!(get(&server, &request("/metrics")).0, 404);
+        assert_eq!(get(&server, "POST /status HTTP/1.1\r\n\r\n").0, 404);
+    }
+
+    #[test]
+    fn a_busy_address_is_an_error() {
+        let first = StatusServer::start("127.0.0.1:0", SharedStatus::default()).unwrap();
+        let err = StatusServer::start(&first.local_addr().to_string(), SharedStatus::default()).err().unwrap();
+        assert!(err.starts_with("Unable to listen on"), "{err}");
+    }
+}
@@ -0,0 +1,608 @@
+//! Moving a release bundle from Blue to Yellow over TCP, in checked chunks that can resume.
+//!
+//! Blue builds without a network, but the finished release still has to reach Yellow, usually
+//! over a one-way staging link that drops now and then. `push-release` sends the release's bundle
+//! (`omega-<id>.tar`, written by `build --bundle`: manifest, signatures, and binaries in one file)
+//! and `receive-release` takes it in. Copying the file by hand worked too, but nothing checked it
+//! on the way and a dropped link meant starting over.
+//!
+//! The two sides talk in frames. Every frame is one kind byte, a 4-byte big-endian payload length,
+//! and the payload:
+//!
+//! ```text
+//! H hello     pusher -> receiver   total size (8) | SHA-256 of the whole file (32) | resume flag (1) | file name
+//! A ack       receiver -> pusher   offset (8): every byte before it is safely on disk
+//! C chunk     pusher -> receiver   offset (8) | SHA-256 of the data (32) | data
+//! F finish    pusher -> receiver   (empty) all bytes sent, please check and install
+//! K done      receiver -> pusher   the receiver's JSON document for the installed release
+//! E refused   receiver -> pusher   why the transfer or the release was refused
+//! ```
+//!
+//! The receiver checks every chunk on arrival: it must start where the last one ended and match
+//! its SHA-256. A good chunk is appended, flushed to disk, and acknowledged; a bad one ends the
+//! connection with `E`, and nothing after the last acknowledged byte is kept.
+//!
+//! Partial uploads wait in `<releases-dir>/.incoming/<sha256>.part`, named by the whole file's
+//! hash, with the acknowledged length beside them in `<sha256>.acked`. With `--resume` the pusher
+//! asks for that length and continues from there, and it reconnects by itself (up to
+//! `RESUME_ATTEMPTS` times) when the link drops. Without `--resume` every push starts at byte 0.
+//!
+//! After `F` the receiver checks the whole file's hash, and the caller's `install` step unpacks
+//! and verifies the bundle in a staging folder before moving it into place (see `run_cli`). A
+//! release that does not verify is never moved into `--releases-dir`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::atomic;
+use crate::log::Logger;
+use crate::sha256;
+
+const LOG: Logger = Logger::new("sentry-transfer");
+
+/// `--chunk-size` when not given.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+/// The largest chunk either side accepts, so a damaged length cannot make the receiver allocate
+/// gigabytes.
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+/// How many times `push` reconnects after a dropped link when resuming.
+pub const RESUME_ATTEMPTS: u32 = 5;
+/// Folder under `--releases-dir` for partial uploads and staging folders.
+pub const INCOMING_DIR: &str = ".incoming";
+/// How long either side waits for the other before giving up on a connection.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+/// Pause before reconnecting; doubles on each attempt.
+const FIRST_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// Offset, hash, and a little room on top of the largest chunk.
+const MAX_FRAME_PAYLOAD: usize = MAX_CHUNK_SIZE + 64;
+
+/// One message on the wire (see the table at the top of this file).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    Hello { name: String, total: u64, sha256: [u8; 32], resume: bool },
+    Ack { offset: u64 },
+    Chunk { offset: u64, sha256: [u8; 32], data: Vec<u8> },
+    Finish,
+    Done { document: String },
+    Refused { reason: String },
+}
+
+impl Frame {
+    /// A chunk of `data` starting at `offset`, with its hash filled in.
+    pub fn chunk(offset: u64, data: &[u8]) -> Frame {
+        Frame::Chunk { offset, sha256: sha256::sha256(data), data: data.to_vec() }
+    }
+
+    /// Kind byte, length, and payload, ready to write.
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, payload) = match self {
+            Frame::Hello { name, total, sha256, resume } => {
+                let mut payload = total.to_be_bytes().to_vec();
+                payload.extend_from_slice(sha256);
+                payload.push(u8::from(*resume));
+                payload.extend_from_slice(name.as_bytes());
+                (b'H', payload)
+            }
+            Frame::Ack { offset } => (b'A', offset.to_be_bytes().to_vec()),
+            Frame::Chunk { offset, sha256, data } => {
+                let mut payload = offset.to_be_bytes().to_vec();
+                payload.extend_from_slice(sha256);
+                payload.extend_from_slice(data);
+                (b'C', payload)
+            }
+            Frame::Finish => (b'F', Vec::new()),
+            Frame::Done { document } => (b'K', document.as_bytes().to_vec()),
+            Frame::Refused { reason } => (b'E', reason.as_bytes().to_vec()),
+        };
+        let mut bytes = vec![kind];
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    /// Read one frame. A clean end of the stream before the kind byte is `UnexpectedEof`.
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Frame> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if length > MAX_FRAME_PAYLOAD {
+            return Err(invalid(format!("frame of {length} bytes is larger than the {MAX_FRAME_PAYLOAD} byte limit")));
+        }
+        let mut payload = vec![0u8; length];
+        reader.read_exact(&mut payload)?;
+        let text = |bytes: &[u8]| String::from_utf8(bytes.to_vec()).map_err(|_| invalid("frame text is not UTF-8".to_string()));
+        match header[0] {
+            b'H' if length >= 41 => Ok(Frame::Hello {
+                total: read_u64(&payload[0..8]),
+                sha256: read_hash(&payload[8..40]),
+                resume: payload[40] != 0,
+                name: text(&payload[41..])?,
+            }),
+            b'A' if length == 8 => Ok(Frame::Ack { offset: read_u64(&payload) }),
+            b'C' if length >= 40 => Ok(Frame::Chunk {
+                offset: read_u64(&payload[0..8]),
+                sha256: read_hash(&payload[8..40]),
+                data: payload[40..].to_vec(),
+            }),
+            b'F' if length == 0 => Ok(Frame::Finish),
+            b'K' => Ok(Frame::Done { document: text(&payload)? }),
+            b'E' => Ok(Frame::Refused { reason: text(&payload)? }),
+            kind => Err(invalid(format!("unknown or malformed frame {:?} ({length} bytes)", kind as char))),
+        }
+    }
+
+    /// Write the frame and flush it.
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.encode())?;
+        writer.flush()
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buffer = [0u8; 8];
+    buffer.copy_from_slice(bytes);
+    u64::from_be_bytes(buffer)
+}
+
+fn read_hash(bytes: &[u8]) -> [u8; 32] {
+    let mut buffer = [0u8; 32];
+    buffer.copy_from_slice(bytes);
+    buffer
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// `push-release` settings.
+#[derive(Clone, Copy, Debug)]
+pub struct PushOptions {
+    pub chunk_size: usize,
+    /// Continue from the receiver's acknowledged offset, and reconnect after a dropped link.
+    pub resume: bool,
+}
+
+impl Default for PushOptions {
+    fn default() -> Self {
+        Self { chunk_size: DEFAULT_CHUNK_SIZE, resume: false }
+    }
+}
+
+/// What happened to one push, rendered into the JSON output.
+#[derive(Clone, Debug)]
+pub struct PushReport {
+    pub target: String,
+    pub bundle: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+    /// The offset the first connection started from (non-zero only with `--resume`).
+    pub resumed_from: u64,
+    /// Connections used, including reconnects.
+    pub connections: u32,
+    /// The receiver's document when it installed the release.
+    pub installed: Option<String>,
+    /// Why the receiver refused the transfer or the release.
+    pub refused: Option<String>,
+}
+
+impl PushReport {
+    /// `{"target":...,"bundle":...,"size":N,"sha256":...,"resumed_from":N,"connections":N,"status":"installed|refused",...}`.
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"target\":\"{}\",\"bundle\":\"{}\",\"size\":{},\"sha256\":\"{}\",\"resumed_from\":{},\"connections\":{},\"status\":\"{}\"",
+            crate::json_escape(&self.target),
+            crate::json_escape(&self.bundle.to_string_lossy()),
+            self.size,
+            self.sha256,
+            self.resumed_from,
+            self.connections,
+            if self.installed.is_some() { "installed" } else { "refused" }
+        );
+        if let Some(document) = &self.installed {
+            json.push_str(&format!(",\"receiver\":{document}"));
+        }
+        if let Some(reason) = &self.refused {
+            json.push_str(&format!(",\"error\":\"{}\"", crate::json_escape(reason)));
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// How one connection of a push ended.
+enum Attempt {
+    /// The receiver answered `K` or `E`.
+    Answered { started_at: u64, installed: Option<String>, refused: Option<String> },
+    /// The link dropped; `started_at` is where this connection began.
+    Dropped { started_at: u64, error: String },
+}
+
+/// Send the bundle at `bundle_path` to `target` (`host:port`). Returns an error only when the
+/// receiver could not be reached or the link kept dropping; a refusal is part of the report.
+pub fn push(target: &str, bundle_path: &Path, options: PushOptions) -> Result<PushReport, String> {
+    let data = fs::read(bundle_path).map_err(|err| format!("Unable to read bundle {:?}: {err}", bundle_path))?;
+    let name = bundle_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let hello = Frame::Hello { name, total: data.len() as u64, sha256: sha256::sha256(&data), resume: options.resume };
+    let chunk_size = options.chunk_size.clamp(1, MAX_CHUNK_SIZE);
+    let mut report = PushReport {
+        target: target.to_string(),
+        bundle: bundle_path.to_path_buf(),
+        size: data.len() as u64,
+        sha256: sha256::sha256_hex(&data),
+        resumed_from: 0,
+        connections: 0,
+        installed: None,
+        refused: None,
+    };
+
+    let mut delay = FIRST_RECONNECT_DELAY;
+    loop {
+        report.connections += 1;
+        let attempt = push_once(target, &hello, &data, chunk_size);
+        let started_at = match &attempt {
+            Attempt::Answered { started_at, .. } | Attempt::Dropped { started_at, .. } => *started_at,
+        };
+        if report.connections == 1 {
+            report.resumed_from = started_at;
+        }
+        match attempt {
+            Attempt::Answered { installed, refused, .. } => {
+                report.installed = installed;
+                report.refused = refused;
+                return Ok(report);
+            }
+            Attempt::Dropped { error, .. } if !options.resume || report.connections > RESUME_ATTEMPTS => {
+                return Err(format!("Push to {target} failed after {} connection(s): {error}", report.connections));
+            }
+            Attempt::Dropped { error, .. } => {
+                LOG.warn("Link dropped; reconnecting to resume", &[("target", target), ("error", &error)]);
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+            }
+        }
+    }
+}
+
+/// One connection: say hello, send every chunk after the offset the receiver acknowledges, and
+/// wait for its verdict.
+fn push_once(target: &str, hello: &Frame, data: &[u8], chunk_size: usize) -> Attempt {
+    let mut started_at = 0;
+    let result = (|| -> Result<Attempt, String> {
+        let mut stream = connect(target)?;
+        let io_failed = |err: io::Error| format!("Connection to {target} failed: {err}");
+        hello.write_to(&mut stream).map_err(io_failed)?;
+        let mut offset = match Frame::read_from(&mut stream).map_err(io_failed)? {
+            Frame::Ack { offset } if offset <= data.len() as u64 => offset,
+            Frame::Refused { reason } => return Ok(Attempt::Answered { started_at, installed: None, refused: Some(reason) }),
+            other => return Err(format!("{target} answered the hello with {other:?}")),
+        };
+        started_at = offset;
+        if offset > 0 {
+            LOG.info("Resuming upload", &[("target", target), ("offset", &offset.to_string())]);
+        }
+        while offset < data.len() as u64 {
+            let end = (offset as usize + chunk_size).min(data.len());
+            Frame::chunk(offset, &data[offset as usize..end]).write_to(&mut stream).map_err(io_failed)?;
+            match Frame::read_from(&mut stream).map_err(io_failed)? {
+                Frame::Ack { offset: acked } if acked == end as u64 => offset = acked,
+                Frame::Refused { reason } => return Ok(Attempt::Answered { started_at, installed: None, refused: Some(reason) }),
+                other => return Err(format!("{target} answered a chunk with {other:?}")),
+            }
+        }
+        Frame::Finish.write_to(&mut stream).map_err(io_failed)?;
+        match Frame::read_from(&mut stream).map_err(io_failed)? {
+            Frame::Done { document } => Ok(Attempt::Answered { started_at, installed: Some(document), refused: None }),
+            Frame::Refused { reason } => Ok(Attempt::Answered { started_at, installed: None, refused: Some(reason) }),
+            other => Err(format!("{target} answered the finish with {other:?}")),
+        }
+    })();
+    result.unwrap_or_else(|error| Attempt::Dropped { started_at, error })
+}
+
+fn connect(target: &str) -> Result<TcpStream, String> {
+    let addresses = target.to_socket_addrs().map_err(|err| format!("Unable to resolve {target}: {err}"))?;
+    let mut last_error = format!("{target} did not resolve to any address");
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, IO_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(IO_TIMEOUT)).map_err(|err| format!("Unable to set read timeout: {err}"))?;
+                stream.set_write_timeout(Some(IO_TIMEOUT)).map_err(|err| format!("Unable to set write timeout: {err}"))?;
+                return Ok(stream);
+            }
+            Err(err) => last_error = format!("Unable to connect to {target}: {err}"),
+        }
+    }
+    Err(last_error)
+}
+
+/// A bundle that arrived whole and matched its hash, waiting for `install`.
+#[derive(Clone, Debug)]
+pub struct ReceivedBundle {
+    /// The complete file, still in `.incoming`.
+    pub path: PathBuf,
+    /// The name the pusher gave it, e.g. `omega-20260101-abcdef012345.tar`.
+    pub name: String,
+    /// Hex SHA-256 of the whole file.
+    pub sha256: String,
+    /// Where `install` should unpack and verify, inside `.incoming`.
+    pub staging_dir: PathBuf,
+}
+
+/// How one connection to `receive-release` ended.
+#[derive(Debug)]
+pub enum Received {
+    /// The release was installed; holds the document sent back to the pusher.
+    Installed(String),
+    /// The transfer or the release was refused; holds the reason sent back.
+    Refused(String),
+    /// The link dropped before `F`. The acknowledged bytes stay for a `--resume`.
+    Incomplete(String),
+}
+
+/// Serve one pusher on `stream`. `install` is called once the whole bundle has arrived and
+/// matched its hash; it returns the document for an installed release or the reason it was refused.
+pub fn receive(stream: &mut TcpStream, releases_dir: &Path, install: &dyn Fn(&ReceivedBundle) -> Result<String, String>) -> Received {
+    let _ = stream.set_read_timeout(Some(IO_TIMEOUT));
+    let _ = stream.set_write_timeout(Some(IO_TIMEOUT));
+    let outcome = serve(stream, releases_dir, install);
+    let reply = match &outcome {
+        Received::Installed(document) => Some(Frame::Done { document: document.clone() }),
+        Received::Refused(reason) => Some(Frame::Refused { reason: reason.clone() }),
+        Received::Incomplete(_) => None,
+    };
+    if let Some(frame) = reply {
+        // The pusher may already be gone; its next run asks again.
+        let _ = frame.write_to(stream);
+    }
+    outcome
+}
+
+fn serve(stream: &mut TcpStream, releases_dir: &Path, install: &dyn Fn(&ReceivedBundle) -> Result<String, String>) -> Received {
+    let dropped = |err: io::Error| Received::Incomplete(format!("Connection dropped: {err}"));
+    let (name, total, expected_hash, resume) = match Frame::read_from(stream) {
+        Ok(Frame::Hello { name, total, sha256, resume }) => (name, total, sha256, resume),
+        Ok(other) => return Received::Refused(format!("Expected a hello, got {other:?}")),
+        Err(err) => return dropped(err),
+    };
+    let incoming = releases_dir.join(INCOMING_DIR);
+    if let Err(err) = fs::create_dir_all(&incoming) {
+        return Received::Refused(format!("Unable to create {:?}: {err}", incoming));
+    }
+    let hash_hex = sha256::to_hex(&expected_hash);
+    let part_path = incoming.join(format!("{hash_hex}.part"));
+    let acked_path = incoming.join(format!("{hash_hex}.acked"));
+    let wanted = if resume { read_acked(&acked_path).min(total) } else { 0 };
+    let (mut part, mut offset) = match open_part(&part_path, wanted) {
+        Ok(opened) => opened,
+        Err(err) => return Received::Refused(format!("Unable to prepare {:?}: {err}", part_path)),
+    };
+    LOG.info("Receiving release bundle", &[("name", &name), ("size", &total.to_string()), ("from_offset", &offset.to_string())]);
+    if let Err(err) = (Frame::Ack { offset }).write_to(stream) {
+        return dropped(err);
+    }
+
+    loop {
+        match Frame::read_from(stream) {
+            Ok(Frame::Chunk { offset: at, sha256: chunk_hash, data }) => {
+                if at != offset {
+                    return Received::Refused(format!("Chunk starts at byte {at}, but the upload is at byte {offset}"));
+                }
+                if data.len() > MAX_CHUNK_SIZE || offset + data.len() as u64 > total {
+                    return Received::Refused(format!("Chunk at byte {at} runs past the announced size of {total} bytes"));
+                }
+                if !sha256::constant_time_eq(&sha256::sha256(&data), &chunk_hash) {
+                    LOG.warn("Chunk failed its hash check", &[("name", &name), ("offset", &at.to_string())]);
+                    return Received::Refused(format!("Chunk at byte {at} failed its SHA-256 check; nothing after byte {offset} was kept"));
+                }
+                // On disk before the ack, so an acknowledged byte is never lost.
+                let written = part.write_all(&data).and_then(|_| part.sync_data());
+                offset += data.len() as u64;
+                let recorded = written.and_then(|_| atomic::atomic_write(&acked_path, format!("{offset}\n").as_bytes()));
+                if let Err(err) = recorded {
+                    return Received::Refused(format!("Unable to store the chunk at byte {at}: {err}"));
+                }
+                if let Err(err) = (Frame::Ack { offset }).write_to(stream) {
+                    return dropped(err);
+                }
+            }
+            Ok(Frame::Finish) => break,
+            Ok(other) => return Received::Refused(format!("Expected a chunk or finish, got {other:?}")),
+            Err(err) => return dropped(err),
+        }
+    }
+
+    if offset != total {
+        return Received::Refused(format!("Finished at byte {offset} of {total}"));
+    }
+    drop(part);
+    let whole_hash = match fs::read(&part_path) {
+        Ok(bytes) => sha256::sha256(&bytes),
+        Err(err) => return Received::Refused(format!("Unable to read back {:?}: {err}", part_path)),
+    };
+    // The upload is over either way; a file that does not match is useless for a later resume.
+    let _ = fs::remove_file(&acked_path);
+    if !sha256::constant_time_eq(&whole_hash, &expected_hash) {
+        let _ = fs::remove_file(&part_path);
+        return Received::Refused("The whole bundle does not match its announced SHA-256".to_string());
+    }
+    let bundle = ReceivedBundle { path: part_path.clone(), name, sha256: hash_hex.clone(), staging_dir: incoming.join(format!("{hash_hex}.staging")) };
+    let result = install(&bundle);
+    // `install` moves what it keeps; whatever is left belongs to a refused release.
+    let _ = fs::remove_file(&part_path);
+    let _ = fs::remove_dir_all(&bundle.staging_dir);
+    match result {
+        Ok(document) => Received::Installed(document),
+        Err(reason) => Received::Refused(reason),
+    }
+}
+
+/// The acknowledged length of an earlier upload, or 0 when there is none.
+fn read_acked(path: &Path) -> u64 {
+    fs::read_to_string(path).ok().and_then(|text| text.trim().parse().ok()).unwrap_or(0)
+}
+
+/// Open the partial file cut to `offset` (bytes after the last ack may be half a chunk) and
+/// return it with the offset it really holds, which is less when the file is shorter.
+fn open_part(path: &Path, offset: u64) -> io::Result<(File, u64)> {
+    let mut file = OpenOptions::new().create(true).write(true).truncate(false).open(path)?;
+    let offset = offset.min(file.metadata()?.len());
+    file.set_len(offset)?;
+    file.seek(SeekFrom::Start(offset))?;
+    Ok((file, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sentry-transfer-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// `len` bytes that differ from chunk to chunk, so a misplaced chunk changes the hash.
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|at| (at * 7 % 251) as u8).collect()
+    }
+
+    /// Accept one connection on a fresh localhost port and serve it with `receive`, installing by
+    /// copying the bundle to `<releases_dir>/.incoming/installed.tar`.
+    fn receiver(releases_dir: &Path) -> (String, thread::JoinHandle<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let releases_dir = releases_dir.to_path_buf();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let install = |bundle: &ReceivedBundle| {
+                fs::copy(&bundle.path, bundle.path.with_file_name("installed.tar")).map_err(|err| err.to_string())?;
+                Ok(format!("{{\"name\":\"{}\"}}", bundle.name))
+            };
+            receive(&mut stream, &releases_dir, &install)
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn every_frame_survives_encode_and_read() {
+        let frames = [
+            Frame::Hello { name: "omega-r1.tar".to_string(), total: 1 << 40, sha256: [9; 32], resume: true },
+            Frame::Ack { offset: 65_536 },
+            Frame::chunk(4_096, b"some bytes"),
+            Frame::Finish,
+            Frame::Done { document: "{\"ok\":true}".to_string() },
+            Frame::Refused { reason: "no".to_string() },
+        ];
+        let mut wire = Vec::new();
+        for frame in &frames {
+            frame.write_to(&mut wire).unwrap();
+        }
+        let mut reader = wire.as_slice();
+        for frame in &frames {
+            assert_eq!(&Frame::read_from(&mut reader).unwrap(), frame);
+        }
+        assert_eq!(Frame::read_from(&mut reader).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(&Frame::Ack { offset: 1 }.encode()[..5], &[b'A', 0, 0, 0, 8]);
+    }
+
+    #[test]
+    fn malformed_frames_are_rejected() {
+        let read = |bytes: &[u8]| Frame::read_from(&mut &bytes[..]).unwrap_err().to_string();
+        let mut huge = vec![b'C'];
+        huge.extend_from_slice(&(MAX_FRAME_PAYLOAD as u32 + 1).to_be_bytes());
+        assert!(read(&huge).contains("larger than"));
+        assert!(read(&[b'Z', 0, 0, 0, 0]).contains("unknown or malformed frame 'Z'"));
+        assert!(read(&[b'A', 0, 0, 0, 4, 0, 0, 0, 1]).contains("malformed"), "an ack needs 8 bytes");
+        assert!(read(&[b'F', 0, 0, 0, 1, 0]).contains("malformed"));
+        assert!(read(&[b'E', 0, 0, 0, 2, 0xff, 0xfe]).contains("not UTF-8"));
+        assert_eq!(Frame::read_from(&mut &[b'A', 0, 0, 0, 8, 0][..]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn a_full_transfer_over_localhost_arrives_whole() {
+        let base = temp_dir("full");
+        let bundle = base.join("omega-r1.tar");
+        let data = payload(10_000);
+        fs::write(&bundle, &data).unwrap();
+        let releases = base.join("releases");
+        let (address, handle) = receiver(&releases);
+        let report = push(&address, &bundle, PushOptions { chunk_size: 4_096, resume: false }).unwrap();
+        assert!(matches!(handle.join().unwrap(), Received::Installed(document) if document == "{\"name\":\"omega-r1.tar\"}"));
+        assert_eq!((report.size, report.resumed_from, report.connections), (10_000, 0, 1));
+        assert_eq!(report.installed.as_deref(), Some("{\"name\":\"omega-r1.tar\"}"));
+        assert!(report.to_json().contains("\"status\":\"installed\",\"receiver\":{\"name\":\"omega-r1.tar\"}"));
+        assert_eq!(fs::read(releases.join(INCOMING_DIR).join("installed.tar")).unwrap(), data);
+        assert!(!releases.join(INCOMING_DIR).join(format!("{}.part", report.sha256)).exists(), "the part file is cleaned up");
+    }
+
+    #[test]
+    fn a_dropped_upload_resumes_from_the_last_ack() {
+        let base = temp_dir("resume");
+        let bundle = base.join("omega-r2.tar");
+        let data = payload(9_000);
+        fs::write(&bundle, &data).unwrap();
+        let releases = base.join("releases");
+
+        // First connection: send the hello and one chunk, then vanish.
+        let (address, handle) = receiver(&releases);
+        let mut stream = TcpStream::connect(&address).unwrap();
+        Frame::Hello { name: "omega-r2.tar".to_string(), total: 9_000, sha256: sha256::sha256(&data), resume: true }.write_to(&mut stream).unwrap();
+        assert_eq!(Frame::read_from(&mut stream).unwrap(), Frame::Ack { offset: 0 });
+        Frame::chunk(0, &data[..4_000]).write_to(&mut stream).unwrap();
+        assert_eq!(Frame::read_from(&mut stream).unwrap(), Frame::Ack { offset: 4_000 });
+        drop(stream);
+        assert!(matches!(handle.join().unwrap(), Received::Incomplete(_)));
+
+        let (address, handle) = receiver(&releases);
+        let report = push(&address, &bundle, PushOptions { chunk_size: 4_000, resume: true }).unwrap();
+        assert!(matches!(handle.join().unwrap(), Received::Installed(_)));
+        assert_eq!((report.resumed_from, report.connections), (4_000, 1));
+        assert_eq!(fs::read(releases.join(INCOMING_DIR).join("installed.tar")).unwrap(), data);
+    }
+
+    #[test]
+    fn a_corrupt_chunk_is_refused_and_not_kept() {
+        let base = temp_dir("corrupt");
+        let data = payload(8_000);
+        let releases = base.join("releases");
+        let (address, handle) = receiver(&releases);
+        let mut stream = TcpStream::connect(&address).unwrap();
+        Frame::Hello { name: "omega-r3.tar".to_string(), total: 8_000, sha256: sha256::sha256(&data), resume: false }.write_to(&mut stream).unwrap();
+        Frame::read_from(&mut stream).unwrap();
+        Frame::chunk(0, &data[..4_000]).write_to(&mut stream).unwrap();
+        assert_eq!(Frame::read_from(&mut stream).unwrap(), Frame::Ack { offset: 4_000 });
+        // The second chunk's bytes were damaged on the way; its hash still describes the original.
+        let Frame::Chunk { sha256, .. } = Frame::chunk(4_000, &data[4_000..]) else { unreachable!() };
+        let mut damaged = data[4_000..].to_vec();
+        damaged[10] ^= 0x01;
+        Frame::Chunk { offset: 4_000, sha256, data: damaged }.write_to(&mut stream).unwrap();
+        let Frame::Refused { reason } = Frame::read_from(&mut stream).unwrap() else { panic!("not refused") };
+        assert!(reason.contains("Chunk at byte 4000 failed its SHA-256 check"), "{reason}");
+        assert!(matches!(handle.join().unwrap(), Received::Refused(_)));
+
+        let part = releases.join(INCOMING_DIR).join(format!("{}.part", sha256::sha256_hex(&data)));
+        assert_eq!(fs::read(&part).unwrap(), data[..4_000]);
+        assert!(!releases.join(INCOMING_DIR).join("installed.tar").exists());
+    }
+
+    #[test]
+    fn a_bundle_that_does_not_match_its_announced_hash_is_refused() {
+        let base = temp_dir("whole-hash");
+        let releases = base.join("releases");
+        let (address, handle) = receiver(&releases);
+        let mut stream = TcpStream::connect(&address).unwrap();
+        Frame::Hello { name: "omega-r4.tar".to_string(), total: 3, sha256: [0; 32], resume: false }.write_to(&mut stream).unwrap();
+        Frame::read_from(&mut stream).unwrap();
+        Frame::chunk(0, b"abc").write_to(&mut stream).unwrap();
+        Frame::read_from(&mut stream).unwrap();
+        Frame::Finish.write_to(&mut stream).unwrap();
+        assert_eq!(Frame::read_from(&mut stream).unwrap(), Frame::Refused { reason: "The whole bundle does not match its announced SHA-256".to_string() });
+        assert!(matches!(handle.join().unwrap(), Received::Refused(_)));
+    }
+}
@@ -0,0 +1,10 @@
+//! Opening vault envelopes in Rust (cargo feature `vault-keys`).
+//!
+//! The reader is `ecosystem_common::vault`, shared with Squire; see its notes for the format.
+//! This module re-exports it and names Sentry's key variable, so Sentry Blue can keep its signing
+//! key encrypted on disk and only decrypt it while it signs.
+
+pub use ecosystem_common::vault::*;
+
+/// Base64 master key used to open the signing-key envelope.
+pub const VAULT_KEY_ENV: &str = "SENTRY_VAULT_KEY";
@@ -0,0 +1,285 @@
+//! `Verifier`: manifest checks for other Rust code, without going through the CLI.
+//!
+//! The hub and Squire's gateway want to ask "are these binaries the ones in the manifest?"
+//! without starting `sentry-omega verify` and reading its JSON. A `Verifier` holds one loaded
+//! manifest and answers that question for a whole folder or for a single file:
+//!
+//! ```text
+//! let verifier = Verifier::open(Path::new("releases/omega-20261016-3a1f6ba04531/manifest.txt"))?;
+//! let report = verifier.verify_dir(Path::new("/srv/squire/bin"))?;
+//! println!("{} of {} entries failed", report.failures().count(), report.checks.len());
+//! if let EntryStatus::Failed(check) = verifier.verify_file(Path::new("/srv/squire/bin/squire-gateway"))? {
+//!     println!("{} is {}", check.rel_path, check.status());
+//! }
+//! ```
+//!
+//! The checks are the ones `verify` runs: the manifest hash, every recorded digest, the file
+//! type, and the mode (`ModeCheck::ExecOnly` unless `with_mode_check` says otherwise). Per-file
+//! signatures and waivers stay CLI features, since they need a key and a waiver file.
+
+use std::path::Path;
+
+use crate::{check_entry, load_manifest_with, BinCheck, ManifestEntry, ModeCheck, OmegaManifest, SentryError};
+
+/// One loaded manifest plus the settings to check files against it.
+#[derive(Clone, Debug)]
+pub struct Verifier {
+    manifest: OmegaManifest,
+    mode_check: ModeCheck,
+    allow_exe_suffix: bool,
+}
+
+/// What `Verifier::verify_dir` found.
+#[derive(Clone, Debug)]
+pub struct VerifyReport {
+    /// The release the manifest describes.
+    pub release_id: String,
+    /// One check per manifest entry, in manifest order.
+    pub checks: Vec<BinCheck>,
+}
+
+impl VerifyReport {
+    /// True when every entry matched (`BinCheck::matched`).
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(BinCheck::matched)
+    }
+
+    /// The entries that did not match.
+    pub fn failures(&self) -> impl Iterator<Item = &BinCheck> {
+        self.checks.iter().filter(|check| !check.matched())
+    }
+}
+
+/// What `Verifier::verify_file` found for one file.
+#[derive(Clone, Debug)]
+pub enum EntryStatus {
+    /// The file belongs to a manifest entry and passed every check.
+    Matched(BinCheck),
+    /// The file belongs to a manifest entry but failed; `BinCheck::status` says how.
+    Failed(BinCheck),
+    /// No manifest entry has this file's name.
+    NotInManifest,
+    /// Several entries share the file's name and the path does not say which one it is. Holds
+    /// their relative paths.
+    Ambiguous(Vec<String>),
+}
+
+impl EntryStatus {
+    pub fn is_match(&self) -> bool {
+        matches!(self, EntryStatus::Matched(_))
+    }
+}
+
+impl Verifier {
+    /// Check files against `manifest` with the same defaults as `verify`: executable bits are
+    /// compared and `.exe` suffixes must match.
+    pub fn new(manifest: OmegaManifest) -> Self {
+        Self { manifest, mode_check: ModeCheck::ExecOnly, allow_exe_suffix: false }
+    }
+
+    /// Load the manifest at `path` (see `load_manifest`). An unreadable file fails with
+    /// `SentryError::ManifestRead`, a damaged line with `SentryError::ManifestParse` (which names
+    /// the line), and entries with absolute paths, or paths that leave the bins directory, with
+    /// `SentryError::UnsafePath`.
+    pub fn open(path: &Path) -> Result<Self, SentryError> {
+        Self::open_with(path, false)
+    }
+
+    /// `open`, optionally accepting absolute file paths (`--trust-absolute-paths`).
+    pub fn open_with(path: &Path, trust_absolute_paths: bool) -> Result<Self, SentryError> {
+        Ok(Self::new(load_manifest_with(path, trust_absolute_paths)?))
+    }
+
+    /// How much of each file's mode to compare (`--check-mode`).
+    pub fn with_mode_check(mut self, mode_check: ModeCheck) -> Self {
+        self.mode_check = mode_check;
+        self
+    }
+
+    /// Accept `squire.exe` for an entry recorded as `squire` and the other way round
+    /// (`--allow-exe-suffix`).
+    pub fn allow_exe_suffix(mut self, allow: bool) -> Self {
+        self.allow_exe_suffix = allow;
+        self
+    }
+
+    pub fn manifest(&self) -> &OmegaManifest {
+        &self.manifest
+    }
+
+    /// Check every entry against the files under `bins_dir`, like `verify --bins-dir`. A missing or
+    /// unreadable file is an error (`SentryError::EntryUnreadable`), as it is for the CLI; so is a
+    /// missing `bins_dir` (`SentryError::BinsDirMissing`).
+    pub fn verify_dir(&self, bins_dir: &Path) -> Result<VerifyReport, SentryError> {
+        let checks = crate::verify_bins(bins_dir, &self.manifest, self.mode_check, self.allow_exe_suffix)?;
+        Ok(VerifyReport { release_id: self.manifest.release_id.clone(), checks })
+    }
+
+    /// Check one file, wherever it is. The entry is found by file name; when several entries share
+    /// the name, the one whose relative path ends the file's path wins (`tools/squire` for
+    /// `/srv/bin/tools/squire`). Only reading the file can fail.
+    pub fn verify_file(&self, path: &Path) -> Result<EntryStatus, SentryError> {
+        let entry = match self.entry_for(path) {
+            Ok(entry) => entry,
+            Err(paths) if paths.is_empty() => return Ok(EntryStatus::NotInManifest),
+            Err(paths) => return Ok(EntryStatus::Ambiguous(paths)),
+        };
+        let check = check_entry(entry, path, self.mode_check)?;
+        Ok(if check.matched() { EntryStatus::Matched(check) } else { EntryStatus::Failed(check) })
+    }
+
+    /// The entry `path` belongs to. Otherwise the relative paths of every entry with its name,
+    /// which is empty when there is none.
+    fn entry_for(&self, path: &Path) -> Result<&ManifestEntry, Vec<String>> {
+        let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let same_name = |entry: &&ManifestEntry| {
+            entry.name == file_name || (self.allow_exe_suffix && without_exe(&entry.name) == without_exe(&file_name))
+        };
+        let candidates: Vec<&ManifestEntry> = self.manifest.entries.iter().filter(same_name).collect();
+        match candidates.as_slice() {
+            [] => return Err(Vec::new()),
+            [only] => return Ok(only),
+            _ => {}
+        }
+        // Compare folder names from the end: `tools/squire` fits `/srv/bin/tools/squire`.
+        let folders: Vec<String> = path
+            .parent()
+            .map(|parent| parent.iter().map(|part| part.to_string_lossy().into_owned()).collect())
+            .unwrap_or_default();
+        let fitting: Vec<&ManifestEntry> = candidates
+            .iter()
+            .copied()
+            .filter(|entry| {
+                let entry_folders: Vec<&str> = entry.rel_path.split('/').rev().skip(1).collect();
+                entry_folders.len() <= folders.len() && entry_folders.iter().zip(folders.iter().rev()).all(|(a, b)| a == b)
+            })
+            .collect();
+        // Several can fit when one entry is `squire` and another `tools/squire`; the deepest is
+        // the most specific.
+        let deepest = fitting.iter().map(|entry| entry.rel_path.matches('/').count()).max();
+        let best: Vec<&ManifestEntry> =
+            fitting.into_iter().filter(|entry| Some(entry.rel_path.matches('/').count()) == deepest).collect();
+        match best.as_slice() {
+            [only] => Ok(only),
+            _ => Err(candidates.iter().map(|entry| entry.rel_path.clone()).collect()),
+        }
+    }
+}
+
+fn without_exe(name: &str) -> &str {
+    name.strip_suffix(".exe").unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{build_manifest, persist_manifest, provenance, DigestAlgorithm, Mode};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sentry-verifier-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// `<base>/bins` holding `files`, recorded into `<base>/releases`; returns the bins folder and
+    /// the manifest file.
+    fn release(base: &Path, files: &[(&str, &[u8])]) -> (PathBuf, PathBuf) {
+        let bins_dir = base.join("bins");
+        for (rel_path, contents) in files {
+            let path = bins_dir.join(rel_path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let provenance = provenance::Provenance::collect(Some("rustc 1.80.0"), None);
+        let manifest = build_manifest(Mode::Blue, &bins_dir, "r1".to_string(), provenance, true, &[DigestAlgorithm::Sha256], None).unwrap();
+        let (folder, _) = persist_manifest(&manifest, &base.join("releases")).unwrap();
+        (bins_dir, folder.join("manifest.txt"))
+    }
+
+    #[test]
+    fn build_persist_open_and_verify_without_the_cli() {
+        let base = temp_dir("round-trip");
+        let (bins_dir, manifest) = release(&base, &[("squire", b"squire v1"), ("tools/hub", b"hub v1")]);
+        let verifier = Verifier::open(&manifest).unwrap();
+        assert_eq!(verifier.manifest().entries.len(), 2);
+
+        let report = verifier.verify_dir(&bins_dir).unwrap();
+        assert_eq!(report.release_id, "r1");
+        assert!(report.passed());
+        assert_eq!(report.failures().count(), 0);
+
+        fs::write(bins_dir.join("tools/hub"), b"hub v2").unwrap();
+        let report = verifier.verify_dir(&bins_dir).unwrap();
+        assert!(!report.passed());
+        let failed: Vec<&str> = report.failures().map(|check| check.rel_path.as_str()).collect();
+        assert_eq!(failed, ["tools/hub"]);
+
+        fs::remove_file(bins_dir.join("squire")).unwrap();
+        assert!(matches!(verifier.verify_dir(&bins_dir), Err(SentryError::EntryUnreadable { ref entry, .. }) if entry == "squire"));
+        assert!(matches!(verifier.verify_dir(&base.join("nowhere")), Err(SentryError::BinsDirMissing(_))));
+    }
+
+    #[test]
+    fn errors_can_be_matched_and_downcast() {
+        let base = temp_dir("errors");
+        let missing = base.join("missing.txt");
+        let err = Verifier::open(&missing).unwrap_err();
+        assert!(matches!(err, SentryError::ManifestRead { ref path, .. } if *path == missing));
+        let source = err.source().and_then(|source| source.downcast_ref::<std::io::Error>()).unwrap();
+        assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+
+        fs::write(base.join("damaged.txt"), "release_id=r1\nmode=purple\n").unwrap();
+        let boxed: Box<dyn Error> = Box::new(Verifier::open(&base.join("damaged.txt")).unwrap_err());
+        match boxed.downcast_ref::<SentryError>() {
+            Some(SentryError::ManifestParse { line, .. }) => assert_eq!(*line, 2),
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+
+        let (bins_dir, manifest) = release(&base, &[("squire", b"v1")]);
+        let text = fs::read_to_string(&manifest).unwrap();
+        let escaping = text.replace("\nsquire|squire|", "\nsquire|../squire|");
+        assert_ne!(text, escaping, "the manifest records squire at squire");
+        fs::write(&manifest, escaping).unwrap();
+        assert!(matches!(Verifier::open(&manifest), Err(SentryError::UnsafePath { ref entry, .. }) if entry == "squire"));
+        assert!(bins_dir.join("squire").is_file());
+    }
+
+    #[test]
+    fn single_files_are_checked_by_name_and_folder() {
+        let base = temp_dir("single");
+        let (bins_dir, manifest) = release(&base, &[("squire", b"squire v1"), ("bard", b"bard v1"), ("tools/squire", b"tool v1")]);
+        let verifier = Verifier::open(&manifest).unwrap();
+
+        // A copy elsewhere is still found by its name.
+        let elsewhere = base.join("elsewhere");
+        fs::create_dir_all(&elsewhere).unwrap();
+        fs::copy(bins_dir.join("bard"), elsewhere.join("bard")).unwrap();
+        assert!(verifier.verify_file(&elsewhere.join("bard")).unwrap().is_match());
+
+        fs::write(elsewhere.join("bard"), b"bard v2").unwrap();
+        match verifier.verify_file(&elsewhere.join("bard")).unwrap() {
+            EntryStatus::Failed(check) => assert_eq!((check.rel_path.as_str(), check.status()), ("bard", "mismatch")),
+            other => panic!("expected a failure, got {other:?}"),
+        }
+
+        fs::write(elsewhere.join("stranger"), b"?").unwrap();
+        assert!(matches!(verifier.verify_file(&elsewhere.join("stranger")).unwrap(), EntryStatus::NotInManifest));
+
+        // Two entries share `squire`: the folder picks the deepest fitting one.
+        match verifier.verify_file(&bins_dir.join("tools/squire")).unwrap() {
+            EntryStatus::Matched(check) => assert_eq!(check.rel_path, "tools/squire"),
+            other => panic!("expected tools/squire, got {other:?}"),
+        }
+        match verifier.verify_file(&bins_dir.join("squire")).unwrap() {
+            EntryStatus::Matched(check) => assert_eq!(check.rel_path, "squire"),
+            other => panic!("expected squire, got {other:?}"),
+        }
+
+        assert!(matches!(verifier.verify_file(&base.join("bard")), Err(SentryError::EntryUnreadable { .. })));
+    }
+}
@@ -0,0 +1,336 @@
+//! A cache of file hashes, so `daemon` does not read every binary again on every pass.
+//!
+//! Hashing a large bins folder on a network mount can take most of the daemon's interval, even
+//! though the files almost never change. With `daemon --cache-file <path>`, Sentry remembers for
+//! each file it hashed its size, its modification time (in nanoseconds), and the hashes it
+//! computed. On the next pass a file whose size and modification time are both unchanged is not
+//! read again: its remembered hashes are compared with the manifest instead. Any change to
+//! either number means the file is read and hashed as usual, and the cache is updated.
+//!
+//! Only the observed hashes are cached, never the verdict, so a manifest that changes between
+//! passes is still compared properly. Mode checks still look at the file every pass.
+//!
+//! Someone who edits a binary and then puts its old modification time back (`touch -d`) would
+//! fool the cache. Two things guard against that:
+//! - every `--full-rehash-every` passes (10 by default), and on every recheck after a mismatch,
+//!   each file is hashed again no matter what the cache says. The first pass after a start is
+//!   always a full one.
+//! - a file that changed in the last `SETTLE_TIME`, or that changed while it was being read, is
+//!   hashed but not remembered, because a second write within the same clock tick would not
+//!   change its modification time.
+//!
+//! The cache file is JSON with a `"format"` and a `"version"`, rewritten atomically after every
+//! pass. A file that is damaged, from another version, or unreadable is logged and ignored: the
+//! pass simply hashes everything, and a good file is written at its end.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::digest::DigestAlgorithm;
+use crate::json::{self, JsonValue};
+use crate::json_escape;
+use crate::log::Logger;
+
+/// `"format"` of a cache file.
+pub const CACHE_FORMAT: &str = "sentry-verify-cache";
+/// `"version"` this Sentry reads and writes. Files with another version are ignored.
+pub const CACHE_VERSION: u64 = 1;
+/// `--full-rehash-every` when the flag is absent.
+pub const DEFAULT_FULL_REHASH_EVERY: u64 = 10;
+/// Files modified more recently than this are not remembered (see the module notes).
+pub const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+const LOG: Logger = Logger::new("sentry-cache");
+
+/// The two numbers that decide whether a file may have changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    /// Modification time in nanoseconds since 1970.
+    pub mtime_nanos: u128,
+}
+
+impl FileStamp {
+    pub fn of(metadata: &fs::Metadata) -> FileStamp {
+        let mtime_nanos = metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map_or(0, |since| since.as_nanos());
+        FileStamp { size: metadata.len(), mtime_nanos }
+    }
+}
+
+/// What was computed for one file the last time it was read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedHash {
+    pub stamp: FileStamp,
+    /// The manifest hash (`hash_bytes`).
+    pub hash: String,
+    /// Standard digests computed at the same time, for entries that record them.
+    pub digests: Vec<(DigestAlgorithm, String)>,
+}
+
+/// How the last pass used the cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Entries answered from the cache without reading the file.
+    pub hits: usize,
+    /// Files read and hashed.
+    pub hashed: usize,
+    /// Whether this pass ignored the cache on purpose (`--full-rehash-every`, a recheck, or the
+    /// first pass).
+    pub full_rehash: bool,
+}
+
+/// Remembered hashes, keyed by the file path verification looked at.
+#[derive(Clone, Debug, Default)]
+pub struct VerifyCache {
+    entries: BTreeMap<String, CachedHash>,
+    /// Paths looked at in the current pass; the rest are dropped when it ends.
+    seen: BTreeSet<String>,
+    stats: CacheStats,
+}
+
+impl VerifyCache {
+    /// Read `path`, or start empty when it is missing or cannot be used. Problems are logged.
+    pub fn load(path: &Path) -> VerifyCache {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return VerifyCache::default(),
+            Err(err) => {
+                LOG.warn("Verification cache unreadable; hashing everything", &[("path", &path.display().to_string()), ("error", &err.to_string())]);
+                return VerifyCache::default();
+            }
+        };
+        match VerifyCache::parse(&text) {
+            Ok(cache) => cache,
+            Err(problem) => {
+                LOG.warn("Verification cache ignored; hashing everything", &[("path", &path.display().to_string()), ("problem", &problem)]);
+                VerifyCache::default()
+            }
+        }
+    }
+
+    /// Read a cache document. Any problem rejects the whole file, so a half-written or edited
+    /// cache can never supply a hash.
+    pub fn parse(text: &str) -> Result<VerifyCache, String> {
+        let document = json::parse(text)?;
+        if document.get("format").and_then(JsonValue::as_str) != Some(CACHE_FORMAT) {
+            return Err(format!("not a {CACHE_FORMAT} file"));
+        }
+        let version = document.get("version").and_then(JsonValue::as_f64);
+        if version != Some(CACHE_VERSION as f64) {
+            return Err(format!("version {} is not {CACHE_VERSION}", version.map_or("missing".to_string(), |version| version.to_string())));
+        }
+        let items = document.get("entries").and_then(JsonValue::as_array).ok_or("\"entries\" is missing")?;
+        let mut entries = BTreeMap::new();
+        for (index, item) in items.iter().enumerate() {
+            let (path, cached) = parse_entry(item).ok_or_else(|| format!("entry {index} is malformed"))?;
+            entries.insert(path, cached);
+        }
+        Ok(VerifyCache { entries, ..VerifyCache::default() })
+    }
+
+    /// The cache as one line of JSON. Sizes and times are strings because JSON numbers lose
+    /// precision above 2^53 and nanosecond times are larger than that.
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(path, cached)| {
+                let digests: Vec<String> =
+                    cached.digests.iter().map(|(algorithm, hex)| format!("\"{}\":\"{}\"", algorithm.as_str(), hex)).collect();
+                format!(
+                    "{{\"path\":\"{}\",\"size\":\"{}\",\"mtime_nanos\":\"{}\",\"hash\":\"{}\",\"digests\":{{{}}}}}",
+                    json_escape(path),
+                    cached.stamp.size,
+                    cached.stamp.mtime_nanos,
+                    cached.hash,
+                    digests.join(",")
+                )
+            })
+            .collect();
+        format!("{{\"format\":\"{CACHE_FORMAT}\",\"version\":{CACHE_VERSION},\"entries\":[{}]}}\n", entries.join(","))
+    }
+
+    /// Write the cache so a reader sees the old file or the new one, never half of one.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        crate::atomic::atomic_write(path, self.to_json().as_bytes())
+    }
+
+    /// Start a pass. With `full_rehash`, `lookup` answers nothing and every file is read.
+    pub fn begin_pass(&mut self, full_rehash: bool) {
+        self.seen.clear();
+        self.stats = CacheStats { full_rehash, ..CacheStats::default() };
+    }
+
+    /// The remembered hashes of `path` if its stamp is unchanged and every algorithm in
+    /// `algorithms` was computed. Counts a hit.
+    pub fn lookup(&mut self, path: &str, stamp: FileStamp, algorithms: &[DigestAlgorithm]) -> Option<CachedHash> {
+        self.seen.insert(path.to_string());
+        if self.stats.full_rehash {
+            return None;
+        }
+        let cached = self.entries.get(path).filter(|cached| cached.stamp == stamp)?;
+        if !algorithms.iter().all(|wanted| cached.digests.iter().any(|(algorithm, _)| algorithm == wanted)) {
+            return None;
+        }
+        self.stats.hits += 1;
+        Some(cached.clone())
+    }
+
+    /// Record that `path` was read and hashed. `before` and `after` are its stamps from just
+    /// before and just after the read; it is only remembered when they agree and the file has
+    /// not changed within `SETTLE_TIME` of `now`.
+    pub fn store(&mut self, path: &str, before: FileStamp, after: Option<FileStamp>, hash: &str, digests: &[(DigestAlgorithm, String)], now: SystemTime) {
+        self.seen.insert(path.to_string());
+        self.stats.hashed += 1;
+        let now_nanos = now.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos());
+        let settled = now_nanos.saturating_sub(before.mtime_nanos) >= SETTLE_TIME.as_nanos();
+        if after == Some(before) && settled {
+            self.entries.insert(path.to_string(), CachedHash { stamp: before, hash: hash.to_string(), digests: digests.to_vec() });
+        } else {
+            // Whatever was remembered describes an older version of the file.
+            self.entries.remove(path);
+        }
+    }
+
+    /// End a pass: forget files this pass did not look at, such as entries of an old manifest.
+    pub fn finish_pass(&mut self) {
+        let seen = &self.seen;
+        self.entries.retain(|path, _| seen.contains(path));
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// How many files are remembered.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn parse_entry(item: &JsonValue) -> Option<(String, CachedHash)> {
+    let text = |key: &str| item.get(key).and_then(JsonValue::as_str);
+    let stamp = FileStamp { size: text("size")?.parse().ok()?, mtime_nanos: text("mtime_nanos")?.parse().ok()? };
+    let hash = text("hash").filter(|hash| !hash.is_empty() && hash.bytes().all(|byte| byte.is_ascii_hexdigit()))?;
+    let JsonValue::Object(fields) = item.get("digests")? else {
+        return None;
+    };
+    let mut digests = Vec::new();
+    for (name, value) in fields {
+        let algorithm = DigestAlgorithm::ALL.into_iter().find(|algorithm| algorithm.as_str() == name)?;
+        digests.push((algorithm, value.as_str()?.to_string()));
+    }
+    Some((text("path")?.to_string(), CachedHash { stamp, hash: hash.to_string(), digests }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW_SECS: u64 = 1_700_000_000;
+
+    fn stamp(size: u64, mtime_secs: u64) -> FileStamp {
+        FileStamp { size, mtime_nanos: u128::from(mtime_secs) * 1_000_000_000 }
+    }
+
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(NOW_SECS)
+    }
+
+    fn digests() -> Vec<(DigestAlgorithm, String)> {
+        vec![(DigestAlgorithm::Sha256, "ab".repeat(32))]
+    }
+
+    /// A cache remembering `squire` at `stamp(9, NOW_SECS - 60)`.
+    fn remembering() -> VerifyCache {
+        let mut cache = VerifyCache::default();
+        cache.begin_pass(true);
+        let old = stamp(9, NOW_SECS - 60);
+        cache.store("bins/squire", old, Some(old), "00ff", &digests(), now());
+        cache.finish_pass();
+        cache
+    }
+
+    #[test]
+    fn an_unchanged_stamp_is_a_hit_and_any_change_a_miss() {
+        let mut cache = remembering();
+        cache.begin_pass(false);
+        let hit = cache.lookup("bins/squire", stamp(9, NOW_SECS - 60), &[DigestAlgorithm::Sha256]).unwrap();
+        assert_eq!((hit.hash.as_str(), hit.digests), ("00ff", digests()));
+        assert_eq!(cache.lookup("bins/squire", stamp(10, NOW_SECS - 60), &[]), None, "size changed");
+        assert_eq!(cache.lookup("bins/squire", stamp(9, NOW_SECS - 59), &[]), None, "mtime changed");
+        assert_eq!(cache.lookup("bins/squire", stamp(9, NOW_SECS - 60), &[DigestAlgorithm::Sha512]), None, "digest never computed");
+        assert_eq!(cache.lookup("bins/bard", stamp(9, NOW_SECS - 60), &[]), None);
+        assert_eq!(cache.stats(), CacheStats { hits: 1, hashed: 0, full_rehash: false });
+
+        cache.begin_pass(true);
+        assert_eq!(cache.lookup("bins/squire", stamp(9, NOW_SECS - 60), &[]), None, "a full pass reads everything");
+        assert!(cache.stats().full_rehash);
+    }
+
+    #[test]
+    fn fresh_or_moving_files_are_hashed_but_not_remembered() {
+        let mut cache = remembering();
+        cache.begin_pass(false);
+        let recent = stamp(9, NOW_SECS - 1);
+        cache.store("bins/squire", recent, Some(recent), "11ee", &digests(), now());
+        assert!(cache.is_empty(), "changed within SETTLE_TIME, and the old hashes are dropped too");
+
+        let settled = stamp(9, NOW_SECS - 2);
+        cache.store("bins/squire", settled, Some(stamp(10, NOW_SECS)), "11ee", &digests(), now());
+        assert!(cache.is_empty(), "changed while it was read");
+        cache.store("bins/squire", settled, None, "11ee", &digests(), now());
+        assert!(cache.is_empty(), "gone after the read");
+        cache.store("bins/squire", settled, Some(settled), "11ee", &digests(), now());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.stats().hashed, 4);
+    }
+
+    #[test]
+    fn a_pass_forgets_files_it_did_not_look_at() {
+        let mut cache = remembering();
+        cache.begin_pass(false);
+        cache.finish_pass();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn the_file_round_trips_and_damage_is_ignored() {
+        let dir = std::env::temp_dir().join(format!("sentry-verify-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache.json");
+        let cache = remembering();
+        cache.save(&path).unwrap();
+        let mut loaded = VerifyCache::load(&path);
+        assert_eq!(loaded.to_json(), cache.to_json());
+        loaded.begin_pass(false);
+        assert!(loaded.lookup("bins/squire", stamp(9, NOW_SECS - 60), &[DigestAlgorithm::Sha256]).is_some(), "nanosecond times survive as strings");
+
+        assert!(VerifyCache::load(&dir.join("missing.json")).is_empty());
+        for (text, problem) in [
+            ("{\"format\":\"sentry-verify-cache\",\"version\":1,\"entries\":[", ""),
+            ("{\"format\":\"other\",\"version\":1,\"entries\":[]}", "not a sentry-verify-cache file"),
+            ("{\"format\":\"sentry-verify-cache\",\"version\":2,\"entries\":[]}", "version 2 is not 1"),
+            ("{\"format\":\"sentry-verify-cache\",\"entries\":[]}", "version missing is not 1"),
+            ("{\"format\":\"sentry-verify-cache\",\"version\":1}", "\"entries\" is missing"),
+        ] {
+            let err = VerifyCache::parse(text).unwrap_err();
+            assert!(err.contains(problem), "{text} gave {err}");
+            fs::write(&path, text).unwrap();
+            assert!(VerifyCache::load(&path).is_empty(), "{text}");
+        }
+        // One bad entry rejects the whole file.
+        let edited = cache.to_json().replace("\"hash\":\"00ff\"", "\"hash\":\"not hex\"");
+        assert_eq!(VerifyCache::parse(&edited).unwrap_err(), "entry 0 is malformed");
+        let unknown = cache.to_json().replace("\"sha256\"", "\"md5\"");
+        assert!(VerifyCache::parse(&unknown).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,149 @@
+//! Verify request tokens: proof that an on-demand `verify` was asked for by Red.
+//!
+//! Red can ask Yellow to verify one release right now. Yellow should only act on requests Red
+//! really sent, and a request copied off the link should stop working soon after. Red and Yellow
+//! share a token key (`SENTRY_VERIFY_TOKEN_KEY`, 64 hex characters); `issue-token` on Red prints a
+//! token and `verify --token` on Yellow checks it:
+//!
+//! ```text
+//! <release_id>.<expiry_unix>.<hmac_hex>
+//! ```
+//!
+//! - The MAC is HMAC-SHA256 under the token key over `<release_id>.<expiry_unix>`, so neither
+//!   field can be changed without the key. It is compared in constant time.
+//! - A token is good until `expiry_unix`, plus the clock skew the reader tolerates
+//!   (`--max-clock-skew-secs`, default 120), so a Yellow clock running a little ahead does not
+//!   turn away a fresh token.
+//! - The release id is the last field that may hold dots; the other two never do.
+//!
+//! A token only names a release and a deadline. Until it expires it can be replayed, which at
+//! worst runs the same read-only check again, so keep `--ttl-secs` short.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::sha256;
+
+/// Variable holding the key shared by the token issuer and the verifier.
+pub const TOKEN_KEY_ENV: &str = "SENTRY_VERIFY_TOKEN_KEY";
+/// `issue-token --ttl-secs` when not given: five minutes.
+pub const DEFAULT_TTL_SECS: u64 = 300;
+
+/// What a valid token says.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyRequestToken {
+    pub release_id: String,
+    pub expiry_unix: u64,
+}
+
+impl VerifyRequestToken {
+    /// A token for `release_id` that expires `ttl_secs` from now.
+    pub fn issue(key: &[u8], release_id: &str, ttl_secs: u64) -> Result<String, String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+        VerifyRequestToken::issue_at(key, release_id, ttl_secs, now)
+    }
+
+    /// `issue` with the current time passed in.
+    pub fn issue_at(key: &[u8], release_id: &str, ttl_secs: u64, now_unix: u64) -> Result<String, String> {
+        if release_id.is_empty() || !release_id.bytes().all(|b| b.is_ascii_graphic()) {
+            return Err(format!("release id {release_id:?} must be non-empty, printable, and without spaces"));
+        }
+        let signed = format!("{release_id}.{}", now_unix.saturating_add(ttl_secs));
+        let mac = sha256::to_hex(&sha256::hmac_sha256(key, signed.as_bytes()));
+        Ok(format!("{signed}.{mac}"))
+    }
+
+    /// Check a token's MAC and expiry with the default skew allowance; see `validate_with_skew`.
+    pub fn validate(key: &[u8], token: &str, now_unix: u64) -> Result<VerifyRequestToken, String> {
+        VerifyRequestToken::validate_with_skew(key, token, now_unix, crate::clock_skew::DEFAULT_MAX_CLOCK_SKEW)
+    }
+
+    /// The release and expiry of a token whose MAC is right and which has not expired by more
+    /// than `max_skew` at `now_unix`. Exactly at the edge of the window still counts.
+    pub fn validate_with_skew(key: &[u8], token: &str, now_unix: u64, max_skew: Duration) -> Result<VerifyRequestToken, String> {
+        let token = token.trim();
+        let (signed, mac_hex) = token.rsplit_once('.').ok_or("token must be release_id.expiry_unix.hmac_hex")?;
+        let (release_id, expiry) = signed.rsplit_once('.').ok_or("token must be release_id.expiry_unix.hmac_hex")?;
+        let mac = sha256::from_hex(mac_hex).filter(|mac| mac.len() == sha256::DIGEST_LEN).ok_or("token MAC is not 64 hex characters")?;
+        // The MAC is checked before anything in the token is believed.
+        if !sha256::constant_time_eq(&mac, &sha256::hmac_sha256(key, signed.as_bytes())) {
+            return Err("token MAC does not match".to_string());
+        }
+        let expiry_unix = expiry.parse::<u64>().map_err(|_| format!("token expiry {expiry:?} is not a whole number"))?;
+        if now_unix > expiry_unix.saturating_add(max_skew.as_secs()) {
+            return Err(format!("token expired at {expiry_unix} (now {now_unix}, allowed skew {}s)", max_skew.as_secs()));
+        }
+        Ok(VerifyRequestToken { release_id: release_id.to_string(), expiry_unix })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"red and yellow share this key...";
+    const NOW: u64 = 1_800_000_000;
+
+    #[test]
+    fn an_issued_token_validates_and_names_its_release() {
+        let token = VerifyRequestToken::issue_at(KEY, "20261016-3a1f6ba04531", 300, NOW).unwrap();
+        let (signed, mac) = token.rsplit_once('.').unwrap();
+        assert_eq!(signed, format!("20261016-3a1f6ba04531.{}", NOW + 300));
+        assert_eq!(mac, sha256::to_hex(&sha256::hmac_sha256(KEY, signed.as_bytes())));
+        let accepted = VerifyRequestToken::validate(KEY, &format!("{token}\n"), NOW + 10).unwrap();
+        assert_eq!(accepted, VerifyRequestToken { release_id: "20261016-3a1f6ba04531".to_string(), expiry_unix: NOW + 300 });
+    }
+
+    #[test]
+    fn a_release_id_may_hold_dots() {
+        let token = VerifyRequestToken::issue_at(KEY, "v1.2.3", 60, NOW).unwrap();
+        assert_eq!(VerifyRequestToken::validate(KEY, &token, NOW).unwrap().release_id, "v1.2.3");
+        for bad in ["", "two words", "tab\there"] {
+            assert!(VerifyRequestToken::issue_at(KEY, bad, 60, NOW).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn tampered_tokens_and_other_keys_fail_the_mac() {
+        let token = VerifyRequestToken::issue_at(KEY, "r1", 300, NOW).unwrap();
+        let mismatch = |token: &str, key: &[u8]| VerifyRequestToken::validate(key, token, NOW).unwrap_err();
+        assert_eq!(mismatch(&token, b"another key"), "token MAC does not match");
+        assert_eq!(mismatch(&token.replacen("r1.", "r2.", 1), KEY), "token MAC does not match");
+        let later = token.replacen(&(NOW + 300).to_string(), &(NOW + 9_000).to_string(), 1);
+        assert_eq!(mismatch(&later, KEY), "token MAC does not match", "a longer expiry needs the key");
+        let last = token.chars().last().unwrap();
+        let flipped = format!("{}{}", &token[..token.len() - 1], if last == '0' { '1' } else { '0' });
+        assert_eq!(mismatch(&flipped, KEY), "token MAC does not match");
+        assert_eq!(mismatch(&token[..token.len() - 2], KEY), "token MAC is not 64 hex characters");
+        assert_eq!(mismatch("r1", KEY), "token must be release_id.expiry_unix.hmac_hex");
+        assert_eq!(mismatch("r1.deadbeef", KEY), "token must be release_id.expiry_unix.hmac_hex");
+    }
+
+    #[test]
+    fn the_mac_is_compared_in_constant_time_before_the_fields_are_read() {
+        // A structural check: the comparison goes through `constant_time_eq`, never `==`, and it
+        // comes before the expiry is parsed.
+        let source = include_str!("verify_token.rs");
+        let body = &source[source.find("pub fn validate_with_skew").unwrap()..source.find("#[cfg(test)]").unwrap()];
+        let compare = body.find("sha256::constant_time_eq(&mac").expect("constant-time comparison");
+        assert!(compare < body.find("expiry.parse").unwrap());
+        assert!(!body.contains("mac ==") && !body.contains("== mac"));
+        // A well-formed expiry that is not a number still has to pass the MAC first.
+        let signed = "r1.soon";
+        let token = format!("{signed}.{}", sha256::to_hex(&sha256::hmac_sha256(KEY, signed.as_bytes())));
+        assert_eq!(VerifyRequestToken::validate(KEY, &token, NOW).unwrap_err(), "token expiry \"soon\" is not a whole number");
+    }
+
+    #[test]
+    fn expiry_allows_exactly_the_clock_skew_window() {
+        let token = VerifyRequestToken::issue_at(KEY, "r1", 60, NOW).unwrap();
+        let expiry = NOW + 60;
+        let check = |now: u64, skew: u64| VerifyRequestToken::validate_with_skew(KEY, &token, now, Duration::from_secs(skew));
+        assert!(check(expiry, 0).is_ok());
+        assert_eq!(check(expiry + 1, 0).unwrap_err(), format!("token expired at {expiry} (now {}, allowed skew 0s)", expiry + 1));
+        assert!(check(expiry + 30, 30).is_ok(), "the edge of the window still counts");
+        assert!(check(expiry + 31, 30).is_err());
+        assert!(VerifyRequestToken::validate(KEY, &token, expiry + 120).is_ok(), "default skew is 120 seconds");
+        assert!(VerifyRequestToken::validate(KEY, &token, expiry + 121).is_err());
+        assert!(check(0, 0).is_ok(), "a clock far behind is not a reason to refuse");
+    }
+}
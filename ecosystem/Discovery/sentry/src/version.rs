@@ -0,0 +1,101 @@
+//! Tool versions, so a reader can tell a manifest is too new for it instead of misreading it.
+//!
+//! Blue, Yellow, and Red are upgraded one at a time. A Yellow verifier once met a manifest from a
+//! newer Blue builder and failed with a puzzling "unknown field" error. Now every manifest says
+//! who wrote it and who can read it:
+//! - `tool_version=` is the Sentry version that wrote it (`CARGO_PKG_VERSION`);
+//! - `min_reader_version=` is the oldest Sentry that understands it (`MIN_READER_VERSION`).
+//!
+//! `load_manifest` compares `min_reader_version` with its own version and stops with
+//! `SentryError::ManifestTooNew` ("manifest requires sentry >= X, this is Y") when it is too old.
+//! Manifests from before these lines count as `0.0.0`, which every reader accepts.
+//!
+//! Versions are compared as three numbers, major first, so `0.10.0` is newer than `0.9.9`.
+//! Pre-release and build suffixes (`-beta.1`, `+abc`) are ignored.
+
+use std::fmt;
+
+/// The version of this Sentry, from `Cargo.toml`.
+pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// The oldest Sentry that reads the manifests this one writes. Raise it when a change to the
+/// format would be misread (not just skipped) by older readers.
+pub const MIN_READER_VERSION: &str = "0.1.0";
+
+/// `major.minor.patch`. The derived ordering compares the fields in that order, as numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    /// What a manifest or document without a version line counts as.
+    pub const LEGACY: Version = Version { major: 0, minor: 0, patch: 0 };
+
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Version { major, minor, patch }
+    }
+
+    /// Read `1.2.3`. A missing minor or patch counts as 0 (`1.2` is `1.2.0`), and anything after
+    /// `-` or `+` is dropped. `None` when a part is not a number or there are more than three.
+    pub fn parse(text: &str) -> Option<Version> {
+        let core = text.trim().split(['-', '+']).next().unwrap_or("");
+        let mut parts = [0u64; 3];
+        for (index, part) in core.split('.').enumerate() {
+            if index == 3 || part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            parts[index] = part.parse().ok()?;
+        }
+        Some(Version::new(parts[0], parts[1], parts[2]))
+    }
+
+    /// This Sentry's own version.
+    pub fn current() -> Version {
+        // `CARGO_PKG_VERSION` is always three numbers, so this cannot fall back in practice.
+        Version::parse(TOOL_VERSION).unwrap_or(Version::LEGACY)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(text: &str) -> Version {
+        Version::parse(text).unwrap()
+    }
+
+    #[test]
+    fn versions_compare_as_numbers_not_text() {
+        assert!(v("0.10.0") > v("0.9.9"));
+        assert!(v("1.0.0") > v("0.99.99"));
+        assert!(v("2.0.10") > v("2.0.9"));
+        assert_eq!(v("1.2.3").cmp(&v("1.2.3")), std::cmp::Ordering::Equal);
+        assert!(Version::LEGACY <= v("0.0.0") && Version::LEGACY < v("0.0.1"));
+    }
+
+    #[test]
+    fn parsing_fills_missing_parts_and_drops_suffixes() {
+        assert_eq!(v("1.2"), Version::new(1, 2, 0));
+        assert_eq!(v("3"), Version::new(3, 0, 0));
+        assert_eq!(v(" 1.4.0-beta.1 "), Version::new(1, 4, 0));
+        assert_eq!(v("1.4.0+abc"), Version::new(1, 4, 0));
+        for bad in ["", "1..2", "1.2.3.4", "v1.2.3", "1.x", "-1.0", "1.2.3 4"] {
+            assert_eq!(Version::parse(bad), None, "{bad:?}");
+        }
+        assert_eq!(v("10.20.30").to_string(), "10.20.30");
+    }
+
+    #[test]
+    fn this_sentry_reads_what_it_writes() {
+        assert_eq!(Version::current().to_string(), TOOL_VERSION);
+        assert!(v(MIN_READER_VERSION) <= Version::current());
+    }
+}
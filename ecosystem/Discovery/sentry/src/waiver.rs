@@ -0,0 +1,187 @@
+//! Waivers: known, temporary hash mismatches that should not fail verification.
+//!
+//! During a staged rollout one binary on the Yellow host is sometimes patched on purpose. Without
+//! a waiver the daemon reports that file as a mismatch every pass until the next release. A
+//! waiver file (`--waivers <path>`) lists the patched files, one per line:
+//!
+//! ```text
+//! # patched gateway for the reconnect fix, remove after release 20261020
+//! name=squire-gateway expected_hash=4f1c2a9e0b7d3c55 expires=1792800000
+//! ```
+//!
+//! - `name` is the manifest entry's relative path, the same key `results` uses. For a flat
+//!   `--bins-dir` that is just the file name. It is normalized like manifest paths (see
+//!   `safe_path`), and an absolute name or one that climbs out with `..` is an error.
+//! - `expected_hash` is the hash the patched file *should* have now, i.e. the hash verify
+//!   observes, not the one in the manifest. A file that changed again does not match the waiver
+//!   and is a mismatch as usual.
+//! - `expires` is a Unix time in seconds. After it the waiver stops applying and the entry is
+//!   reported as `waiver-expired`, which fails the run like a mismatch does. That way a forgotten
+//!   waiver cannot hide a patched file forever.
+//!
+//! A waiver only ever turns a hash mismatch into `waived`. Mode checks still apply, and a waived
+//! file's signature is not compared because the `.sig` belongs to the original file.
+
+use std::fs;
+use std::path::Path;
+
+use crate::{json_escape, safe_path, BinCheck, WaiverCheck};
+
+/// One line of a waiver file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Waiver {
+    /// The entry's relative path (`ManifestEntry::rel_path`).
+    pub name: String,
+    /// The hash of the patched file this waiver accepts.
+    pub expected_hash: String,
+    /// Unix time (seconds) after which the waiver no longer applies.
+    pub expires_unix: u64,
+}
+
+/// What happened to one waiver during a verification pass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaiverUse {
+    /// It matched a mismatched file and downgraded it to `waived`.
+    Applied,
+    /// It matched a mismatched file but has expired, so the mismatch stands.
+    Expired,
+    /// No entry had this name with this observed hash and a mismatch.
+    Unused,
+}
+
+impl WaiverUse {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WaiverUse::Applied => "applied",
+            WaiverUse::Expired => "expired",
+            WaiverUse::Unused => "unused",
+        }
+    }
+}
+
+/// Read a waiver file. Blank lines and lines starting with `#` are skipped; any other line must
+/// hold exactly the three `key=value` fields, in any order.
+pub fn load_waivers(path: &Path) -> Result<Vec<Waiver>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("Unable to read waivers {:?}: {err}", path))?;
+    let mut waivers = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let waiver = parse_line(line).map_err(|problem| format!("{:?} line {}: {problem}", path, index + 1))?;
+        waivers.push(waiver);
+    }
+    Ok(waivers)
+}
+
+fn parse_line(line: &str) -> Result<Waiver, String> {
+    let (mut name, mut expected_hash, mut expires_unix) = (None, None, None);
+    for field in line.split_whitespace() {
+        let (key, value) = field.split_once('=').ok_or_else(|| format!("expected key=value, got {field:?}"))?;
+        let slot = match key {
+            "name" => &mut name,
+            "expected_hash" => &mut expected_hash,
+            "expires" => &mut expires_unix,
+            other => return Err(format!("unknown field {other:?}")),
+        };
+        if slot.replace(value.to_string()).is_some() {
+            return Err(format!("{key} is given twice"));
+        }
+    }
+    let expires = expires_unix.ok_or("missing expires=")?;
+    let name = name.ok_or("missing name=")?;
+    // Same rules as manifest keys, so `./tools/squire` waives `tools/squire` and `../x` is refused.
+    let name = safe_path::normalize(&name).map_err(|problem| format!("name {name:?} {problem}"))?;
+    Ok(Waiver {
+        name,
+        expected_hash: expected_hash.ok_or("missing expected_hash=")?.to_ascii_lowercase(),
+        expires_unix: expires.parse().map_err(|_| format!("expires must be Unix seconds, got {expires:?}"))?,
+    })
+}
+
+/// Mark every mismatched entry that a waiver covers, and return what happened to each waiver in
+/// file order. A waiver still counts as expired at exactly `expires`.
+pub fn apply_waivers(report: &mut [BinCheck], waivers: &[Waiver], now_unix: u64) -> Vec<(Waiver, WaiverUse)> {
+    waivers
+        .iter()
+        .map(|waiver| {
+            let covered = report.iter_mut().find(|check| {
+                check.rel_path == waiver.name
+                    && check.expected_hash != check.observed_hash
+                    && check.observed_hash.eq_ignore_ascii_case(&waiver.expected_hash)
+            });
+            let used = match covered {
+                Some(check) if now_unix < waiver.expires_unix => {
+                    check.waiver = WaiverCheck::Applied;
+                    WaiverUse::Applied
+                }
+                Some(check) => {
+                    check.waiver = WaiverCheck::Expired;
+                    WaiverUse::Expired
+                }
+                None => WaiverUse::Unused,
+            };
+            (waiver.clone(), used)
+        })
+        .collect()
+}
+
+/// `[{"name":..,"expected_hash":..,"expires":..,"status":"applied"},...]` for the status document.
+pub fn waivers_json(uses: &[(Waiver, WaiverUse)]) -> String {
+    let items: Vec<String> = uses
+        .iter()
+        .map(|(waiver, used)| {
+            format!(
+                "{{\"name\":\"{}\",\"expected_hash\":\"{}\",\"expires\":{},\"status\":\"{}\"}}",
+                json_escape(&waiver.name),
+                json_escape(&waiver.expected_hash),
+                waiver.expires_unix,
+                used.as_str()
+            )
+        })
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(text: &str) -> Result<Vec<Waiver>, String> {
+        let path = std::env::temp_dir().join(format!("sentry-waiver-{}-{}", std::process::id(), text.len()));
+        fs::write(&path, text).unwrap();
+        let waivers = load_waivers(&path);
+        let _ = fs::remove_file(&path);
+        waivers
+    }
+
+    #[test]
+    fn lines_parse_in_any_order_with_normalized_names() {
+        let waivers = load("# patched for the reconnect fix\n\nexpires=1792800000 name=./tools/squire expected_hash=4F1C\n").unwrap();
+        assert_eq!(waivers, [Waiver { name: "tools/squire".to_string(), expected_hash: "4f1c".to_string(), expires_unix: 1_792_800_000 }]);
+
+        for (line, problem) in [
+            ("name=squire expected_hash=ab", "missing expires="),
+            ("name=squire expected_hash=ab expires=soon", "expires must be Unix seconds"),
+            ("name=../squire expected_hash=ab expires=1", "name \"../squire\""),
+            ("name=a name=b expected_hash=ab expires=1", "name is given twice"),
+            ("name=squire hash=ab expires=1", "unknown field \"hash\""),
+            ("squire", "expected key=value"),
+        ] {
+            let err = load(&format!("# header\n{line}\n")).unwrap_err();
+            assert!(err.contains("line 2: ") && err.contains(problem), "{line}: {err}");
+        }
+    }
+
+    #[test]
+    fn report_lists_every_waiver_with_its_status() {
+        let waiver = |name: &str, status| (Waiver { name: name.to_string(), expected_hash: "ab".to_string(), expires_unix: 5 }, status);
+        let json = waivers_json(&[waiver("squire", WaiverUse::Applied), waiver("a\"b", WaiverUse::Unused)]);
+        assert_eq!(
+            json,
+            "[{\"name\":\"squire\",\"expected_hash\":\"ab\",\"expires\":5,\"status\":\"applied\"},{\"name\":\"a\\\"b\",\"expected_hash\":\"ab\",\"expires\":5,\"status\":\"unused\"}]"
+        );
+        assert_eq!(waivers_json(&[]), "[]");
+    }
+}
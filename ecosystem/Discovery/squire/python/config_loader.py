@@ -7,15 +7,24 @@ understandable even for readers who are new to programming.
 """
 
 import base64  # Base64 encoding/decoding keeps binary data readable in JSON files.
+import getpass  # Prompts for a password with echo turned off when stdin is a terminal.
 import hashlib  # Provides PBKDF2-HMAC-SHA256 for deriving keys from passphrases.
 import json  # Handles reading and parsing JSON configuration files.
 import os  # Gives access to environment variables where secrets are stored.
-from dataclasses import dataclass  # Simplifies the creation of lightweight data containers.
+from dataclasses import dataclass, field  # Simplifies the creation of lightweight data containers.
 from pathlib import Path  # Helps point at the config file inside this bot folder.
-from typing import List, Optional  # Type hints keep intent obvious to readers.
-
-from crypto import passwords
-from crypto import secrets as secret_vault
+import sys  # Command-line arguments and error output for the small CLI at the bottom.
+import tempfile  # Temporary sibling file so the encrypted config is replaced atomically.
+from typing import Any, Dict, List, Optional  # Type hints keep intent obvious to readers.
+
+try:
+    from .crypto import passwords
+    from .crypto import secrets as secret_vault
+    from .crypto import vault_agent
+except ImportError:  # Run from inside ``python/`` (``python main.py``) there is no package.
+    from crypto import passwords
+    from crypto import secrets as secret_vault
+    from crypto import vault_agent
 
 # Default path to the bot-local configuration file so the demo works out of the box
 # even after the repository was reorganized into per-bot folders.
@@ -50,13 +59,15 @@ class SecretRecord:
     Fields store the base64-encoded ciphertext, nonce (which includes the salt),
     and authentication tag produced by the ChaCha20-Poly1305 vault encryption
     routine. The values remain unreadable without the vault key supplied at
-    runtime.
+    runtime. ``format`` is ``"secretbox"`` for a secret carried over from the
+    old libsodium tooling (see ``crypto/secrets.py``); it is left out otherwise.
     """
 
     name: str
     nonce: str
     ciphertext: str
     tag: str
+    format: str = secret_vault.FORMAT_CHACHA_POLY
 
 
 @dataclass
@@ -65,11 +76,18 @@ class AppConfig:
     Aggregates the vault settings, encrypted secrets, and password hashes in a
     single structure so the rest of the program can operate on a strongly typed
     object rather than raw dictionaries.
+
+    ``additional_secrets`` holds the optional ``"additional_secrets"`` map from
+    the config file. Each value is either an envelope object (encrypted, for
+    things like a database password) or a plain string (for non-sensitive
+    settings that still belong with the rest). Older configs without the map
+    simply get an empty one.
     """
 
     vault: VaultConfig
     secrets: List[SecretRecord]
     password_hashes: List[str]
+    additional_secrets: Dict[str, Any] = field(default_factory=dict)
 
 
 def _load_json(path: Path) -> dict:
@@ -81,6 +99,25 @@ def _load_json(path: Path) -> dict:
         return json.load(handle)
 
 
+def _key_from_passphrase(passphrase: str, salt_b64: str) -> bytes:
+    """
+    Stretch a passphrase into a 32-byte master key with PBKDF2-HMAC-SHA256.
+
+    The 200,000 rounds are deliberately slow; ``vault-agent start`` runs them
+    once and keeps the result so later commands do not have to.
+    """
+
+    salt = base64.b64decode(salt_b64)
+
+    return hashlib.pbkdf2_hmac(
+        "sha256",
+        passphrase.encode("utf-8"),
+        salt,
+        200_000,
+        dklen=32,
+    )
+
+
 def _derive_master_key(vault_cfg: VaultConfig) -> Optional[bytes]:
     """
     Derive or load the master key based on the configuration flags.
@@ -92,15 +129,7 @@ def _derive_master_key(vault_cfg: VaultConfig) -> Optional[bytes]:
         if not passphrase or not salt_b64:
             return None
 
-        salt = base64.b64decode(salt_b64)
-
-        return hashlib.pbkdf2_hmac(
-            "sha256",
-            passphrase.encode("utf-8"),
-            salt,
-            200_000,
-            dklen=32,
-        )
+        return _key_from_passphrase(passphrase, salt_b64)
     else:
         key_b64 = os.environ.get(vault_cfg.key_env)
         if not key_b64:
@@ -133,26 +162,35 @@ def load_config(path: Path = DEFAULT_CONFIG_PATH) -> Optional[AppConfig]:
                 nonce=item["nonce"],
                 ciphertext=item["ciphertext"],
                 tag=item["tag"],
+                format=item.get("format", secret_vault.FORMAT_CHACHA_POLY),
             )
         )
 
+    additional = raw.get("additional_secrets", {})
+    if not isinstance(additional, dict):
+        raise TemplateError("/additional_secrets", "must be an object of name -> envelope or string")
+    for name, value in additional.items():
+        # The JSON shape tells the two kinds apart: an object is an envelope,
+        # a string is a plain value. Anything else is a mistake in the file.
+        if not isinstance(value, str) and not _is_envelope(value):
+            raise TemplateError(
+                f"/additional_secrets/{_pointer_part(name)}",
+                f"secret {name!r} must be a nonce/ciphertext/tag envelope or a plain string",
+            )
+
     cfg = AppConfig(
         vault=vault_cfg,
         secrets=secrets,
         password_hashes=raw.get("password_hashes", []),
+        additional_secrets=dict(additional),
     )
 
     master_key = _derive_master_key(vault_cfg)
     if master_key is None:
         return None
 
-    for record in cfg.secrets:
-        bundle = secret_vault.EncryptedSecret(
-            nonce=base64.b64decode(record.nonce),
-            ciphertext=base64.b64decode(record.ciphertext),
-            tag=base64.b64decode(record.tag),
-        )
-        plaintext = secret_vault.decrypt_secret(master_key, bundle)
+    for index, record in enumerate(cfg.secrets):
+        plaintext = secret_vault.decrypt_secret(master_key, _record_bundle(record, f"/secrets/{index}"))
         if plaintext is None:
             return None
 
@@ -160,6 +198,10 @@ def load_config(path: Path = DEFAULT_CONFIG_PATH) -> Optional[AppConfig]:
         if not passwords.is_probably_valid_hash(entry):
             return None
 
+    # Unlike the records above, a broken additional secret raises instead of
+    # returning ``None``, so the error can say which one is broken.
+    decrypt_additional_secrets(cfg, master_key)
+
     return cfg
 
 
@@ -175,15 +217,547 @@ def decrypt_all_secrets(cfg: AppConfig, master_key: Optional[bytes] = None) -> O
         return None
 
     decrypted: List[tuple[str, bytes]] = []
-    for record in cfg.secrets:
-        bundle = secret_vault.EncryptedSecret(
-            nonce=base64.b64decode(record.nonce),
-            ciphertext=base64.b64decode(record.ciphertext),
-            tag=base64.b64decode(record.tag),
-        )
-        plaintext = secret_vault.decrypt_secret(key_to_use, bundle)
+    for index, record in enumerate(cfg.secrets):
+        plaintext = secret_vault.decrypt_secret(key_to_use, _record_bundle(record, f"/secrets/{index}"))
         if plaintext is None:
             return None
         decrypted.append((record.name, plaintext))
 
     return decrypted
+
+
+def _record_bundle(record: SecretRecord, pointer: str) -> "secret_vault.EncryptedSecret":
+    """
+    Turn a ``secrets`` record into an envelope. A record whose ``format`` does
+    not fit its nonce raises ``TemplateError`` naming both formats, rather than
+    failing authentication without a reason.
+    """
+
+    bundle = secret_vault.EncryptedSecret(
+        nonce=base64.b64decode(record.nonce),
+        ciphertext=base64.b64decode(record.ciphertext),
+        tag=base64.b64decode(record.tag),
+        format=record.format,
+    )
+    try:
+        secret_vault.check_format(bundle)
+    except secret_vault.SecretFormatError as error:
+        raise TemplateError(pointer, f"secret {record.name!r}: {error}") from None
+    return bundle
+
+
+def decrypt_additional_secrets(cfg: AppConfig, master_key: bytes) -> Dict[str, str]:
+    """
+    Return every entry of ``additional_secrets`` as text: envelopes are
+    decrypted with ``master_key`` and plain strings are passed through.
+
+    Raises ``TemplateError`` whose pointer (``/additional_secrets/<name>``) and
+    message name the secret that could not be decrypted.
+    """
+
+    values: Dict[str, str] = {}
+    for name, value in cfg.additional_secrets.items():
+        pointer = f"/additional_secrets/{_pointer_part(name)}"
+        try:
+            values[name] = decrypt_template(master_key, value, pointer)
+        except TemplateError as error:
+            raise TemplateError(pointer, f"secret {name!r}: {error.reason}") from None
+    return values
+
+
+# -- Encrypting a whole config template ---------------------------------------
+#
+# Preparing a deployment used to mean encrypting one secret at a time and
+# pasting each envelope into ``config.json`` by hand. Instead, write the config
+# as ordinary JSON and mark each secret with the ``@secret:`` prefix:
+#
+#     {"discord_token": "@secret:abc123", "feature_flags": {"gateway": true}}
+#
+# ``encrypt_template`` swaps every marked string for an envelope object
+# (``{"nonce": ..., "ciphertext": ..., "tag": ...}``) and leaves everything else,
+# including the order of keys, exactly as it was.
+
+SECRET_PREFIX = "@secret:"
+
+# The keys of an envelope written by ``EncryptedSecret.to_storable``. A
+# secretbox envelope carried over from the old tooling also has ``"format"``.
+_ENVELOPE_KEYS = {"nonce", "ciphertext", "tag"}
+
+
+class TemplateError(ValueError):
+    """
+    One field of a template could not be encrypted or decrypted.
+
+    ``pointer`` is the JSON Pointer (RFC 6901) of that field, for example
+    ``/features/webhook/signing_key`` or ``/secrets/0``, so the message says
+    exactly where to look.
+    """
+
+    def __init__(self, pointer: str, reason: str) -> None:
+        super().__init__(f"{pointer or '/'}: {reason}")
+        self.pointer = pointer
+        self.reason = reason
+
+
+def _pointer_part(key: Any) -> str:
+    """Escape one key for a JSON Pointer: ``~`` becomes ``~0`` and ``/`` ``~1``."""
+
+    return str(key).replace("~", "~0").replace("/", "~1")
+
+
+def _is_envelope(value: Any) -> bool:
+    return isinstance(value, dict) and set(value) - {"format"} == _ENVELOPE_KEYS
+
+
+def encrypt_template(master_key: bytes, document: Any, pointer: str = "") -> Any:
+    """
+    Return a copy of ``document`` with every ``"@secret:..."`` string replaced
+    by an encrypted envelope. Objects and arrays are walked at any depth.
+    Raises ``TemplateError`` naming the field that failed.
+    """
+
+    if isinstance(document, dict):
+        return {
+            key: encrypt_template(master_key, value, f"{pointer}/{_pointer_part(key)}")
+            for key, value in document.items()
+        }
+    if isinstance(document, list):
+        return [
+            encrypt_template(master_key, value, f"{pointer}/{index}")
+            for index, value in enumerate(document)
+        ]
+    if isinstance(document, str) and document.startswith(SECRET_PREFIX):
+        plaintext = document[len(SECRET_PREFIX):]
+        if not plaintext:
+            raise TemplateError(pointer, "the secret after @secret: is empty")
+        try:
+            bundle = secret_vault.encrypt_secret(master_key, plaintext.encode("utf-8"))
+        except ValueError as error:
+            raise TemplateError(pointer, str(error)) from None
+        return json.loads(bundle.to_storable())
+    return document
+
+
+def _open_envelope(master_key: bytes, document: dict, pointer: str) -> bytes:
+    """Decrypt one envelope object, raising ``TemplateError`` at ``pointer`` when it cannot be opened."""
+
+    try:
+        bundle = secret_vault.EncryptedSecret(
+            nonce=base64.b64decode(document["nonce"], validate=True),
+            ciphertext=base64.b64decode(document["ciphertext"], validate=True),
+            tag=base64.b64decode(document["tag"], validate=True),
+            format=document.get("format", secret_vault.FORMAT_CHACHA_POLY),
+        )
+    except (TypeError, ValueError):
+        raise TemplateError(pointer, "envelope fields are not valid base64") from None
+    try:
+        secret_vault.check_format(bundle)
+    except secret_vault.SecretFormatError as error:
+        raise TemplateError(pointer, str(error)) from None
+    plaintext = secret_vault.decrypt_secret(master_key, bundle)
+    if plaintext is None:
+        raise TemplateError(pointer, "envelope failed authentication (wrong key or edited value)")
+    return plaintext
+
+
+def decrypt_template(master_key: bytes, document: Any, pointer: str = "") -> Any:
+    """
+    The reverse of ``encrypt_template``, for debugging: every envelope becomes
+    its plaintext string again. Only ever print the result; never save it.
+    """
+
+    if _is_envelope(document):
+        return _open_envelope(master_key, document, pointer).decode("utf-8", errors="replace")
+    if isinstance(document, dict):
+        return {
+            key: decrypt_template(master_key, value, f"{pointer}/{_pointer_part(key)}")
+            for key, value in document.items()
+        }
+    if isinstance(document, list):
+        return [
+            decrypt_template(master_key, value, f"{pointer}/{index}")
+            for index, value in enumerate(document)
+        ]
+    return document
+
+
+def reencrypt_template(master_key: bytes, document: Any, pointer: str = "") -> Any:
+    """
+    Return a copy of ``document`` with every secretbox envelope (see
+    ``crypto/secrets.py``) opened and sealed again in the native format. This
+    covers ``additional_secrets`` values and ``secrets`` records alike; a
+    record keeps its ``name``. Native envelopes and plain values are left
+    exactly as they are, so running it twice changes nothing the second time.
+    """
+
+    if isinstance(document, dict) and _ENVELOPE_KEYS <= set(document):
+        if document.get("format") != secret_vault.FORMAT_SECRETBOX:
+            return document
+        plaintext = _open_envelope(master_key, document, pointer)
+        fresh = json.loads(secret_vault.encrypt_secret(master_key, plaintext).to_storable())
+        extra = {key: value for key, value in document.items() if key not in _ENVELOPE_KEYS and key != "format"}
+        return {**extra, **fresh}
+    if isinstance(document, dict):
+        return {
+            key: reencrypt_template(master_key, value, f"{pointer}/{_pointer_part(key)}")
+            for key, value in document.items()
+        }
+    if isinstance(document, list):
+        return [
+            reencrypt_template(master_key, value, f"{pointer}/{index}")
+            for index, value in enumerate(document)
+        ]
+    return document
+
+
+def _write_json_atomically(path: Path, document: Any) -> None:
+    """
+    Write ``document`` to a temporary file in the same folder, flush it to disk,
+    then rename it over ``path``. A crash leaves the old file or the new one,
+    never half of one. The file is readable by its owner only (0600).
+    """
+
+    folder = path.parent if str(path.parent) else Path(".")
+    descriptor, temp_name = tempfile.mkstemp(prefix=path.name + ".tmp-", dir=folder)
+    try:
+        with os.fdopen(descriptor, "w", encoding="utf-8") as handle:
+            json.dump(document, handle, indent=2)
+            handle.write("\n")
+            handle.flush()
+            os.fsync(handle.fileno())
+        os.replace(temp_name, path)
+    except BaseException:
+        if os.path.exists(temp_name):
+            os.remove(temp_name)
+        raise
+
+
+# -- Migrating a legacy plaintext config ---------------------------------------
+#
+# Older deployments keep the token in plain text (or as a ``$ENV{NAME}``
+# placeholder) in a flat file:
+#
+#     {"discord_token": "...", "application_id": "...", "public_key": "...",
+#      "database_path": "data/squire.db", "feature_flags": {"gateway": true}}
+#
+# ``migrate_legacy`` turns that into the vault layout this loader reads: the
+# token and application id become encrypted ``secrets`` records, the public key
+# (which is public by definition) becomes a plain ``additional_secrets`` value,
+# and the path and flags are copied across as they are.
+
+# Legacy field -> name of the encrypted record in the new ``secrets`` list.
+_LEGACY_ENCRYPTED_FIELDS = {"discord_token": "discord_bot_token", "application_id": "application_id"}
+
+
+def _resolve_env_placeholder(field_name: str, value: Any) -> Any:
+    """
+    Swap a whole-value ``$ENV{NAME}`` placeholder for the variable's value, the
+    same rule the Rust gateway's ``src/config.rs`` uses. Other values pass
+    through unchanged.
+    """
+
+    if not isinstance(value, str) or not value.startswith("$ENV{"):
+        return value
+    if not value.endswith("}") or len(value) <= len("$ENV{}"):
+        raise TemplateError(f"/{field_name}", f"{value!r} is not a valid $ENV{{NAME}} placeholder")
+    name = value[len("$ENV{"):-1]
+    resolved = os.environ.get(name)
+    if resolved is None:
+        raise TemplateError(f"/{field_name}", f"environment variable {name} is not set")
+    return resolved
+
+
+def migrate_legacy(master_key: bytes, legacy: dict, key_env: str) -> dict:
+    """
+    Build a vault-format config from a legacy plaintext one. ``key_env`` is
+    recorded in the new file so ``load_config`` knows where the key lives.
+    """
+
+    if not isinstance(legacy, dict):
+        raise TemplateError("", "the legacy config must be a JSON object")
+    token = _resolve_env_placeholder("discord_token", legacy.get("discord_token"))
+    if not isinstance(token, str) or not token:
+        raise TemplateError("/discord_token", "a non-empty discord_token is required")
+
+    records = []
+    for field_name, record_name in _LEGACY_ENCRYPTED_FIELDS.items():
+        value = _resolve_env_placeholder(field_name, legacy.get(field_name))
+        if value is None:
+            continue
+        if isinstance(value, int) and not isinstance(value, bool):
+            value = str(value)  # Some files stored the application id as a number.
+        if not isinstance(value, str):
+            raise TemplateError(f"/{field_name}", "must be a string")
+        envelope = encrypt_template(master_key, SECRET_PREFIX + value, f"/{field_name}")
+        records.append({"name": record_name, **envelope})
+
+    migrated: dict = {
+        "vault": {"key_env": key_env, "salt_env": "", "derived_from_passphrase": False},
+        "secrets": records,
+        "password_hashes": [],
+        "additional_secrets": {},
+        "feature_flags": legacy.get("feature_flags", {}),
+    }
+    public_key = _resolve_env_placeholder("public_key", legacy.get("public_key"))
+    if public_key is not None:
+        migrated["additional_secrets"]["public_key"] = str(public_key)
+    if legacy.get("database_path") is not None:
+        migrated["database_path"] = legacy["database_path"]
+    if "logging_channel_id" in legacy:
+        migrated["logging_channel_id"] = legacy["logging_channel_id"]
+    return migrated
+
+
+def _master_key_from_env(key_env: str) -> bytes:
+    """Read a base64 master key from ``key_env`` or stop with a clear message."""
+
+    key = _derive_master_key(VaultConfig(key_env=key_env, salt_env="", derived_from_passphrase=False))
+    if not key:
+        raise SystemExit(f"{key_env} is not set to a base64 master key")
+    return key
+
+
+def _secret_vault_for(key_env: str):
+    """
+    The vault ``encrypt-secret`` and ``decrypt-secret`` work with.
+
+    When ``SQUIRE_VAULT_AGENT`` names a running agent, requests go to it and
+    ``key_env`` is never read. When the variable is unset, or the socket is
+    missing or nobody answers on it, the master key comes from ``key_env`` as
+    it always did; a stale socket gets a note on stderr so the fallback is not
+    silent.
+    """
+
+    agent = vault_agent.client_from_env()
+    if agent is not None:
+        try:
+            agent.fingerprint()
+            return agent
+        except (OSError, secret_vault.SecretVaultError) as error:
+            print(f"vault agent at {agent.socket_path} is not answering ({error}); using {key_env}", file=sys.stderr)
+    return secret_vault.SecretVault(_master_key_from_env(key_env))
+
+
+def _vault_agent_start(args: List[str]) -> int:
+    """``vault-agent start <salt_env> [--socket <path>] [--idle-timeout <secs>]``."""
+
+    salt_env, options = args[0], args[1:]
+    socket_path = os.environ.get(vault_agent.AGENT_ENV, "")
+    idle_timeout = float(vault_agent.DEFAULT_IDLE_TIMEOUT_SECS)
+    while options:
+        if len(options) < 2 or options[0] not in ("--socket", "--idle-timeout"):
+            print(_USAGE, file=sys.stderr)
+            return 2
+        if options[0] == "--socket":
+            socket_path = options[1]
+        else:
+            try:
+                idle_timeout = float(options[1])
+            except ValueError:
+                idle_timeout = 0.0
+            if not idle_timeout > 0:
+                print(f"vault-agent start: --idle-timeout must be a positive number of seconds, not {options[1]!r}", file=sys.stderr)
+                return 2
+        options = options[2:]
+    if not socket_path:
+        print(f"vault-agent start: give --socket <path> or set {vault_agent.AGENT_ENV}", file=sys.stderr)
+        return 2
+    salt_b64 = os.environ.get(salt_env)
+    if not salt_b64:
+        raise SystemExit(f"{salt_env} is not set to the base64 vault salt")
+    # The passphrase is read from the terminal or stdin, never from the
+    # environment: keeping it out of every later command is the agent's job.
+    passphrase = _read_secret(None, "Vault passphrase: ")
+    if not passphrase:
+        print("vault-agent start: the passphrase is empty", file=sys.stderr)
+        return 1
+    agent = vault_agent.VaultAgent(
+        _key_from_passphrase(passphrase, salt_b64),
+        os.path.expanduser(socket_path),
+        idle_timeout=idle_timeout,
+    )
+    try:
+        agent.bind()
+    except (OSError, vault_agent.AgentError) as error:
+        agent.wipe()
+        print(f"vault-agent start failed: {error}", file=sys.stderr)
+        return 1
+    print(
+        f"vault agent listening on {socket_path} (key {agent.fingerprint}); "
+        f"it stops after {idle_timeout:g}s without a request",
+        file=sys.stderr,
+    )
+    try:
+        agent.serve()
+    except KeyboardInterrupt:
+        pass  # ``serve`` has already removed the socket and wiped the key.
+    return 0
+
+
+def _read_secret(argument: Optional[str], prompt: str) -> str:
+    """
+    The secret a command works on, kept out of argv where possible.
+
+    ``-`` or a missing argument means "not on the command line": on a terminal
+    the user is prompted with echo off, otherwise stdin is read to the end and
+    exactly one trailing newline is dropped, so ``echo pw | ...`` gives the
+    same text as ``printf pw | ...``. Any other argument is used as given.
+    """
+
+    if argument not in (None, "-"):
+        return argument
+    if sys.stdin.isatty():
+        return getpass.getpass(prompt)
+    text = sys.stdin.read()
+    if text.endswith("\r\n"):
+        return text[:-2]
+    if text.endswith("\n"):
+        return text[:-1]
+    return text
+
+
+_USAGE = """Usage:
+  python config_loader.py encrypt-config <key_env> <template.json> <out.json>
+      Replace every "@secret:<text>" string with a vault envelope and write out.json.
+  python config_loader.py decrypt-config <key_env> <config.json>
+      Print the config with envelopes decrypted. Output goes to stdout only.
+  python config_loader.py reencrypt-config <key_env> <config.json> <out.json>
+      Seal every libsodium "secretbox" envelope again in the native format.
+  python config_loader.py migrate-config <key_env> <old.json> <new.json> [--force]
+      Convert a legacy plaintext config to the vault format. An existing
+      new.json is only replaced with --force.
+  python config_loader.py audit-log read <key_env> <audit.log>
+      Decrypt a vault audit log (SecretVault.with_audit) and print one JSON
+      record per line, oldest first.
+  python config_loader.py hash-password [<password>|-]
+      Print a scrypt hash of the password.
+  python config_loader.py verify-password <stored_hash> [<password>|-]
+      Exit 0 when the password matches the hash, 1 when it does not.
+  python config_loader.py encrypt-secret <key_env> [<plaintext>|-]
+      Print a vault envelope (JSON) sealing the plaintext.
+  python config_loader.py decrypt-secret <key_env> [<envelope.json>|-]
+      Print the plaintext of an envelope read from a file or stdin.
+  python config_loader.py vault-agent start <salt_env> [--socket <path>] [--idle-timeout <secs>]
+      Read the vault passphrase once, derive the key, and answer
+      encrypt-secret/decrypt-secret over a 0600 Unix socket until idle for
+      <secs> (default 900). The socket defaults to $SQUIRE_VAULT_AGENT.
+  python config_loader.py vault-agent fingerprint
+      Print the key fingerprint of the agent named by $SQUIRE_VAULT_AGENT.
+
+<key_env> names the environment variable holding the base64 master key.
+A password or plaintext given as "-" or left out is read from stdin (one
+trailing newline is dropped), or prompted for with echo off on a terminal.
+Prefer that over the command line, which shows up in shell history and ps.
+With $SQUIRE_VAULT_AGENT pointing at a running agent, encrypt-secret and
+decrypt-secret use the agent's key and do not read <key_env>; without a
+reachable agent they fall back to <key_env>."""
+
+
+def main(argv: List[str]) -> int:
+    """Tiny command line for preparing deployments; see ``_USAGE``."""
+
+    if len(argv) == 4 and argv[0] == "encrypt-config":
+        key_env, template_path, out_path = argv[1:]
+        master_key = _master_key_from_env(key_env)
+        try:
+            encrypted = encrypt_template(master_key, _load_json(Path(template_path)))
+        except TemplateError as error:
+            print(f"encrypt-config failed at {error}", file=sys.stderr)
+            return 1
+        _write_json_atomically(Path(out_path), encrypted)
+        return 0
+    if len(argv) == 3 and argv[0] == "decrypt-config":
+        key_env, config_path = argv[1:]
+        master_key = _master_key_from_env(key_env)
+        try:
+            decrypted = decrypt_template(master_key, _load_json(Path(config_path)))
+        except TemplateError as error:
+            print(f"decrypt-config failed at {error}", file=sys.stderr)
+            return 1
+        print(json.dumps(decrypted, indent=2))
+        return 0
+    if len(argv) == 4 and argv[0] == "reencrypt-config":
+        key_env, config_path, out_path = argv[1:]
+        master_key = _master_key_from_env(key_env)
+        try:
+            upgraded = reencrypt_template(master_key, _load_json(Path(config_path)))
+        except TemplateError as error:
+            print(f"reencrypt-config failed at {error}", file=sys.stderr)
+            return 1
+        _write_json_atomically(Path(out_path), upgraded)
+        return 0
+    if argv[:1] == ["migrate-config"] and len(argv) in (4, 5) and argv[4:] in ([], ["--force"]):
+        key_env, old_path, new_path = argv[1:4]
+        if Path(new_path).exists() and "--force" not in argv:
+            print(f"migrate-config refused: {new_path} already exists (add --force to replace it)", file=sys.stderr)
+            return 1
+        master_key = _master_key_from_env(key_env)
+        try:
+            migrated = migrate_legacy(master_key, _load_json(Path(old_path)), key_env)
+        except TemplateError as error:
+            # The error names the field, never its value, so the token is not echoed.
+            print(f"migrate-config failed at {error}", file=sys.stderr)
+            return 1
+        _write_json_atomically(Path(new_path), migrated)
+        return 0
+    if len(argv) == 4 and argv[:2] == ["audit-log", "read"]:
+        key_env, log_path = argv[2:]
+        # Records are sealed by the audit subvault, never by the master key itself.
+        audit_vault = secret_vault.SecretVault(_master_key_from_env(key_env)).derive_subvault(secret_vault.AUDIT_PURPOSE)
+        try:
+            records = secret_vault.read_audit_log(Path(log_path), audit_vault)
+        except (OSError, secret_vault.SecretVaultError) as error:
+            print(f"audit-log read failed: {error}", file=sys.stderr)
+            return 1
+        for record in records:
+            print(json.dumps(record, sort_keys=True))
+        return 0
+    if argv[:1] == ["hash-password"] and len(argv) <= 2:
+        print(passwords.hash_password(_read_secret(argv[1] if len(argv) == 2 else None, "Password: ")))
+        return 0
+    if argv[:1] == ["verify-password"] and len(argv) in (2, 3):
+        stored_hash = argv[1]
+        plaintext = _read_secret(argv[2] if len(argv) == 3 else None, "Password: ")
+        if passwords.verify_password(plaintext, stored_hash):
+            return 0
+        print("verify-password: the password does not match", file=sys.stderr)
+        return 1
+    if argv[:1] == ["encrypt-secret"] and len(argv) in (2, 3):
+        vault = _secret_vault_for(argv[1])
+        plaintext = _read_secret(argv[2] if len(argv) == 3 else None, "Secret: ")
+        try:
+            print(vault.encrypt(plaintext.encode("utf-8")).to_storable())
+        except (OSError, ValueError) as error:
+            print(f"encrypt-secret failed: {error}", file=sys.stderr)
+            return 1
+        return 0
+    if argv[:1] == ["decrypt-secret"] and len(argv) in (2, 3):
+        vault = _secret_vault_for(argv[1])
+        source = argv[2] if len(argv) == 3 else "-"
+        try:
+            serialized = sys.stdin.read() if source == "-" else Path(source).read_text(encoding="utf-8")
+            plaintext = vault.decrypt(secret_vault.EncryptedSecret.from_storable(serialized)).decode("utf-8")
+        except (OSError, ValueError, KeyError) as error:
+            print(f"decrypt-secret failed: {error}", file=sys.stderr)
+            return 1
+        sys.stdout.write(plaintext)
+        return 0
+    if argv[:2] == ["vault-agent", "start"] and len(argv) >= 3:
+        return _vault_agent_start(argv[2:])
+    if argv == ["vault-agent", "fingerprint"]:
+        agent = vault_agent.client_from_env()
+        if agent is None:
+            print(f"vault-agent fingerprint: {vault_agent.AGENT_ENV} does not name a socket", file=sys.stderr)
+            return 1
+        try:
+            print(agent.fingerprint())
+        except (OSError, secret_vault.SecretVaultError) as error:
+            print(f"vault-agent fingerprint failed: {error}", file=sys.stderr)
+            return 1
+        return 0
+    print(_USAGE, file=sys.stderr)
+    return 2
+
+
+if __name__ == "__main__":
+    sys.exit(main(sys.argv[1:]))
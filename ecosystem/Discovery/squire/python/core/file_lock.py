@@ -0,0 +1,164 @@
+"""
+Advisory lock files shared with the Rust hub and gateway.
+
+Why this exists
+---------------
+Several programs append to the same ``Discovery/gateway_queue.log``: this
+logger, the Rust gateway, and the ecosystem hub's router. When two of them
+write at the same moment their lines can get mixed together. Everyone agrees
+to create ``<file>.lock`` first and to delete it when done. Creating the lock
+uses ``os.O_CREAT | os.O_EXCL``, which asks the operating system to fail if
+the file already exists, so only one writer can hold it at a time.
+
+The Rust side lives in ``ecosystem/common/src/lockfile.rs`` and follows the same rules:
+- the lock is the target path plus ``.lock``;
+- a lock older than ``STALE_AFTER_SECONDS`` (30) belongs to a writer that
+  crashed, so it is removed and a warning is printed;
+- a lock is never deleted by name. It is renamed aside first and deleted only
+  if it still holds the owner line that was judged stale (or, on release, our
+  own line). A fresh lock moved aside by mistake is linked back, so two
+  waiters reclaiming at once cannot both end up holding it.
+
+No network access happens here; only local files are touched.
+"""
+
+import itertools
+import os
+import sys
+import threading
+import time
+from typing import Optional
+
+# A lock untouched for this long is considered abandoned and reclaimed.
+STALE_AFTER_SECONDS = 30.0
+
+# First pause between attempts; it doubles up to ``MAX_BACKOFF_SECONDS``.
+FIRST_BACKOFF_SECONDS = 0.002
+MAX_BACKOFF_SECONDS = 0.1
+
+# Numbers owner lines and aside names, so two threads never write the same one.
+_SEQUENCE = itertools.count()
+_SEQUENCE_LOCK = threading.Lock()
+
+
+def _next_sequence() -> int:
+    with _SEQUENCE_LOCK:
+        return next(_SEQUENCE)
+
+
+def lock_path(target: str) -> str:
+    """Return the lock file used for ``target`` (the same name Rust uses)."""
+
+    return target + ".lock"
+
+
+def _remove_if_owned(path: str, owner: str) -> bool:
+    """
+    Delete the lock at ``path`` only if it still holds ``owner``.
+
+    The lock is renamed to a name nobody else uses before it is read, so the
+    check and the delete see the same file. A lock that belongs to someone
+    else is linked back (a link never replaces a lock created meanwhile).
+    """
+
+    aside = f"{path}.aside-{os.getpid()}-{_next_sequence()}"
+    try:
+        os.rename(path, aside)
+    except FileNotFoundError:
+        return False
+    try:
+        with open(aside, encoding="utf-8") as handle:
+            found = handle.read()
+    except OSError:
+        found = None
+    if found == owner:
+        os.remove(aside)
+        return True
+    try:
+        os.link(aside, path)
+    except OSError as error:
+        print(f"[file_lock] Could not put back another writer's lock {path}: {error}", file=sys.stderr)
+    os.remove(aside)
+    return False
+
+
+def _create(path: str) -> Optional[str]:
+    """Create the lock if nobody has it. Returns our owner line, or ``None``."""
+
+    try:
+        descriptor = os.open(path, os.O_CREAT | os.O_EXCL | os.O_WRONLY, 0o644)
+    except FileExistsError:
+        return None
+    # Write who owns the lock so a person inspecting a stuck lock can tell;
+    # with the sequence number the line is ours alone.
+    owner = f"pid={os.getpid()} at={int(time.time() * 1000)} seq={_next_sequence()}\n"
+    os.write(descriptor, owner.encode("utf-8"))
+    os.close(descriptor)
+    return owner
+
+
+def _try_create(path: str) -> Optional[str]:
+    """
+    Make one attempt to create the lock, reclaiming it first when stale.
+    Returns our owner line when we now hold it, or ``None``.
+    """
+
+    owner = _create(path)
+    if owner is not None:
+        return owner
+    try:
+        age = time.time() - os.path.getmtime(path)
+        if age <= STALE_AFTER_SECONDS:
+            return None
+        with open(path, encoding="utf-8") as handle:
+            stale_owner = handle.read()
+    except OSError:
+        # The owner released it between our calls; just try again.
+        return None
+    if _remove_if_owned(path, stale_owner):
+        print(f"[file_lock] Reclaimed stale lock {path}", file=sys.stderr)
+        return _create(path)
+    return None
+
+
+class FileLock:
+    """
+    Hold ``<target>.lock`` for the length of a ``with`` block.
+
+    Example::
+
+        with FileLock("Discovery/gateway_queue.log"):
+            ...append to the file...
+    """
+
+    def __init__(self, target: str) -> None:
+        self.path = lock_path(target)
+        self.owner: Optional[str] = None
+
+    def __enter__(self) -> "FileLock":
+        backoff = FIRST_BACKOFF_SECONDS
+        # This loop always ends: at worst the lock turns stale and is reclaimed.
+        while True:
+            self.owner = _try_create(self.path)
+            if self.owner is not None:
+                return self
+            time.sleep(backoff)
+            backoff = min(backoff * 2, MAX_BACKOFF_SECONDS)
+
+    def __exit__(self, exc_type, exc, traceback) -> None:
+        # Someone may have reclaimed the lock while we were slow; theirs stays.
+        try:
+            _remove_if_owned(self.path, self.owner)
+        except OSError:
+            pass
+
+
+def append_line_locked(path: str, line: str) -> None:
+    """Append ``line`` and a newline to ``path`` while holding its lock."""
+
+    folder = os.path.dirname(path)
+    if folder:
+        os.makedirs(folder, exist_ok=True)
+    with FileLock(path):
+        with open(path, "a", encoding="utf-8") as handle:
+            handle.write(line + "\n")
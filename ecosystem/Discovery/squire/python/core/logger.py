@@ -10,6 +10,11 @@ entire logging pipeline is visible within this repository.
 import datetime  # Standard-library time handling for timestamps.
 import os  # Used to resolve default log file locations inside the bot folder.
 
+try:
+    from .file_lock import append_line_locked  # Shared lock convention with Rust.
+except ImportError:  # Running this file directly as a script has no package.
+    from file_lock import append_line_locked
+
 # `BASE_DIR` pins all file output to the bot’s own folder even if the process is
 # launched from somewhere else. This reduces the risk of logs spilling into
 # unexpected locations on a compromised host.
@@ -73,13 +78,12 @@ def _with_timestamp(sink_function):
 def _write_line(path: str, line: str) -> None:
     """
     Append a single line to the given path, creating parent folders as needed.
+
+    The write holds ``<path>.lock`` (see ``file_lock.py``) so lines from the
+    Rust gateway or hub writing the same file at the same time never get mixed.
     """
 
-    folder = os.path.dirname(path)
-    if folder and not os.path.exists(folder):
-        os.makedirs(folder, exist_ok=True)
-    with open(path, "a", encoding="utf-8") as handle:
-        handle.write(line + "\n")
+    append_line_locked(path, line)
 
 
 def create_logger(level: str = "info", log_to_file: bool = True) -> dict:
@@ -0,0 +1,120 @@
+"""Tests for the advisory lock files.
+
+Run from ``ecosystem/Discovery`` with
+`python -m unittest squire.python.core.test_file_lock`.
+"""
+
+import os
+import tempfile
+import threading
+import time
+import unittest
+
+from squire.python.core import file_lock
+
+
+class FileLockTests(unittest.TestCase):
+    def test_two_threads_append_without_mixing_lines(self):
+        """500 lines from each of two threads arrive as exactly 1000 whole lines."""
+
+        with tempfile.TemporaryDirectory() as folder:
+            path = os.path.join(folder, "Discovery", "gateway_queue.log")
+
+            def writer(name):
+                for number in range(500):
+                    file_lock.append_line_locked(path, f"{name}-{number}-" + "x" * 200)
+
+            threads = [threading.Thread(target=writer, args=(name,)) for name in ("a", "b")]
+            for thread in threads:
+                thread.start()
+            for thread in threads:
+                thread.join()
+
+            with open(path, encoding="utf-8") as handle:
+                lines = handle.read().splitlines()
+            self.assertEqual(len(lines), 1000)
+            for line in lines:
+                self.assertRegex(line, r"^[ab]-\d+-x{200}$")
+            self.assertFalse(os.path.exists(file_lock.lock_path(path)))
+
+    def test_stale_lock_is_reclaimed(self):
+        """A lock left behind by a crashed writer does not block forever."""
+
+        with tempfile.TemporaryDirectory() as folder:
+            path = os.path.join(folder, "queue.log")
+            lock = file_lock.lock_path(path)
+            with open(lock, "w", encoding="utf-8") as handle:
+                handle.write("pid=1 at=0\n")
+            old = time.time() - file_lock.STALE_AFTER_SECONDS - 5
+            os.utime(lock, (old, old))
+
+            file_lock.append_line_locked(path, "after the crash")
+
+            with open(path, encoding="utf-8") as handle:
+                self.assertEqual(handle.read(), "after the crash\n")
+            self.assertFalse(os.path.exists(lock))
+
+    def test_release_leaves_a_lock_that_now_belongs_to_someone_else(self):
+        """A holder whose lock was reclaimed does not delete the new owner's lock."""
+
+        with tempfile.TemporaryDirectory() as folder:
+            path = os.path.join(folder, "queue.log")
+            lock = file_lock.lock_path(path)
+            with file_lock.FileLock(path):
+                with open(lock, "w", encoding="utf-8") as handle:
+                    handle.write("pid=2 at=5 seq=9\n")
+            with open(lock, encoding="utf-8") as handle:
+                self.assertEqual(handle.read(), "pid=2 at=5 seq=9\n")
+            self.assertEqual(os.listdir(folder), ["queue.log.lock"])
+
+    def test_waiters_racing_for_a_stale_lock_never_hold_it_together(self):
+        """Eight threads that all find the same stale lock take turns."""
+
+        with tempfile.TemporaryDirectory() as folder:
+            path = os.path.join(folder, "queue.log")
+            lock = file_lock.lock_path(path)
+            with open(lock, "w", encoding="utf-8") as handle:
+                handle.write("pid=1 at=0\n")
+            old = time.time() - file_lock.STALE_AFTER_SECONDS - 5
+            os.utime(lock, (old, old))
+            holders = []
+            overlaps = []
+
+            def worker():
+                for _ in range(20):
+                    with file_lock.FileLock(path):
+                        holders.append(1)
+                        if len(holders) > 1:
+                            overlaps.append(1)
+                        time.sleep(0.0002)
+                        holders.pop()
+
+            threads = [threading.Thread(target=worker) for _ in range(8)]
+            for thread in threads:
+                thread.start()
+            for thread in threads:
+                thread.join()
+            self.assertEqual(overlaps, [])
+            self.assertEqual(os.listdir(folder), [])
+
+    def test_waiting_writer_proceeds_once_lock_is_released(self):
+        """A second writer waits for the first instead of deadlocking."""
+
+        with tempfile.TemporaryDirectory() as folder:
+            path = os.path.join(folder, "queue.log")
+            finished = threading.Event()
+
+            def second_writer():
+                file_lock.append_line_locked(path, "second")
+                finished.set()
+
+            with file_lock.FileLock(path):
+                thread = threading.Thread(target=second_writer)
+                thread.start()
+                self.assertFalse(finished.wait(0.1))
+            self.assertTrue(finished.wait(5))
+            thread.join()
+
+
+if __name__ == "__main__":
+    unittest.main()
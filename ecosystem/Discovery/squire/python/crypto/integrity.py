@@ -1,13 +1,31 @@
 """
-Integrity helpers that use SHA-256 and HMAC from Python's standard library.
+Integrity helpers that use SHA-256, SHA-512, BLAKE2b, and HMAC from Python's
+standard library.
 
 The functions here are intentionally verbose to demystify what hashing and HMAC
 mean in practice.
 """
 
+import enum
 import hashlib
 import hmac
-from typing import Tuple
+from pathlib import Path
+from typing import Tuple, Union
+
+# ``FILE_CHUNK_BYTES`` controls how much of a file is read at once when
+# fingerprinting it. 64 KiB keeps memory flat even for multi-gigabyte files.
+FILE_CHUNK_BYTES: int = 64 * 1024
+
+# Length of an HMAC-SHA256 tag in raw bytes (64 characters once hex-encoded).
+HMAC_SHA256_TAG_BYTES: int = 32
+
+
+class IntegrityError(ValueError):
+    """
+    Raised when an input is not even shaped like a tag (for example, a hex
+    string containing a ``g``). A well-formed but wrong tag is *not* an error;
+    the verify helpers simply return ``False`` for it.
+    """
 
 
 def sha256_digest(data: bytes) -> str:
@@ -23,6 +41,86 @@ def sha256_digest(data: bytes) -> str:
     return digest
 
 
+def sha512_hex(data: bytes) -> str:
+    """
+    Compute the SHA-512 hash of ``data`` as hex.
+
+    SHA-512 is the bigger sibling of SHA-256. Package archives (Debian, for
+    example) often publish both so a reader can cross-check either one.
+    """
+
+    return hashlib.sha512(data).hexdigest()
+
+
+def blake2b256_hex(data: bytes) -> str:
+    """
+    Compute a 256-bit BLAKE2b hash of ``data`` as hex.
+
+    BLAKE2b is fast on 64-bit machines. ``digest_size=32`` asks for 32 bytes
+    (256 bits) of output so the hex string has the same length as SHA-256.
+    """
+
+    return hashlib.blake2b(data, digest_size=32).hexdigest()
+
+
+class DigestAlgorithm(enum.Enum):
+    """
+    Names every digest this module can compute.
+
+    The enum values double as the labels used in text records, so manifest
+    code can write ``hash_sha512=...`` style fields by reading ``.value``.
+    """
+
+    SHA256 = "sha256"
+    SHA512 = "sha512"
+    BLAKE2B256 = "blake2b256"
+
+    @staticmethod
+    def from_name(name: str) -> "DigestAlgorithm":
+        """Look up an algorithm by its label, raising ``ValueError`` when unknown."""
+
+        for algorithm in DigestAlgorithm:
+            if algorithm.value == name:
+                return algorithm
+        known = ", ".join(a.value for a in DigestAlgorithm)
+        raise ValueError(f"unknown digest algorithm {name!r}; expected one of {known}")
+
+    def new_hasher(self):
+        """Return a fresh ``hashlib`` object for this algorithm."""
+
+        if self is DigestAlgorithm.SHA256:
+            return hashlib.sha256()
+        if self is DigestAlgorithm.SHA512:
+            return hashlib.sha512()
+        return hashlib.blake2b(digest_size=32)
+
+
+def digest_hex(algorithm: DigestAlgorithm, data: bytes) -> str:
+    """Hash in-memory ``data`` with the chosen algorithm and return hex."""
+
+    hasher = algorithm.new_hasher()
+    hasher.update(data)
+    return hasher.hexdigest()
+
+
+def digest_file(algorithm: DigestAlgorithm, path: Union[str, Path]) -> str:
+    """
+    Hash a file with the chosen algorithm, streaming it in chunks.
+
+    Unreadable files raise the usual ``OSError`` (for example
+    ``FileNotFoundError``) so callers can report which path failed.
+    """
+
+    hasher = algorithm.new_hasher()
+    with open(path, "rb") as handle:
+        while True:
+            chunk = handle.read(FILE_CHUNK_BYTES)
+            if not chunk:
+                break
+            hasher.update(chunk)
+    return hasher.hexdigest()
+
+
 def hmac_sha256(key: bytes, data: bytes) -> str:
     """
     Compute an HMAC tag using SHA-256.
@@ -37,6 +135,63 @@ def hmac_sha256(key: bytes, data: bytes) -> str:
     return tag
 
 
+def sha256_file(path: Union[str, Path]) -> str:
+    """
+    Compute the SHA-256 of a file without loading it all into memory.
+
+    The file is read in ``FILE_CHUNK_BYTES`` pieces and each piece is fed to
+    the same hash object, so the result equals ``sha256_digest`` of the full
+    contents while memory use stays constant.
+    """
+
+    return digest_file(DigestAlgorithm.SHA256, path)
+
+
+def hmac_sha256_verify(key: bytes, data: bytes, tag: bytes) -> bool:
+    """
+    Check a raw (binary) HMAC-SHA256 tag in constant time.
+
+    Writing ``tag == expected`` would stop at the first differing byte, which
+    lets an attacker measure how much of a forged tag was right. This helper
+    always compares every byte with ``hmac.compare_digest``. Tags of the wrong
+    length simply fail instead of raising.
+    """
+
+    if len(tag) != HMAC_SHA256_TAG_BYTES:
+        return False
+    expected = hmac.new(key, data, hashlib.sha256).digest()
+    return hmac.compare_digest(expected, tag)
+
+
+def sign_then_hex(key: bytes, data: bytes) -> str:
+    """
+    Produce a lowercase hex HMAC-SHA256 tag, ready to write into a text file.
+
+    This is the partner of ``verify_hex``: whatever this function writes,
+    ``verify_hex`` accepts.
+    """
+
+    return hmac_sha256(key, data)
+
+
+def verify_hex(key: bytes, data: bytes, hex_tag: str) -> bool:
+    """
+    Verify a hex-encoded HMAC-SHA256 tag such as one read from a ``.sig`` file.
+
+    Surrounding whitespace (like a trailing newline) is ignored and uppercase
+    hex is accepted. Text that is not hex at all raises ``IntegrityError`` so
+    a corrupted file is reported differently from a wrong signature.
+    """
+
+    cleaned = hex_tag.strip()
+    try:
+        # ``bytes.fromhex`` accepts both upper- and lowercase digits.
+        tag = bytes.fromhex(cleaned)
+    except ValueError as error:
+        raise IntegrityError(f"tag is not valid hex: {error}") from error
+    return hmac_sha256_verify(key, data, tag)
+
+
 def hkdf_sha256(secret: bytes, salt: bytes, info: bytes, length: int) -> bytes:
     """
     A friendly wrapper around the HKDF logic used elsewhere in the repository.
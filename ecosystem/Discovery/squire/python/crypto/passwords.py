@@ -14,9 +14,14 @@ Design goals
 """
 
 import base64
+import binascii
+import enum
 import hashlib
+import hmac
 import os
-from typing import Dict
+import time
+from dataclasses import dataclass
+from typing import Dict, Optional, Tuple
 
 # ``DEFAULT_SCRYPT_PARAMS`` is a plain dictionary that lists the knobs controlling
 # how scrypt behaves. The keys are intentionally verbose and hold integers that
@@ -39,8 +44,156 @@ DEFAULT_SCRYPT_PARAMS: Dict[str, int] = {
 # value 16 bytes (128 bits) is a widely used baseline.
 SALT_LENGTH_BYTES: int = 16
 
+# ``LEGACY_PBKDF2_PREFIX`` is the label older deployments wrote at the start of
+# each stored hash. Records look like
+# ``pbkdf2_sha256$<iterations>$<salt>$<base64 hash>``. This is the same layout
+# Django uses: the salt is stored as plain text and used as its UTF-8 bytes,
+# while the derived key is standard base64.
+LEGACY_PBKDF2_PREFIX: str = "pbkdf2_sha256"
 
-def _encode_hash(salt: bytes, derived_key: bytes) -> str:
+# ``MAX_SCRYPT_MEMORY`` is the most working memory (in bytes) a stored record
+# may ask for. The heaviest calibrated profile needs 512 MiB; a record asking
+# for more is damaged or hostile, and running it would stall the login flow.
+# The Rust reader (``src/password.rs``) uses the same ceiling.
+MAX_SCRYPT_MEMORY: int = 1 << 30
+
+
+def _scrypt_maxmem(n: int, r: int, p: int) -> int:
+    """
+    Work out how much memory scrypt needs for the given parameters.
+
+    OpenSSL refuses to run scrypt when the work area exceeds its default 32 MiB
+    ceiling, and ``n=2**15, r=8`` lands exactly on that edge. Passing an explicit
+    ``maxmem`` (128 * r * (n + p + 2) bytes, plus 1 MiB of headroom) lets the
+    recipe run everywhere without silently lowering the cost.
+    """
+
+    return 128 * r * (n + p + 2) + 1024 * 1024
+
+
+@dataclass(frozen=True)
+class ScryptProfile:
+    """
+    A named bundle of scrypt cost parameters.
+
+    Different machines need different costs: a Raspberry Pi would take seconds
+    with the settings that suit a large server, while a large server could
+    afford far more than a Pi. A profile keeps the three knobs together so the
+    same recipe is applied consistently wherever it is chosen.
+
+    - ``n``: CPU/memory cost. Must be a power of two.
+    - ``r``: Block size. Memory use grows linearly with it.
+    - ``p``: Parallelization. Each extra unit repeats the whole computation.
+    """
+
+    n: int
+    r: int
+    p: int
+
+    @property
+    def memory_kib(self) -> int:
+        """Approximate working memory scrypt needs for this profile, in KiB."""
+
+        return 128 * self.r * self.n // 1024
+
+    @staticmethod
+    def interactive() -> "ScryptProfile":
+        """Light profile (16 MiB) for small hosts such as a Raspberry Pi."""
+
+        return ScryptProfile(n=2 ** 14, r=8, p=1)
+
+    @staticmethod
+    def moderate() -> "ScryptProfile":
+        """The long-standing default (32 MiB), matching ``DEFAULT_SCRYPT_PARAMS``."""
+
+        return ScryptProfile(
+            n=DEFAULT_SCRYPT_PARAMS["n"],
+            r=DEFAULT_SCRYPT_PARAMS["r"],
+            p=DEFAULT_SCRYPT_PARAMS["p"],
+        )
+
+    @staticmethod
+    def sensitive() -> "ScryptProfile":
+        """Heavy profile (128 MiB) for well-provisioned hosts guarding admin accounts."""
+
+        return ScryptProfile(n=2 ** 17, r=8, p=1)
+
+    @staticmethod
+    def calibrate(target_ms: int) -> "ScryptProfile":
+        """
+        Measure this machine and pick a profile whose hash time lands near
+        ``target_ms`` (within 25% either way when the ceiling allows it).
+
+        The search runs in two stages:
+        1. Double ``n`` starting from ``interactive()`` while one hash is still
+           faster than the target. Doubling ``n`` roughly doubles the time.
+        2. Nudge ``r`` upward one step at a time (about +12% each) to close the
+           remaining gap, because doubling alone can jump over the window.
+
+        ``CALIBRATION_MAX_N`` and ``CALIBRATION_MAX_R`` cap the search so a
+        generous target can never ask for more memory than the host has.
+        """
+
+        if target_ms <= 0:
+            raise ValueError("target_ms must be positive")
+
+        lower = target_ms * 0.75
+        upper = target_ms * 1.25
+
+        profile = ScryptProfile.interactive()
+        elapsed = _time_profile_ms(profile)
+        while elapsed < lower and profile.n * 2 <= CALIBRATION_MAX_N:
+            candidate = ScryptProfile(n=profile.n * 2, r=profile.r, p=profile.p)
+            candidate_elapsed = _time_profile_ms(candidate)
+            if candidate_elapsed > upper:
+                # The doubled cost overshoots; stay here and let ``r`` fine-tune.
+                break
+            profile, elapsed = candidate, candidate_elapsed
+
+        while elapsed < lower and profile.r < CALIBRATION_MAX_R:
+            candidate = ScryptProfile(n=profile.n, r=profile.r + 1, p=profile.p)
+            candidate_elapsed = _time_profile_ms(candidate)
+            if candidate_elapsed > upper:
+                break
+            profile, elapsed = candidate, candidate_elapsed
+
+        return profile
+
+
+# Upper bounds for ``ScryptProfile.calibrate``. 2**18 with r=16 is 512 MiB,
+# which is already more than most bot hosts should spend on one login.
+CALIBRATION_MAX_N: int = 2 ** 18
+CALIBRATION_MAX_R: int = 16
+
+
+def _time_profile_ms(profile: ScryptProfile) -> float:
+    """Hash a throwaway password with ``profile`` and return the elapsed milliseconds."""
+
+    started = time.perf_counter()
+    _derive_scrypt(b"calibration-probe", os.urandom(SALT_LENGTH_BYTES), profile, 32)
+    return (time.perf_counter() - started) * 1000
+
+
+def _derive_scrypt(password_bytes: bytes, salt: bytes, profile: ScryptProfile, dklen: int) -> bytes:
+    """
+    Run ``hashlib.scrypt`` with the parameters from ``profile``.
+
+    Both hashing and verification go through this helper so the memory ceiling
+    (``maxmem``) is always computed the same way.
+    """
+
+    return hashlib.scrypt(
+        password_bytes,
+        salt=salt,
+        n=profile.n,
+        r=profile.r,
+        p=profile.p,
+        maxmem=_scrypt_maxmem(profile.n, profile.r, profile.p),
+        dklen=dklen,
+    )
+
+
+def _encode_hash(salt: bytes, derived_key: bytes, profile: ScryptProfile) -> str:
     """
     Combine the salt, the derived key, and the parameter choices into one
     portable string.
@@ -56,14 +209,15 @@ def _encode_hash(salt: bytes, derived_key: bytes) -> str:
     salt_b64 = base64.b64encode(salt).decode("utf-8")
     key_b64 = base64.b64encode(derived_key).decode("utf-8")
     return (
-        f"scrypt$n={DEFAULT_SCRYPT_PARAMS['n']}$r={DEFAULT_SCRYPT_PARAMS['r']}" \
-        f"$p={DEFAULT_SCRYPT_PARAMS['p']}$salt={salt_b64}$key={key_b64}"
+        f"scrypt$n={profile.n}$r={profile.r}" \
+        f"$p={profile.p}$salt={salt_b64}$key={key_b64}"
     )
 
 
 def hash_password(plaintext: str) -> str:
     """
-    Turn a user-provided plaintext password into a stored hash string.
+    Turn a user-provided plaintext password into a stored hash string using
+    the default (``moderate``) profile.
 
     Step-by-step process explained in plain English:
     1. Generate a unique, random salt for this password using ``os.urandom``.
@@ -76,6 +230,17 @@ def hash_password(plaintext: str) -> str:
        string so we can store everything required for verification later.
     """
 
+    return hash_password_with(plaintext, ScryptProfile.moderate())
+
+
+def hash_password_with(plaintext: str, profile: ScryptProfile) -> str:
+    """
+    Same as ``hash_password`` but with an explicit cost profile.
+
+    The chosen parameters are written into the stored string, so
+    ``verify_password`` needs no extra configuration to check it later.
+    """
+
     # Convert the incoming text into bytes because scrypt operates on byte
     # sequences rather than Python strings.
     password_bytes = plaintext.encode("utf-8")
@@ -83,19 +248,12 @@ def hash_password(plaintext: str) -> str:
     # Create a fresh cryptographic salt for this password hash.
     salt = os.urandom(SALT_LENGTH_BYTES)
 
-    # Run scrypt with the centralized parameters. ``dklen`` sets the length of
-    # the derived key; 32 bytes (256 bits) is plenty for verification purposes.
-    derived_key = hashlib.scrypt(
-        password_bytes,
-        salt=salt,
-        n=DEFAULT_SCRYPT_PARAMS["n"],
-        r=DEFAULT_SCRYPT_PARAMS["r"],
-        p=DEFAULT_SCRYPT_PARAMS["p"],
-        dklen=32,
-    )
+    # ``dklen`` sets the length of the derived key; 32 bytes (256 bits) is
+    # plenty for verification purposes.
+    derived_key = _derive_scrypt(password_bytes, salt, profile, 32)
 
     # Return the full record as a text string ready to store in config files.
-    return _encode_hash(salt, derived_key)
+    return _encode_hash(salt, derived_key, profile)
 
 
 def verify_password(plaintext: str, stored_hash: str) -> bool:
@@ -107,36 +265,25 @@ def verify_password(plaintext: str, stored_hash: str) -> bool:
     matches the stored one, the password is correct.
     """
 
-    # Split the stored string back into its labeled parts. The format is
-    # rigidly defined in ``_encode_hash`` so we can rely on the ordering here.
+    # Split the stored string back into its labeled parts and decode the
+    # base64 fields. ``_parse_scrypt_record`` raises ``ValueError`` for anything
+    # that does not follow ``_encode_hash``, including bad base64 and costs
+    # scrypt cannot (or should not) run with. Returning False keeps the caller
+    # safe without crashing.
     try:
-        _, n_part, r_part, p_part, salt_part, key_part = stored_hash.split("$")
-        n_value = int(n_part.split("=")[1])
-        r_value = int(r_part.split("=")[1])
-        p_value = int(p_part.split("=")[1])
-        salt_b64 = salt_part.split("=")[1]
-        key_b64 = key_part.split("=")[1]
-    except (ValueError, IndexError):
-        # If parsing fails, the stored string is malformed. Returning False keeps
-        # the caller safe without crashing.
+        profile, salt, expected_key = _parse_scrypt_record(stored_hash)
+    except ValueError:
         return False
 
-    # Decode the base64 fields back into raw bytes so we can feed them into
-    # ``hashlib.scrypt`` for verification.
-    salt = base64.b64decode(salt_b64)
-    expected_key = base64.b64decode(key_b64)
-
     # Run scrypt with the exact same parameters and salt. Using the provided
     # values (rather than the defaults) ensures compatibility with hashes that
-    # may have been created with different settings in the future.
-    derived_key = hashlib.scrypt(
-        plaintext.encode("utf-8"),
-        salt=salt,
-        n=n_value,
-        r=r_value,
-        p=p_value,
-        dklen=len(expected_key),
-    )
+    # may have been created with different settings in the future. OpenSSL can
+    # still refuse parameters the parser let through (for example when memory
+    # is short), and that is a failed check too, not a crash.
+    try:
+        derived_key = _derive_scrypt(plaintext.encode("utf-8"), salt, profile, len(expected_key))
+    except (ValueError, MemoryError, OverflowError):
+        return False
 
     # Perform a constant-time comparison by comparing lengths first and then
     # iterating byte-by-byte. This simple loop avoids the subtle timing
@@ -148,3 +295,265 @@ def verify_password(plaintext: str, stored_hash: str) -> bool:
     for left, right in zip(derived_key, expected_key):
         mismatch |= left ^ right
     return mismatch == 0
+
+
+class VerifyOutcome(enum.Enum):
+    """
+    Describes how a password check went when legacy records are allowed.
+
+    - ``MATCH_CURRENT``: the password matched a hash written by this module.
+    - ``MATCH_LEGACY``: the password matched an older PBKDF2 record. The caller
+      should store a fresh scrypt hash (see ``rehash_if_legacy``) so the old
+      format slowly disappears as people log in.
+    - ``NO_MATCH``: wrong password, tampered record, or unknown format.
+    """
+
+    MATCH_CURRENT = "match-current"
+    MATCH_LEGACY = "match-legacy"
+    NO_MATCH = "no-match"
+
+
+@dataclass
+class LegacyPbkdf2Record:
+    """
+    The pieces of a ``pbkdf2_sha256$iterations$salt$hash`` string after parsing.
+
+    Keeping them in a small dataclass lets migration tooling inspect a record
+    (for example, to count how many users still use low iteration counts)
+    without re-implementing the parsing rules.
+    """
+
+    iterations: int
+    salt: bytes
+    derived_key: bytes
+
+
+def parse_legacy_pbkdf2(stored_hash: str) -> LegacyPbkdf2Record:
+    """
+    Split a legacy PBKDF2 record into its parts.
+
+    Raises ``ValueError`` with a message naming the broken field so operators
+    can tell a truncated record apart from a record in some other format.
+    """
+
+    parts = stored_hash.split("$")
+    if len(parts) != 4:
+        raise ValueError(
+            f"legacy hash must have 4 '$'-separated fields, found {len(parts)}"
+        )
+
+    prefix, iterations_text, salt_text, key_b64 = parts
+    if prefix != LEGACY_PBKDF2_PREFIX:
+        raise ValueError(f"legacy hash must start with '{LEGACY_PBKDF2_PREFIX}'")
+
+    # ``isdigit`` rejects signs and spaces so "-5" or " 100" never sneak through.
+    if not iterations_text.isdigit() or int(iterations_text) < 1:
+        raise ValueError(f"iteration count {iterations_text!r} is not a positive integer")
+
+    if not salt_text:
+        raise ValueError("salt field is empty")
+
+    try:
+        # ``validate=True`` refuses characters outside the base64 alphabet
+        # instead of silently skipping them.
+        derived_key = base64.b64decode(key_b64, validate=True)
+    except ValueError as error:
+        raise ValueError(f"hash field is not valid base64: {error}") from error
+    if not derived_key:
+        raise ValueError("hash field is empty")
+
+    return LegacyPbkdf2Record(
+        iterations=int(iterations_text),
+        salt=salt_text.encode("utf-8"),
+        derived_key=derived_key,
+    )
+
+
+def _verify_legacy_pbkdf2(plaintext: str, stored_hash: str) -> bool:
+    """
+    Re-run PBKDF2-HMAC-SHA256 with the stored salt and iteration count.
+
+    Malformed records return ``False`` just like ``verify_password`` does, so a
+    corrupted row locks out only that user instead of crashing the login flow.
+    """
+
+    try:
+        record = parse_legacy_pbkdf2(stored_hash)
+    except ValueError:
+        return False
+
+    derived_key = hashlib.pbkdf2_hmac(
+        "sha256",
+        plaintext.encode("utf-8"),
+        record.salt,
+        record.iterations,
+        dklen=len(record.derived_key),
+    )
+    # ``hmac.compare_digest`` takes the same time whether the first or the last
+    # byte differs, so attackers learn nothing from response timing.
+    return hmac.compare_digest(derived_key, record.derived_key)
+
+
+def verify_password_any(plaintext: str, stored_hash: str) -> VerifyOutcome:
+    """
+    Check a password against either the current scrypt format or a legacy
+    PBKDF2 record, and report which one matched.
+
+    The prefix before the first ``$`` decides which verifier runs, so a legacy
+    string is never fed to scrypt (and vice versa). A damaged record of either
+    format is ``NO_MATCH``, never an exception, so one bad row cannot break the
+    login flow for everyone.
+    """
+
+    prefix = stored_hash.split("$", 1)[0]
+    if prefix == "scrypt":
+        if verify_password(plaintext, stored_hash):
+            return VerifyOutcome.MATCH_CURRENT
+        return VerifyOutcome.NO_MATCH
+
+    if prefix == LEGACY_PBKDF2_PREFIX:
+        if _verify_legacy_pbkdf2(plaintext, stored_hash):
+            return VerifyOutcome.MATCH_LEGACY
+        return VerifyOutcome.NO_MATCH
+
+    return VerifyOutcome.NO_MATCH
+
+
+def rehash_if_legacy(plaintext: str, stored_hash: str) -> Optional[str]:
+    """
+    Return a fresh scrypt hash when ``plaintext`` matches a legacy record.
+
+    Login flows call this right after a successful check: if the result is not
+    ``None``, they replace the stored value with it. Wrong passwords and records
+    that already use scrypt return ``None`` so nothing is rewritten.
+    """
+
+    if verify_password_any(plaintext, stored_hash) is VerifyOutcome.MATCH_LEGACY:
+        return hash_password(plaintext)
+    return None
+
+
+def _parse_scrypt_profile(stored_hash: str) -> ScryptProfile:
+    """
+    Read the cost parameters back out of a ``scrypt$n=..$r=..$p=..$...`` string.
+
+    Only the labels and numbers are checked here, not the salt or key, because
+    the question being answered is "how expensive was this hash to make?".
+    Raises ``ValueError`` for anything that does not follow ``_encode_hash``,
+    and for costs no record we write could have: an ``n`` that is not a power
+    of two, or more memory than ``MAX_SCRYPT_MEMORY``.
+    """
+
+    parts = stored_hash.split("$")
+    if len(parts) != 6 or parts[0] != "scrypt":
+        raise ValueError("not a scrypt$n=..$r=..$p=..$salt=..$key=.. record")
+
+    values = {}
+    for label, part in zip(("n", "r", "p"), parts[1:4]):
+        name, _, number = part.partition("=")
+        # ``isdigit`` rejects signs and spaces, like the legacy parser does.
+        if name != label or not number.isdigit() or int(number) < 1:
+            raise ValueError(f"field {label!r} is missing or not a positive integer")
+        values[label] = int(number)
+    n_value, r_value, p_value = values["n"], values["r"], values["p"]
+    if n_value < 2 or n_value & (n_value - 1):
+        raise ValueError(f"field 'n' must be a power of two above 1, got {n_value}")
+    if 128 * r_value * (n_value + p_value) > MAX_SCRYPT_MEMORY:
+        raise ValueError(f"parameters need more than the {MAX_SCRYPT_MEMORY} bytes of memory allowed")
+    return ScryptProfile(n=n_value, r=r_value, p=p_value)
+
+
+def _decode_scrypt_field(part: str, label: str) -> bytes:
+    """
+    Decode one ``<label>=<base64>`` field of a scrypt record.
+
+    ``validate=True`` refuses characters outside the base64 alphabet instead of
+    silently skipping them, as in ``parse_legacy_pbkdf2``.
+    """
+
+    # ``partition`` splits at the first "=" only, so base64 padding ("==")
+    # stays attached to the value.
+    name, separator, value = part.partition("=")
+    if name != label or not separator:
+        raise ValueError(f"field {label!r} is missing")
+    try:
+        decoded = base64.b64decode(value, validate=True)
+    except binascii.Error as error:
+        raise ValueError(f"field {label!r} is not valid base64: {error}") from error
+    if not decoded:
+        raise ValueError(f"field {label!r} is empty")
+    return decoded
+
+
+def _parse_scrypt_record(stored_hash: str) -> Tuple[ScryptProfile, bytes, bytes]:
+    """
+    Split a scrypt record into its profile, salt and derived key.
+
+    Raises ``ValueError`` naming the broken field, like ``parse_legacy_pbkdf2``.
+    """
+
+    profile = _parse_scrypt_profile(stored_hash)
+    parts = stored_hash.split("$")
+    return profile, _decode_scrypt_field(parts[4], "salt"), _decode_scrypt_field(parts[5], "key")
+
+
+def needs_rehash(stored_hash: str, profile: Optional[ScryptProfile] = None) -> bool:
+    """
+    Report whether ``stored_hash`` is weaker than what we would write today.
+
+    Raising the cost profile only affects new hashes; every stored record keeps
+    the parameters it was made with. This function lets a login flow (or a
+    sweep over all accounts) spot the old ones. It returns ``True`` when:
+    - the record is a legacy PBKDF2 string (a different, older algorithm);
+    - any of ``n``, ``r`` or ``p`` is below ``profile`` (default: ``moderate``);
+    - the record cannot be parsed at all, including a salt or key that is not
+      base64. Such a record can never verify, so flagging it lets an operator
+      find it and reset that password.
+
+    Records at or above the current costs return ``False``; we never "upgrade"
+    a hash by making it cheaper.
+    """
+
+    current = profile if profile is not None else ScryptProfile.moderate()
+    try:
+        stored, _, _ = _parse_scrypt_record(stored_hash)
+    except ValueError:
+        return True
+    return stored.n < current.n or stored.r < current.r or stored.p < current.p
+
+
+@dataclass(frozen=True)
+class VerifyUpgrade:
+    """
+    The result of ``verify_and_upgrade``.
+
+    - ``matched``: whether the password was correct.
+    - ``new_hash``: a fresh hash to store in place of the old record, or
+      ``None`` when nothing needs rewriting (wrong password, or the record
+      already meets the current costs).
+    """
+
+    matched: bool
+    new_hash: Optional[str]
+
+
+def verify_and_upgrade(
+    plaintext: str, stored_hash: str, profile: Optional[ScryptProfile] = None
+) -> VerifyUpgrade:
+    """
+    Check a password and, when it is right, rehash it if the record is outdated.
+
+    This is the one call a login flow needs: it accepts current scrypt records
+    and legacy PBKDF2 ones, and after a successful check hands back a new hash
+    made with ``profile`` (default ``moderate``) whenever ``needs_rehash`` says
+    the old one is weaker. Users move to the new costs one login at a time,
+    without anyone having to know their password. A wrong password never
+    produces a new hash.
+    """
+
+    current = profile if profile is not None else ScryptProfile.moderate()
+    if verify_password_any(plaintext, stored_hash) is VerifyOutcome.NO_MATCH:
+        return VerifyUpgrade(matched=False, new_hash=None)
+    if needs_rehash(stored_hash, current):
+        return VerifyUpgrade(matched=True, new_hash=hash_password_with(plaintext, current))
+    return VerifyUpgrade(matched=True, new_hash=None)
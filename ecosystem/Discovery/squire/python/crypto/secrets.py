@@ -12,13 +12,18 @@ variables so no secret bytes live in the repository.
 """
 
 import base64
+import hashlib
 import hmac
 import json
 import os
 import struct
+import sys
+import time
 from dataclasses import dataclass
 from typing import Optional
 
+from . import integrity
+
 # Constants that mirror the RFC 8439 parameters. Changing these would break
 # interoperability and should not be done unless you fully understand the
 # cryptographic ramifications.
@@ -26,6 +31,23 @@ CHACHA20_KEY_BYTES = 32  # ChaCha20 keys are always 256 bits.
 CHACHA20_NONCE_BYTES = 12  # The "IETF" variant uses a 96-bit (12-byte) nonce.
 POLY1305_KEY_BYTES = 32  # Poly1305 one-time keys are 256 bits.
 
+# Fixed HKDF salt for ``SecretVault.derive_subvault``. It is not secret; it only
+# keeps subkeys apart from keys other programs might derive from the same master
+# key. Changing it changes every subkey, so old envelopes would stop opening.
+SUBVAULT_SALT = b"squire-subvault-v1"
+
+# The two envelope formats this module can open. ``chacha-poly`` is what
+# ``encrypt_secret`` writes. ``secretbox`` is libsodium's ``crypto_secretbox``
+# (XSalsa20-Poly1305), which older tooling used through PyNaCl; it can be read
+# so those secrets can be moved over, but nothing new is written in it.
+FORMAT_CHACHA_POLY = "chacha-poly"
+FORMAT_SECRETBOX = "secretbox"
+XSALSA20_NONCE_BYTES = 24  # secretbox nonces are 192 bits, long enough to pick at random.
+
+# The purpose of the subvault that seals audit records (see ``SecretVault.with_audit``).
+# ``config_loader.py audit-log read`` derives the same subvault to open them.
+AUDIT_PURPOSE = "vault-audit"
+
 
 @dataclass
 class EncryptedSecret:
@@ -38,11 +60,21 @@ class EncryptedSecret:
       plaintext with the ChaCha20 keystream.
     - ``tag``: The 16-byte Poly1305 authentication tag that detects any
       tampering with either the ciphertext or the associated data.
+    - ``context``: The purpose of the ``SecretVault`` subvault that sealed it
+      (for example ``"config-secrets"``), or ``None`` for envelopes made with
+      the master key directly. It is a label, not a secret: it lets the wrong
+      subvault say "this belongs to another purpose" instead of a bare
+      authentication failure.
+    - ``format``: ``FORMAT_CHACHA_POLY`` (the default) or ``FORMAT_SECRETBOX``
+      for an envelope carried over from libsodium. A secretbox ``nonce`` is 24
+      bytes and its key is the 32-byte key itself, with no salt.
     """
 
     nonce: bytes
     ciphertext: bytes
     tag: bytes
+    context: Optional[str] = None
+    format: str = FORMAT_CHACHA_POLY
 
     def to_storable(self) -> str:
         """
@@ -55,6 +87,14 @@ class EncryptedSecret:
             "ciphertext": base64.b64encode(self.ciphertext).decode("utf-8"),
             "tag": base64.b64encode(self.tag).decode("utf-8"),
         }
+        # Only subvault envelopes carry a context, so older readers (including
+        # the Rust reader in ``ecosystem/common/src/vault.rs``) see the same
+        # three fields as before.
+        if self.context is not None:
+            payload["context"] = self.context
+        # The same goes for ``format``: native envelopes leave it out.
+        if self.format != FORMAT_CHACHA_POLY:
+            payload["format"] = self.format
         return json.dumps(payload, indent=2)
 
     @staticmethod
@@ -68,8 +108,24 @@ class EncryptedSecret:
             nonce=base64.b64decode(data["nonce"]),
             ciphertext=base64.b64decode(data["ciphertext"]),
             tag=base64.b64decode(data["tag"]),
+            context=data.get("context"),
+            format=data.get("format", FORMAT_CHACHA_POLY),
         )
 
+    @staticmethod
+    def from_secretbox(nonce: bytes, box: bytes) -> "EncryptedSecret":
+        """
+        Wrap the output of libsodium's ``crypto_secretbox_easy`` (PyNaCl's
+        ``SecretBox.encrypt`` gives it as ``.nonce`` and ``.ciphertext``).
+
+        libsodium puts the 16-byte tag in front of the encrypted bytes; it is
+        split off here so the envelope has the usual three fields.
+        """
+
+        if len(box) < 16:
+            raise ValueError("secretbox output is shorter than its 16-byte tag")
+        return EncryptedSecret(nonce=nonce, ciphertext=box[16:], tag=box[:16], format=FORMAT_SECRETBOX)
+
 
 # -- ChaCha20 core -----------------------------------------------------------
 
@@ -284,6 +340,89 @@ def _poly1305_aead_tag(aad: bytes, ciphertext: bytes, otk: bytes) -> bytes:
     return accumulator.to_bytes(16, "little")
 
 
+# -- XSalsa20-Poly1305 (libsodium secretbox, opening only) --------------------
+#
+# Salsa20 is ChaCha20's older sibling: the same add-rotate-XOR idea, but the
+# 16 words are laid out differently and the rotations are 7, 9, 13, and 18.
+# XSalsa20 stretches the nonce to 24 bytes: HSalsa20 turns the key and the
+# first 16 nonce bytes into a fresh subkey, and Salsa20 with that subkey and
+# the last 8 nonce bytes makes the keystream. secretbox then uses the first 32
+# keystream bytes as the Poly1305 key and the rest to mask the message.
+
+_SALSA20_CONSTANTS = (0x61707865, 0x3320646E, 0x79622D32, 0x6B206574)  # "expand 32-byte k"
+
+
+def _salsa20_rounds(state: list[int]) -> list[int]:
+    """Run Salsa20's 20 rounds (10 column + 10 row rounds) on a copy of ``state``."""
+
+    x = state.copy()
+
+    def quarter(a: int, b: int, c: int, d: int) -> None:
+        x[b] ^= _rotate_left((x[a] + x[d]) & 0xFFFFFFFF, 7)
+        x[c] ^= _rotate_left((x[b] + x[a]) & 0xFFFFFFFF, 9)
+        x[d] ^= _rotate_left((x[c] + x[b]) & 0xFFFFFFFF, 13)
+        x[a] ^= _rotate_left((x[d] + x[c]) & 0xFFFFFFFF, 18)
+
+    for _ in range(10):
+        # Column rounds
+        quarter(0, 4, 8, 12)
+        quarter(5, 9, 13, 1)
+        quarter(10, 14, 2, 6)
+        quarter(15, 3, 7, 11)
+        # Row rounds
+        quarter(0, 1, 2, 3)
+        quarter(5, 6, 7, 4)
+        quarter(10, 11, 8, 9)
+        quarter(15, 12, 13, 14)
+    return x
+
+
+def _salsa20_state(key: bytes, middle: bytes) -> list[int]:
+    """Constants on the diagonal, the key around them, 16 bytes of ``middle`` in the centre."""
+
+    k = list(struct.unpack("<8I", key))
+    m = list(struct.unpack("<4I", middle))
+    c = _SALSA20_CONSTANTS
+    return [c[0], *k[:4], c[1], *m, c[2], *k[4:], c[3]]
+
+
+def _hsalsa20(key: bytes, nonce16: bytes) -> bytes:
+    """Derive the XSalsa20 subkey: the rounds without the final addition, eight words kept."""
+
+    x = _salsa20_rounds(_salsa20_state(key, nonce16))
+    return struct.pack("<8I", *(x[i] for i in (0, 5, 10, 15, 6, 7, 8, 9)))
+
+
+def _xsalsa20_stream(key: bytes, nonce: bytes, length: int) -> bytes:
+    """``length`` keystream bytes for a 32-byte key and a 24-byte nonce."""
+
+    subkey = _hsalsa20(key, nonce[:16])
+    stream = bytearray()
+    counter = 0
+    while len(stream) < length:
+        state = _salsa20_state(subkey, nonce[16:] + struct.pack("<Q", counter))
+        mixed = _salsa20_rounds(state)
+        stream.extend(struct.pack("<16I", *((mixed[i] + state[i]) & 0xFFFFFFFF for i in range(16))))
+        counter += 1
+    return bytes(stream[:length])
+
+
+def decrypt_secretbox(key: bytes, bundle: EncryptedSecret) -> Optional[bytes]:
+    """
+    Open a ``FORMAT_SECRETBOX`` envelope with the 32-byte key it was sealed
+    with. Returns ``None`` when the key, nonce, or tag is wrong, like
+    ``decrypt_secret``. The tag is checked before anything is decrypted.
+    """
+
+    if len(key) != CHACHA20_KEY_BYTES or len(bundle.nonce) != XSALSA20_NONCE_BYTES:
+        return None
+    stream = _xsalsa20_stream(key, bundle.nonce, POLY1305_KEY_BYTES + len(bundle.ciphertext))
+    expected_tag = _poly1305_mac(bundle.ciphertext, stream[:POLY1305_KEY_BYTES])
+    if not hmac.compare_digest(expected_tag, bundle.tag):
+        return None
+    return bytes(c ^ k for c, k in zip(bundle.ciphertext, stream[POLY1305_KEY_BYTES:]))
+
+
 # -- Public API --------------------------------------------------------------
 
 def derive_key(master: bytes, salt: bytes) -> bytes:
@@ -343,8 +482,13 @@ def decrypt_secret(master_key: bytes, bundle: EncryptedSecret, aad: bytes = b"")
     Decrypt and authenticate an ``EncryptedSecret``.
 
     Returns the plaintext bytes on success or ``None`` if authentication fails.
+    A ``FORMAT_SECRETBOX`` envelope is opened with ``decrypt_secretbox``, which
+    uses ``master_key`` as it is; secretbox has no associated data, so ``aad``
+    must be empty for it.
     """
 
+    if bundle.format == FORMAT_SECRETBOX:
+        return None if aad else decrypt_secretbox(master_key, bundle)
     if len(bundle.nonce) != 16 + CHACHA20_NONCE_BYTES:
         return None
 
@@ -364,3 +508,288 @@ def decrypt_secret(master_key: bytes, bundle: EncryptedSecret, aad: bytes = b"")
 
     plaintext = _chacha20_encrypt(aead_key, nonce, bundle.ciphertext, counter=1)
     return plaintext
+
+
+# -- Per-purpose subvaults ----------------------------------------------------
+
+class SecretVaultError(ValueError):
+    """Raised when a ``SecretVault`` cannot open or create an envelope."""
+
+
+class SecretFormatError(SecretVaultError):
+    """
+    Raised when an envelope's ``format`` does not fit its contents, or names a
+    format this module does not know. The message names both formats, so the
+    fix (adding or correcting ``"format"``) is obvious.
+    """
+
+
+def check_format(bundle: EncryptedSecret) -> None:
+    """
+    Make sure ``bundle`` looks like the format it claims to be.
+
+    The nonce length gives it away: a native envelope stores 16 bytes of salt
+    plus a 12-byte nonce (28 bytes), a secretbox one a 24-byte nonce. A
+    mismatch would otherwise end as a bare "failed authentication".
+    """
+
+    native_nonce = 16 + CHACHA20_NONCE_BYTES
+    if bundle.format == FORMAT_CHACHA_POLY:
+        if len(bundle.nonce) == XSALSA20_NONCE_BYTES:
+            raise SecretFormatError(
+                f"envelope is marked {FORMAT_CHACHA_POLY!r} but has a {XSALSA20_NONCE_BYTES}-byte nonce, "
+                f"which is what a libsodium {FORMAT_SECRETBOX!r} envelope has; "
+                f'add "format": "{FORMAT_SECRETBOX}" if it came from the old tooling'
+            )
+    elif bundle.format == FORMAT_SECRETBOX:
+        if len(bundle.nonce) == native_nonce:
+            raise SecretFormatError(
+                f"envelope is marked {FORMAT_SECRETBOX!r} but has a {native_nonce}-byte salt+nonce, "
+                f"which is what a native {FORMAT_CHACHA_POLY!r} envelope has; remove its \"format\" field"
+            )
+    else:
+        raise SecretFormatError(
+            f"unknown envelope format {bundle.format!r}; expected {FORMAT_CHACHA_POLY!r} or {FORMAT_SECRETBOX!r}"
+        )
+
+
+class PurposeMismatchError(SecretVaultError):
+    """
+    Raised when an envelope was sealed by a subvault for another purpose.
+
+    ``expected`` is the purpose of the vault asked to decrypt, ``found`` the
+    purpose recorded in the envelope (``None`` means the master key itself).
+    """
+
+    def __init__(self, expected: Optional[str], found: Optional[str]):
+        self.expected = expected
+        self.found = found
+        super().__init__(
+            f"envelope belongs to purpose {found!r}, but this vault is for {expected!r}"
+        )
+
+
+class SecretVault:
+    """
+    One key that seals and opens ``EncryptedSecret`` envelopes.
+
+    A deployment keeps a single master key, but should not use it for
+    everything: if the spool's key leaked, the config secrets should stay safe.
+    ``derive_subvault("config-secrets")`` therefore turns the master key into a
+    separate 32-byte key for one purpose:
+
+        subkey = HKDF-SHA256(IKM = master key, salt = SUBVAULT_SALT,
+                             info = purpose as UTF-8, length = 32)
+
+    The same master key and purpose always give the same subkey, so nothing
+    extra has to be stored. Envelopes sealed by a subvault record the purpose in
+    ``context``; opening one with a subvault for another purpose raises
+    ``PurposeMismatchError`` before any decryption is tried.
+    """
+
+    def __init__(self, master_key: bytes, purpose: Optional[str] = None):
+        if len(master_key) < 16:
+            raise SecretVaultError("Master key must be at least 128 bits to be meaningful")
+        self._key = master_key
+        self._purpose = purpose
+        # Set by ``with_audit``: ``None`` means operations are not recorded.
+        self._audit: Optional["_AuditLog"] = None
+
+    @property
+    def purpose(self) -> Optional[str]:
+        """The purpose this vault was derived for, or ``None`` for the master vault."""
+
+        return self._purpose
+
+    def derive_subvault(self, purpose: str) -> "SecretVault":
+        """
+        Return a vault whose key is derived from this one for ``purpose``.
+
+        Deriving from a subvault again works too; its purpose is then written
+        as ``"outer/inner"`` so the two levels stay distinguishable.
+        """
+
+        if not purpose:
+            raise SecretVaultError("purpose must not be empty")
+        subkey = integrity.hkdf_sha256(self._key, SUBVAULT_SALT, purpose.encode("utf-8"), CHACHA20_KEY_BYTES)
+        label = purpose if self._purpose is None else f"{self._purpose}/{purpose}"
+        derived = SecretVault(subkey, purpose=label)
+        # A subvault of an audited vault is audited too, into the same log.
+        derived._audit = self._audit
+        return derived
+
+    def with_audit(self, log_path, audit_vault: "SecretVault", fail_closed: bool = False) -> "SecretVault":
+        """
+        Return a copy of this vault that records every ``encrypt`` and ``decrypt``.
+
+        Each operation appends one line to ``log_path``: a record sealed by
+        ``audit_vault`` (normally ``master.derive_subvault(AUDIT_PURPOSE)``),
+        so the log itself reveals nothing but how many operations happened.
+        See ``_AuditLog`` for what a record holds.
+
+        If the line cannot be written, ``fail_closed=True`` makes the operation
+        raise ``AuditLogError`` instead of returning its result. The default,
+        fail-open, prints a warning to stderr and carries on, so a full disk
+        does not stop the bot from reading its own config.
+        """
+
+        audited = SecretVault(self._key, purpose=self._purpose)
+        audited._audit = _AuditLog(str(log_path), audit_vault, fail_closed)
+        return audited
+
+    def encrypt(self, plaintext: bytes, aad: bytes = b"") -> EncryptedSecret:
+        """Seal ``plaintext`` and label the envelope with this vault's purpose."""
+
+        bundle = self._seal(plaintext, aad)
+        if self._audit is not None:
+            self._audit.record("encrypt", self._purpose, bundle, ok=True)
+        return bundle
+
+    def _seal(self, plaintext: bytes, aad: bytes = b"") -> EncryptedSecret:
+        """``encrypt`` without the audit record; the audit log seals its own records with this."""
+
+        bundle = encrypt_secret(self._key, plaintext, aad)
+        bundle.context = self._purpose
+        return bundle
+
+    def decrypt(self, bundle: EncryptedSecret, aad: bytes = b"") -> bytes:
+        """
+        Open an envelope sealed by this vault; see ``_open`` for the checks.
+
+        With an audit log, a failed attempt is recorded too, with the class of
+        the error, and the error is raised as before.
+        """
+
+        if self._audit is None:
+            return self._open(bundle, aad)
+        try:
+            plaintext = self._open(bundle, aad)
+        except SecretVaultError as error:
+            self._audit.record("decrypt", self._purpose, bundle, ok=False, error=type(error).__name__)
+            raise
+        self._audit.record("decrypt", self._purpose, bundle, ok=True)
+        return plaintext
+
+    def _open(self, bundle: EncryptedSecret, aad: bytes = b"") -> bytes:
+        """
+        Open an envelope sealed by this vault.
+
+        The recorded purpose is compared first, so a mix-up between purposes is
+        reported as such, then the format (``SecretFormatError``). Anything
+        else that fails (a wrong master key, a changed byte) raises
+        ``SecretVaultError``. Secretbox envelopes are opened with this vault's
+        key as it is, so it must be the 32-byte key they were sealed with.
+        """
+
+        if bundle.context != self._purpose:
+            raise PurposeMismatchError(self._purpose, bundle.context)
+        check_format(bundle)
+        if bundle.format == FORMAT_SECRETBOX and len(self._key) != CHACHA20_KEY_BYTES:
+            raise SecretVaultError(
+                f"a {FORMAT_SECRETBOX!r} envelope needs the 32-byte key it was sealed with; this vault's key is {len(self._key)} bytes"
+            )
+        plaintext = decrypt_secret(self._key, bundle, aad)
+        if plaintext is None:
+            raise SecretVaultError("envelope failed authentication (wrong key or tampered data)")
+        return plaintext
+
+    def reencrypt(self, bundle: EncryptedSecret, aad: bytes = b"") -> EncryptedSecret:
+        """
+        Open ``bundle`` and seal the plaintext again as a fresh native envelope.
+
+        This is how secretbox envelopes are upgraded: once every secret has
+        been through ``reencrypt``, the old format is no longer needed. A
+        native envelope comes back with a new salt and nonce.
+        """
+
+        return self.encrypt(self.decrypt(bundle, aad), aad)
+
+
+# -- Audit log -----------------------------------------------------------------
+
+class AuditLogError(SecretVaultError):
+    """Raised by a fail-closed audited vault when its audit record cannot be written."""
+
+
+def envelope_fingerprint(bundle: EncryptedSecret) -> str:
+    """
+    SHA-256 (hex) of an envelope's nonce, ciphertext, and tag.
+
+    The same envelope always gives the same fingerprint, so an audit record can
+    be matched to the file it came from without the record holding anything
+    that helps decrypt it.
+    """
+
+    return hashlib.sha256(bundle.nonce + bundle.ciphertext + bundle.tag).hexdigest()
+
+
+class _AuditLog:
+    """
+    Where an audited ``SecretVault`` writes its records.
+
+    One record is a small JSON object:
+
+        {"op": "decrypt", "purpose": "config-secrets", "fingerprint": "<sha256 hex>",
+         "at_unix_ms": 1760000000000, "ok": false, "error": "PurposeMismatchError"}
+
+    It never holds plaintext or key material: only what was done, by which
+    purpose, to which envelope (by fingerprint), when, and whether it worked.
+    Even so the purposes and timing say something about how secrets are used,
+    so the record is sealed as an ``EncryptedSecret`` and written as one line
+    of compact JSON. Lines are only ever appended, in a single write each.
+    """
+
+    def __init__(self, path: str, vault: SecretVault, fail_closed: bool):
+        self.path = path
+        self.vault = vault
+        self.fail_closed = fail_closed
+
+    def record(self, op: str, purpose: Optional[str], bundle: EncryptedSecret, ok: bool, error: Optional[str] = None) -> None:
+        entry = {
+            "op": op,
+            "purpose": purpose,
+            "fingerprint": envelope_fingerprint(bundle),
+            "at_unix_ms": time.time_ns() // 1_000_000,
+            "ok": ok,
+        }
+        if error is not None:
+            entry["error"] = error
+        # ``_seal`` skips auditing, so an audited audit vault cannot loop forever.
+        sealed = self.vault._seal(json.dumps(entry).encode("utf-8"))
+        line = json.dumps(json.loads(sealed.to_storable()), separators=(",", ":")) + "\n"
+        try:
+            # O_APPEND puts every write at the current end, even with several writers.
+            descriptor = os.open(self.path, os.O_WRONLY | os.O_APPEND | os.O_CREAT, 0o600)
+            try:
+                os.write(descriptor, line.encode("utf-8"))
+            finally:
+                os.close(descriptor)
+        except OSError as failure:
+            if self.fail_closed:
+                raise AuditLogError(f"could not write vault audit record to {self.path}: {failure}") from failure
+            print(f"[vault-audit] Record not written to {self.path}: {failure}", file=sys.stderr)
+
+
+def read_audit_log(path, audit_vault: SecretVault) -> list:
+    """
+    Open every record in an audit log, oldest first (the order they were written).
+
+    A line that is not an envelope, or does not open with ``audit_vault``,
+    raises ``SecretVaultError`` naming its line number, since a log that was
+    edited is exactly what an auditor wants to hear about.
+    """
+
+    records = []
+    with open(path, "r", encoding="utf-8") as handle:
+        for number, line in enumerate(handle, start=1):
+            if not line.strip():
+                continue
+            try:
+                bundle = EncryptedSecret.from_storable(line)
+            except (ValueError, KeyError, TypeError) as error:
+                raise SecretVaultError(f"audit log line {number} is not an envelope: {error}") from error
+            try:
+                records.append(json.loads(audit_vault._open(bundle)))
+            except SecretVaultError as error:
+                raise SecretVaultError(f"audit log line {number} does not open: {error}") from error
+    return records
@@ -0,0 +1,102 @@
+"""Lightweight tests for the integrity helpers.
+
+Run from ``ecosystem/Discovery`` with
+`python -m unittest squire.python.crypto.test_integrity`.
+"""
+
+import os
+import tempfile
+import unittest
+
+from squire.python.crypto import integrity
+
+
+class HmacVerificationTests(unittest.TestCase):
+    KEY = b"classroom-signing-key"
+    DATA = b"release manifest contents"
+
+    def test_verify_accepts_good_tag_and_rejects_bad_one(self):
+        """A fresh tag verifies; flipping one bit does not."""
+
+        tag = bytes.fromhex(integrity.sign_then_hex(self.KEY, self.DATA))
+        self.assertTrue(integrity.hmac_sha256_verify(self.KEY, self.DATA, tag))
+        forged = tag[:-1] + bytes([tag[-1] ^ 0x01])
+        self.assertFalse(integrity.hmac_sha256_verify(self.KEY, self.DATA, forged))
+        self.assertFalse(integrity.hmac_sha256_verify(b"other-key", self.DATA, tag))
+
+    def test_hex_verification_ignores_case_and_newlines(self):
+        """Uppercase tags and trailing newlines from text files still verify."""
+
+        hex_tag = integrity.sign_then_hex(self.KEY, self.DATA)
+        self.assertTrue(integrity.verify_hex(self.KEY, self.DATA, hex_tag.upper() + "\n"))
+
+    def test_wrong_length_and_non_hex_tags(self):
+        """Short tags fail quietly; non-hex text raises IntegrityError."""
+
+        self.assertFalse(integrity.hmac_sha256_verify(self.KEY, self.DATA, b"\x00" * 8))
+        self.assertFalse(integrity.verify_hex(self.KEY, self.DATA, "abcd"))
+        with self.assertRaises(integrity.IntegrityError):
+            integrity.verify_hex(self.KEY, self.DATA, "not-hex")
+
+
+class FileDigestTests(unittest.TestCase):
+    def test_file_digest_matches_in_memory_digest(self):
+        """Streaming a file yields the same digest as hashing its bytes."""
+
+        payload = os.urandom(integrity.FILE_CHUNK_BYTES * 3 + 17)
+        with tempfile.TemporaryDirectory() as folder:
+            path = os.path.join(folder, "blob.bin")
+            with open(path, "wb") as handle:
+                handle.write(payload)
+            self.assertEqual(integrity.sha256_file(path), integrity.sha256_digest(payload))
+
+
+class DigestAlgorithmTests(unittest.TestCase):
+    # Published "abc" vectors: FIPS 180-4 for the SHA-2 family and the
+    # reference BLAKE2 implementation for 32-byte BLAKE2b output.
+    ABC_VECTORS = {
+        integrity.DigestAlgorithm.SHA256:
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        integrity.DigestAlgorithm.SHA512:
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a"
+            "2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+        integrity.DigestAlgorithm.BLAKE2B256:
+            "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319",
+    }
+
+    def test_known_vectors(self):
+        """Each algorithm reproduces its published digest of "abc"."""
+
+        for algorithm, expected in self.ABC_VECTORS.items():
+            with self.subTest(algorithm=algorithm):
+                self.assertEqual(integrity.digest_hex(algorithm, b"abc"), expected)
+        self.assertEqual(integrity.sha512_hex(b"abc"), self.ABC_VECTORS[integrity.DigestAlgorithm.SHA512])
+        self.assertEqual(integrity.blake2b256_hex(b"abc"), self.ABC_VECTORS[integrity.DigestAlgorithm.BLAKE2B256])
+
+    def test_file_and_memory_digests_agree(self):
+        """Streaming every algorithm over a file matches hashing the bytes."""
+
+        payload = os.urandom(integrity.FILE_CHUNK_BYTES + 5)
+        with tempfile.TemporaryDirectory() as folder:
+            path = os.path.join(folder, "blob.bin")
+            with open(path, "wb") as handle:
+                handle.write(payload)
+            for algorithm in integrity.DigestAlgorithm:
+                with self.subTest(algorithm=algorithm):
+                    self.assertEqual(
+                        integrity.digest_file(algorithm, path),
+                        integrity.digest_hex(algorithm, payload),
+                    )
+
+    def test_unreadable_file_and_unknown_name(self):
+        """Missing files raise OSError; unknown labels raise ValueError."""
+
+        with tempfile.TemporaryDirectory() as folder:
+            with self.assertRaises(OSError):
+                integrity.digest_file(integrity.DigestAlgorithm.SHA512, os.path.join(folder, "absent"))
+        with self.assertRaises(ValueError):
+            integrity.DigestAlgorithm.from_name("md5")
+
+
+if __name__ == "__main__":
+    unittest.main()
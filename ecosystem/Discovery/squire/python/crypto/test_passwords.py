@@ -0,0 +1,233 @@
+"""Lightweight tests for password hashing and legacy PBKDF2 migration.
+
+Run them from ``ecosystem/Discovery`` with
+`python -m unittest squire.python.crypto.test_passwords`. The PBKDF2 vector
+comes from RFC 7914 section 11 so it can be checked against any other
+implementation.
+"""
+
+import base64
+import os
+import unittest
+
+from squire.python.crypto import passwords
+
+# RFC 7914 section 11: PBKDF2-HMAC-SHA256 with P="passwd", S="salt", c=1, dkLen=64.
+RFC7914_KEY = bytes.fromhex(
+    "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc"
+    "49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+)
+RFC7914_RECORD = "pbkdf2_sha256$1$salt$" + base64.b64encode(RFC7914_KEY).decode("utf-8")
+
+
+class LegacyPbkdf2Tests(unittest.TestCase):
+    def test_known_vector_matches_as_legacy(self):
+        """The RFC vector verifies and is flagged for migration."""
+
+        outcome = passwords.verify_password_any("passwd", RFC7914_RECORD)
+        self.assertIs(outcome, passwords.VerifyOutcome.MATCH_LEGACY)
+        self.assertIs(
+            passwords.verify_password_any("wrong", RFC7914_RECORD),
+            passwords.VerifyOutcome.NO_MATCH,
+        )
+
+    def test_rehash_produces_current_format(self):
+        """Migration swaps the legacy record for a verifiable scrypt hash."""
+
+        new_hash = passwords.rehash_if_legacy("passwd", RFC7914_RECORD)
+        self.assertIsNotNone(new_hash)
+        self.assertTrue(new_hash.startswith("scrypt$"))
+        self.assertIs(
+            passwords.verify_password_any("passwd", new_hash),
+            passwords.VerifyOutcome.MATCH_CURRENT,
+        )
+        # Current-format hashes and wrong passwords never trigger a rewrite.
+        self.assertIsNone(passwords.rehash_if_legacy("passwd", new_hash))
+        self.assertIsNone(passwords.rehash_if_legacy("wrong", RFC7914_RECORD))
+
+    def test_tampered_legacy_records_are_rejected(self):
+        """Edited iterations, salts, or keys all fail closed."""
+
+        tampered = [
+            RFC7914_RECORD.replace("$1$", "$2$"),
+            RFC7914_RECORD.replace("$salt$", "$pepper$"),
+            RFC7914_RECORD[:-6] + "AAAA==",
+            "pbkdf2_sha256$-1$salt$" + RFC7914_RECORD.rsplit("$", 1)[1],
+            "pbkdf2_sha256$1$salt",
+        ]
+        for record in tampered:
+            with self.subTest(record=record):
+                self.assertIs(
+                    passwords.verify_password_any("passwd", record),
+                    passwords.VerifyOutcome.NO_MATCH,
+                )
+
+    def test_tampered_current_records_are_rejected(self):
+        """A damaged scrypt record fails closed just like a legacy one."""
+
+        stored = passwords.hash_password_with("passwd", passwords.ScryptProfile(n=2 ** 10, r=8, p=1))
+        key = stored.rsplit("$key=", 1)[1]
+        tampered = [
+            stored.replace(f"$n={2 ** 10}$", f"$n={2 ** 11}$"),
+            stored.replace("$salt=", "$salt=AAAA", 1),
+            stored[: -len(key)] + "A" * len(key),
+            stored.replace("$salt=", "$salt=!!", 1),
+            stored.replace("$key=", "$key=not*base64", 1),
+            stored.replace(f"$n={2 ** 10}$", "$n=1000$"),
+            stored.replace(f"$n={2 ** 10}$", f"$n={2 ** 62}$"),
+            stored.rsplit("$", 1)[0],
+        ]
+        for record in tampered:
+            with self.subTest(record=record):
+                self.assertIs(
+                    passwords.verify_password_any("passwd", record),
+                    passwords.VerifyOutcome.NO_MATCH,
+                )
+                self.assertIsNone(passwords.rehash_if_legacy("passwd", record))
+
+    def test_parser_errors_name_the_broken_field(self):
+        """Malformed records raise ValueError with a readable reason."""
+
+        with self.assertRaisesRegex(ValueError, "iteration count"):
+            passwords.parse_legacy_pbkdf2("pbkdf2_sha256$many$salt$AAAA")
+        with self.assertRaisesRegex(ValueError, "base64"):
+            passwords.parse_legacy_pbkdf2("pbkdf2_sha256$1$salt$not*base64")
+
+
+class ScryptProfileTests(unittest.TestCase):
+    def test_non_default_profile_round_trip(self):
+        """Hashes record their own parameters, so verify needs no profile."""
+
+        profile = passwords.ScryptProfile.interactive()
+        stored = passwords.hash_password_with("hunter2", profile)
+        self.assertIn(f"$n={profile.n}$r={profile.r}$p={profile.p}$", stored)
+        self.assertTrue(passwords.verify_password("hunter2", stored))
+        self.assertFalse(passwords.verify_password("hunter3", stored))
+
+    def test_default_hash_uses_moderate_profile(self):
+        """``hash_password`` keeps writing the long-standing default parameters."""
+
+        moderate = passwords.ScryptProfile.moderate()
+        self.assertIn(f"$n={moderate.n}$", passwords.hash_password("hunter2"))
+
+    @unittest.skipUnless(
+        os.environ.get("SQUIRE_RUN_CALIBRATION"),
+        "set SQUIRE_RUN_CALIBRATION=1 to time scrypt on this machine",
+    )
+    def test_calibrate_stays_within_ceiling(self):
+        """Calibration returns a usable profile no larger than the caps."""
+
+        profile = passwords.ScryptProfile.calibrate(250)
+        self.assertLessEqual(profile.n, passwords.CALIBRATION_MAX_N)
+        self.assertLessEqual(profile.r, passwords.CALIBRATION_MAX_R)
+        self.assertTrue(
+            passwords.verify_password("probe", passwords.hash_password_with("probe", profile))
+        )
+
+
+class RehashTests(unittest.TestCase):
+    def test_lower_costs_are_flagged(self):
+        """A record below the current profile in any parameter needs a rehash."""
+
+        moderate = passwords.ScryptProfile.moderate()
+        weak = passwords.hash_password_with("hunter2", passwords.ScryptProfile.interactive())
+        self.assertTrue(passwords.needs_rehash(weak))
+        lower_r = weak.replace(f"$n={2 ** 14}$r=8$", f"$n={moderate.n}$r=4$")
+        self.assertTrue(passwords.needs_rehash(lower_r))
+        self.assertTrue(passwords.needs_rehash(RFC7914_RECORD))
+
+    def test_current_costs_are_not_flagged(self):
+        """Records at or above the profile stay as they are."""
+
+        interactive = passwords.ScryptProfile.interactive()
+        stored = passwords.hash_password_with("hunter2", interactive)
+        self.assertFalse(passwords.needs_rehash(stored, interactive))
+        self.assertFalse(passwords.needs_rehash(passwords.hash_password("hunter2")))
+        # A stronger record is never "upgraded" to a cheaper one.
+        stronger = stored.replace(f"$n={interactive.n}$", f"$n={interactive.n * 4}$")
+        self.assertFalse(passwords.needs_rehash(stronger, interactive))
+
+    def test_upgrade_produces_hash_under_current_costs(self):
+        """A correct login on a weak record returns a verifiable current hash."""
+
+        weak = passwords.hash_password_with("hunter2", passwords.ScryptProfile(n=2 ** 10, r=8, p=1))
+        interactive = passwords.ScryptProfile.interactive()
+        result = passwords.verify_and_upgrade("hunter2", weak, interactive)
+        self.assertTrue(result.matched)
+        self.assertIsNotNone(result.new_hash)
+        self.assertIn(f"$n={interactive.n}$r={interactive.r}$p={interactive.p}$", result.new_hash)
+        self.assertTrue(passwords.verify_password("hunter2", result.new_hash))
+        self.assertFalse(passwords.needs_rehash(result.new_hash, interactive))
+        # Nothing to do once upgraded, and nothing for a wrong password.
+        self.assertEqual(
+            passwords.verify_and_upgrade("hunter2", result.new_hash, interactive),
+            passwords.VerifyUpgrade(matched=True, new_hash=None),
+        )
+        self.assertEqual(
+            passwords.verify_and_upgrade("wrong", weak, interactive),
+            passwords.VerifyUpgrade(matched=False, new_hash=None),
+        )
+
+    def test_legacy_record_upgrades_to_scrypt(self):
+        """PBKDF2 records are always outdated and move to scrypt on login."""
+
+        interactive = passwords.ScryptProfile.interactive()
+        result = passwords.verify_and_upgrade("passwd", RFC7914_RECORD, interactive)
+        self.assertTrue(result.matched)
+        self.assertTrue(result.new_hash.startswith("scrypt$"))
+
+    def test_malformed_records_are_flagged_not_raised(self):
+        """Broken strings report needs_rehash=True and never match."""
+
+        for record in ["", "scrypt$n=abc$r=8$p=1$salt=AA$key=AA", "scrypt$n=16384", "bcrypt$2b$12$abc"]:
+            with self.subTest(record=record):
+                self.assertTrue(passwords.needs_rehash(record))
+                self.assertEqual(
+                    passwords.verify_and_upgrade("hunter2", record),
+                    passwords.VerifyUpgrade(matched=False, new_hash=None),
+                )
+
+    def test_undecodable_or_unrunnable_records_are_flagged_not_raised(self):
+        """Bad base64 and costs scrypt refuses never raise and always need a rehash."""
+
+        stored = passwords.hash_password_with("hunter2", passwords.ScryptProfile(n=2 ** 10, r=8, p=1))
+        broken = {
+            "salt not base64": stored.replace("$salt=", "$salt=*", 1),
+            "key not base64": stored.replace("$key=", "$key=%%", 1),
+            "empty key": stored.rsplit("$key=", 1)[0] + "$key=",
+            "n not a power of two": stored.replace(f"$n={2 ** 10}$", f"$n={2 ** 15 + 2}$"),
+            "n too costly": stored.replace(f"$n={2 ** 10}$", f"$n={2 ** 40}$"),
+            "r too costly": stored.replace("$r=8$", f"$r={2 ** 31}$"),
+        }
+        for reason, record in broken.items():
+            with self.subTest(reason=reason):
+                self.assertFalse(passwords.verify_password("hunter2", record))
+                self.assertIs(
+                    passwords.verify_password_any("hunter2", record),
+                    passwords.VerifyOutcome.NO_MATCH,
+                )
+                self.assertTrue(passwords.needs_rehash(record))
+                self.assertEqual(
+                    passwords.verify_and_upgrade("hunter2", record),
+                    passwords.VerifyUpgrade(matched=False, new_hash=None),
+                )
+
+    def test_scrypt_refusing_the_parameters_is_no_match(self):
+        """An error from hashlib itself is a failed check, not a crash."""
+
+        stored = passwords.hash_password_with("hunter2", passwords.ScryptProfile(n=2 ** 10, r=8, p=1))
+        real_derive = passwords._derive_scrypt
+
+        def refuse(*args, **kwargs):
+            raise ValueError("[digital envelope routines] memory limit exceeded")
+
+        passwords._derive_scrypt = refuse
+        try:
+            self.assertFalse(passwords.verify_password("hunter2", stored))
+        finally:
+            passwords._derive_scrypt = real_derive
+        self.assertTrue(passwords.verify_password("hunter2", stored))
+
+
+if __name__ == "__main__":
+    unittest.main()
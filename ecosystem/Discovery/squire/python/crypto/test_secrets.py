@@ -6,9 +6,14 @@ packages. The vectors come from RFC 8439 so they can be compared with
 other implementations.
 """
 
+import base64
+import contextlib
+import io
+import os
+import tempfile
 import unittest
 
-from squire.python.crypto import secrets
+from squire.python.crypto import integrity, secrets
 
 
 class ChaCha20Poly1305Tests(unittest.TestCase):
@@ -95,5 +100,180 @@ class ChaCha20Poly1305Tests(unittest.TestCase):
         self.assertIsNone(secrets.decrypt_secret(master_key, forged))
 
 
+class SubvaultTests(unittest.TestCase):
+    MASTER = b"deployment-master-key-32-bytes!!"
+
+    def test_same_purpose_always_gives_the_same_subkey(self):
+        """Two derivations for one purpose open each other's envelopes."""
+
+        first = secrets.SecretVault(self.MASTER).derive_subvault("config-secrets")
+        second = secrets.SecretVault(self.MASTER).derive_subvault("config-secrets")
+        self.assertEqual(first._key, second._key)
+        self.assertEqual(second.decrypt(first.encrypt(b"db password")), b"db password")
+
+    def test_other_purpose_is_refused_with_a_purpose_error(self):
+        """The wrong subvault names both purposes instead of failing the tag check."""
+
+        vault = secrets.SecretVault(self.MASTER)
+        bundle = vault.derive_subvault("spool-encryption").encrypt(b"queued message")
+        with self.assertRaises(secrets.PurposeMismatchError) as caught:
+            vault.derive_subvault("webhook-signing").decrypt(bundle)
+        self.assertEqual(caught.exception.expected, "webhook-signing")
+        self.assertEqual(caught.exception.found, "spool-encryption")
+        with self.assertRaises(secrets.PurposeMismatchError):
+            vault.decrypt(bundle)
+
+    def test_subkey_matches_a_manual_hkdf_derivation(self):
+        """The subkey is plain HKDF-SHA256, so other tools can derive it too."""
+
+        manual_key = integrity.hkdf_sha256(self.MASTER, secrets.SUBVAULT_SALT, b"config-secrets", 32)
+        bundle = secrets.SecretVault(self.MASTER).derive_subvault("config-secrets").encrypt(b"token")
+        self.assertEqual(secrets.decrypt_secret(manual_key, bundle), b"token")
+        manual = secrets.SecretVault(manual_key, purpose="config-secrets")
+        self.assertEqual(manual.decrypt(bundle), b"token")
+
+    def test_context_survives_storage_and_tampering_is_still_caught(self):
+        """``context`` round-trips through JSON; a changed byte still fails."""
+
+        vault = secrets.SecretVault(self.MASTER).derive_subvault("config-secrets")
+        stored = secrets.EncryptedSecret.from_storable(vault.encrypt(b"secret").to_storable())
+        self.assertEqual(stored.context, "config-secrets")
+        stored.tag = bytes([stored.tag[0] ^ 0x01]) + stored.tag[1:]
+        with self.assertRaises(secrets.SecretVaultError):
+            vault.decrypt(stored)
+
+
+class SecretboxCompatTests(unittest.TestCase):
+    # Made once with libsodium's crypto_secretbox_easy (what PyNaCl's
+    # SecretBox.encrypt calls) from this test-only key, the nonce 00 01 ... 17,
+    # and the message b"legacy webhook signing token". The 16-byte tag comes first.
+    KEY = b"squire-secretbox-fixture-key-32b"
+    NONCE = bytes(range(24))
+    BOX = bytes.fromhex(
+        "bc68a678df67cfefdecadc955e65587c"
+        "1e11bb1e91cbbd8408b8d928eb5eb3f042e16fe863cc63e0a9aa7b96"
+    )
+
+    def _fixture(self):
+        return secrets.EncryptedSecret.from_secretbox(self.NONCE, self.BOX)
+
+    def test_libsodium_fixture_opens_and_survives_storage(self):
+        """The Python XSalsa20-Poly1305 reader agrees with libsodium byte for byte."""
+
+        stored = secrets.EncryptedSecret.from_storable(self._fixture().to_storable())
+        self.assertEqual(stored.format, secrets.FORMAT_SECRETBOX)
+        self.assertEqual(secrets.SecretVault(self.KEY).decrypt(stored), b"legacy webhook signing token")
+        stored.ciphertext = bytes([stored.ciphertext[0] ^ 0x01]) + stored.ciphertext[1:]
+        self.assertIsNone(secrets.decrypt_secret(self.KEY, stored))
+
+    def test_reencrypt_upgrades_to_the_native_format(self):
+        """``reencrypt`` turns a secretbox envelope into a native one with the same plaintext."""
+
+        vault = secrets.SecretVault(self.KEY)
+        upgraded = vault.reencrypt(self._fixture())
+        self.assertEqual(upgraded.format, secrets.FORMAT_CHACHA_POLY)
+        self.assertNotIn("format", upgraded.to_storable())
+        self.assertEqual(len(upgraded.nonce), 28)
+        self.assertEqual(vault.decrypt(upgraded), b"legacy webhook signing token")
+
+    def test_wrong_format_label_names_both_formats(self):
+        """A secretbox envelope marked native (and the reverse) gets a format error, not a tag failure."""
+
+        vault = secrets.SecretVault(self.KEY)
+        unmarked = self._fixture()
+        unmarked.format = secrets.FORMAT_CHACHA_POLY
+        with self.assertRaises(secrets.SecretFormatError) as caught:
+            vault.decrypt(unmarked)
+        self.assertIn("'chacha-poly'", str(caught.exception))
+        self.assertIn("'secretbox'", str(caught.exception))
+
+        native = vault.encrypt(b"new secret")
+        native.format = secrets.FORMAT_SECRETBOX
+        with self.assertRaises(secrets.SecretFormatError) as caught:
+            vault.decrypt(native)
+        self.assertIn("'chacha-poly'", str(caught.exception))
+
+
+class AuditLogTests(unittest.TestCase):
+    MASTER = b"deployment-master-key-32-bytes!!"
+
+    def setUp(self):
+        self.folder = tempfile.TemporaryDirectory()
+        self.addCleanup(self.folder.cleanup)
+        self.log = os.path.join(self.folder.name, "audit.log")
+        master = secrets.SecretVault(self.MASTER)
+        self.audit_vault = master.derive_subvault(secrets.AUDIT_PURPOSE)
+        self.vault = master.with_audit(self.log, self.audit_vault).derive_subvault("config-secrets")
+
+    def test_each_operation_adds_one_record_in_order(self):
+        """Encrypt, decrypt, and a refused decrypt give three records, read back oldest first."""
+
+        bundle = self.vault.encrypt(b"db password")
+        self.assertEqual(self.vault.decrypt(bundle), b"db password")
+        foreign = secrets.SecretVault(self.MASTER).derive_subvault("spool-encryption").encrypt(b"queued")
+        with self.assertRaises(secrets.PurposeMismatchError):
+            self.vault.decrypt(foreign)
+
+        with open(self.log, encoding="utf-8") as handle:
+            self.assertEqual(len(handle.read().splitlines()), 3)
+        records = secrets.read_audit_log(self.log, self.audit_vault)
+        self.assertEqual([(r["op"], r["ok"]) for r in records], [("encrypt", True), ("decrypt", True), ("decrypt", False)])
+        self.assertEqual({r["purpose"] for r in records}, {"config-secrets"})
+        self.assertEqual(records[0]["fingerprint"], secrets.envelope_fingerprint(bundle))
+        self.assertEqual(records[1]["fingerprint"], records[0]["fingerprint"])
+        self.assertEqual(records[2]["fingerprint"], secrets.envelope_fingerprint(foreign))
+        self.assertEqual(records[2]["error"], "PurposeMismatchError")
+        self.assertNotIn("error", records[0])
+        stamps = [r["at_unix_ms"] for r in records]
+        self.assertEqual(stamps, sorted(stamps))
+
+    def test_log_bytes_hold_no_plaintext_or_key_material(self):
+        """Scanning the raw file finds neither the secret, the labels, nor any key in any encoding."""
+
+        plaintext = b"super-secret-webhook-token"
+        self.vault.decrypt(self.vault.encrypt(plaintext))
+        with open(self.log, "rb") as handle:
+            raw = handle.read()
+        keys = [self.MASTER, self.vault._key, self.audit_vault._key]
+        for needle in [plaintext, b"config-secrets", b"decrypt", *keys]:
+            self.assertNotIn(needle, raw)
+            self.assertNotIn(base64.b64encode(needle), raw)
+            self.assertNotIn(needle.hex().encode(), raw)
+
+    def test_fail_closed_raises_and_fail_open_warns(self):
+        """An unwritable log stops the operation only when fail_closed is set."""
+
+        missing = os.path.join(self.folder.name, "no-such-folder", "audit.log")
+        master = secrets.SecretVault(self.MASTER)
+        closed = master.with_audit(missing, self.audit_vault, fail_closed=True)
+        with self.assertRaises(secrets.AuditLogError):
+            closed.encrypt(b"secret")
+        bundle = master.encrypt(b"secret")
+        with self.assertRaises(secrets.AuditLogError):
+            closed.decrypt(bundle)
+
+        opened = master.with_audit(missing, self.audit_vault)
+        warnings = io.StringIO()
+        with contextlib.redirect_stderr(warnings):
+            self.assertEqual(opened.decrypt(bundle), b"secret")
+        self.assertIn("[vault-audit] Record not written", warnings.getvalue())
+
+    def test_edited_line_names_its_number(self):
+        """A changed record fails to open, and the error says which line."""
+
+        self.vault.encrypt(b"one")
+        self.vault.encrypt(b"two")
+        with open(self.log, encoding="utf-8") as handle:
+            lines = handle.read().splitlines()
+        edited = secrets.EncryptedSecret.from_storable(lines[1])
+        edited.ciphertext = bytes([edited.ciphertext[0] ^ 0x01]) + edited.ciphertext[1:]
+        lines[1] = edited.to_storable().replace("\n", "")
+        with open(self.log, "w", encoding="utf-8") as handle:
+            handle.write("\n".join(lines) + "\n")
+        with self.assertRaises(secrets.SecretVaultError) as caught:
+            secrets.read_audit_log(self.log, self.audit_vault)
+        self.assertIn("line 2 does not open", str(caught.exception))
+
+
 if __name__ == "__main__":
     unittest.main()
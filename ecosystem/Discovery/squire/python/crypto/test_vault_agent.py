@@ -0,0 +1,148 @@
+"""Tests for the vault agent and its socket.
+
+Run them from ``ecosystem/Discovery`` with
+`python -m unittest squire.python.crypto.test_vault_agent`. The agent runs in a
+thread of the test process and its idle clock is a plain number the tests move
+by hand, so nothing here waits for real time to pass.
+"""
+
+import os
+import socket
+import stat
+import struct
+import tempfile
+import threading
+import unittest
+
+from squire.python.crypto import secrets as secret_vault
+from squire.python.crypto import vault_agent
+
+MASTER_KEY = bytes(range(32))
+
+
+class FakeClock:
+    def __init__(self):
+        self.now = 0.0
+
+    def __call__(self):
+        return self.now
+
+
+class RunningAgentTests(unittest.TestCase):
+    def setUp(self):
+        folder = tempfile.TemporaryDirectory()
+        self.addCleanup(folder.cleanup)
+        self.path = os.path.join(folder.name, "agent.sock")
+        self.clock = FakeClock()
+        self.agent = vault_agent.VaultAgent(MASTER_KEY, self.path, idle_timeout=60, clock=self.clock, poll_interval=0.01)
+        self.agent.bind()
+        self.thread = threading.Thread(target=self.agent.serve, daemon=True)
+        self.thread.start()
+        self.addCleanup(self.stop)
+        self.client = vault_agent.AgentClient(self.path)
+
+    def stop(self):
+        self.clock.now += 3600
+        self.thread.join(5)
+
+    def test_round_trip_matches_a_local_vault(self):
+        """What the agent seals a local vault opens, and the other way round."""
+
+        local = secret_vault.SecretVault(MASTER_KEY)
+        self.assertEqual(local.decrypt(self.client.encrypt(b"bot-token-123")), b"bot-token-123")
+        self.assertEqual(self.client.decrypt(local.encrypt(b"webhook key")), b"webhook key")
+        self.assertEqual(self.client.fingerprint(), vault_agent.key_fingerprint(MASTER_KEY))
+
+    def test_socket_is_owner_only(self):
+        mode = os.stat(self.path).st_mode
+        self.assertTrue(stat.S_ISSOCK(mode))
+        self.assertEqual(stat.S_IMODE(mode), 0o600)
+
+    def test_refusals_come_back_as_errors(self):
+        """A wrong key, a changed byte, or an unknown op is refused, and the agent keeps serving."""
+
+        other = secret_vault.SecretVault(bytes(32)).encrypt(b"not ours")
+        with self.assertRaises(vault_agent.AgentError):
+            self.client.decrypt(other)
+        with self.assertRaises(vault_agent.AgentError):
+            self.client.request({"op": "export-key"})
+        with self.assertRaises(vault_agent.AgentError):
+            self.client.request({"op": "encrypt", "plaintext": "not base64!"})
+        # A frame that claims to be bigger than allowed just gets the connection closed.
+        with socket.socket(socket.AF_UNIX, socket.SOCK_STREAM) as raw:
+            raw.connect(self.path)
+            raw.sendall(struct.pack(">I", vault_agent.MAX_FRAME_BYTES + 1))
+            self.assertEqual(raw.recv(1), b"")
+        self.assertEqual(self.client.decrypt(self.client.encrypt(b"still here")), b"still here")
+
+    def test_second_agent_on_the_same_path_is_refused(self):
+        with self.assertRaises(vault_agent.AgentError):
+            vault_agent.VaultAgent(MASTER_KEY, self.path).bind()
+        self.assertEqual(self.client.fingerprint(), vault_agent.key_fingerprint(MASTER_KEY))
+
+
+class LifecycleTests(unittest.TestCase):
+    def setUp(self):
+        folder = tempfile.TemporaryDirectory()
+        self.addCleanup(folder.cleanup)
+        self.path = os.path.join(folder.name, "agent.sock")
+
+    def test_idle_agent_stops_removes_its_socket_and_wipes_the_key(self):
+        clock = FakeClock()
+        agent = vault_agent.VaultAgent(MASTER_KEY, self.path, idle_timeout=60, clock=clock, poll_interval=0.01)
+        agent.bind()
+        thread = threading.Thread(target=agent.serve, daemon=True)
+        thread.start()
+        client = vault_agent.AgentClient(self.path)
+        client.fingerprint()
+        clock.now += 59
+        client.fingerprint()  # A request restarts the idle countdown...
+        clock.now += 59
+        thread.join(0.2)
+        self.assertTrue(thread.is_alive())
+        clock.now += 1  # ...and a full minute without one ends it.
+        thread.join(5)
+        self.assertFalse(thread.is_alive())
+        self.assertFalse(os.path.lexists(self.path))
+        self.assertEqual(agent.fingerprint, vault_agent.key_fingerprint(bytes(32)))
+        with self.assertRaises(OSError):
+            client.fingerprint()
+
+    def test_stale_socket_is_replaced_but_other_files_are_not(self):
+        leftover = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        leftover.bind(self.path)
+        leftover.close()  # Bound but never listening: what a crashed agent leaves.
+        agent = vault_agent.VaultAgent(MASTER_KEY, self.path, idle_timeout=0.01).bind()
+        agent.serve()
+        self.assertFalse(os.path.lexists(self.path))
+
+        with open(self.path, "w", encoding="utf-8") as handle:
+            handle.write("not a socket")
+        with self.assertRaises(vault_agent.AgentError):
+            vault_agent.VaultAgent(MASTER_KEY, self.path).bind()
+        with open(self.path, encoding="utf-8") as handle:
+            self.assertEqual(handle.read(), "not a socket")
+
+    def test_short_keys_are_refused_before_binding(self):
+        with self.assertRaises(secret_vault.SecretVaultError):
+            vault_agent.VaultAgent(b"short", self.path)
+        self.assertFalse(os.path.lexists(self.path))
+
+    def test_client_from_env_needs_a_socket(self):
+        """Unset, empty, missing, or a plain file all mean "no agent"; use the local key."""
+
+        self.assertIsNone(vault_agent.client_from_env({}))
+        self.assertIsNone(vault_agent.client_from_env({vault_agent.AGENT_ENV: " "}))
+        self.assertIsNone(vault_agent.client_from_env({vault_agent.AGENT_ENV: self.path}))
+        with open(self.path, "w", encoding="utf-8"):
+            pass
+        self.assertIsNone(vault_agent.client_from_env({vault_agent.AGENT_ENV: self.path}))
+        os.unlink(self.path)
+        agent = vault_agent.VaultAgent(MASTER_KEY, self.path, idle_timeout=0.01).bind()
+        self.addCleanup(agent.serve)
+        client = vault_agent.client_from_env({vault_agent.AGENT_ENV: self.path})
+        self.assertEqual(client.socket_path, self.path)
+
+
+if __name__ == "__main__":
+    unittest.main()
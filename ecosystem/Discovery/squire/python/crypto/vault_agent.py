@@ -0,0 +1,306 @@
+"""
+A small background agent that holds the vault key so command-line calls do not
+have to derive it again every time.
+
+Why it exists
+-------------
+A vault whose key comes from a passphrase (``derived_from_passphrase`` in the
+config) runs 200,000 rounds of PBKDF2 for every command, and each command needs
+the passphrase in an environment variable. Running several ``encrypt-secret``
+or ``decrypt-secret`` commands in a row repeats both. The agent asks for the
+passphrase once, derives the key once, and then answers requests from later
+commands over a Unix domain socket:
+
+    python config_loader.py vault-agent start SQUIRE_VAULT_SALT --socket ~/.squire-agent &
+    export SQUIRE_VAULT_AGENT=~/.squire-agent
+    python config_loader.py encrypt-secret SQUIRE_VAULT_KEY - < token.txt
+
+How it talks
+------------
+Each connection carries one request and one reply. Both are a 4-byte
+big-endian length followed by that many bytes of UTF-8 JSON:
+
+- ``{"op": "encrypt", "plaintext": "<base64>"}`` ->
+  ``{"ok": true, "envelope": "<EncryptedSecret.to_storable() text>"}``
+- ``{"op": "decrypt", "envelope": "<to_storable() text>"}`` ->
+  ``{"ok": true, "plaintext": "<base64>"}``
+- ``{"op": "fingerprint"}`` -> ``{"ok": true, "fingerprint": "<16 hex>"}``
+
+A request that fails gets ``{"ok": false, "error": "<reason>"}``.
+
+Keeping it contained
+--------------------
+- The socket is created readable and writable by its owner only (0600), so
+  other accounts on the host cannot ask the agent for anything.
+- The key lives in one ``bytearray`` inside the agent and is overwritten with
+  zeros when the agent stops. Python may still hold short-lived copies while a
+  request is being answered; the point is that the key never leaves the agent
+  process and never sits in an environment variable.
+- The agent stops by itself after ``idle_timeout`` seconds without a request
+  (``DEFAULT_IDLE_TIMEOUT_SECS`` unless told otherwise) and removes its socket.
+"""
+
+import base64
+import hashlib
+import hmac
+import json
+import os
+import socket
+import stat
+import struct
+import time
+from typing import Callable, Optional
+
+from . import secrets as secret_vault
+
+# The environment variable that points command-line calls at a running agent.
+AGENT_ENV = "SQUIRE_VAULT_AGENT"
+
+# How long the agent waits for a request before it stops: fifteen minutes.
+DEFAULT_IDLE_TIMEOUT_SECS = 15 * 60
+
+# A request or reply larger than this is refused; vault secrets are tokens and
+# passwords, not files.
+MAX_FRAME_BYTES = 1024 * 1024
+
+# How long one connection may take to send its request or read its reply, so a
+# stuck client cannot hold the agent forever.
+CONNECTION_TIMEOUT_SECS = 5.0
+
+# Label mixed into ``key_fingerprint`` so the fingerprint is not a MAC anyone
+# else computes with the same key.
+_FINGERPRINT_LABEL = b"squire-vault-agent-fingerprint"
+
+
+class AgentError(secret_vault.SecretVaultError):
+    """The agent refused a request or answered with something unreadable."""
+
+
+def key_fingerprint(key) -> str:
+    """
+    A short name for a key that reveals nothing about it: the first 16 hex
+    characters of HMAC-SHA256(key, label). Two keys with the same fingerprint
+    are, for all practical purposes, the same key.
+    """
+
+    return hmac.new(bytes(key), _FINGERPRINT_LABEL, hashlib.sha256).hexdigest()[:16]
+
+
+def _send_frame(connection: socket.socket, message: dict) -> None:
+    payload = json.dumps(message).encode("utf-8")
+    if len(payload) > MAX_FRAME_BYTES:
+        raise AgentError(f"message of {len(payload)} bytes is larger than the {MAX_FRAME_BYTES} allowed")
+    connection.sendall(struct.pack(">I", len(payload)) + payload)
+
+
+def _recv_exact(connection: socket.socket, count: int) -> bytes:
+    chunks = []
+    while count:
+        chunk = connection.recv(min(count, 65536))
+        if not chunk:
+            raise AgentError("connection closed in the middle of a message")
+        chunks.append(chunk)
+        count -= len(chunk)
+    return b"".join(chunks)
+
+
+def _recv_frame(connection: socket.socket) -> dict:
+    (length,) = struct.unpack(">I", _recv_exact(connection, 4))
+    if length > MAX_FRAME_BYTES:
+        raise AgentError(f"message of {length} bytes is larger than the {MAX_FRAME_BYTES} allowed")
+    try:
+        message = json.loads(_recv_exact(connection, length).decode("utf-8"))
+    except (UnicodeDecodeError, json.JSONDecodeError) as error:
+        raise AgentError(f"message is not JSON: {error}") from None
+    if not isinstance(message, dict):
+        raise AgentError("message must be a JSON object")
+    return message
+
+
+def _bind_owner_only(path: str) -> socket.socket:
+    """
+    Listen on ``path`` with mode 0600.
+
+    The umask is tightened around ``bind`` so the socket never exists with
+    looser permissions, even for an instant. A socket file left behind by an
+    agent that crashed is replaced; a live agent, or anything that is not a
+    socket, is left alone and reported.
+    """
+
+    if os.path.lexists(path):
+        if not stat.S_ISSOCK(os.lstat(path).st_mode):
+            raise AgentError(f"{path} exists and is not a socket; refusing to replace it")
+        probe = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        try:
+            probe.connect(path)
+        except OSError:
+            os.unlink(path)  # Nobody is listening: a leftover from a crash.
+        else:
+            raise AgentError(f"an agent is already listening on {path}")
+        finally:
+            probe.close()
+
+    listener = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    previous = os.umask(0o177)
+    try:
+        listener.bind(path)
+    except OSError:
+        listener.close()
+        raise
+    finally:
+        os.umask(previous)
+    os.chmod(path, 0o600)
+    listener.listen(8)
+    return listener
+
+
+class VaultAgent:
+    """
+    Answers encrypt, decrypt, and fingerprint requests with one key.
+
+    ``bind()`` creates the socket; ``serve()`` answers requests until the agent
+    has been idle for ``idle_timeout`` seconds, then removes the socket and
+    wipes the key. ``clock`` (default ``time.monotonic``) measures idleness and
+    ``poll_interval`` is how often it is checked; tests replace both.
+    """
+
+    def __init__(
+        self,
+        master_key: bytes,
+        socket_path,
+        idle_timeout: float = DEFAULT_IDLE_TIMEOUT_SECS,
+        clock: Callable[[], float] = time.monotonic,
+        poll_interval: float = 1.0,
+    ):
+        # Checked here so a short key fails at start-up, not at the first request.
+        secret_vault.SecretVault(master_key)
+        self._key = bytearray(master_key)
+        self._path = os.fspath(socket_path)
+        self._idle_timeout = idle_timeout
+        self._clock = clock
+        self._poll_interval = poll_interval
+        self._listener: Optional[socket.socket] = None
+        self._last_request = 0.0
+
+    @property
+    def fingerprint(self) -> str:
+        return key_fingerprint(self._key)
+
+    def bind(self) -> "VaultAgent":
+        self._listener = _bind_owner_only(self._path)
+        # The idle countdown starts once clients can connect, not when ``serve``
+        # happens to get going.
+        self._last_request = self._clock()
+        return self
+
+    def serve(self) -> None:
+        if self._listener is None:
+            self.bind()
+        listener = self._listener
+        listener.settimeout(min(self._poll_interval, self._idle_timeout))
+        try:
+            while self._clock() - self._last_request < self._idle_timeout:
+                try:
+                    connection, _ = listener.accept()
+                except socket.timeout:
+                    continue
+                # Stamped on arrival: once the reply is out the client may
+                # already be doing something else.
+                self._last_request = self._clock()
+                with connection:
+                    connection.settimeout(CONNECTION_TIMEOUT_SECS)
+                    self._answer(connection)
+        finally:
+            listener.close()
+            self._listener = None
+            try:
+                os.unlink(self._path)
+            except FileNotFoundError:
+                pass
+            self.wipe()
+
+    def wipe(self) -> None:
+        """Overwrite the key with zeros. The agent cannot answer requests afterwards."""
+
+        for index in range(len(self._key)):
+            self._key[index] = 0
+
+    def _answer(self, connection: socket.socket) -> None:
+        try:
+            request = _recv_frame(connection)
+        except (AgentError, OSError):
+            return  # Nothing sensible to reply to; the client sees the connection close.
+        try:
+            reply = {"ok": True, **self._handle(request)}
+        except (secret_vault.SecretVaultError, ValueError, KeyError, TypeError) as error:
+            reply = {"ok": False, "error": str(error) or type(error).__name__}
+        try:
+            _send_frame(connection, reply)
+        except (AgentError, OSError):
+            pass
+
+    def _handle(self, request: dict) -> dict:
+        vault = secret_vault.SecretVault(self._key)
+        op = request.get("op")
+        if op == "encrypt":
+            plaintext = base64.b64decode(request["plaintext"], validate=True)
+            return {"envelope": vault.encrypt(plaintext).to_storable()}
+        if op == "decrypt":
+            bundle = secret_vault.EncryptedSecret.from_storable(request["envelope"])
+            return {"plaintext": base64.b64encode(vault.decrypt(bundle)).decode("ascii")}
+        if op == "fingerprint":
+            return {"fingerprint": self.fingerprint}
+        raise AgentError(f"unknown op {op!r}")
+
+
+class AgentClient:
+    """
+    Sends requests to a running agent. ``encrypt`` and ``decrypt`` behave like
+    ``SecretVault``'s, so a caller can use either one. A refused request raises
+    ``AgentError``; a socket nobody listens on raises ``OSError``.
+    """
+
+    def __init__(self, socket_path, timeout: float = CONNECTION_TIMEOUT_SECS):
+        self.socket_path = os.fspath(socket_path)
+        self._timeout = timeout
+
+    def request(self, message: dict) -> dict:
+        with socket.socket(socket.AF_UNIX, socket.SOCK_STREAM) as connection:
+            connection.settimeout(self._timeout)
+            connection.connect(self.socket_path)
+            _send_frame(connection, message)
+            reply = _recv_frame(connection)
+        if reply.get("ok") is not True:
+            raise AgentError(f"agent refused the request: {reply.get('error', 'no reason given')}")
+        return reply
+
+    def encrypt(self, plaintext: bytes) -> secret_vault.EncryptedSecret:
+        reply = self.request({"op": "encrypt", "plaintext": base64.b64encode(plaintext).decode("ascii")})
+        return secret_vault.EncryptedSecret.from_storable(reply["envelope"])
+
+    def decrypt(self, bundle: secret_vault.EncryptedSecret) -> bytes:
+        reply = self.request({"op": "decrypt", "envelope": bundle.to_storable()})
+        return base64.b64decode(reply["plaintext"], validate=True)
+
+    def fingerprint(self) -> str:
+        return self.request({"op": "fingerprint"})["fingerprint"]
+
+
+def client_from_env(environ=None) -> Optional[AgentClient]:
+    """
+    The agent named by ``SQUIRE_VAULT_AGENT``, or ``None`` when the variable is
+    unset or the socket is not there. Callers then use their own key, exactly
+    as they did before the agent existed.
+    """
+
+    environ = os.environ if environ is None else environ
+    path = environ.get(AGENT_ENV, "").strip()
+    if not path:
+        return None
+    path = os.path.expanduser(path)
+    try:
+        if not stat.S_ISSOCK(os.stat(path).st_mode):
+            return None
+    except OSError:
+        return None
+    return AgentClient(path)
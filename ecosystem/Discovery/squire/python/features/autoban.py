@@ -11,7 +11,7 @@ clear audit records for the Rust wrapper to relay to Discord.
 Security stance
 ---------------
 - No network calls occur here; all Discord communication must be routed through
-  the Rust wrapper described in ``rust/discord_gateway.rs``.
+  the Rust wrapper described in ``src/gateway.rs``.
 - All data is stored in simple Python structures. If persistence is desired, the
   Rust layer can serialize ``state`` to disk between restarts.
 """
@@ -11,19 +11,25 @@ from typing import Dict, List, Optional
 
 def validate_channel_id(channel_id: str) -> bool:
     """
-    Confirm that a channel identifier is non-empty and purely numeric.
-
-    The original code guarded against invalid Discord IDs. This lightweight
-    check mirrors that behavior without external libraries.
+    Confirm that a channel identifier looks like a real Discord ID ("snowflake").
+
+    The rules match ``Snowflake::parse`` in ``ecosystem/common/src/snowflake.rs``, so Python and
+    the Rust gateway accept exactly the same IDs:
+    - only the digits 0-9 (no spaces or signs),
+    - no leading zero,
+    - at least 17 digits (every Discord ID since 2015 has that many),
+    - small enough to fit in 64 bits (at most 18446744073709551615).
     """
 
     if channel_id is None:
         return False
-    if channel_id.strip() == "":
+    # ``isdigit`` alone would also accept characters such as "²", so the check
+    # is limited to plain ASCII digits.
+    if channel_id == "" or not all("0" <= ch <= "9" for ch in channel_id):
+        return False
+    if channel_id.startswith("0"):
         return False
-    # ``isdigit`` ensures the value contains only digits, which matches the
-    # typical shape of Discord snowflakes.
-    return channel_id.isdigit()
+    return 10**16 <= int(channel_id) <= 2**64 - 1
 
 
 def prepare_setup_summary(features: List[str], channels: Dict[str, str]) -> str:
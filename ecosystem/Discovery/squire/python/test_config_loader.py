@@ -0,0 +1,464 @@
+"""Tests for encrypting and decrypting whole config templates.
+
+Run from ``ecosystem/Discovery`` with
+`python -m unittest squire.python.test_config_loader`.
+"""
+
+import contextlib
+import io
+import json
+import os
+import socket
+import stat
+import sys
+import tempfile
+import threading
+import unittest
+from unittest import mock
+from pathlib import Path
+
+from squire.python import config_loader
+
+MASTER_KEY = bytes(range(32))
+
+
+class TemplateTests(unittest.TestCase):
+    def test_nested_template_round_trip(self):
+        """Secrets inside objects and arrays encrypt, and decrypt back to the same text."""
+
+        template = {
+            "discord_token": "@secret:token-123",
+            "features": {"webhook": {"signing_key": "@secret:hook-key"}},
+            "backups": ["@secret:first", {"password": "@secret:second"}],
+        }
+        encrypted = config_loader.encrypt_template(MASTER_KEY, template)
+
+        self.assertEqual(set(encrypted["discord_token"]), {"nonce", "ciphertext", "tag"})
+        self.assertNotIn("token-123", json.dumps(encrypted))
+        self.assertEqual(
+            config_loader.decrypt_template(MASTER_KEY, encrypted),
+            {
+                "discord_token": "token-123",
+                "features": {"webhook": {"signing_key": "hook-key"}},
+                "backups": ["first", {"password": "second"}],
+            },
+        )
+
+    def test_non_secret_fields_are_untouched(self):
+        """Plain strings, numbers, booleans, nulls, and key order stay as they were."""
+
+        template = {"name": "squire", "port": 7420, "debug": False, "channel": None, "api": "@secret:x"}
+        encrypted = config_loader.encrypt_template(MASTER_KEY, template)
+
+        self.assertEqual(list(encrypted), list(template))
+        self.assertEqual(encrypted["name"], "squire")
+        self.assertEqual(encrypted["port"], 7420)
+        self.assertIs(encrypted["debug"], False)
+        self.assertIsNone(encrypted["channel"])
+
+    def test_errors_name_the_json_pointer(self):
+        """A field that cannot be handled is reported by its JSON Pointer."""
+
+        with self.assertRaises(config_loader.TemplateError) as caught:
+            config_loader.encrypt_template(MASTER_KEY, {"a/b": [{"token": "@secret:"}]})
+        self.assertEqual(caught.exception.pointer, "/a~1b/0/token")
+
+        encrypted = config_loader.encrypt_template(MASTER_KEY, {"outer": {"token": "@secret:value"}})
+        with self.assertRaises(config_loader.TemplateError) as caught:
+            config_loader.decrypt_template(bytes(32), encrypted)
+        self.assertEqual(caught.exception.pointer, "/outer/token")
+
+    def test_encrypt_config_command_writes_owner_only_file(self):
+        """The CLI reads the key from the named variable and writes out.json privately."""
+
+        with tempfile.TemporaryDirectory() as folder:
+            template = Path(folder, "template.json")
+            output = Path(folder, "config.json")
+            template.write_text(json.dumps({"token": "@secret:abc"}), encoding="utf-8")
+            os.environ["TEST_TEMPLATE_KEY"] = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8="
+            try:
+                code = config_loader.main(["encrypt-config", "TEST_TEMPLATE_KEY", str(template), str(output)])
+            finally:
+                del os.environ["TEST_TEMPLATE_KEY"]
+
+            self.assertEqual(code, 0)
+            written = json.loads(output.read_text(encoding="utf-8"))
+            self.assertEqual(config_loader.decrypt_template(MASTER_KEY, written), {"token": "abc"})
+            if os.name == "posix":
+                self.assertEqual(stat.S_IMODE(output.stat().st_mode), 0o600)
+            self.assertEqual(sorted(os.listdir(folder)), ["config.json", "template.json"])
+
+
+
+class AdditionalSecretsTests(unittest.TestCase):
+    KEY_ENV = "TEST_ADDITIONAL_KEY"
+
+    def setUp(self):
+        os.environ[self.KEY_ENV] = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8="
+        self.folder = tempfile.TemporaryDirectory()
+
+    def tearDown(self):
+        del os.environ[self.KEY_ENV]
+        self.folder.cleanup()
+
+    def _write_config(self, additional=None):
+        token = json.loads(config_loader.secret_vault.encrypt_secret(MASTER_KEY, b"token").to_storable())
+        raw = {
+            "vault": {"key_env": self.KEY_ENV, "salt_env": "UNUSED", "derived_from_passphrase": False},
+            "secrets": [dict(name="discord_bot_token", **token)],
+        }
+        if additional is not None:
+            raw["additional_secrets"] = additional
+        path = Path(self.folder.name, "config.json")
+        path.write_text(json.dumps(raw), encoding="utf-8")
+        return path
+
+    def _envelope(self, plaintext):
+        return config_loader.encrypt_template(MASTER_KEY, "@secret:" + plaintext)
+
+    def test_two_encrypted_extras_and_one_plain_value(self):
+        """Envelopes decrypt, plain strings pass through, all under their own names."""
+
+        path = self._write_config({
+            "database_password": self._envelope("db-pass"),
+            "webhook_signing_key": self._envelope("hook-key"),
+            "database_host": "localhost",
+        })
+        cfg = config_loader.load_config(path)
+
+        self.assertEqual(
+            config_loader.decrypt_additional_secrets(cfg, MASTER_KEY),
+            {"database_password": "db-pass", "webhook_signing_key": "hook-key", "database_host": "localhost"},
+        )
+
+    def test_corrupted_extra_names_itself(self):
+        """A tampered envelope fails loudly and the error says which secret it was."""
+
+        broken = self._envelope("db-pass")
+        broken["tag"] = "AAAAAAAAAAAAAAAAAAAAAA=="
+        path = self._write_config({"database_password": broken})
+
+        with self.assertRaises(config_loader.TemplateError) as caught:
+            config_loader.load_config(path)
+        self.assertEqual(caught.exception.pointer, "/additional_secrets/database_password")
+        self.assertIn("database_password", str(caught.exception))
+
+    def test_config_without_the_map_still_loads(self):
+        """Existing configs that predate additional_secrets keep working."""
+
+        cfg = config_loader.load_config(self._write_config())
+
+        self.assertEqual(cfg.additional_secrets, {})
+        self.assertEqual(config_loader.decrypt_additional_secrets(cfg, MASTER_KEY), {})
+
+
+    def test_secretbox_records_load_and_reencrypt_to_native(self):
+        """A libsodium envelope loads as it is and ``reencrypt-config`` upgrades it."""
+
+        legacy = {
+            "format": "secretbox",
+            "nonce": "AAECAwQFBgcICQoLDA0ODxAREhMUFRYX",
+            "ciphertext": "HhG7HpHLvYQIuNko616z8ELhb+hjzGPgqap7lg==",
+            "tag": "vGimeN9nz+/eytyVXmVYfA==",
+        }
+        os.environ[self.KEY_ENV] = "c3F1aXJlLXNlY3JldGJveC1maXh0dXJlLWtleS0zMmI="
+        raw = {
+            "vault": {"key_env": self.KEY_ENV, "salt_env": "UNUSED", "derived_from_passphrase": False},
+            "secrets": [dict(name="webhook_signing_key", **legacy)],
+            "additional_secrets": {"old_hook": legacy},
+        }
+        path = Path(self.folder.name, "config.json")
+        path.write_text(json.dumps(raw), encoding="utf-8")
+        self.assertEqual(config_loader.decrypt_all_secrets(config_loader.load_config(path)),
+                         [("webhook_signing_key", b"legacy webhook signing token")])
+
+        out = Path(self.folder.name, "upgraded.json")
+        self.assertEqual(config_loader.main(["reencrypt-config", self.KEY_ENV, str(path), str(out)]), 0)
+        upgraded = json.loads(out.read_text(encoding="utf-8"))
+        self.assertNotIn("format", upgraded["secrets"][0])
+        self.assertEqual(upgraded["secrets"][0]["name"], "webhook_signing_key")
+        cfg = config_loader.load_config(out)
+        self.assertEqual(config_loader.decrypt_additional_secrets(cfg, config_loader._master_key_from_env(self.KEY_ENV)),
+                         {"old_hook": "legacy webhook signing token"})
+
+    def test_mislabelled_format_names_the_record(self):
+        """A secretbox record without its ``format`` says so instead of failing authentication."""
+
+        os.environ[self.KEY_ENV] = "c3F1aXJlLXNlY3JldGJveC1maXh0dXJlLWtleS0zMmI="
+        raw = {
+            "vault": {"key_env": self.KEY_ENV, "salt_env": "UNUSED", "derived_from_passphrase": False},
+            "secrets": [{
+                "name": "webhook_signing_key",
+                "nonce": "AAECAwQFBgcICQoLDA0ODxAREhMUFRYX",
+                "ciphertext": "HhG7HpHLvYQIuNko616z8ELhb+hjzGPgqap7lg==",
+                "tag": "vGimeN9nz+/eytyVXmVYfA==",
+            }],
+        }
+        path = Path(self.folder.name, "config.json")
+        path.write_text(json.dumps(raw), encoding="utf-8")
+        with self.assertRaises(config_loader.TemplateError) as caught:
+            config_loader.load_config(path)
+        self.assertEqual(caught.exception.pointer, "/secrets/0")
+        self.assertIn("secretbox", caught.exception.reason)
+
+
+class MigrationTests(unittest.TestCase):
+    KEY_ENV = "TEST_MIGRATION_KEY"
+    LEGACY = {
+        "discord_token": "$ENV{TEST_MIGRATION_TOKEN}",
+        "application_id": 123456789,
+        "public_key": "abcdef",
+        "database_path": "data/squire.db",
+        "feature_flags": {"gateway": True},
+    }
+
+    def setUp(self):
+        os.environ[self.KEY_ENV] = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8="
+        os.environ["TEST_MIGRATION_TOKEN"] = "token-from-env"
+        self.folder = tempfile.TemporaryDirectory()
+        self.old = Path(self.folder.name, "old.json")
+        self.new = Path(self.folder.name, "new.json")
+        self.old.write_text(json.dumps(self.LEGACY), encoding="utf-8")
+
+    def tearDown(self):
+        del os.environ[self.KEY_ENV]
+        del os.environ["TEST_MIGRATION_TOKEN"]
+        self.folder.cleanup()
+
+    def _migrate(self, *extra):
+        return config_loader.main(["migrate-config", self.KEY_ENV, str(self.old), str(self.new), *extra])
+
+    def test_migrated_file_loads_with_placeholders_resolved(self):
+        """The token is read from its $ENV placeholder, encrypted, and loads back."""
+
+        self.assertEqual(self._migrate(), 0)
+        self.assertNotIn("token-from-env", self.new.read_text(encoding="utf-8"))
+
+        cfg = config_loader.load_config(self.new)
+        secrets = dict(config_loader.decrypt_all_secrets(cfg))
+        self.assertEqual(secrets, {"discord_bot_token": b"token-from-env", "application_id": b"123456789"})
+        self.assertEqual(config_loader.decrypt_additional_secrets(cfg, MASTER_KEY), {"public_key": "abcdef"})
+        raw = json.loads(self.new.read_text(encoding="utf-8"))
+        self.assertEqual(raw["database_path"], "data/squire.db")
+        self.assertEqual(raw["feature_flags"], {"gateway": True})
+
+    @unittest.skipUnless(os.name == "posix", "permission bits are Unix-only")
+    def test_new_file_is_owner_only(self):
+        self.assertEqual(self._migrate(), 0)
+        self.assertEqual(stat.S_IMODE(self.new.stat().st_mode), 0o600)
+
+    def test_existing_output_needs_force(self):
+        """An existing file is left alone unless --force is given."""
+
+        self.new.write_text("keep me", encoding="utf-8")
+        self.assertEqual(self._migrate(), 1)
+        self.assertEqual(self.new.read_text(encoding="utf-8"), "keep me")
+
+        self.assertEqual(self._migrate("--force"), 0)
+        self.assertIn("secrets", json.loads(self.new.read_text(encoding="utf-8")))
+
+
+class AuditLogCommandTests(unittest.TestCase):
+    def test_audit_log_read_prints_records_in_order(self):
+        """``audit-log read`` opens the log with the audit subvault of the named key."""
+
+        vault = config_loader.secret_vault.SecretVault(MASTER_KEY)
+        audit_vault = vault.derive_subvault(config_loader.secret_vault.AUDIT_PURPOSE)
+        with tempfile.TemporaryDirectory() as folder:
+            log = Path(folder, "audit.log")
+            audited = vault.with_audit(log, audit_vault)
+            audited.decrypt(audited.encrypt(b"token"))
+            os.environ["TEST_AUDIT_KEY"] = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8="
+            output = io.StringIO()
+            try:
+                with contextlib.redirect_stdout(output):
+                    code = config_loader.main(["audit-log", "read", "TEST_AUDIT_KEY", str(log)])
+            finally:
+                del os.environ["TEST_AUDIT_KEY"]
+
+        self.assertEqual(code, 0)
+        records = [json.loads(line) for line in output.getvalue().splitlines()]
+        self.assertEqual([record["op"] for record in records], ["encrypt", "decrypt"])
+        self.assertNotIn("token", output.getvalue())
+
+
+class SecretInputCommandTests(unittest.TestCase):
+    """Passwords and plaintexts come from stdin or a prompt, not only from argv."""
+
+    KEY_ENV = "TEST_SECRET_INPUT_KEY"
+
+    def setUp(self):
+        os.environ[self.KEY_ENV] = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8="
+        self.addCleanup(os.environ.pop, self.KEY_ENV, None)
+        # A vault agent in the developer's shell must not answer for these tests.
+        patcher = mock.patch.dict(os.environ)
+        patcher.start()
+        self.addCleanup(patcher.stop)
+        os.environ.pop(config_loader.vault_agent.AGENT_ENV, None)
+
+    def run_main(self, argv, stdin=""):
+        """``main(argv)`` with ``stdin`` piped in; returns (exit code, stdout)."""
+
+        output = io.StringIO()
+        with mock.patch.object(sys, "stdin", io.StringIO(stdin)), contextlib.redirect_stdout(output):
+            code = config_loader.main(argv)
+        return code, output.getvalue()
+
+    def test_piped_password_round_trips_through_hash_and_verify(self):
+        code, stored = self.run_main(["hash-password", "-"], stdin="correct horse\n")
+        self.assertEqual(code, 0)
+        self.assertNotIn("correct horse", stored)
+        stored = stored.strip()
+        self.assertEqual(self.run_main(["verify-password", stored], stdin="correct horse\n")[0], 0)
+        with contextlib.redirect_stderr(io.StringIO()):
+            self.assertEqual(self.run_main(["verify-password", stored, "-"], stdin="wrong horse\n")[0], 1)
+
+    def test_one_trailing_newline_is_dropped_to_match_argv(self):
+        code, stored = self.run_main(["hash-password", "pw"])
+        self.assertEqual(code, 0)
+        stored = stored.strip()
+        for piped in ("pw", "pw\n", "pw\r\n"):
+            self.assertEqual(self.run_main(["verify-password", stored, "-"], stdin=piped)[0], 0, repr(piped))
+        # Only one newline is dropped; a second one is part of the password.
+        with contextlib.redirect_stderr(io.StringIO()):
+            self.assertEqual(self.run_main(["verify-password", stored, "-"], stdin="pw\n\n")[0], 1)
+
+    def test_dash_sentinel_encrypts_and_decrypts(self):
+        code, envelope = self.run_main(["encrypt-secret", self.KEY_ENV, "-"], stdin="bot-token-123\n")
+        self.assertEqual(code, 0)
+        self.assertNotIn("bot-token-123", envelope)
+        self.assertEqual(self.run_main(["decrypt-secret", self.KEY_ENV, "-"], stdin=envelope), (0, "bot-token-123"))
+        with tempfile.TemporaryDirectory() as folder:
+            path = Path(folder, "envelope.json")
+            path.write_text(envelope, encoding="utf-8")
+            self.assertEqual(self.run_main(["decrypt-secret", self.KEY_ENV, str(path)]), (0, "bot-token-123"))
+
+    def test_tampered_envelope_is_refused(self):
+        _, envelope = self.run_main(["encrypt-secret", self.KEY_ENV, "secret"])
+        document = json.loads(envelope)
+        document["ciphertext"] = "AAAAAAAA"
+        with contextlib.redirect_stderr(io.StringIO()):
+            self.assertEqual(self.run_main(["decrypt-secret", self.KEY_ENV], stdin=json.dumps(document))[0], 1)
+
+    def test_terminal_prompts_without_echo(self):
+        terminal = io.StringIO()
+        terminal.isatty = lambda: True
+        output = io.StringIO()
+        with mock.patch.object(sys, "stdin", terminal), mock.patch.object(
+            config_loader.getpass, "getpass", return_value="typed"
+        ) as prompt, contextlib.redirect_stdout(output):
+            self.assertEqual(config_loader.main(["hash-password"]), 0)
+        prompt.assert_called_once_with("Password: ")
+        self.assertTrue(config_loader.passwords.verify_password("typed", output.getvalue().strip()))
+
+
+class VaultAgentCommandTests(SecretInputCommandTests):
+    """
+    ``encrypt-secret``/``decrypt-secret`` through ``SQUIRE_VAULT_AGENT``, and
+    ``vault-agent start``. The inherited tests run again with the variable
+    naming a socket that does not exist, which must work as if it were unset.
+    """
+
+    SALT_ENV = "TEST_VAULT_AGENT_SALT"
+
+    def setUp(self):
+        super().setUp()
+        folder = tempfile.TemporaryDirectory()
+        self.addCleanup(folder.cleanup)
+        self.socket_path = os.path.join(folder.name, "agent.sock")
+        os.environ[config_loader.vault_agent.AGENT_ENV] = self.socket_path
+
+    def start_agent(self, key):
+        now = [0.0]
+        agent = config_loader.vault_agent.VaultAgent(
+            key, self.socket_path, idle_timeout=60, clock=lambda: now[0], poll_interval=0.01
+        ).bind()
+        thread = threading.Thread(target=agent.serve, daemon=True)
+        thread.start()
+
+        def stop():
+            now[0] += 3600
+            thread.join(5)
+
+        self.addCleanup(stop)
+
+    def test_agent_is_used_instead_of_key_env(self):
+        """With an agent running, the key comes from it and ``<key_env>`` is never read."""
+
+        self.start_agent(MASTER_KEY)
+        del os.environ[self.KEY_ENV]
+        code, envelope = self.run_main(["encrypt-secret", self.KEY_ENV, "-"], stdin="bot-token-123\n")
+        self.assertEqual(code, 0)
+        self.assertNotIn("bot-token-123", envelope)
+        self.assertEqual(self.run_main(["decrypt-secret", self.KEY_ENV], stdin=envelope), (0, "bot-token-123"))
+        # The agent's envelopes are ordinary vault envelopes.
+        vault = config_loader.secret_vault.SecretVault(MASTER_KEY)
+        self.assertEqual(vault.decrypt(config_loader.secret_vault.EncryptedSecret.from_storable(envelope)), b"bot-token-123")
+        self.assertEqual(
+            self.run_main(["vault-agent", "fingerprint"]),
+            (0, config_loader.vault_agent.key_fingerprint(MASTER_KEY) + "\n"),
+        )
+
+    def test_agent_refusal_is_reported(self):
+        """An agent holding another key refuses a local envelope with exit code 1."""
+
+        _, local_envelope = self.run_main(["encrypt-secret", self.KEY_ENV, "secret"])
+        self.start_agent(bytes(32))
+        errors = io.StringIO()
+        with contextlib.redirect_stderr(errors):
+            self.assertEqual(self.run_main(["decrypt-secret", self.KEY_ENV], stdin=local_envelope), (1, ""))
+        self.assertIn("agent refused the request", errors.getvalue())
+
+    def test_stale_socket_falls_back_to_key_env(self):
+        """A socket nobody answers on gets a note on stderr, then ``<key_env>`` is used."""
+
+        leftover = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        leftover.bind(self.socket_path)
+        leftover.close()
+        errors = io.StringIO()
+        with contextlib.redirect_stderr(errors):
+            code, envelope = self.run_main(["encrypt-secret", self.KEY_ENV, "local"])
+        self.assertEqual(code, 0)
+        self.assertIn("not answering", errors.getvalue())
+        self.assertIn(self.KEY_ENV, errors.getvalue())
+        vault = config_loader.secret_vault.SecretVault(MASTER_KEY)
+        self.assertEqual(vault.decrypt(config_loader.secret_vault.EncryptedSecret.from_storable(envelope)), b"local")
+
+    def test_start_reads_the_passphrase_once_and_stops_when_idle(self):
+        """The passphrase comes from stdin, the socket is 0600, and an idle agent exits and cleans up."""
+
+        os.environ[self.SALT_ENV] = "c3F1aXJlLXNhbHQ="
+        self.addCleanup(os.environ.pop, self.SALT_ENV, None)
+        expected = config_loader._key_from_passphrase("correct horse", "c3F1aXJlLXNhbHQ=")
+        modes = []
+        real_serve = config_loader.vault_agent.VaultAgent.serve
+
+        def serve(agent):
+            modes.append(stat.S_IMODE(os.stat(self.socket_path).st_mode))
+            real_serve(agent)
+
+        errors = io.StringIO()
+        with mock.patch.object(config_loader.vault_agent.VaultAgent, "serve", serve), contextlib.redirect_stderr(errors):
+            code, _ = self.run_main(
+                ["vault-agent", "start", self.SALT_ENV, "--idle-timeout", "0.05"], stdin="correct horse\n"
+            )
+        self.assertEqual(code, 0)
+        self.assertEqual(modes, [0o600])
+        self.assertIn(config_loader.vault_agent.key_fingerprint(expected), errors.getvalue())
+        self.assertNotIn("correct horse", errors.getvalue())
+        self.assertFalse(os.path.lexists(self.socket_path))
+
+    def test_start_refuses_bad_options(self):
+        os.environ[self.SALT_ENV] = "c3F1aXJlLXNhbHQ="
+        self.addCleanup(os.environ.pop, self.SALT_ENV, None)
+        with contextlib.redirect_stderr(io.StringIO()):
+            for extra in (["--idle-timeout", "0"], ["--idle-timeout", "soon"], ["--socket"], ["--verbose", "1"]):
+                with self.subTest(extra=extra):
+                    self.assertEqual(self.run_main(["vault-agent", "start", self.SALT_ENV, *extra], stdin="pw")[0], 2)
+            del os.environ[config_loader.vault_agent.AGENT_ENV]
+            self.assertEqual(self.run_main(["vault-agent", "start", self.SALT_ENV], stdin="pw")[0], 2)
+        self.assertFalse(os.path.lexists(self.socket_path))
+
+
+if __name__ == "__main__":
+    unittest.main()
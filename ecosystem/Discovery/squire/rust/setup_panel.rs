@@ -1,19 +1,130 @@
 // Setup panel rewritten in Rust with lavish commentary and no external crates.
 //
-// This file is intentionally standalone: it uses only the Rust standard library
-// so that all logic is auditable without fetching dependencies. The goal is to
-// mirror the intent of the former JavaScript setup panel: collect configuration
-// choices from an operator, validate them, and render a clear summary that can
-// later be fed into the rest of the application.
+// It is the `setup_panel` binary of the `squire-gateway` crate (see its
+// Cargo.toml) and, like the rest of the crate, uses only the Rust standard
+// library so that all logic is auditable without fetching dependencies. The
+// goal is to mirror the intent of the former JavaScript setup panel: collect
+// configuration choices from an operator, validate them, and write a
+// `config.json` that the `squire-gateway` binary (`src/config.rs`) and the
+// Python loader can read.
 //
-// The program does not perform any network or filesystem writes. Instead, it
-// reads from standard input and prints to standard output so it can be executed
-// safely in isolated environments. The design favors explicit variable names and
-// step-by-step comments so even readers new to Rust can follow along.
+// Fields have a kind (`FieldKind`): free text, Discord ids, yes/no questions,
+// fixed choices, and secrets. A field may have a default, used when the
+// operator just presses Enter, and may depend on an earlier answer: the
+// logging channel is only asked for when log forwarding was answered "yes".
+//
+// Two ways to run it:
+//   setup_panel [--out config.json] [--force]
+//       Interactive. Each answer is checked right away; a bad answer is asked
+//       again up to three more times before the panel gives up.
+//   setup_panel --from-file answers.txt [--out config.json] [--force]
+//       Scripted. The answers come from a `key=value` file instead (see
+//       `SetupPanel::apply_answers`) and a bad value stops the run at once.
+//
+// The bot token is checked for the rough Discord shape but never printed, not
+// even in error messages. By default the written file does not contain it at
+// all: `discord_token` becomes the placeholder `$ENV{SQUIRE_DISCORD_TOKEN}`,
+// which the gateway fills in from the environment at startup. Writing the real
+// token into the file needs an explicit "yes" (or `inline_token=yes` in an
+// answers file), and such a file is made readable by its owner only.
+//
+// The program makes no network calls. Its only filesystem write is the config
+// file named by `--out`, and an existing file is only replaced with `--force`.
 
-use std::io::{self, Write};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+// The id rules are shared with the gateway.
+use squire_gateway::snowflake::Snowflake;
+
+/// How many extra tries an operator gets after a bad answer.
+const MAX_RETRIES: usize = 3;
+
+/// Placeholder written instead of the token unless the operator confirms.
+const TOKEN_PLACEHOLDER: &str = "$ENV{SQUIRE_DISCORD_TOKEN}";
+
+/// What kind of value a field holds, which decides how an answer is checked and
+/// how it is stored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldKind {
+    /// Any non-empty text, such as a display name.
+    Text,
+    /// A Discord id ("snowflake"): 17 to 20 digits, no leading zero, at most `u64::MAX`.
+    Snowflake,
+    /// A yes/no question. `y`, `yes`, `true` and `n`, `no`, `false` are accepted
+    /// (in any case) and stored as `yes` or `no`.
+    Boolean,
+    /// One of a fixed list of words, matched without regard to case and stored
+    /// in the spelling given here.
+    Choice(Vec<String>),
+    /// Any non-empty text that must never be shown, such as a password.
+    Secret,
+    /// A Discord bot token: a `Secret` that must also look like `xxx.yyy.zzz`.
+    Token,
+}
+
+impl FieldKind {
+    /// Check an answer and return it in its stored (canonical) form. The error
+    /// explains the rule but never repeats the answer, so a token typed into the
+    /// wrong prompt does not end up on screen or in a log.
+    pub fn normalize(&self, value: &str) -> Result<String, String> {
+        match self {
+            FieldKind::Text | FieldKind::Secret if value.is_empty() => Err("this value cannot be empty".to_string()),
+            FieldKind::Text | FieldKind::Secret => Ok(value.to_string()),
+            // Discord ids are 64-bit numbers; written out they take 17 to 20 digits.
+            FieldKind::Snowflake => match Snowflake::parse(value) {
+                Ok(id) => Ok(id.to_string()),
+                Err(reason) => Err(format!("a Discord id {} (right-click > Copy ID in Discord)", reason)),
+            },
+            FieldKind::Boolean => match value.to_ascii_lowercase().as_str() {
+                "y" | "yes" | "true" => Ok("yes".to_string()),
+                "n" | "no" | "false" => Ok("no".to_string()),
+                _ => Err("answer yes or no (y/n and true/false work too)".to_string()),
+            },
+            FieldKind::Choice(choices) => choices
+                .iter()
+                .find(|choice| choice.eq_ignore_ascii_case(value))
+                .cloned()
+                .ok_or_else(|| format!("pick one of: {}", choices.join(", "))),
+            FieldKind::Token => {
+                if value.is_empty() {
+                    return Err("the bot token cannot be empty".to_string());
+                }
+                // Real tokens look like `xxx.yyy.zzz`, each part made of base64url characters.
+                // This is only a shape check; Discord itself decides whether the token works.
+                let parts: Vec<&str> = value.split('.').collect();
+                let well_formed = parts.len() == 3
+                    && parts
+                        .iter()
+                        .all(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_'));
+                if well_formed {
+                    Ok(value.to_string())
+                } else {
+                    Err("that does not look like a Discord bot token (three parts separated by dots)".to_string())
+                }
+            }
+        }
+    }
+
+    /// Secrets and tokens are never displayed.
+    pub fn is_secret(&self) -> bool {
+        matches!(self, FieldKind::Secret | FieldKind::Token)
+    }
+
+    /// The allowed answers, shown in the prompt: `[yes/no]` or `[send/offline]`.
+    fn hint(&self) -> Option<String> {
+        match self {
+            FieldKind::Boolean => Some("[yes/no]".to_string()),
+            FieldKind::Choice(choices) => Some(format!("[{}]", choices.join("/"))),
+            _ => None,
+        }
+    }
+}
+
 /// Represents one configuration item the operator can supply.
 /// Each field is public for ease of inspection and because there is no need for
 /// encapsulation in this simple, single-file module.
@@ -21,13 +132,73 @@ use std::time::{SystemTime, UNIX_EPOCH};
 pub struct SetupField {
     /// Human-readable label describing what the value means.
     pub label: String,
-    /// The actual user-provided value captured from stdin.
+    /// Key of this value in `config.json` and in an answers file.
+    pub key: String,
+    /// Which check the value must pass.
+    pub kind: FieldKind,
+    /// Used when the operator just presses Enter (or an answers file leaves the
+    /// key out). Shown in the prompt. Secret fields should not have one.
+    pub default: Option<String>,
+    /// `Some((index, value))`: only ask this field when the field at `index`
+    /// (an earlier one) was answered with `value`. Otherwise it is skipped and
+    /// stays empty.
+    pub depends_on: Option<(usize, String)>,
+    /// The answer in its canonical form (see `FieldKind::normalize`). Empty for
+    /// skipped fields.
     pub value: String,
     /// Flag indicating whether the field is considered sensitive. This affects
     /// how we display it in the final summary.
     pub is_secret: bool,
 }
 
+impl SetupField {
+    /// A field without a default or a condition.
+    pub fn new(label: &str, key: &str, kind: FieldKind) -> Self {
+        SetupField {
+            label: label.to_string(),
+            key: key.to_string(),
+            is_secret: kind.is_secret(),
+            kind,
+            default: None,
+            depends_on: None,
+            value: String::new(),
+        }
+    }
+
+    /// Answer used on empty input.
+    pub fn with_default(mut self, default: &str) -> Self {
+        self.default = Some(default.to_string());
+        self
+    }
+
+    /// Only ask this field when field number `index` holds `value`.
+    pub fn depends_on(mut self, index: usize, value: &str) -> Self {
+        self.depends_on = Some((index, value.to_string()));
+        self
+    }
+
+    /// The prompt line, e.g. `Please enter Forward log lines? [yes/no] (default: yes):`.
+    fn prompt(&self) -> String {
+        let mut prompt = format!("Please enter {}", self.label);
+        if let Some(hint) = self.kind.hint() {
+            prompt.push_str(&format!(" {hint}"));
+        }
+        if let Some(default) = &self.default {
+            prompt.push_str(&format!(" (default: {default})"));
+        }
+        prompt.push(':');
+        prompt
+    }
+
+    /// Check a raw answer, falling back to the default when it is empty.
+    fn accept(&self, raw: &str) -> Result<String, String> {
+        match (&self.default, raw.is_empty()) {
+            (Some(default), true) => self.kind.normalize(default),
+            _ => self.kind.normalize(raw),
+        }
+    }
+}
+
 /// Holds the overall panel state while we prompt the user.
 #[derive(Clone, Debug)]
 pub struct SetupPanel {
@@ -38,6 +209,12 @@ pub struct SetupPanel {
     pub started_at: u128,
 }
 
+impl Default for SetupPanel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SetupPanel {
     /// Create a new panel with no preloaded fields.
     pub fn new() -> Self {
@@ -51,67 +228,520 @@ impl SetupPanel {
         }
     }
 
-    /// Add a field definition to the panel. The function accepts ownership of
-    /// the provided data and pushes it into the ordered list.
-    pub fn add_field(&mut self, label: &str, is_secret: bool) {
-        self.fields.push(SetupField {
-            label: label.to_string(),
-            value: String::new(),
-            is_secret,
-        });
+    /// Convenience for plain fields: `Text`, or `Secret` when `is_secret`.
+    pub fn add_field(&mut self, label: &str, key: &str, is_secret: bool) {
+        let kind = if is_secret { FieldKind::Secret } else { FieldKind::Text };
+        self.push_field(SetupField::new(label, key, kind));
     }
 
-    /// Prompt the operator for each field in order. For simplicity and
-    /// portability we use ``std::io`` without any line-editing dependencies.
-    pub fn collect_inputs(&mut self) -> io::Result<()> {
-        let stdin = io::stdin();
-        let mut stdout = io::stdout();
+    /// Add a fully described field and return its index, for `depends_on`.
+    /// A condition may only point at an earlier field, because answers are
+    /// collected in order.
+    pub fn push_field(&mut self, field: SetupField) -> usize {
+        if let Some((index, _)) = &field.depends_on {
+            assert!(*index < self.fields.len(), "field {:?} depends on a later field", field.key);
+        }
+        self.fields.push(field);
+        self.fields.len() - 1
+    }
 
-        for field in &mut self.fields {
-            writeln!(stdout, "Please enter {}:", field.label)?;
-            write!(stdout, "> ")?;
-            stdout.flush()?;
+    /// Whether field `index` should be asked, given the answers so far. The
+    /// wanted value is normalized with the earlier field's kind, so a condition
+    /// written as `y` still matches the stored `yes`.
+    pub fn is_active(&self, index: usize) -> bool {
+        let Some((earlier, wanted)) = &self.fields[index].depends_on else {
+            return true;
+        };
+        let earlier = &self.fields[*earlier];
+        let wanted = earlier.kind.normalize(wanted).unwrap_or_else(|_| wanted.clone());
+        !earlier.value.is_empty() && earlier.value == wanted
+    }
 
-            let mut buffer = String::new();
-            stdin.read_line(&mut buffer)?;
-            // ``trim`` removes trailing newlines without altering intentional
-            // interior spaces.
-            field.value = buffer.trim().to_string();
+    /// Prompt the operator for each active field in order and keep asking (up to
+    /// `MAX_RETRIES` more times) until the answer passes its check. Fields whose
+    /// condition is not met are skipped and left empty. The reader and writer
+    /// are parameters, so the same code serves a terminal (`stdin().lock()`,
+    /// `stdout()`) or a script feeding prepared input.
+    pub fn collect_inputs<R: BufRead, W: Write>(&mut self, input: &mut R, output: &mut W) -> io::Result<()> {
+        for index in 0..self.fields.len() {
+            if !self.is_active(index) {
+                self.fields[index].value.clear();
+                continue;
+            }
+            let field = &mut self.fields[index];
+            let mut attempt = 0;
+            loop {
+                writeln!(output, "{}", field.prompt())?;
+                write!(output, "> ")?;
+                output.flush()?;
+
+                let mut buffer = String::new();
+                if input.read_line(&mut buffer)? == 0 {
+                    return Err(invalid(format!("input ended before {} was entered", field.label)));
+                }
+                // ``trim`` removes trailing newlines without altering intentional
+                // interior spaces.
+                match field.accept(buffer.trim()) {
+                    Ok(value) => {
+                        field.value = value;
+                        break;
+                    }
+                    Err(reason) if attempt < MAX_RETRIES => {
+                        writeln!(output, "Not accepted: {reason}. Try again ({} tries left).", MAX_RETRIES - attempt)?;
+                        attempt += 1;
+                    }
+                    Err(reason) => {
+                        return Err(invalid(format!("{}: {reason}; giving up after {} retries", field.label, MAX_RETRIES)));
+                    }
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Ask whether the real token should go into the file. Only an explicit
+    /// `yes` counts; anything else (including just pressing Enter) keeps the
+    /// `$ENV{...}` placeholder.
+    pub fn confirm_inline_token<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<bool> {
+        writeln!(output, "Write the bot token itself into the config file instead of {TOKEN_PLACEHOLDER}?")?;
+        writeln!(output, "Anyone who can read the file can then run the bot. Type yes to confirm, anything else to keep the placeholder.")?;
+        write!(output, "> ")?;
+        output.flush()?;
+        let mut buffer = String::new();
+        input.read_line(&mut buffer)?;
+        Ok(buffer.trim() == "yes")
+    }
+
+    /// Fill the fields from an answers file instead of prompting. The file holds
+    /// `key=value` lines using the fields' keys, plus `inline_token=yes|no`;
+    /// blank lines and lines starting with `#` are skipped. Missing keys take
+    /// their default, fields whose condition is not met are skipped, and the
+    /// token may be left out when it is not written inline, because the
+    /// placeholder does not need it. Returns whether `inline_token=yes` was set.
+    pub fn apply_answers(&mut self, text: &str) -> io::Result<bool> {
+        let mut inline_token = false;
+        let mut answers: Vec<(usize, String, String)> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(invalid(format!("answers line {} is not key=value", index + 1)));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if key == "inline_token" {
+                inline_token = match value {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(invalid(format!("answers line {}: inline_token must be yes or no", index + 1))),
+                };
+            } else if self.fields.iter().any(|field| field.key == key) {
+                answers.push((index + 1, key.to_string(), value.to_string()));
+            } else {
+                let mut known: Vec<&str> = self.fields.iter().map(|field| field.key.as_str()).collect();
+                known.push("inline_token");
+                return Err(invalid(format!("answers line {}: unknown key {key:?} (expected one of {})", index + 1, known.join(", "))));
+            }
+        }
+
+        // Walk the fields in order, like the interactive panel, so conditions see
+        // the answers before them.
+        for index in 0..self.fields.len() {
+            self.fields[index].value.clear();
+            if !self.is_active(index) {
+                continue;
+            }
+            let field = &mut self.fields[index];
+            let answer = answers.iter().rev().find(|(_, key, _)| *key == field.key);
+            match answer {
+                Some((line, key, value)) => {
+                    field.value = field.accept(value).map_err(|reason| invalid(format!("answers line {line} ({key}): {reason}")))?;
+                }
+                None if field.default.is_some() => {
+                    field.value = field.accept("").map_err(|reason| invalid(format!("default for {}: {reason}", field.key)))?;
+                }
+                None if field.kind == FieldKind::Token && !inline_token => {}
+                None => return Err(invalid(format!("answers file has no {}", field.key))),
+            }
+        }
+        Ok(inline_token)
+    }
+
+    /// Render `config.json`. Text, ids, and choices become strings (the way
+    /// `src/config.rs` reads `logging_channel_id`), yes/no answers become `true`
+    /// or `false`, and skipped fields become `null`. `discord_token` is the
+    /// placeholder unless `inline_token` is true. The `gateway_mode` choice also
+    /// sets `feature_flags.gateway`. Keys the gateway does not know are ignored
+    /// by it and kept for the Python side.
+    pub fn render_config(&self, inline_token: bool) -> String {
+        let mut lines = Vec::new();
+        for (index, field) in self.fields.iter().enumerate() {
+            let value = if field.kind == FieldKind::Token && !inline_token {
+                format!("\"{}\"", json_escape(TOKEN_PLACEHOLDER))
+            } else if !self.is_active(index) || field.value.is_empty() {
+                "null".to_string()
+            } else if field.kind == FieldKind::Boolean {
+                (field.value == "yes").to_string()
+            } else {
+                format!("\"{}\"", json_escape(&field.value))
+            };
+            lines.push(format!("  \"{}\": {}", json_escape(&field.key), value));
+        }
+        // `offline` keeps only the Discovery/ files in order; anything else talks to Discord.
+        let gateway = self.fields.iter().find(|field| field.key == "gateway_mode").is_none_or(|field| field.value != "offline");
+        lines.push(format!("  \"feature_flags\": {{\n    \"gateway\": {gateway}\n  }}"));
+        format!("{{\n{}\n}}\n", lines.join(",\n"))
+    }
+
     /// Render a human-friendly summary of the collected values. Sensitive
     /// entries are masked to avoid accidental exposure while still confirming
-    /// that the value was captured.
-    pub fn render_summary(&self) {
-        println!("\nSetup Panel Summary");
-        println!("-------------------");
-        println!("Session started at UNIX millis: {}", self.started_at);
-        for field in &self.fields {
-            if field.is_secret {
-                println!("{}: [hidden length {} characters]", field.label, field.value.len());
+    /// that the value was captured. Yes/no answers and choices show their
+    /// canonical spelling, whatever the operator typed.
+    pub fn render_summary<W: Write>(&self, output: &mut W, written_to: &Path, inline_token: bool) -> io::Result<()> {
+        writeln!(output, "\nSetup Panel Summary")?;
+        writeln!(output, "-------------------")?;
+        writeln!(output, "Session started at UNIX millis: {}", self.started_at)?;
+        for (index, field) in self.fields.iter().enumerate() {
+            if !self.is_active(index) {
+                writeln!(output, "{}: [skipped]", field.label)?;
+            } else if field.is_secret && field.value.is_empty() {
+                writeln!(output, "{}: [not given]", field.label)?;
+            } else if field.is_secret {
+                writeln!(output, "{}: [hidden length {} characters]", field.label, field.value.len())?;
             } else {
-                println!("{}: {}", field.label, field.value);
+                writeln!(output, "{}: {}", field.label, field.value)?;
+            }
+        }
+        writeln!(output, "Config written to: {}", written_to.display())?;
+        if inline_token {
+            writeln!(output, "The token is stored in that file; keep it private.")?;
+        } else {
+            writeln!(output, "The file uses {TOKEN_PLACEHOLDER}; export SQUIRE_DISCORD_TOKEN before starting the gateway.")?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the config, refusing to replace an existing file unless `force`.
+/// The text goes to a temporary sibling first and is then renamed into place,
+/// so a crash never leaves half a config behind. A file holding the real token
+/// is made readable by its owner only.
+fn write_config(path: &Path, contents: &str, force: bool, inline_token: bool) -> io::Result<()> {
+    if path.exists() && !force {
+        return Err(invalid(format!("{} already exists; pass --force to replace it", path.display())));
+    }
+    let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp = path.with_file_name(format!(".{file_name}.tmp-{}", process::id()));
+    fs::write(&temp, contents)?;
+    #[cfg(unix)]
+    if inline_token {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&temp, fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    let _ = inline_token;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+/// Escape a string for a JSON string literal.
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// An `io::Error` carrying a plain message.
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Command-line options.
+struct Options {
+    out: PathBuf,
+    from_file: Option<PathBuf>,
+    force: bool,
+}
+
+/// Read the flags. `None` means `--help` was printed to `output` and there is nothing to do.
+fn parse_options<W: Write>(args: &[String], output: &mut W) -> io::Result<Option<Options>> {
+    let mut options = Options { out: PathBuf::from("config.json"), from_file: None, force: false };
+    let mut index = 0;
+    while index < args.len() {
+        let flag = args[index].as_str();
+        let value = || args.get(index + 1).cloned().ok_or_else(|| invalid(format!("{flag} needs a value")));
+        match flag {
+            "--out" => {
+                options.out = PathBuf::from(value()?);
+                index += 1;
             }
+            "--from-file" => {
+                options.from_file = Some(PathBuf::from(value()?));
+                index += 1;
+            }
+            "--force" => options.force = true,
+            "--help" | "-h" => {
+                writeln!(output, "Usage: setup_panel [--out config.json] [--from-file answers.txt] [--force]")?;
+                return Ok(None);
+            }
+            other => return Err(invalid(format!("unknown argument {other:?} (try --help)"))),
         }
+        index += 1;
     }
+    Ok(Some(options))
 }
 
-/// Demonstration entrypoint showing how the setup panel could be used.
-pub fn main() -> io::Result<()> {
+/// The questions this panel asks, in order.
+fn squire_panel() -> SetupPanel {
     let mut panel = SetupPanel::new();
 
     // Example fields chosen to mirror typical bot configuration needs. The
-    // values are gathered interactively rather than hard-coded to keep secrets
+    // values are gathered at run time rather than hard-coded to keep secrets
     // out of the repository.
-    panel.add_field("Server display name", false);
-    panel.add_field("Moderator role ID", false);
-    panel.add_field("Discord bot token (will not be printed)", true);
-    panel.add_field("Logging channel ID", false);
-
-    panel.collect_inputs()?;
-    panel.render_summary();
-    Ok(())
+    panel.add_field("Server display name", "server_display_name", false);
+    panel.push_field(SetupField::new("Moderator role ID", "moderator_role_id", FieldKind::Snowflake));
+    panel.push_field(SetupField::new("Discord bot token (will not be printed)", "discord_token", FieldKind::Token));
+    let gateway_mode = FieldKind::Choice(vec!["send".to_string(), "offline".to_string()]);
+    panel.push_field(SetupField::new("Gateway mode (offline only tends the Discovery/ files)", "gateway_mode", gateway_mode).with_default("send"));
+    // The channel is only asked for when log forwarding is wanted.
+    let logging = panel.push_field(SetupField::new("Forward log lines to a Discord channel?", "logging_enabled", FieldKind::Boolean).with_default("yes"));
+    panel.push_field(SetupField::new("Logging channel ID", "logging_channel_id", FieldKind::Snowflake).depends_on(logging, "yes"));
+    panel
+}
+
+/// Run the panel with `args` (without the program name). Prompts read `input` and everything
+/// shown goes to `output`, so tests can drive it the way a terminal would.
+fn run<R: BufRead, W: Write>(args: &[String], input: &mut R, output: &mut W) -> io::Result<()> {
+    let Some(options) = parse_options(args, output)? else {
+        return Ok(());
+    };
+
+    let mut panel = squire_panel();
+    let inline_token = match &options.from_file {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|err| invalid(format!("cannot read {}: {err}", path.display())))?;
+            panel.apply_answers(&text)?
+        }
+        None => {
+            panel.collect_inputs(input, output)?;
+            SetupPanel::confirm_inline_token(input, output)?
+        }
+    };
+
+    write_config(&options.out, &panel.render_config(inline_token), options.force, inline_token)?;
+    panel.render_summary(output, &options.out, inline_token)
+}
+
+/// Entry point: run the panel and turn any error into a message and exit code 1.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(err) = run(&args, &mut io::stdin().lock(), &mut io::stdout()) {
+        eprintln!("setup panel: {err}");
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const TOKEN: &str = "MTIz.NDU2.Nzg5-_x";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("squire-setup-panel-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Run the panel with `typed` as the keyboard and return what it printed.
+    fn run_panel(arguments: &[String], typed: &str) -> io::Result<String> {
+        let mut output = Vec::new();
+        run(arguments, &mut Cursor::new(typed.as_bytes()), &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn snowflake_fields_apply_the_discord_id_rules() {
+        let kind = FieldKind::Snowflake;
+        assert_eq!(kind.normalize("80351110224678912"), Ok("80351110224678912".to_string()));
+        assert_eq!(kind.normalize("18446744073709551615"), Ok("18446744073709551615".to_string()));
+        for bad in ["", "1234", "012345678901234567", "8035111022467891x", "18446744073709551616", "123456789012345678901"] {
+            let err = kind.normalize(bad).unwrap_err();
+            assert!(err.starts_with("a Discord id "), "{bad:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn token_fields_check_the_shape_and_never_echo_the_value() {
+        assert_eq!(FieldKind::Token.normalize(TOKEN), Ok(TOKEN.to_string()));
+        for bad in ["", "abc", "a.b", "a..c", "a.b.c d", "secret.with.$ign"] {
+            let err = FieldKind::Token.normalize(bad).unwrap_err();
+            assert!(bad.is_empty() || !err.contains(bad), "{err}");
+        }
+    }
+
+    #[test]
+    fn booleans_and_choices_are_stored_in_canonical_spelling() {
+        assert_eq!(FieldKind::Boolean.normalize("Y"), Ok("yes".to_string()));
+        assert_eq!(FieldKind::Boolean.normalize("FALSE"), Ok("no".to_string()));
+        assert!(FieldKind::Boolean.normalize("maybe").is_err());
+        let choice = FieldKind::Choice(vec!["send".to_string(), "offline".to_string()]);
+        assert_eq!(choice.normalize("OFFLINE"), Ok("offline".to_string()));
+        assert_eq!(choice.normalize("later"), Err("pick one of: send, offline".to_string()));
+    }
+
+    #[test]
+    fn from_file_writes_a_config_with_the_token_placeholder() {
+        let dir = temp_dir("from-file");
+        let answers = dir.join("answers.txt");
+        let out = dir.join("config.json");
+        fs::write(&answers, "# scripted\nserver_display_name = Guild Hall\nmoderator_role_id=80351110224678912\nlogging_enabled=no\n").unwrap();
+
+        let printed = run_panel(&args(&["--from-file", answers.to_str().unwrap(), "--out", out.to_str().unwrap()]), "").unwrap();
+        let config = fs::read_to_string(&out).unwrap();
+        assert_eq!(
+            config,
+            "{\n  \"server_display_name\": \"Guild Hall\",\n  \"moderator_role_id\": \"80351110224678912\",\n  \
+             \"discord_token\": \"$ENV{SQUIRE_DISCORD_TOKEN}\",\n  \"gateway_mode\": \"send\",\n  \"logging_enabled\": false,\n  \
+             \"logging_channel_id\": null,\n  \"feature_flags\": {\n    \"gateway\": true\n  }\n}\n"
+        );
+        assert!(printed.contains("Logging channel ID: [skipped]"));
+        assert!(printed.contains("export SQUIRE_DISCORD_TOKEN"));
+
+        // The file exists now, so a second run needs --force.
+        let again = run_panel(&args(&["--from-file", answers.to_str().unwrap(), "--out", out.to_str().unwrap()]), "");
+        assert!(again.unwrap_err().to_string().contains("pass --force"));
+        run_panel(&args(&["--from-file", answers.to_str().unwrap(), "--out", out.to_str().unwrap(), "--force"]), "").unwrap();
+    }
+
+    #[test]
+    fn from_file_stops_at_a_bad_line_and_names_it() {
+        let dir = temp_dir("bad-answers");
+        let answers = dir.join("answers.txt");
+        let out = dir.join("config.json");
+        let run_with = |text: &str| {
+            fs::write(&answers, text).unwrap();
+            run_panel(&args(&["--from-file", answers.to_str().unwrap(), "--out", out.to_str().unwrap()]), "").unwrap_err().to_string()
+        };
+
+        let err = run_with("server_display_name=Hall\nmoderator_role_id=0123\n");
+        assert!(err.starts_with("answers line 2 (moderator_role_id): a Discord id"), "{err}");
+        let err = run_with("server_display_name=Hall\ncolour=blue\n");
+        assert!(err.starts_with("answers line 2: unknown key \"colour\""), "{err}");
+        let err = run_with("server_display_name=Hall\nmoderator_role_id=80351110224678912\ninline_token=yes\n");
+        assert_eq!(err, "answers file has no discord_token");
+        let err = run_with(&format!("server_display_name=Hall\nmoderator_role_id=80351110224678912\ndiscord_token={TOKEN}\ninline_token=maybe\n"));
+        assert_eq!(err, "answers line 4: inline_token must be yes or no");
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn interactive_run_retries_bad_ids_and_keeps_the_token_out_of_the_output() {
+        let dir = temp_dir("interactive");
+        let out = dir.join("config.json");
+        let typed = format!("Guild Hall\n123\n80351110224678912\n{TOKEN}\n\nyes\n80351110224678913\nyes\n");
+
+        let printed = run_panel(&args(&["--out", out.to_str().unwrap()]), &typed).unwrap();
+        assert!(printed.contains("Not accepted: a Discord id"), "{printed}");
+        assert!(!printed.contains(TOKEN));
+        assert!(printed.contains(&format!("[hidden length {} characters]", TOKEN.len())));
+        let config = fs::read_to_string(&out).unwrap();
+        assert!(config.contains(&format!("\"discord_token\": \"{TOKEN}\"")));
+        assert!(config.contains("\"logging_channel_id\": \"80351110224678913\""));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&out).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn interactive_run_gives_up_after_the_retries() {
+        let dir = temp_dir("give-up");
+        let out = dir.join("config.json");
+        let err = run_panel(&args(&["--out", out.to_str().unwrap()]), "Hall\n1\n2\n3\n4\n").unwrap_err();
+        assert!(err.to_string().contains("giving up after 3 retries"), "{err}");
+        let err = run_panel(&args(&["--out", out.to_str().unwrap()]), "Hall\n").unwrap_err();
+        assert_eq!(err.to_string(), "input ended before Moderator role ID was entered");
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn help_and_unknown_flags() {
+        assert!(run_panel(&args(&["--help"]), "").unwrap().starts_with("Usage: setup_panel"));
+        assert!(run_panel(&args(&["--colour"]), "").unwrap_err().to_string().contains("unknown argument"));
+        assert_eq!(run_panel(&args(&["--out"]), "").unwrap_err().to_string(), "--out needs a value");
+    }
+
+    /// A panel with a choice, a yes/no question and a field that depends on it.
+    fn conditional_panel() -> SetupPanel {
+        let mut panel = SetupPanel::new();
+        let colours = FieldKind::Choice(vec!["red".to_string(), "blue".to_string()]);
+        panel.push_field(SetupField::new("Colour", "colour", colours).with_default("blue"));
+        let logging = panel.push_field(SetupField::new("Log?", "log", FieldKind::Boolean));
+        panel.push_field(SetupField::new("Log channel", "log_channel", FieldKind::Snowflake).depends_on(logging, "y"));
+        panel
+    }
+
+    fn collect(panel: &mut SetupPanel, typed: &str) -> io::Result<String> {
+        let mut output = Vec::new();
+        panel.collect_inputs(&mut Cursor::new(typed.as_bytes()), &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn a_bad_choice_is_asked_again_and_an_empty_answer_takes_the_default() {
+        let mut panel = conditional_panel();
+        let printed = collect(&mut panel, "green\nRED\nno\n").unwrap();
+        assert!(printed.starts_with("Please enter Colour [red/blue] (default: blue):\n> Not accepted: pick one of: red, blue. Try again (3 tries left).\n"), "{printed}");
+        assert_eq!(panel.fields[0].value, "red");
+
+        let mut panel = conditional_panel();
+        collect(&mut panel, "\nno\n").unwrap();
+        assert_eq!(panel.fields[0].value, "blue");
+        let err = collect(&mut conditional_panel(), "\n\n\n\n\n").unwrap_err().to_string();
+        assert!(err.starts_with("Log?: ") && err.ends_with("giving up after 3 retries"), "no default for the boolean: {err}");
+    }
+
+    #[test]
+    fn a_dependent_field_is_only_asked_when_its_condition_holds() {
+        let mut panel = conditional_panel();
+        let printed = collect(&mut panel, "red\nFalse\n").unwrap();
+        assert!(!printed.contains("Log channel"), "{printed}");
+        assert_eq!((panel.fields[1].value.as_str(), panel.fields[2].value.as_str()), ("no", ""));
+
+        // `y` in the condition matches the stored `yes`, whatever the operator typed.
+        for typed in ["y", "YES", "true"] {
+            let mut panel = conditional_panel();
+            let printed = collect(&mut panel, &format!("red\n{typed}\n80351110224678912\n")).unwrap();
+            assert!(printed.contains("Please enter Log channel"), "{typed}: {printed}");
+            assert_eq!((panel.fields[1].value.as_str(), panel.fields[2].value.as_str()), ("yes", "80351110224678912"));
+        }
+
+        let mut summary = Vec::new();
+        let mut panel = conditional_panel();
+        collect(&mut panel, "BLUE\nn\n").unwrap();
+        panel.render_summary(&mut summary, Path::new("config.json"), false).unwrap();
+        let summary = String::from_utf8(summary).unwrap();
+        assert!(summary.contains("Colour: blue\nLog?: no\nLog channel: [skipped]\n"), "{summary}");
+    }
 }
@@ -0,0 +1,463 @@
+//! Slash-command definitions and the diff that decides whether Discord needs an update.
+//!
+//! Discord keeps a bot's global slash commands on its side. Registering them means sending the
+//! whole set in one `PUT /applications/<id>/commands` ("bulk overwrite"). Doing that on every
+//! start wastes a request and can hit Discord's daily command-create limit, so the gateway keeps
+//! the last set it synced in `Discovery/commands_synced.json` and only sends when the set it
+//! wants (`CommandRegistry`) differs from that copy.
+//!
+//! The cache file is a JSON array with one command per line. Each line is exactly the JSON this
+//! module writes for that command, so comparing two definitions is comparing two strings and no
+//! JSON parser is needed to read the cache back.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::atomic::atomic_write;
+use crate::message::json_escape;
+
+/// Longest command or option name Discord allows.
+pub const MAX_NAME_CHARS: usize = 32;
+/// Longest command, option, or choice description Discord allows.
+pub const MAX_DESCRIPTION_CHARS: usize = 100;
+/// Most options (or choices per option) Discord allows.
+pub const MAX_OPTIONS: usize = 25;
+
+/// Why a command definition was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// Names must be 1-32 characters of lowercase letters, digits, `-`, or `_`.
+    InvalidName(String),
+    /// Descriptions must be 1-100 characters.
+    InvalidDescription { name: String, chars: usize },
+    TooManyOptions { name: String, count: usize },
+    TooManyChoices { name: String, count: usize },
+    /// Discord rejects a required option listed after an optional one.
+    RequiredAfterOptional { name: String },
+    /// Two commands with the same name.
+    Duplicate(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::InvalidName(name) => write!(
+                f,
+                "name {:?} must be 1-{} lowercase letters, digits, '-' or '_'",
+                name, MAX_NAME_CHARS
+            ),
+            CommandError::InvalidDescription { name, chars } => write!(
+                f,
+                "description of {:?} is {} characters (must be 1-{})",
+                name, chars, MAX_DESCRIPTION_CHARS
+            ),
+            CommandError::TooManyOptions { name, count } => {
+                write!(f, "{:?} has {} options (limit {})", name, count, MAX_OPTIONS)
+            }
+            CommandError::TooManyChoices { name, count } => {
+                write!(f, "option {:?} has {} choices (limit {})", name, count, MAX_OPTIONS)
+            }
+            CommandError::RequiredAfterOptional { name } => {
+                write!(f, "required option {:?} comes after an optional one", name)
+            }
+            CommandError::Duplicate(name) => write!(f, "command {:?} is defined twice", name),
+        }
+    }
+}
+
+/// The value type of an option. The numbers are Discord's option type codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionType {
+    String,
+    Integer,
+    Boolean,
+    User,
+    Channel,
+    Role,
+    Number,
+}
+
+impl OptionType {
+    fn code(self) -> u8 {
+        match self {
+            OptionType::String => 3,
+            OptionType::Integer => 4,
+            OptionType::Boolean => 5,
+            OptionType::User => 6,
+            OptionType::Channel => 7,
+            OptionType::Role => 8,
+            OptionType::Number => 10,
+        }
+    }
+}
+
+/// One argument of a slash command, such as the `user` in `/ban user:@someone`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOption {
+    pub kind: OptionType,
+    pub name: String,
+    pub description: String,
+    pub required: bool,
+    /// Fixed `(label, value)` pairs the user picks from. Only used for string options here.
+    pub choices: Vec<(String, String)>,
+}
+
+impl CommandOption {
+    pub fn new(kind: OptionType, name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self { kind, name: name.into(), description: description.into(), required: false, choices: Vec::new() }
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn choice(mut self, label: impl Into<String>, value: impl Into<String>) -> Self {
+        self.choices.push((label.into(), value.into()));
+        self
+    }
+
+    fn validate(&self) -> Result<(), CommandError> {
+        validate_name(&self.name)?;
+        validate_description(&self.name, &self.description)?;
+        if self.choices.len() > MAX_OPTIONS {
+            return Err(CommandError::TooManyChoices { name: self.name.clone(), count: self.choices.len() });
+        }
+        for (label, _) in &self.choices {
+            validate_description(&self.name, label)?;
+        }
+        Ok(())
+    }
+
+    fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"type\":{},\"name\":\"{}\",\"description\":\"{}\",\"required\":{}",
+            self.kind.code(),
+            json_escape(&self.name),
+            json_escape(&self.description),
+            self.required
+        );
+        if !self.choices.is_empty() {
+            let choices = self
+                .choices
+                .iter()
+                .map(|(label, value)| {
+                    format!("{{\"name\":\"{}\",\"value\":\"{}\"}}", json_escape(label), json_escape(value))
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            json.push_str(&format!(",\"choices\":[{}]", choices));
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// A chat-input ("slash") command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlashCommand {
+    pub name: String,
+    pub description: String,
+    pub options: Vec<CommandOption>,
+}
+
+impl SlashCommand {
+    pub fn new(name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self { name: name.into(), description: description.into(), options: Vec::new() }
+    }
+
+    pub fn option(mut self, option: CommandOption) -> Self {
+        self.options.push(option);
+        self
+    }
+
+    /// Check the name, description, and options against Discord's rules.
+    pub fn validate(&self) -> Result<(), CommandError> {
+        validate_name(&self.name)?;
+        validate_description(&self.name, &self.description)?;
+        if self.options.len() > MAX_OPTIONS {
+            return Err(CommandError::TooManyOptions { name: self.name.clone(), count: self.options.len() });
+        }
+        let mut seen_optional = false;
+        for option in &self.options {
+            option.validate()?;
+            if option.required && seen_optional {
+                return Err(CommandError::RequiredAfterOptional { name: option.name.clone() });
+            }
+            seen_optional |= !option.required;
+        }
+        Ok(())
+    }
+
+    /// The JSON Discord expects for one command. `type` 1 means a chat-input command.
+    pub fn to_json(&self) -> String {
+        let options = self.options.iter().map(CommandOption::to_json).collect::<Vec<_>>().join(",");
+        format!(
+            "{{\"name\":\"{}\",\"type\":1,\"description\":\"{}\",\"options\":[{}]}}",
+            json_escape(&self.name),
+            json_escape(&self.description),
+            options
+        )
+    }
+}
+
+/// Names are 1-32 characters: lowercase ASCII letters, digits, `-`, and `_`.
+fn validate_name(name: &str) -> Result<(), CommandError> {
+    let valid_chars = name.chars().all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '-' || ch == '_');
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS || !valid_chars {
+        return Err(CommandError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+fn validate_description(name: &str, description: &str) -> Result<(), CommandError> {
+    let chars = description.chars().count();
+    if chars == 0 || chars > MAX_DESCRIPTION_CHARS {
+        return Err(CommandError::InvalidDescription { name: name.to_string(), chars });
+    }
+    Ok(())
+}
+
+/// How one command differs between the desired set and the last synced set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandChange {
+    Added(String),
+    Removed(String),
+    Modified(String),
+}
+
+impl fmt::Display for CommandChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandChange::Added(name) => write!(f, "+{}", name),
+            CommandChange::Removed(name) => write!(f, "-{}", name),
+            CommandChange::Modified(name) => write!(f, "~{}", name),
+        }
+    }
+}
+
+/// The set of commands the bot wants registered, kept in name order so the JSON is stable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, SlashCommand>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Validate and add a command. Names must be unique.
+    pub fn add(&mut self, command: SlashCommand) -> Result<(), CommandError> {
+        command.validate()?;
+        if self.commands.contains_key(&command.name) {
+            return Err(CommandError::Duplicate(command.name));
+        }
+        self.commands.insert(command.name.clone(), command);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Name to JSON for every command; the form the cache stores.
+    pub fn definitions(&self) -> BTreeMap<String, String> {
+        self.commands.iter().map(|(name, command)| (name.clone(), command.to_json())).collect()
+    }
+
+    /// The bulk-overwrite body: a JSON array of every command, one per line.
+    pub fn to_json(&self) -> String {
+        definitions_to_json(&self.definitions())
+    }
+
+    /// Compare with the last synced definitions. Empty means nothing to send.
+    pub fn diff(&self, synced: &BTreeMap<String, String>) -> Vec<CommandChange> {
+        let desired = self.definitions();
+        let mut changes = Vec::new();
+        for (name, json) in &desired {
+            match synced.get(name) {
+                None => changes.push(CommandChange::Added(name.clone())),
+                Some(old) if old != json => changes.push(CommandChange::Modified(name.clone())),
+                Some(_) => {}
+            }
+        }
+        for name in synced.keys() {
+            if !desired.contains_key(name) {
+                changes.push(CommandChange::Removed(name.clone()));
+            }
+        }
+        changes
+    }
+}
+
+fn definitions_to_json(definitions: &BTreeMap<String, String>) -> String {
+    if definitions.is_empty() {
+        return "[]\n".to_string();
+    }
+    let lines = definitions.values().cloned().collect::<Vec<_>>().join(",\n");
+    format!("[\n{}\n]\n", lines)
+}
+
+/// Read the cache written by `save_synced`. A missing file means nothing was synced yet.
+pub fn load_synced(path: &Path) -> Result<BTreeMap<String, String>, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(err) => return Err(format!("Unable to read {:?}: {}", path, err)),
+    };
+
+    let mut definitions = BTreeMap::new();
+    for (number, raw_line) in contents.lines().enumerate() {
+        let line = raw_line.trim().trim_end_matches(',');
+        if line.is_empty() || line == "[" || line == "]" || line == "[]" {
+            continue;
+        }
+        // Every command line starts with its name, because `SlashCommand::to_json` puts it first.
+        let name = line
+            .strip_prefix("{\"name\":\"")
+            .and_then(|rest| rest.split_once('"'))
+            .map(|(name, _)| name.to_string())
+            .ok_or_else(|| format!("{:?} line {} is not a command written by the gateway", path, number + 1))?;
+        definitions.insert(name, line.to_string());
+    }
+    Ok(definitions)
+}
+
+/// Write the synced definitions with `atomic_write`, so a crash never leaves half a cache.
+pub fn save_synced(path: &Path, registry: &CommandRegistry) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    atomic_write(path, registry.to_json().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("squire-commands-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn ban() -> SlashCommand {
+        SlashCommand::new("ban", "Ban a member")
+            .option(CommandOption::new(OptionType::User, "user", "Who to ban").required())
+            .option(CommandOption::new(OptionType::String, "reason", "Why").choice("Spam \"bot\"", "spam"))
+    }
+
+    fn registry(commands: Vec<SlashCommand>) -> CommandRegistry {
+        let mut registry = CommandRegistry::new();
+        for command in commands {
+            registry.add(command).unwrap();
+        }
+        registry
+    }
+
+    #[test]
+    fn unchanged_commands_have_no_diff() {
+        let registry = registry(vec![ban(), SlashCommand::new("ping", "Check the bot is alive")]);
+        assert!(registry.diff(&registry.definitions()).is_empty());
+        assert_eq!(CommandRegistry::new().diff(&BTreeMap::new()), Vec::new());
+    }
+
+    #[test]
+    fn each_command_is_reported_as_added_removed_or_modified() {
+        let synced = registry(vec![ban(), SlashCommand::new("kick", "Kick a member")]).definitions();
+        let with_option = ban().option(CommandOption::new(OptionType::Integer, "days", "Messages to delete"));
+        let desired = registry(vec![with_option, SlashCommand::new("ping", "Check the bot is alive")]);
+
+        let changes = desired.diff(&synced);
+        assert_eq!(
+            changes,
+            vec![
+                CommandChange::Modified("ban".to_string()),
+                CommandChange::Added("ping".to_string()),
+                CommandChange::Removed("kick".to_string()),
+            ]
+        );
+        assert_eq!(changes.iter().map(ToString::to_string).collect::<Vec<_>>(), ["~ban", "+ping", "-kick"]);
+    }
+
+    #[test]
+    fn cache_round_trips_and_a_missing_cache_is_empty() {
+        let dir = temp_dir("cache");
+        let path = dir.join("Discovery").join("commands_synced.json");
+        assert_eq!(load_synced(&path), Ok(BTreeMap::new()));
+
+        let registry = registry(vec![ban(), SlashCommand::new("ping", "Check the bot is alive")]);
+        save_synced(&path, &registry).unwrap();
+        assert_eq!(load_synced(&path), Ok(registry.definitions()));
+        assert!(registry.diff(&load_synced(&path).unwrap()).is_empty());
+
+        save_synced(&path, &CommandRegistry::new()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[]\n");
+        assert_eq!(load_synced(&path), Ok(BTreeMap::new()));
+
+        fs::write(&path, "[\n{\"type\":1}\n]\n").unwrap();
+        assert!(load_synced(&path).unwrap_err().contains("line 2"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn bulk_overwrite_body_is_valid_json_in_name_order() {
+        let registry = registry(vec![SlashCommand::new("ping", "Check the bot is alive"), ban()]);
+        let body = crate::json::parse(&registry.to_json()).unwrap();
+        let commands = body.as_array().unwrap();
+        let names = commands.iter().map(|command| command.get("name").and_then(|name| name.as_str()).unwrap()).collect::<Vec<_>>();
+        assert_eq!(names, ["ban", "ping"]);
+        let options = commands[0].get("options").and_then(|options| options.as_array()).unwrap();
+        assert_eq!(options[0].get("type").and_then(|kind| kind.as_f64()), Some(6.0));
+        assert_eq!(options[0].get("required").and_then(|required| required.as_bool()), Some(true));
+        let choice = &options[1].get("choices").and_then(|choices| choices.as_array()).unwrap()[0];
+        assert_eq!(choice.get("name").and_then(|name| name.as_str()), Some("Spam \"bot\""));
+    }
+
+    #[test]
+    fn names_and_descriptions_outside_discords_rules_are_refused() {
+        for name in ["", "Ban", "ban user", "bän", &"a".repeat(MAX_NAME_CHARS + 1)] {
+            assert_eq!(SlashCommand::new(name, "ok").validate(), Err(CommandError::InvalidName(name.to_string())), "{name:?}");
+        }
+        for name in ["a", "ban-user_2", &"a".repeat(MAX_NAME_CHARS)] {
+            assert_eq!(SlashCommand::new(name, "ok").validate(), Ok(()), "{name:?}");
+        }
+
+        assert_eq!(SlashCommand::new("ping", "d".repeat(MAX_DESCRIPTION_CHARS)).validate(), Ok(()));
+        assert_eq!(
+            SlashCommand::new("ping", "d".repeat(MAX_DESCRIPTION_CHARS + 1)).validate(),
+            Err(CommandError::InvalidDescription { name: "ping".to_string(), chars: MAX_DESCRIPTION_CHARS + 1 })
+        );
+        assert_eq!(
+            SlashCommand::new("ping", "").validate(),
+            Err(CommandError::InvalidDescription { name: "ping".to_string(), chars: 0 })
+        );
+        let bad_option = SlashCommand::new("ban", "Ban").option(CommandOption::new(OptionType::User, "User", "Who"));
+        assert_eq!(bad_option.validate(), Err(CommandError::InvalidName("User".to_string())));
+    }
+
+    #[test]
+    fn registry_refuses_duplicates_and_misordered_options() {
+        let mut registry = registry(vec![ban()]);
+        assert_eq!(registry.add(ban()), Err(CommandError::Duplicate("ban".to_string())));
+        assert_eq!(registry.len(), 1);
+
+        let misordered = SlashCommand::new("warn", "Warn a member")
+            .option(CommandOption::new(OptionType::String, "reason", "Why"))
+            .option(CommandOption::new(OptionType::User, "user", "Who").required());
+        assert_eq!(registry.add(misordered), Err(CommandError::RequiredAfterOptional { name: "user".to_string() }));
+        let too_many = (0..=MAX_OPTIONS).fold(SlashCommand::new("many", "Many"), |command, n| {
+            command.option(CommandOption::new(OptionType::String, format!("o{n}"), "opt"))
+        });
+        assert_eq!(registry.add(too_many), Err(CommandError::TooManyOptions { name: "many".to_string(), count: MAX_OPTIONS + 1 }));
+    }
+}
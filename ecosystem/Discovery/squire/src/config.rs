@@ -0,0 +1,709 @@
+//! The parts of Squire's `config.json` the Rust gateway needs at startup.
+//!
+//! The file is shared with Python (see `python/config_loader.py`), so this reader only looks at
+//! the keys it understands and ignores the rest:
+//! - `discord_token`: the bot token. Keep it out of the file by writing the placeholder
+//!   `"$ENV{SQUIRE_DISCORD_TOKEN}"`; the named environment variable is read at load time.
+//! - `logging_channel_id`: optional numeric channel that receives forwarded log lines. Older
+//!   config files without it still load.
+//! - `database_path`: optional folder for Squire's own data files, such as the XP log
+//!   (`src/storage.rs`). Relative paths are taken from the folder of the file that sets it.
+//! - `feature_flags`: optional object of switches. A value is either `true`/`false` or a staged
+//!   rollout such as `{"enabled": true, "percentage": 20}` (on for about 20% of guilds).
+//!   `"gateway": false` starts the binary in a mode that only keeps the `Discovery/` files in
+//!   order and never talks to Discord.
+//!
+//! A deployment can keep the shared settings in one file and only the differences in another:
+//! a top-level `"include": "base.json"` loads that file first (relative to the including file),
+//! then the including file's keys replace the base's. `feature_flags` is the exception: the two
+//! objects are merged flag by flag, so `prod.json` can switch one flag without repeating the
+//! rest. An included file may include another, up to `MAX_INCLUDE_DEPTH` steps; a longer chain
+//! or a file that includes itself again is an error that prints the chain.
+//!
+//! A file ending in `.toml` is read by `config_toml` instead of the JSON parser. It understands the
+//! same keys (with `feature_flags` as a `[feature_flags]` table of `true`/`false`), and the result
+//! is checked exactly like JSON, so the two formats can even include each other.
+//!
+//! Problems are collected into one `AppError` instead of stopping at the first, so an operator
+//! fixes the whole file in one go.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config_toml;
+use crate::runtime::{EnvSource, ProcessEnv};
+use crate::sha256::{sha256, to_hex};
+use crate::json::{self, JsonValue};
+
+/// How many `include` steps one file may take: `a -> b -> c -> d -> e` is the longest chain.
+pub const MAX_INCLUDE_DEPTH: usize = 4;
+
+/// Every problem found while starting up, reported together.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppError {
+    pub problems: Vec<String>,
+}
+
+impl AppError {
+    pub fn new(problem: impl Into<String>) -> Self {
+        Self { problems: vec![problem.into()] }
+    }
+
+    pub fn push(&mut self, problem: impl Into<String>) {
+        self.problems.push(problem.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "startup failed with {} problem(s):", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Startup settings read from `config.json`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Config {
+    /// Where the file was read from (the last one for `load_layers`).
+    pub path: PathBuf,
+    /// SHA-256 of the file as read, in lowercase hex. Print it so operators can compare it with
+    /// the copy they reviewed. With includes or layers it covers every file in `layers` order,
+    /// the same value as `cat base.json prod.json | sha256sum`.
+    pub fingerprint: String,
+    /// Every file that was read, bases first. Just `[path]` when nothing is included.
+    pub layers: Vec<PathBuf>,
+    /// Bot token after `$ENV{...}` expansion. `None` when the file has none or the variable is
+    /// unset.
+    pub discord_token: Option<String>,
+    /// Default channel for forwarded log lines.
+    pub logging_channel_id: Option<String>,
+    /// Folder for data files such as `xp.log`, already joined onto the config file's folder.
+    pub database_path: Option<PathBuf>,
+    /// Switches from `feature_flags`.
+    pub feature_flags: FeatureFlags,
+}
+
+/// One entry of `feature_flags`. A plain `true` is `{enabled: true, percentage: 100}`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeatureFlag {
+    pub enabled: bool,
+    /// Share of ids (0 to 100) that get the feature while `enabled` is true.
+    pub percentage: u8,
+}
+
+/// The evaluated `feature_flags` table with typed lookups. Flags that are not listed are off.
+///
+/// Rollouts are decided per id (usually a guild id): the id and the flag name are hashed with
+/// SHA-256 into a bucket from 0 to 99, and the id gets the feature when its bucket is below the
+/// percentage. The same id always lands in the same bucket, so raising 20% to 50% keeps the
+/// first 20% and adds more; it never reshuffles who has the feature.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeatureFlags {
+    flags: BTreeMap<String, FeatureFlag>,
+}
+
+impl FeatureFlags {
+    /// Read the `feature_flags` object, adding one message per bad entry to `errors`.
+    pub(crate) fn parse(fields: &[(String, JsonValue)], errors: &mut AppError) -> FeatureFlags {
+        let mut flags = BTreeMap::new();
+        for (name, value) in fields {
+            match parse_flag(value) {
+                Ok(flag) => {
+                    flags.insert(name.clone(), flag);
+                }
+                Err(problem) => errors.push(format!("feature_flags.{}: {}", name, problem)),
+            }
+        }
+        FeatureFlags { flags }
+    }
+
+    pub fn get(&self, name: &str) -> Option<FeatureFlag> {
+        self.flags.get(name).copied()
+    }
+
+    /// On for everyone: enabled at 100%. A partial rollout needs an id, see `is_enabled_for`.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.percentage(name) == 100
+    }
+
+    /// Whether `stable_id` (a guild or user id) falls inside the flag's rollout.
+    pub fn is_enabled_for(&self, name: &str, stable_id: &str) -> bool {
+        let percentage = self.percentage(name);
+        percentage == 100 || (percentage > 0 && rollout_bucket(name, stable_id) < percentage)
+    }
+
+    /// Share of ids that get the feature: 0 when the flag is off or not listed.
+    pub fn percentage(&self, name: &str) -> u8 {
+        match self.get(name) {
+            Some(flag) if flag.enabled => flag.percentage,
+            _ => 0,
+        }
+    }
+
+    /// Every listed flag in name order, for printing.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, FeatureFlag)> {
+        self.flags.iter().map(|(name, flag)| (name.as_str(), *flag))
+    }
+
+    /// List `name` as `flag`, replacing what was there.
+    pub fn insert(&mut self, name: impl Into<String>, flag: FeatureFlag) {
+        self.flags.insert(name.into(), flag);
+    }
+
+    /// Stop listing `name`, so it falls back to whatever lies underneath.
+    pub fn remove(&mut self, name: &str) -> Option<FeatureFlag> {
+        self.flags.remove(name)
+    }
+
+    /// These flags with `overrides` laid on top: a flag listed in both takes the override's
+    /// value, and flags listed in only one are kept as they are.
+    pub fn overlaid(&self, overrides: &FeatureFlags) -> FeatureFlags {
+        let mut flags = self.flags.clone();
+        flags.extend(overrides.iter().map(|(name, flag)| (name.to_string(), flag)));
+        FeatureFlags { flags }
+    }
+}
+
+/// `true`, `false`, or `{"enabled": bool, "percentage": 0-100}` (percentage defaults to 100).
+pub(crate) fn parse_flag(value: &JsonValue) -> Result<FeatureFlag, String> {
+    if let Some(enabled) = value.as_bool() {
+        return Ok(FeatureFlag { enabled, percentage: 100 });
+    }
+    let JsonValue::Object(fields) = value else {
+        return Err("must be true, false, or {\"enabled\": true, \"percentage\": 20}".to_string());
+    };
+    if let Some((unknown, _)) = fields.iter().find(|(key, _)| key != "enabled" && key != "percentage") {
+        // A typo such as "percentge" would otherwise quietly mean 100%.
+        return Err(format!("unknown key {:?}; only \"enabled\" and \"percentage\" are allowed", unknown));
+    }
+    let enabled = value
+        .get("enabled")
+        .and_then(JsonValue::as_bool)
+        .ok_or("\"enabled\" must be true or false")?;
+    let percentage = match value.get("percentage") {
+        None => 100,
+        Some(number) => match number.as_f64() {
+            Some(number) if number.fract() == 0.0 && (0.0..=100.0).contains(&number) => number as u8,
+            _ => return Err("\"percentage\" must be a whole number from 0 to 100".to_string()),
+        },
+    };
+    Ok(FeatureFlag { enabled, percentage })
+}
+
+/// Bucket 0..100 for `stable_id` under flag `name`. The name is part of the hash so each flag
+/// picks a different slice of ids.
+fn rollout_bucket(name: &str, stable_id: &str) -> u8 {
+    let digest = sha256(format!("{name}:{stable_id}").as_bytes());
+    let mut first = [0u8; 8];
+    first.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(first) % 100) as u8
+}
+
+// Hand-written so `{:?}` never prints the token.
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("path", &self.path)
+            .field("fingerprint", &self.fingerprint)
+            .field("layers", &self.layers)
+            .field("discord_token", &self.discord_token.as_ref().map(|_| "<redacted>"))
+            .field("logging_channel_id", &self.logging_channel_id)
+            .field("database_path", &self.database_path)
+            .field("feature_flags", &self.feature_flags)
+            .finish()
+    }
+}
+
+impl Config {
+    /// Read and check `path`, after the files it `include`s. Every problem in every layer is listed
+    /// in the error.
+    pub fn load(path: &Path) -> Result<Config, AppError> {
+        Self::load_layers(&[path])
+    }
+
+    /// Read several files as layers, for example `base.json`, `prod.json`, `host.json` named on
+    /// the command line. Each later file overrides the earlier ones the same way an including file
+    /// overrides its `include`, and each file may still have its own `include`.
+    pub fn load_layers<P: AsRef<Path>>(paths: &[P]) -> Result<Config, AppError> {
+        Self::load_layers_from(paths, &ProcessEnv)
+    }
+
+    /// `load_layers` with `$ENV{...}` placeholders read from `env`.
+    pub fn load_layers_from<P: AsRef<Path>>(paths: &[P], env: &dyn EnvSource) -> Result<Config, AppError> {
+        let Some(last) = paths.last() else {
+            return Err(AppError::new("no config file given"));
+        };
+        let mut layers = Layers::default();
+        for path in paths {
+            layers.read(path.as_ref(), &mut Vec::new())?;
+        }
+        layers.into_config(last.as_ref(), env)
+    }
+
+    /// Unlike `FeatureFlags::is_enabled`, a flag missing from the file counts as on here, so
+    /// older configs keep the gateway running. Listed flags must be fully on (100%).
+    pub fn feature_enabled(&self, name: &str) -> bool {
+        self.feature_flags.get(name).is_none() || self.feature_flags.is_enabled(name)
+    }
+
+    /// Whether the gateway may talk to Discord (`feature_flags.gateway`, on by default).
+    pub fn gateway_enabled(&self) -> bool {
+        self.feature_enabled("gateway")
+    }
+}
+
+/// One top-level key after merging, with the file(s) it came from. Only a merged
+/// `feature_flags` has more than one.
+struct LayeredField {
+    key: String,
+    value: JsonValue,
+    origins: Vec<PathBuf>,
+}
+
+impl LayeredField {
+    /// Prefix for this field's problems, e.g. `base.json + prod.json: `.
+    fn prefix(&self, problem: &str) -> String {
+        let files: Vec<String> = self.origins.iter().map(|origin| origin.display().to_string()).collect();
+        format!("{}: {}", files.join(" + "), problem)
+    }
+}
+
+/// Every file read so far, merged in load order (bases first).
+#[derive(Default)]
+struct Layers {
+    fields: Vec<LayeredField>,
+    files: Vec<PathBuf>,
+    /// The files' bytes one after another, for the fingerprint.
+    bytes: Vec<u8>,
+}
+
+impl Layers {
+    /// Read `path`, then its `include` chain underneath it. `chain` holds the files that included
+    /// this one as (canonical path, path as written), to spot cycles and over-deep chains.
+    fn read(&mut self, path: &Path, chain: &mut Vec<(PathBuf, PathBuf)>) -> Result<(), AppError> {
+        let bytes = fs::read(path).map_err(|err| AppError::new(format!("Unable to read {:?}: {}", path, err)))?;
+        // The canonical path makes `./base.json` and `../conf/base.json` count as the same file.
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if chain.iter().any(|(seen, _)| *seen == canonical) {
+            return Err(AppError::new(format!("include cycle: {}", show_chain(chain, path))));
+        }
+        if chain.len() > MAX_INCLUDE_DEPTH {
+            return Err(AppError::new(format!(
+                "include chain is deeper than {}: {}",
+                MAX_INCLUDE_DEPTH,
+                show_chain(chain, path)
+            )));
+        }
+        let text = String::from_utf8(bytes).map_err(|_| AppError::new(format!("{:?} is not UTF-8", path)))?;
+        let document = if is_toml(path) { config_toml::parse(&text) } else { json::parse(&text) };
+        let document = document.map_err(|err| AppError::new(format!("{:?}: {}", path, err)))?;
+        let JsonValue::Object(fields) = document else {
+            return Err(AppError::new(format!("{:?} must hold a JSON object", path)));
+        };
+
+        match fields.iter().find(|(key, _)| key == "include").map(|(_, value)| value) {
+            None | Some(JsonValue::Null) => {}
+            Some(JsonValue::String(base)) if !base.is_empty() => {
+                // Relative to the including file, so a nested `prod/squire.json` can say `"../base.json"`.
+                let base = path.parent().unwrap_or(Path::new("")).join(base);
+                chain.push((canonical, path.to_path_buf()));
+                let result = self.read(&base, chain);
+                chain.pop();
+                result?;
+            }
+            Some(_) => return Err(AppError::new(format!("{}: include must be a file path", path.display()))),
+        }
+
+        self.bytes.extend_from_slice(text.as_bytes());
+        self.files.push(path.to_path_buf());
+        self.overlay(fields, path);
+        Ok(())
+    }
+
+    /// Lay one file's keys over what is already there: a key replaces the earlier value, except
+    /// that two `feature_flags` objects are merged flag by flag.
+    fn overlay(&mut self, fields: Vec<(String, JsonValue)>, origin: &Path) {
+        for (key, value) in fields {
+            if key == "include" {
+                continue;
+            }
+            let existing = self.fields.iter_mut().find(|field| field.key == key);
+            match (existing, value) {
+                (Some(field), JsonValue::Object(flags)) if key == "feature_flags" && matches!(field.value, JsonValue::Object(_)) => {
+                    let JsonValue::Object(base_flags) = &mut field.value else { continue };
+                    for (name, flag) in flags {
+                        match base_flags.iter_mut().find(|(base_name, _)| *base_name == name) {
+                            Some(slot) => slot.1 = flag,
+                            None => base_flags.push((name, flag)),
+                        }
+                    }
+                    if !field.origins.iter().any(|seen| seen == origin) {
+                        field.origins.push(origin.to_path_buf());
+                    }
+                }
+                (Some(field), value) => {
+                    field.value = value;
+                    field.origins = vec![origin.to_path_buf()];
+                }
+                (None, value) => self.fields.push(LayeredField { key, value, origins: vec![origin.to_path_buf()] }),
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&LayeredField> {
+        self.fields.iter().find(|field| field.key == key)
+    }
+
+    /// Check the merged fields. Each problem names the file that set the field.
+    fn into_config(self, path: &Path, env: &dyn EnvSource) -> Result<Config, AppError> {
+        let mut errors = AppError::default();
+
+        let discord_token = match self.get("discord_token") {
+            None => None,
+            Some(field) => match &field.value {
+                JsonValue::Null => None,
+                JsonValue::String(raw) => match expand_env(raw, env) {
+                    Ok(token) => token.filter(|token| !token.is_empty()),
+                    Err(problem) => {
+                        errors.push(field.prefix(&format!("discord_token: {}", problem)));
+                        None
+                    }
+                },
+                _ => {
+                    errors.push(field.prefix("discord_token must be a string"));
+                    None
+                }
+            },
+        };
+
+        let logging_channel_id = match self.get("logging_channel_id") {
+            None => None,
+            Some(field) => match &field.value {
+                JsonValue::Null => None,
+                JsonValue::String(id) if !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()) => Some(id.clone()),
+                _ => {
+                    // Discord ids exceed what a JSON number can hold exactly, so only strings are accepted.
+                    errors.push(field.prefix(
+                        "logging_channel_id must be a numeric id written as a string, e.g. \"123456789012345678\"",
+                    ));
+                    None
+                }
+            },
+        };
+
+        let database_path = match self.get("database_path") {
+            None => None,
+            Some(field) => match &field.value {
+                JsonValue::Null => None,
+                JsonValue::String(folder) if !folder.is_empty() => {
+                    // A relative path means "next to the config file that says so", not "wherever
+                    // the service started". With layers, that is the file that set the value.
+                    let origin = field.origins.last().map(PathBuf::as_path).unwrap_or(path);
+                    Some(origin.parent().unwrap_or(Path::new("")).join(folder))
+                }
+                _ => {
+                    errors.push(field.prefix("database_path must be a non-empty folder path"));
+                    None
+                }
+            },
+        };
+
+        let feature_flags = match self.get("feature_flags") {
+            None => FeatureFlags::default(),
+            Some(field) => match &field.value {
+                JsonValue::Null => FeatureFlags::default(),
+                JsonValue::Object(fields) => {
+                    let mut flag_errors = AppError::default();
+                    let flags = FeatureFlags::parse(fields, &mut flag_errors);
+                    for problem in flag_errors.problems {
+                        errors.push(field.prefix(&problem));
+                    }
+                    flags
+                }
+                _ => {
+                    errors.push(field.prefix("feature_flags must be an object of switches"));
+                    FeatureFlags::default()
+                }
+            },
+        };
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Config {
+            path: path.to_path_buf(),
+            fingerprint: to_hex(&sha256(&self.bytes)),
+            layers: self.files,
+            discord_token,
+            logging_channel_id,
+            database_path,
+            feature_flags,
+        })
+    }
+}
+
+/// Files ending in `.toml` (in any case) are TOML; everything else is JSON.
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml"))
+}
+
+/// `a.json -> base.json -> a.json`: the files that included each other, then `next`.
+fn show_chain(chain: &[(PathBuf, PathBuf)], next: &Path) -> String {
+    let mut shown: Vec<String> = chain.iter().map(|(_, written)| written.display().to_string()).collect();
+    shown.push(next.display().to_string());
+    shown.join(" -> ")
+}
+
+/// SHA-256 of a file as lowercase hex, the same value `sha256sum` prints.
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    Ok(to_hex(&sha256(&fs::read(path)?)))
+}
+
+/// Replace a whole-value `$ENV{NAME}` placeholder with the variable's value. Other strings are
+/// returned unchanged. An unset variable is `Ok(None)`; a malformed placeholder is an error.
+fn expand_env(raw: &str, env: &dyn EnvSource) -> Result<Option<String>, String> {
+    let Some(rest) = raw.strip_prefix("$ENV{") else {
+        return Ok(Some(raw.to_string()));
+    };
+    let name = rest
+        .strip_suffix('}')
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| format!("{:?} is not a valid $ENV{{NAME}} placeholder", raw))?;
+    Ok(env.var(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::MapEnv;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("squire-config-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// The flags of `{"feature_flags": <flags_json>}`, or the problems found.
+    fn flags(flags_json: &str) -> Result<FeatureFlags, AppError> {
+        let JsonValue::Object(fields) = json::parse(flags_json).unwrap() else { panic!("{flags_json} is not an object") };
+        let mut errors = AppError::default();
+        let flags = FeatureFlags::parse(&fields, &mut errors);
+        if errors.is_empty() { Ok(flags) } else { Err(errors) }
+    }
+
+    #[test]
+    fn plain_boolean_flags_are_all_or_nothing() {
+        let flags = flags(r#"{"xp": true, "gateway": false}"#).unwrap();
+        assert!(flags.is_enabled("xp") && flags.is_enabled_for("xp", "123"));
+        assert!(!flags.is_enabled("gateway") && !flags.is_enabled_for("gateway", "123"));
+        assert!(!flags.is_enabled("unlisted"));
+        assert_eq!((flags.percentage("xp"), flags.percentage("gateway"), flags.percentage("unlisted")), (100, 0, 0));
+        assert_eq!(flags.get("gateway"), Some(FeatureFlag { enabled: false, percentage: 100 }));
+    }
+
+    #[test]
+    fn rollout_buckets_are_stable_and_roughly_the_asked_share() {
+        let flags = flags(r#"{"new_xp": {"enabled": true, "percentage": 20}, "wider": {"enabled": true, "percentage": 50}}"#).unwrap();
+        let ids: Vec<String> = (0..1000u64).map(|n| (100_000_000_000_000_000 + n * 7919).to_string()).collect();
+        let on: Vec<&String> = ids.iter().filter(|id| flags.is_enabled_for("new_xp", id)).collect();
+        assert!((150..=250).contains(&on.len()), "{} of 1000 ids", on.len());
+        assert!(ids.iter().all(|id| flags.is_enabled_for("new_xp", id) == flags.is_enabled_for("new_xp", id)));
+        assert!(!flags.is_enabled("new_xp"), "a partial rollout is not on for everyone");
+
+        // Raising the share keeps everyone who already had the feature.
+        let mut raised = flags.clone();
+        raised.insert("new_xp", FeatureFlag { enabled: true, percentage: 60 });
+        assert!(on.iter().all(|id| raised.is_enabled_for("new_xp", id)));
+        // The flag name is part of the hash, so two rollouts pick different ids.
+        let first_wider: Vec<bool> = ids.iter().take(50).map(|id| rollout_bucket("wider", id) < 20).collect();
+        let first_new: Vec<bool> = ids.iter().take(50).map(|id| rollout_bucket("new_xp", id) < 20).collect();
+        assert_ne!(first_wider, first_new);
+
+        let off = self::flags(r#"{"new_xp": {"enabled": false, "percentage": 20}}"#).unwrap();
+        assert!(ids.iter().all(|id| !off.is_enabled_for("new_xp", id)));
+    }
+
+    #[test]
+    fn bad_flag_values_are_rejected_at_load_time_each_with_its_name() {
+        let err = flags(r#"{"a": {"enabled": true, "percentage": 101}, "b": {"enabled": true, "percentage": 2.5}, "c": {"enabled": true, "percentge": 5}, "d": "yes", "e": {"percentage": 5}}"#)
+            .unwrap_err();
+        assert_eq!(err.problems.len(), 5, "{err}");
+        assert!(err.problems[0].starts_with("feature_flags.a: \"percentage\" must be a whole number from 0 to 100"));
+        assert!(err.problems[1].starts_with("feature_flags.b: "));
+        assert!(err.problems[2].contains("unknown key \"percentge\""));
+        assert!(err.problems[3].starts_with("feature_flags.d: must be true, false"));
+        assert!(err.problems[4].contains("\"enabled\" must be true or false"));
+        assert!(flags(r#"{"edge": {"enabled": true, "percentage": 0}, "full": {"enabled": true, "percentage": 100}}"#).is_ok());
+
+        let dir = temp_dir("bad-flags");
+        let path = dir.join("config.json");
+        fs::write(&path, r#"{"feature_flags": {"gateway": {"enabled": true, "percentage": 150}}}"#).unwrap();
+        let err = Config::load(&path).unwrap_err();
+        assert!(err.problems.iter().any(|problem| problem.ends_with("config.json: feature_flags.gateway: \"percentage\" must be a whole number from 0 to 100")), "{err}");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn an_unlisted_gateway_flag_keeps_the_gateway_on() {
+        let dir = temp_dir("gateway-default");
+        let path = dir.join("config.json");
+        fs::write(&path, r#"{"logging_channel_id": "123"}"#).unwrap();
+        let config = Config::load(&path).unwrap();
+        assert!(config.gateway_enabled());
+        assert!(!config.feature_flags.is_enabled("gateway"), "the typed lookup still says unlisted is off");
+
+        fs::write(&path, r#"{"feature_flags": {"gateway": {"enabled": true, "percentage": 50}}}"#).unwrap();
+        assert!(!Config::load(&path).unwrap().gateway_enabled(), "the gateway needs a full rollout");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn an_included_base_is_overridden_field_by_field() {
+        let dir = temp_dir("include");
+        let base = r#"{"logging_channel_id": "111", "database_path": "data"}"#;
+        let prod = r#"{"include": "base.json", "logging_channel_id": "222"}"#;
+        fs::write(dir.join("base.json"), base).unwrap();
+        fs::write(dir.join("prod.json"), prod).unwrap();
+
+        let config = Config::load(&dir.join("prod.json")).unwrap();
+        assert_eq!(config.logging_channel_id.as_deref(), Some("222"));
+        assert_eq!(config.database_path, Some(dir.join("data")), "kept from the base");
+        assert_eq!(config.layers, [dir.join("base.json"), dir.join("prod.json")]);
+        assert_eq!(config.path, dir.join("prod.json"));
+        assert_eq!(config.fingerprint, to_hex(&sha256(format!("{base}{prod}").as_bytes())));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn feature_flags_merge_flag_by_flag() {
+        let dir = temp_dir("include-flags");
+        fs::write(dir.join("base.json"), r#"{"feature_flags": {"gateway": true, "xp": true, "new_xp": {"enabled": true, "percentage": 10}}}"#).unwrap();
+        fs::write(dir.join("prod.json"), r#"{"include": "base.json", "feature_flags": {"xp": false, "new_xp": {"enabled": true, "percentage": 50}, "webhooks": true}}"#).unwrap();
+        let flags = Config::load(&dir.join("prod.json")).unwrap().feature_flags;
+        assert!(flags.is_enabled("gateway"), "untouched by prod.json");
+        assert!(!flags.is_enabled("xp"));
+        assert_eq!(flags.percentage("new_xp"), 50);
+        assert!(flags.is_enabled("webhooks"));
+
+        // A bad flag in the merged table names both files.
+        fs::write(dir.join("prod.json"), r#"{"include": "base.json", "feature_flags": {"xp": "maybe"}}"#).unwrap();
+        let err = Config::load(&dir.join("prod.json")).unwrap_err();
+        assert!(err.problems[0].contains("base.json + ") && err.problems[0].contains("prod.json: feature_flags.xp"), "{err}");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cycles_and_deep_chains_print_the_chain() {
+        let dir = temp_dir("include-cycle");
+        fs::write(dir.join("a.json"), r#"{"include": "b.json"}"#).unwrap();
+        fs::write(dir.join("b.json"), r#"{"include": "./a.json"}"#).unwrap();
+        let err = Config::load(&dir.join("a.json")).unwrap_err();
+        let expected = format!("include cycle: {} -> {} -> {}", dir.join("a.json").display(), dir.join("b.json").display(), dir.join("./a.json").display());
+        assert_eq!(err.problems, [expected]);
+
+        // f -> e -> d -> c -> b -> a is five steps, one more than allowed.
+        fs::write(dir.join("a.json"), "{}").unwrap();
+        for (file, base) in [("b", "a"), ("c", "b"), ("d", "c"), ("e", "d"), ("f", "e")] {
+            fs::write(dir.join(format!("{file}.json")), format!("{{\"include\": \"{base}.json\"}}")).unwrap();
+        }
+        assert_eq!(Config::load(&dir.join("e.json")).unwrap().layers.len(), MAX_INCLUDE_DEPTH + 1);
+        let err = Config::load(&dir.join("f.json")).unwrap_err();
+        assert!(err.problems[0].starts_with("include chain is deeper than 4: "), "{err}");
+        assert!(err.problems[0].ends_with(&format!("b.json -> {}", dir.join("a.json").display())), "{err}");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn includes_resolve_from_the_including_files_folder() {
+        let dir = temp_dir("include-nested");
+        fs::create_dir_all(dir.join("envs/prod")).unwrap();
+        fs::write(dir.join("base.json"), r#"{"database_path": "data", "logging_channel_id": "111"}"#).unwrap();
+        fs::write(dir.join("envs/prod/squire.json"), r#"{"include": "../../base.json", "logging_channel_id": "333"}"#).unwrap();
+        let config = Config::load(&dir.join("envs/prod/squire.json")).unwrap();
+        assert_eq!(config.logging_channel_id.as_deref(), Some("333"));
+        // `database_path` is relative to base.json, the file that set it.
+        assert_eq!(config.database_path, Some(dir.join("envs/prod/../../data")));
+
+        let err = Config::load(&dir.join("base.json").with_file_name("missing.json")).unwrap_err();
+        assert!(err.problems[0].starts_with("Unable to read"), "{err}");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn explicit_layers_override_in_order() {
+        let dir = temp_dir("layers");
+        fs::write(dir.join("base.json"), r#"{"logging_channel_id": "1", "feature_flags": {"xp": true}}"#).unwrap();
+        fs::write(dir.join("prod.json"), r#"{"logging_channel_id": "2", "feature_flags": {"gateway": false}}"#).unwrap();
+        fs::write(dir.join("host.json"), r#"{"logging_channel_id": "3"}"#).unwrap();
+        let config = Config::load_layers(&[dir.join("base.json"), dir.join("prod.json"), dir.join("host.json")]).unwrap();
+        assert_eq!(config.logging_channel_id.as_deref(), Some("3"));
+        assert!(config.feature_flags.is_enabled("xp") && !config.gateway_enabled());
+        assert_eq!(config.path, dir.join("host.json"));
+        assert_eq!(config.layers.len(), 3);
+        assert_eq!(Config::load_layers::<PathBuf>(&[]).unwrap_err().problems, ["no config file given"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn toml_and_json_give_the_same_config() {
+        let dir = temp_dir("toml-same");
+        fs::write(dir.join("config.json"), r#"{"discord_token": "$ENV{SQUIRE_TEST_TOKEN}", "logging_channel_id": "123456789012345678", "database_path": "data", "feature_flags": {"gateway": true, "beta": false}}"#).unwrap();
+        fs::write(
+            dir.join("config.toml"),
+            "# same settings\ndiscord_token = \"$ENV{SQUIRE_TEST_TOKEN}\"\nlogging_channel_id = \"123456789012345678\"  # logs\ndatabase_path = \"data\"\n\n[feature_flags]\ngateway = true\nbeta = false\n",
+        )
+        .unwrap();
+        let env = MapEnv::new().with("SQUIRE_TEST_TOKEN", "token-from-env");
+        let json = Config::load_layers_from(&[dir.join("config.json")], &env).unwrap();
+        let toml = Config::load_layers_from(&[dir.join("config.toml")], &env).unwrap();
+        assert_eq!(toml.discord_token.as_deref(), Some("token-from-env"), "expanded inside a TOML string");
+        assert_eq!(
+            (&toml.discord_token, &toml.logging_channel_id, &toml.database_path, &toml.feature_flags),
+            (&json.discord_token, &json.logging_channel_id, &json.database_path, &json.feature_flags)
+        );
+        assert_eq!(Config::load_layers_from(&[dir.join("config.toml")], &MapEnv::new()).unwrap().discord_token, None, "unset variable");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn the_extension_picks_the_parser() {
+        let dir = temp_dir("toml-dispatch");
+        let text = "logging_channel_id = \"1\"\n";
+        for name in ["config.toml", "CONFIG.TOML"] {
+            fs::write(dir.join(name), text).unwrap();
+            assert_eq!(Config::load(&dir.join(name)).unwrap().logging_channel_id.as_deref(), Some("1"), "{name}");
+        }
+        // The same text in a `.json` or extensionless file goes to the JSON parser and fails there.
+        for name in ["config.json", "config"] {
+            fs::write(dir.join(name), text).unwrap();
+            let err = Config::load(&dir.join(name)).unwrap_err();
+            assert!(!err.problems[0].contains("line 1:"), "{err}");
+        }
+        // The formats can include each other.
+        fs::write(dir.join("prod.json"), r#"{"include": "config.toml", "database_path": "data"}"#).unwrap();
+        let config = Config::load(&dir.join("prod.json")).unwrap();
+        assert_eq!((config.logging_channel_id.as_deref(), config.layers.len()), (Some("1"), 2));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn toml_errors_carry_the_line_number() {
+        let dir = temp_dir("toml-errors");
+        let path = dir.join("config.toml");
+        fs::write(&path, "# comment\nlogging_channel_id = \"1\"\nbroken line\n").unwrap();
+        let err = Config::load(&path).unwrap_err();
+        assert_eq!(err.problems.len(), 1);
+        assert!(err.problems[0].ends_with("config.toml\": line 3: expected key = value"), "{err}");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
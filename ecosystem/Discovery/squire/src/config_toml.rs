@@ -0,0 +1,240 @@
+//! A small TOML reader for hand-edited config files, as an alternative to `config.json`.
+//!
+//! JSON is strict: no comments, no trailing commas, every key in quotes. That is right for files a
+//! program writes, but awkward for files people edit. `Config::load` therefore also accepts a file
+//! ending in `.toml`, read here into the same `JsonValue` object a JSON file would give, so the
+//! checks, `$ENV{...}` expansion, and `include` merging that follow are exactly the same.
+//!
+//! Only the shapes `Config` needs are understood, nothing more:
+//!
+//! ```text
+//! # Comments start with `#`, on their own line or after a value.
+//! include = "base.json"
+//! discord_token = "$ENV{SQUIRE_DISCORD_TOKEN}"
+//! logging_channel_id = "123456789012345678"
+//!
+//! [feature_flags]
+//! gateway = true
+//! beta = false
+//! ```
+//!
+//! - Top-level `key = "string"` and `key = true` / `key = false`. Keys are bare words of letters,
+//!   digits, `_`, and `-`. Strings use double quotes and the JSON escapes (`\"`, `\\`, `\n`,
+//!   `\t`, `\r`, `\uXXXX`).
+//! - One table, `[feature_flags]`, holding `name = true` / `name = false`. Everything after the
+//!   table header belongs to the table, as in TOML itself. Staged rollouts
+//!   (`{"enabled": true, "percentage": 20}`) still need a JSON file.
+//!
+//! Anything else TOML allows (numbers, arrays, inline or nested tables, dotted or quoted keys,
+//! `'literal'` and `"""multi-line"""` strings) is refused with "not supported in the minimal TOML
+//! subset" instead of being guessed at. Every error starts with `line N:`.
+
+use crate::json::JsonValue;
+
+/// The one table a file may have.
+const FEATURE_FLAGS: &str = "feature_flags";
+
+/// Read a TOML file into a `JsonValue::Object`, with `[feature_flags]` as a nested object.
+pub fn parse(text: &str) -> Result<JsonValue, String> {
+    let mut top: Vec<(String, JsonValue)> = Vec::new();
+    // `Some` once the `[feature_flags]` header has been seen.
+    let mut flags: Option<Vec<(String, JsonValue)>> = None;
+
+    for (index, raw) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            let header = strip_comment(line);
+            if header.starts_with("[[") {
+                return Err(unsupported(number, "arrays of tables ([[...]])"));
+            }
+            let name = header.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')).map(str::trim);
+            match name {
+                Some(FEATURE_FLAGS) if flags.is_none() => flags = Some(Vec::new()),
+                Some(FEATURE_FLAGS) => return Err(at(number, "[feature_flags] appears twice")),
+                Some(_) => return Err(unsupported(number, "tables other than [feature_flags]")),
+                None => return Err(at(number, "a table header must look like [feature_flags]")),
+            }
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(|| at(number, "expected key = value"))?;
+        let key = parse_key(key.trim()).map_err(|problem| at(number, &problem))?;
+        let value = parse_value(value.trim()).map_err(|problem| at(number, &problem))?;
+        let fields = match flags.as_mut() {
+            Some(flags) => {
+                if !matches!(value, JsonValue::Bool(_)) {
+                    return Err(at(number, &format!("feature_flags.{key} must be true or false")));
+                }
+                flags
+            }
+            None if key == FEATURE_FLAGS => return Err(at(number, "write feature_flags as a [feature_flags] table")),
+            None => &mut top,
+        };
+        if fields.iter().any(|(seen, _)| *seen == key) {
+            return Err(at(number, &format!("{key} is set twice")));
+        }
+        fields.push((key, value));
+    }
+
+    if let Some(flags) = flags {
+        top.push((FEATURE_FLAGS.to_string(), JsonValue::Object(flags)));
+    }
+    Ok(JsonValue::Object(top))
+}
+
+/// A bare key: letters, digits, `_`, and `-`.
+fn parse_key(key: &str) -> Result<String, String> {
+    if key.is_empty() {
+        return Err("the key before = is missing".to_string());
+    }
+    if key.starts_with('"') || key.starts_with('\'') || key.contains('.') {
+        return Err("quoted and dotted keys are not supported in the minimal TOML subset".to_string());
+    }
+    if !key.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-') {
+        return Err(format!("{key:?} is not a valid key; use letters, digits, _ and -"));
+    }
+    Ok(key.to_string())
+}
+
+/// `"string"`, `true`, or `false`, optionally followed by a `# comment`.
+fn parse_value(value: &str) -> Result<JsonValue, String> {
+    if value.starts_with("\"\"\"") || value.starts_with("'''") {
+        return Err("multi-line strings are not supported in the minimal TOML subset".to_string());
+    }
+    if let Some(rest) = value.strip_prefix('"') {
+        let (text, after) = read_string(rest)?;
+        let after = after.trim();
+        if !(after.is_empty() || after.starts_with('#')) {
+            return Err(format!("unexpected text after the string: {after:?}"));
+        }
+        return Ok(JsonValue::String(text));
+    }
+    match strip_comment(value) {
+        "true" => Ok(JsonValue::Bool(true)),
+        "false" => Ok(JsonValue::Bool(false)),
+        "" => Err("the value after = is missing".to_string()),
+        other => {
+            let kind = match other.as_bytes()[0] {
+                b'[' => "arrays",
+                b'{' => "inline tables",
+                b'\'' => "'literal' strings (use double quotes)",
+                b'0'..=b'9' | b'+' | b'-' => "numbers and dates (write ids as \"strings\")",
+                _ => return Err(format!("{other:?} is not a value; strings need double quotes")),
+            };
+            Err(format!("{kind} are not supported in the minimal TOML subset"))
+        }
+    }
+}
+
+/// The contents of a `"..."` string whose opening quote is already gone, and what follows it.
+fn read_string(rest: &str) -> Result<(String, &str), String> {
+    let mut text = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((index, ch)) = chars.next() {
+        match ch {
+            '"' => return Ok((text, &rest[index + 1..])),
+            '\\' => match chars.next().map(|(_, escaped)| escaped) {
+                Some('"') => text.push('"'),
+                Some('\\') => text.push('\\'),
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some('r') => text.push('\r'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).map(|(_, digit)| digit).collect();
+                    let decoded = u32::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == 4).and_then(char::from_u32);
+                    text.push(decoded.ok_or_else(|| format!("\\u{hex} is not a valid \\uXXXX escape"))?);
+                }
+                Some(other) => return Err(format!("unknown escape \\{other}")),
+                None => break,
+            },
+            ch => text.push(ch),
+        }
+    }
+    Err("the string has no closing \"".to_string())
+}
+
+/// Drop a trailing `# comment` from a line that holds no string.
+fn strip_comment(text: &str) -> &str {
+    text.split_once('#').map_or(text, |(before, _)| before).trim()
+}
+
+fn at(line: usize, problem: &str) -> String {
+    format!("line {line}: {problem}")
+}
+
+fn unsupported(line: usize, what: &str) -> String {
+    at(line, &format!("{what} are not supported in the minimal TOML subset"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(text: &str) -> Vec<(String, JsonValue)> {
+        match parse(text).unwrap() {
+            JsonValue::Object(fields) => fields,
+            other => panic!("not an object: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn strings_booleans_and_the_flags_table() {
+        let parsed = fields("include = \"base.json\"\nname = \"tab\\there \\\"q\\\" \\u00e9 # not a comment\" # a comment\non = true\n[ feature_flags ]  # flags\ngateway = false\nnew-xp = true\n");
+        assert_eq!(parsed[0], ("include".to_string(), JsonValue::String("base.json".to_string())));
+        assert_eq!(parsed[1].1, JsonValue::String("tab\there \"q\" é # not a comment".to_string()));
+        assert_eq!(parsed[2].1, JsonValue::Bool(true));
+        assert_eq!(
+            parsed[3],
+            (
+                "feature_flags".to_string(),
+                JsonValue::Object(vec![("gateway".to_string(), JsonValue::Bool(false)), ("new-xp".to_string(), JsonValue::Bool(true))])
+            )
+        );
+        assert_eq!(parse("# nothing\n\n").unwrap(), JsonValue::Object(Vec::new()));
+    }
+
+    #[test]
+    fn every_error_names_its_line() {
+        for (text, expected) in [
+            ("a = \"x\"\nb", "line 2: expected key = value"),
+            ("a = \"x\"\na = \"y\"", "line 2: a is set twice"),
+            ("feature_flags = true", "line 1: write feature_flags as a [feature_flags] table"),
+            ("[feature_flags]\nbeta = \"yes\"", "line 2: feature_flags.beta must be true or false"),
+            ("[feature_flags]\n[feature_flags]", "line 2: [feature_flags] appears twice"),
+            ("a = \"open", "line 1: the string has no closing \""),
+            ("a = \"x\" y", "line 1: unexpected text after the string: \"y\""),
+            ("a = \"\\q\"", "line 1: unknown escape \\q"),
+            ("a = \"\\u12\"", "line 1: \\u12\" is not a valid \\uXXXX escape"),
+            ("a = ", "line 1: the value after = is missing"),
+            ("= \"x\"", "line 1: the key before = is missing"),
+            ("a b = true", "line 1: \"a b\" is not a valid key; use letters, digits, _ and -"),
+            ("a = yes", "line 1: \"yes\" is not a value; strings need double quotes"),
+            ("[feature_flags", "line 1: a table header must look like [feature_flags]"),
+        ] {
+            assert_eq!(parse(text).unwrap_err(), expected, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn the_rest_of_toml_is_refused_not_guessed() {
+        for (text, what) in [
+            ("ids = [1, 2]", "arrays"),
+            ("owner = { name = \"x\" }", "inline tables"),
+            ("a = 'literal'", "'literal' strings"),
+            ("port = 7420", "numbers and dates"),
+            ("when = 2026-10-16", "numbers and dates"),
+            ("a = \"\"\"multi\nline\"\"\"", "multi-line strings"),
+            ("[servers]", "tables other than [feature_flags]"),
+            ("[[servers]]", "arrays of tables"),
+            ("a.b = true", "quoted and dotted keys"),
+            ("\"quoted\" = true", "quoted and dotted keys"),
+        ] {
+            let err = parse(text).unwrap_err();
+            assert!(err.starts_with("line 1: ") && err.contains(what) && err.ends_with("not supported in the minimal TOML subset"), "{text:?} gave {err:?}");
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! C functions for the Python side (cargo feature `ffi`).
+//!
+//! The remaining Python pieces verify passwords and open the occasional vault envelope. Running
+//! a CLI for each request was slow and put the secrets in its arguments, so the library also
+//! builds as a shared object (`libsquire_gateway.so`) that `ctypes` can load:
+//!
+//! ```text
+//! char *squire_hash_password(const char *plaintext);                 // free with squire_free_string
+//! int32_t squire_verify_password(const char *plaintext, const char *hash);
+//! uint8_t *squire_decrypt_secret(const char *key_b64, const char *envelope_json,
+//!                                size_t *out_len);                     // free with squire_free_bytes
+//! const char *squire_last_error(void);
+//! ```
+//!
+//! - Strings are NUL-terminated UTF-8. A null pointer or text that is not UTF-8 is an error.
+//! - On error a function returns null (or `-1`) and `squire_last_error` says why. Its text
+//!   belongs to the library and stays valid until the next call on the same thread; it is null
+//!   after a call that succeeded.
+//! - A panic is caught before it reaches the caller and reported like any other error.
+//! - Memory returned by one of these functions is only ever freed by its `squire_free_*`
+//!   function. `squire_free_bytes` zeroes the plaintext before releasing it.
+//!
+//! Hashes are the Python format (see `password`); the key is base64, as in `SQUIRE_VAULT_KEY`,
+//! and the envelope is the JSON `EncryptedSecret.to_storable()` writes (see `vault`).
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::gateway::SecretBytes;
+use crate::password::{self, PasswordMatch};
+use crate::vault::{base64_decode, EncryptedSecret, SecretVault};
+
+thread_local! {
+    /// Why the last call on this thread failed, for `squire_last_error`.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+#[cfg(test)]
+thread_local! {
+    /// How many allocations the `squire_free_*` functions released on this thread.
+    static FREED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Run `body` with the last error cleared, turning an `Err` or a panic into `on_error` plus a
+/// message for `squire_last_error`.
+fn guarded<T>(on_error: T, body: impl FnOnce() -> Result<T, String>) -> T {
+    set_last_error(None);
+    let outcome = panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let reason = payload
+            .downcast_ref::<&str>()
+            .map(|text| text.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(format!("internal error: {reason}"))
+    });
+    outcome.unwrap_or_else(|err| {
+        set_last_error(Some(err));
+        on_error
+    })
+}
+
+fn set_last_error(message: Option<String>) {
+    // A message with a NUL in it is cut there rather than lost.
+    let message = message.map(|text| CString::new(text.split('\0').next().unwrap_or_default()).unwrap_or_default());
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = message);
+}
+
+/// The text behind a C string argument.
+///
+/// # Safety
+/// `pointer` is null or points to a NUL-terminated string that outlives the call.
+unsafe fn text_arg<'a>(pointer: *const c_char, name: &str) -> Result<&'a str, String> {
+    if pointer.is_null() {
+        return Err(format!("{name} is a null pointer"));
+    }
+    CStr::from_ptr(pointer).to_str().map_err(|_| format!("{name} is not UTF-8"))
+}
+
+/// Hash a password with a fresh salt (see `password::hash_password`). Returns a string to free
+/// with `squire_free_string`, or null on error.
+///
+/// # Safety
+/// `plaintext` is null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn squire_hash_password(plaintext: *const c_char) -> *mut c_char {
+    guarded(ptr::null_mut(), || {
+        let plaintext = text_arg(plaintext, "plaintext")?;
+        let hash = password::hash_password(plaintext)?;
+        let hash = CString::new(hash).map_err(|_| "hash contains a NUL byte".to_string())?;
+        Ok(hash.into_raw())
+    })
+}
+
+/// Check a password against a stored hash: `1` for a current scrypt record, `2` for a legacy
+/// PBKDF2 record that should be replaced with a new hash, `0` for no match (including a damaged
+/// record), `-1` on error.
+///
+/// # Safety
+/// Both arguments are null or NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn squire_verify_password(plaintext: *const c_char, hash: *const c_char) -> i32 {
+    guarded(-1, || {
+        let plaintext = text_arg(plaintext, "plaintext")?;
+        let hash = text_arg(hash, "hash")?;
+        Ok(match password::verify_password(plaintext, hash) {
+            PasswordMatch::Current => 1,
+            PasswordMatch::Legacy => 2,
+            PasswordMatch::NoMatch => 0,
+        })
+    })
+}
+
+/// Open a vault envelope with a base64 master key. Returns the plaintext and stores its length
+/// in `out_len`; free it with `squire_free_bytes(pointer, length)`. Returns null on error, with
+/// `out_len` set to 0. An empty plaintext is a valid pointer with length 0.
+///
+/// # Safety
+/// `key_b64` and `envelope_json` are null or NUL-terminated strings; `out_len` is null or
+/// points to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn squire_decrypt_secret(key_b64: *const c_char, envelope_json: *const c_char, out_len: *mut usize) -> *mut u8 {
+    if !out_len.is_null() {
+        *out_len = 0;
+    }
+    guarded(ptr::null_mut(), || {
+        if out_len.is_null() {
+            return Err("out_len is a null pointer".to_string());
+        }
+        let key = text_arg(key_b64, "key_b64")?;
+        let envelope = EncryptedSecret::parse(text_arg(envelope_json, "envelope_json")?)?;
+        let master = base64_decode(key.trim()).ok_or("key_b64 is not valid base64")?;
+        let plaintext = SecretVault::new(SecretBytes::new(master))?.decrypt(&envelope)?;
+        // The copy handed out is zeroed by `squire_free_bytes`; the original by `SecretBytes`.
+        let copy: Box<[u8]> = plaintext.expose().into();
+        *out_len = copy.len();
+        Ok(Box::into_raw(copy) as *mut u8)
+    })
+}
+
+/// Free a string from `squire_hash_password`. Null is ignored.
+///
+/// # Safety
+/// `pointer` is null or came from `squire_hash_password` and was not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn squire_free_string(pointer: *mut c_char) {
+    if !pointer.is_null() {
+        drop(CString::from_raw(pointer));
+        #[cfg(test)]
+        FREED.with(|freed| freed.set(freed.get() + 1));
+    }
+}
+
+/// Zero and free the plaintext from `squire_decrypt_secret`. Null is ignored.
+///
+/// # Safety
+/// `pointer` is null or came from `squire_decrypt_secret` together with `len`, and was not
+/// freed before.
+#[no_mangle]
+pub unsafe extern "C" fn squire_free_bytes(pointer: *mut u8, len: usize) {
+    if !pointer.is_null() {
+        let mut bytes = Box::from_raw(ptr::slice_from_raw_parts_mut(pointer, len));
+        bytes.fill(0);
+        std::hint::black_box(&bytes);
+        #[cfg(test)]
+        FREED.with(|freed| freed.set(freed.get() + 1));
+    }
+}
+
+/// Why the last call on this thread failed, or null when it succeeded. The text stays valid
+/// until the next call on the same thread; do not free it.
+#[no_mangle]
+pub extern "C" fn squire_last_error() -> *const c_char {
+    LAST_ERROR.with(|slot| slot.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `encrypt_secret(bytes([0x42]) * 32, b"bot-token-123")` from the Python vault.
+    const ENVELOPE: &str = r#"{"nonce":"AAECAwQFBgcICQoLDA0OD2RlZmdoaWprbG1ubw==","ciphertext":"TFm+XcV0JaAi1r4O9g==","tag":"F7UcgJS54mxd35h8QhgdTQ=="}"#;
+    /// 32 bytes of 0x42 in base64.
+    const KEY_B64: &str = "QkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkI=";
+
+    fn c(text: &str) -> CString {
+        CString::new(text).unwrap()
+    }
+
+    fn last_error() -> Option<String> {
+        let message = squire_last_error();
+        (!message.is_null()).then(|| unsafe { CStr::from_ptr(message) }.to_str().unwrap().to_string())
+    }
+
+    fn freed() -> usize {
+        FREED.with(|freed| freed.get())
+    }
+
+    /// Decrypt through the C symbols and hand back a copy of the plaintext, freeing the original.
+    fn decrypt(key: &CStr, envelope: &CStr) -> Result<Vec<u8>, String> {
+        let mut len = usize::MAX;
+        let pointer = unsafe { squire_decrypt_secret(key.as_ptr(), envelope.as_ptr(), &mut len) };
+        if pointer.is_null() {
+            assert_eq!(len, 0);
+            return Err(last_error().expect("a failed call sets the last error"));
+        }
+        assert_eq!(last_error(), None);
+        let plaintext = unsafe { std::slice::from_raw_parts(pointer, len) }.to_vec();
+        unsafe { squire_free_bytes(pointer, len) };
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn a_hash_from_the_c_side_verifies_on_the_c_side() {
+        let hash = unsafe { squire_hash_password(c("hunter2").as_ptr()) };
+        assert!(!hash.is_null(), "{:?}", last_error());
+        assert_eq!(last_error(), None);
+        let stored = unsafe { CStr::from_ptr(hash) }.to_owned();
+        assert!(stored.to_str().unwrap().starts_with("scrypt$n=32768$r=8$p=1$"));
+        let before = freed();
+        unsafe { squire_free_string(hash) };
+        assert_eq!(freed(), before + 1);
+
+        assert_eq!(unsafe { squire_verify_password(c("hunter2").as_ptr(), stored.as_ptr()) }, 1);
+        // The default parameters are slow in a debug build; cheap ones check a wrong password.
+        let cheap = c(&password::hash_password_with("hunter2", password::ScryptParams { n: 16, r: 1, p: 1 }).unwrap());
+        assert_eq!(unsafe { squire_verify_password(c("hunter3").as_ptr(), cheap.as_ptr()) }, 0);
+        assert_eq!(unsafe { squire_verify_password(c("hunter2").as_ptr(), c("scrypt$damaged").as_ptr()) }, 0);
+        let hostile = cheap.to_str().unwrap().replace("n=16", "n=4611686018427387904");
+        assert_eq!(unsafe { squire_verify_password(c("hunter2").as_ptr(), c(&hostile).as_ptr()) }, 0, "too costly is no match, not an error");
+        assert_eq!(last_error(), None, "no match is an answer, not an error");
+    }
+
+    #[test]
+    fn a_legacy_record_asks_for_a_new_hash() {
+        // `hashlib.pbkdf2_hmac("sha256", b"password", b"salt", 1)` in Django's layout.
+        let legacy = c("pbkdf2_sha256$1$salt$Eg+2z/z4syxD5yJSVsT4N6hlSMkszDVICAWYfLcL4Xs=");
+        assert_eq!(unsafe { squire_verify_password(c("password").as_ptr(), legacy.as_ptr()) }, 2);
+    }
+
+    #[test]
+    fn null_pointers_and_non_utf8_text_are_errors() {
+        assert!(unsafe { squire_hash_password(ptr::null()) }.is_null());
+        assert_eq!(last_error().as_deref(), Some("plaintext is a null pointer"));
+        assert_eq!(unsafe { squire_verify_password(c("x").as_ptr(), ptr::null()) }, -1);
+        assert_eq!(last_error().as_deref(), Some("hash is a null pointer"));
+
+        let latin1 = CString::new(vec![b'p', 0xe9]).unwrap();
+        assert!(unsafe { squire_hash_password(latin1.as_ptr()) }.is_null());
+        assert_eq!(last_error().as_deref(), Some("plaintext is not UTF-8"));
+        assert_eq!(unsafe { squire_verify_password(latin1.as_ptr(), c("scrypt$").as_ptr()) }, -1);
+        assert_eq!(last_error().as_deref(), Some("plaintext is not UTF-8"));
+
+        let mut len = 7;
+        assert!(unsafe { squire_decrypt_secret(ptr::null(), c(ENVELOPE).as_ptr(), &mut len) }.is_null());
+        assert_eq!((len, last_error().as_deref()), (0, Some("key_b64 is a null pointer")));
+        assert!(unsafe { squire_decrypt_secret(c(KEY_B64).as_ptr(), latin1.as_ptr(), &mut len) }.is_null());
+        assert_eq!(last_error().as_deref(), Some("envelope_json is not UTF-8"));
+        assert!(unsafe { squire_decrypt_secret(c(KEY_B64).as_ptr(), c(ENVELOPE).as_ptr(), ptr::null_mut()) }.is_null());
+        assert_eq!(last_error().as_deref(), Some("out_len is a null pointer"));
+
+        // Null is ignored by the free functions.
+        let before = freed();
+        unsafe {
+            squire_free_string(ptr::null_mut());
+            squire_free_bytes(ptr::null_mut(), 0);
+        }
+        assert_eq!(freed(), before);
+    }
+
+    #[test]
+    fn envelopes_open_and_bad_ones_say_why() {
+        assert_eq!(decrypt(&c(KEY_B64), &c(ENVELOPE)).unwrap(), b"bot-token-123");
+        assert_eq!(decrypt(&c(&format!(" {KEY_B64}\n")), &c(ENVELOPE)).unwrap(), b"bot-token-123", "the key is trimmed");
+
+        let wrong_key = c("Q0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0M=");
+        assert!(decrypt(&wrong_key, &c(ENVELOPE)).unwrap_err().contains("failed authentication"));
+        assert_eq!(decrypt(&c("not base64"), &c(ENVELOPE)).unwrap_err(), "key_b64 is not valid base64");
+        assert!(decrypt(&c("QkJC"), &c(ENVELOPE)).is_err(), "a 3-byte key");
+        assert!(decrypt(&c(KEY_B64), &c("{}")).unwrap_err().contains("missing the \"nonce\" string"));
+        assert!(decrypt(&c(KEY_B64), &c("not json")).is_err());
+        let tampered = ENVELOPE.replace("TFm+", "TFm/");
+        assert!(decrypt(&c(KEY_B64), &c(&tampered)).unwrap_err().contains("failed authentication"));
+
+        // A success after a failure clears the last error.
+        assert!(decrypt(&c(KEY_B64), &c(ENVELOPE)).is_ok());
+        assert_eq!(last_error(), None);
+    }
+
+    #[test]
+    fn repeated_calls_free_every_allocation() {
+        let before = freed();
+        let (key, envelope) = (c(KEY_B64), c(ENVELOPE));
+        for _ in 0..200 {
+            assert_eq!(decrypt(&key, &envelope).unwrap(), b"bot-token-123");
+            assert!(decrypt(&key, &c("{}")).is_err());
+        }
+        assert_eq!(freed(), before + 200, "one free per plaintext handed out, none for failures");
+    }
+
+    #[test]
+    fn panics_stop_at_the_boundary() {
+        let quiet = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        let static_text = guarded(-1, || -> Result<i32, String> { panic!("static text") });
+        let formatted = guarded(ptr::null_mut::<u8>(), || -> Result<*mut u8, String> { panic!("formatted {}", 42) });
+        let other = guarded(-1, || -> Result<i32, String> { panic::panic_any(7u8) });
+        panic::set_hook(quiet);
+
+        assert_eq!(static_text, -1);
+        assert!(formatted.is_null());
+        assert_eq!(other, -1);
+        assert_eq!(last_error().as_deref(), Some("internal error: unknown panic"));
+        assert_eq!(guarded(-1, || Ok(5)), 5);
+        assert_eq!(last_error(), None);
+    }
+
+    #[test]
+    fn a_message_with_a_nul_is_cut_there() {
+        assert_eq!(guarded(0, || Err("before\0after".to_string())), 0);
+        assert_eq!(last_error().as_deref(), Some("before"));
+    }
+}
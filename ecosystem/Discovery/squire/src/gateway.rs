@@ -0,0 +1,3610 @@
+//! Rust wrapper responsible for all Discord-facing communication.
+//!
+//! This wrapper owns the network boundary so Python modules never open sockets.
+//! It also checks for the ecosystem presence file inside `Discovery/` to decide
+//! when bot-to-bot chatter is allowed. Everything uses only Rust's standard
+//! library for full auditability. Every file it touches is listed in a
+//! `DiscoveryLayout`, so tests and services can point it anywhere.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::atomic::atomic_write;
+use crate::commands::{load_synced, save_synced, CommandChange, CommandRegistry};
+use crate::lockfile::append_locked;
+use crate::log::{redact, Logger};
+use crate::message::{json_escape, validate_raw, MessageError};
+use crate::recorder::recording_from_env;
+use crate::runtime::{Clock, EnvSource, ProcessEnv, Sleeper, SystemClock, ThreadSleeper};
+pub use crate::secret::SecretBytes;
+use crate::sha256::{constant_time_eq, hmac_sha256, sha256, to_hex};
+use crate::snowflake::Snowflake;
+use crate::webhook::WebhookUrl;
+
+/// File name that signals the ecosystem hub has announced itself.
+const PRESENCE_FILE_NAME: &str = "ecosystem_presence.txt";
+/// Optional queue file where Python can drop logs for forwarding to a logging channel.
+const DISPATCH_FILE_NAME: &str = "gateway_queue.log";
+/// Optional file where the gateway can summarize HTTPS intent without dumping secrets to stdout.
+const SECURE_DISPATCH_FILE_NAME: &str = "secure_transport.log";
+/// Folder where the hub drops `<millis>-<seq>.cmd` command files for this bot.
+const INBOX_DIR_NAME: &str = "gateway_inbox";
+/// Spool file that keeps queued messages safe across restarts.
+const SPOOL_FILE_NAME: &str = "outbound_spool.log";
+/// Last slash-command set sent to Discord, compared before every sync.
+const COMMANDS_SYNCED_FILE_NAME: &str = "commands_synced.json";
+/// Hash-chained moderation audit log (see `src/modlog.rs`).
+const MODLOG_FILE_NAME: &str = "modlog.jsonl";
+/// Liveness file the ecosystem hub reads: `pid=<pid> seq=<n> at=<unix millis>`.
+const HEARTBEAT_FILE_NAME: &str = "heartbeat.txt";
+/// Nonce the hub asks this bot to sign: `nonce=<hex>`, `issued_at=<millis>`, `ttl_secs=<n>`.
+const CHALLENGE_FILE_NAME: &str = "challenge.txt";
+/// The gateway's answer to `challenge.txt` (see `DiscordGateway::answer_challenge`).
+const CHALLENGE_RESPONSE_FILE_NAME: &str = "challenge_response.txt";
+/// Discord application id, needed for the slash-command endpoint.
+const APPLICATION_ID_ENV: &str = "SQUIRE_APPLICATION_ID";
+/// Optional absolute path of this bot's `Discovery/` folder (see `DiscoveryLayout::resolve`).
+const DISCOVERY_ROOT_ENV: &str = "SQUIRE_DISCOVERY_ROOT";
+/// Environment variable shared with the hub to authenticate presence markers.
+const PRESENCE_KEY_ENV: &str = "ECOSYSTEM_PRESENCE_KEY";
+/// Optional override (in seconds) for how long a signed presence file stays valid.
+const PRESENCE_TTL_ENV: &str = "ECOSYSTEM_PRESENCE_TTL_SECS";
+/// Default presence lifetime: 15 minutes. The hub refreshes markers well within this.
+const DEFAULT_PRESENCE_TTL_SECS: u64 = 15 * 60;
+/// Clock difference tolerated between the hub and this host, in either direction.
+const PRESENCE_CLOCK_SKEW_SECS: u64 = 2 * 60;
+/// `status=` value of a marker the hub wrote to say "stop": on shutdown or when this bot was
+/// decommissioned. The hub also ends the signed nonce with `|revoked`.
+const PRESENCE_REVOKED: &str = "revoked";
+/// Start of the error `validate_presence_file` returns for a genuine revocation, so callers can
+/// log it apart from a missing or broken marker.
+pub const PRESENCE_REVOKED_ERROR: &str = "presence revoked by the hub";
+/// Bot token, used when no other `TokenSource` was handed to the gateway.
+const TOKEN_ENV: &str = "SQUIRE_DISCORD_TOKEN";
+/// Set to `1` to force the dry-run transport even when a token and proxy are configured.
+const DRY_RUN_ENV: &str = "SQUIRE_DRY_RUN";
+/// `host:port` of the local TLS-terminating proxy that forwards to discord.com:443.
+const PROXY_ENV: &str = "SQUIRE_DISCORD_PROXY";
+/// Host header sent with every request; the proxy uses it to pick the upstream.
+const DISCORD_HOST: &str = "discord.com";
+/// Upper bound for connecting, writing, and reading one HTTP exchange.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many times one message may be put back after a 429 before flush gives up on it.
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+
+/// Log lines from this module carry the component name `gateway`.
+const LOG: Logger = Logger::new("gateway");
+
+/// Every file and folder the gateway reads or writes, all inside one `Discovery/` folder.
+///
+/// Relative paths like `Discovery/ecosystem_presence.txt` only work when the binary starts in the
+/// bot folder; under systemd or cron the working directory is usually `/`, and the gateway would
+/// report "presence file missing" even though the hub wrote it. `DiscoveryLayout::resolve` picks
+/// the folder in this order: the `SQUIRE_DISCOVERY_ROOT` environment variable, the path passed
+/// in, then `Discovery/` under the current directory. `DiscoveryLayout::new(root)` uses `root`
+/// exactly and ignores the environment, which is what tests want.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveryLayout {
+    /// The `Discovery/` folder itself. The gateway creates it before its first heartbeat, so a
+    /// fresh root works from the first flush.
+    pub root: PathBuf,
+    /// Signed marker written by the ecosystem hub.
+    pub presence_file: PathBuf,
+    /// Log lines Python hands to Rust for forwarding.
+    pub dispatch_file: PathBuf,
+    /// Redacted summaries of every request the gateway makes.
+    pub secure_dispatch_file: PathBuf,
+    /// Command files from the hub.
+    pub inbox_dir: PathBuf,
+    /// Durable copy of the outbound queue (used by `with_spool`).
+    pub spool_file: PathBuf,
+    /// The slash commands most recently synced to Discord.
+    pub commands_synced_file: PathBuf,
+    /// Moderation audit log.
+    pub modlog_file: PathBuf,
+    /// Rewritten on every flush so the hub can tell the gateway is still running.
+    pub heartbeat_file: PathBuf,
+    /// The hub's current challenge nonce.
+    pub challenge_file: PathBuf,
+    /// This gateway's signed answer to it.
+    pub challenge_response_file: PathBuf,
+}
+
+impl DiscoveryLayout {
+    /// The layout inside `root`, which is the `Discovery/` folder itself.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        Self {
+            presence_file: root.join(PRESENCE_FILE_NAME),
+            dispatch_file: root.join(DISPATCH_FILE_NAME),
+            secure_dispatch_file: root.join(SECURE_DISPATCH_FILE_NAME),
+            inbox_dir: root.join(INBOX_DIR_NAME),
+            spool_file: root.join(SPOOL_FILE_NAME),
+            commands_synced_file: root.join(COMMANDS_SYNCED_FILE_NAME),
+            modlog_file: root.join(MODLOG_FILE_NAME),
+            heartbeat_file: root.join(HEARTBEAT_FILE_NAME),
+            challenge_file: root.join(CHALLENGE_FILE_NAME),
+            challenge_response_file: root.join(CHALLENGE_RESPONSE_FILE_NAME),
+            root,
+        }
+    }
+
+    /// The layout inside `<bot_dir>/Discovery/`.
+    pub fn under(bot_dir: impl AsRef<Path>) -> Self {
+        Self::new(bot_dir.as_ref().join("Discovery"))
+    }
+
+    /// `SQUIRE_DISCOVERY_ROOT` if it is set, otherwise `root`, otherwise `./Discovery`.
+    pub fn resolve(root: Option<PathBuf>) -> Self {
+        Self::resolve_from(root, &ProcessEnv)
+    }
+
+    /// `resolve`, reading `SQUIRE_DISCOVERY_ROOT` from `env` instead of the process environment.
+    pub fn resolve_from(root: Option<PathBuf>, env: &dyn EnvSource) -> Self {
+        match env.var(DISCOVERY_ROOT_ENV) {
+            Some(from_env) if !from_env.is_empty() => Self::new(from_env),
+            _ => match root {
+                Some(root) => Self::new(root),
+                None => Self::under(env::current_dir().unwrap_or_default()),
+            },
+        }
+    }
+}
+
+impl Default for DiscoveryLayout {
+    /// `SQUIRE_DISCOVERY_ROOT`, or `Discovery/` under the current directory.
+    fn default() -> Self {
+        Self::resolve(None)
+    }
+}
+
+/// The parts of an HTTP reply the gateway cares about.
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// Header names are lowercased so lookups do not depend on server casing.
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpResponse {
+    /// Find a header by (lowercase) name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Why a request never produced a response.
+#[derive(Debug, Clone)]
+pub enum TransportError {
+    /// No connection could be opened. These are worth one retry.
+    Connect(String),
+    /// The connection opened but reading or writing failed.
+    Io(String),
+    /// The reply was not understandable HTTP.
+    Protocol(String),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Connect(msg) => write!(f, "connect failed: {}", msg),
+            TransportError::Io(msg) => write!(f, "i/o failed: {}", msg),
+            TransportError::Protocol(msg) => write!(f, "bad response: {}", msg),
+        }
+    }
+}
+
+/// The one seam between the gateway and the network. Everything that leaves the process goes
+/// through one of its methods, so tests can swap in a mock that records requests instead of sending
+/// them.
+pub trait Transport {
+    /// Send a POST to `path` (for example `/api/v10/channels/123/messages`). Implementations
+    /// must never log header values, because `Authorization` carries the bot token.
+    fn post(&mut self, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError>;
+
+    /// Send a POST to a webhook outside Discord, at the URL's own host and path. The path is a
+    /// secret, so implementations must not log it either. Transports written before webhooks
+    /// existed report them as unsupported.
+    fn post_webhook(&mut self, _url: &WebhookUrl, _headers: &[(String, String)], _body: &str) -> Result<HttpResponse, TransportError> {
+        Err(TransportError::Protocol("this transport does not support webhooks".to_string()))
+    }
+
+    /// Send a PUT, used for the slash-command bulk overwrite. Transports written before PUT
+    /// existed report it as unsupported instead of failing to compile.
+    fn put(&mut self, _path: &str, _headers: &[(String, String)], _body: &str) -> Result<HttpResponse, TransportError> {
+        Err(TransportError::Protocol("this transport does not support PUT".to_string()))
+    }
+
+    /// Send a PATCH, used to edit a message the bot sent before. Unsupported unless implemented.
+    fn patch(&mut self, _path: &str, _headers: &[(String, String)], _body: &str) -> Result<HttpResponse, TransportError> {
+        Err(TransportError::Protocol("this transport does not support PATCH".to_string()))
+    }
+
+    /// Send a DELETE, which carries no body, used to remove a message. Unsupported unless implemented.
+    fn delete(&mut self, _path: &str, _headers: &[(String, String)]) -> Result<HttpResponse, TransportError> {
+        Err(TransportError::Protocol("this transport does not support DELETE".to_string()))
+    }
+
+    /// True for transports that never touch the network. Only those may run without a token.
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+}
+
+/// Transport that sends nothing and pretends Discord answered 200, which keeps offline runs
+/// and classrooms safe. The gateway marks its summaries in the secure dispatch log `DRY-RUN`.
+pub struct DryRunTransport;
+
+impl Transport for DryRunTransport {
+    fn post(&mut self, _path: &str, _headers: &[(String, String)], _body: &str) -> Result<HttpResponse, TransportError> {
+        Ok(HttpResponse { status: 200, headers: Vec::new(), body: String::new() })
+    }
+
+    fn put(&mut self, _path: &str, _headers: &[(String, String)], _body: &str) -> Result<HttpResponse, TransportError> {
+        Ok(HttpResponse { status: 200, headers: Vec::new(), body: String::new() })
+    }
+
+    fn post_webhook(&mut self, _url: &WebhookUrl, _headers: &[(String, String)], _body: &str) -> Result<HttpResponse, TransportError> {
+        Ok(HttpResponse { status: 200, headers: Vec::new(), body: String::new() })
+    }
+
+    fn patch(&mut self, _path: &str, _headers: &[(String, String)], _body: &str) -> Result<HttpResponse, TransportError> {
+        Ok(HttpResponse { status: 200, headers: Vec::new(), body: String::new() })
+    }
+
+    fn delete(&mut self, _path: &str, _headers: &[(String, String)]) -> Result<HttpResponse, TransportError> {
+        Ok(HttpResponse { status: 200, headers: Vec::new(), body: String::new() })
+    }
+
+    fn is_dry_run(&self) -> bool {
+        true
+    }
+}
+
+/// Real transport: plain HTTP/1.1 over TCP to a local TLS-terminating proxy (stunnel, nginx,
+/// or similar) that forwards to `discord.com:443`. The standard library has no TLS, and this
+/// project does not pull in crates, so encryption is the proxy's job; keep the proxy on
+/// localhost so the unencrypted leg never crosses a network.
+///
+/// Webhook requests go to the same proxy with the webhook's own `Host` header, so the proxy
+/// must pick its upstream by `Host` (nginx `server_name`, for example) once webhooks are used.
+pub struct ProxyTransport {
+    proxy_addr: String,
+}
+
+impl ProxyTransport {
+    pub fn new(proxy_addr: String) -> Self {
+        Self { proxy_addr }
+    }
+
+    fn connect(&self) -> Result<TcpStream, TransportError> {
+        let addresses = self
+            .proxy_addr
+            .to_socket_addrs()
+            .map_err(|err| TransportError::Connect(format!("{}: {}", self.proxy_addr, err)))?;
+        let mut last_error = format!("{} resolved to no addresses", self.proxy_addr);
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, HTTP_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = format!("{}: {}", address, err),
+            }
+        }
+        Err(TransportError::Connect(last_error))
+    }
+
+    /// Send one request with `method` and read the whole reply.
+    fn send(&mut self, method: &str, host: &str, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+        let mut stream = self.connect()?;
+        let io = |err: std::io::Error| TransportError::Io(err.to_string());
+        stream.set_read_timeout(Some(HTTP_TIMEOUT)).map_err(io)?;
+        stream.set_write_timeout(Some(HTTP_TIMEOUT)).map_err(io)?;
+
+        let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, host);
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", body.len()));
+        request.push_str(body);
+        stream.write_all(request.as_bytes()).map_err(io)?;
+
+        // `Connection: close` means the reply ends when the proxy hangs up.
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).map_err(io)?;
+        parse_http_response(&raw)
+    }
+}
+
+impl Transport for ProxyTransport {
+    fn post(&mut self, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+        self.send("POST", DISCORD_HOST, path, headers, body)
+    }
+
+    fn put(&mut self, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+        self.send("PUT", DISCORD_HOST, path, headers, body)
+    }
+
+    fn post_webhook(&mut self, url: &WebhookUrl, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+        self.send("POST", &url.host_header(), url.path(), headers, body)
+    }
+
+    fn patch(&mut self, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+        self.send("PATCH", DISCORD_HOST, path, headers, body)
+    }
+
+    fn delete(&mut self, path: &str, headers: &[(String, String)]) -> Result<HttpResponse, TransportError> {
+        self.send("DELETE", DISCORD_HOST, path, headers, "")
+    }
+}
+
+/// Split a raw HTTP/1.1 reply into status, headers, and body.
+fn parse_http_response(raw: &[u8]) -> Result<HttpResponse, TransportError> {
+    let text = String::from_utf8_lossy(raw);
+    let (head, body) = text
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| TransportError::Protocol("missing header terminator".to_string()))?;
+    let mut lines = head.lines();
+    let status_line = lines.next().unwrap_or("");
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| TransportError::Protocol(format!("bad status line {:?}", status_line)))?;
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    Ok(HttpResponse { status, headers, body: body.to_string() })
+}
+
+/// Pick a transport from the environment: dry-run when `SQUIRE_DRY_RUN=1`, when no token is
+/// set, or when no proxy is configured; otherwise the proxy transport. Either one is recorded
+/// when `SQUIRE_RECORD_REQUESTS` is set (see `recorder`).
+fn default_transport() -> Box<dyn Transport> {
+    transport_for_source(&TokenSource::default())
+}
+
+/// Same rules as `default_transport`, for any token source. An envelope counts as a token
+/// without being opened here; if it cannot be decrypted, `flush` says so and sends nothing.
+fn transport_for_source(source: &TokenSource) -> Box<dyn Transport> {
+    let dry_run = env::var(DRY_RUN_ENV).map(|v| v.trim() == "1").unwrap_or(false);
+    let token_missing = !source.is_configured();
+    let transport: Box<dyn Transport> = match env::var(PROXY_ENV) {
+        Ok(proxy) if !dry_run && !token_missing && !proxy.trim().is_empty() => {
+            Box::new(ProxyTransport::new(proxy.trim().to_string()))
+        }
+        _ => Box::new(DryRunTransport),
+    };
+    recording_from_env(transport, &ProcessEnv)
+}
+
+/// Zero a string that held a secret and leave it empty.
+fn wipe(text: &mut String) {
+    drop(SecretBytes::new(std::mem::take(text).into_bytes()));
+}
+
+/// Where the gateway gets its bot token. The token is fetched again at every flush and dropped
+/// (zeroed) when the flush ends, so the gateway never keeps it between flushes.
+#[derive(Debug, Clone)]
+pub enum TokenSource {
+    /// Read this environment variable, normally `SQUIRE_DISCORD_TOKEN`.
+    Env(String),
+    /// Decrypt a Python vault envelope (`EncryptedSecret.to_storable()`) with the base64 master
+    /// key held in the environment variable `key_env`. Needs the cargo feature `vault`.
+    #[cfg(feature = "vault")]
+    VaultEnvelope { key_env: String, envelope_path: PathBuf },
+    /// A token already in memory, such as the one from `config.json`, or a fixed one in a test.
+    Static(SecretBytes),
+}
+
+impl Default for TokenSource {
+    fn default() -> Self {
+        TokenSource::Env(TOKEN_ENV.to_string())
+    }
+}
+
+impl TokenSource {
+    /// Short name for logs: `env`, `vault`, or `static`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TokenSource::Env(_) => "env",
+            #[cfg(feature = "vault")]
+            TokenSource::VaultEnvelope { .. } => "vault",
+            TokenSource::Static(_) => "static",
+        }
+    }
+
+    /// Whether a token is expected at all. Checking an envelope would mean decrypting it, so an
+    /// envelope always counts.
+    fn is_configured(&self) -> bool {
+        match self {
+            TokenSource::Env(name) => env::var(name).is_ok_and(|token| !token.is_empty()),
+            #[cfg(feature = "vault")]
+            TokenSource::VaultEnvelope { .. } => true,
+            TokenSource::Static(token) => !token.expose().is_empty(),
+        }
+    }
+
+    /// Fetch the token. An unset variable gives an empty token, which `flush` reports as missing.
+    /// A broken envelope or master key is an error.
+    pub fn resolve(&self) -> Result<SecretBytes, String> {
+        self.resolve_from(&ProcessEnv)
+    }
+
+    /// `resolve`, reading `TokenSource::Env` and the vault's master key from `env` instead of the
+    /// process environment.
+    pub fn resolve_from(&self, env: &dyn EnvSource) -> Result<SecretBytes, String> {
+        match self {
+            TokenSource::Env(name) => Ok(SecretBytes::new(env.var(name).unwrap_or_default().into_bytes())),
+            #[cfg(feature = "vault")]
+            TokenSource::VaultEnvelope { key_env, envelope_path } => {
+                let envelope = crate::vault::EncryptedSecret::load(envelope_path)?;
+                let plaintext = crate::vault::SecretVault::from_env(env, key_env)?.decrypt(&envelope)?;
+                let text = std::str::from_utf8(plaintext.expose()).map_err(|_| "The decrypted token is not text".to_string())?;
+                // `plaintext` is zeroed when it goes out of scope; only the trimmed copy lives on.
+                Ok(SecretBytes::new(text.trim().as_bytes().to_vec()))
+            }
+            TokenSource::Static(token) => Ok(token.clone()),
+        }
+    }
+}
+
+/// Append one line to a log file under its lock (see `lockfile`), creating its folder first.
+fn append_line(path: &Path, message: &str) {
+    let _ = append_locked(path, message);
+}
+
+/// Where one outbound message goes.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Destination {
+    /// A Discord channel, by its numeric id. Sent with the bot token.
+    DiscordChannel(String),
+    /// An HTTPS webhook outside Discord, such as Mattermost. Sent without a token, because the
+    /// secret is part of the URL (see `webhook`).
+    Webhook { url: String },
+}
+
+impl Destination {
+    /// `discord` or `webhook`, for logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Destination::DiscordChannel(_) => "discord",
+            Destination::Webhook { .. } => "webhook",
+        }
+    }
+
+    /// The rate-limiter bucket this destination uses. A Discord channel keeps its bare id, so
+    /// pacing is unchanged; every webhook URL gets a bucket of its own.
+    fn rate_key(&self) -> String {
+        match self {
+            Destination::DiscordChannel(channel_id) => channel_id.clone(),
+            Destination::Webhook { url } => format!("webhook {}", url),
+        }
+    }
+
+    /// Safe form for logs: `discord:<channel id>` or `webhook:<host> path-sha256=<8 hex>`.
+    /// A webhook URL that does not parse is shown as `webhook:<invalid url>`.
+    pub fn redacted(&self) -> String {
+        match self {
+            Destination::DiscordChannel(channel_id) => format!("discord:{}", channel_id),
+            Destination::Webhook { url } => match WebhookUrl::parse(url) {
+                Ok(parsed) => format!("webhook:{}", parsed.redacted()),
+                Err(_) => "webhook:<invalid url>".to_string(),
+            },
+        }
+    }
+}
+
+/// `Debug` and `Display` both show the redacted form, so webhook secrets stay out of logs.
+impl fmt::Debug for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.redacted())
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.redacted())
+    }
+}
+
+/// How urgent a message is. `flush` sends higher priorities first; within one priority, the
+/// oldest message goes first. Ordered from `Low` to `Critical`, so `>` means "more urgent".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Can wait, such as XP announcements.
+    Low,
+    /// Everything that does not say otherwise.
+    #[default]
+    Normal,
+    High,
+    /// Sent even when the destination is out of rate-limit budget (see `RateLimiter::borrow`).
+    /// Keep it for moderation alerts and the like: each one makes later messages wait longer.
+    Critical,
+}
+
+impl Priority {
+    /// Every priority, from least to most urgent.
+    pub const ALL: [Priority; 4] = [Priority::Low, Priority::Normal, Priority::High, Priority::Critical];
+
+    /// `low`, `normal`, `high`, or `critical`, as used in the spool and inbox files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Critical => "critical",
+        }
+    }
+
+    /// Read a name written by `as_str`, ignoring case.
+    pub fn parse(text: &str) -> Option<Priority> {
+        Priority::ALL.into_iter().find(|priority| priority.as_str().eq_ignore_ascii_case(text.trim()))
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What to do at the destination: post a new message, or change or remove one sent before.
+///
+/// Edits and deletes need the id Discord gave the message when it was created, so they only
+/// work for Discord channels; a webhook send has no id to refer back to. They go through the
+/// same queue, spool, and rate-limit bucket as new messages for the channel (see `RateLimiter`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// `POST /channels/{id}/messages` with `body`, or a POST to the webhook.
+    Create { body: String },
+    /// `PATCH /channels/{id}/messages/{message_id}` with `body`, which replaces what is set in it.
+    Edit { message_id: String, body: String },
+    /// `DELETE /channels/{id}/messages/{message_id}`. Sends no body at all.
+    Delete { message_id: String },
+}
+
+impl Action {
+    /// `create`, `edit`, or `delete`, as used in logs and in the spool and inbox files.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Action::Create { .. } => "create",
+            Action::Edit { .. } => "edit",
+            Action::Delete { .. } => "delete",
+        }
+    }
+
+    /// The JSON payload; empty for a delete.
+    pub fn body(&self) -> &str {
+        match self {
+            Action::Create { body } | Action::Edit { body, .. } => body,
+            Action::Delete { .. } => "",
+        }
+    }
+
+    /// The id of the message to change, for edits and deletes.
+    pub fn message_id(&self) -> Option<&str> {
+        match self {
+            Action::Create { .. } => None,
+            Action::Edit { message_id, .. } | Action::Delete { message_id } => Some(message_id),
+        }
+    }
+}
+
+/// Represents a message ready to be sent, to Discord or to a webhook. Build Discord messages
+/// with `MessageBuilder` to have Discord's limits checked first.
+#[derive(Debug, Clone)]
+pub struct OutboundMessage {
+    /// Where the message goes.
+    pub destination: Destination,
+    /// Create, edit, or delete, with the JSON payload as a plain string so it can be inspected
+    /// before send.
+    pub action: Action,
+    /// Which messages go first; `Normal` unless set with `with_priority`.
+    pub priority: Priority,
+    /// Do not send before this time, in Unix milliseconds (the gateway clock's `now_millis`).
+    /// `None` sends at the next flush.
+    pub deliver_after_millis: Option<u128>,
+}
+
+impl OutboundMessage {
+    /// A message for the Discord channel `channel_id`.
+    pub fn discord(channel_id: impl Into<String>, body: impl Into<String>) -> Self {
+        Self::to(Destination::DiscordChannel(channel_id.into()), body)
+    }
+
+    /// A message for the webhook at `url`; `webhook::text_body` builds a plain-text body.
+    pub fn webhook(url: impl Into<String>, body: impl Into<String>) -> Self {
+        Self::to(Destination::Webhook { url: url.into() }, body)
+    }
+
+    /// A `Normal` message for `destination`, sent at the next flush.
+    pub fn to(destination: Destination, body: impl Into<String>) -> Self {
+        Self { destination, action: Action::Create { body: body.into() }, priority: Priority::Normal, deliver_after_millis: None }
+    }
+
+    /// Replace the message `message_id` in the Discord channel `channel_id` with `body`.
+    pub fn edit(channel_id: impl Into<String>, message_id: impl Into<String>, body: impl Into<String>) -> Self {
+        let action = Action::Edit { message_id: message_id.into(), body: body.into() };
+        Self { destination: Destination::DiscordChannel(channel_id.into()), action, priority: Priority::Normal, deliver_after_millis: None }
+    }
+
+    /// Remove the message `message_id` from the Discord channel `channel_id`.
+    pub fn delete(channel_id: impl Into<String>, message_id: impl Into<String>) -> Self {
+        let action = Action::Delete { message_id: message_id.into() };
+        Self { destination: Destination::DiscordChannel(channel_id.into()), action, priority: Priority::Normal, deliver_after_millis: None }
+    }
+
+    /// The JSON payload; empty for a delete.
+    pub fn body(&self) -> &str {
+        self.action.body()
+    }
+
+    /// The same message with another priority.
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// The same message, held back until the Unix time `millis`.
+    pub fn deliver_after(mut self, millis: u128) -> Self {
+        self.deliver_after_millis = Some(millis);
+        self
+    }
+
+    /// Whether the message may be sent at the Unix time `now_millis`.
+    pub fn is_due(&self, now_millis: u128) -> bool {
+        self.deliver_after_millis.is_none_or(|after| after <= now_millis)
+    }
+}
+
+/// Token bucket for one destination. Each send spends one token; tokens trickle back over time.
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    /// Set after a 429: no sends to this destination before this instant.
+    blocked_until: Option<Instant>,
+}
+
+/// Per-destination send pacing. Discord limits each channel separately, so one busy channel must
+/// not slow down the others; each webhook gets its own bucket the same way. The defaults
+/// (5 messages, refilling 1 per second) approximate Discord's "5 messages per 5 seconds per
+/// channel" rule. Buckets are keyed by a channel id, or by `webhook <url>` for webhooks.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(5, 1.0)
+    }
+}
+
+impl RateLimiter {
+    /// `capacity` is the burst size; `refill_per_sec` is how many tokens return each second.
+    pub fn new(capacity: u32, refill_per_sec: f64) -> Self {
+        Self {
+            capacity: f64::from(capacity.max(1)),
+            refill_per_sec: refill_per_sec.max(0.001),
+            buckets: HashMap::new(),
+        }
+    }
+
+    fn bucket(&mut self, key: &str, now: Instant) -> &mut Bucket {
+        let capacity = self.capacity;
+        let rate = self.refill_per_sec;
+        let bucket = self.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+            blocked_until: None,
+        });
+        // Top the bucket up for the time that passed since we last looked.
+        let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.last_refill = now;
+        bucket
+    }
+
+    /// Earliest instant a message for the bucket `key` may be sent.
+    pub fn ready_at(&mut self, key: &str, now: Instant) -> Instant {
+        let rate = self.refill_per_sec;
+        let bucket = self.bucket(key, now);
+        if let Some(blocked_until) = bucket.blocked_until {
+            if blocked_until > now {
+                return blocked_until;
+            }
+            bucket.blocked_until = None;
+        }
+        if bucket.tokens >= 1.0 {
+            now
+        } else {
+            now + Duration::from_secs_f64((1.0 - bucket.tokens) / rate)
+        }
+    }
+
+    /// Spend one token for a send that is happening now.
+    pub fn consume(&mut self, key: &str, now: Instant) {
+        let bucket = self.bucket(key, now);
+        bucket.tokens = (bucket.tokens - 1.0).max(0.0);
+    }
+
+    /// Spend one token even when there is none, for a `Priority::Critical` send. The bucket goes
+    /// below zero, so the messages after it wait until the debt has refilled.
+    pub fn borrow(&mut self, key: &str, now: Instant) {
+        let bucket = self.bucket(key, now);
+        bucket.tokens -= 1.0;
+    }
+
+    /// The end of a 429 block on the bucket `key`, if one is still running. Borrowing does not
+    /// get past it: Discord would only answer 429 again.
+    pub fn blocked_until(&mut self, key: &str, now: Instant) -> Option<Instant> {
+        self.bucket(key, now).blocked_until.filter(|until| *until > now)
+    }
+
+    /// Honour a 429: block the destination for `retry_after_ms` and empty its bucket.
+    pub fn penalize(&mut self, key: &str, retry_after_ms: u64, now: Instant) {
+        let bucket = self.bucket(key, now);
+        bucket.tokens = 0.0;
+        bucket.blocked_until = Some(now + Duration::from_millis(retry_after_ms));
+    }
+
+    /// One-line state for the flush summary, e.g. `2 destination(s), 1 throttled, 0 blocked by 429`.
+    pub fn describe(&mut self, now: Instant) -> String {
+        let channels: Vec<String> = self.buckets.keys().cloned().collect();
+        let mut throttled = 0;
+        let mut blocked = 0;
+        for channel in &channels {
+            let bucket = self.bucket(channel, now);
+            if bucket.blocked_until.is_some_and(|until| until > now) {
+                blocked += 1;
+            } else if bucket.tokens < 1.0 {
+                throttled += 1;
+            }
+        }
+        format!("{} destination(s), {} throttled, {} blocked by 429", channels.len(), throttled, blocked)
+    }
+}
+
+/// Why `SecureDiscordClient::send_message` did not succeed.
+#[derive(Debug, Clone)]
+enum SendError {
+    /// Discord answered 429; wait this long before sending to the channel again.
+    RateLimited { retry_after_ms: u64, summary: String },
+    /// The network or Discord itself had a problem (connect failure, 5xx); worth trying later.
+    Transient(String),
+    /// Discord refused this particular request (4xx); trying again will not help.
+    Failed(String),
+}
+
+/// Read Discord's wait hint from a 429: the `retry-after` header or the JSON body's
+/// `"retry_after"` field, both in (possibly fractional) seconds. Falls back to one second.
+fn retry_after_ms(response: &HttpResponse) -> u64 {
+    let from_header = response.header("retry-after").and_then(|v| v.trim().parse::<f64>().ok());
+    let from_body = response.body.split_once("\"retry_after\"").and_then(|(_, rest)| {
+        let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+        let end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+        rest[..end].parse::<f64>().ok()
+    });
+    from_header
+        .or(from_body)
+        .map(|seconds| (seconds * 1000.0).ceil() as u64)
+        .unwrap_or(1000)
+}
+
+/// Append-only journal of queued messages so a crash between `enqueue` and `flush` loses
+/// nothing.
+///
+/// Record layout (one per line, so the file stays readable in a text editor):
+/// - `msg <id> <checksum> <length>:<destination>\t<body>` where `<length>` counts the bytes of
+///   `<destination>\t<body>` and `<checksum>` is their FNV-1a hash in hex. The length lets bodies
+///   contain newlines; the checksum catches records that were only half written. The destination
+///   is a Discord channel id, or `webhook=<url>` for a webhook; a webhook URL is a secret, so the
+///   spool file must stay as private as the rest of `Discovery/`. A message that is not `Normal`
+///   or has a schedule carries `priority=<name>` and `after=<unix millis>` after the length, as in
+///   `msg 7 1a2b3c4d 31 priority=critical:<destination>\t<body>`.
+/// - `ack <id>` marks a message as finished (sent, or rejected for good).
+///
+/// `load` replays the file, drops acknowledged messages, skips damaged records with a warning,
+/// and rewrites a compacted copy.
+pub struct Spool {
+    path: PathBuf,
+}
+
+impl Spool {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Read every unacknowledged message in enqueue order, then compact the file so later
+    /// appends never land after a damaged tail. Warnings describe each skipped record.
+    pub fn load(&self) -> (Vec<(u64, OutboundMessage)>, Vec<String>) {
+        let mut warnings = Vec::new();
+        let raw = match fs::read(&self.path) {
+            Ok(raw) => raw,
+            Err(_) => return (Vec::new(), warnings),
+        };
+
+        let mut messages: Vec<(u64, OutboundMessage)> = Vec::new();
+        let mut acked = Vec::new();
+        let mut position = 0usize;
+        while position < raw.len() {
+            match parse_spool_record(&raw[position..]) {
+                Ok((SpoolRecord::Message(id, message), used)) => {
+                    messages.push((id, message));
+                    position += used;
+                }
+                Ok((SpoolRecord::Ack(id), used)) => {
+                    acked.push(id);
+                    position += used;
+                }
+                Err(reason) => {
+                    warnings.push(format!("skipped damaged spool record at byte {}: {}", position, reason));
+                    // Resynchronise at the next line that starts a record.
+                    position = match raw[position..].iter().position(|&b| b == b'\n') {
+                        Some(offset) => position + offset + 1,
+                        None => raw.len(),
+                    };
+                }
+            }
+        }
+
+        messages.retain(|(id, _)| !acked.contains(id));
+        if let Err(err) = self.rewrite(&messages) {
+            warnings.push(format!("could not compact spool: {}", err));
+        }
+        (messages, warnings)
+    }
+
+    /// Durably record a newly queued message.
+    pub fn append_message(&self, id: u64, message: &OutboundMessage) -> std::io::Result<()> {
+        self.append(&encode_spool_message(id, message))
+    }
+
+    /// Durably record that a message no longer needs sending.
+    pub fn append_ack(&self, id: u64) -> std::io::Result<()> {
+        self.append(format!("ack {}\n", id).as_bytes())
+    }
+
+    /// Replace the spool with just `messages` through `atomic_write`, so a crash mid-write
+    /// leaves either the old or the new file, never a mix.
+    pub fn rewrite(&self, messages: &[(u64, OutboundMessage)]) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents: Vec<u8> = messages.iter().flat_map(|(id, message)| encode_spool_message(*id, message)).collect();
+        atomic_write(&self.path, &contents)
+    }
+
+    fn append(&self, record: &[u8]) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::options().create(true).append(true).open(&self.path)?;
+        file.write_all(record)?;
+        file.sync_data()
+    }
+}
+
+enum SpoolRecord {
+    Message(u64, OutboundMessage),
+    Ack(u64),
+}
+
+fn encode_spool_message(id: u64, message: &OutboundMessage) -> Vec<u8> {
+    let destination = match &message.destination {
+        Destination::DiscordChannel(channel_id) => channel_id.clone(),
+        Destination::Webhook { url } => format!("webhook={}", url),
+    };
+    let payload = format!("{}\t{}", destination, message.body());
+    // Priority, schedule, and edits or deletes are only written when set, so plain messages keep
+    // the old layout. A delete's payload ends right after the tab.
+    let mut extra = String::new();
+    if let Some(message_id) = message.action.message_id() {
+        extra.push_str(&format!(" action={} message_id={}", message.action.kind(), message_id));
+    }
+    if message.priority != Priority::Normal {
+        extra.push_str(&format!(" priority={}", message.priority));
+    }
+    if let Some(after) = message.deliver_after_millis {
+        extra.push_str(&format!(" after={}", after));
+    }
+    format!("msg {} {:08x} {}{}:{}\n", id, fnv1a(payload.as_bytes()), payload.len(), extra, payload).into_bytes()
+}
+
+/// Parse one record from the start of `raw`, returning it and the number of bytes it used.
+fn parse_spool_record(raw: &[u8]) -> Result<(SpoolRecord, usize), String> {
+    if let Some(rest) = raw.strip_prefix(b"ack ") {
+        let end = rest.iter().position(|&b| b == b'\n').ok_or("ack record has no line ending")?;
+        let id = std::str::from_utf8(&rest[..end])
+            .ok()
+            .and_then(|text| text.parse::<u64>().ok())
+            .ok_or("ack record has a bad id")?;
+        return Ok((SpoolRecord::Ack(id), 4 + end + 1));
+    }
+
+    let rest = raw.strip_prefix(b"msg ").ok_or("unknown record type")?;
+    let colon = rest.iter().position(|&b| b == b':').ok_or("message header has no ':'")?;
+    let header = std::str::from_utf8(&rest[..colon]).map_err(|_| "message header is not UTF-8")?;
+    let fields: Vec<&str> = header.split(' ').collect();
+    let [id, checksum, length, ref extra @ ..] = fields[..] else {
+        return Err("message header needs id, checksum, and length".to_string());
+    };
+    let (mut priority, mut deliver_after_millis) = (Priority::Normal, None);
+    let (mut action, mut message_id) = ("create", None);
+    for field in extra {
+        match field.split_once('=') {
+            Some(("action", kind)) if ["edit", "delete"].contains(&kind) => action = kind,
+            Some(("message_id", id)) => message_id = Some(id.to_string()),
+            Some(("priority", name)) => priority = Priority::parse(name).ok_or("message has a bad priority")?,
+            Some(("after", millis)) => deliver_after_millis = Some(millis.parse::<u128>().map_err(|_| "message has a bad after= time")?),
+            _ => return Err(format!("message header has an unknown field {:?}", field)),
+        }
+    }
+    let id = id.parse::<u64>().map_err(|_| "message has a bad id")?;
+    let checksum = u32::from_str_radix(checksum, 16).map_err(|_| "message has a bad checksum field")?;
+    let length = length.parse::<usize>().map_err(|_| "message has a bad length")?;
+
+    let payload_start = colon + 1;
+    let payload = rest
+        .get(payload_start..payload_start + length)
+        .ok_or("message is shorter than its length (truncated write?)")?;
+    if rest.get(payload_start + length) != Some(&b'\n') {
+        return Err("message is missing its line ending (truncated write?)".to_string());
+    }
+    if fnv1a(payload) != checksum {
+        return Err("message checksum does not match".to_string());
+    }
+
+    let payload = std::str::from_utf8(payload).map_err(|_| "message payload is not UTF-8")?;
+    let (destination, body) = payload.split_once('\t').ok_or("message payload has no channel separator")?;
+    // Records written before webhooks existed hold a bare channel id.
+    let destination = match destination.strip_prefix("webhook=") {
+        Some(url) => Destination::Webhook { url: url.to_string() },
+        None => Destination::DiscordChannel(destination.to_string()),
+    };
+    let action = match (action, message_id) {
+        ("create", None) => Action::Create { body: body.to_string() },
+        ("edit", Some(message_id)) => Action::Edit { message_id, body: body.to_string() },
+        ("delete", Some(message_id)) if body.is_empty() => Action::Delete { message_id },
+        ("delete", Some(_)) => return Err("delete record carries a body".to_string()),
+        _ => return Err("message header has action= and message_id= out of step".to_string()),
+    };
+    let message = OutboundMessage { destination, action, priority, deliver_after_millis };
+    Ok((SpoolRecord::Message(id, message), 4 + payload_start + length + 1))
+}
+
+/// 32-bit FNV-1a: a tiny checksum that is plenty for spotting torn writes (not for security).
+fn fnv1a(data: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in data {
+        hash ^= u32::from(*byte);
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+/// Counts from one `poll_inbox` pass.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InboxReport {
+    /// `type=message` files turned into queued messages.
+    pub queued: usize,
+    /// `type=sync-commands` files that triggered a slash-command sync.
+    pub syncs: usize,
+    /// Files moved to `rejected/` with a `.reason` sidecar.
+    pub rejected: usize,
+}
+
+/// A command read from one inbox file.
+enum InboxCommand {
+    Message(OutboundMessage),
+    SyncCommands,
+}
+
+/// Sort key for `<millis>-<seq>.cmd`, compared as numbers so `9-1` sorts before `10-1`.
+fn inbox_sort_key(file_name: &str) -> Option<(u128, u64)> {
+    let stem = file_name.strip_suffix(".cmd")?;
+    let (millis, seq) = stem.split_once('-')?;
+    Some((millis.parse().ok()?, seq.parse().ok()?))
+}
+
+/// Parse an inbox file. Header lines are `key=value`; `body=` must come last and everything
+/// after it (newlines included) is the body, so JSON payloads can span lines. A message names
+/// either `channel_id=` or `webhook_url=`, and may add `priority=` and `deliver_after_millis=`.
+/// `action=edit` or `action=delete` with `message_id=` changes or removes a message sent to a
+/// Discord channel before; an edit needs `body=`, and a delete must not have one.
+fn parse_inbox_command(contents: &str) -> Result<InboxCommand, String> {
+    let mut kind = None;
+    let mut action = None;
+    let mut message_id = None;
+    let mut channel_id = None;
+    let mut webhook_url = None;
+    let mut priority = Priority::Normal;
+    let mut deliver_after_millis = None;
+    let mut body = None;
+
+    let mut rest = contents;
+    while !rest.is_empty() {
+        let (line, remaining) = rest.split_once('\n').unwrap_or((rest, ""));
+        let line = line.trim_end_matches('\r');
+        if line.starts_with("body=") {
+            // Keep everything that follows, minus the single trailing newline editors add.
+            let full = &rest["body=".len()..];
+            body = Some(full.strip_suffix('\n').unwrap_or(full).to_string());
+            break;
+        }
+        rest = remaining;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| format!("line {:?} is not key=value", line))?;
+        match key.trim() {
+            "type" => kind = Some(value.trim().to_string()),
+            "channel_id" => channel_id = Some(value.trim().to_string()),
+            "webhook_url" => webhook_url = Some(value.trim().to_string()),
+            "action" => action = Some(value.trim().to_string()),
+            "message_id" => message_id = Some(value.trim().to_string()),
+            "priority" => {
+                priority = Priority::parse(value).ok_or_else(|| format!("priority {:?} is not low, normal, high, or critical", value.trim()))?
+            }
+            "deliver_after_millis" => {
+                let millis = value.trim().parse::<u128>().map_err(|_| format!("deliver_after_millis {:?} is not Unix milliseconds", value.trim()))?;
+                deliver_after_millis = Some(millis);
+            }
+            other => return Err(format!("unknown key {:?}", other)),
+        }
+    }
+
+    match kind.as_deref() {
+        Some("message") => {
+            let destination = match (channel_id, webhook_url) {
+                (Some(channel_id), None) => {
+                    if let Err(reason) = Snowflake::parse(&channel_id) {
+                        return Err(format!("channel_id {:?} is not a Discord id: it {}", channel_id, reason));
+                    }
+                    Destination::DiscordChannel(channel_id)
+                }
+                (None, Some(url)) => {
+                    // The reason names the broken part without repeating the secret URL.
+                    WebhookUrl::parse(&url)?;
+                    Destination::Webhook { url }
+                }
+                (Some(_), Some(_)) => return Err("message names both channel_id= and webhook_url=".to_string()),
+                (None, None) => return Err("message is missing channel_id= (or webhook_url=)".to_string()),
+            };
+            if let Some(message_id) = &message_id {
+                if let Err(reason) = Snowflake::parse(message_id) {
+                    return Err(format!("message_id {:?} is not a Discord id: it {}", message_id, reason));
+                }
+            }
+            let action = match (action.as_deref().unwrap_or("create"), message_id) {
+                ("create", None) => Action::Create { body: body.filter(|b| !b.trim().is_empty()).ok_or("message is missing body=")? },
+                ("create", Some(_)) => return Err("message_id= is only for action=edit or action=delete".to_string()),
+                ("edit" | "delete", _) if matches!(destination, Destination::Webhook { .. }) => {
+                    return Err("webhook messages cannot be edited or deleted; use channel_id=".to_string())
+                }
+                ("edit", Some(message_id)) => {
+                    Action::Edit { message_id, body: body.filter(|b| !b.trim().is_empty()).ok_or("edit is missing body=")? }
+                }
+                ("delete", Some(_)) if body.is_some() => return Err("delete carries no body=; remove it".to_string()),
+                ("delete", Some(message_id)) => Action::Delete { message_id },
+                ("edit" | "delete", None) => return Err("edit and delete need message_id=".to_string()),
+                (other, _) => return Err(format!("action {:?} is not create, edit, or delete", other)),
+            };
+            Ok(InboxCommand::Message(OutboundMessage { destination, action, priority, deliver_after_millis }))
+        }
+        Some("sync-commands") => Ok(InboxCommand::SyncCommands),
+        Some(other) => Err(format!("unknown type {:?}", other)),
+        None => Err("missing type=".to_string()),
+    }
+}
+
+/// The gateway's waiting messages in sending order: the highest priority first, and the oldest
+/// (lowest id) first within one priority. Ids only ever grow, so a message put back after a
+/// failed send, or restored from the spool, returns to its old place instead of the back.
+#[derive(Debug, Clone, Default)]
+pub struct OutboundQueue {
+    entries: BTreeMap<(Reverse<Priority>, u64), OutboundMessage>,
+}
+
+impl OutboundQueue {
+    pub fn push(&mut self, id: u64, message: OutboundMessage) {
+        self.entries.insert((Reverse(message.priority), id), message);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Every message with its id, in sending order.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &OutboundMessage)> {
+        self.entries.iter().map(|((_, id), message)| (*id, message))
+    }
+
+    /// Take out the message with `id`.
+    pub fn remove(&mut self, id: u64) -> Option<OutboundMessage> {
+        let key = *self.entries.keys().find(|(_, entry_id)| *entry_id == id)?;
+        self.entries.remove(&key)
+    }
+
+    /// Take out the first message, in sending order, that may be sent at the Unix time
+    /// `now_millis`. Messages scheduled for later stay where they are.
+    pub fn pop_due(&mut self, now_millis: u128) -> Option<(u64, OutboundMessage)> {
+        let id = self.iter().find(|(_, message)| message.is_due(now_millis)).map(|(id, _)| id)?;
+        self.remove(id).map(|message| (id, message))
+    }
+
+    /// Take out every message that may be sent at `now_millis`, keeping their order.
+    pub fn take_due(&mut self, now_millis: u128) -> OutboundQueue {
+        let mut due = OutboundQueue::default();
+        while let Some((id, message)) = self.pop_due(now_millis) {
+            due.push(id, message);
+        }
+        due
+    }
+
+    /// Messages held back by `deliver_after_millis` at `now_millis`.
+    pub fn scheduled(&self, now_millis: u128) -> usize {
+        self.entries.values().filter(|message| !message.is_due(now_millis)).count()
+    }
+
+    /// The most urgent priority waiting, if anything is.
+    pub fn highest_priority(&self) -> Option<Priority> {
+        self.entries.keys().next().map(|(Reverse(priority), _)| *priority)
+    }
+}
+
+/// What one `flush` did, and what it left for the next one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlushReport {
+    pub sent: u32,
+    pub failed: u32,
+    pub rate_limited: u32,
+    /// `Critical` messages sent while their destination had no budget left.
+    pub borrowed: u32,
+    /// Messages still queued afterwards: scheduled for later, or kept after a failure (or the
+    /// whole queue, when the flush could not send at all).
+    pub deferred: usize,
+    /// How many of `deferred` are waiting for their `deliver_after_millis`.
+    pub scheduled: usize,
+    /// The most urgent priority among `deferred`.
+    pub highest_waiting: Option<Priority>,
+}
+
+/// The first message, in sending order, whose destination has budget at `now`, or the earliest
+/// instant one will. Messages for the same destination share a bucket, so this keeps
+/// per-destination order while letting quiet channels and webhooks overtake a throttled one.
+fn next_sendable(pending: &OutboundQueue, limiter: &mut RateLimiter, now: Instant) -> Result<u64, Instant> {
+    let mut earliest: Option<Instant> = None;
+    for (id, item) in pending.iter() {
+        let rate_key = item.destination.rate_key();
+        // A critical message only waits out a 429 block, never an empty bucket.
+        let ready_at = match item.priority {
+            Priority::Critical => limiter.blocked_until(&rate_key, now).unwrap_or(now),
+            _ => limiter.ready_at(&rate_key, now),
+        };
+        if ready_at <= now {
+            return Ok(id);
+        }
+        earliest = Some(earliest.map_or(ready_at, |e| e.min(ready_at)));
+    }
+    Err(earliest.unwrap_or(now))
+}
+
+/// What `DiscordGateway::plan` expects to happen to one queued message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanStatus {
+    /// Sent by this flush, `send_offset` after it starts.
+    Send,
+    /// Held back by `deliver_after_millis`; a later flush sends it.
+    Scheduled,
+    /// Due, but the flush would send nothing (see `FlushPlan::blocked`).
+    Blocked,
+}
+
+impl PlanStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PlanStatus::Send => "send",
+            PlanStatus::Scheduled => "scheduled",
+            PlanStatus::Blocked => "blocked",
+        }
+    }
+}
+
+/// One message in a `FlushPlan`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedMessage {
+    /// The id of its spool record.
+    pub id: u64,
+    /// `Destination::redacted`, so a plan can be shared without webhook secrets.
+    pub destination: String,
+    /// `create`, `edit`, or `delete`.
+    pub action: &'static str,
+    pub priority: Priority,
+    pub deliver_after_millis: Option<u128>,
+    pub body_bytes: usize,
+    pub status: PlanStatus,
+    /// How long after the flush starts it goes out, following the rate limiter. `None` unless
+    /// `status` is `Send`.
+    pub send_offset: Option<Duration>,
+    /// A `Critical` message sent on a borrowed token (see `RateLimiter::borrow`).
+    pub borrowed: bool,
+    /// Why the destination would refuse it (`message::validate_raw`), or `None` when it passes.
+    /// `flush` still tries such a message once and then drops it.
+    pub invalid: Option<String>,
+}
+
+/// What a flush started now would do: the result of `DiscordGateway::plan`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlushPlan {
+    /// Why the flush would send nothing (no token, presence file missing, unsigned, or revoked),
+    /// or `None` when it would send.
+    pub blocked: Option<String>,
+    /// Every queued message: what this flush sends, in sending order, then what it cannot send
+    /// (blocked), then what waits for its time (scheduled).
+    pub messages: Vec<PlannedMessage>,
+}
+
+impl FlushPlan {
+    /// How many messages have `status`.
+    pub fn count(&self, status: PlanStatus) -> usize {
+        self.messages.iter().filter(|message| message.status == status).count()
+    }
+
+    /// The plan as JSON lines: one summary, then one line per message in `messages` order.
+    ///
+    /// ```text
+    /// {"action":"plan","blocked":null,"queued":3,"send":2,"scheduled":1,"blocked_messages":0,"invalid":0,"duration_ms":1000}
+    /// {"id":4,"status":"send","destination":"discord:123","action":"create","priority":"high","send_offset_ms":0,...}
+    /// ```
+    pub fn to_json_lines(&self) -> Vec<String> {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        let quoted = |text: &str| format!("\"{}\"", json_escape(text));
+        let duration = self.messages.iter().filter_map(|message| message.send_offset).max().unwrap_or_default();
+        let mut lines = vec![format!(
+            "{{\"action\":\"plan\",\"blocked\":{},\"queued\":{},\"send\":{},\"scheduled\":{},\"blocked_messages\":{},\"invalid\":{},\"duration_ms\":{}}}",
+            optional(self.blocked.as_deref().map(quoted)),
+            self.messages.len(),
+            self.count(PlanStatus::Send),
+            self.count(PlanStatus::Scheduled),
+            self.count(PlanStatus::Blocked),
+            self.messages.iter().filter(|message| message.invalid.is_some()).count(),
+            duration.as_millis()
+        )];
+        for message in &self.messages {
+            lines.push(format!(
+                "{{\"id\":{},\"status\":\"{}\",\"destination\":{},\"action\":\"{}\",\"priority\":\"{}\",\"send_offset_ms\":{},\"borrowed\":{},\"deliver_after_millis\":{},\"body_bytes\":{},\"valid\":{},\"error\":{}}}",
+                message.id,
+                message.status.as_str(),
+                quoted(&message.destination),
+                message.action,
+                message.priority.as_str(),
+                optional(message.send_offset.map(|offset| offset.as_millis().to_string())),
+                message.borrowed,
+                optional(message.deliver_after_millis.map(|millis| millis.to_string())),
+                message.body_bytes,
+                message.invalid.is_none(),
+                optional(message.invalid.as_deref().map(quoted))
+            ));
+        }
+        lines
+    }
+}
+
+/// Minimal gateway that queues messages and flushes them through a `Transport`.
+pub struct DiscordGateway {
+    /// Queued messages with the id used for their spool records.
+    queue: OutboundQueue,
+    next_id: u64,
+    spool: Option<Spool>,
+    transport: Box<dyn Transport>,
+    rate_limiter: RateLimiter,
+    layout: DiscoveryLayout,
+    commands: CommandRegistry,
+    /// Where the bot token comes from; read again at every flush.
+    token_source: TokenSource,
+    /// `short_digest` of the last token used, so a rotated token is logged once without the
+    /// token itself ever being kept.
+    token_fingerprint: Option<u64>,
+    /// Heartbeats written by this process. Starts again at 1 after a restart, which is how the
+    /// hub notices one.
+    heartbeat_seq: u64,
+    /// SHA-256 of this executable, worked out the first time a challenge is answered.
+    binary_sha256: Option<[u8; 32]>,
+    /// Whether `layout.root` has been created; checked once per layout, not on every tick.
+    layout_ready: bool,
+    /// Time for heartbeats, presence checks, and rate limits; the real clock unless `with_clock`.
+    clock: Rc<dyn Clock>,
+    /// Waits for a rate-limit bucket to refill; really sleeps unless `with_sleeper`.
+    sleeper: Rc<dyn Sleeper>,
+    /// Token, application id, and presence settings; the process environment unless `with_env`.
+    env: Rc<dyn EnvSource>,
+}
+
+impl Default for DiscordGateway {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DiscordGateway {
+    /// Create a new gateway instance with an empty queue and the transport the environment asks
+    /// for (see `default_transport`).
+    pub fn new() -> Self {
+        Self::with_transport(default_transport())
+    }
+
+    /// Create a gateway that sends through `transport`, e.g. a mock that records requests.
+    pub fn with_transport(transport: Box<dyn Transport>) -> Self {
+        Self {
+            queue: OutboundQueue::default(),
+            next_id: 1,
+            spool: None,
+            transport,
+            rate_limiter: RateLimiter::default(),
+            layout: DiscoveryLayout::default(),
+            commands: CommandRegistry::default(),
+            token_source: TokenSource::default(),
+            token_fingerprint: None,
+            heartbeat_seq: 0,
+            binary_sha256: None,
+            layout_ready: false,
+            clock: Rc::new(SystemClock),
+            sleeper: Rc::new(ThreadSleeper),
+            env: Rc::new(ProcessEnv),
+        }
+    }
+
+    /// Create a gateway with a token the caller already loaded, such as the one from
+    /// `config.json`. The transport is chosen as in `new`, but from this token instead of
+    /// `SQUIRE_DISCORD_TOKEN`, and the environment is never read for the token again.
+    pub fn with_token(token: String) -> Self {
+        Self::from_token_source(TokenSource::Static(SecretBytes::new(token.into_bytes())))
+    }
+
+    /// Create a gateway that fetches its token from `source` at every flush, such as a vault
+    /// envelope. The transport is chosen as in `new`.
+    pub fn from_token_source(source: TokenSource) -> Self {
+        let transport = transport_for_source(&source);
+        Self::with_transport(transport).with_token_source(source)
+    }
+
+    /// Take the token from `source` but keep the transport, e.g. a mock one in a test.
+    pub fn with_token_source(mut self, source: TokenSource) -> Self {
+        self.token_source = source;
+        self
+    }
+
+    /// Use `clock` for heartbeats, presence freshness, and rate limiting. Pass the same
+    /// `runtime::ManualClock` to `with_sleeper` so waiting for a bucket moves that clock.
+    pub fn with_clock(mut self, clock: Rc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Use `sleeper` when every destination is out of rate-limit budget.
+    pub fn with_sleeper(mut self, sleeper: Rc<dyn Sleeper>) -> Self {
+        self.sleeper = sleeper;
+        self
+    }
+
+    /// Read the token (for `TokenSource::Env`), `SQUIRE_APPLICATION_ID`, and the presence key and
+    /// TTL from `env` instead of the process environment. The transport was already picked when
+    /// the gateway was created.
+    pub fn with_env(mut self, env: Rc<dyn EnvSource>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Fetch the token for one flush and log its fingerprint when it is new. The text stays
+    /// inside the returned `SecretBytes`, which zeroes it when dropped.
+    fn token(&mut self) -> Result<SecretBytes, String> {
+        let token = self.token_source.resolve_from(&*self.env)?;
+        let text = std::str::from_utf8(token.expose()).map_err(|_| "The bot token is not text".to_string())?;
+        if !text.is_empty() {
+            let fingerprint = short_digest(text);
+            if self.token_fingerprint != Some(fingerprint) {
+                LOG.info(
+                    "Using bot token",
+                    &[("source", self.token_source.kind()), ("fingerprint", &format!("{:016x}", fingerprint))],
+                );
+                self.token_fingerprint = Some(fingerprint);
+            }
+        }
+        Ok(token)
+    }
+
+    /// Read and write the files in `layout` instead of the default one. Two gateways with
+    /// different layouts share nothing on disk, so they can run side by side.
+    pub fn with_layout(mut self, layout: DiscoveryLayout) -> Self {
+        self.layout = layout;
+        self.layout_ready = false;
+        self
+    }
+
+    /// Create the `Discovery/` folder the first time this gateway writes into it. A failure is
+    /// logged and tried again next time.
+    fn ensure_layout(&mut self) {
+        if self.layout_ready {
+            return;
+        }
+        match fs::create_dir_all(&self.layout.root) {
+            Ok(()) => self.layout_ready = true,
+            Err(err) => LOG.warn(
+                "Could not create Discovery folder",
+                &[("path", &self.layout.root.display().to_string()), ("error", &err.to_string())],
+            ),
+        }
+    }
+
+    /// The files this gateway uses.
+    pub fn layout(&self) -> &DiscoveryLayout {
+        &self.layout
+    }
+
+    /// The slash commands `sync_commands` keeps registered. The default is an empty set.
+    pub fn with_commands(mut self, commands: CommandRegistry) -> Self {
+        self.commands = commands;
+        self
+    }
+
+    /// Replace the default pacing (5 messages, 1 token per second, per channel).
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Keep the queue in a spool file (see `Spool`) and reload anything a previous run left
+    /// unsent. Pass `layout().spool_file` for the standard location.
+    pub fn with_spool(mut self, path: impl Into<PathBuf>) -> Self {
+        let spool = Spool::new(path);
+        let (messages, warnings) = spool.load();
+        for warning in warnings {
+            LOG.warn("Spool warning", &[("detail", &warning)]);
+        }
+        if !messages.is_empty() {
+            LOG.info("Restored unsent messages from the spool", &[("count", &messages.len().to_string())]);
+        }
+        self.next_id = messages.iter().map(|(id, _)| id + 1).max().unwrap_or(1).max(self.next_id);
+        for (id, message) in messages {
+            self.queue.push(id, message);
+        }
+        self.spool = Some(spool);
+        self
+    }
+
+    /// Accept a payload prepared by a Python module and enqueue it for sending. Its `priority`
+    /// and `deliver_after_millis` decide which flush sends it, and in what order.
+    pub fn enqueue(&mut self, msg: OutboundMessage) {
+        let id = self.next_id;
+        self.next_id += 1;
+        if let Some(spool) = &self.spool {
+            if let Err(err) = spool.append_message(id, &msg) {
+                LOG.error("Could not spool message", &[("id", &id.to_string()), ("error", &err.to_string())]);
+            }
+        }
+        self.queue.push(id, msg);
+    }
+
+    /// Like `enqueue`, but refuse a raw body that Discord would reject: a non-numeric channel,
+    /// an empty body, or `content` longer than 2000 characters (see `message::validate_raw`).
+    /// Webhook messages are refused for an empty body or a URL that is not `https://`.
+    /// Messages from `MessageBuilder` were already checked in full by `build()`.
+    pub fn enqueue_validated(&mut self, msg: OutboundMessage) -> Result<(), MessageError> {
+        validate_raw(&msg)?;
+        self.enqueue(msg);
+        Ok(())
+    }
+
+    /// Replace `Discovery/heartbeat.txt` with this process id, the next sequence number, and
+    /// the time. It is written before anything else in `flush`, so a gateway that is up but
+    /// waiting for the hub still counts as alive. A failed write is logged and otherwise ignored.
+    pub fn write_heartbeat(&mut self) {
+        self.ensure_layout();
+        self.heartbeat_seq += 1;
+        let line = format!("pid={} seq={} at={}\n", std::process::id(), self.heartbeat_seq, self.clock.now_millis());
+        if let Err(err) = atomic_write(&self.layout.heartbeat_file, line.as_bytes()) {
+            LOG.warn("Could not write heartbeat", &[("error", &err.to_string())]);
+        }
+    }
+
+    /// Answer the hub's challenge, proving this is the bot the hub handed a key to.
+    ///
+    /// The hub writes a random nonce to `Discovery/challenge.txt`. The answer in
+    /// `Discovery/challenge_response.txt` is
+    ///
+    /// ```text
+    /// nonce=<the nonce>
+    /// binary_sha256=<SHA-256 of this executable>
+    /// at=<unix millis>
+    /// signature=<hex HMAC-SHA256(ECOSYSTEM_PRESENCE_KEY, nonce bytes || binary_sha256 bytes)>
+    /// ```
+    ///
+    /// The key is this bot's derived presence key, so a process squatting on the folder without
+    /// it cannot answer, and the hash ties the answer to the binary that is running. A response is
+    /// written when the nonce is new or the last answer is half a `ttl_secs` old, so the hub
+    /// keeps seeing a fresh one. Returns `Ok(true)` when a response was written and `Ok(false)`
+    /// when there was nothing to do, including when there is no challenge file at all (a hub that
+    /// does not ask, or has not asked yet).
+    pub fn answer_challenge(&mut self) -> Result<bool, String> {
+        let Ok(text) = fs::read_to_string(&self.layout.challenge_file) else {
+            return Ok(false);
+        };
+        let field = |text: &str, name: &str| text.lines().find_map(|line| line.strip_prefix(name)).map(|value| value.trim().to_string());
+        let nonce = field(&text, "nonce=").ok_or("challenge file has no nonce= line")?;
+        let nonce_bytes = parse_hex(&nonce).filter(|bytes| !bytes.is_empty()).ok_or("challenge nonce is not hex")?;
+        let ttl_ms = field(&text, "ttl_secs=").and_then(|value| value.parse::<u128>().ok()).unwrap_or(0) * 1000;
+
+        let now = self.clock.now_millis();
+        let previous = fs::read_to_string(&self.layout.challenge_response_file).unwrap_or_default();
+        let answered_at = field(&previous, "at=").and_then(|value| value.parse::<u128>().ok());
+        let same_nonce = field(&previous, "nonce=").as_deref() == Some(nonce.as_str());
+        if same_nonce && answered_at.is_some_and(|at| now.saturating_sub(at) < ttl_ms / 2) {
+            return Ok(false);
+        }
+
+        let PresenceKey::Hmac(key) = load_presence_key(&*self.env)? else {
+            return Err(format!("challenges need an HMAC presence key; {} keys cannot sign them", PRESENCE_LEGACY_ENV));
+        };
+        let digest = match self.binary_sha256 {
+            Some(digest) => digest,
+            None => {
+                let exe = env::current_exe().map_err(|err| format!("Unable to find the running executable: {err}"))?;
+                let bytes = fs::read(&exe).map_err(|err| format!("Unable to read {}: {err}", exe.display()))?;
+                *self.binary_sha256.insert(sha256(&bytes))
+            }
+        };
+        let binary_sha256 = to_hex(&digest);
+        let mut message = nonce_bytes;
+        message.extend_from_slice(&digest);
+        let response = format!(
+            "nonce={}\nbinary_sha256={}\nat={}\nsignature={}\n",
+            nonce,
+            binary_sha256,
+            now,
+            to_hex(&hmac_sha256(&key, &message))
+        );
+        atomic_write(&self.layout.challenge_response_file, response.as_bytes()).map_err(|err| format!("Could not write the challenge response: {err}"))?;
+        if !same_nonce {
+            LOG.info("Answered the hub's challenge", &[("binary_sha256", &binary_sha256)]);
+        }
+        Ok(true)
+    }
+
+    /// Process command files the hub left in `Discovery/gateway_inbox/`, oldest first.
+    ///
+    /// Writers should create `<millis>-<seq>.tmp` and rename it to `.cmd` when complete, so the
+    /// gateway never reads a half-written command. Handled files move to `processed/`; bad ones
+    /// move to `rejected/` next to a `<name>.reason` file explaining why.
+    pub fn poll_inbox(&mut self) -> InboxReport {
+        let mut report = InboxReport::default();
+        let inbox = self.layout.inbox_dir.clone();
+        let Ok(entries) = fs::read_dir(&inbox) else {
+            return report;
+        };
+
+        let mut files: Vec<(Option<(u128, u64)>, String)> = entries
+            .flatten()
+            .filter(|entry| entry.path().is_file())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(".cmd"))
+            .map(|name| (inbox_sort_key(&name), name))
+            .collect();
+        // Badly named files sort first so they are rejected before anything else runs.
+        files.sort();
+
+        for (key, name) in files {
+            let path = inbox.join(&name);
+            let outcome = match key {
+                None => Err("file name must look like <millis>-<seq>.cmd".to_string()),
+                Some(_) => fs::read_to_string(&path)
+                    .map_err(|err| format!("unreadable: {}", err))
+                    .and_then(|contents| parse_inbox_command(&contents)),
+            };
+
+            let destination = match outcome {
+                Ok(InboxCommand::Message(message)) => {
+                    self.enqueue(message);
+                    report.queued += 1;
+                    "processed"
+                }
+                Ok(InboxCommand::SyncCommands) => {
+                    self.sync_slash_commands();
+                    report.syncs += 1;
+                    "processed"
+                }
+                Err(reason) => {
+                    report.rejected += 1;
+                    let reason_path = inbox.join("rejected").join(format!("{}.reason", name));
+                    let _ = fs::create_dir_all(inbox.join("rejected"));
+                    let _ = atomic_write(&reason_path, format!("{}\n", reason).as_bytes());
+                    LOG.warn("Rejected inbox file", &[("file", &name), ("reason", &reason)]);
+                    "rejected"
+                }
+            };
+
+            let _ = fs::create_dir_all(inbox.join(destination));
+            if let Err(err) = fs::rename(&path, inbox.join(destination).join(&name)) {
+                LOG.error("Could not move inbox file", &[("file", &name), ("error", &err.to_string())]);
+            }
+        }
+
+        report
+    }
+
+    /// Number of messages waiting to be sent, so the hub can report backlog.
+    pub fn pending_len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Check whether the ecosystem presence file exists, which signals that
+    /// cross-bot communication is permitted.
+    fn ecosystem_ready(&self) -> bool {
+        match self.validate_presence_file() {
+            Ok(valid) => valid,
+            Err(err) if err.starts_with(PRESENCE_REVOKED_ERROR) => {
+                LOG.info("Hub revoked presence; cross-bot messages stay off until it announces again", &[("reason", &err)]);
+                false
+            }
+            Err(err) => {
+                LOG.warn("Presence validation failed", &[("reason", &err)]);
+                false
+            }
+        }
+    }
+
+    /// Sync slash commands with the token from the environment and log the outcome. Runs during
+    /// every flush and for `type=sync-commands` inbox files; unchanged commands cost nothing.
+    fn sync_slash_commands(&mut self) {
+        let token = match self.token() {
+            Ok(token) => token,
+            Err(err) => {
+                LOG.warn("Slash-command sync skipped", &[("reason", &err)]);
+                return;
+            }
+        };
+        match self.sync_commands(&String::from_utf8_lossy(token.expose())) {
+            Ok(changes) if changes.is_empty() => LOG.debug("Slash commands unchanged; nothing to sync", &[]),
+            Ok(changes) => {
+                let listed = changes.iter().map(ToString::to_string).collect::<Vec<_>>().join(" ");
+                LOG.info("Synced slash commands", &[("changes", &listed)]);
+            }
+            Err(err) => LOG.warn("Slash-command sync skipped", &[("reason", &err)]),
+        }
+    }
+
+    /// Bring Discord's slash commands in line with `with_commands`.
+    ///
+    /// The desired set is compared with `Discovery/commands_synced.json`, the copy saved after the
+    /// last successful sync. When they match, nothing is sent and the returned list is empty.
+    /// Otherwise the whole set goes to Discord in one bulk-overwrite PUT (which also deletes
+    /// commands that are no longer listed), the cache is replaced, and the per-command changes
+    /// are returned. The cache only changes after Discord accepted the PUT.
+    pub fn sync_commands(&mut self, token: &str) -> Result<Vec<CommandChange>, String> {
+        let synced = load_synced(&self.layout.commands_synced_file)?;
+        let changes = self.commands.diff(&synced);
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+
+        if token.is_empty() && !self.transport.is_dry_run() {
+            return Err("the bot token is missing".to_string());
+        }
+        let application_id = self.env.var(APPLICATION_ID_ENV).unwrap_or_default();
+        if application_id.is_empty() || !application_id.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("{} must be the bot's numeric application id", APPLICATION_ID_ENV));
+        }
+
+        let mut client = SecureDiscordClient::new(token.to_string(), self.transport.as_mut());
+        let outcome = client.put_commands(&application_id, &self.commands.to_json());
+        let summary = match outcome {
+            Ok(summary) => summary,
+            Err(SendError::RateLimited { summary, .. } | SendError::Transient(summary) | SendError::Failed(summary)) => {
+                append_line(&self.layout.secure_dispatch_file, &summary);
+                return Err(summary);
+            }
+        };
+        let listed = changes.iter().map(ToString::to_string).collect::<Vec<_>>().join(" ");
+        append_line(&self.layout.secure_dispatch_file, &format!("{} | changes={}", summary, listed));
+
+        save_synced(&self.layout.commands_synced_file, &self.commands)
+            .map_err(|err| format!("commands synced but the cache could not be written: {}", err))?;
+        Ok(changes)
+    }
+
+    /// Send the queued messages through the transport. Keeping this inside Rust enforces the
+    /// "all Discord I/O through Rust" policy even if the Python layer is compromised.
+    ///
+    /// Messages go out highest priority first, oldest first within a priority. A message whose
+    /// `deliver_after_millis` is still ahead of the clock when the flush starts stays queued for
+    /// a later flush. The report says how many messages were left and the most urgent of them.
+    pub fn flush(&mut self) -> FlushReport {
+        self.write_heartbeat();
+        if let Err(err) = self.answer_challenge() {
+            LOG.warn("Could not answer the hub's challenge", &[("reason", &err)]);
+        }
+        let inbox = self.poll_inbox();
+        if inbox != InboxReport::default() {
+            LOG.info(
+                "Inbox processed",
+                &[
+                    ("queued", &inbox.queued.to_string()),
+                    ("syncs", &inbox.syncs.to_string()),
+                    ("rejected", &inbox.rejected.to_string()),
+                ],
+            );
+        }
+
+        // Fetched before the queue is touched, so a broken envelope or key loses nothing: every
+        // message stays queued (and spooled) for the next flush.
+        let token = match self.token() {
+            Ok(token) => token,
+            Err(err) => {
+                LOG.error("Could not load the bot token; leaving the queue for the next flush", &[("error", &err)]);
+                return self.waiting(FlushReport::default());
+            }
+        };
+        let token_text = String::from_utf8_lossy(token.expose());
+        let ready = self.ecosystem_ready();
+
+        // The token itself never reaches the log; `redact` only says whether one is set.
+        LOG.info(
+            "Flush starting",
+            &[
+                ("ready", if ready { "yes" } else { "no" }),
+                ("queued", &self.queue.len().to_string()),
+                ("token", &redact(&token_text)),
+            ],
+        );
+
+        if token_text.is_empty() && !self.transport.is_dry_run() {
+            LOG.error("Missing bot token (config or SQUIRE_DISCORD_TOKEN); refusing to send HTTPS requests", &[]);
+            return self.waiting(FlushReport::default());
+        }
+
+        if !ready {
+            LOG.warn("Presence file missing or unsigned; skipping send", &[]);
+            return self.waiting(FlushReport::default());
+        }
+
+        self.sync_slash_commands();
+
+        let secure_log = self.layout.secure_dispatch_file.clone();
+        // The client's copy is zeroed when the client is dropped at the end of this flush.
+        let mut client = SecureDiscordClient::new(token_text.into_owned(), self.transport.as_mut());
+        let limiter = &mut self.rate_limiter;
+        let (clock, sleeper) = (Rc::clone(&self.clock), Rc::clone(&self.sleeper));
+        // Scheduled messages that are not due yet stay in `self.queue`, untouched.
+        let mut pending = self.queue.take_due(clock.now_millis());
+        // How many 429s each pending message has hit so far.
+        let mut attempts: HashMap<u64, u32> = HashMap::new();
+        // Messages that hit a temporary failure wait for the next flush instead of being lost.
+        let mut deferred: Vec<(u64, OutboundMessage)> = Vec::new();
+        let spool = self.spool.as_ref();
+        let acknowledge = |id: u64| {
+            if let Some(spool) = spool {
+                if let Err(err) = spool.append_ack(id) {
+                    LOG.error("Could not mark spool record done", &[("id", &id.to_string()), ("error", &err.to_string())]);
+                }
+            }
+        };
+        let mut report = FlushReport::default();
+        let mut waited = Duration::ZERO;
+
+        while !pending.is_empty() {
+            let now = clock.instant();
+            let id = match next_sendable(&pending, limiter, now) {
+                Ok(id) => id,
+                Err(earliest) => {
+                    // Every destination is out of budget: sleep only until the first one refills.
+                    let pause = earliest.saturating_duration_since(now);
+                    waited += pause;
+                    sleeper.sleep(pause);
+                    continue;
+                }
+            };
+
+            let Some(item) = pending.remove(id) else { break };
+            let rate_key = item.destination.rate_key();
+            // Only the redacted form reaches the secure log; webhook URLs carry secrets.
+            let target = item.destination.redacted();
+            if limiter.ready_at(&rate_key, now) > now {
+                // Only a critical message gets here without budget; it goes now and the bucket
+                // owes the token.
+                report.borrowed += 1;
+                limiter.borrow(&rate_key, now);
+            } else {
+                limiter.consume(&rate_key, now);
+            }
+            match client.send_message(&item) {
+                Ok(summary) => {
+                    report.sent += 1;
+                    acknowledge(id);
+                    append_line(&secure_log, &format!("{} | {}", target, summary));
+                }
+                Err(SendError::RateLimited { retry_after_ms, summary }) => {
+                    report.rate_limited += 1;
+                    limiter.penalize(&rate_key, retry_after_ms, clock.instant());
+                    let tries = attempts.entry(id).or_insert(0);
+                    *tries += 1;
+                    if *tries > MAX_RATE_LIMIT_RETRIES {
+                        report.failed += 1;
+                        append_line(
+                            &secure_log,
+                            &format!("{} still rate limited after {} tries; kept for next flush: {}", target, MAX_RATE_LIMIT_RETRIES, summary),
+                        );
+                        deferred.push((id, item));
+                    } else {
+                        append_line(
+                            &secure_log,
+                            &format!("{} rate limited; retrying in {}ms: {}", target, retry_after_ms, summary),
+                        );
+                        // Back in its old place, so it still goes out before later messages.
+                        pending.push(id, item);
+                    }
+                }
+                Err(SendError::Transient(err)) => {
+                    report.failed += 1;
+                    append_line(&secure_log, &format!("{} failed to send (kept for next flush): {}", target, err));
+                    deferred.push((id, item));
+                }
+                Err(SendError::Failed(err)) => {
+                    // The receiver rejected the message itself (bad channel, bad body, bad URL);
+                    // retrying would fail the same way, so it is dropped from the spool.
+                    report.failed += 1;
+                    acknowledge(id);
+                    append_line(&secure_log, &format!("{} failed to send: {}", target, err));
+                }
+            }
+        }
+
+        for (id, item) in deferred {
+            self.queue.push(id, item);
+        }
+        if let Some(spool) = &self.spool {
+            // Compact: the spool now holds exactly the messages still waiting.
+            let remaining: Vec<(u64, OutboundMessage)> = self.queue.iter().map(|(id, item)| (id, item.clone())).collect();
+            if let Err(err) = spool.rewrite(&remaining) {
+                LOG.error("Could not compact spool", &[("error", &err.to_string())]);
+            }
+        }
+
+        // Done sending: the client (and its copy of the token) can go.
+        drop(client);
+        let limiter_state = limiter.describe(clock.instant());
+        let report = self.waiting(report);
+        let highest = report.highest_waiting.map_or("none", Priority::as_str);
+        let summary = format!(
+            "[Rust gateway] Flush done: sent={} failed={} rate_limited={} borrowed={} deferred={} scheduled={} highest_waiting={} waited={}ms | limiter: {}",
+            report.sent,
+            report.failed,
+            report.rate_limited,
+            report.borrowed,
+            report.deferred,
+            report.scheduled,
+            highest,
+            waited.as_millis(),
+            limiter_state
+        );
+        append_line(&secure_log, &summary);
+        LOG.info(
+            "Flush done",
+            &[
+                ("sent", &report.sent.to_string()),
+                ("failed", &report.failed.to_string()),
+                ("rate_limited", &report.rate_limited.to_string()),
+                ("borrowed", &report.borrowed.to_string()),
+                ("deferred", &report.deferred.to_string()),
+                ("scheduled", &report.scheduled.to_string()),
+                ("highest_waiting", highest),
+                ("waited_ms", &waited.as_millis().to_string()),
+                ("limiter", &limiter_state),
+            ],
+        );
+        report
+    }
+
+    /// What `flush` would do right now, without doing any of it: see `FlushPlan`.
+    pub fn plan(&self) -> FlushPlan {
+        self.plan_with(Vec::new())
+    }
+
+    /// `plan` as if `extra` had been enqueued first (without spooling them), so the binary can
+    /// show new dispatch lines next to the queue without moving the dispatch cursor.
+    ///
+    /// The flush is played through on copies of the queue and the rate limiter, with a clock
+    /// that only moves when every destination is out of budget, so nothing is sent, written,
+    /// waited for, or taken off the queue. Every send is assumed to succeed: a 429 or a refused
+    /// message would push the later ones back.
+    pub fn plan_with(&self, extra: Vec<OutboundMessage>) -> FlushPlan {
+        let mut queue = self.queue.clone();
+        for (offset, message) in (0u64..).zip(extra) {
+            queue.push(self.next_id + offset, message);
+        }
+        let blocked = self.plan_blocked();
+        let planned = |id: u64, message: &OutboundMessage, status: PlanStatus, send_offset: Option<Duration>, borrowed: bool| PlannedMessage {
+            id,
+            destination: message.destination.redacted(),
+            action: message.action.kind(),
+            priority: message.priority,
+            deliver_after_millis: message.deliver_after_millis,
+            body_bytes: message.body().len(),
+            status,
+            send_offset,
+            borrowed,
+            invalid: validate_raw(message).err().map(|err| err.to_string()),
+        };
+
+        let mut messages = Vec::new();
+        // What is left in `queue` afterwards is scheduled for later, as in `flush`.
+        let mut pending = queue.take_due(self.clock.now_millis());
+        if blocked.is_none() {
+            let mut limiter = self.rate_limiter.clone();
+            let start = self.clock.instant();
+            let mut now = start;
+            while !pending.is_empty() {
+                let id = match next_sendable(&pending, &mut limiter, now) {
+                    Ok(id) => id,
+                    Err(earliest) => {
+                        // Where `flush` would sleep, the plan's clock jumps ahead instead.
+                        now = earliest.max(now);
+                        continue;
+                    }
+                };
+                let Some(item) = pending.remove(id) else { break };
+                let rate_key = item.destination.rate_key();
+                let borrowed = limiter.ready_at(&rate_key, now) > now;
+                if borrowed {
+                    limiter.borrow(&rate_key, now);
+                } else {
+                    limiter.consume(&rate_key, now);
+                }
+                messages.push(planned(id, &item, PlanStatus::Send, Some(now - start), borrowed));
+            }
+        }
+        messages.extend(pending.iter().map(|(id, item)| planned(id, item, PlanStatus::Blocked, None, false)));
+        messages.extend(queue.iter().map(|(id, item)| planned(id, item, PlanStatus::Scheduled, None, false)));
+        FlushPlan { blocked, messages }
+    }
+
+    /// Why a flush started now would send nothing, checked in the same order as `flush` does.
+    fn plan_blocked(&self) -> Option<String> {
+        let token = match self.token_source.resolve_from(&*self.env) {
+            Ok(token) => token,
+            Err(err) => return Some(format!("could not load the bot token: {err}")),
+        };
+        if token.expose().is_empty() && !self.transport.is_dry_run() {
+            return Some("missing bot token (config or SQUIRE_DISCORD_TOKEN)".to_string());
+        }
+        match self.validate_presence_file() {
+            Ok(true) => None,
+            Ok(false) => Some("presence file has a bad signature".to_string()),
+            Err(err) => Some(err),
+        }
+    }
+
+    /// `report` with what is still queued filled in.
+    fn waiting(&self, report: FlushReport) -> FlushReport {
+        FlushReport {
+            deferred: self.queue.len(),
+            scheduled: self.queue.scheduled(self.clock.now_millis()),
+            highest_waiting: self.queue.highest_priority(),
+            ..report
+        }
+    }
+
+    /// Append a note to the dispatch file so the ecosystem hub can route it if desired.
+    pub fn append_dispatch(&self, message: &str) {
+        append_line(&self.layout.dispatch_file, message);
+    }
+
+
+    /// Validate the presence file signature with HMAC-SHA256 so only the hub can flip the ready flag.
+    /// `ECOSYSTEM_PRESENCE_KEY` here is this bot's own derived key, not the hub's master key.
+    ///
+    /// A correctly signed revocation (nonce ending in `|revoked`) returns an error starting with
+    /// `PRESENCE_REVOKED_ERROR`, whatever its age. A `status=revoked` line without that signed
+    /// nonce is ignored with a warning, so nobody but the hub can silence the bot.
+    pub fn validate_presence_file(&self) -> Result<bool, String> {
+        let key = load_presence_key(&*self.env)?;
+
+        let contents = fs::read_to_string(&self.layout.presence_file)
+            .map_err(|_| "presence file missing".to_string())?;
+
+        let mut nonce = None;
+        let mut signature = None;
+        let mut status = None;
+        for line in contents.lines() {
+            if let Some(rest) = line.strip_prefix("nonce=") {
+                nonce = Some(rest.to_string());
+            }
+            if let Some(rest) = line.strip_prefix("signature=") {
+                signature = Some(rest.to_string());
+            }
+            if let Some(rest) = line.strip_prefix("status=") {
+                status = Some(rest.trim().to_string());
+            }
+        }
+
+        let nonce = nonce.ok_or_else(|| "nonce missing from presence file".to_string())?;
+        let signature = signature.ok_or_else(|| "signature missing from presence file".to_string())?;
+
+        if signature.starts_with("missing-") {
+            return Err("presence file is unsigned".to_string());
+        }
+
+        // Name the scheme mismatch instead of reporting a plain bad signature, since it usually
+        // means the hub and this bot were upgraded at different times.
+        match (&key, signature.len()) {
+            (PresenceKey::Hmac(_), 16) => {
+                return Err(format!(
+                    "presence file carries a legacy SipHash signature; upgrade the hub key or set {}=1 with the old key",
+                    PRESENCE_LEGACY_ENV
+                ))
+            }
+            (PresenceKey::Legacy(_), 64) => {
+                return Err("presence file carries an HMAC-SHA256 signature but this bot has a legacy key".to_string())
+            }
+            _ => {}
+        }
+
+        let expected = sign_presence(&key, &nonce);
+        if !constant_time_eq(expected.as_bytes(), signature.to_ascii_lowercase().as_bytes()) {
+            return Ok(false);
+        }
+
+        // The signature covers the nonce, so a revoked nonce really came from the hub. An old
+        // revocation still means stop: only a fresh announcement undoes it.
+        if let Some(rest) = nonce.strip_suffix(&format!("|{}", PRESENCE_REVOKED)) {
+            let at = rest.rsplit_once('|').map_or("unknown", |(_, stamp)| stamp);
+            return Err(format!("{} (signed at {} ms)", PRESENCE_REVOKED_ERROR, at));
+        }
+        if let Some(status) = status.filter(|status| status != "active") {
+            LOG.warn("Ignoring a presence status the signature does not cover", &[("status", &status)]);
+        }
+
+        // A valid signature only proves the hub wrote the file at some point; the timestamp
+        // proves it did so recently, so a dead hub stops counting as present.
+        check_presence_freshness(&nonce, self.clock.now_millis(), presence_ttl_secs(&*self.env))?;
+        Ok(true)
+    }
+}
+
+/// Builds Discord and webhook requests and hands them to a `Transport`, logging only redacted
+/// summaries.
+struct SecureDiscordClient<'a> {
+    token: String,
+    transport: &'a mut dyn Transport,
+}
+
+impl<'a> SecureDiscordClient<'a> {
+    fn new(token: String, transport: &'a mut dyn Transport) -> Self {
+        Self { token, transport }
+    }
+
+    /// The headers every Discord request carries. Index 0 is `Authorization`.
+    fn headers(&self) -> Vec<(String, String)> {
+        vec![
+            ("Authorization".to_string(), format!("Bot {}", self.token)),
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("User-Agent".to_string(), "DiscordBot (squire, 0.1)".to_string()),
+        ]
+    }
+
+    /// PUT the full slash-command set. Same retry and redaction rules as `send_message`.
+    fn put_commands(&mut self, application_id: &str, body: &str) -> Result<String, SendError> {
+        let path = format!("/api/v10/applications/{}/commands", application_id);
+        let mut headers = self.headers();
+        let auth_digest = short_digest(&headers[0].1);
+        let millis = now_millis();
+
+        let response = match self.transport.put(&path, &headers, body) {
+            Err(TransportError::Connect(first)) => {
+                LOG.warn("Connect failed; retrying once", &[("error", &first.to_string())]);
+                self.transport.put(&path, &headers, body)
+            }
+            other => other,
+        };
+        wipe(&mut headers[0].1);
+        let response = response.map_err(|err| SendError::Transient(err.to_string()))?;
+
+        let summary = format!(
+            "{}PUT {} | status={} | body={} bytes | auth-digest={:016x} | sent_at={}ms",
+            if self.transport.is_dry_run() { "DRY-RUN " } else { "" },
+            path,
+            response.status,
+            body.len(),
+            auth_digest,
+            millis
+        );
+        classify(&response, summary)
+    }
+
+    /// Send one message to its destination: a POST for a new message, a PATCH for an edit, or a
+    /// DELETE. A connection failure is retried once; a 429 is reported separately so the caller
+    /// can wait and requeue; any other non-2xx status is an error. Returns a redacted summary for
+    /// the secure dispatch log.
+    fn send_message(&mut self, message: &OutboundMessage) -> Result<String, SendError> {
+        match (&message.destination, &message.action) {
+            (Destination::DiscordChannel(channel_id), action) => self.send_channel(channel_id, action),
+            (Destination::Webhook { url }, Action::Create { body }) => self.post_webhook(url, body),
+            (Destination::Webhook { .. }, action) => {
+                Err(SendError::Failed(format!("webhook messages cannot be edited or deleted (action={})", action.kind())))
+            }
+        }
+    }
+
+    /// Send one action to a Discord channel. Edits and deletes name the message in the path and
+    /// add `action=` and `message_id=` to the summary, so the log shows what was changed.
+    fn send_channel(&mut self, channel_id: &str, action: &Action) -> Result<String, SendError> {
+        let (method, path, changed) = match action.message_id() {
+            None => ("POST", format!("/api/v10/channels/{}/messages", channel_id), String::new()),
+            Some(message_id) => {
+                let method = if matches!(action, Action::Edit { .. }) { "PATCH" } else { "DELETE" };
+                let changed = format!(" | action={} message_id={}", action.kind(), message_id);
+                (method, format!("/api/v10/channels/{}/messages/{}", channel_id, message_id), changed)
+            }
+        };
+        let body = action.body();
+        let mut headers = self.headers();
+
+        // We avoid printing headers with tokens; only a short digest is logged for troubleshooting.
+        let auth_digest = short_digest(&headers[0].1);
+        let millis = now_millis();
+
+        let attempt = |transport: &mut dyn Transport| match action {
+            Action::Create { .. } => transport.post(&path, &headers, body),
+            Action::Edit { .. } => transport.patch(&path, &headers, body),
+            Action::Delete { .. } => transport.delete(&path, &headers),
+        };
+        let response = match attempt(&mut *self.transport) {
+            Err(TransportError::Connect(first)) => {
+                LOG.warn("Connect failed; retrying once", &[("error", &first.to_string())]);
+                attempt(&mut *self.transport)
+            }
+            other => other,
+        };
+        // The header holds the token in plain text; it is not needed once the request is out.
+        wipe(&mut headers[0].1);
+        let response = response.map_err(|err| SendError::Transient(err.to_string()))?;
+
+        let summary = format!(
+            "{}{} {}{} | status={} | body={} bytes | auth-digest={:016x} | sent_at={}ms",
+            if self.transport.is_dry_run() { "DRY-RUN " } else { "" },
+            method,
+            path,
+            changed,
+            response.status,
+            body.len(),
+            auth_digest,
+            millis
+        );
+        classify(&response, summary)
+    }
+
+    /// POST to a webhook outside Discord. It gets no `Authorization` header, so the bot token
+    /// never leaves for another service, and its summary shows the redacted URL only.
+    fn post_webhook(&mut self, url: &str, body: &str) -> Result<String, SendError> {
+        let url = WebhookUrl::parse(url).map_err(SendError::Failed)?;
+        let headers = vec![
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("User-Agent".to_string(), "squire-gateway (webhook)".to_string()),
+        ];
+        let millis = now_millis();
+
+        let response = match self.transport.post_webhook(&url, &headers, body) {
+            Err(TransportError::Connect(first)) => {
+                LOG.warn("Connect failed; retrying once", &[("error", &first.to_string())]);
+                self.transport.post_webhook(&url, &headers, body)
+            }
+            other => other,
+        };
+        let response = response.map_err(|err| SendError::Transient(err.to_string()))?;
+
+        let summary = format!(
+            "{}POST webhook {} | status={} | body={} bytes | sent_at={}ms",
+            if self.transport.is_dry_run() { "DRY-RUN " } else { "" },
+            url.redacted(),
+            response.status,
+            body.len(),
+            millis
+        );
+        classify(&response, summary)
+    }
+}
+
+/// Sort a reply into success, 429, worth-retrying (5xx), or refused (any other status).
+fn classify(response: &HttpResponse, summary: String) -> Result<String, SendError> {
+    match response.status {
+        200..=299 => Ok(summary),
+        429 => Err(SendError::RateLimited { retry_after_ms: retry_after_ms(response), summary }),
+        500..=599 => Err(SendError::Transient(summary)),
+        _ => Err(SendError::Failed(summary)),
+    }
+}
+
+impl Drop for SecureDiscordClient<'_> {
+    fn drop(&mut self) {
+        wipe(&mut self.token);
+    }
+}
+
+/// Milliseconds since 1970, the unit the hub writes into presence nonces.
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+/// Presence lifetime from `ECOSYSTEM_PRESENCE_TTL_SECS`, or the 15-minute default.
+fn presence_ttl_secs(env: &dyn EnvSource) -> u64 {
+    env.var(PRESENCE_TTL_ENV)
+        .and_then(|raw| raw.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_PRESENCE_TTL_SECS)
+}
+
+/// Check the `<path>|<millis>` nonce against the clock. Files older than the TTL are stale, and
+/// files from the future are suspicious; both get `PRESENCE_CLOCK_SKEW_SECS` of slack so small
+/// clock differences between hosts do not flap the ready flag.
+pub fn check_presence_freshness(nonce: &str, now_millis: u128, ttl_secs: u64) -> Result<(), String> {
+    let (_, stamp) = nonce
+        .rsplit_once('|')
+        .ok_or_else(|| "presence nonce has no timestamp".to_string())?;
+    let signed_at = stamp
+        .trim()
+        .parse::<u128>()
+        .map_err(|_| format!("presence timestamp {:?} is not a number", stamp))?;
+
+    let skew_ms = u128::from(PRESENCE_CLOCK_SKEW_SECS) * 1000;
+    if signed_at > now_millis {
+        let ahead_ms = signed_at - now_millis;
+        if ahead_ms > skew_ms {
+            return Err(format!(
+                "presence file is dated {}s in the future (allowed skew {}s)",
+                ahead_ms / 1000,
+                PRESENCE_CLOCK_SKEW_SECS
+            ));
+        }
+        return Ok(());
+    }
+
+    let age_ms = now_millis - signed_at;
+    if age_ms > u128::from(ttl_secs) * 1000 + skew_ms {
+        return Err(format!(
+            "presence file is stale: signed {}s ago (TTL {}s, skew allowance {}s)",
+            age_ms / 1000,
+            ttl_secs,
+            PRESENCE_CLOCK_SKEW_SECS
+        ));
+    }
+    Ok(())
+}
+
+/// Set to `1` to keep the old 16-byte SipHash presence scheme for one transition period.
+const PRESENCE_LEGACY_ENV: &str = "ECOSYSTEM_PRESENCE_LEGACY";
+
+/// The presence key, in whichever signing scheme is active.
+#[derive(Clone, Copy)]
+enum PresenceKey {
+    /// 32-byte HMAC-SHA256 key (64 hex characters).
+    Hmac([u8; 32]),
+    /// Old 16-byte SipHash key, only accepted while `ECOSYSTEM_PRESENCE_LEGACY=1`.
+    Legacy([u8; 16]),
+}
+
+/// Decode hex text into bytes, or `None` when it has an odd length or a non-hex character.
+fn parse_hex(raw: &str) -> Option<Vec<u8>> {
+    if !raw.len().is_multiple_of(2) || !raw.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    raw.as_bytes()
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Parse `ECOSYSTEM_PRESENCE_KEY`. A 64-hex key selects HMAC-SHA256; the old 32-hex key is only
+/// allowed in legacy mode, and otherwise produces an error that says how to upgrade.
+fn parse_presence_key(raw: &str, legacy_allowed: bool) -> Result<PresenceKey, String> {
+    let bytes = parse_hex(raw).ok_or_else(|| format!("{} is not valid hex", PRESENCE_KEY_ENV))?;
+    match bytes.len() {
+        32 => {
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&bytes);
+            Ok(PresenceKey::Hmac(key))
+        }
+        16 if legacy_allowed => {
+            let mut key = [0u8; 16];
+            key.copy_from_slice(&bytes);
+            Ok(PresenceKey::Legacy(key))
+        }
+        16 => Err(format!(
+            "{} is 32 hex characters (16 bytes), the old SipHash key size; presence keys are now 64 hex characters (32 bytes). Generate one with `openssl rand -hex 32`, or set {}=1 to keep the old scheme during the transition",
+            PRESENCE_KEY_ENV, PRESENCE_LEGACY_ENV
+        )),
+        other => Err(format!(
+            "{} must be 64 hex characters (32 bytes), got {} bytes",
+            PRESENCE_KEY_ENV, other
+        )),
+    }
+}
+
+/// Load the presence key from the environment, honouring the legacy escape hatch.
+fn load_presence_key(env: &dyn EnvSource) -> Result<PresenceKey, String> {
+    let raw = env.var(PRESENCE_KEY_ENV).ok_or_else(|| format!("{} is unset", PRESENCE_KEY_ENV))?;
+    let legacy_allowed = env.var(PRESENCE_LEGACY_ENV).is_some_and(|v| v.trim() == "1");
+    parse_presence_key(raw.trim(), legacy_allowed)
+}
+
+/// Sign a nonce: 64 hex characters of HMAC-SHA256, or 16 of SipHash in legacy mode.
+fn sign_presence(key: &PresenceKey, nonce: &str) -> String {
+    match key {
+        PresenceKey::Hmac(key) => to_hex(&hmac_sha256(key, nonce.as_bytes())),
+        PresenceKey::Legacy(key) => sign_presence_legacy(key, nonce),
+    }
+}
+
+/// The pre-HMAC scheme, kept only for `ECOSYSTEM_PRESENCE_LEGACY=1`. `SipHasher` is deprecated,
+/// which is one of the reasons this path is on its way out.
+#[allow(deprecated)]
+fn sign_presence_legacy(key_bytes: &[u8; 16], nonce: &str) -> String {
+    use std::hash::{Hasher, SipHasher};
+
+    let mut k0 = 0u64;
+    let mut k1 = 0u64;
+    for (i, b) in key_bytes.iter().enumerate() {
+        if i < 8 {
+            k0 = (k0 << 8) | (*b as u64);
+        } else {
+            k1 = (k1 << 8) | (*b as u64);
+        }
+    }
+
+    let mut hasher = SipHasher::new_with_keys(k0, k1);
+    hasher.write(nonce.as_bytes());
+    format!("{:016x}", hasher.finish())
+}
+
+/// Short SHA-256 tag of the Authorization header so logs can tell tokens apart without leaking them.
+pub(crate) fn short_digest(input: &str) -> u64 {
+    let mut salted = b"gateway-log-salt!".to_vec();
+    salted.extend_from_slice(input.as_bytes());
+    let digest = sha256(&salted);
+    let mut first = [0u8; 8];
+    first.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(first)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{CommandOption, OptionType, SlashCommand};
+    use crate::runtime::{ManualClock, MapEnv};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    const KEY_HEX: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+    const TOKEN: &str = "test-token-value";
+    const CHANNEL: &str = "123456789012345678";
+    const OTHER_CHANNEL: &str = "223456789012345678";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("squire-gateway-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn gateway(bot_dir: &Path, clock: &Rc<ManualClock>) -> DiscordGateway {
+        DiscordGateway::with_transport(Box::new(DryRunTransport))
+            .with_clock(clock.clone())
+            .with_sleeper(clock.clone())
+            .with_env(Rc::new(MapEnv::new()))
+            .with_layout(DiscoveryLayout::under(bot_dir))
+    }
+
+    /// One request a `MockTransport` saw.
+    #[derive(Debug, Clone)]
+    struct Request {
+        method: &'static str,
+        path: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    /// Records every request and answers from a script, then with 200. Clones share both lists,
+    /// so a test keeps one copy while the gateway owns the other.
+    #[derive(Clone, Default)]
+    struct MockTransport {
+        requests: Rc<RefCell<Vec<Request>>>,
+        replies: Rc<RefCell<VecDeque<Result<HttpResponse, TransportError>>>>,
+    }
+
+    impl MockTransport {
+        fn reply(self, reply: Result<HttpResponse, TransportError>) -> Self {
+            self.replies.borrow_mut().push_back(reply);
+            self
+        }
+
+        fn requests(&self) -> Vec<Request> {
+            self.requests.borrow().clone()
+        }
+
+        fn answer(&mut self, method: &'static str, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+            self.requests.borrow_mut().push(Request { method, path: path.to_string(), headers: headers.to_vec(), body: body.to_string() });
+            self.replies.borrow_mut().pop_front().unwrap_or_else(|| Ok(status(200)))
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn post(&mut self, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+            self.answer("POST", path, headers, body)
+        }
+
+        fn post_webhook(&mut self, url: &WebhookUrl, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+            self.answer("WEBHOOK", &url.redacted(), headers, body)
+        }
+
+        fn put(&mut self, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+            self.answer("PUT", path, headers, body)
+        }
+
+        fn patch(&mut self, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+            self.answer("PATCH", path, headers, body)
+        }
+
+        fn delete(&mut self, path: &str, headers: &[(String, String)]) -> Result<HttpResponse, TransportError> {
+            self.answer("DELETE", path, headers, "")
+        }
+    }
+
+    fn status(code: u16) -> HttpResponse {
+        HttpResponse { status: code, headers: Vec::new(), body: String::new() }
+    }
+
+    fn hmac_key() -> PresenceKey {
+        PresenceKey::Hmac([0x11; 32])
+    }
+
+    /// The environment of a bot the hub has set up: a presence key and a bot token.
+    fn ready_env() -> MapEnv {
+        MapEnv::new().with(PRESENCE_KEY_ENV, KEY_HEX).with(TOKEN_ENV, TOKEN)
+    }
+
+    /// Write a presence file for `nonce`, signed with `key`.
+    fn write_presence(layout: &DiscoveryLayout, key: &PresenceKey, nonce: &str) {
+        fs::create_dir_all(&layout.root).unwrap();
+        let text = format!("nonce={}\nsignature={}\n", nonce, sign_presence(key, nonce));
+        fs::write(&layout.presence_file, text).unwrap();
+    }
+
+    /// A gateway that may send: the hub announced itself at the clock's current time.
+    fn ready_gateway(bot_dir: &Path, clock: &Rc<ManualClock>, transport: &MockTransport) -> DiscordGateway {
+        let gateway = DiscordGateway::with_transport(Box::new(transport.clone()))
+            .with_clock(clock.clone())
+            .with_sleeper(clock.clone())
+            .with_env(Rc::new(ready_env()))
+            .with_layout(DiscoveryLayout::under(bot_dir));
+        write_presence(gateway.layout(), &hmac_key(), &format!("squire|{}", clock.now_millis()));
+        gateway
+    }
+
+    fn secure_log(gateway: &DiscordGateway) -> String {
+        fs::read_to_string(&gateway.layout().secure_dispatch_file).unwrap_or_default()
+    }
+
+    #[test]
+    fn http_response_is_split_into_status_headers_and_body() {
+        let raw = b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1.5\r\nContent-Type: application/json\r\n\r\n{\"retry_after\": 1.5}";
+        let response = parse_http_response(raw).unwrap();
+        assert_eq!(response.status, 429);
+        assert_eq!(response.header("retry-after"), Some("1.5"));
+        assert_eq!(response.header("content-type"), Some("application/json"));
+        assert_eq!(response.body, "{\"retry_after\": 1.5}");
+        assert_eq!(retry_after_ms(&response), 1500);
+
+        assert!(matches!(parse_http_response(b"HTTP/1.1 200 OK\r\n"), Err(TransportError::Protocol(_))));
+        assert!(matches!(parse_http_response(b"garbage\r\n\r\n"), Err(TransportError::Protocol(_))));
+    }
+
+    #[test]
+    fn flush_posts_the_message_with_the_token_but_never_logs_it() {
+        let bot_dir = temp_dir("transport-post");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport);
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{\"content\":\"hi\"}"));
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.failed, report.deferred), (1, 0, 0));
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, format!("/api/v10/channels/{CHANNEL}/messages"));
+        assert_eq!(requests[0].body, "{\"content\":\"hi\"}");
+        assert!(requests[0].headers.contains(&("Authorization".to_string(), format!("Bot {TOKEN}"))));
+
+        let log = secure_log(&gateway);
+        assert!(log.contains(&format!("POST /api/v10/channels/{CHANNEL}/messages | status=200")));
+        assert!(!log.contains(TOKEN));
+        assert!(!log.contains("DRY-RUN"));
+    }
+
+    #[test]
+    fn a_connect_failure_is_retried_once() {
+        let bot_dir = temp_dir("transport-retry");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default().reply(Err(TransportError::Connect("refused".to_string())));
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport);
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{}"));
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.failed), (1, 0));
+        assert_eq!(transport.requests().len(), 2);
+
+        // Two connect failures in a row keep the message for the next flush.
+        let transport = MockTransport::default()
+            .reply(Err(TransportError::Connect("refused".to_string())))
+            .reply(Err(TransportError::Connect("refused".to_string())));
+        let mut gateway = ready_gateway(&temp_dir("transport-retry-twice"), &clock, &transport);
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{}"));
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.failed, report.deferred), (0, 1, 1));
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[test]
+    fn a_server_error_keeps_the_message_and_a_refusal_drops_it() {
+        let bot_dir = temp_dir("transport-500");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default().reply(Ok(status(500))).reply(Ok(status(400)));
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport);
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{}"));
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.failed, report.deferred), (0, 1, 1));
+        assert_eq!(transport.requests().len(), 1);
+        assert!(secure_log(&gateway).contains("status=500"));
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.failed, report.deferred), (0, 1, 0));
+        assert_eq!(gateway.pending_len(), 0);
+    }
+
+    #[test]
+    fn the_dry_run_transport_needs_no_token_and_marks_the_secure_log() {
+        let bot_dir = temp_dir("transport-dry-run");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let mut gateway = DiscordGateway::with_transport(Box::new(DryRunTransport))
+            .with_clock(clock.clone())
+            .with_sleeper(clock.clone())
+            .with_env(Rc::new(MapEnv::new().with(PRESENCE_KEY_ENV, KEY_HEX)))
+            .with_layout(DiscoveryLayout::under(&bot_dir));
+        write_presence(gateway.layout(), &hmac_key(), &format!("squire|{}", clock.now_millis()));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{}"));
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.failed), (1, 0));
+        assert!(secure_log(&gateway).contains(&format!("DRY-RUN POST /api/v10/channels/{CHANNEL}/messages | status=200")));
+
+        // A real transport without a token refuses to send and keeps the queue.
+        let transport = MockTransport::default();
+        let mut gateway = DiscordGateway::with_transport(Box::new(transport.clone()))
+            .with_clock(clock.clone())
+            .with_sleeper(clock.clone())
+            .with_env(Rc::new(MapEnv::new().with(PRESENCE_KEY_ENV, KEY_HEX)))
+            .with_layout(DiscoveryLayout::under(&bot_dir));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{}"));
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.deferred), (0, 1));
+        assert!(transport.requests().is_empty());
+    }
+
+    #[test]
+    fn heartbeat_on_an_empty_root_creates_the_discovery_folder() {
+        let bot_dir = temp_dir("heartbeat");
+        let clock = Rc::new(ManualClock::new(1_000));
+        let mut gateway = gateway(&bot_dir, &clock);
+        assert!(!gateway.layout().root.exists());
+
+        gateway.write_heartbeat();
+        let first = fs::read_to_string(&gateway.layout().heartbeat_file).unwrap();
+        assert_eq!(first, format!("pid={} seq=1 at=1000\n", std::process::id()));
+
+        clock.advance(Duration::from_secs(5));
+        gateway.write_heartbeat();
+        let second = fs::read_to_string(&gateway.layout().heartbeat_file).unwrap();
+        assert_eq!(second, format!("pid={} seq=2 at=6000\n", std::process::id()));
+    }
+
+    #[test]
+    fn the_limiter_spends_the_burst_then_waits_for_refills() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(2, 1.0);
+        for _ in 0..2 {
+            assert_eq!(limiter.ready_at(CHANNEL, start), start);
+            limiter.consume(CHANNEL, start);
+        }
+        assert_eq!(limiter.ready_at(CHANNEL, start), start + Duration::from_secs(1));
+        // Another channel has its own bucket.
+        assert_eq!(limiter.ready_at(OTHER_CHANNEL, start), start);
+
+        limiter.penalize(CHANNEL, 2_500, start);
+        assert_eq!(limiter.blocked_until(CHANNEL, start), Some(start + Duration::from_millis(2_500)));
+        assert_eq!(limiter.ready_at(CHANNEL, start), start + Duration::from_millis(2_500));
+        assert_eq!(limiter.describe(start), "2 destination(s), 0 throttled, 1 blocked by 429");
+    }
+
+    #[test]
+    fn a_burst_to_one_channel_waits_only_for_the_budget() {
+        let bot_dir = temp_dir("rate-burst");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport).with_rate_limiter(RateLimiter::new(5, 1.0));
+        for n in 0..10 {
+            gateway.enqueue(OutboundMessage::discord(CHANNEL, format!("{{\"content\":\"{n}\"}}")));
+        }
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.rate_limited), (10, 0));
+        // Five go at once; each of the other five waits one second for a token.
+        assert_eq!(clock.slept(), vec![Duration::from_secs(1); 5]);
+        let bodies: Vec<String> = transport.requests().into_iter().map(|request| request.body).collect();
+        let expected: Vec<String> = (0..10).map(|n| format!("{{\"content\":\"{n}\"}}")).collect();
+        assert_eq!(bodies, expected);
+    }
+
+    #[test]
+    fn channels_interleave_instead_of_waiting_on_each_other() {
+        let bot_dir = temp_dir("rate-interleave");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport).with_rate_limiter(RateLimiter::new(1, 1.0));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "a1"));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "a2"));
+        gateway.enqueue(OutboundMessage::discord(OTHER_CHANNEL, "b1"));
+        gateway.enqueue(OutboundMessage::discord(OTHER_CHANNEL, "b2"));
+
+        let report = gateway.flush();
+        assert_eq!(report.sent, 4);
+        let bodies: Vec<String> = transport.requests().into_iter().map(|request| request.body).collect();
+        assert_eq!(bodies, ["a1", "b1", "a2", "b2"]);
+        assert_eq!(clock.slept(), vec![Duration::from_secs(1)]);
+    }
+
+    #[test]
+    fn a_429_is_retried_once_after_the_wait_it_names() {
+        let bot_dir = temp_dir("rate-429");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let limited = HttpResponse { status: 429, headers: vec![("retry-after".to_string(), "0.25".to_string())], body: String::new() };
+        let transport = MockTransport::default().reply(Ok(limited));
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport);
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{}"));
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.rate_limited, report.failed), (1, 1, 0));
+        assert_eq!(transport.requests().len(), 2);
+        let slept = clock.slept();
+        assert_eq!(slept.first(), Some(&Duration::from_millis(250)));
+        assert!(secure_log(&gateway).contains("rate limited; retrying in 250ms"));
+    }
+
+    fn queued_bodies(gateway: &DiscordGateway) -> Vec<String> {
+        gateway.queue.iter().map(|(_, message)| message.body().to_string()).collect()
+    }
+
+    #[test]
+    fn the_spool_brings_unsent_messages_back_in_order() {
+        let bot_dir = temp_dir("spool-restart");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let spool_file = DiscoveryLayout::under(&bot_dir).spool_file;
+        let mut first = gateway(&bot_dir, &clock).with_spool(&spool_file);
+        first.enqueue(OutboundMessage::discord(CHANNEL, "one"));
+        first.enqueue(OutboundMessage::discord(CHANNEL, "two\nlines"));
+        first.enqueue(OutboundMessage::webhook("https://hooks.example.com/a/secret", "three").with_priority(Priority::Low));
+        first.enqueue(OutboundMessage::delete(CHANNEL, "323456789012345678"));
+        drop(first);
+
+        let second = gateway(&bot_dir, &clock).with_spool(&spool_file);
+        assert_eq!(second.pending_len(), 4);
+        assert_eq!(queued_bodies(&second), ["one", "two\nlines", "", "three"]);
+        let (messages, warnings) = Spool::new(&spool_file).load();
+        assert!(warnings.is_empty());
+        let ids: Vec<u64> = messages.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, [1, 2, 3, 4]);
+        assert_eq!(messages[2].1.priority, Priority::Low);
+        assert_eq!(messages[3].1.action, Action::Delete { message_id: "323456789012345678".to_string() });
+
+        // New messages continue the numbering instead of reusing a spooled id.
+        let mut second = second;
+        second.enqueue(OutboundMessage::discord(CHANNEL, "five"));
+        assert_eq!(Spool::new(&spool_file).load().0.last().map(|(id, _)| *id), Some(5));
+    }
+
+    #[test]
+    fn a_sent_message_is_acknowledged_in_the_spool() {
+        let bot_dir = temp_dir("spool-ack");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default().reply(Ok(status(200))).reply(Ok(status(503)));
+        let spool_file = DiscoveryLayout::under(&bot_dir).spool_file;
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport).with_spool(&spool_file);
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "sent"));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "kept"));
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.deferred), (1, 1));
+        let (messages, _) = Spool::new(&spool_file).load();
+        let bodies: Vec<&str> = messages.iter().map(|(_, message)| message.body()).collect();
+        assert_eq!(bodies, ["kept"]);
+    }
+
+    #[test]
+    fn a_truncated_last_record_is_skipped_with_a_warning() {
+        let bot_dir = temp_dir("spool-truncated");
+        let spool = Spool::new(DiscoveryLayout::under(&bot_dir).spool_file);
+        spool.append_message(1, &OutboundMessage::discord(CHANNEL, "whole")).unwrap();
+        spool.append_message(2, &OutboundMessage::discord(CHANNEL, "cut short")).unwrap();
+        let raw = fs::read(&spool.path).unwrap();
+        fs::write(&spool.path, &raw[..raw.len() - 4]).unwrap();
+
+        let (messages, warnings) = spool.load();
+        let bodies: Vec<&str> = messages.iter().map(|(_, message)| message.body()).collect();
+        assert_eq!(bodies, ["whole"]);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("truncated write"), "{}", warnings[0]);
+
+        // The damaged tail was compacted away, so a later append reads back cleanly.
+        spool.append_message(3, &OutboundMessage::discord(CHANNEL, "after")).unwrap();
+        let (messages, warnings) = spool.load();
+        assert!(warnings.is_empty());
+        let ids: Vec<u64> = messages.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, [1, 3]);
+    }
+
+    #[test]
+    fn a_corrupted_record_in_the_middle_does_not_poison_the_rest() {
+        let bot_dir = temp_dir("spool-corrupt");
+        let spool = Spool::new(DiscoveryLayout::under(&bot_dir).spool_file);
+        spool.append_message(1, &OutboundMessage::discord(CHANNEL, "first")).unwrap();
+        spool.append_message(2, &OutboundMessage::discord(CHANNEL, "second")).unwrap();
+        spool.append_message(3, &OutboundMessage::discord(CHANNEL, "third")).unwrap();
+        spool.append_ack(1).unwrap();
+        let text = fs::read_to_string(&spool.path).unwrap().replace("second", "sEcond");
+        fs::write(&spool.path, text).unwrap();
+
+        let (messages, warnings) = spool.load();
+        let bodies: Vec<&str> = messages.iter().map(|(_, message)| message.body()).collect();
+        assert_eq!(bodies, ["third"]);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("checksum"), "{}", warnings[0]);
+    }
+
+    fn write_inbox(gateway: &DiscordGateway, name: &str, contents: &str) {
+        fs::create_dir_all(&gateway.layout().inbox_dir).unwrap();
+        fs::write(gateway.layout().inbox_dir.join(name), contents).unwrap();
+    }
+
+    #[test]
+    fn inbox_files_are_queued_in_numeric_name_order() {
+        let bot_dir = temp_dir("inbox-order");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let mut gateway = gateway(&bot_dir, &clock);
+        write_inbox(&gateway, "10-1.cmd", &format!("type=message\nchannel_id={CHANNEL}\nbody=ten\n"));
+        write_inbox(&gateway, "9-2.cmd", &format!("type=message\nchannel_id={CHANNEL}\nbody=nine two\n"));
+        write_inbox(&gateway, "9-1.cmd", &format!("type=message\nchannel_id={CHANNEL}\npriority=normal\nbody={{\n  \"content\": \"nine\"\n}}\n"));
+        write_inbox(&gateway, "11-1.tmp", "type=message\nhalf written");
+
+        let report = gateway.poll_inbox();
+        assert_eq!(report, InboxReport { queued: 3, syncs: 0, rejected: 0 });
+        assert_eq!(queued_bodies(&gateway), ["{\n  \"content\": \"nine\"\n}", "nine two", "ten"]);
+        let inbox = &gateway.layout().inbox_dir;
+        assert!(inbox.join("processed").join("10-1.cmd").is_file());
+        assert!(!inbox.join("10-1.cmd").exists());
+        // Files still being written are left alone.
+        assert!(inbox.join("11-1.tmp").is_file());
+    }
+
+    #[test]
+    fn a_malformed_inbox_file_is_rejected_with_a_reason() {
+        let bot_dir = temp_dir("inbox-rejected");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let mut gateway = gateway(&bot_dir, &clock);
+        write_inbox(&gateway, "1-1.cmd", "type=message\nchannel_id=general\nbody=hi\n");
+        write_inbox(&gateway, "notes.cmd", &format!("type=message\nchannel_id={CHANNEL}\nbody=hi\n"));
+        write_inbox(&gateway, "2-1.cmd", &format!("type=message\nchannel_id={CHANNEL}\nbody=fine\n"));
+
+        let report = gateway.poll_inbox();
+        assert_eq!(report, InboxReport { queued: 1, syncs: 0, rejected: 2 });
+        assert_eq!(queued_bodies(&gateway), ["fine"]);
+        let rejected = gateway.layout().inbox_dir.join("rejected");
+        assert!(rejected.join("1-1.cmd").is_file());
+        let reason = fs::read_to_string(rejected.join("1-1.cmd.reason")).unwrap();
+        assert!(reason.contains("is not a Discord id"), "{reason}");
+        let reason = fs::read_to_string(rejected.join("notes.cmd.reason")).unwrap();
+        assert!(reason.contains("<millis>-<seq>.cmd"), "{reason}");
+    }
+
+    #[test]
+    fn inbox_commands_are_parsed_strictly() {
+        let parse = |text: &str| match parse_inbox_command(text) {
+            Ok(InboxCommand::Message(message)) => Ok(Some(message)),
+            Ok(InboxCommand::SyncCommands) => Ok(None),
+            Err(reason) => Err(reason),
+        };
+        assert!(parse("type=sync-commands\n").unwrap().is_none());
+        let message = parse(&format!("type=message\nchannel_id={CHANNEL}\npriority=critical\ndeliver_after_millis=5\nbody=x")).unwrap().unwrap();
+        assert_eq!((message.priority, message.deliver_after_millis), (Priority::Critical, Some(5)));
+        let edit = parse(&format!("type=message\nchannel_id={CHANNEL}\naction=edit\nmessage_id={OTHER_CHANNEL}\nbody=new")).unwrap().unwrap();
+        assert_eq!(edit.action, Action::Edit { message_id: OTHER_CHANNEL.to_string(), body: "new".to_string() });
+
+        for (text, reason) in [
+            ("channel_id=1\nbody=x", "missing type="),
+            ("type=ping", "unknown type"),
+            ("type=message\nbody=x", "missing channel_id="),
+            (&format!("type=message\nchannel_id={CHANNEL}\ncolour=red\nbody=x") as &str, "unknown key"),
+            (&format!("type=message\nchannel_id={CHANNEL}\nbody=  "), "missing body="),
+            (&format!("type=message\nchannel_id={CHANNEL}\npriority=urgent\nbody=x"), "is not low, normal"),
+            (&format!("type=message\nchannel_id={CHANNEL}\naction=delete\nmessage_id={OTHER_CHANNEL}\nbody=x"), "delete carries no body="),
+            (&format!("type=message\nchannel_id={CHANNEL}\naction=edit\nbody=x"), "need message_id="),
+        ] {
+            let err = parse(text).unwrap_err();
+            assert!(err.contains(reason), "{text:?} gave {err:?}");
+        }
+    }
+
+    #[test]
+    fn presence_freshness_allows_the_ttl_plus_skew() {
+        let now = 1_700_000_000_000u128;
+        let ttl_secs = 60;
+        let skew_ms = u128::from(PRESENCE_CLOCK_SKEW_SECS) * 1000;
+        let nonce = |at: u128| format!("bots/squire|{at}");
+
+        assert!(check_presence_freshness(&nonce(now), now, ttl_secs).is_ok());
+        assert!(check_presence_freshness(&nonce(now - 60_000 - skew_ms), now, ttl_secs).is_ok());
+        let err = check_presence_freshness(&nonce(now - 60_000 - skew_ms - 1_000), now, ttl_secs).unwrap_err();
+        assert!(err.contains("stale: signed 181s ago (TTL 60s"), "{err}");
+
+        // A hub clock running up to the skew ahead is fine; further ahead is not.
+        assert!(check_presence_freshness(&nonce(now + skew_ms), now, ttl_secs).is_ok());
+        let err = check_presence_freshness(&nonce(now + skew_ms + 1_000), now, ttl_secs).unwrap_err();
+        assert!(err.contains("121s in the future"), "{err}");
+
+        assert!(check_presence_freshness("bots/squire", now, ttl_secs).unwrap_err().contains("no timestamp"));
+        assert!(check_presence_freshness("bots/squire|soon", now, ttl_secs).unwrap_err().contains("is not a number"));
+    }
+
+    #[test]
+    fn a_presence_file_goes_stale_as_the_clock_moves() {
+        let bot_dir = temp_dir("presence-ttl");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let env = MapEnv::new().with(PRESENCE_KEY_ENV, KEY_HEX).with(PRESENCE_TTL_ENV, "60");
+        let gateway = gateway(&bot_dir, &clock).with_env(Rc::new(env));
+        write_presence(gateway.layout(), &hmac_key(), &format!("squire|{}", clock.now_millis()));
+
+        assert_eq!(gateway.validate_presence_file(), Ok(true));
+        clock.advance(Duration::from_secs(60 + PRESENCE_CLOCK_SKEW_SECS));
+        assert_eq!(gateway.validate_presence_file(), Ok(true));
+        clock.advance(Duration::from_secs(1));
+        assert!(gateway.validate_presence_file().unwrap_err().contains("stale"));
+
+        // Without the variable the default TTL of 15 minutes applies.
+        let gateway = gateway.with_env(Rc::new(MapEnv::new().with(PRESENCE_KEY_ENV, KEY_HEX)));
+        assert_eq!(gateway.validate_presence_file(), Ok(true));
+    }
+
+    #[test]
+    fn presence_signatures_round_trip_only_under_the_right_key() {
+        let bot_dir = temp_dir("presence-hmac");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        // The hub derives one key per bot from its master key; each bot holds only its own.
+        let master = [0x42u8; 32];
+        let squire_key = hmac_sha256(&master, b"bots/squire");
+        let other_key = hmac_sha256(&master, b"bots/other");
+        let gateway = gateway(&bot_dir, &clock).with_env(Rc::new(MapEnv::new().with(PRESENCE_KEY_ENV, &to_hex(&squire_key))));
+        let nonce = format!("bots/squire|{}", clock.now_millis());
+
+        write_presence(gateway.layout(), &PresenceKey::Hmac(squire_key), &nonce);
+        assert_eq!(gateway.validate_presence_file(), Ok(true));
+        assert_eq!(sign_presence(&PresenceKey::Hmac(squire_key), &nonce).len(), 64);
+
+        write_presence(gateway.layout(), &PresenceKey::Hmac(other_key), &nonce);
+        assert_eq!(gateway.validate_presence_file(), Ok(false));
+
+        // An upper-case signature is the same signature.
+        let text = format!("nonce={}\nsignature={}\n", nonce, sign_presence(&PresenceKey::Hmac(squire_key), &nonce).to_ascii_uppercase());
+        fs::write(&gateway.layout().presence_file, text).unwrap();
+        assert_eq!(gateway.validate_presence_file(), Ok(true));
+    }
+
+    #[test]
+    fn legacy_signatures_need_the_escape_hatch() {
+        let bot_dir = temp_dir("presence-legacy");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let legacy_hex = "00112233445566778899aabbccddeeff";
+        let legacy_key = PresenceKey::Legacy(parse_hex(legacy_hex).unwrap().try_into().unwrap());
+        let nonce = format!("bots/squire|{}", clock.now_millis());
+        let env = MapEnv::new().with(PRESENCE_KEY_ENV, legacy_hex).with(PRESENCE_LEGACY_ENV, "1");
+        let gateway = gateway(&bot_dir, &clock).with_env(Rc::new(env));
+        write_presence(gateway.layout(), &legacy_key, &nonce);
+        assert_eq!(sign_presence(&legacy_key, &nonce).len(), 16);
+        assert_eq!(gateway.validate_presence_file(), Ok(true));
+
+        // Without ECOSYSTEM_PRESENCE_LEGACY=1 the old key is refused with upgrade advice.
+        let gateway = gateway.with_env(Rc::new(MapEnv::new().with(PRESENCE_KEY_ENV, legacy_hex)));
+        let err = gateway.validate_presence_file().unwrap_err();
+        assert!(err.contains("the old SipHash key size") && err.contains(PRESENCE_LEGACY_ENV), "{err}");
+
+        // An HMAC key meeting a SipHash signature names the mismatch.
+        let gateway = gateway.with_env(Rc::new(MapEnv::new().with(PRESENCE_KEY_ENV, KEY_HEX)));
+        assert!(gateway.validate_presence_file().unwrap_err().contains("legacy SipHash signature"));
+        write_presence(gateway.layout(), &hmac_key(), &nonce);
+        let env = MapEnv::new().with(PRESENCE_KEY_ENV, legacy_hex).with(PRESENCE_LEGACY_ENV, "1");
+        let gateway = gateway.with_env(Rc::new(env));
+        assert!(gateway.validate_presence_file().unwrap_err().contains("this bot has a legacy key"));
+    }
+
+    #[test]
+    fn presence_keys_of_the_wrong_size_are_explained() {
+        assert!(matches!(parse_presence_key(KEY_HEX, false), Ok(PresenceKey::Hmac(key)) if key == [0x11; 32]));
+        assert!(matches!(parse_presence_key(&"ab".repeat(16), true), Ok(PresenceKey::Legacy(_))));
+
+        let err = parse_presence_key(&"ab".repeat(10), true).err().unwrap();
+        assert!(err.contains("must be 64 hex characters (32 bytes), got 10 bytes"), "{err}");
+        let err = parse_presence_key(&"zz".repeat(32), false).err().unwrap();
+        assert!(err.contains("is not valid hex"), "{err}");
+        let err = parse_presence_key("abc", false).err().unwrap();
+        assert!(err.contains("is not valid hex"), "{err}");
+        let err = load_presence_key(&MapEnv::new()).err().unwrap();
+        assert!(err.contains("is unset"), "{err}");
+    }
+
+    #[test]
+    fn the_environment_overrides_the_root_passed_in() {
+        let passed = PathBuf::from("/srv/squire/Discovery");
+        let env = MapEnv::new().with(DISCOVERY_ROOT_ENV, "/var/lib/squire/Discovery");
+        let layout = DiscoveryLayout::resolve_from(Some(passed.clone()), &env);
+        assert_eq!(layout.root, PathBuf::from("/var/lib/squire/Discovery"));
+        assert_eq!(layout.presence_file, PathBuf::from("/var/lib/squire/Discovery/ecosystem_presence.txt"));
+        assert_eq!(layout.inbox_dir, PathBuf::from("/var/lib/squire/Discovery/gateway_inbox"));
+
+        assert_eq!(DiscoveryLayout::resolve_from(Some(passed.clone()), &MapEnv::new()), DiscoveryLayout::new(&passed));
+        // An empty variable counts as unset.
+        let empty = MapEnv::new().with(DISCOVERY_ROOT_ENV, "");
+        assert_eq!(DiscoveryLayout::resolve_from(Some(passed.clone()), &empty).root, passed);
+        let fallback = DiscoveryLayout::resolve_from(None, &MapEnv::new());
+        assert_eq!(fallback.root, env::current_dir().unwrap().join("Discovery"));
+    }
+
+    #[test]
+    fn gateways_with_separate_layouts_do_not_interfere() {
+        let workers: Vec<_> = ["left", "right"]
+            .into_iter()
+            .map(|name| {
+                std::thread::spawn(move || {
+                    let bot_dir = temp_dir(&format!("layout-{name}"));
+                    let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+                    let transport = MockTransport::default();
+                    let mut gateway = ready_gateway(&bot_dir, &clock, &transport);
+                    for n in 0..3 {
+                        gateway.enqueue(OutboundMessage::discord(CHANNEL, format!("{name} {n}")));
+                        gateway.append_dispatch(&format!("{name} line {n}"));
+                        assert_eq!(gateway.flush().sent, 1);
+                    }
+                    let bodies: Vec<String> = transport.requests().into_iter().map(|request| request.body).collect();
+                    (bot_dir, bodies)
+                })
+            })
+            .collect();
+
+        for (worker, name) in workers.into_iter().zip(["left", "right"]) {
+            let (bot_dir, bodies) = worker.join().unwrap();
+            assert_eq!(bodies, [0, 1, 2].map(|n| format!("{name} {n}")));
+            let layout = DiscoveryLayout::under(&bot_dir);
+            let dispatch = fs::read_to_string(&layout.dispatch_file).unwrap();
+            assert_eq!(dispatch.lines().count(), 3);
+            assert!(dispatch.lines().all(|line| line.starts_with(name)), "{dispatch}");
+            let heartbeat = fs::read_to_string(&layout.heartbeat_file).unwrap();
+            assert!(heartbeat.contains("seq=3"), "{heartbeat}");
+        }
+    }
+
+    /// A ready gateway that knows its application id and wants `/ping` registered.
+    fn commands_gateway(bot_dir: &Path, clock: &Rc<ManualClock>, transport: &MockTransport, commands: Vec<SlashCommand>) -> DiscordGateway {
+        let mut registry = CommandRegistry::new();
+        for command in commands {
+            registry.add(command).unwrap();
+        }
+        ready_gateway(bot_dir, clock, transport)
+            .with_env(Rc::new(ready_env().with(APPLICATION_ID_ENV, "42")))
+            .with_commands(registry)
+    }
+
+    #[test]
+    fn sync_commands_puts_once_and_then_stays_quiet() {
+        let dir = temp_dir("sync-commands");
+        let clock = Rc::new(ManualClock::new(1_000_000));
+        let transport = MockTransport::default();
+        let ping = SlashCommand::new("ping", "Check the bot is alive");
+        let mut gateway = commands_gateway(&dir, &clock, &transport, vec![ping.clone()]);
+
+        assert_eq!(gateway.sync_commands(TOKEN), Ok(vec![CommandChange::Added("ping".to_string())]));
+        assert_eq!(gateway.sync_commands(TOKEN), Ok(Vec::new()));
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!((requests[0].method, requests[0].path.as_str()), ("PUT", "/api/v10/applications/42/commands"));
+        assert!(secure_log(&gateway).contains("changes=+ping"));
+
+        // Adding an option is a modification and sends the whole set again.
+        let with_option = ping.option(CommandOption::new(OptionType::Boolean, "verbose", "Show timings"));
+        let mut gateway = commands_gateway(&dir, &clock, &transport, vec![with_option]);
+        assert_eq!(gateway.sync_commands(TOKEN), Ok(vec![CommandChange::Modified("ping".to_string())]));
+        assert_eq!(transport.requests().len(), 2);
+        assert!(transport.requests()[1].body.contains("\"verbose\""));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejected_command_sync_leaves_the_cache_alone() {
+        let dir = temp_dir("sync-commands-rejected");
+        let clock = Rc::new(ManualClock::new(1_000_000));
+        let transport = MockTransport::default().reply(Ok(status(400)));
+        let mut gateway = commands_gateway(&dir, &clock, &transport, vec![SlashCommand::new("ping", "Check the bot is alive")]);
+
+        assert!(gateway.sync_commands(TOKEN).is_err());
+        assert!(!gateway.layout().commands_synced_file.exists());
+        // The next attempt sends again because nothing was recorded as synced.
+        assert_eq!(gateway.sync_commands(TOKEN), Ok(vec![CommandChange::Added("ping".to_string())]));
+        assert_eq!(transport.requests().len(), 2);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn command_sync_needs_an_application_id() {
+        let dir = temp_dir("sync-commands-no-app");
+        let clock = Rc::new(ManualClock::new(1_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = commands_gateway(&dir, &clock, &transport, vec![SlashCommand::new("ping", "Check the bot is alive")])
+            .with_env(Rc::new(ready_env()));
+
+        assert_eq!(gateway.sync_commands(TOKEN), Err(format!("{} must be the bot's numeric application id", APPLICATION_ID_ENV)));
+        assert!(transport.requests().is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+
+    /// Written by the Python vault for the master key `[0x42; 32]`; it holds `bot-token-123`.
+    #[cfg(feature = "vault")]
+    const TOKEN_ENVELOPE: &str = r#"{"nonce": "AAECAwQFBgcICQoLDA0OD2RlZmdoaWprbG1ubw==", "ciphertext": "TFm+XcV0JaAi1r4O9g==", "tag": "F7UcgJS54mxd35h8QhgdTQ=="}"#;
+    #[cfg(feature = "vault")]
+    const VAULT_KEY_BASE64: &str = "QkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkI=";
+
+    /// Enqueue one message on a ready gateway reading its token from `source` and flush it.
+    #[cfg(feature = "vault")]
+    fn flush_with_source(name: &str, source: TokenSource, env: MapEnv) -> (FlushReport, Vec<Request>, DiscordGateway) {
+        let bot_dir = temp_dir(name);
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport).with_token_source(source).with_env(Rc::new(env));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{\"content\":\"hi\"}"));
+        let report = gateway.flush();
+        (report, transport.requests(), gateway)
+    }
+
+    #[cfg(feature = "vault")]
+    #[test]
+    fn an_envelope_token_sends_the_same_request_as_an_env_token() {
+        let bot_dir = temp_dir("vault-token");
+        let envelope_path = bot_dir.join("token.enc.json");
+        fs::write(&envelope_path, TOKEN_ENVELOPE).unwrap();
+        let vault = TokenSource::VaultEnvelope { key_env: "TEST_VAULT_KEY".to_string(), envelope_path };
+        let (vault_report, vault_requests, _) = flush_with_source("vault-token-send", vault, ready_env().with("TEST_VAULT_KEY", VAULT_KEY_BASE64));
+        let env_source = TokenSource::Env("TEST_TOKEN".to_string());
+        let (env_report, env_requests, _) = flush_with_source("env-token-send", env_source, ready_env().with("TEST_TOKEN", "bot-token-123"));
+
+        assert_eq!((vault_report.sent, env_report.sent), (1, 1));
+        let strip = |requests: &[Request]| requests.iter().map(|request| (request.method, request.path.clone(), request.headers.clone(), request.body.clone())).collect::<Vec<_>>();
+        assert_eq!(strip(&vault_requests), strip(&env_requests));
+        assert!(vault_requests[0].headers.contains(&("Authorization".to_string(), "Bot bot-token-123".to_string())));
+    }
+
+    #[cfg(feature = "vault")]
+    #[test]
+    fn a_token_that_cannot_be_decrypted_keeps_the_queue() {
+        let bot_dir = temp_dir("vault-broken");
+        let envelope_path = bot_dir.join("token.enc.json");
+        fs::write(&envelope_path, TOKEN_ENVELOPE).unwrap();
+        let wrong_key = "Q0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0NDQ0M=";
+        for (name, env) in [("vault-wrong-key", ready_env().with("TEST_VAULT_KEY", wrong_key)), ("vault-no-key", ready_env())] {
+            let source = TokenSource::VaultEnvelope { key_env: "TEST_VAULT_KEY".to_string(), envelope_path: envelope_path.clone() };
+            let (report, requests, gateway) = flush_with_source(name, source, env);
+            assert_eq!((report.sent, report.failed, report.deferred), (0, 0, 1), "{name}");
+            assert!(requests.is_empty(), "{name}");
+            assert_eq!(gateway.pending_len(), 1, "{name}");
+        }
+        let missing = TokenSource::VaultEnvelope { key_env: "TEST_VAULT_KEY".to_string(), envelope_path: bot_dir.join("absent.json") };
+        assert!(missing.resolve_from(&MapEnv::new().with("TEST_VAULT_KEY", VAULT_KEY_BASE64)).unwrap_err().contains("Unable to read envelope"));
+    }
+
+
+    #[test]
+    fn webhooks_go_out_without_the_token_and_are_logged_redacted() {
+        let bot_dir = temp_dir("webhook-send");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport);
+        let hook = "https://chat.example.org/hooks/3x8ksecret";
+        gateway.enqueue(OutboundMessage::webhook(hook, crate::webhook::text_body("deployed")));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{\"content\":\"deployed\"}"));
+        gateway.enqueue(OutboundMessage::webhook("http://chat.example.org/hooks/plain", "{}"));
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.failed), (2, 1), "the http webhook is refused");
+        let requests = transport.requests();
+        let redacted = WebhookUrl::parse(hook).unwrap().redacted();
+        assert_eq!((requests[0].method, requests[0].path.as_str(), requests[0].body.as_str()), ("WEBHOOK", redacted.as_str(), "{\"text\":\"deployed\"}"));
+        assert!(requests[0].headers.iter().all(|(name, _)| name != "Authorization"));
+        assert_eq!((requests[1].method, requests[1].headers[0].0.as_str()), ("POST", "Authorization"));
+        assert_eq!(requests.len(), 2);
+
+        let log = secure_log(&gateway);
+        assert!(log.contains(&format!("POST webhook {redacted} | status=200")));
+        assert!(!log.contains("3x8ksecret") && !log.contains(TOKEN));
+        let destination = Destination::Webhook { url: hook.to_string() };
+        assert_eq!((destination.kind(), destination.to_string()), ("webhook", format!("webhook:{redacted}")));
+    }
+
+    #[test]
+    fn each_webhook_has_its_own_rate_limit_bucket() {
+        let bot_dir = temp_dir("webhook-buckets");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport).with_rate_limiter(RateLimiter::new(1, 0.001));
+        for hook in ["https://a.example.org/hooks/1", "https://a.example.org/hooks/1", "https://b.example.org/hooks/2"] {
+            gateway.enqueue(OutboundMessage::webhook(hook, "{}"));
+        }
+        let report = gateway.flush();
+        assert_eq!(report.sent, 3);
+        // One token per bucket: the second host goes first while the first one's bucket refills,
+        // and only then does the flush wait.
+        let hosts: Vec<String> = transport.requests().iter().map(|request| request.path.split(' ').next().unwrap().to_string()).collect();
+        assert_eq!(hosts, ["a.example.org", "b.example.org", "a.example.org"]);
+        assert_eq!(clock.slept().len(), 1);
+    }
+
+    #[test]
+    fn the_presence_ttl_comes_from_the_injected_env() {
+        assert_eq!(presence_ttl_secs(&MapEnv::new()), DEFAULT_PRESENCE_TTL_SECS);
+        assert_eq!(presence_ttl_secs(&MapEnv::new().with(PRESENCE_TTL_ENV, " 90 ")), 90);
+        assert_eq!(presence_ttl_secs(&MapEnv::new().with(PRESENCE_TTL_ENV, "soon")), DEFAULT_PRESENCE_TTL_SECS);
+    }
+
+    #[test]
+    fn the_queue_sends_higher_priorities_first_and_keeps_fifo_within_one() {
+        let mut queue = OutboundQueue::default();
+        let priorities = [Priority::Normal, Priority::Low, Priority::Critical, Priority::Normal, Priority::High, Priority::Critical, Priority::Low];
+        for (id, priority) in priorities.into_iter().enumerate() {
+            queue.push(id as u64 + 1, OutboundMessage::discord(CHANNEL, format!("m{}", id + 1)).with_priority(priority));
+        }
+        let order: Vec<u64> = queue.iter().map(|(id, _)| id).collect();
+        assert_eq!(order, [3, 6, 5, 1, 4, 2, 7]);
+        assert_eq!(queue.highest_priority(), Some(Priority::Critical));
+
+        // A message put back under its old id returns to its old place, not the back.
+        let (id, message) = queue.pop_due(0).unwrap();
+        assert_eq!(id, 3);
+        queue.push(id, message);
+        assert_eq!(queue.iter().next().map(|(id, _)| id), Some(3));
+        assert_eq!(OutboundQueue::default().highest_priority(), None);
+    }
+
+    #[test]
+    fn a_scheduled_message_waits_until_its_time() {
+        let mut queue = OutboundQueue::default();
+        queue.push(1, OutboundMessage::discord(CHANNEL, "later").with_priority(Priority::Critical).deliver_after(2_000));
+        queue.push(2, OutboundMessage::discord(CHANNEL, "now"));
+        assert_eq!(queue.scheduled(1_999), 1);
+        let due = queue.take_due(1_999);
+        assert_eq!(due.iter().map(|(id, _)| id).collect::<Vec<_>>(), [2]);
+        assert_eq!(queue.pop_due(1_999).map(|(id, _)| id), None);
+        assert_eq!(queue.pop_due(2_000).map(|(id, _)| id), Some(1));
+
+        let bot_dir = temp_dir("priority-scheduled");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport);
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "later").deliver_after(1_700_000_005_000).with_priority(Priority::High));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "now"));
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.deferred, report.scheduled), (1, 1, 1));
+        assert_eq!(report.highest_waiting, Some(Priority::High));
+        assert!(clock.slept().is_empty(), "a scheduled message is never waited for");
+        clock.advance(Duration::from_millis(4_999));
+        assert_eq!(gateway.flush().sent, 0);
+
+        clock.advance(Duration::from_millis(1));
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.deferred, report.scheduled), (1, 0, 0));
+        let bodies: Vec<String> = transport.requests().into_iter().map(|request| request.body).collect();
+        assert_eq!(bodies, ["now", "later"]);
+    }
+
+    #[test]
+    fn a_critical_message_borrows_a_token_and_the_bucket_owes_it() {
+        let bot_dir = temp_dir("priority-critical");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport).with_rate_limiter(RateLimiter::new(1, 1.0));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "first"));
+        assert_eq!(gateway.flush().sent, 1);
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "normal"));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "alarm").with_priority(Priority::Critical));
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.borrowed), (2, 1));
+        let bodies: Vec<String> = transport.requests().into_iter().map(|request| request.body).collect();
+        assert_eq!(bodies, ["first", "alarm", "normal"]);
+        // The critical send left the bucket one token in debt, so the normal one waited for two.
+        assert_eq!(clock.slept(), vec![Duration::from_secs(2)]);
+
+        let mut limiter = RateLimiter::new(1, 1.0);
+        let start = Instant::now();
+        limiter.consume(CHANNEL, start);
+        limiter.borrow(CHANNEL, start);
+        assert_eq!(limiter.ready_at(CHANNEL, start), start + Duration::from_secs(2));
+        limiter.penalize(CHANNEL, 500, start);
+        assert_eq!(limiter.blocked_until(CHANNEL, start), Some(start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn a_signed_revocation_stops_the_gateway_and_a_forged_one_does_not() {
+        let bot_dir = temp_dir("presence-revoked");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport);
+        let layout = gateway.layout().clone();
+        let signed_at = clock.now_millis();
+
+        // A status line the signature does not cover is ignored: the marker is still good.
+        let nonce = format!("squire|{signed_at}");
+        let forged = format!("nonce={nonce}\nsignature={}\nstatus={PRESENCE_REVOKED}\n", sign_presence(&hmac_key(), &nonce));
+        fs::write(&layout.presence_file, forged).unwrap();
+        assert_eq!(gateway.validate_presence_file(), Ok(true));
+
+        // A revoked nonce under a signature from another key is just a bad marker.
+        let revoked_nonce = format!("squire|{signed_at}|{PRESENCE_REVOKED}");
+        let forged = format!("nonce={revoked_nonce}\nsignature={}\nstatus={PRESENCE_REVOKED}\n", sign_presence(&PresenceKey::Hmac([0x22; 32]), &revoked_nonce));
+        fs::write(&layout.presence_file, forged).unwrap();
+        assert_eq!(gateway.validate_presence_file(), Ok(false));
+
+        write_presence(&layout, &hmac_key(), &revoked_nonce);
+        let err = gateway.validate_presence_file().unwrap_err();
+        assert_eq!(err, format!("{PRESENCE_REVOKED_ERROR} (signed at {signed_at} ms)"));
+        // An old revocation still means stop.
+        clock.advance(Duration::from_secs(DEFAULT_PRESENCE_TTL_SECS * 2));
+        assert!(gateway.validate_presence_file().unwrap_err().starts_with(PRESENCE_REVOKED_ERROR));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{}"));
+        assert_eq!(gateway.flush().deferred, 1);
+        assert!(transport.requests().is_empty());
+
+        // A missing marker reads differently from a revoked one.
+        fs::remove_file(&layout.presence_file).unwrap();
+        let err = gateway.validate_presence_file().unwrap_err();
+        assert_eq!(err, "presence file missing");
+        assert!(!err.starts_with(PRESENCE_REVOKED_ERROR));
+    }
+
+    #[test]
+    fn recordings_hold_each_request_without_the_token_and_replay_the_same_bodies() {
+        use crate::recorder::{load_recordings, replay_message, RecordingTransport, RequestRecorder};
+        let bot_dir = temp_dir("recorder");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let folder = bot_dir.join("recordings");
+        let recording_gateway = |transport: &MockTransport| {
+            let recorder = RequestRecorder::open(&folder).unwrap().with_clock(clock.clone());
+            let gateway = DiscordGateway::with_transport(Box::new(RecordingTransport::new(Box::new(transport.clone()), recorder)))
+                .with_clock(clock.clone())
+                .with_sleeper(clock.clone())
+                .with_env(Rc::new(ready_env()))
+                .with_layout(DiscoveryLayout::under(&bot_dir));
+            write_presence(gateway.layout(), &hmac_key(), &format!("squire|{}", clock.now_millis()));
+            gateway
+        };
+
+        let transport = MockTransport::default();
+        let mut first = recording_gateway(&transport);
+        first.enqueue(OutboundMessage::discord(CHANNEL, "{\"content\":\"one\"}"));
+        first.enqueue(OutboundMessage::edit(CHANNEL, OTHER_CHANNEL, "{\"content\":\"two\"}"));
+        assert_eq!(first.flush().sent, 2);
+        let recordings = load_recordings(&folder).unwrap();
+        assert_eq!(recordings.iter().map(|recording| recording.index).collect::<Vec<_>>(), [1, 2]);
+        let sent = transport.requests();
+        let authorization = sent[0].headers.iter().find(|(name, _)| name == "Authorization").map(|(_, value)| value.clone()).unwrap();
+        let recorded = recordings[0].headers.iter().find(|(name, _)| name == "Authorization").map(|(_, value)| value.clone()).unwrap();
+        assert_eq!(recorded, format!("<redacted auth-digest={:016x}>", short_digest(&authorization)));
+        for entry in fs::read_dir(&folder).unwrap() {
+            assert!(!fs::read_to_string(entry.unwrap().path()).unwrap().contains(TOKEN));
+        }
+        assert_eq!((recordings[1].method.as_str(), recordings[1].at_unix_millis), ("PATCH", 1_700_000_000_000));
+
+        // A second gateway continues the numbering and replays what the first one sent.
+        let transport = MockTransport::default();
+        let mut second = recording_gateway(&transport);
+        for recording in &recordings {
+            second.enqueue(replay_message(recording).unwrap());
+        }
+        assert_eq!(second.flush().sent, 2);
+        let replayed: Vec<(&str, String, String)> = transport.requests().into_iter().map(|request| (request.method, request.path, request.body)).collect();
+        let original: Vec<(&str, String, String)> = sent.into_iter().map(|request| (request.method, request.path, request.body)).collect();
+        assert_eq!(replayed, original);
+        assert_eq!(load_recordings(&folder).unwrap().iter().map(|recording| recording.index).collect::<Vec<_>>(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn challenges_are_answered_with_the_presence_key_and_this_binary() {
+        let bot_dir = temp_dir("challenge");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let mut gateway = gateway(&bot_dir, &clock).with_env(Rc::new(ready_env()));
+        fs::create_dir_all(&gateway.layout().root).unwrap();
+        assert_eq!(gateway.answer_challenge(), Ok(false), "no challenge file is nothing to do");
+        assert!(!gateway.layout().challenge_response_file.exists());
+
+        let nonce = "00112233445566778899aabbccddeeff";
+        fs::write(&gateway.layout().challenge_file, format!("nonce={nonce}\nissued_at=1\nttl_secs=60\n")).unwrap();
+        assert_eq!(gateway.answer_challenge(), Ok(true));
+        let response = fs::read_to_string(&gateway.layout().challenge_response_file).unwrap();
+        let field = |name: &str| response.lines().find_map(|line| line.strip_prefix(name)).unwrap().to_string();
+        let binary = sha256(&fs::read(env::current_exe().unwrap()).unwrap());
+        assert_eq!(field("nonce="), nonce);
+        assert_eq!(field("binary_sha256="), to_hex(&binary));
+        assert_eq!(field("at="), "1700000000000");
+        let mut message = parse_hex(nonce).unwrap();
+        message.extend_from_slice(&binary);
+        assert_eq!(field("signature="), to_hex(&hmac_sha256(&[0x11; 32], &message)));
+
+        // The same nonce is answered again only once half the TTL has passed.
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(gateway.answer_challenge(), Ok(false));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(gateway.answer_challenge(), Ok(true));
+        fs::write(&gateway.layout().challenge_file, "nonce=ffee\nttl_secs=60\n").unwrap();
+        assert_eq!(gateway.answer_challenge(), Ok(true), "a new nonce is answered at once");
+
+        fs::write(&gateway.layout().challenge_file, "nonce=zz\n").unwrap();
+        assert_eq!(gateway.answer_challenge(), Err("challenge nonce is not hex".to_string()));
+        fs::write(&gateway.layout().challenge_file, "nonce=ab12\n").unwrap();
+        let mut keyless = gateway.with_env(Rc::new(MapEnv::new()));
+        assert!(keyless.answer_challenge().unwrap_err().contains("is unset"));
+    }
+
+    const MESSAGE: &str = "323456789012345678";
+
+    #[test]
+    fn edits_and_deletes_use_their_own_method_and_route_but_the_channel_bucket() {
+        let bot_dir = temp_dir("edit-delete");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport).with_rate_limiter(RateLimiter::new(1, 0.001));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{\"content\":\"v1\"}"));
+        gateway.enqueue(OutboundMessage::edit(CHANNEL, MESSAGE, "{\"content\":\"v2\"}"));
+        gateway.enqueue(OutboundMessage::delete(CHANNEL, MESSAGE));
+        gateway.enqueue(OutboundMessage::discord(OTHER_CHANNEL, "{\"content\":\"elsewhere\"}"));
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.failed), (4, 0));
+        let sent: Vec<(&str, String, String)> =
+            transport.requests().into_iter().map(|request| (request.method, request.path, request.body)).collect();
+        let message_path = format!("/api/v10/channels/{CHANNEL}/messages/{MESSAGE}");
+        assert_eq!(
+            sent,
+            [
+                ("POST", format!("/api/v10/channels/{CHANNEL}/messages"), "{\"content\":\"v1\"}".to_string()),
+                // The other channel has its own bucket and goes while this one refills ...
+                ("POST", format!("/api/v10/channels/{OTHER_CHANNEL}/messages"), "{\"content\":\"elsewhere\"}".to_string()),
+                // ... but the edit and the delete wait for the channel's bucket like a new message.
+                ("PATCH", message_path.clone(), "{\"content\":\"v2\"}".to_string()),
+                ("DELETE", message_path.clone(), String::new()),
+            ]
+        );
+        assert_eq!(clock.slept().len(), 2);
+        assert!(transport.requests()[3].headers.contains(&("Authorization".to_string(), format!("Bot {TOKEN}"))));
+
+        let log = secure_log(&gateway);
+        assert!(log.contains(&format!("PATCH {message_path} | action=edit message_id={MESSAGE} | status=200 | body=16 bytes")), "{log}");
+        assert!(log.contains(&format!("DELETE {message_path} | action=delete message_id={MESSAGE} | status=200 | body=0 bytes")), "{log}");
+        assert!(!log.contains(TOKEN));
+    }
+
+    #[test]
+    fn dry_runs_log_edits_and_deletes_and_webhooks_cannot_take_them() {
+        let bot_dir = temp_dir("edit-delete-dry-run");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let mut gateway = DiscordGateway::with_transport(Box::new(DryRunTransport))
+            .with_clock(clock.clone())
+            .with_sleeper(clock.clone())
+            .with_env(Rc::new(MapEnv::new().with(PRESENCE_KEY_ENV, KEY_HEX)))
+            .with_layout(DiscoveryLayout::under(&bot_dir));
+        write_presence(gateway.layout(), &hmac_key(), &format!("squire|{}", clock.now_millis()));
+        gateway.enqueue(OutboundMessage::edit(CHANNEL, MESSAGE, "{\"content\":\"v2\"}"));
+        gateway.enqueue(OutboundMessage::delete(CHANNEL, MESSAGE));
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.failed), (2, 0));
+        let log = secure_log(&gateway);
+        let message_path = format!("/api/v10/channels/{CHANNEL}/messages/{MESSAGE}");
+        assert!(log.contains(&format!("DRY-RUN PATCH {message_path} | action=edit message_id={MESSAGE} | status=200")), "{log}");
+        assert!(log.contains(&format!("DRY-RUN DELETE {message_path} | action=delete message_id={MESSAGE} | status=200")), "{log}");
+
+        // Something that slipped past validation still never reaches a webhook as an edit.
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&temp_dir("edit-webhook"), &clock, &transport);
+        let edit = Action::Edit { message_id: MESSAGE.to_string(), body: "{}".to_string() };
+        gateway.enqueue(OutboundMessage { action: edit, ..OutboundMessage::webhook("https://hooks.example.com/x", "") });
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.failed), (0, 1));
+        assert!(transport.requests().is_empty());
+    }
+
+    /// `(id, status, send offset in ms, borrowed)` for every planned message.
+    fn plan_rows(plan: &FlushPlan) -> Vec<(u64, PlanStatus, Option<u128>, bool)> {
+        plan.messages.iter().map(|message| (message.id, message.status, message.send_offset.map(|offset| offset.as_millis()), message.borrowed)).collect()
+    }
+
+    #[test]
+    fn a_plan_changes_nothing_and_the_flush_then_does_what_it_said() {
+        let bot_dir = temp_dir("plan-untouched");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let spool_file = DiscoveryLayout::under(&bot_dir).spool_file;
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport).with_rate_limiter(RateLimiter::new(1, 2.0)).with_spool(&spool_file);
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "one"));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "two"));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "urgent").with_priority(Priority::High));
+        let bodies_by_id: BTreeMap<u64, String> = gateway.queue.iter().map(|(id, message)| (id, message.body().to_string())).collect();
+        let spool = fs::read(&spool_file).unwrap();
+
+        let plan = gateway.plan();
+        assert_eq!(plan, gateway.plan(), "planning twice gives the same plan");
+        assert_eq!(queued_bodies(&gateway).len(), 3);
+        assert_eq!(fs::read(&spool_file).unwrap(), spool);
+        assert!(!gateway.layout().secure_dispatch_file.exists());
+        assert!(transport.requests().is_empty() && clock.slept().is_empty());
+
+        let report = gateway.flush();
+        assert_eq!(report.sent, 3);
+        let sent: Vec<String> = transport.requests().into_iter().map(|request| request.body).collect();
+        let planned: Vec<String> = plan.messages.iter().map(|message| bodies_by_id[&message.id].clone()).collect();
+        assert_eq!(sent, planned);
+        assert_eq!(sent, ["urgent", "one", "two"]);
+        let slept: u128 = clock.slept().iter().map(Duration::as_millis).sum();
+        assert_eq!(plan.messages.last().and_then(|message| message.send_offset).map(|offset| offset.as_millis()), Some(slept));
+    }
+
+    #[test]
+    fn send_offsets_follow_the_rate_limiter() {
+        let bot_dir = temp_dir("plan-offsets");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        // Two tokens to start with, then one every 500 ms per destination.
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport).with_rate_limiter(RateLimiter::new(2, 2.0));
+        for n in 1..=4 {
+            gateway.enqueue(OutboundMessage::discord(CHANNEL, format!("c{n}")));
+        }
+        gateway.enqueue(OutboundMessage::discord(OTHER_CHANNEL, "elsewhere"));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "alarm").with_priority(Priority::Critical));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "tomorrow").deliver_after(1_700_086_400_000));
+
+        let plan = gateway.plan();
+        assert_eq!(plan.blocked, None);
+        let ids: Vec<u64> = gateway.queue.iter().map(|(id, _)| id).collect();
+        let id_of = |body: &str| gateway.queue.iter().find(|(_, message)| message.body() == body).map(|(id, _)| id).unwrap();
+        assert_eq!(ids.len(), 7);
+        assert_eq!(
+            plan_rows(&plan),
+            [
+                // The critical message goes first on the channel's own budget ...
+                (id_of("alarm"), PlanStatus::Send, Some(0), false),
+                (id_of("c1"), PlanStatus::Send, Some(0), false),
+                // ... the quiet channel overtakes the empty bucket ...
+                (id_of("elsewhere"), PlanStatus::Send, Some(0), false),
+                // ... and the rest wait a refill each.
+                (id_of("c2"), PlanStatus::Send, Some(500), false),
+                (id_of("c3"), PlanStatus::Send, Some(1_000), false),
+                (id_of("c4"), PlanStatus::Send, Some(1_500), false),
+                (id_of("tomorrow"), PlanStatus::Scheduled, None, false),
+            ]
+        );
+        assert_eq!((plan.count(PlanStatus::Send), plan.count(PlanStatus::Scheduled), plan.count(PlanStatus::Blocked)), (6, 1, 0));
+
+        // With the bucket already empty, a critical message borrows instead of waiting.
+        let mut gateway = ready_gateway(&temp_dir("plan-borrow"), &clock, &transport).with_rate_limiter(RateLimiter::new(1, 1.0));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "first"));
+        assert_eq!(gateway.flush().sent, 1);
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "normal"));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "alarm").with_priority(Priority::Critical));
+        let offsets: Vec<(Option<u128>, bool)> = gateway.plan().messages.iter().map(|message| (message.send_offset.map(|offset| offset.as_millis()), message.borrowed)).collect();
+        // The same two seconds `a_critical_message_borrows_a_token_and_the_bucket_owes_it` sleeps.
+        assert_eq!(offsets, [(Some(0), true), (Some(2_000), false)]);
+    }
+
+    #[test]
+    fn a_plan_says_why_nothing_would_be_sent() {
+        let bot_dir = temp_dir("plan-blocked");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport);
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{\"content\":\"hi\"}"));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "later").deliver_after(1_700_000_060_000));
+
+        fs::remove_file(&gateway.layout().presence_file).unwrap();
+        let plan = gateway.plan();
+        assert!(plan.blocked.as_deref().is_some_and(|reason| !reason.is_empty()), "{plan:?}");
+        assert_eq!(plan.messages.iter().map(|message| message.status).collect::<Vec<_>>(), [PlanStatus::Blocked, PlanStatus::Scheduled]);
+        assert!(plan.messages.iter().all(|message| message.send_offset.is_none()));
+
+        fs::write(&gateway.layout().presence_file, format!("nonce=squire|{}\nsignature={}\n", clock.now_millis(), "00".repeat(32))).unwrap();
+        assert_eq!(gateway.plan().blocked.as_deref(), Some("presence file has a bad signature"));
+
+        write_presence(gateway.layout(), &hmac_key(), &format!("squire|{}", clock.now_millis()));
+        assert_eq!(gateway.plan().blocked, None);
+        let gateway = gateway.with_env(Rc::new(MapEnv::new().with(PRESENCE_KEY_ENV, KEY_HEX)));
+        assert_eq!(gateway.plan().blocked.as_deref(), Some("missing bot token (config or SQUIRE_DISCORD_TOKEN)"));
+        assert!(transport.requests().is_empty());
+    }
+
+    #[test]
+    fn plans_print_as_a_summary_line_and_one_line_per_message() {
+        let bot_dir = temp_dir("plan-json");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let transport = MockTransport::default();
+        let mut gateway = ready_gateway(&bot_dir, &clock, &transport).with_rate_limiter(RateLimiter::new(1, 1.0));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "{\"content\":\"a\"}").with_priority(Priority::High));
+        gateway.enqueue(OutboundMessage::discord(CHANNEL, "  "));
+        gateway.enqueue(OutboundMessage::webhook("https://hooks.example.com/api/webhooks/1/secret-token", "{}").deliver_after(1_700_000_009_000));
+
+        let lines = gateway.plan().to_json_lines();
+        assert_eq!(lines.len(), 4);
+        let parsed: Vec<crate::json::JsonValue> = lines.iter().map(|line| crate::json::parse(line).unwrap()).collect();
+        let number = |value: &crate::json::JsonValue, key: &str| value.get(key).and_then(crate::json::JsonValue::as_f64);
+        let text = |value: &crate::json::JsonValue, key: &str| value.get(key).and_then(crate::json::JsonValue::as_str).map(str::to_string);
+        let summary = &parsed[0];
+        assert_eq!(text(summary, "action").as_deref(), Some("plan"));
+        assert_eq!(summary.get("blocked"), Some(&crate::json::JsonValue::Null));
+        let counts: Vec<Option<f64>> = ["queued", "send", "scheduled", "blocked_messages", "invalid", "duration_ms"].iter().map(|key| number(summary, key)).collect();
+        assert_eq!(counts, [Some(3.0), Some(2.0), Some(1.0), Some(0.0), Some(1.0), Some(1_000.0)]);
+
+        let first = &parsed[1];
+        assert_eq!((text(first, "status").as_deref(), text(first, "priority").as_deref()), (Some("send"), Some("high")));
+        assert_eq!(text(first, "destination"), Some(format!("discord:{CHANNEL}")));
+        assert_eq!((text(first, "action").as_deref(), number(first, "send_offset_ms"), number(first, "body_bytes")), (Some("create"), Some(0.0), Some(15.0)));
+        assert_eq!((first.get("valid"), first.get("error")), (Some(&crate::json::JsonValue::Bool(true)), Some(&crate::json::JsonValue::Null)));
+        let empty = &parsed[2];
+        assert_eq!((number(empty, "send_offset_ms"), empty.get("valid")), (Some(1_000.0), Some(&crate::json::JsonValue::Bool(false))));
+        assert_eq!(text(empty, "error").as_deref(), Some("message has no content, embeds, or buttons"));
+        let scheduled = &parsed[3];
+        assert_eq!((text(scheduled, "status").as_deref(), number(scheduled, "deliver_after_millis")), (Some("scheduled"), Some(1_700_000_009_000.0)));
+        assert_eq!(scheduled.get("send_offset_ms"), Some(&crate::json::JsonValue::Null));
+        assert!(!lines[3].contains("secret-token"), "{}", lines[3]);
+    }
+}
@@ -0,0 +1,453 @@
+//! Per-guild settings laid over the global `config.json`.
+//!
+//! `Config` holds one set of settings for the whole bot, but a bot in several guilds (Discord
+//! servers) needs some of them per guild: which channel gets forwarded log lines, whether XP is
+//! on, how fast XP grows. Each guild can therefore have its own small file,
+//! `<database_path>/guilds/<guild id>.json`, holding only what differs from the global config:
+//!
+//! ```text
+//! {
+//!   "logging_channel_id": "123456789012345678",
+//!   "feature_flags": {"xp": false, "beta_commands": {"enabled": true, "percentage": 20}},
+//!   "xp_multiplier": 1.5
+//! }
+//! ```
+//!
+//! Every key may be `null` or left out, meaning "use the global value". `effective` works out
+//! the merged result: a guild's value wins over the global one, and feature flags are merged
+//! flag by flag, the same way an `include`d config file is.
+//!
+//! How the files are handled:
+//! - **Lazy creation.** The first `load` of a guild without a file writes one with every key set
+//!   to `null`, so operators find a template to edit instead of having to know the key names.
+//! - **Safe updates.** `set` holds the file's lock (see `lockfile`) while it reads, changes one
+//!   key, and writes the file back with `atomic::atomic_write`. Two writers, in two threads or two
+//!   processes, take turns, so neither one's change is lost.
+//! - **Bad files.** A file that is not valid JSON, or holds a value of the wrong kind, is renamed
+//!   to `<guild id>.json.bad-<unix millis>` (with `-<n>` added if that name is taken) with a
+//!   warning and replaced by a fresh template. The guild falls back to the global settings; the
+//!   bot keeps running.
+//! - **Cache.** Lookups happen on every event, so parsed files are kept in memory and only read
+//!   again when their modification time or size changes.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::atomic::atomic_write;
+use crate::config::{self, AppError, Config, FeatureFlags};
+use crate::json::{self, JsonValue};
+use crate::lockfile::FileLock;
+use crate::log::Logger;
+use crate::message::json_escape;
+use crate::snowflake::Snowflake;
+
+/// Folder inside `database_path` that holds one file per guild.
+pub const GUILDS_DIR: &str = "guilds";
+/// XP multiplier for guilds that do not set one.
+pub const DEFAULT_XP_MULTIPLIER: f64 = 1.0;
+/// Keys a guild file may hold. `set` also takes `feature_flags.<name>` for a single flag.
+pub const GUILD_KEYS: &[&str] = &["logging_channel_id", "feature_flags", "xp_multiplier"];
+
+const LOG: Logger = Logger::new("guild-settings");
+
+/// What one guild file overrides. `None` (or a flag that is not listed) means "use the global value".
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GuildOverrides {
+    pub logging_channel_id: Option<String>,
+    pub feature_flags: FeatureFlags,
+    pub xp_multiplier: Option<f64>,
+}
+
+impl GuildOverrides {
+    /// Read a guild file. Every problem is listed in the error, like `Config::load` does.
+    pub fn parse(text: &str) -> Result<GuildOverrides, String> {
+        let document = json::parse(text)?;
+        let JsonValue::Object(fields) = &document else {
+            return Err("a guild file must hold one JSON object".to_string());
+        };
+        let mut overrides = GuildOverrides::default();
+        let mut errors = AppError::default();
+        for (key, value) in fields {
+            if let Err(problem) = overrides.apply(key, value) {
+                errors.push(problem);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors.problems.join("; "));
+        }
+        Ok(overrides)
+    }
+
+    /// Change one key: `logging_channel_id`, `xp_multiplier`, the whole `feature_flags` object,
+    /// or one flag as `feature_flags.<name>`. `null` clears the override.
+    pub fn apply(&mut self, key: &str, value: &JsonValue) -> Result<(), String> {
+        match (key, value) {
+            ("logging_channel_id", JsonValue::Null) => self.logging_channel_id = None,
+            ("logging_channel_id", JsonValue::String(id)) => match Snowflake::parse(id) {
+                Ok(_) => self.logging_channel_id = Some(id.clone()),
+                Err(problem) => return Err(format!("logging_channel_id {}", problem)),
+            },
+            ("logging_channel_id", _) => {
+                // Discord ids exceed what a JSON number can hold exactly, so only strings are accepted.
+                return Err("logging_channel_id must be a numeric id written as a string, or null".to_string());
+            }
+            ("xp_multiplier", JsonValue::Null) => self.xp_multiplier = None,
+            ("xp_multiplier", value) => match value.as_f64() {
+                Some(multiplier) if multiplier.is_finite() && multiplier >= 0.0 => self.xp_multiplier = Some(multiplier),
+                _ => return Err("xp_multiplier must be a number of at least 0, or null".to_string()),
+            },
+            ("feature_flags", JsonValue::Null) => self.feature_flags = FeatureFlags::default(),
+            ("feature_flags", JsonValue::Object(fields)) => {
+                let mut errors = AppError::default();
+                let flags = FeatureFlags::parse(fields, &mut errors);
+                if !errors.is_empty() {
+                    return Err(errors.problems.join("; "));
+                }
+                self.feature_flags = flags;
+            }
+            ("feature_flags", _) => return Err("feature_flags must be an object of switches, or null".to_string()),
+            (key, value) => {
+                let Some(name) = key.strip_prefix("feature_flags.").filter(|name| !name.is_empty()) else {
+                    // A typo such as "xp_multiplyer" would otherwise be kept and never used.
+                    return Err(format!("unknown key {:?}; expected one of {}", key, GUILD_KEYS.join(", ")));
+                };
+                if matches!(value, JsonValue::Null) {
+                    self.feature_flags.remove(name);
+                } else {
+                    let flag = config::parse_flag(value).map_err(|problem| format!("feature_flags.{}: {}", name, problem))?;
+                    self.feature_flags.insert(name, flag);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The file as written by `set`: every key on its own line, `null` for what is not overridden.
+    pub fn to_json(&self) -> String {
+        let channel = match &self.logging_channel_id {
+            Some(id) => format!("\"{}\"", json_escape(id)),
+            None => "null".to_string(),
+        };
+        let flags: Vec<String> = self
+            .feature_flags
+            .iter()
+            .map(|(name, flag)| {
+                let value = if flag.percentage == 100 {
+                    flag.enabled.to_string()
+                } else {
+                    format!("{{\"enabled\": {}, \"percentage\": {}}}", flag.enabled, flag.percentage)
+                };
+                format!("\"{}\": {}", json_escape(name), value)
+            })
+            .collect();
+        let multiplier = self.xp_multiplier.map_or("null".to_string(), |multiplier| multiplier.to_string());
+        format!(
+            "{{\n  \"logging_channel_id\": {},\n  \"feature_flags\": {{{}}},\n  \"xp_multiplier\": {}\n}}\n",
+            channel,
+            flags.join(", "),
+            multiplier
+        )
+    }
+}
+
+/// A guild's settings after its overrides are laid over the global config.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EffectiveSettings {
+    pub logging_channel_id: Option<String>,
+    pub feature_flags: FeatureFlags,
+    pub xp_multiplier: f64,
+}
+
+/// The per-guild files under `<database_path>/guilds/`, with a cache of what was read.
+///
+/// Share one value between threads (it is `Sync`); every method takes `&self`.
+#[derive(Debug)]
+pub struct GuildSettings {
+    dir: PathBuf,
+    /// guild id -> (modification time and size when read, parsed file).
+    cache: Mutex<BTreeMap<String, (FileStamp, GuildOverrides)>>,
+}
+
+/// Modification time and size of a file, to notice when it was changed.
+type FileStamp = (SystemTime, u64);
+
+impl GuildSettings {
+    /// Settings kept under `<database_path>/guilds/`. Nothing is created until it is needed.
+    pub fn new(database_path: &Path) -> GuildSettings {
+        GuildSettings { dir: database_path.join(GUILDS_DIR), cache: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// The `guilds/` folder.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// `<dir>/<guild id>.json`. The id must be a Discord id, so it can never name a file
+    /// outside the folder.
+    pub fn path_for(&self, guild_id: &str) -> Result<PathBuf, String> {
+        Snowflake::parse(guild_id).map_err(|problem| format!("guild id {:?} {}", guild_id, problem))?;
+        Ok(self.dir.join(format!("{}.json", guild_id)))
+    }
+
+    /// The guild's overrides, creating its file with no overrides when there is none yet.
+    pub fn load(&self, guild_id: &str) -> Result<GuildOverrides, String> {
+        let path = self.path_for(guild_id)?;
+        if let Some(stamp) = file_stamp(&path) {
+            let cache = self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Some((cached_stamp, overrides)) = cache.get(guild_id) {
+                if *cached_stamp == stamp {
+                    return Ok(overrides.clone());
+                }
+            }
+        }
+        // Writers hold the lock, so the file read here is a whole one and its stamp matches it.
+        let _lock = self.lock(&path)?;
+        let overrides = read_or_repair(&path)?;
+        self.remember(guild_id, &path, &overrides);
+        Ok(overrides)
+    }
+
+    /// The guild's settings with its overrides laid over `global`.
+    pub fn effective(&self, global: &Config, guild_id: &str) -> Result<EffectiveSettings, String> {
+        let overrides = self.load(guild_id)?;
+        Ok(EffectiveSettings {
+            logging_channel_id: overrides.logging_channel_id.or_else(|| global.logging_channel_id.clone()),
+            feature_flags: global.feature_flags.overlaid(&overrides.feature_flags),
+            xp_multiplier: overrides.xp_multiplier.unwrap_or(DEFAULT_XP_MULTIPLIER),
+        })
+    }
+
+    /// Just the merged feature flags; see `effective`.
+    pub fn effective_flags(&self, global: &Config, guild_id: &str) -> Result<FeatureFlags, String> {
+        Ok(self.effective(global, guild_id)?.feature_flags)
+    }
+
+    /// Change one key in the guild's file (see `GuildOverrides::apply` for the keys) and return
+    /// the overrides as written. The file is read again under its lock rather than taken from the
+    /// cache, so a change made meanwhile by another process is kept.
+    pub fn set(&self, guild_id: &str, key: &str, value: &JsonValue) -> Result<GuildOverrides, String> {
+        let path = self.path_for(guild_id)?;
+        let _lock = self.lock(&path)?;
+        let mut overrides = read_or_repair(&path)?;
+        overrides.apply(key, value)?;
+        atomic_write(&path, overrides.to_json().as_bytes()).map_err(|err| format!("Unable to write {:?}: {}", path, err))?;
+        self.remember(guild_id, &path, &overrides);
+        LOG.info("Guild setting changed", &[("guild", guild_id), ("key", key)]);
+        Ok(overrides)
+    }
+
+    /// Create the folder if needed and take the file's lock.
+    fn lock(&self, path: &Path) -> Result<FileLock, String> {
+        fs::create_dir_all(&self.dir).map_err(|err| format!("Unable to create {:?}: {}", self.dir, err))?;
+        FileLock::acquire(path).map_err(|err| format!("Unable to lock {:?}: {}", path, err))
+    }
+
+    fn remember(&self, guild_id: &str, path: &Path, overrides: &GuildOverrides) {
+        let mut cache = self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match file_stamp(path) {
+            Some(stamp) => {
+                cache.insert(guild_id.to_string(), (stamp, overrides.clone()));
+            }
+            None => {
+                cache.remove(guild_id);
+            }
+        }
+    }
+}
+
+/// Read `path`, which the caller has locked. A missing file is created with no overrides; a bad
+/// one is moved aside (see the module notes) and replaced the same way.
+fn read_or_repair(path: &Path) -> Result<GuildOverrides, String> {
+    let problem = match fs::read(path) {
+        Ok(bytes) => match String::from_utf8(bytes).map_err(|_| "not UTF-8 text".to_string()).and_then(|text| GuildOverrides::parse(&text)) {
+            Ok(overrides) => return Ok(overrides),
+            Err(problem) => Some(problem),
+        },
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => return Err(format!("Unable to read {:?}: {}", path, err)),
+    };
+    if let Some(problem) = problem {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis()).unwrap_or(0);
+        // Two bad files in one millisecond must not overwrite each other's evidence.
+        let mut quarantine = PathBuf::from(format!("{}.bad-{}", path.display(), millis));
+        for n in 1.. {
+            if !quarantine.exists() {
+                break;
+            }
+            quarantine = PathBuf::from(format!("{}.bad-{}-{}", path.display(), millis, n));
+        }
+        fs::rename(path, &quarantine).map_err(|err| format!("Unable to move bad file {:?} aside: {}", path, err))?;
+        LOG.warn(
+            "Guild settings file is invalid; moved aside, using the global settings",
+            &[("file", &path.display().to_string()), ("moved_to", &quarantine.display().to_string()), ("problem", &problem)],
+        );
+    }
+    let overrides = GuildOverrides::default();
+    atomic_write(path, overrides.to_json().as_bytes()).map_err(|err| format!("Unable to create {:?}: {}", path, err))?;
+    Ok(overrides)
+}
+
+fn file_stamp(path: &Path) -> Option<FileStamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FeatureFlag;
+
+    const GUILD: &str = "323456789012345678";
+    const OTHER_GUILD: &str = "423456789012345678";
+    const GLOBAL_CHANNEL: &str = "123456789012345678";
+    const GUILD_CHANNEL: &str = "223456789012345678";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("squire-guild-settings-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn global(database_path: &Path) -> Config {
+        let mut feature_flags = FeatureFlags::default();
+        feature_flags.insert("xp", FeatureFlag { enabled: true, percentage: 100 });
+        feature_flags.insert("beta_commands", FeatureFlag { enabled: false, percentage: 100 });
+        Config {
+            path: database_path.join("config.json"),
+            fingerprint: String::new(),
+            layers: Vec::new(),
+            discord_token: None,
+            logging_channel_id: Some(GLOBAL_CHANNEL.to_string()),
+            database_path: Some(database_path.to_path_buf()),
+            feature_flags,
+        }
+    }
+
+    fn quarantined(settings: &GuildSettings) -> Vec<PathBuf> {
+        let mut found: Vec<PathBuf> = fs::read_dir(settings.dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().contains(".json.bad-"))
+            .collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn a_guild_value_wins_over_the_global_one() {
+        let dir = temp_dir("precedence");
+        let settings = GuildSettings::new(&dir);
+        let global = global(&dir);
+        fs::create_dir_all(settings.dir()).unwrap();
+        let file = format!("{{\"logging_channel_id\": \"{GUILD_CHANNEL}\", \"feature_flags\": {{\"xp\": false, \"rank\": {{\"enabled\": true, \"percentage\": 20}}}}, \"xp_multiplier\": 1.5}}");
+        fs::write(settings.path_for(GUILD).unwrap(), file).unwrap();
+
+        let effective = settings.effective(&global, GUILD).unwrap();
+        assert_eq!(effective.logging_channel_id.as_deref(), Some(GUILD_CHANNEL));
+        assert_eq!(effective.xp_multiplier, 1.5);
+        assert!(!effective.feature_flags.is_enabled("xp"), "the guild turned xp off");
+        assert_eq!(effective.feature_flags.get("beta_commands"), Some(FeatureFlag { enabled: false, percentage: 100 }), "kept from the global config");
+        assert_eq!(effective.feature_flags.get("rank"), Some(FeatureFlag { enabled: true, percentage: 20 }));
+        assert_eq!(settings.effective_flags(&global, GUILD).unwrap(), effective.feature_flags);
+
+        // Clearing an override falls back to the global value.
+        settings.set(GUILD, "logging_channel_id", &JsonValue::Null).unwrap();
+        settings.set(GUILD, "feature_flags.xp", &JsonValue::Null).unwrap();
+        let effective = settings.effective(&global, GUILD).unwrap();
+        assert_eq!(effective.logging_channel_id.as_deref(), Some(GLOBAL_CHANNEL));
+        assert!(effective.feature_flags.is_enabled("xp"));
+
+        let untouched = settings.effective(&global, OTHER_GUILD).unwrap();
+        assert_eq!(untouched, EffectiveSettings { logging_channel_id: Some(GLOBAL_CHANNEL.to_string()), feature_flags: global.feature_flags.clone(), xp_multiplier: DEFAULT_XP_MULTIPLIER });
+    }
+
+    #[test]
+    fn an_unknown_guild_gets_a_template_file_on_first_load() {
+        let dir = temp_dir("lazy");
+        let settings = GuildSettings::new(&dir);
+        assert!(!settings.dir().exists(), "nothing is created up front");
+        assert_eq!(settings.load(GUILD).unwrap(), GuildOverrides::default());
+        let text = fs::read_to_string(settings.path_for(GUILD).unwrap()).unwrap();
+        assert_eq!(text, "{\n  \"logging_channel_id\": null,\n  \"feature_flags\": {},\n  \"xp_multiplier\": null\n}\n");
+        assert_eq!(GuildOverrides::parse(&text).unwrap(), GuildOverrides::default());
+
+        for bad in ["../escape", "guild", ""] {
+            assert!(settings.path_for(bad).unwrap_err().starts_with("guild id"), "{bad}");
+        }
+    }
+
+    #[test]
+    fn two_threads_setting_keys_at_once_lose_nothing() {
+        let dir = temp_dir("concurrent");
+        let settings = GuildSettings::new(&dir);
+        std::thread::scope(|scope| {
+            for thread in 0..2 {
+                let settings = &settings;
+                scope.spawn(move || {
+                    for n in 0..15 {
+                        settings.set(GUILD, &format!("feature_flags.t{thread}_{n}"), &JsonValue::Bool(true)).unwrap();
+                    }
+                });
+            }
+        });
+        let written = GuildOverrides::parse(&fs::read_to_string(settings.path_for(GUILD).unwrap()).unwrap()).unwrap();
+        assert_eq!(written.feature_flags.iter().count(), 30);
+        assert_eq!(settings.load(GUILD).unwrap(), written);
+    }
+
+    #[test]
+    fn an_invalid_guild_file_is_moved_aside_and_replaced() {
+        let dir = temp_dir("quarantine");
+        let settings = GuildSettings::new(&dir);
+        let global = global(&dir);
+        fs::create_dir_all(settings.dir()).unwrap();
+        let path = settings.path_for(GUILD).unwrap();
+        fs::write(&path, "{\"xp_multiplier\": ").unwrap();
+
+        let effective = settings.effective(&global, GUILD).unwrap();
+        assert_eq!(effective.logging_channel_id.as_deref(), Some(GLOBAL_CHANNEL), "the guild falls back to the global settings");
+        let moved = quarantined(&settings);
+        assert_eq!(moved.len(), 1);
+        assert_eq!(fs::read_to_string(&moved[0]).unwrap(), "{\"xp_multiplier\": ");
+        assert_eq!(fs::read_to_string(&path).unwrap(), GuildOverrides::default().to_json());
+
+        // Valid JSON with a value of the wrong kind is just as unusable.
+        fs::write(&path, "{\"xp_multiplier\": \"fast\", \"colour\": \"red\"}").unwrap();
+        assert_eq!(settings.load(GUILD).unwrap(), GuildOverrides::default());
+        assert_eq!(quarantined(&settings).len(), 2);
+    }
+
+    #[test]
+    fn the_cache_notices_a_file_edited_by_hand() {
+        let dir = temp_dir("cache");
+        let settings = GuildSettings::new(&dir);
+        settings.set(GUILD, "xp_multiplier", &JsonValue::Number(2.0)).unwrap();
+        assert_eq!(settings.load(GUILD).unwrap().xp_multiplier, Some(2.0));
+        fs::write(settings.path_for(GUILD).unwrap(), "{\"xp_multiplier\": 0.25}").unwrap();
+        assert_eq!(settings.load(GUILD).unwrap().xp_multiplier, Some(0.25));
+    }
+
+    #[test]
+    fn keys_and_values_are_checked_before_anything_is_written() {
+        let mut overrides = GuildOverrides::default();
+        let string = |text: &str| JsonValue::String(text.to_string());
+        assert!(overrides.apply("xp_multiplyer", &JsonValue::Number(1.0)).unwrap_err().starts_with("unknown key \"xp_multiplyer\""));
+        assert!(overrides.apply("xp_multiplier", &JsonValue::Number(-1.0)).is_err());
+        assert!(overrides.apply("logging_channel_id", &string("general")).unwrap_err().starts_with("logging_channel_id"));
+        assert!(overrides.apply("logging_channel_id", &JsonValue::Number(1.0)).unwrap_err().contains("written as a string"));
+        assert!(overrides.apply("feature_flags", &JsonValue::Bool(true)).is_err());
+        assert!(overrides.apply("feature_flags.", &JsonValue::Bool(true)).is_err());
+        assert_eq!(overrides, GuildOverrides::default());
+        assert!(GuildOverrides::parse("[]").unwrap_err().contains("one JSON object"));
+
+        let dir = temp_dir("rejected");
+        let settings = GuildSettings::new(&dir);
+        assert!(settings.set(GUILD, "colour", &string("red")).is_err());
+        assert_eq!(settings.load(GUILD).unwrap(), GuildOverrides::default());
+    }
+}
@@ -0,0 +1,53 @@
+//! Squire's Rust side as a library.
+//!
+//! `gateway` holds the Discord gateway: the outbound queue, its spool, rate limiting, the hub
+//! inbox, and presence validation. `message` builds message bodies (content, embeds, buttons)
+//! and checks them against Discord's limits. `commands` defines slash commands and works out
+//! which ones changed since the last sync. `config` reads the startup
+//! settings from `config.json` (or a small TOML subset, read by `config_toml`), `log` is the shared logger, and `lockfile` keeps concurrent
+//! writers of the `Discovery/` files from mixing lines, and `atomic` replaces whole files
+//! (spool, command cache, offsets) so a crash never leaves half of one. `queue_file` (shared with the
+//! hub) caps line length and rotates the dispatch file, remembering where Squire stopped reading. `dotenv` loads a `.env`
+//! file into the environment at startup. `storage` keeps XP totals
+//! in an append-only log under `database_path`, `guild_settings` lays per-guild overrides from
+//! `database_path/guilds/` over the global config, and `modlog` is the hash-chained moderation
+//! audit log. `self_verify` (shared with the hub and Sentry) checks the running binary against a
+//! Sentry manifest at startup. `recorder` keeps a numbered copy of every request handed to the
+//! transport when `SQUIRE_RECORD_REQUESTS` is set, for audits and `--replay`. `snowflake` (shared with the hub) checks Discord ids and reads the
+//! creation time inside them; `message` and the dispatch file use it for channel ids. `webhook` checks and redacts the HTTPS URLs of non-Discord destinations
+//! (`Destination::Webhook`), such as a Mattermost channel that mirrors the log lines. With the
+//! cargo feature `vault`, `vault` opens encrypted envelopes so the bot token
+//! can stay encrypted on disk (`TokenSource::VaultEnvelope`). `password` hashes and checks
+//! passwords in the Python `crypto/passwords.py` format, and with the cargo feature `ffi`, `ffi`
+//! exports both to C so Python can call them in-process. The `squire-gateway` binary in `src/main.rs` wires
+//! them to the environment; tests and other tools can build a `DiscordGateway` with their own
+//! `DiscoveryLayout`. `runtime` (shared with the hub and Sentry) puts the clock, sleeping, and
+//! environment variables behind traits; `DiscordGateway::with_clock`, `with_sleeper`, and
+//! `with_env` swap in fakes so rate limiting and presence checks run without real waiting.
+//! `watchdog` (shared with the hub and Sentry) tells systemd the binary started, is alive, and is
+//! stopping, through `NOTIFY_SOCKET`.
+
+pub mod commands;
+pub mod config;
+pub mod config_toml;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod gateway;
+pub mod guild_settings;
+pub mod message;
+pub mod modlog;
+pub mod password;
+pub mod recorder;
+pub mod storage;
+#[cfg(feature = "vault")]
+pub mod vault;
+pub mod webhook;
+
+pub use ecosystem_common::{atomic, dotenv, json, lockfile, log, queue_file, runtime, secret, self_verify, sha256, snowflake, watchdog};
+
+pub use commands::{CommandOption, CommandRegistry, OptionType, SlashCommand};
+pub use gateway::{
+    Action, Destination, DiscordGateway, DiscoveryLayout, FlushPlan, FlushReport, OutboundMessage, OutboundQueue, PlanStatus,
+    PlannedMessage, Priority, SecretBytes, TokenSource, PRESENCE_REVOKED_ERROR,
+};
+pub use message::{Button, EmbedBuilder, MessageBuilder, MessageError};
@@ -1,26 +1,673 @@
-//! Squire gateway placeholder for Cargo builds.
-//! The handwritten Rust gateways in `rust/` still show how Discord I/O will work; this binary
-//! simply keeps the Cargo workspace runnable and teaches newcomers about the directory layout.
+//! Squire gateway binary: one pass of Squire's outbound work.
+//!
+//! The gateway itself lives in the library (`src/gateway.rs`). This binary loads `config.json`
+//! (or a `.toml` file) when one is given (`--config <path>` or `SQUIRE_CONFIG`; repeat `--config` to lay files over
+//! each other, see `Config::load_layers`), restores the spool, turns new log
+//! lines from the dispatch file into messages for the logging channel (and the logging webhook,
+//! when `SQUIRE_LOG_WEBHOOK_URL` names one), and flushes. Without a
+//! config file every setting comes from the environment, as before. A `.env` file is loaded into
+//! the environment first (see `dotenv`), without overriding variables that are already set.
+//!
+//! `--dump-leaderboard <guild id>` is a debug aid: it prints the top XP holders of one guild from
+//! the XP log in the config's `database_path` and exits without touching Discord.
+//!
+//! `--replay <folder>` queues the channel messages recorded in a `SQUIRE_RECORD_REQUESTS` folder
+//! again (see `recorder`) and flushes them instead of the dispatch file's new lines.
+//!
+//! `--plan` prints what the flush would do as JSON lines (see `FlushPlan::to_json_lines`) and
+//! exits instead: the spool, the dispatch file and its offset, and Discord are left alone. New
+//! dispatch lines (or the `--replay` recordings) show up in the plan as if they were queued.
+//!
+//! The bot token comes from the config (or `SQUIRE_DISCORD_TOKEN`). Builds with the cargo feature
+//! `vault` can instead keep it encrypted: `SQUIRE_TOKEN_ENVELOPE` names a vault envelope that is
+//! opened with `SQUIRE_VAULT_KEY` at every flush, and it wins over the other two.
+//!
+//! Under systemd (`NOTIFY_SOCKET` set) it sends `READY=1` once startup is done, `WATCHDOG=1`
+//! before the flush, and `STOPPING=1` before it exits (see `watchdog`).
+//!
+//! It uses `Discovery/` under the current directory; service units that start elsewhere set
+//! `SQUIRE_DISCOVERY_ROOT` to the absolute path of Squire's `Discovery/` folder instead.
 
+use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use squire_gateway::atomic::{atomic_write, clean_stale_temps};
+use squire_gateway::config::{AppError, Config};
+use squire_gateway::dotenv;
+use squire_gateway::log::Logger;
+use squire_gateway::recorder;
+use squire_gateway::runtime::{EnvSource, ProcessEnv};
+use squire_gateway::self_verify;
+use squire_gateway::watchdog::Watchdog;
+use squire_gateway::message::MAX_CONTENT_CHARS;
+use squire_gateway::queue_file::{QueueCursor, QueueFile};
+use squire_gateway::storage::{level_for, XpStore};
+use squire_gateway::webhook::{text_body, WebhookUrl};
+use squire_gateway::{
+    CommandOption, CommandRegistry, DiscordGateway, DiscoveryLayout, MessageBuilder, OptionType, OutboundMessage,
+    PlanStatus, SlashCommand, TokenSource,
+};
+
+/// Discord channel that receives forwarded log lines. Overrides `logging_channel_id` from the
+/// config. Without either, log lines stay on disk.
+const LOG_CHANNEL_ENV: &str = "SQUIRE_LOG_CHANNEL_ID";
+/// Optional `https://` webhook (Mattermost, for example) that receives the same log lines as
+/// `{"text": ...}`. Kept out of `config.json` because the URL itself is a secret.
+const LOG_WEBHOOK_ENV: &str = "SQUIRE_LOG_WEBHOOK_URL";
+/// Path of `config.json`, used when `--config` is not given.
+const CONFIG_ENV: &str = "SQUIRE_CONFIG";
+/// Optional expected SHA-256 of the config file; startup stops if the file differs.
+const CONFIG_SHA256_ENV: &str = "SQUIRE_CONFIG_SHA256";
+/// Same switch the gateway library reads; here it lets a config without a token start.
+const DRY_RUN_ENV: &str = "SQUIRE_DRY_RUN";
+/// Path of a vault envelope holding the bot token (cargo feature `vault`).
+const TOKEN_ENVELOPE_ENV: &str = "SQUIRE_TOKEN_ENVELOPE";
+
+const LOG: Logger = Logger::new("squire-gateway");
+
+const USAGE: &str =
+    "usage: squire-gateway [--config <path to config.json or .toml>]... [--dump-leaderboard <guild id>] [--replay <recording folder>] [--plan]";
+
+/// How many members `--dump-leaderboard` prints.
+const LEADERBOARD_SIZE: usize = 10;
+
+/// What the command line asked for.
+struct Args {
+    /// Config files as layers, later ones overriding earlier ones. Empty when there is no config.
+    config_paths: Vec<String>,
+    /// Guild whose leaderboard to print instead of running the gateway.
+    dump_leaderboard: Option<String>,
+    /// Folder of recorded requests to queue again instead of the dispatch file's lines.
+    replay: Option<PathBuf>,
+    /// Print what the flush would do instead of flushing.
+    plan: bool,
+}
 
 fn main() {
-    let working_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-    let discovery_path = working_dir.join("Discovery");
+    // Before the config's `$ENV{...}` placeholders and the gateway read the environment.
+    dotenv::load_default_dotenv();
+    // With `SQUIRE_MANIFEST` set, a binary that differs from the manifest exits here (code 7).
+    self_verify::enforce_at_startup();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Args { config_paths, dump_leaderboard, replay, plan } = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            process::exit(1);
+        }
+    };
+
+    // The leaderboard dump only reads the XP log, so it skips the token and fingerprint checks.
+    let load = |paths: Vec<String>| match dump_leaderboard {
+        Some(_) => Config::load_layers(&paths),
+        None => load_checked_config(&paths, &ProcessEnv),
+    };
+    let config = match Some(config_paths).filter(|paths| !paths.is_empty()).map(load).transpose() {
+        Ok(config) => config,
+        Err(err) => {
+            LOG.error("Startup failed", &[("problems", &err.problems.len().to_string())]);
+            for problem in &err.problems {
+                LOG.error("Startup problem", &[("problem", problem)]);
+            }
+            process::exit(1);
+        }
+    };
+
+    if let Some(guild) = dump_leaderboard {
+        let code = match print_leaderboard(config.as_ref(), &guild) {
+            Ok(()) => 0,
+            Err(err) => {
+                LOG.error("Leaderboard dump failed", &[("error", &err)]);
+                1
+            }
+        };
+        process::exit(code);
+    }
+
+    let layout = DiscoveryLayout::resolve(None);
+    LOG.info("Using Discovery folder", &[("path", &layout.root.display().to_string())]);
+    if plan {
+        let code = match print_plan(layout, config.as_ref(), replay.as_deref()) {
+            Ok(()) => 0,
+            Err(err) => {
+                LOG.error("Plan failed", &[("error", &err)]);
+                1
+            }
+        };
+        process::exit(code);
+    }
+    let leftovers = clean_stale_temps(&layout.root);
+    if leftovers > 0 {
+        LOG.warn("Removed temporary files left by a crash", &[("files", &leftovers.to_string())]);
+    }
+
+    let watchdog = Watchdog::from_env(&ProcessEnv);
+    notify(watchdog.ready());
+
+    if let Some(config) = config.as_ref().filter(|config| !config.gateway_enabled()) {
+        maintain_discovery_only(&mut DiscordGateway::new().with_layout(layout), config);
+        notify(watchdog.stopping());
+        return;
+    }
+
+    let mut gateway = configured_gateway(layout, config.as_ref());
+
+    if let Some(folder) = replay {
+        if let Err(err) = replay_recordings(&mut gateway, &folder) {
+            LOG.error("Replay failed", &[("error", &err)]);
+            process::exit(1);
+        }
+        flush_then_stop(&mut gateway, &watchdog);
+        return;
+    }
+
+    let channel = log_channel(config.as_ref(), &ProcessEnv);
+    let webhook = log_webhook();
+    if channel.is_none() && webhook.is_none() {
+        LOG.info("No logging channel or webhook configured; log lines stay in the dispatch file", &[]);
+    } else {
+        let queued = enqueue_dispatch_lines(&mut gateway, channel.as_deref(), webhook.as_ref());
+        let webhook_shown = webhook.as_ref().map_or_else(|| "none".to_string(), |(_, parsed)| parsed.redacted());
+        LOG.info(
+            "Queued new log lines",
+            &[("count", &queued.to_string()), ("channel", channel.as_deref().unwrap_or("none")), ("webhook", &webhook_shown)],
+        );
+    }
+
+    flush_then_stop(&mut gateway, &watchdog);
+}
+
+/// The gateway with the config's token (or the token envelope), Squire's slash commands, and
+/// the spool's unsent messages restored.
+fn configured_gateway(layout: DiscoveryLayout, config: Option<&Config>) -> DiscordGateway {
+    let spool_file = layout.spool_file.clone();
+    let gateway = match (envelope_source(), config.and_then(|config| config.discord_token.clone())) {
+        (Some(source), _) => DiscordGateway::from_token_source(source),
+        (None, Some(token)) => DiscordGateway::with_token(token),
+        (None, None) => DiscordGateway::new(),
+    };
+    gateway.with_layout(layout).with_commands(squire_commands()).with_spool(spool_file)
+}
+
+/// Print the plan of the flush this run would do, with the recordings in `replay` or the
+/// dispatch file's new lines added to the queue, without spooling them or moving the offset.
+fn print_plan(layout: DiscoveryLayout, config: Option<&Config>, replay: Option<&Path>) -> Result<(), String> {
+    let gateway = configured_gateway(layout, config);
+    let extra = match replay {
+        Some(folder) => recorder::load_recordings(folder)?.iter().filter_map(|recording| recorder::replay_message(recording).ok()).collect(),
+        None => {
+            let channel = log_channel(config, &ProcessEnv);
+            let webhook = log_webhook();
+            dispatch_messages(&gateway, channel.as_deref(), webhook.as_ref()).map_or_else(Vec::new, |(messages, _)| messages)
+        }
+    };
+    let mut plan = gateway.plan_with(extra);
+    if config.is_some_and(|config| !config.gateway_enabled()) {
+        // This run would only maintain the Discovery folder, so nothing goes out.
+        plan.blocked = Some("feature_flags.gateway is false".to_string());
+        for message in plan.messages.iter_mut().filter(|message| message.status == PlanStatus::Send) {
+            message.status = PlanStatus::Blocked;
+            message.send_offset = None;
+            message.borrowed = false;
+        }
+    }
+    for line in plan.to_json_lines() {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Ping the watchdog, flush, and tell systemd the run is over.
+fn flush_then_stop(gateway: &mut DiscordGateway, watchdog: &Watchdog) {
+    notify(watchdog.ping());
+    gateway.flush();
+    notify(watchdog.stopping());
+}
+
+/// Log (not stop on) a notification systemd did not get.
+fn notify(sent: std::io::Result<()>) {
+    if let Err(err) = sent {
+        LOG.warn("Could not notify systemd", &[("error", &err.to_string())]);
+    }
+}
+
+/// The config paths are every `--config <path>` in order if any is given, otherwise
+/// `SQUIRE_CONFIG`, otherwise none.
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut config_paths = Vec::new();
+    let mut dump_leaderboard = None;
+    let mut replay = None;
+    let mut plan = false;
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--config" => config_paths.push(iter.next().cloned().ok_or("--config needs a value")?),
+            "--dump-leaderboard" => {
+                dump_leaderboard = Some(iter.next().cloned().ok_or("--dump-leaderboard needs a guild id")?)
+            }
+            "--replay" => replay = Some(PathBuf::from(iter.next().ok_or("--replay needs a recording folder")?)),
+            "--plan" => plan = true,
+            other => return Err(format!("Unknown argument {other:?}")),
+        }
+    }
+    if config_paths.is_empty() {
+        config_paths.extend(env::var(CONFIG_ENV).ok().filter(|path| !path.is_empty()));
+    }
+    Ok(Args { config_paths, dump_leaderboard, replay, plan })
+}
+
+/// Print `rank user xp level` lines for the guild's top members.
+fn print_leaderboard(config: Option<&Config>, guild: &str) -> Result<(), String> {
+    let folder = config
+        .and_then(|config| config.database_path.as_ref())
+        .ok_or("--dump-leaderboard needs a config with database_path")?;
+    let store = XpStore::open(folder)?;
+    let top = store.top_n(guild, LEADERBOARD_SIZE);
+    if top.is_empty() {
+        println!("No XP recorded for guild {guild}");
+    }
+    for (rank, (user, total)) in top.iter().enumerate() {
+        println!("{:>2}. {user}  {total} xp  level {}", rank + 1, level_for(*total));
+    }
+    Ok(())
+}
+
+/// Queue every replayable recording in `folder` (see `recorder::replay_message`), in number
+/// order. The spool keeps them, so a flush that cannot send them leaves them for the next run.
+fn replay_recordings(gateway: &mut DiscordGateway, folder: &Path) -> Result<(), String> {
+    let recordings = recorder::load_recordings(folder)?;
+    let mut queued = 0;
+    for recording in &recordings {
+        match recorder::replay_message(recording) {
+            Ok(message) => {
+                gateway.enqueue(message);
+                queued += 1;
+            }
+            Err(reason) => LOG.warn("Not replaying recording", &[("index", &recording.index.to_string()), ("reason", &reason)]),
+        }
+    }
+    LOG.info(
+        "Queued recorded requests again",
+        &[("folder", &folder.display().to_string()), ("queued", &queued.to_string()), ("skipped", &(recordings.len() - queued).to_string())],
+    );
+    Ok(())
+}
+
+/// Load the config, print its fingerprint, and collect every reason it cannot be used.
+fn load_checked_config(paths: &[String], env: &dyn EnvSource) -> Result<Config, AppError> {
+    let config = Config::load_layers_from(paths, env)?;
+    let layers: Vec<String> = config.layers.iter().map(|layer| layer.display().to_string()).collect();
+    LOG.info(
+        "Config loaded",
+        &[("path", &config.path.display().to_string()), ("layers", &layers.join(" + ")), ("sha256", &config.fingerprint)],
+    );
+    // One line per flag as evaluated, so a rollout typo shows up in the startup log.
+    for (name, flag) in config.feature_flags.iter() {
+        LOG.info(
+            "Feature flag",
+            &[
+                ("name", name),
+                ("enabled", &flag.enabled.to_string()),
+                ("percentage", &config.feature_flags.percentage(name).to_string()),
+            ],
+        );
+    }
+
+    let mut errors = AppError::default();
+    if let Some(expected) = env.var(CONFIG_SHA256_ENV) {
+        if !expected.trim().eq_ignore_ascii_case(&config.fingerprint) {
+            errors.push(format!("{CONFIG_SHA256_ENV} expects {}, but the file hashes to {}", expected.trim(), config.fingerprint));
+        }
+    }
+    let dry_run = env.var(DRY_RUN_ENV).is_some_and(|value| value.trim() == "1");
+    let envelope = cfg!(feature = "vault") && envelope_configured(env);
+    if config.gateway_enabled() && config.discord_token.is_none() && !dry_run && !envelope {
+        errors.push(format!(
+            "no bot token: set discord_token (e.g. \"$ENV{{SQUIRE_DISCORD_TOKEN}}\") and its variable, or {DRY_RUN_ENV}=1"
+        ));
+    }
+
+    if errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Whether `SQUIRE_TOKEN_ENVELOPE` names an envelope, whether or not this build can open it.
+fn envelope_configured(env: &dyn EnvSource) -> bool {
+    env.var(TOKEN_ENVELOPE_ENV).is_some_and(|path| !path.trim().is_empty())
+}
+
+/// The token envelope from `SQUIRE_TOKEN_ENVELOPE`, opened with `SQUIRE_VAULT_KEY`.
+#[cfg(feature = "vault")]
+fn envelope_source() -> Option<TokenSource> {
+    let path = env::var(TOKEN_ENVELOPE_ENV).ok().filter(|path| !path.trim().is_empty())?;
+    Some(TokenSource::VaultEnvelope {
+        key_env: squire_gateway::vault::VAULT_KEY_ENV.to_string(),
+        envelope_path: PathBuf::from(path.trim()),
+    })
+}
+
+/// Without the `vault` feature an envelope cannot be opened; say so instead of silently
+/// falling back to another token.
+#[cfg(not(feature = "vault"))]
+fn envelope_source() -> Option<TokenSource> {
+    if envelope_configured(&ProcessEnv) {
+        LOG.warn("SQUIRE_TOKEN_ENVELOPE is set, but this build cannot open envelopes; rebuild with --features vault", &[]);
+    }
+    None
+}
+
+/// `feature_flags.gateway` is off: make sure the Discovery folder exists and report presence,
+/// but send nothing and leave the spool and dispatch file untouched.
+fn maintain_discovery_only(gateway: &mut DiscordGateway, config: &Config) {
+    LOG.info(
+        "feature_flags.gateway is false; not contacting Discord",
+        &[("config", &config.path.display().to_string())],
+    );
+    if let Err(err) = fs::create_dir_all(&gateway.layout().root) {
+        LOG.error("Could not create the Discovery folder", &[("error", &err.to_string())]);
+    }
+    // Still beat, so the hub sees the bot running even while it stays off Discord.
+    gateway.write_heartbeat();
+    match gateway.answer_challenge() {
+        Ok(answered) => LOG.info("Hub challenge checked", &[("answered", if answered { "yes" } else { "no" })]),
+        Err(err) => LOG.warn("Could not answer the hub's challenge", &[("reason", &err)]),
+    }
+    let presence = match gateway.validate_presence_file() {
+        Ok(true) => "valid".to_string(),
+        Ok(false) => "bad signature".to_string(),
+        Err(err) => err,
+    };
+    LOG.info("Presence marker checked", &[("result", &presence)]);
+}
+
+/// Squire's slash commands, mirroring the moderation and XP features in `python/features/`.
+fn squire_commands() -> CommandRegistry {
+    let target = || CommandOption::new(OptionType::User, "user", "Member to act on").required();
+    let reason = || CommandOption::new(OptionType::String, "reason", "Why, for the moderation log");
+    let commands = [
+        SlashCommand::new("warn", "Warn a member").option(target()).option(reason()),
+        SlashCommand::new("mute", "Time out a member")
+            .option(target())
+            .option(CommandOption::new(OptionType::Integer, "minutes", "How long the timeout lasts").required())
+            .option(reason()),
+        SlashCommand::new("kick", "Remove a member from the server").option(target()).option(reason()),
+        SlashCommand::new("ban", "Ban a member").option(target()).option(reason()),
+        SlashCommand::new("xp", "Show experience points")
+            .option(CommandOption::new(OptionType::User, "user", "Member to look up (defaults to you)")),
+    ];
+
+    let mut registry = CommandRegistry::new();
+    for command in commands {
+        if let Err(err) = registry.add(command) {
+            LOG.warn("Ignoring slash command", &[("reason", &err.to_string())]);
+        }
+    }
+    registry
+}
+
+/// The logging channel id from `SQUIRE_LOG_CHANNEL_ID` or the config's `logging_channel_id`.
+/// The environment wins over the config so one run can be pointed elsewhere without editing it.
+fn log_channel(config: Option<&Config>, env: &dyn EnvSource) -> Option<String> {
+    let channel = env.var(LOG_CHANNEL_ENV).or_else(|| config.and_then(|config| config.logging_channel_id.clone()));
+    match channel {
+        Some(channel) if !channel.is_empty() && channel.bytes().all(|b| b.is_ascii_digit()) => Some(channel),
+        Some(channel) => {
+            LOG.warn("Logging channel is not a numeric id; not forwarding logs to Discord", &[("channel", &channel)]);
+            None
+        }
+        None => None,
+    }
+}
+
+/// The logging webhook from `SQUIRE_LOG_WEBHOOK_URL`, as the raw URL and its parsed form. A URL
+/// that is not `https://` is refused with a warning that names the problem, never the URL.
+fn log_webhook() -> Option<(String, WebhookUrl)> {
+    let url = env::var(LOG_WEBHOOK_ENV).ok()?.trim().to_string();
+    if url.is_empty() {
+        return None;
+    }
+    match WebhookUrl::parse(&url) {
+        Ok(parsed) => Some((url, parsed)),
+        Err(reason) => {
+            LOG.warn("Logging webhook refused; not forwarding logs to it", &[("reason", &reason)]);
+            None
+        }
+    }
+}
+
+/// Queue every complete line added to the dispatch file since the last run, once for the
+/// logging channel and once for the logging webhook, whichever are set.
+///
+/// Where reading stopped is kept next to the dispatch file (`gateway_queue.offset`) as a
+/// `QueueCursor`, so a line is queued once even though the file keeps growing. Lines starting with
+/// `to=` are addressed to other bots and belong to the ecosystem hub's router, so they are skipped.
+/// A last line without a newline may still be being written and waits for the next run. Once the
+/// file reaches its size limit it is rotated to `gateway_queue.log.1`; the cursor finishes the
+/// rotated file before starting on the new one, so no line is skipped.
+fn enqueue_dispatch_lines(gateway: &mut DiscordGateway, channel_id: Option<&str>, webhook: Option<&(String, WebhookUrl)>) -> usize {
+    let Some((messages, cursor)) = dispatch_messages(gateway, channel_id, webhook) else {
+        return 0;
+    };
+    let queued = messages.len();
+    for message in messages {
+        gateway.enqueue(message);
+    }
+
+    // The spool already holds the queued messages, so moving the cursor now cannot lose any.
+    let queue = QueueFile::new(gateway.layout().dispatch_file.clone());
+    let offset_file = queue.path().with_extension("offset");
+    if let Err(err) = atomic_write(&offset_file, format!("{}\n", cursor.to_state()).as_bytes()) {
+        LOG.error(
+            "Could not save dispatch offset",
+            &[("file", &offset_file.display().to_string()), ("error", &err.to_string())],
+        );
+    }
+    if let Err(err) = queue.rotate_if_full() {
+        LOG.warn("Could not rotate dispatch file", &[("error", &err.to_string())]);
+    }
+    queued
+}
+
+/// The messages for the dispatch file's lines after the saved offset, and the cursor just past
+/// them. Reads only; `None` when the dispatch file cannot be read.
+fn dispatch_messages(
+    gateway: &DiscordGateway,
+    channel_id: Option<&str>,
+    webhook: Option<&(String, WebhookUrl)>,
+) -> Option<(Vec<OutboundMessage>, QueueCursor)> {
+    let queue = QueueFile::new(gateway.layout().dispatch_file.clone());
+    let offset_file = queue.path().with_extension("offset");
+    let cursor = fs::read_to_string(&offset_file).ok().and_then(|raw| QueueCursor::parse(&raw)).unwrap_or_default();
+    let batch = queue.read_from(cursor).ok()?;
+    if batch.truncated > 0 {
+        LOG.warn("Cut over-long dispatch lines", &[("lines", &batch.truncated.to_string())]);
+    }
+
+    let mut messages = Vec::new();
+    for line in &batch.lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("to=") {
+            continue;
+        }
+        if let Some(channel_id) = channel_id {
+            let content: String = line.chars().take(MAX_CONTENT_CHARS).collect();
+            match MessageBuilder::new(channel_id).content(content).build() {
+                Ok(message) => messages.push(message),
+                Err(err) => LOG.warn("Skipping dispatch line", &[("reason", &err.to_string())]),
+            }
+        }
+        if let Some((url, _)) = webhook {
+            messages.push(OutboundMessage::webhook(url.clone(), text_body(line)));
+        }
+    }
+    Some((messages, batch.cursor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use squire_gateway::gateway::DryRunTransport;
+    use squire_gateway::runtime::{Clock, ManualClock, MapEnv};
+    use squire_gateway::config::sha256_file;
+    use squire_gateway::sha256::{hmac_sha256, sha256, to_hex};
+
+    const KEY: [u8; 32] = [0x22; 32];
+    const CHANNEL: &str = "123456789012345678";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("squire-main-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A dry-run gateway in `bot_dir` whose presence file the hub signed at the clock's time.
+    fn dry_run_gateway(bot_dir: &Path, clock: &Rc<ManualClock>) -> DiscordGateway {
+        let env = MapEnv::new().with("ECOSYSTEM_PRESENCE_KEY", &to_hex(&KEY));
+        let gateway = DiscordGateway::with_transport(Box::new(DryRunTransport))
+            .with_clock(clock.clone())
+            .with_sleeper(clock.clone())
+            .with_env(Rc::new(env))
+            .with_layout(DiscoveryLayout::under(bot_dir));
+        fs::create_dir_all(&gateway.layout().root).unwrap();
+        let nonce = format!("squire|{}", clock.now_millis());
+        let signature = to_hex(&hmac_sha256(&KEY, nonce.as_bytes()));
+        fs::write(&gateway.layout().presence_file, format!("nonce={nonce}\nsignature={signature}\n")).unwrap();
+        gateway
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn dispatch_lines_are_queued_once_and_flushed_in_dry_run() {
+        let bot_dir = temp_dir("dispatch");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let mut gateway = dry_run_gateway(&bot_dir, &clock);
+        gateway.append_dispatch("Server restarted");
+        gateway.append_dispatch("to=bard|from=squire|body=for the hub");
+        gateway.append_dispatch("Nightly backup done");
+
+        assert_eq!(enqueue_dispatch_lines(&mut gateway, Some(CHANNEL), None), 2);
+        // The offset file remembers where reading stopped, so nothing is queued twice.
+        assert_eq!(enqueue_dispatch_lines(&mut gateway, Some(CHANNEL), None), 0);
+        assert_eq!(gateway.pending_len(), 2);
+
+        let report = gateway.flush();
+        assert_eq!((report.sent, report.failed, report.deferred), (2, 0, 0));
+        let log = read(&gateway.layout().secure_dispatch_file);
+        assert_eq!(log.matches(&format!("DRY-RUN POST /api/v10/channels/{CHANNEL}/messages")).count(), 2, "{log}");
+        // Nothing was written outside the temporary layout.
+        assert!(gateway.layout().root.starts_with(&bot_dir));
+    }
 
-    if let Err(error) = fs::create_dir_all(&discovery_path) {
-        eprintln!("Squire could not prepare Discovery/: {error}");
-        std::process::exit(1);
+    #[test]
+    fn without_a_channel_or_webhook_no_line_is_queued() {
+        let bot_dir = temp_dir("dispatch-nowhere");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let mut gateway = dry_run_gateway(&bot_dir, &clock);
+        gateway.append_dispatch("Server restarted");
+        assert_eq!(enqueue_dispatch_lines(&mut gateway, None, None), 0);
+
+        let webhook = ("https://hooks.example.com/hooks/abc".to_string(), WebhookUrl::parse("https://hooks.example.com/hooks/abc").unwrap());
+        gateway.append_dispatch("Backup done");
+        let (messages, _) = dispatch_messages(&gateway, Some(CHANNEL), Some(&webhook)).unwrap();
+        // The offset moved past the first line already; the second goes to both targets.
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].body(), text_body("Backup done"));
+    }
+
+    #[test]
+    fn presence_is_validated_against_the_signed_temp_file() {
+        let bot_dir = temp_dir("presence");
+        let clock = Rc::new(ManualClock::new(1_700_000_000_000));
+        let gateway = dry_run_gateway(&bot_dir, &clock);
+        assert_eq!(gateway.validate_presence_file(), Ok(true));
+
+        let forged = read(&gateway.layout().presence_file).replace("squire|", "bard|");
+        fs::write(&gateway.layout().presence_file, forged).unwrap();
+        assert_eq!(gateway.validate_presence_file(), Ok(false));
+        fs::remove_file(&gateway.layout().presence_file).unwrap();
+        assert_eq!(gateway.validate_presence_file(), Err("presence file missing".to_string()));
+    }
+
+    /// Write `config.json` with `contents` in a fresh temp folder and return its path.
+    fn write_config(name: &str, contents: &str) -> (PathBuf, String) {
+        let dir = temp_dir(name);
+        let path = dir.join("config.json");
+        fs::write(&path, contents).unwrap();
+        (dir, path.display().to_string())
+    }
+
+    #[test]
+    fn startup_with_the_gateway_flag_on_uses_the_token_and_logging_channel() {
+        let (dir, path) = write_config(
+            "startup-on",
+            &format!(r#"{{"discord_token": "token-from-file", "logging_channel_id": "{CHANNEL}", "feature_flags": {{"gateway": true}}}}"#),
+        );
+        let config = load_checked_config(&[path], &MapEnv::new()).unwrap();
+        assert!(config.gateway_enabled());
+        assert_eq!(config.discord_token.as_deref(), Some("token-from-file"));
+        assert_eq!(log_channel(Some(&config), &MapEnv::new()).as_deref(), Some(CHANNEL));
+        // The environment points one run elsewhere; a channel that is not an id is dropped.
+        assert_eq!(log_channel(Some(&config), &MapEnv::new().with(LOG_CHANNEL_ENV, "42")).as_deref(), Some("42"));
+        assert_eq!(log_channel(Some(&config), &MapEnv::new().with(LOG_CHANNEL_ENV, "#logs")), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn startup_with_the_gateway_flag_off_only_maintains_discovery() {
+        let (dir, path) = write_config("startup-off", r#"{"feature_flags": {"gateway": false}}"#);
+        // No token is needed when the gateway never talks to Discord.
+        let config = load_checked_config(&[path], &MapEnv::new()).unwrap();
+        assert!(!config.gateway_enabled());
+
+        let clock = Rc::new(ManualClock::new(1_000_000));
+        let mut gateway = dry_run_gateway(&dir, &clock);
+        maintain_discovery_only(&mut gateway, &config);
+        let layout = gateway.layout();
+        assert!(read(&layout.heartbeat_file).ends_with(" seq=1 at=1000000\n"));
+        assert!(!layout.spool_file.exists());
+        assert!(!layout.secure_dispatch_file.exists());
+        let _ = fs::remove_dir_all(&dir);
     }
 
-    let queue_path = discovery_path.join("gateway_queue.log");
-    let message = "Squire gateway placeholder initialized.\n";
-    if let Err(error) = fs::write(&queue_path, message) {
-        eprintln!("Squire could not write gateway queue: {error}");
-        std::process::exit(1);
+    #[test]
+    fn a_missing_token_is_a_startup_error_unless_dry_run() {
+        let (dir, path) = write_config("startup-no-token", &format!(r#"{{"logging_channel_id": "{CHANNEL}"}}"#));
+        let err = load_checked_config(std::slice::from_ref(&path), &MapEnv::new()).unwrap_err();
+        assert_eq!(err.problems.len(), 1);
+        assert!(err.problems[0].starts_with("no bot token"), "{:?}", err.problems);
+        assert!(load_checked_config(std::slice::from_ref(&path), &MapEnv::new().with(DRY_RUN_ENV, "1")).is_ok());
+        assert!(load_checked_config(&[path], &MapEnv::new().with(DRY_RUN_ENV, "0")).is_err());
+        let _ = fs::remove_dir_all(&dir);
     }
 
-    println!("Squire gateway stub ready at {:?}", queue_path);
-    println!("Use the existing Rust files in squire/rust/ as the authoritative Discord bridge when you add features.");
+    #[test]
+    fn fingerprint_matches_an_independent_hash_of_the_file() {
+        let contents = r#"{"discord_token": "token-from-file"}"#;
+        let (dir, path) = write_config("startup-fingerprint", contents);
+        let expected = to_hex(&sha256(contents.as_bytes()));
+
+        let config = load_checked_config(std::slice::from_ref(&path), &MapEnv::new()).unwrap();
+        assert_eq!(config.fingerprint, expected);
+        assert_eq!(sha256_file(Path::new(&path)).unwrap(), expected);
+        let pinned = MapEnv::new().with(CONFIG_SHA256_ENV, &expected.to_uppercase());
+        assert!(load_checked_config(std::slice::from_ref(&path), &pinned).is_ok());
+
+        // A pin that no longer matches is reported together with every other problem.
+        let stale = MapEnv::new().with(CONFIG_SHA256_ENV, &"0".repeat(64));
+        fs::write(&path, r#"{"logging_channel_id": "1"}"#).unwrap();
+        let err = load_checked_config(&[path], &stale).unwrap_err();
+        assert_eq!(err.problems.len(), 2, "{:?}", err.problems);
+        assert!(err.problems[0].contains("but the file hashes to"));
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,787 @@
+//! Typed Discord messages, checked against Discord's limits before they are queued.
+//!
+//! `OutboundMessage` carries a raw JSON body, which is fine for payloads Python already built but
+//! lets a too-long message travel all the way to Discord before it is refused. `MessageBuilder`
+//! builds the body here instead: content, embeds, and button rows go in, and `build()` either
+//! returns an `OutboundMessage` or a `MessageError` naming the exact limit that was broken.
+//!
+//! Lengths are counted in characters (`chars().count()`), not bytes, because that is how Discord
+//! counts them: "é" is one character even though UTF-8 stores it in two bytes.
+//!
+//! ```text
+//! let message = MessageBuilder::new("123456789012345678")
+//!     .content("Release ready")
+//!     .embed(EmbedBuilder::new().title("v1.2").description("Notes ..."))
+//!     .build()?;
+//! gateway.enqueue(message);
+//! ```
+
+use std::fmt;
+
+use crate::gateway::{Action, Destination, OutboundMessage};
+use crate::snowflake::{Snowflake, SnowflakeError};
+use crate::webhook::WebhookUrl;
+
+/// Longest `content` Discord accepts.
+pub const MAX_CONTENT_CHARS: usize = 2000;
+/// Most embeds one message may carry.
+pub const MAX_EMBEDS: usize = 10;
+/// Longest embed title.
+pub const MAX_EMBED_TITLE_CHARS: usize = 256;
+/// Longest embed description.
+pub const MAX_EMBED_DESCRIPTION_CHARS: usize = 4096;
+/// Most fields one embed may carry.
+pub const MAX_EMBED_FIELDS: usize = 25;
+/// Longest field name.
+pub const MAX_FIELD_NAME_CHARS: usize = 256;
+/// Longest field value.
+pub const MAX_FIELD_VALUE_CHARS: usize = 1024;
+/// Longest embed footer.
+pub const MAX_FOOTER_CHARS: usize = 2048;
+/// Limit on all embed text in one message added together (titles, descriptions, field names
+/// and values, footers).
+pub const MAX_TOTAL_EMBED_CHARS: usize = 6000;
+/// Most button rows one message may carry.
+pub const MAX_ACTION_ROWS: usize = 5;
+/// Most buttons in one row.
+pub const MAX_BUTTONS_PER_ROW: usize = 5;
+/// Longest button label.
+pub const MAX_BUTTON_LABEL_CHARS: usize = 80;
+/// Longest button `custom_id`.
+pub const MAX_CUSTOM_ID_CHARS: usize = 100;
+
+/// Which Discord limit a message broke. Embed and row numbers count from 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageError {
+    /// The channel id is not a Discord id (see `Snowflake::parse`); `reason` says which rule it broke.
+    InvalidChannelId { id: String, reason: SnowflakeError },
+    /// A webhook destination's URL is not a usable `https://` URL. Holds the reason, never the
+    /// URL, which is a secret.
+    InvalidWebhookUrl(String),
+    /// The id of the message to edit or delete is not a Discord id.
+    InvalidMessageId { id: String, reason: SnowflakeError },
+    /// An edit or delete aimed at a webhook, which gives no message id to refer back to.
+    WebhookNotEditable,
+    /// No content, no embeds, and no buttons: Discord refuses empty messages.
+    Empty,
+    ContentTooLong { chars: usize },
+    TooManyEmbeds { count: usize },
+    EmbedTitleTooLong { embed: usize, chars: usize },
+    EmbedDescriptionTooLong { embed: usize, chars: usize },
+    TooManyFields { embed: usize, count: usize },
+    FieldNameTooLong { embed: usize, field: usize, chars: usize },
+    FieldValueTooLong { embed: usize, field: usize, chars: usize },
+    FooterTooLong { embed: usize, chars: usize },
+    EmbedTotalTooLong { chars: usize },
+    TooManyActionRows { count: usize },
+    TooManyButtons { row: usize, count: usize },
+    ButtonLabelTooLong { row: usize, button: usize, chars: usize },
+    CustomIdTooLong { row: usize, button: usize, chars: usize },
+    /// A raw body (see `DiscordGateway::enqueue_validated`) has a `content` string that is not
+    /// properly terminated, so its length cannot be checked.
+    MalformedBody(String),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::InvalidChannelId { id, reason } => write!(f, "channel id {:?} is not a Discord id: it {}", id, reason),
+            MessageError::InvalidWebhookUrl(reason) => write!(f, "{}", reason),
+            MessageError::InvalidMessageId { id, reason } => write!(f, "message id {:?} is not a Discord id: it {}", id, reason),
+            MessageError::WebhookNotEditable => write!(f, "webhook messages cannot be edited or deleted"),
+            MessageError::Empty => write!(f, "message has no content, embeds, or buttons"),
+            MessageError::ContentTooLong { chars } => {
+                write!(f, "content is {} characters (limit {})", chars, MAX_CONTENT_CHARS)
+            }
+            MessageError::TooManyEmbeds { count } => write!(f, "{} embeds (limit {})", count, MAX_EMBEDS),
+            MessageError::EmbedTitleTooLong { embed, chars } => {
+                write!(f, "embed {} title is {} characters (limit {})", embed, chars, MAX_EMBED_TITLE_CHARS)
+            }
+            MessageError::EmbedDescriptionTooLong { embed, chars } => write!(
+                f,
+                "embed {} description is {} characters (limit {})",
+                embed, chars, MAX_EMBED_DESCRIPTION_CHARS
+            ),
+            MessageError::TooManyFields { embed, count } => {
+                write!(f, "embed {} has {} fields (limit {})", embed, count, MAX_EMBED_FIELDS)
+            }
+            MessageError::FieldNameTooLong { embed, field, chars } => write!(
+                f,
+                "embed {} field {} name is {} characters (limit {})",
+                embed, field, chars, MAX_FIELD_NAME_CHARS
+            ),
+            MessageError::FieldValueTooLong { embed, field, chars } => write!(
+                f,
+                "embed {} field {} value is {} characters (limit {})",
+                embed, field, chars, MAX_FIELD_VALUE_CHARS
+            ),
+            MessageError::FooterTooLong { embed, chars } => {
+                write!(f, "embed {} footer is {} characters (limit {})", embed, chars, MAX_FOOTER_CHARS)
+            }
+            MessageError::EmbedTotalTooLong { chars } => {
+                write!(f, "embeds hold {} characters in total (limit {})", chars, MAX_TOTAL_EMBED_CHARS)
+            }
+            MessageError::TooManyActionRows { count } => {
+                write!(f, "{} button rows (limit {})", count, MAX_ACTION_ROWS)
+            }
+            MessageError::TooManyButtons { row, count } => {
+                write!(f, "row {} has {} buttons (limit {})", row, count, MAX_BUTTONS_PER_ROW)
+            }
+            MessageError::ButtonLabelTooLong { row, button, chars } => write!(
+                f,
+                "row {} button {} label is {} characters (limit {})",
+                row, button, chars, MAX_BUTTON_LABEL_CHARS
+            ),
+            MessageError::CustomIdTooLong { row, button, chars } => write!(
+                f,
+                "row {} button {} custom_id is {} characters (limit {})",
+                row, button, chars, MAX_CUSTOM_ID_CHARS
+            ),
+            MessageError::MalformedBody(reason) => write!(f, "body is malformed: {}", reason),
+        }
+    }
+}
+
+/// One embed field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EmbedField {
+    name: String,
+    value: String,
+    inline: bool,
+}
+
+/// A rich embed. Every part is optional; set only what you need.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmbedBuilder {
+    title: Option<String>,
+    description: Option<String>,
+    url: Option<String>,
+    color: Option<u32>,
+    fields: Vec<EmbedField>,
+    footer: Option<String>,
+}
+
+impl EmbedBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Link the title points to.
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Side-bar colour as `0xRRGGBB`.
+    pub fn color(mut self, color: u32) -> Self {
+        self.color = Some(color & 0xFF_FFFF);
+        self
+    }
+
+    /// Add a `name: value` field; `inline` lets Discord put up to three fields side by side.
+    pub fn field(mut self, name: impl Into<String>, value: impl Into<String>, inline: bool) -> Self {
+        self.fields.push(EmbedField { name: name.into(), value: value.into(), inline });
+        self
+    }
+
+    pub fn footer(mut self, text: impl Into<String>) -> Self {
+        self.footer = Some(text.into());
+        self
+    }
+
+    /// Characters that count towards the 6000-character total for all embeds.
+    fn text_chars(&self) -> usize {
+        let count = |text: &Option<String>| text.as_deref().map_or(0, |text| text.chars().count());
+        count(&self.title)
+            + count(&self.description)
+            + count(&self.footer)
+            + self
+                .fields
+                .iter()
+                .map(|field| field.name.chars().count() + field.value.chars().count())
+                .sum::<usize>()
+    }
+
+    /// Check this embed's own limits. `index` is only used in the error.
+    fn validate(&self, index: usize) -> Result<(), MessageError> {
+        if let Some(title) = &self.title {
+            let chars = title.chars().count();
+            if chars > MAX_EMBED_TITLE_CHARS {
+                return Err(MessageError::EmbedTitleTooLong { embed: index, chars });
+            }
+        }
+        if let Some(description) = &self.description {
+            let chars = description.chars().count();
+            if chars > MAX_EMBED_DESCRIPTION_CHARS {
+                return Err(MessageError::EmbedDescriptionTooLong { embed: index, chars });
+            }
+        }
+        if self.fields.len() > MAX_EMBED_FIELDS {
+            return Err(MessageError::TooManyFields { embed: index, count: self.fields.len() });
+        }
+        for (number, field) in self.fields.iter().enumerate() {
+            let chars = field.name.chars().count();
+            if chars > MAX_FIELD_NAME_CHARS {
+                return Err(MessageError::FieldNameTooLong { embed: index, field: number, chars });
+            }
+            let chars = field.value.chars().count();
+            if chars > MAX_FIELD_VALUE_CHARS {
+                return Err(MessageError::FieldValueTooLong { embed: index, field: number, chars });
+            }
+        }
+        if let Some(footer) = &self.footer {
+            let chars = footer.chars().count();
+            if chars > MAX_FOOTER_CHARS {
+                return Err(MessageError::FooterTooLong { embed: index, chars });
+            }
+        }
+        Ok(())
+    }
+
+    fn to_json(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(title) = &self.title {
+            parts.push(format!("\"title\":\"{}\"", json_escape(title)));
+        }
+        if let Some(description) = &self.description {
+            parts.push(format!("\"description\":\"{}\"", json_escape(description)));
+        }
+        if let Some(url) = &self.url {
+            parts.push(format!("\"url\":\"{}\"", json_escape(url)));
+        }
+        if let Some(color) = self.color {
+            parts.push(format!("\"color\":{}", color));
+        }
+        if !self.fields.is_empty() {
+            let fields = self
+                .fields
+                .iter()
+                .map(|field| {
+                    format!(
+                        "{{\"name\":\"{}\",\"value\":\"{}\",\"inline\":{}}}",
+                        json_escape(&field.name),
+                        json_escape(&field.value),
+                        field.inline
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            parts.push(format!("\"fields\":[{}]", fields));
+        }
+        if let Some(footer) = &self.footer {
+            parts.push(format!("\"footer\":{{\"text\":\"{}\"}}", json_escape(footer)));
+        }
+        format!("{{{}}}", parts.join(","))
+    }
+}
+
+/// How a button looks and what pressing it does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ButtonAction {
+    /// Sends an interaction with this `custom_id` back to the bot. The number is Discord's
+    /// style code: 1 primary (blurple), 2 secondary (grey), 3 success (green), 4 danger (red).
+    Interaction { style: u8, custom_id: String },
+    /// Opens a URL in the user's browser (style 5). Discord sends the bot nothing.
+    Link { url: String },
+}
+
+/// One button for an action row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Button {
+    label: String,
+    action: ButtonAction,
+}
+
+impl Button {
+    pub fn primary(custom_id: impl Into<String>, label: impl Into<String>) -> Self {
+        Self::interaction(1, custom_id, label)
+    }
+
+    pub fn secondary(custom_id: impl Into<String>, label: impl Into<String>) -> Self {
+        Self::interaction(2, custom_id, label)
+    }
+
+    pub fn success(custom_id: impl Into<String>, label: impl Into<String>) -> Self {
+        Self::interaction(3, custom_id, label)
+    }
+
+    pub fn danger(custom_id: impl Into<String>, label: impl Into<String>) -> Self {
+        Self::interaction(4, custom_id, label)
+    }
+
+    pub fn link(url: impl Into<String>, label: impl Into<String>) -> Self {
+        Self { label: label.into(), action: ButtonAction::Link { url: url.into() } }
+    }
+
+    fn interaction(style: u8, custom_id: impl Into<String>, label: impl Into<String>) -> Self {
+        Self { label: label.into(), action: ButtonAction::Interaction { style, custom_id: custom_id.into() } }
+    }
+
+    fn to_json(&self) -> String {
+        // Component type 2 is a button.
+        match &self.action {
+            ButtonAction::Interaction { style, custom_id } => format!(
+                "{{\"type\":2,\"style\":{},\"label\":\"{}\",\"custom_id\":\"{}\"}}",
+                style,
+                json_escape(&self.label),
+                json_escape(custom_id)
+            ),
+            ButtonAction::Link { url } => format!(
+                "{{\"type\":2,\"style\":5,\"label\":\"{}\",\"url\":\"{}\"}}",
+                json_escape(&self.label),
+                json_escape(url)
+            ),
+        }
+    }
+}
+
+/// Builds a checked `OutboundMessage` for one channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageBuilder {
+    channel_id: String,
+    content: Option<String>,
+    embeds: Vec<EmbedBuilder>,
+    rows: Vec<Vec<Button>>,
+}
+
+impl MessageBuilder {
+    pub fn new(channel_id: impl Into<String>) -> Self {
+        Self { channel_id: channel_id.into(), content: None, embeds: Vec::new(), rows: Vec::new() }
+    }
+
+    /// Plain message text.
+    pub fn content(mut self, content: impl Into<String>) -> Self {
+        self.content = Some(content.into());
+        self
+    }
+
+    /// Add an embed after any added before.
+    pub fn embed(mut self, embed: EmbedBuilder) -> Self {
+        self.embeds.push(embed);
+        self
+    }
+
+    /// Add one row of buttons below the message.
+    pub fn button_row(mut self, buttons: Vec<Button>) -> Self {
+        self.rows.push(buttons);
+        self
+    }
+
+    /// Check every limit, then write the JSON body. The first broken limit is returned, checked
+    /// in the order: channel, content, embeds, buttons.
+    pub fn build(self) -> Result<OutboundMessage, MessageError> {
+        validate_channel_id(&self.channel_id)?;
+
+        let content = self.content.filter(|content| !content.is_empty());
+        if content.is_none() && self.embeds.is_empty() && self.rows.is_empty() {
+            return Err(MessageError::Empty);
+        }
+        if let Some(content) = &content {
+            check_content_length(content)?;
+        }
+
+        if self.embeds.len() > MAX_EMBEDS {
+            return Err(MessageError::TooManyEmbeds { count: self.embeds.len() });
+        }
+        for (index, embed) in self.embeds.iter().enumerate() {
+            embed.validate(index)?;
+        }
+        let total: usize = self.embeds.iter().map(EmbedBuilder::text_chars).sum();
+        if total > MAX_TOTAL_EMBED_CHARS {
+            return Err(MessageError::EmbedTotalTooLong { chars: total });
+        }
+
+        if self.rows.len() > MAX_ACTION_ROWS {
+            return Err(MessageError::TooManyActionRows { count: self.rows.len() });
+        }
+        for (row_index, row) in self.rows.iter().enumerate() {
+            if row.len() > MAX_BUTTONS_PER_ROW {
+                return Err(MessageError::TooManyButtons { row: row_index, count: row.len() });
+            }
+            for (button_index, button) in row.iter().enumerate() {
+                let chars = button.label.chars().count();
+                if chars > MAX_BUTTON_LABEL_CHARS {
+                    return Err(MessageError::ButtonLabelTooLong { row: row_index, button: button_index, chars });
+                }
+                if let ButtonAction::Interaction { custom_id, .. } = &button.action {
+                    let chars = custom_id.chars().count();
+                    if chars > MAX_CUSTOM_ID_CHARS {
+                        return Err(MessageError::CustomIdTooLong { row: row_index, button: button_index, chars });
+                    }
+                }
+            }
+        }
+
+        let mut parts = Vec::new();
+        if let Some(content) = &content {
+            parts.push(format!("\"content\":\"{}\"", json_escape(content)));
+        }
+        if !self.embeds.is_empty() {
+            let embeds = self.embeds.iter().map(EmbedBuilder::to_json).collect::<Vec<_>>().join(",");
+            parts.push(format!("\"embeds\":[{}]", embeds));
+        }
+        if !self.rows.is_empty() {
+            // Component type 1 is an action row holding the buttons.
+            let rows = self
+                .rows
+                .iter()
+                .map(|row| {
+                    let buttons = row.iter().map(Button::to_json).collect::<Vec<_>>().join(",");
+                    format!("{{\"type\":1,\"components\":[{}]}}", buttons)
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            parts.push(format!("\"components\":[{}]", rows));
+        }
+
+        Ok(OutboundMessage::discord(self.channel_id, format!("{{{}}}", parts.join(","))))
+    }
+
+    /// Like `build`, but the result replaces the text, embeds, and buttons of the message
+    /// `message_id` that was sent to this channel before. The same limits apply as for a new one.
+    pub fn into_edit(self, message_id: impl Into<String>) -> Result<OutboundMessage, MessageError> {
+        let message_id = message_id.into();
+        validate_channel_id(&self.channel_id)?;
+        validate_message_id(&message_id)?;
+        let channel_id = self.channel_id.clone();
+        let built = self.build()?;
+        Ok(OutboundMessage::edit(channel_id, message_id, built.body()))
+    }
+
+    /// A checked request to remove the message `message_id` from `channel_id`. It has no body,
+    /// so there is nothing to build.
+    pub fn delete(channel_id: impl Into<String>, message_id: impl Into<String>) -> Result<OutboundMessage, MessageError> {
+        let (channel_id, message_id) = (channel_id.into(), message_id.into());
+        validate_channel_id(&channel_id)?;
+        validate_message_id(&message_id)?;
+        Ok(OutboundMessage::delete(channel_id, message_id))
+    }
+}
+
+/// Discord ids are decimal numbers ("snowflakes"); `Snowflake::parse` holds the rules.
+fn validate_channel_id(channel_id: &str) -> Result<(), MessageError> {
+    Snowflake::parse(channel_id)
+        .map(|_| ())
+        .map_err(|reason| MessageError::InvalidChannelId { id: channel_id.to_string(), reason })
+}
+
+/// Message ids are snowflakes too.
+fn validate_message_id(message_id: &str) -> Result<(), MessageError> {
+    Snowflake::parse(message_id)
+        .map(|_| ())
+        .map_err(|reason| MessageError::InvalidMessageId { id: message_id.to_string(), reason })
+}
+
+fn check_content_length(content: &str) -> Result<(), MessageError> {
+    let chars = content.chars().count();
+    if chars > MAX_CONTENT_CHARS {
+        return Err(MessageError::ContentTooLong { chars });
+    }
+    Ok(())
+}
+
+/// Checks for a message whose body was written elsewhere (usually by Python): the channel id,
+/// a non-empty body, and the length of the top-level `"content"` string if there is one.
+/// Webhook messages get their URL checked (https only) and a non-empty body; Discord's content
+/// limit does not apply to them.
+///
+/// An edit is checked like a new message, plus its message id. A delete only needs valid ids,
+/// and is refused for a webhook, as is an edit.
+///
+/// The body is not fully parsed. Embeds inside a raw body are left for Discord to judge; build
+/// the message with `MessageBuilder` to have them checked here too.
+pub fn validate_raw(message: &OutboundMessage) -> Result<(), MessageError> {
+    let is_discord = match &message.destination {
+        Destination::DiscordChannel(channel_id) => {
+            validate_channel_id(channel_id)?;
+            true
+        }
+        Destination::Webhook { url } => {
+            WebhookUrl::parse(url).map_err(MessageError::InvalidWebhookUrl)?;
+            false
+        }
+    };
+    if let Some(message_id) = message.action.message_id() {
+        if !is_discord {
+            return Err(MessageError::WebhookNotEditable);
+        }
+        validate_message_id(message_id)?;
+    }
+    if matches!(message.action, Action::Delete { .. }) {
+        return Ok(());
+    }
+    if message.body().trim().is_empty() {
+        return Err(MessageError::Empty);
+    }
+    if !is_discord {
+        return Ok(());
+    }
+    if let Some(content) = raw_content(message.body())? {
+        check_content_length(&content)?;
+    }
+    Ok(())
+}
+
+/// Find `"content":"..."` in a JSON body and decode the string. Only escapes are decoded, which
+/// is all that is needed to count characters (`\n` is one character, `é` is one character).
+fn raw_content(body: &str) -> Result<Option<String>, MessageError> {
+    let Some(key_start) = body.find("\"content\"") else {
+        return Ok(None);
+    };
+    let after_key = body[key_start + "\"content\"".len()..].trim_start();
+    let Some(after_colon) = after_key.strip_prefix(':') else {
+        return Ok(None);
+    };
+    let Some(string) = after_colon.trim_start().strip_prefix('"') else {
+        // `"content": null` and friends carry no text.
+        return Ok(None);
+    };
+
+    let mut decoded = String::new();
+    let mut chars = string.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => return Ok(Some(decoded)),
+            '\\' => match chars.next() {
+                Some('u') => {
+                    // Skip the four hex digits; a surrogate pair then counts as two characters,
+                    // which only errs towards rejecting.
+                    for _ in 0..4 {
+                        chars.next();
+                    }
+                    decoded.push('?');
+                }
+                Some(escaped) => decoded.push(escaped),
+                None => break,
+            },
+            other => decoded.push(other),
+        }
+    }
+    Err(MessageError::MalformedBody("content string is not terminated".to_string()))
+}
+
+/// Escape text for a JSON string literal.
+pub(crate) fn json_escape(raw: &str) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+    for ch in raw.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+
+    const CHANNEL: &str = "123456789012345678";
+
+    /// `count` characters, each two bytes in UTF-8, so byte counts would get the limits wrong.
+    fn text(count: usize) -> String {
+        "é".repeat(count)
+    }
+
+    fn build(embeds: Vec<EmbedBuilder>) -> Result<OutboundMessage, MessageError> {
+        embeds.into_iter().fold(MessageBuilder::new(CHANNEL), MessageBuilder::embed).build()
+    }
+
+    #[test]
+    fn content_is_limited_in_characters() {
+        assert!(MessageBuilder::new(CHANNEL).content(text(MAX_CONTENT_CHARS)).build().is_ok());
+        assert_eq!(
+            MessageBuilder::new(CHANNEL).content(text(MAX_CONTENT_CHARS + 1)).build().err(),
+            Some(MessageError::ContentTooLong { chars: MAX_CONTENT_CHARS + 1 })
+        );
+        assert_eq!(MessageBuilder::new(CHANNEL).content("").build().err(), Some(MessageError::Empty));
+        assert!(matches!(MessageBuilder::new("general").content("hi").build(), Err(MessageError::InvalidChannelId { .. })));
+    }
+
+    #[test]
+    fn embed_count_title_and_description_stop_at_their_limits() {
+        let small = || EmbedBuilder::new().title("t");
+        assert!(build(vec![small(); MAX_EMBEDS]).is_ok());
+        assert_eq!(build(vec![small(); MAX_EMBEDS + 1]).err(), Some(MessageError::TooManyEmbeds { count: MAX_EMBEDS + 1 }));
+
+        assert!(build(vec![EmbedBuilder::new().title(text(MAX_EMBED_TITLE_CHARS))]).is_ok());
+        assert_eq!(
+            build(vec![small(), EmbedBuilder::new().title(text(MAX_EMBED_TITLE_CHARS + 1))]).err(),
+            Some(MessageError::EmbedTitleTooLong { embed: 1, chars: MAX_EMBED_TITLE_CHARS + 1 })
+        );
+        assert!(build(vec![EmbedBuilder::new().description(text(MAX_EMBED_DESCRIPTION_CHARS))]).is_ok());
+        assert_eq!(
+            build(vec![EmbedBuilder::new().description(text(MAX_EMBED_DESCRIPTION_CHARS + 1))]).err(),
+            Some(MessageError::EmbedDescriptionTooLong { embed: 0, chars: MAX_EMBED_DESCRIPTION_CHARS + 1 })
+        );
+    }
+
+    #[test]
+    fn fields_and_footer_stop_at_their_limits() {
+        let fields = |count: usize| (0..count).fold(EmbedBuilder::new(), |embed, n| embed.field(format!("f{n}"), "v", false));
+        assert!(build(vec![fields(MAX_EMBED_FIELDS)]).is_ok());
+        assert_eq!(build(vec![fields(MAX_EMBED_FIELDS + 1)]).err(), Some(MessageError::TooManyFields { embed: 0, count: MAX_EMBED_FIELDS + 1 }));
+
+        let field = |name: usize, value: usize| EmbedBuilder::new().field("ok", "ok", true).field(text(name), text(value), false);
+        assert!(build(vec![field(MAX_FIELD_NAME_CHARS, MAX_FIELD_VALUE_CHARS)]).is_ok());
+        assert_eq!(
+            build(vec![field(MAX_FIELD_NAME_CHARS + 1, 1)]).err(),
+            Some(MessageError::FieldNameTooLong { embed: 0, field: 1, chars: MAX_FIELD_NAME_CHARS + 1 })
+        );
+        assert_eq!(
+            build(vec![field(1, MAX_FIELD_VALUE_CHARS + 1)]).err(),
+            Some(MessageError::FieldValueTooLong { embed: 0, field: 1, chars: MAX_FIELD_VALUE_CHARS + 1 })
+        );
+
+        assert!(build(vec![EmbedBuilder::new().footer(text(MAX_FOOTER_CHARS))]).is_ok());
+        assert_eq!(
+            build(vec![EmbedBuilder::new().footer(text(MAX_FOOTER_CHARS + 1))]).err(),
+            Some(MessageError::FooterTooLong { embed: 0, chars: MAX_FOOTER_CHARS + 1 })
+        );
+    }
+
+    #[test]
+    fn all_embeds_together_stop_at_the_total_limit() {
+        // Each embed is within its own limits; only the sum is checked here.
+        let half = MAX_TOTAL_EMBED_CHARS / 2;
+        let embed = |chars: usize| EmbedBuilder::new().description(text(chars));
+        assert!(build(vec![embed(half), embed(half)]).is_ok());
+        assert_eq!(build(vec![embed(half), embed(half + 1)]).err(), Some(MessageError::EmbedTotalTooLong { chars: MAX_TOTAL_EMBED_CHARS + 1 }));
+    }
+
+    #[test]
+    fn buttons_stop_at_their_limits() {
+        let row = |count: usize| (0..count).map(|n| Button::primary(format!("b{n}"), "Go")).collect::<Vec<_>>();
+        let rows = |count: usize| (0..count).fold(MessageBuilder::new(CHANNEL), |builder, _| builder.button_row(row(1)));
+        assert!(rows(MAX_ACTION_ROWS).build().is_ok());
+        assert_eq!(rows(MAX_ACTION_ROWS + 1).build().err(), Some(MessageError::TooManyActionRows { count: MAX_ACTION_ROWS + 1 }));
+        assert!(MessageBuilder::new(CHANNEL).button_row(row(MAX_BUTTONS_PER_ROW)).build().is_ok());
+        assert_eq!(
+            MessageBuilder::new(CHANNEL).button_row(row(MAX_BUTTONS_PER_ROW + 1)).build().err(),
+            Some(MessageError::TooManyButtons { row: 0, count: MAX_BUTTONS_PER_ROW + 1 })
+        );
+
+        let label = Button::secondary("id", text(MAX_BUTTON_LABEL_CHARS + 1));
+        assert_eq!(
+            MessageBuilder::new(CHANNEL).button_row(vec![label]).build().err(),
+            Some(MessageError::ButtonLabelTooLong { row: 0, button: 0, chars: MAX_BUTTON_LABEL_CHARS + 1 })
+        );
+        let custom_id = Button::danger("x".repeat(MAX_CUSTOM_ID_CHARS + 1), "Ban");
+        assert_eq!(
+            MessageBuilder::new(CHANNEL).button_row(vec![Button::success("ok", "Ok"), custom_id]).build().err(),
+            Some(MessageError::CustomIdTooLong { row: 0, button: 1, chars: MAX_CUSTOM_ID_CHARS + 1 })
+        );
+        // A link button has no custom id, so only its label is checked.
+        assert!(MessageBuilder::new(CHANNEL).button_row(vec![Button::link("https://example.com", "Docs")]).build().is_ok());
+    }
+
+    #[test]
+    fn quotes_and_newlines_in_embed_fields_survive_as_json() {
+        let message = MessageBuilder::new(CHANNEL)
+            .content("Say \"hi\"\n")
+            .embed(
+                EmbedBuilder::new()
+                    .title("Quest \"Dragon\"")
+                    .description("line one\nline two\t\\ end\u{1}")
+                    .color(0x00ff00)
+                    .field("Reward\n", "50 \"gold\"", true)
+                    .footer("by \\squire"),
+            )
+            .button_row(vec![Button::primary("id\"1", "Accept \"now\"")])
+            .build()
+            .unwrap();
+        let body = json::parse(message.body()).unwrap();
+        assert_eq!(body.get("content").and_then(|value| value.as_str()), Some("Say \"hi\"\n"));
+        let embed = &body.get("embeds").and_then(|embeds| embeds.as_array()).unwrap()[0];
+        let text_of = |value: Option<&json::JsonValue>| value.and_then(|value| value.as_str()).map(str::to_string);
+        assert_eq!(text_of(embed.get("title")).as_deref(), Some("Quest \"Dragon\""));
+        assert_eq!(text_of(embed.get("description")).as_deref(), Some("line one\nline two\t\\ end\u{1}"));
+        assert_eq!(embed.get("color").and_then(|value| value.as_f64()), Some(f64::from(0x00ff00)));
+        let field = &embed.get("fields").and_then(|fields| fields.as_array()).unwrap()[0];
+        assert_eq!(text_of(field.get("name")).as_deref(), Some("Reward\n"));
+        assert_eq!(text_of(field.get("value")).as_deref(), Some("50 \"gold\""));
+        assert_eq!(text_of(embed.get("footer").and_then(|footer| footer.get("text"))).as_deref(), Some("by \\squire"));
+        let button = &body.get("components").and_then(|rows| rows.as_array()).unwrap()[0]
+            .get("components")
+            .and_then(|buttons| buttons.as_array())
+            .unwrap()[0];
+        assert_eq!(text_of(button.get("custom_id")).as_deref(), Some("id\"1"));
+        assert_eq!(text_of(button.get("label")).as_deref(), Some("Accept \"now\""));
+    }
+
+    #[test]
+    fn raw_bodies_get_the_content_limit_and_destination_checks() {
+        let raw = |body: String| validate_raw(&OutboundMessage::discord(CHANNEL, body));
+        // `\n` and `\"` each count as one character once decoded.
+        let at_limit = format!("{{\"content\": \"{}\\n\\\"\"}}", "a".repeat(MAX_CONTENT_CHARS - 2));
+        assert_eq!(raw(at_limit), Ok(()));
+        let over = format!("{{\"content\":\"{}\"}}", text(MAX_CONTENT_CHARS + 1));
+        assert_eq!(raw(over).err(), Some(MessageError::ContentTooLong { chars: MAX_CONTENT_CHARS + 1 }));
+        assert_eq!(raw("{\"content\":null,\"embeds\":[]}".to_string()), Ok(()));
+        assert!(matches!(raw("{\"content\":\"open".to_string()).err(), Some(MessageError::MalformedBody(_))));
+        assert_eq!(raw("  ".to_string()).err(), Some(MessageError::Empty));
+
+        // Webhooks have no content limit, but need https and cannot be edited.
+        let long = format!("{{\"text\":\"{}\"}}", "a".repeat(MAX_CONTENT_CHARS * 2));
+        assert_eq!(validate_raw(&OutboundMessage::webhook("https://hooks.example.com/x", long)), Ok(()));
+        assert!(matches!(validate_raw(&OutboundMessage::webhook("http://hooks.example.com/x", "{}")).err(), Some(MessageError::InvalidWebhookUrl(_))));
+        let edit = OutboundMessage { action: Action::Delete { message_id: "223456789012345678".to_string() }, ..OutboundMessage::webhook("https://hooks.example.com/x", "") };
+        assert_eq!(validate_raw(&edit).err(), Some(MessageError::WebhookNotEditable));
+
+        // A delete carries no body; it only needs valid ids.
+        assert_eq!(MessageBuilder::delete(CHANNEL, "223456789012345678").map(|message| validate_raw(&message)), Ok(Ok(())));
+        assert!(matches!(MessageBuilder::delete(CHANNEL, "0").err(), Some(MessageError::InvalidMessageId { .. })));
+    }
+
+    #[test]
+    fn channel_ids_are_checked_as_snowflakes_before_queueing() {
+        for (id, reason) in [("general", SnowflakeError::NotDigits), ("0123456789012345678", SnowflakeError::LeadingZero), ("12345", SnowflakeError::OutOfRange), ("", SnowflakeError::Empty)] {
+            assert_eq!(MessageBuilder::new(id).content("hi").build().err(), Some(MessageError::InvalidChannelId { id: id.to_string(), reason }), "{id:?}");
+        }
+        let err = MessageBuilder::new("12345").content("hi").build().unwrap_err();
+        assert_eq!(err.to_string(), "channel id \"12345\" is not a Discord id: it must be 17 to 20 digits and fit in 64 bits");
+    }
+
+    #[test]
+    fn edits_are_held_to_the_same_limits_as_new_messages() {
+        let message_id = "223456789012345678";
+        let edit = MessageBuilder::new(CHANNEL).content("fixed typo").into_edit(message_id).unwrap();
+        let created = MessageBuilder::new(CHANNEL).content("fixed typo").build().unwrap();
+        assert_eq!(edit.destination, Destination::DiscordChannel(CHANNEL.to_string()));
+        assert_eq!(edit.action, Action::Edit { message_id: message_id.to_string(), body: created.body().to_string() });
+        assert_eq!(validate_raw(&edit), Ok(()));
+
+        assert_eq!(
+            MessageBuilder::new(CHANNEL).content(text(MAX_CONTENT_CHARS + 1)).into_edit(message_id).err(),
+            Some(MessageError::ContentTooLong { chars: MAX_CONTENT_CHARS + 1 })
+        );
+        assert_eq!(MessageBuilder::new(CHANNEL).into_edit(message_id).err(), Some(MessageError::Empty));
+        let err = MessageBuilder::new(CHANNEL).content("hi").into_edit("latest").unwrap_err();
+        assert_eq!(err, MessageError::InvalidMessageId { id: "latest".to_string(), reason: SnowflakeError::NotDigits });
+        assert_eq!(err.to_string(), "message id \"latest\" is not a Discord id: it must contain only the digits 0-9");
+        assert!(matches!(MessageBuilder::new("general").content("hi").into_edit(message_id), Err(MessageError::InvalidChannelId { .. })));
+
+        // Raw edits get the content limit too; a delete has no body to check.
+        let over = OutboundMessage::edit(CHANNEL, message_id, format!("{{\"content\":\"{}\"}}", text(MAX_CONTENT_CHARS + 1)));
+        assert_eq!(validate_raw(&over).err(), Some(MessageError::ContentTooLong { chars: MAX_CONTENT_CHARS + 1 }));
+        assert_eq!(validate_raw(&OutboundMessage::edit(CHANNEL, message_id, " ")).err(), Some(MessageError::Empty));
+        let delete = MessageBuilder::delete(CHANNEL, message_id).unwrap();
+        assert_eq!((delete.action.kind(), delete.body(), delete.action.message_id()), ("delete", "", Some(message_id)));
+    }
+}
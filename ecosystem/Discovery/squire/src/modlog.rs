@@ -0,0 +1,355 @@
+//! Moderation audit trail that shows when someone edits it.
+//!
+//! Each warn, mute, kick, or ban is one JSON line in `Discovery/modlog.jsonl`:
+//! `{"id":3,"ts":1700000000000,"actor":"1","target":"2","action":"ban","reason":"spam","prev_hash":"<hex>"}`.
+//! `prev_hash` is the SHA-256 of the whole previous line (the first line uses 64 zeros). The lines
+//! therefore form a chain: editing a line changes its hash, so the *next* line's `prev_hash` no
+//! longer matches, and deleting a line breaks the link across the gap as well as the `id`
+//! sequence. `verify_chain` walks the file and names the first line where that happens.
+//!
+//! What the chain cannot show on its own is the newest lines being cut off the end, because
+//! nothing points at them yet. Posting `export_summary` to a channel now and then keeps an outside
+//! copy of the latest id to compare against.
+//!
+//! Appends hold the file's lock (`lockfile::FileLock`) from reading the last line to writing the
+//! new one, so two moderators acting at once still produce a valid chain. Reasons are JSON-escaped,
+//! so a reason with newlines stays on one line.
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::gateway::DiscoveryLayout;
+use crate::sha256::{sha256, to_hex};
+use crate::json::{self, JsonValue};
+use crate::lockfile::FileLock;
+use crate::message::{json_escape, MAX_CONTENT_CHARS};
+
+/// `prev_hash` of the first record.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Position of a record in the log, starting at 1.
+pub type RecordId = u64;
+
+/// The moderation actions Squire records, matching `python/features/moderation_commands.py`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModAction {
+    Warn,
+    Mute,
+    Unmute,
+    Kick,
+    Ban,
+    Unban,
+}
+
+impl ModAction {
+    pub const ALL: [ModAction; 6] =
+        [ModAction::Warn, ModAction::Mute, ModAction::Unmute, ModAction::Kick, ModAction::Ban, ModAction::Unban];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModAction::Warn => "warn",
+            ModAction::Mute => "mute",
+            ModAction::Unmute => "unmute",
+            ModAction::Kick => "kick",
+            ModAction::Ban => "ban",
+            ModAction::Unban => "unban",
+        }
+    }
+
+    pub fn parse(raw: &str) -> Option<ModAction> {
+        Self::ALL.into_iter().find(|action| action.as_str() == raw)
+    }
+}
+
+/// What a moderator did, before it gets an id and a place in the chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModEvent {
+    pub actor_id: String,
+    pub target_id: String,
+    pub action: ModAction,
+    pub reason: String,
+}
+
+/// One line of the log, read back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModRecord {
+    pub id: RecordId,
+    /// Milliseconds since 1970.
+    pub timestamp_ms: u64,
+    pub event: ModEvent,
+    pub prev_hash: String,
+}
+
+/// Result of `verify_chain`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChainStatus {
+    /// Every link checks out; holds the number of records.
+    Intact(usize),
+    /// The first broken link: its 1-based line number and what was wrong.
+    Broken { line: usize, problem: String },
+}
+
+/// The moderation log at one path.
+#[derive(Clone, Debug)]
+pub struct ModLog {
+    path: PathBuf,
+}
+
+impl ModLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The log inside a Discovery folder (`modlog.jsonl`).
+    pub fn in_layout(layout: &DiscoveryLayout) -> Self {
+        Self::new(layout.modlog_file.clone())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add `event` to the end of the chain and return its id.
+    pub fn append(&self, event: &ModEvent) -> Result<RecordId, String> {
+        self.append_at(event, now_millis())
+    }
+
+    /// `append` with the record's time passed in.
+    pub fn append_at(&self, event: &ModEvent, timestamp_ms: u64) -> Result<RecordId, String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|err| format!("Unable to create {:?}: {}", parent, err))?;
+        }
+        let _lock = FileLock::acquire(&self.path).map_err(|err| format!("Unable to lock {:?}: {}", self.path, err))?;
+
+        let text = self.read_text()?;
+        let last = text.lines().rev().find(|line| !line.trim().is_empty());
+        let (id, prev_hash) = match last {
+            Some(line) => {
+                let previous = parse_record(line).ok_or_else(|| format!("The last line of {:?} is not a record", self.path))?;
+                (previous.id + 1, to_hex(&sha256(line.as_bytes())))
+            }
+            None => (1, GENESIS_HASH.to_string()),
+        };
+        let line = render_record(&ModRecord { id, timestamp_ms, event: event.clone(), prev_hash });
+
+        let mut file = File::options()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| format!("Unable to open {:?}: {}", self.path, err))?;
+        // One write per record so the whole line lands together.
+        file.write_all(format!("{}\n", line).as_bytes())
+            .map_err(|err| format!("Unable to append to {:?}: {}", self.path, err))?;
+        Ok(id)
+    }
+
+    /// Every record in order. Lines that do not parse are skipped; `verify_chain` reports them.
+    pub fn records(&self) -> Result<Vec<ModRecord>, String> {
+        Ok(self.read_text()?.lines().filter_map(parse_record).collect())
+    }
+
+    /// Every action taken against `target_id`, oldest first.
+    pub fn entries_for(&self, target_id: &str) -> Result<Vec<ModRecord>, String> {
+        Ok(self.records()?.into_iter().filter(|record| record.event.target_id == target_id).collect())
+    }
+
+    /// Walk the chain from the first line and stop at the first broken link.
+    pub fn verify_chain(&self) -> Result<ChainStatus, String> {
+        let text = self.read_text()?;
+        let mut expected_hash = GENESIS_HASH.to_string();
+        let mut count = 0;
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            // Ids count up from 1 with no gaps, so each one equals its line number.
+            let expected_id = line_number as RecordId;
+            let broken = |problem: String| Ok(ChainStatus::Broken { line: line_number, problem });
+            let Some(record) = parse_record(line) else {
+                return broken("not a valid record".to_string());
+            };
+            if record.prev_hash != expected_hash {
+                return broken("prev_hash does not match the line before (edited or removed)".to_string());
+            }
+            if record.id != expected_id {
+                return broken(format!("id is {} but {} was expected (a record is missing)", record.id, expected_id));
+            }
+            expected_hash = to_hex(&sha256(line.as_bytes()));
+            count += 1;
+        }
+        Ok(ChainStatus::Intact(count))
+    }
+
+    /// A short text for posting through the gateway: chain status, counts per action, and the
+    /// latest records. Reasons are left out and ids shortened to their last four digits, so the
+    /// post can go to a wider channel than the log itself. Fits one Discord message.
+    pub fn export_summary(&self, latest: usize) -> Result<String, String> {
+        let records = self.records()?;
+        let status = match self.verify_chain()? {
+            ChainStatus::Intact(count) => format!("chain intact ({} records)", count),
+            ChainStatus::Broken { line, .. } => format!("CHAIN BROKEN at line {}", line),
+        };
+        let mut summary = format!("Moderation log: {}\n", status);
+        let counts: Vec<String> = ModAction::ALL
+            .iter()
+            .map(|action| (action, records.iter().filter(|record| record.event.action == *action).count()))
+            .filter(|(_, count)| *count > 0)
+            .map(|(action, count)| format!("{} {}", action.as_str(), count))
+            .collect();
+        if !counts.is_empty() {
+            summary.push_str(&format!("Totals: {}\n", counts.join(", ")));
+        }
+        for record in records.iter().rev().take(latest).rev() {
+            let line = format!(
+                "#{} {} target …{} by …{}\n",
+                record.id,
+                record.event.action.as_str(),
+                last_digits(&record.event.target_id),
+                last_digits(&record.event.actor_id)
+            );
+            if summary.chars().count() + line.chars().count() > MAX_CONTENT_CHARS {
+                break;
+            }
+            summary.push_str(&line);
+        }
+        Ok(summary)
+    }
+
+    fn read_text(&self) -> Result<String, String> {
+        match fs::read(&self.path) {
+            Ok(bytes) => String::from_utf8(bytes).map_err(|_| format!("{:?} is not UTF-8", self.path)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(String::new()),
+            Err(err) => Err(format!("Unable to read {:?}: {}", self.path, err)),
+        }
+    }
+}
+
+fn render_record(record: &ModRecord) -> String {
+    format!(
+        "{{\"id\":{},\"ts\":{},\"actor\":\"{}\",\"target\":\"{}\",\"action\":\"{}\",\"reason\":\"{}\",\"prev_hash\":\"{}\"}}",
+        record.id,
+        record.timestamp_ms,
+        json_escape(&record.event.actor_id),
+        json_escape(&record.event.target_id),
+        record.event.action.as_str(),
+        json_escape(&record.event.reason),
+        record.prev_hash
+    )
+}
+
+fn parse_record(line: &str) -> Option<ModRecord> {
+    let document = json::parse(line).ok()?;
+    let number = |key: &str| document.get(key).and_then(JsonValue::as_f64).filter(|n| *n >= 0.0 && n.fract() == 0.0);
+    let text = |key: &str| document.get(key).and_then(JsonValue::as_str).map(str::to_string);
+    Some(ModRecord {
+        id: number("id")? as RecordId,
+        timestamp_ms: number("ts")? as u64,
+        event: ModEvent {
+            actor_id: text("actor")?,
+            target_id: text("target")?,
+            action: ModAction::parse(document.get("action")?.as_str()?)?,
+            reason: text("reason")?,
+        },
+        prev_hash: text("prev_hash")?,
+    })
+}
+
+/// The last four characters of an id, enough to tell people apart in a summary.
+fn last_digits(id: &str) -> String {
+    let chars: Vec<char> = id.chars().collect();
+    chars[chars.len().saturating_sub(4)..].iter().collect()
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START_MS: u64 = 1_700_000_000_000;
+
+    fn temp_log(name: &str) -> ModLog {
+        let dir = std::env::temp_dir().join(format!("squire-modlog-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        ModLog::new(dir.join("Discovery").join("modlog.jsonl"))
+    }
+
+    fn event(target: &str, action: ModAction, reason: &str) -> ModEvent {
+        ModEvent { actor_id: "111111111111111111".to_string(), target_id: target.to_string(), action, reason: reason.to_string() }
+    }
+
+    /// A log of four actions against two members, one second apart.
+    fn filled_log(name: &str) -> ModLog {
+        let log = temp_log(name);
+        let events = [
+            event("222222222222222222", ModAction::Warn, "spam"),
+            event("333333333333333333", ModAction::Mute, "line one\nline \"two\""),
+            event("222222222222222222", ModAction::Kick, "spam again"),
+            event("222222222222222222", ModAction::Ban, "third strike"),
+        ];
+        for (n, event) in events.iter().enumerate() {
+            assert_eq!(log.append_at(event, START_MS + n as u64 * 1000), Ok(n as RecordId + 1));
+        }
+        log
+    }
+
+    fn rewrite_lines(log: &ModLog, edit: impl FnOnce(&mut Vec<String>)) {
+        let mut lines: Vec<String> = fs::read_to_string(log.path()).unwrap().lines().map(str::to_string).collect();
+        edit(&mut lines);
+        fs::write(log.path(), lines.iter().map(|line| format!("{line}\n")).collect::<String>()).unwrap();
+    }
+
+    #[test]
+    fn a_clean_log_verifies_and_reads_back() {
+        let log = filled_log("clean");
+        assert_eq!(log.verify_chain(), Ok(ChainStatus::Intact(4)));
+        let records = log.records().unwrap();
+        assert_eq!(records[0].prev_hash, GENESIS_HASH);
+        assert_eq!(records[1].timestamp_ms, START_MS + 1000);
+        assert_eq!(records[1].event.reason, "line one\nline \"two\"");
+        assert_eq!(fs::read_to_string(log.path()).unwrap().lines().count(), 4, "a reason with a newline stays on one line");
+        assert_eq!(temp_log("empty").verify_chain(), Ok(ChainStatus::Intact(0)));
+    }
+
+    #[test]
+    fn editing_a_middle_line_breaks_the_next_link() {
+        let log = filled_log("edited");
+        rewrite_lines(&log, |lines| lines[1] = lines[1].replace("\"mute\"", "\"warn\""));
+        let status = log.verify_chain().unwrap();
+        assert!(matches!(&status, ChainStatus::Broken { line: 3, problem } if problem.starts_with("prev_hash")), "{status:?}");
+        assert!(log.export_summary(10).unwrap().starts_with("Moderation log: CHAIN BROKEN at line 3\n"));
+    }
+
+    #[test]
+    fn deleting_a_line_is_detected() {
+        let log = filled_log("deleted");
+        rewrite_lines(&log, |lines| {
+            lines.remove(1);
+        });
+        assert!(matches!(log.verify_chain().unwrap(), ChainStatus::Broken { line: 2, .. }));
+
+        // Removing the first line leaves the new first line pointing at a missing predecessor.
+        let log = filled_log("deleted-first");
+        rewrite_lines(&log, |lines| {
+            lines.remove(0);
+        });
+        assert!(matches!(log.verify_chain().unwrap(), ChainStatus::Broken { line: 1, .. }));
+    }
+
+    #[test]
+    fn actions_are_listed_per_target_and_summarized_without_reasons() {
+        let log = filled_log("query");
+        let actions: Vec<ModAction> = log.entries_for("222222222222222222").unwrap().iter().map(|record| record.event.action).collect();
+        assert_eq!(actions, [ModAction::Warn, ModAction::Kick, ModAction::Ban]);
+        assert!(log.entries_for("999").unwrap().is_empty());
+
+        let summary = log.export_summary(2).unwrap();
+        assert_eq!(
+            summary,
+            "Moderation log: chain intact (4 records)\nTotals: warn 1, mute 1, kick 1, ban 1\n#3 kick target …2222 by …1111\n#4 ban target …2222 by …1111\n"
+        );
+        assert!(!summary.contains("spam"));
+    }
+}
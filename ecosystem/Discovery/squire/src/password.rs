@@ -0,0 +1,388 @@
+//! Password hashes in the format of Squire's Python `crypto/passwords.py`.
+//!
+//! The Python side stores `scrypt$n=<n>$r=<r>$p=<p>$salt=<base64>$key=<base64>` records, and
+//! older deployments still hold `pbkdf2_sha256$<iterations>$<salt>$<base64 key>` (Django's
+//! layout, with the salt as plain text). This module writes the first and checks both, so a
+//! record made on one side verifies on the other. It backs the `ffi` layer, which lets the
+//! remaining Python pieces check passwords without shelling out to a CLI.
+//!
+//! scrypt (RFC 7914) is written out here on top of the gateway's SHA-256, like the rest of the
+//! crate's cryptography: PBKDF2-HMAC-SHA256 spreads the password over `p` blocks, each block is
+//! mixed through `n` Salsa20/8 rounds that need `128 * r * n` bytes of memory, and PBKDF2 runs
+//! once more to squeeze out the key.
+
+use std::fs::File;
+use std::io::Read;
+
+use crate::gateway::SecretBytes;
+use crate::sha256::{constant_time_eq, hmac_sha256};
+
+/// Salt bytes for a new hash, as `SALT_LENGTH_BYTES` in Python.
+const SALT_LEN: usize = 16;
+/// Derived key bytes for a new hash.
+const KEY_LEN: usize = 32;
+/// Prefix of legacy PBKDF2 records.
+const LEGACY_PBKDF2_PREFIX: &str = "pbkdf2_sha256";
+/// The most working memory a stored record may ask for. Python's heaviest calibrated profile
+/// needs 512 MiB; anything beyond this is a damaged or hostile record, not a real hash.
+const MAX_SCRYPT_MEMORY: u64 = 1 << 30;
+/// The most PBKDF2 rounds a legacy record may ask for. Django's current default is about a
+/// million; a count far beyond that only makes every login attempt burn CPU.
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+
+/// scrypt cost parameters, like Python's `ScryptProfile`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScryptParams {
+    /// CPU/memory cost, a power of two.
+    pub n: u64,
+    /// Block size.
+    pub r: u32,
+    /// Parallelization.
+    pub p: u32,
+}
+
+impl ScryptParams {
+    /// The Python default (`ScryptProfile.moderate()`): 32 MiB.
+    pub const MODERATE: ScryptParams = ScryptParams { n: 1 << 15, r: 8, p: 1 };
+
+    /// Refuse parameters scrypt cannot run with, or that would need more than
+    /// `MAX_SCRYPT_MEMORY`.
+    fn check(self) -> Result<(), String> {
+        if self.n < 2 || !self.n.is_power_of_two() {
+            return Err(format!("scrypt n must be a power of two above 1, got {}", self.n));
+        }
+        if self.r == 0 || self.p == 0 {
+            return Err("scrypt r and p must be at least 1".to_string());
+        }
+        // Checked, so a record with an enormous n or r cannot wrap around to a small figure.
+        let memory = self.n.checked_add(u64::from(self.p)).and_then(|blocks| blocks.checked_mul(128 * u64::from(self.r)));
+        match memory {
+            Some(memory) if memory <= MAX_SCRYPT_MEMORY => {}
+            Some(memory) => {
+                return Err(format!("scrypt parameters need {memory} bytes of memory, more than the {MAX_SCRYPT_MEMORY} allowed"));
+            }
+            None => return Err(format!("scrypt parameters need more than the {MAX_SCRYPT_MEMORY} bytes of memory allowed")),
+        }
+        Ok(())
+    }
+}
+
+/// How a stored hash matched, like Python's `VerifyOutcome`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PasswordMatch {
+    /// An `scrypt$` record matched.
+    Current,
+    /// A legacy `pbkdf2_sha256$` record matched; store a fresh `hash_password` for the user.
+    Legacy,
+    /// Wrong password, a damaged record, or an unknown format.
+    NoMatch,
+}
+
+/// Hash `plaintext` with a fresh salt and `ScryptParams::MODERATE`.
+pub fn hash_password(plaintext: &str) -> Result<String, String> {
+    hash_password_with(plaintext, ScryptParams::MODERATE)
+}
+
+/// `hash_password` with explicit parameters. They are written into the record, so
+/// `verify_password` needs nothing else to check it. The salt comes from `/dev/urandom`; without
+/// it there is no hash, since a guessable salt would defeat its purpose.
+pub fn hash_password_with(plaintext: &str, params: ScryptParams) -> Result<String, String> {
+    params.check()?;
+    let mut salt = [0u8; SALT_LEN];
+    File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut salt))
+        .map_err(|err| format!("Unable to read random bytes for the salt: {err}"))?;
+    let key = scrypt(plaintext.as_bytes(), &salt, params, KEY_LEN);
+    Ok(format!(
+        "scrypt$n={}$r={}$p={}$salt={}$key={}",
+        params.n,
+        params.r,
+        params.p,
+        base64_encode(&salt),
+        base64_encode(key.expose())
+    ))
+}
+
+/// Check `plaintext` against a stored `scrypt$` or `pbkdf2_sha256$` record. The prefix picks the
+/// verifier, as in Python's `verify_password_any`. A damaged record is `NoMatch`, so one bad row
+/// locks out one user instead of failing the caller.
+pub fn verify_password(plaintext: &str, stored: &str) -> PasswordMatch {
+    let matched = match stored.split_once('$').map(|(prefix, _)| prefix) {
+        Some("scrypt") => verify_scrypt(plaintext, stored).map(|ok| (ok, PasswordMatch::Current)),
+        Some(LEGACY_PBKDF2_PREFIX) => verify_pbkdf2(plaintext, stored).map(|ok| (ok, PasswordMatch::Legacy)),
+        _ => None,
+    };
+    match matched {
+        Some((true, outcome)) => outcome,
+        _ => PasswordMatch::NoMatch,
+    }
+}
+
+/// `None` when the record cannot be read.
+fn verify_scrypt(plaintext: &str, stored: &str) -> Option<bool> {
+    let fields: Vec<&str> = stored.split('$').collect();
+    let [_, n, r, p, salt, key] = fields.as_slice() else {
+        return None;
+    };
+    let params = ScryptParams {
+        n: n.strip_prefix("n=")?.parse().ok()?,
+        r: r.strip_prefix("r=")?.parse().ok()?,
+        p: p.strip_prefix("p=")?.parse().ok()?,
+    };
+    params.check().ok()?;
+    let salt = base64_decode(salt.strip_prefix("salt=")?)?;
+    let expected = base64_decode(key.strip_prefix("key=")?)?;
+    if expected.is_empty() {
+        return None;
+    }
+    let derived = scrypt(plaintext.as_bytes(), &salt, params, expected.len());
+    Some(constant_time_eq(derived.expose(), &expected))
+}
+
+/// `None` when the record cannot be read.
+fn verify_pbkdf2(plaintext: &str, stored: &str) -> Option<bool> {
+    let fields: Vec<&str> = stored.split('$').collect();
+    let [_, iterations, salt, key] = fields.as_slice() else {
+        return None;
+    };
+    // Digits only, so "-5" or " 100" never sneak through, as in `parse_legacy_pbkdf2`.
+    if iterations.is_empty() || !iterations.bytes().all(|b| b.is_ascii_digit()) || salt.is_empty() {
+        return None;
+    }
+    let iterations: u32 = iterations.parse().ok().filter(|count| (1..=MAX_PBKDF2_ITERATIONS).contains(count))?;
+    let expected = base64_decode(key)?;
+    if expected.is_empty() {
+        return None;
+    }
+    let derived = pbkdf2_sha256(plaintext.as_bytes(), salt.as_bytes(), iterations, expected.len());
+    Some(constant_time_eq(derived.expose(), &expected))
+}
+
+/// scrypt (RFC 7914), with `params` already checked.
+fn scrypt(password: &[u8], salt: &[u8], params: ScryptParams, key_len: usize) -> SecretBytes {
+    let block_len = 128 * params.r as usize;
+    let mut blocks = pbkdf2_sha256(password, salt, 1, block_len * params.p as usize).expose().to_vec();
+    let mut scratch = vec![0u8; block_len * params.n as usize];
+    for block in blocks.chunks_mut(block_len) {
+        ro_mix(block, params.n as usize, &mut scratch);
+    }
+    let key = pbkdf2_sha256(password, &blocks, 1, key_len);
+    // Both hold material derived from the password.
+    scratch.fill(0);
+    blocks.fill(0);
+    key
+}
+
+/// PBKDF2-HMAC-SHA256 (RFC 8018) with `key_len` bytes of output.
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32, key_len: usize) -> SecretBytes {
+    let mut output = Vec::with_capacity(key_len.div_ceil(32) * 32);
+    let mut message = salt.to_vec();
+    message.extend_from_slice(&[0; 4]);
+    for index in 1..=key_len.div_ceil(32) as u32 {
+        message[salt.len()..].copy_from_slice(&index.to_be_bytes());
+        let mut u = hmac_sha256(password, &message);
+        let mut t = u;
+        for _ in 1..iterations {
+            u = hmac_sha256(password, &u);
+            for (acc, byte) in t.iter_mut().zip(u) {
+                *acc ^= byte;
+            }
+        }
+        output.extend_from_slice(&t);
+    }
+    output.truncate(key_len);
+    SecretBytes::new(output)
+}
+
+/// scryptROMix: fill `scratch` with `n` successive mixes of `block`, then mix `block` with
+/// entries picked by its own contents.
+fn ro_mix(block: &mut [u8], n: usize, scratch: &mut [u8]) {
+    let len = block.len();
+    for chunk in scratch.chunks_mut(len) {
+        chunk.copy_from_slice(block);
+        block_mix(block);
+    }
+    for _ in 0..n {
+        // Integerify: the first little-endian word of the last 64-byte sub-block, modulo n.
+        let tail = &block[len - 64..len - 56];
+        let j = (u64::from_le_bytes(tail.try_into().expect("8 bytes")) % n as u64) as usize;
+        for (byte, other) in block.iter_mut().zip(&scratch[j * len..(j + 1) * len]) {
+            *byte ^= other;
+        }
+        block_mix(block);
+    }
+}
+
+/// scryptBlockMix with Salsa20/8: chain the 64-byte sub-blocks, then put the even outputs
+/// before the odd ones.
+fn block_mix(block: &mut [u8]) {
+    let count = block.len() / 64;
+    let mut x: [u8; 64] = block[block.len() - 64..].try_into().expect("64 bytes");
+    let mut output = vec![0u8; block.len()];
+    for (index, chunk) in block.chunks(64).enumerate() {
+        for (byte, other) in x.iter_mut().zip(chunk) {
+            *byte ^= other;
+        }
+        salsa20_8(&mut x);
+        let slot = if index % 2 == 0 { index / 2 } else { count / 2 + index / 2 };
+        output[slot * 64..(slot + 1) * 64].copy_from_slice(&x);
+    }
+    block.copy_from_slice(&output);
+    output.fill(0);
+}
+
+/// The Salsa20 core with 8 rounds, applied in place to one 64-byte block.
+fn salsa20_8(block: &mut [u8; 64]) {
+    let mut input = [0u32; 16];
+    for (word, bytes) in input.iter_mut().zip(block.chunks(4)) {
+        *word = u32::from_le_bytes(bytes.try_into().expect("4 bytes"));
+    }
+    let mut x = input;
+    let quarter = |x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize| {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    };
+    for _ in 0..4 {
+        quarter(&mut x, 0, 4, 8, 12);
+        quarter(&mut x, 5, 9, 13, 1);
+        quarter(&mut x, 10, 14, 2, 6);
+        quarter(&mut x, 15, 3, 7, 11);
+        quarter(&mut x, 0, 1, 2, 3);
+        quarter(&mut x, 5, 6, 7, 4);
+        quarter(&mut x, 10, 11, 8, 9);
+        quarter(&mut x, 15, 12, 13, 14);
+    }
+    for ((bytes, word), original) in block.chunks_mut(4).zip(x).zip(input) {
+        bytes.copy_from_slice(&word.wrapping_add(original).to_le_bytes());
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with `=` padding, as Python's `base64.b64encode` writes it.
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, byte)| bits | (u32::from(*byte) << (16 - 8 * index)));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                output.push(BASE64_ALPHABET[(bits >> (18 - 6 * index)) as usize & 63] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+/// Strict standard base64: padded, no other characters. `None` for anything else, which
+/// `verify_password` treats as a damaged record.
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let bytes = text.as_bytes();
+    if !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut output = Vec::with_capacity(bytes.len() / 4 * 3);
+    for (index, quad) in bytes.chunks(4).enumerate() {
+        let padding = quad.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && index != bytes.len() / 4 - 1) {
+            return None;
+        }
+        let mut bits = 0u32;
+        for &c in &quad[..4 - padding] {
+            let value = BASE64_ALPHABET.iter().position(|a| *a == c)? as u32;
+            bits = (bits << 6) | value;
+        }
+        bits <<= 6 * padding as u32;
+        output.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha256::to_hex;
+
+    /// Cheap enough for tests; the format is the same as for real parameters.
+    const FAST: ScryptParams = ScryptParams { n: 16, r: 1, p: 1 };
+
+    #[test]
+    fn scrypt_and_pbkdf2_match_rfc_7914() {
+        assert_eq!(
+            to_hex(scrypt(b"", b"", FAST, 64).expose()),
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+             fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+        );
+        assert_eq!(
+            to_hex(pbkdf2_sha256(b"passwd", b"salt", 1, 64).expose()),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+             49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+        );
+    }
+
+    #[test]
+    fn a_new_hash_verifies_and_records_its_parameters() {
+        let stored = hash_password_with("hunter2", FAST).unwrap();
+        assert!(stored.starts_with("scrypt$n=16$r=1$p=1$salt="), "{stored}");
+        assert_eq!(verify_password("hunter2", &stored), PasswordMatch::Current);
+        assert_eq!(verify_password("hunter3", &stored), PasswordMatch::NoMatch);
+        assert_ne!(hash_password_with("hunter2", FAST).unwrap(), stored, "every hash gets a fresh salt");
+    }
+
+    #[test]
+    fn legacy_pbkdf2_records_match_as_legacy() {
+        let key = base64_encode(pbkdf2_sha256(b"hunter2", b"pepper", 3, 32).expose());
+        let stored = format!("pbkdf2_sha256$3$pepper${key}");
+        assert_eq!(verify_password("hunter2", &stored), PasswordMatch::Legacy);
+        assert_eq!(verify_password("hunter3", &stored), PasswordMatch::NoMatch);
+        for damaged in [format!("pbkdf2_sha256$-3$pepper${key}"), format!("pbkdf2_sha256$0$pepper${key}"), "pbkdf2_sha256$3$pepper$".to_string()] {
+            assert_eq!(verify_password("hunter2", &damaged), PasswordMatch::NoMatch, "{damaged}");
+        }
+    }
+
+    #[test]
+    fn damaged_or_costly_records_never_match() {
+        let stored = hash_password_with("hunter2", FAST).unwrap();
+        for damaged in [
+            stored.replace("n=16", "n=15"),
+            stored.replace("n=16", "n=1073741824"),
+            stored.replace("n=16", "n=4611686018427387904"),
+            stored.replace("n=16", "n=9223372036854775808"),
+            stored.replace("r=1", "r=4294967295"),
+            stored.replace("p=1", "p=4294967295"),
+            stored.replace("salt=", "salt=!"),
+            stored.replacen("$key=", "$extra$key=", 1),
+            "md5$abc".to_string(),
+            String::new(),
+        ] {
+            assert_eq!(verify_password("hunter2", &damaged), PasswordMatch::NoMatch, "{damaged}");
+        }
+        assert!(hash_password_with("x", ScryptParams { n: 1 << 30, r: 8, p: 1 }).unwrap_err().contains("more than the"));
+        assert!(hash_password_with("x", ScryptParams { n: 16, r: 0, p: 1 }).is_err());
+        assert!(hash_password_with("x", ScryptParams { n: 1 << 62, r: 8, p: 1 }).unwrap_err().contains("more than the"));
+        assert!(hash_password_with("x", ScryptParams { n: 1 << 63, r: u32::MAX, p: u32::MAX }).unwrap_err().contains("more than the"));
+    }
+
+    #[test]
+    fn legacy_records_asking_for_too_many_rounds_never_match() {
+        let key = base64_encode(pbkdf2_sha256(b"hunter2", b"pepper", 3, 32).expose());
+        for iterations in [u32::MAX, MAX_PBKDF2_ITERATIONS + 1] {
+            let stored = format!("pbkdf2_sha256${iterations}$pepper${key}");
+            assert_eq!(verify_password("hunter2", &stored), PasswordMatch::NoMatch, "{stored}");
+        }
+    }
+
+    #[test]
+    fn base64_round_trips_and_is_strict() {
+        for bytes in [&b""[..], b"h", b"hi", b"hey", &[0xff; 17]] {
+            assert_eq!(base64_decode(&base64_encode(bytes)).as_deref(), Some(bytes));
+        }
+        assert_eq!(base64_encode(b"hi"), "aGk=");
+        assert_eq!(base64_decode("aGk"), None);
+        assert_eq!(base64_decode("aG=k"), None);
+        assert_eq!(base64_decode("aGk=\n"), None);
+    }
+}
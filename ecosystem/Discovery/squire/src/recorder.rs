@@ -0,0 +1,498 @@
+//! Request recording: keep a copy of every request the gateway hands to its transport.
+//!
+//! A security review wants to see exactly what Squire would send to Discord, without trusting
+//! a summary and without running it live. With `SQUIRE_RECORD_REQUESTS=<folder>` set, the
+//! transport is wrapped in a `RecordingTransport`, which writes each request to its own numbered
+//! file before passing it on: `000001.req`, `000002.req`, and so on. It records dry runs too,
+//! so a whole batch can be reviewed offline with `SQUIRE_DRY_RUN=1`.
+//!
+//! One file looks like this:
+//!
+//! ```text
+//! method=POST
+//! target=/api/v10/channels/123456789012345678/messages
+//! at_unix_millis=1792146146421
+//! header=Authorization: <redacted auth-digest=3f9c0a1b2c3d4e5f>
+//! header=Content-Type: application/json
+//! body_bytes=27
+//! truncated_bytes=0
+//! body:
+//! {"content":"hello, world"}
+//! ```
+//!
+//! - The `Authorization` header holds the bot token, so only its short digest is kept. It is the
+//!   same `auth-digest` the secure dispatch log shows, so the two can be matched up.
+//! - A webhook's URL is a secret too; its `target` is the redacted URL (`webhook=true`).
+//! - Bodies longer than `SQUIRE_RECORD_MAX_BODY_BYTES` (default 64 KiB) are cut there, and the file
+//!   says so twice: `truncated_bytes=` above and a `[... N more bytes not recorded ...]` line at
+//!   the end.
+//! - Numbers continue after the highest file already in the folder, so a restart never
+//!   overwrites an earlier recording. Files are created with "only if new", so two gateways
+//!   recording into one folder skip each other's numbers instead of colliding.
+//! - Recording problems are logged and never stop a request from going out.
+//!
+//! `squire-gateway --replay <folder>` reads the files back and queues the Discord messages again
+//! (see `replay_message`), for example after fixing a bug that made a batch fail.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::gateway::{short_digest, HttpResponse, OutboundMessage, Transport, TransportError};
+use crate::log::Logger;
+use crate::runtime::{Clock, EnvSource, SystemClock};
+use crate::webhook::WebhookUrl;
+
+/// Folder to record requests into. Unset or empty means no recording.
+pub const RECORD_ENV: &str = "SQUIRE_RECORD_REQUESTS";
+/// Largest body kept in one recording, in bytes.
+pub const RECORD_MAX_BODY_ENV: &str = "SQUIRE_RECORD_MAX_BODY_BYTES";
+/// `SQUIRE_RECORD_MAX_BODY_BYTES` when it is not set: 64 KiB.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 64 * 1024;
+/// Extension of recording files.
+pub const RECORD_EXTENSION: &str = "req";
+
+const LOG: Logger = Logger::new("recorder");
+
+/// One recorded request, as written to and read from a `.req` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedRequest {
+    /// The number in the file name.
+    pub index: u64,
+    /// `POST`, `PUT`, `PATCH`, or `DELETE`.
+    pub method: String,
+    /// The Discord path, or the redacted URL for a webhook.
+    pub target: String,
+    pub webhook: bool,
+    pub at_unix_millis: u128,
+    /// Headers as sent, except that `Authorization` only shows its digest.
+    pub headers: Vec<(String, String)>,
+    /// The body as recorded: the whole body, or its first `max_body_bytes` when `truncated_bytes > 0`.
+    pub body: String,
+    /// Length of the body that was sent.
+    pub body_bytes: usize,
+    /// How many bytes of the body were left out.
+    pub truncated_bytes: usize,
+}
+
+impl RecordedRequest {
+    /// The file contents (see the module notes).
+    pub fn render(&self) -> String {
+        let mut text = format!("method={}\ntarget={}\n", self.method, self.target);
+        if self.webhook {
+            text.push_str("webhook=true\n");
+        }
+        text.push_str(&format!("at_unix_millis={}\n", self.at_unix_millis));
+        for (name, value) in &self.headers {
+            text.push_str(&format!("header={}: {}\n", name, value));
+        }
+        text.push_str(&format!("body_bytes={}\ntruncated_bytes={}\nbody:\n{}", self.body_bytes, self.truncated_bytes, self.body));
+        if self.truncated_bytes > 0 {
+            text.push_str(&format!("\n[... {} more bytes not recorded ...]", self.truncated_bytes));
+        }
+        text.push('\n');
+        text
+    }
+
+    /// Read a file written by `render`; `index` comes from its name.
+    pub fn parse(index: u64, text: &str) -> Result<RecordedRequest, String> {
+        let (head, body) = text
+            .split_once("\nbody:\n")
+            .ok_or("no \"body:\" line")?;
+        let mut request = RecordedRequest {
+            index,
+            method: String::new(),
+            target: String::new(),
+            webhook: false,
+            at_unix_millis: 0,
+            headers: Vec::new(),
+            body: String::new(),
+            body_bytes: 0,
+            truncated_bytes: 0,
+        };
+        let number = |key: &str, value: &str| value.parse::<u128>().map_err(|_| format!("{key} is not a number: {value:?}"));
+        for line in head.lines() {
+            let (key, value) = line.split_once('=').ok_or_else(|| format!("line {line:?} is not key=value"))?;
+            match key {
+                "method" => request.method = value.to_string(),
+                "target" => request.target = value.to_string(),
+                "webhook" => request.webhook = value == "true",
+                "at_unix_millis" => request.at_unix_millis = number(key, value)?,
+                "header" => {
+                    let (name, value) = value.split_once(": ").ok_or_else(|| format!("header {value:?} has no \": \""))?;
+                    request.headers.push((name.to_string(), value.to_string()));
+                }
+                "body_bytes" => request.body_bytes = number(key, value)? as usize,
+                "truncated_bytes" => request.truncated_bytes = number(key, value)? as usize,
+                _ => return Err(format!("unknown key {key:?}")),
+            }
+        }
+        if request.method.is_empty() || request.target.is_empty() {
+            return Err("method or target is missing".to_string());
+        }
+        // `render` ends the file with one newline of its own, after the body or the marker.
+        let body = body.strip_suffix('\n').unwrap_or(body);
+        request.body = match request.truncated_bytes {
+            0 => body.to_string(),
+            _ => body.rsplit_once("\n[... ").map_or(body, |(kept, _)| kept).to_string(),
+        };
+        Ok(request)
+    }
+}
+
+/// Writes numbered `.req` files into one folder.
+pub struct RequestRecorder {
+    dir: PathBuf,
+    next_index: u64,
+    max_body_bytes: usize,
+    /// Stamps `at_unix_millis`.
+    clock: Rc<dyn Clock>,
+}
+
+impl RequestRecorder {
+    /// Record into `dir`, creating it when needed. Numbering continues after the highest
+    /// recording already there.
+    pub fn open(dir: &Path) -> io::Result<RequestRecorder> {
+        fs::create_dir_all(dir)?;
+        let highest = recording_files(dir)?.into_iter().map(|(index, _)| index).max().unwrap_or(0);
+        Ok(RequestRecorder { dir: dir.to_path_buf(), next_index: highest + 1, max_body_bytes: DEFAULT_MAX_BODY_BYTES, clock: Rc::new(SystemClock) })
+    }
+
+    /// Keep at most `bytes` of each body instead of `DEFAULT_MAX_BODY_BYTES`.
+    pub fn with_max_body_bytes(mut self, bytes: usize) -> Self {
+        self.max_body_bytes = bytes;
+        self
+    }
+
+    /// Take the recording time from `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Rc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Write one request to the next free number and return the file's path.
+    pub fn record(&mut self, method: &str, target: &str, webhook: bool, headers: &[(String, String)], body: &str) -> io::Result<PathBuf> {
+        let mut cut = body.len().min(self.max_body_bytes);
+        while !body.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        let mut request = RecordedRequest {
+            index: 0,
+            method: method.to_string(),
+            target: target.to_string(),
+            webhook,
+            at_unix_millis: self.clock.now_millis(),
+            headers: headers.iter().map(|(name, value)| (name.clone(), redact_header(name, value))).collect(),
+            body: body[..cut].to_string(),
+            body_bytes: body.len(),
+            truncated_bytes: body.len() - cut,
+        };
+        loop {
+            request.index = self.next_index;
+            self.next_index += 1;
+            let path = self.dir.join(format!("{:06}.{}", request.index, RECORD_EXTENSION));
+            // "Only if new": a number another gateway took in the meantime is skipped.
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(request.render().as_bytes())?;
+                    return Ok(path);
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// `Authorization` keeps only its digest; every other header is kept as it is.
+fn redact_header(name: &str, value: &str) -> String {
+    if name.eq_ignore_ascii_case("authorization") {
+        format!("<redacted auth-digest={:016x}>", short_digest(value))
+    } else {
+        value.to_string()
+    }
+}
+
+/// A transport that records every request, then hands it to the transport it wraps.
+pub struct RecordingTransport {
+    inner: Box<dyn Transport>,
+    recorder: RequestRecorder,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: Box<dyn Transport>, recorder: RequestRecorder) -> Self {
+        Self { inner, recorder }
+    }
+
+    fn record(&mut self, method: &str, target: &str, webhook: bool, headers: &[(String, String)], body: &str) {
+        if let Err(err) = self.recorder.record(method, target, webhook, headers, body) {
+            LOG.warn(
+                "Could not record request; sending it anyway",
+                &[("dir", &self.recorder.dir().display().to_string()), ("error", &err.to_string())],
+            );
+        }
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn post(&mut self, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+        self.record("POST", path, false, headers, body);
+        self.inner.post(path, headers, body)
+    }
+
+    fn post_webhook(&mut self, url: &WebhookUrl, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+        self.record("POST", &url.redacted(), true, headers, body);
+        self.inner.post_webhook(url, headers, body)
+    }
+
+    fn put(&mut self, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+        self.record("PUT", path, false, headers, body);
+        self.inner.put(path, headers, body)
+    }
+
+    fn patch(&mut self, path: &str, headers: &[(String, String)], body: &str) -> Result<HttpResponse, TransportError> {
+        self.record("PATCH", path, false, headers, body);
+        self.inner.patch(path, headers, body)
+    }
+
+    fn delete(&mut self, path: &str, headers: &[(String, String)]) -> Result<HttpResponse, TransportError> {
+        self.record("DELETE", path, false, headers, "");
+        self.inner.delete(path, headers)
+    }
+
+    fn is_dry_run(&self) -> bool {
+        self.inner.is_dry_run()
+    }
+}
+
+/// `transport`, wrapped in a `RecordingTransport` when `SQUIRE_RECORD_REQUESTS` names a folder.
+/// A folder that cannot be created is logged, and the transport is returned unwrapped.
+pub fn recording_from_env(transport: Box<dyn Transport>, env: &dyn EnvSource) -> Box<dyn Transport> {
+    let Some(dir) = env.var(RECORD_ENV).filter(|dir| !dir.trim().is_empty()) else {
+        return transport;
+    };
+    let max_body_bytes = match env.var(RECORD_MAX_BODY_ENV) {
+        None => DEFAULT_MAX_BODY_BYTES,
+        Some(value) => value.trim().parse::<usize>().unwrap_or_else(|_| {
+            LOG.warn("SQUIRE_RECORD_MAX_BODY_BYTES is not a whole number; using the default", &[("value", &value)]);
+            DEFAULT_MAX_BODY_BYTES
+        }),
+    };
+    match RequestRecorder::open(Path::new(dir.trim())) {
+        Ok(recorder) => {
+            LOG.info("Recording requests", &[("dir", dir.trim()), ("max_body_bytes", &max_body_bytes.to_string())]);
+            Box::new(RecordingTransport::new(transport, recorder.with_max_body_bytes(max_body_bytes)))
+        }
+        Err(err) => {
+            LOG.error("Could not open the request recording folder; not recording", &[("dir", dir.trim()), ("error", &err.to_string())]);
+            transport
+        }
+    }
+}
+
+/// Every recording in `dir`, in number order. A file that does not parse is an error naming it.
+pub fn load_recordings(dir: &Path) -> Result<Vec<RecordedRequest>, String> {
+    let files = recording_files(dir).map_err(|err| format!("Unable to read {:?}: {}", dir, err))?;
+    files
+        .into_iter()
+        .map(|(index, path)| {
+            let text = fs::read_to_string(&path).map_err(|err| format!("Unable to read {:?}: {}", path, err))?;
+            RecordedRequest::parse(index, &text).map_err(|problem| format!("{:?}: {}", path, problem))
+        })
+        .collect()
+}
+
+/// The message to queue again for one recording. Only whole Discord channel messages can be
+/// replayed: a webhook's URL was not recorded, a cut body is not the message that was sent, and
+/// slash commands (`PUT`) are synced on every flush anyway. Edits (`PATCH`) and deletes
+/// (`DELETE`) are replayed as the same edit or delete.
+pub fn replay_message(request: &RecordedRequest) -> Result<OutboundMessage, String> {
+    if request.webhook {
+        return Err("webhook URLs are not recorded, so webhook requests cannot be replayed".to_string());
+    }
+    if request.truncated_bytes > 0 {
+        return Err(format!("the body was cut short ({} bytes not recorded)", request.truncated_bytes));
+    }
+    let is_id = |id: &&str| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit());
+    let not_a_message = || format!("{} {} is not a channel message", request.method, request.target);
+    let rest = request.target.strip_prefix("/api/v10/channels/").ok_or_else(not_a_message)?;
+    match (request.method.as_str(), rest.split_once("/messages")) {
+        ("POST", Some((channel_id, ""))) if is_id(&channel_id) => Ok(OutboundMessage::discord(channel_id, request.body.clone())),
+        (method @ ("PATCH" | "DELETE"), Some((channel_id, tail))) => {
+            let message_id = tail.strip_prefix('/').filter(is_id).ok_or_else(not_a_message)?;
+            if !is_id(&channel_id) {
+                return Err(not_a_message());
+            }
+            Ok(match method {
+                "PATCH" => OutboundMessage::edit(channel_id, message_id, request.body.clone()),
+                _ => OutboundMessage::delete(channel_id, message_id),
+            })
+        }
+        _ => Err(not_a_message()),
+    }
+}
+
+/// `(number, path)` of every `<number>.req` file in `dir`, sorted by number.
+fn recording_files(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        let index = path
+            .extension()
+            .filter(|extension| *extension == RECORD_EXTENSION)
+            .and(path.file_stem())
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok());
+        if let Some(index) = index {
+            files.push((index, path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::DryRunTransport;
+    use crate::runtime::{ManualClock, MapEnv};
+
+    const CHANNEL: &str = "123456789012345678";
+    const MESSAGE: &str = "223456789012345678";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("squire-recorder-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn recorder(dir: &Path) -> RequestRecorder {
+        RequestRecorder::open(dir).unwrap().with_clock(Rc::new(ManualClock::new(1_700_000_000_000)))
+    }
+
+    fn headers(token: &str) -> Vec<(String, String)> {
+        vec![("Authorization".to_string(), format!("Bot {token}")), ("Content-Type".to_string(), "application/json".to_string())]
+    }
+
+    fn request(method: &str, target: &str, body: &str) -> RecordedRequest {
+        RecordedRequest {
+            index: 1,
+            method: method.to_string(),
+            target: target.to_string(),
+            webhook: false,
+            at_unix_millis: 5,
+            headers: Vec::new(),
+            body: body.to_string(),
+            body_bytes: body.len(),
+            truncated_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn a_recording_redacts_the_token_and_reads_back_the_same() {
+        let dir = temp_dir("round-trip");
+        let path = recorder(&dir).record("POST", &format!("/api/v10/channels/{CHANNEL}/messages"), false, &headers("secret-token"), "{\"content\":\"hi\"}\nsecond line").unwrap();
+        assert_eq!(path, dir.join("000001.req"));
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("secret-token"));
+        let digest = format!("<redacted auth-digest={:016x}>", short_digest("Bot secret-token"));
+        assert!(text.starts_with(&format!(
+            "method=POST\ntarget=/api/v10/channels/{CHANNEL}/messages\nat_unix_millis=1700000000000\nheader=Authorization: {digest}\nheader=Content-Type: application/json\nbody_bytes=28\ntruncated_bytes=0\nbody:\n"
+        )), "{text}");
+
+        let parsed = RecordedRequest::parse(1, &text).unwrap();
+        assert_eq!(parsed.body, "{\"content\":\"hi\"}\nsecond line");
+        assert_eq!(parsed.headers[0], ("Authorization".to_string(), digest));
+        assert_eq!(parsed.render(), text);
+
+        assert!(RecordedRequest::parse(1, "method=POST\n").unwrap_err().contains("body:"));
+        assert!(RecordedRequest::parse(1, "method=POST\ncolour=red\nbody:\n").unwrap_err().contains("unknown key"));
+        assert!(RecordedRequest::parse(1, "target=/x\nbody:\n").unwrap_err().contains("missing"));
+    }
+
+    #[test]
+    fn long_bodies_are_cut_at_the_cap_and_marked() {
+        let dir = temp_dir("cap");
+        let mut recorder = recorder(&dir).with_max_body_bytes(10);
+        // `é` is two bytes; the cut moves back to the last whole character.
+        let body = "abcdefghi\u{e9}tail";
+        let path = recorder.record("POST", "/api/v10/channels/1/messages", false, &[], body).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains("body_bytes=15\ntruncated_bytes=6\nbody:\nabcdefghi\n[... 6 more bytes not recorded ...]\n"), "{text}");
+        let parsed = RecordedRequest::parse(1, &text).unwrap();
+        assert_eq!((parsed.body.as_str(), parsed.body_bytes, parsed.truncated_bytes), ("abcdefghi", 15, 6));
+        assert!(replay_message(&parsed).unwrap_err().contains("cut short"));
+
+        let whole = recorder.record("POST", "/api/v10/channels/1/messages", false, &[], "short").unwrap();
+        assert!(fs::read_to_string(whole).unwrap().ends_with("truncated_bytes=0\nbody:\nshort\n"));
+    }
+
+    #[test]
+    fn numbering_continues_after_a_restart_and_skips_taken_numbers() {
+        let dir = temp_dir("numbering");
+        let mut first = recorder(&dir);
+        first.record("POST", "/a", false, &[], "1").unwrap();
+        first.record("POST", "/a", false, &[], "2").unwrap();
+        fs::write(dir.join("notes.txt"), "not a recording").unwrap();
+        fs::write(dir.join("draft.req"), "not numbered").unwrap();
+
+        let mut second = recorder(&dir);
+        assert_eq!(second.record("POST", "/a", false, &[], "3").unwrap(), dir.join("000003.req"));
+        // Another recorder that opened before `second` wrote still gets a number of its own.
+        let mut racing = recorder(&dir);
+        second.record("POST", "/a", false, &[], "4").unwrap();
+        assert_eq!(racing.record("POST", "/a", false, &[], "5").unwrap(), dir.join("000005.req"));
+        let bodies: Vec<String> = load_recordings(&dir).unwrap().into_iter().map(|recording| recording.body).collect();
+        assert_eq!(bodies, ["1", "2", "3", "4", "5"]);
+
+        fs::write(dir.join("000009.req"), "garbage").unwrap();
+        assert!(load_recordings(&dir).unwrap_err().contains("000009.req"));
+    }
+
+    /// Destination and action agree; priority and schedule are not recorded.
+    fn same(replayed: &OutboundMessage, expected: &OutboundMessage) -> bool {
+        replayed.destination == expected.destination && replayed.action == expected.action
+    }
+
+    #[test]
+    fn only_whole_channel_messages_are_replayed() {
+        let post = request("POST", &format!("/api/v10/channels/{CHANNEL}/messages"), "{\"content\":\"hi\"}");
+        assert!(same(&replay_message(&post).unwrap(), &OutboundMessage::discord(CHANNEL, "{\"content\":\"hi\"}")));
+        let patch = request("PATCH", &format!("/api/v10/channels/{CHANNEL}/messages/{MESSAGE}"), "{\"content\":\"new\"}");
+        assert!(same(&replay_message(&patch).unwrap(), &OutboundMessage::edit(CHANNEL, MESSAGE, "{\"content\":\"new\"}")));
+        let delete = request("DELETE", &format!("/api/v10/channels/{CHANNEL}/messages/{MESSAGE}"), "");
+        assert!(same(&replay_message(&delete).unwrap(), &OutboundMessage::delete(CHANNEL, MESSAGE)));
+
+        let webhook = RecordedRequest { webhook: true, ..request("POST", "https://discord.com/api/webhooks/1/<redacted>", "{}") };
+        assert!(replay_message(&webhook).unwrap_err().contains("webhook"));
+        for (method, target) in [
+            ("PUT", "/api/v10/applications/1/commands".to_string()),
+            ("POST", format!("/api/v10/channels/{CHANNEL}/messages/{MESSAGE}")),
+            ("PATCH", format!("/api/v10/channels/{CHANNEL}/messages/")),
+            ("POST", "/api/v10/channels/general/messages".to_string()),
+        ] {
+            assert!(replay_message(&request(method, &target, "{}")).unwrap_err().ends_with("is not a channel message"), "{method} {target}");
+        }
+    }
+
+    #[test]
+    fn recording_is_switched_on_by_the_environment() {
+        let dir = temp_dir("env");
+        let folder = dir.join("recordings");
+        let mut transport = recording_from_env(Box::new(DryRunTransport), &MapEnv::new().with(RECORD_ENV, &folder.to_string_lossy()).with(RECORD_MAX_BODY_ENV, "4"));
+        assert!(transport.is_dry_run());
+        transport.post("/api/v10/channels/1/messages", &headers("t"), "hello").unwrap();
+        let recordings = load_recordings(&folder).unwrap();
+        assert_eq!((recordings.len(), recordings[0].truncated_bytes), (1, 1));
+
+        let mut plain = recording_from_env(Box::new(DryRunTransport), &MapEnv::new().with(RECORD_ENV, " "));
+        plain.post("/api/v10/channels/1/messages", &[], "hello").unwrap();
+        assert_eq!(load_recordings(&folder).unwrap().len(), 1, "an empty folder name records nothing");
+    }
+}
@@ -0,0 +1,265 @@
+//! Experience points (XP) per guild member, kept in an append-only file.
+//!
+//! Every award is one JSON line added to `xp.log` in the configured `database_path` folder:
+//! `{"guild":"123","user":"456","xp":15}`. Appending never rewrites earlier lines, so a crash can
+//! at worst cut the very last line short. On open the whole log is read back into an in-memory
+//! index of totals; a torn last line is dropped (and cut from the file) with a warning, while a
+//! bad line in the middle is reported as an error because that is damage, not a crash.
+//!
+//! The log grows with every award, so once it holds more than `compact_after` lines `compact`
+//! rewrites it with one line per member holding their total. The rewrite goes through
+//! `atomic::atomic_write` while the file's lock is held, so readers and other writers see the old
+//! log or the new one, never a mix. Appends use `lockfile::append_locked` like every other
+//! Discovery file. Only one process should award XP at a time: compaction writes the totals this
+//! process knows about.
+//!
+//! Levels follow the Python feature (`python/features/experience.py`): every `LEVEL_SCALE` (100)
+//! XP is one level, starting at level 1. So 0–99 XP is level 1, 100–199 is level 2, and so on.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::atomic::atomic_write;
+use crate::json::{self, JsonValue};
+use crate::lockfile::{append_locked, FileLock};
+use crate::log::Logger;
+use crate::message::json_escape;
+
+/// XP needed per level, the same default as the Python `ExperienceTracker`.
+pub const LEVEL_SCALE: u64 = 100;
+/// Compact once the log holds more lines than this.
+pub const DEFAULT_COMPACT_AFTER: usize = 1000;
+/// File name of the log inside `database_path`.
+pub const XP_LOG_FILE: &str = "xp.log";
+
+const LOG: Logger = Logger::new("storage");
+
+/// Level for a total: `total / LEVEL_SCALE + 1`.
+pub fn level_for(total: u64) -> u64 {
+    total / LEVEL_SCALE + 1
+}
+
+/// XP totals for every member, backed by `<database_path>/xp.log`.
+#[derive(Debug)]
+pub struct XpStore {
+    path: PathBuf,
+    /// guild id -> user id -> total XP.
+    totals: BTreeMap<String, BTreeMap<String, u64>>,
+    /// Lines currently in the log, to know when to compact.
+    lines: usize,
+    compact_after: usize,
+}
+
+impl XpStore {
+    /// Open (or start) the log in `database_path`, creating the folder when needed.
+    pub fn open(database_path: &Path) -> Result<XpStore, String> {
+        fs::create_dir_all(database_path)
+            .map_err(|err| format!("Unable to create database folder {:?}: {}", database_path, err))?;
+        let path = database_path.join(XP_LOG_FILE);
+        let text = match fs::read(&path) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("Unable to read {:?}: {}", path, err)),
+        };
+
+        let mut store = XpStore { path, totals: BTreeMap::new(), lines: 0, compact_after: DEFAULT_COMPACT_AFTER };
+        let mut kept = String::new();
+        // Bad lines are only forgiven when nothing valid follows them.
+        let mut pending_bad: Option<usize> = None;
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_line(line) {
+                Some((guild, user, amount)) => {
+                    if let Some(bad) = pending_bad {
+                        return Err(format!("{:?} line {} is corrupted", store.path, bad + 1));
+                    }
+                    store.apply(&guild, &user, amount);
+                    store.lines += 1;
+                    kept.push_str(line);
+                    kept.push('\n');
+                }
+                None => pending_bad = pending_bad.or(Some(index)),
+            }
+        }
+
+        let torn_without_newline = !text.is_empty() && !text.ends_with('\n');
+        if pending_bad.is_some() || torn_without_newline {
+            // Cut the torn tail off so the next append starts on a clean line.
+            LOG.warn("Repaired a torn line at the end of the XP log", &[("file", &store.path.display().to_string())]);
+            atomic_write(&store.path, kept.as_bytes()).map_err(|err| format!("Unable to repair {:?}: {}", store.path, err))?;
+        }
+        Ok(store)
+    }
+
+    /// Compact after `lines` log lines instead of `DEFAULT_COMPACT_AFTER`.
+    pub fn with_compact_after(mut self, lines: usize) -> Self {
+        self.compact_after = lines.max(1);
+        self
+    }
+
+    /// Where the log lives.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record `amount` XP for a member and return their new total.
+    pub fn add_xp(&mut self, guild: &str, user: &str, amount: u64) -> Result<u64, String> {
+        let line = render_line(guild, user, amount);
+        append_locked(&self.path, &line).map_err(|err| format!("Unable to append to {:?}: {}", self.path, err))?;
+        let total = self.apply(guild, user, amount);
+        self.lines += 1;
+        if self.lines > self.compact_after {
+            self.compact()?;
+        }
+        Ok(total)
+    }
+
+    /// Total XP for a member (0 when they have none).
+    pub fn total(&self, guild: &str, user: &str) -> u64 {
+        self.totals.get(guild).and_then(|users| users.get(user)).copied().unwrap_or(0)
+    }
+
+    /// Level for a member, see `level_for`.
+    pub fn get_level(&self, guild: &str, user: &str) -> u64 {
+        level_for(self.total(guild, user))
+    }
+
+    /// The `n` members of `guild` with the most XP, highest first. Ties are ordered by user id so
+    /// the list is the same every time.
+    pub fn top_n(&self, guild: &str, n: usize) -> Vec<(String, u64)> {
+        let mut members: Vec<(String, u64)> = self
+            .totals
+            .get(guild)
+            .map(|users| users.iter().map(|(user, total)| (user.clone(), *total)).collect())
+            .unwrap_or_default();
+        members.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        members.truncate(n);
+        members
+    }
+
+    /// Rewrite the log as one line per member holding their total.
+    pub fn compact(&mut self) -> Result<(), String> {
+        let mut text = String::new();
+        let mut lines = 0;
+        for (guild, users) in &self.totals {
+            for (user, total) in users {
+                text.push_str(&render_line(guild, user, *total));
+                text.push('\n');
+                lines += 1;
+            }
+        }
+        let _lock = FileLock::acquire(&self.path).map_err(|err| format!("Unable to lock {:?}: {}", self.path, err))?;
+        atomic_write(&self.path, text.as_bytes()).map_err(|err| format!("Unable to compact {:?}: {}", self.path, err))?;
+        LOG.info("Compacted the XP log", &[("before", &self.lines.to_string()), ("after", &lines.to_string())]);
+        self.lines = lines;
+        Ok(())
+    }
+
+    fn apply(&mut self, guild: &str, user: &str, amount: u64) -> u64 {
+        let total = self.totals.entry(guild.to_string()).or_default().entry(user.to_string()).or_insert(0);
+        *total = total.saturating_add(amount);
+        *total
+    }
+}
+
+/// `{"guild":"..","user":"..","xp":N}`.
+fn render_line(guild: &str, user: &str, amount: u64) -> String {
+    format!("{{\"guild\":\"{}\",\"user\":\"{}\",\"xp\":{}}}", json_escape(guild), json_escape(user), amount)
+}
+
+fn parse_line(line: &str) -> Option<(String, String, u64)> {
+    let document = json::parse(line).ok()?;
+    let guild = document.get("guild")?.as_str()?.to_string();
+    let user = document.get("user")?.as_str()?.to_string();
+    let amount = document.get("xp").and_then(JsonValue::as_f64)?;
+    if amount < 0.0 || amount.fract() != 0.0 {
+        return None;
+    }
+    Some((guild, user, amount as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("squire-storage-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn log_lines(store: &XpStore) -> usize {
+        fs::read_to_string(store.path()).unwrap().lines().count()
+    }
+
+    #[test]
+    fn totals_accumulate_across_reopening() {
+        let dir = temp_dir("reopen");
+        let mut store = XpStore::open(&dir.join("data")).unwrap();
+        assert_eq!(store.add_xp("g1", "alice", 60), Ok(60));
+        assert_eq!(store.add_xp("g1", "alice", 50), Ok(110));
+        assert_eq!(store.add_xp("g2", "alice", 5), Ok(5));
+        drop(store);
+
+        let store = XpStore::open(&dir.join("data")).unwrap();
+        assert_eq!((store.total("g1", "alice"), store.get_level("g1", "alice")), (110, 2));
+        assert_eq!(store.total("g2", "alice"), 5);
+        assert_eq!((store.total("g1", "nobody"), store.get_level("g1", "nobody")), (0, 1));
+        assert_eq!((level_for(99), level_for(100), level_for(250)), (1, 2, 3));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn compaction_keeps_every_total_in_one_line_per_member() {
+        let dir = temp_dir("compact");
+        let mut store = XpStore::open(&dir).unwrap().with_compact_after(4);
+        for (user, amount) in [("alice", 10), ("bob", 20), ("alice", 30), ("bob", 1)] {
+            store.add_xp("g1", user, amount).unwrap();
+        }
+        assert_eq!(log_lines(&store), 4);
+        // The fifth line passes the limit and folds the log down to one line per member.
+        store.add_xp("g2", "carol", 7).unwrap();
+        assert_eq!(log_lines(&store), 3);
+        let reopened = XpStore::open(&dir).unwrap();
+        assert_eq!((reopened.total("g1", "alice"), reopened.total("g1", "bob"), reopened.total("g2", "carol")), (40, 21, 7));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn leaderboard_is_highest_first_with_ties_by_user_id() {
+        let dir = temp_dir("leaderboard");
+        let mut store = XpStore::open(&dir).unwrap();
+        for (user, amount) in [("dave", 5), ("bob", 50), ("carol", 50), ("alice", 10)] {
+            store.add_xp("g1", user, amount).unwrap();
+        }
+        store.add_xp("g2", "zed", 1000).unwrap();
+        let top = store.top_n("g1", 3);
+        assert_eq!(top, [("bob".to_string(), 50), ("carol".to_string(), 50), ("alice".to_string(), 10)]);
+        assert!(store.top_n("unknown", 3).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_torn_last_line_is_cut_but_damage_in_the_middle_is_an_error() {
+        let dir = temp_dir("torn");
+        let path = dir.join(XP_LOG_FILE);
+        fs::write(&path, "{\"guild\":\"g1\",\"user\":\"alice\",\"xp\":10}\n{\"guild\":\"g1\",\"us").unwrap();
+        let mut store = XpStore::open(&dir).unwrap();
+        assert_eq!(store.total("g1", "alice"), 10);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"guild\":\"g1\",\"user\":\"alice\",\"xp\":10}\n");
+        // The next award starts on a clean line.
+        store.add_xp("g1", "alice", 5).unwrap();
+        assert_eq!(XpStore::open(&dir).unwrap().total("g1", "alice"), 15);
+
+        fs::write(&path, "{\"guild\":\"g1\",\"user\":\"a\",\"xp\":1}\nnot json\n{\"guild\":\"g1\",\"user\":\"a\",\"xp\":2}\n").unwrap();
+        assert!(XpStore::open(&dir).unwrap_err().ends_with("line 2 is corrupted"));
+        fs::write(&path, "{\"guild\":\"g1\",\"user\":\"a\",\"xp\":-3}\n").unwrap();
+        assert_eq!(XpStore::open(&dir).unwrap().total("g1", "a"), 0, "a negative award is dropped as a torn line");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
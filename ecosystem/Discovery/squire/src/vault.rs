@@ -0,0 +1,11 @@
+//! Opening vault envelopes in Rust (cargo feature `vault`).
+//!
+//! The reader is `ecosystem_common::vault`, shared with Sentry; see its notes for the format.
+//! This module re-exports it and names Squire's default key variable, so the gateway can keep its
+//! bot token encrypted on disk and only decrypt it while it sends (see
+//! `TokenSource::VaultEnvelope`).
+
+pub use ecosystem_common::vault::*;
+
+/// Base64 master key used to open envelopes when the caller names no other variable.
+pub const VAULT_KEY_ENV: &str = "SQUIRE_VAULT_KEY";
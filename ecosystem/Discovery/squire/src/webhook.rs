@@ -0,0 +1,169 @@
+//! Webhook URLs for destinations outside Discord, such as a Mattermost channel that mirrors the
+//! gateway's log lines.
+//!
+//! A webhook is just an HTTPS URL that accepts a JSON POST. Unlike Discord it needs no
+//! `Authorization` header: the secret is part of the URL itself (Mattermost's look like
+//! `https://chat.example.org/hooks/3x8k...`). That has two consequences here:
+//! - Only `https://` URLs are accepted, so the secret never travels in plain text past the local
+//!   proxy.
+//! - The full URL must never reach a log. `WebhookUrl::redacted` keeps the host and replaces the
+//!   path with the first 8 hex digits of its SHA-256, which is enough to tell two webhooks apart
+//!   in `Discovery/secure_transport.log` and useless for sending to them.
+//!
+//! Parsing is deliberately small: `https://host[:port]/path[?query]`. User names in the URL
+//! (`https://user@host`) and IPv6 literals are refused; a `#fragment` is dropped because it is
+//! never sent to the server anyway. Error messages name the broken part but never repeat the URL.
+
+use crate::sha256::{sha256, to_hex};
+use crate::message::json_escape;
+
+/// Port used when the URL does not name one.
+const HTTPS_PORT: u16 = 443;
+
+/// A checked `https://` webhook URL, split into the parts a request needs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct WebhookUrl {
+    /// Lowercased host name or IPv4 address.
+    host: String,
+    port: u16,
+    /// Path plus query string, always starting with `/`. This is the secret part.
+    path: String,
+}
+
+impl WebhookUrl {
+    /// Check `url` and split it. Anything but `https://host[:port]/path[?query]` is an error.
+    pub fn parse(url: &str) -> Result<Self, String> {
+        if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err("webhook URL contains spaces or control characters".to_string());
+        }
+        let (scheme, rest) = url.split_once("://").ok_or("webhook URL has no scheme (expected https://)")?;
+        if !scheme.eq_ignore_ascii_case("https") {
+            return Err(format!("webhook URLs must use https, not {:?}", scheme.to_ascii_lowercase()));
+        }
+
+        // The authority ends at the first `/`, `?`, or `#`.
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, rest) = rest.split_at(authority_end);
+        if authority.contains('@') {
+            return Err("webhook URL must not carry a user name or password before the host".to_string());
+        }
+        if authority.starts_with('[') {
+            return Err("webhook URL hosts written as IPv6 literals are not supported".to_string());
+        }
+        let (host, port) = match authority.split_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|port| *port != 0)
+                    .ok_or("webhook URL port must be a number from 1 to 65535")?;
+                (host, port)
+            }
+            None => (authority, HTTPS_PORT),
+        };
+        check_host(host)?;
+
+        // A fragment stays in the browser; servers never see it.
+        let rest = rest.split_once('#').map_or(rest, |(before, _)| before);
+        let path = if rest.starts_with('/') { rest.to_string() } else { format!("/{rest}") };
+        Ok(Self { host: host.to_ascii_lowercase(), port, path })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Path and query for the request line, e.g. `/hooks/3x8k...`. Treat it as a secret.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Value for the `Host` header: the host, plus `:port` when it is not 443.
+    pub fn host_header(&self) -> String {
+        if self.port == HTTPS_PORT {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// Safe form for logs, e.g. `chat.example.org path-sha256=1f0c9a3e`.
+    pub fn redacted(&self) -> String {
+        let digest = to_hex(&sha256(self.path.as_bytes()));
+        format!("{} path-sha256={}", self.host_header(), &digest[..8])
+    }
+}
+
+/// `Debug` shows the redacted form, so a stray `{:?}` cannot leak the secret path.
+impl std::fmt::Debug for WebhookUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WebhookUrl({})", self.redacted())
+    }
+}
+
+/// Letters, digits, dots, and hyphens, like `chat.example.org` or `10.0.0.5`.
+fn check_host(host: &str) -> Result<(), String> {
+    let valid = !host.is_empty()
+        && host.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'-')
+        && host.split('.').all(|label| !label.is_empty() && !label.starts_with('-') && !label.ends_with('-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("webhook URL host {:?} is not a valid host name", host))
+    }
+}
+
+/// The JSON body Mattermost (and Slack-compatible) webhooks expect for plain text:
+/// `{"text":"..."}`.
+pub fn text_body(text: &str) -> String {
+    format!("{{\"text\":\"{}\"}}", json_escape(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_split_into_host_port_and_path() {
+        let url = WebhookUrl::parse("HTTPS://Chat.Example.org/hooks/3x8k?channel=ops#frag").unwrap();
+        assert_eq!((url.host(), url.port(), url.path()), ("chat.example.org", 443, "/hooks/3x8k?channel=ops"));
+        assert_eq!(url.host_header(), "chat.example.org");
+
+        let url = WebhookUrl::parse("https://10.0.0.5:8443").unwrap();
+        assert_eq!((url.port(), url.path(), url.host_header().as_str()), (8443, "/", "10.0.0.5:8443"));
+        assert_eq!(WebhookUrl::parse("https://chat.example.org?x=1").unwrap().path(), "/?x=1");
+    }
+
+    #[test]
+    fn only_plain_https_urls_are_accepted() {
+        for (url, problem) in [
+            ("http://chat.example.org/hooks/abc", "must use https, not \"http\""),
+            ("chat.example.org/hooks/abc", "no scheme"),
+            ("https://user:pw@chat.example.org/hooks", "user name or password"),
+            ("https://[::1]/hooks", "IPv6"),
+            ("https://chat.example.org:0/hooks", "port must be"),
+            ("https://chat.example.org:https/hooks", "port must be"),
+            ("https://-bad.example.org/hooks", "not a valid host name"),
+            ("https://chat..org/hooks", "not a valid host name"),
+            ("https://chat.example.org/hooks/a b", "spaces or control characters"),
+        ] {
+            let err = WebhookUrl::parse(url).unwrap_err();
+            assert!(err.contains(problem), "{url}: {err}");
+            assert!(!err.contains("hooks"), "errors never repeat the URL: {err}");
+        }
+    }
+
+    #[test]
+    fn the_redacted_form_hides_the_secret_path() {
+        let url = WebhookUrl::parse("https://chat.example.org:8443/hooks/3x8ksecret").unwrap();
+        let digest = to_hex(&sha256(b"/hooks/3x8ksecret"));
+        assert_eq!(url.redacted(), format!("chat.example.org:8443 path-sha256={}", &digest[..8]));
+        assert!(!format!("{url:?}").contains("3x8ksecret"));
+        assert_ne!(url.redacted(), WebhookUrl::parse("https://chat.example.org:8443/hooks/other").unwrap().redacted());
+        assert_eq!(text_body("line \"one\"\n"), "{\"text\":\"line \\\"one\\\"\\n\"}");
+    }
+}
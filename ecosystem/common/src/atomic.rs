@@ -0,0 +1,200 @@
+//! Crash-safe file replacement.
+//!
+//! Writing a file in place has a weak moment: if the process dies half-way, the next reader finds
+//! a truncated file (a presence marker without its signature, half a manifest). `atomic_write`
+//! avoids that in four steps:
+//! 1. write everything to a temporary sibling, `<name>.tmp-<process id>`;
+//! 2. `fsync` it, so the bytes are really on disk;
+//! 3. rename it over the destination (a rename inside one folder is all-or-nothing);
+//! 4. `fsync` the folder too (Unix only), so the rename itself survives a power cut.
+//!
+//! Readers therefore see the old file or the new one, never a mix. A crash between steps 1 and 3
+//! leaves only the temporary file behind; `clean_stale_temps` removes those on the next start.
+//!
+//! The hub, Squire, Sentry, and Bard all use this module through `ecosystem-common`.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::runtime::{Clock, SystemClock};
+
+/// A temporary file untouched for this long was left by a crashed writer. Younger ones may belong
+/// to another program writing right now, so they are left alone.
+pub const STALE_TEMP_AFTER: Duration = Duration::from_secs(60);
+
+/// Replace `path` with `bytes` so readers never see a partial file. The folder must exist.
+pub fn atomic_write(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temp = temp_path(path)?;
+    let result = write_and_rename(&temp, path, bytes);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// The temporary sibling used for `path` by this process.
+pub fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} has no file name", path)))?;
+    Ok(path.with_file_name(format!("{}.tmp-{}", name.to_string_lossy(), std::process::id())))
+}
+
+fn write_and_rename(temp: &Path, path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = File::create(temp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+
+    // Windows refuses to rename onto an existing file, so the old one goes first there. That
+    // reopens a tiny gap where the file is missing, which readers already treat as "not yet".
+    #[cfg(windows)]
+    {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    fs::rename(temp, path)?;
+    sync_parent(path);
+    Ok(())
+}
+
+/// Flush the folder entry for `path`. Only Unix lets a folder be opened for this; elsewhere the
+/// rename is already as durable as the platform allows.
+fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+    }
+}
+
+/// Remove `*.tmp-<pid>` files in `dir` that a crashed writer left behind: ones from another
+/// process id that are older than `STALE_TEMP_AFTER`. Returns how many were removed.
+pub fn clean_stale_temps(dir: &Path) -> usize {
+    clean_stale_temps_with(dir, &SystemClock)
+}
+
+/// `clean_stale_temps` with the file ages measured against `clock`.
+pub fn clean_stale_temps_with(dir: &Path, clock: &dyn Clock) -> usize {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    let own_suffix = format!(".tmp-{}", std::process::id());
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some((_, pid)) = name.rsplit_once(".tmp-") else {
+            continue;
+        };
+        if pid.is_empty() || !pid.bytes().all(|b| b.is_ascii_digit()) || name.ends_with(&own_suffix) {
+            continue;
+        }
+        let old_enough = entry
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .is_some_and(|modified| clock.now_millis().saturating_sub(modified.as_millis()) > STALE_TEMP_AFTER.as_millis());
+        if old_enough && entry.file_type().is_ok_and(|kind| kind.is_file()) && fs::remove_file(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::ManualClock;
+    use std::env;
+    use std::time::SystemTime;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ecosystem-atomic-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap().flatten().map(|entry| entry.file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    /// A clock `ahead` past the real time, so files written just now look that old.
+    fn clock_ahead(ahead: Duration) -> ManualClock {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        ManualClock::new((now + ahead).as_millis())
+    }
+
+    #[test]
+    fn writes_leave_only_the_complete_file() {
+        let dir = temp_dir("write");
+        let path = dir.join("presence.txt");
+        atomic_write(&path, b"nonce=1\nsignature=abc\n").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"nonce=1\nsignature=abc\n");
+        assert_eq!(names(&dir), ["presence.txt"]);
+        assert!(!temp_path(&path).unwrap().exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn overwrite_replaces_the_whole_file() {
+        let dir = temp_dir("overwrite");
+        let path = dir.join("manifest.json");
+        atomic_write(&path, b"a much longer first version").unwrap();
+        atomic_write(&path, b"short").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "short");
+        assert_eq!(names(&dir), ["manifest.json"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_failed_write_removes_its_temporary_file() {
+        let dir = temp_dir("failed");
+        // Renaming a file over a folder fails after the temporary file was written.
+        let path = dir.join("registry.json");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inside"), b"keep").unwrap();
+        assert!(atomic_write(&path, b"{}").is_err());
+        assert_eq!(names(&dir), ["registry.json"]);
+        assert!(atomic_write(Path::new("/"), b"x").is_err_and(|err| err.kind() == io::ErrorKind::InvalidInput));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn only_old_temps_from_other_processes_are_cleaned() {
+        let dir = temp_dir("clean");
+        let own = temp_path(&dir.join("mine.txt")).unwrap();
+        for name in ["presence.txt.tmp-1", "manifest.json.tmp-99999999", "notes.tmp-abc", "notes.txt"] {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+        fs::write(&own, b"x").unwrap();
+        fs::create_dir(dir.join("folder.tmp-2")).unwrap();
+
+        // Just written: every file is too young to be a crashed writer's.
+        assert_eq!(clean_stale_temps_with(&dir, &clock_ahead(Duration::ZERO)), 0);
+        assert_eq!(clean_stale_temps_with(&dir, &clock_ahead(STALE_TEMP_AFTER - Duration::from_secs(5))), 0);
+
+        assert_eq!(clean_stale_temps_with(&dir, &clock_ahead(STALE_TEMP_AFTER + Duration::from_secs(5))), 2);
+        let mut expected = vec!["folder.tmp-2".to_string(), "notes.tmp-abc".to_string(), "notes.txt".to_string()];
+        expected.push(own.file_name().unwrap().to_string_lossy().into_owned());
+        expected.sort();
+        assert_eq!(names(&dir), expected);
+        assert_eq!(clean_stale_temps_with(&dir.join("missing"), &clock_ahead(Duration::ZERO)), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,267 @@
+//! `.env` file loader, so services do not have to export every setting by hand.
+//!
+//! The docs say hostnames, tokens, and keys "come from .env"; this module is what makes that
+//! true. It reads `KEY=value` lines and copies them into the process environment, so the rest of
+//! the program keeps calling `env::var` as before. The rules are the common dotenv ones:
+//! - blank lines and lines starting with `#` are skipped;
+//! - a leading `export ` is ignored, so the same file can be `source`d by a shell;
+//! - `"double quotes"` understand `\n`, `\t`, `\r`, `\\`, and `\"`; `'single quotes'` keep
+//!   everything literally; unquoted values end at ` #` (a comment) and lose trailing spaces.
+//!
+//! A variable that is already set wins over the file. That way a systemd unit or a one-off
+//! `VAR=x ./binary` can still override a single value without editing `.env`.
+//!
+//! Where the file is looked for (`default_dotenv_path`): the path in `SQUIRE_ENV_FILE` when it is
+//! set, otherwise `.env` beside the executable, otherwise `.env` in the current directory. Only
+//! the first one found is read. A missing file is normal and not an error; a line that does not
+//! parse is logged as a warning and skipped, because one typo should not stop a service.
+//!
+//! Call it first thing in `main`, before other threads start: changing the environment while
+//! another thread reads it is not safe.
+//!
+//! The hub, Squire, and Sentry all load their `.env` through this module (`ecosystem-common`).
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::log::Logger;
+use crate::runtime::{EnvSource, ProcessEnv};
+
+/// Path of the file to load instead of searching for `.env`.
+pub const ENV_FILE_ENV: &str = "SQUIRE_ENV_FILE";
+/// File name searched for beside the executable and in the current directory.
+pub const DOTENV_FILE_NAME: &str = ".env";
+
+const LOG: Logger = Logger::new("dotenv");
+
+/// The file exists but could not be read (permissions, not UTF-8, ...).
+#[derive(Debug)]
+pub struct DotenvError {
+    pub path: PathBuf,
+    pub source: io::Error,
+}
+
+impl fmt::Display for DotenvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unable to read {:?}: {}", self.path, self.source)
+    }
+}
+
+impl std::error::Error for DotenvError {}
+
+/// What `parse_dotenv` found in a file's text.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Dotenv {
+    /// `(key, value)` pairs in file order. A key listed twice keeps both; the first one set wins.
+    pub vars: Vec<(String, String)>,
+    /// One message per skipped line, starting with `line <n>:`.
+    pub warnings: Vec<String>,
+}
+
+/// Parse `.env` text without touching the environment.
+pub fn parse_dotenv(text: &str) -> Dotenv {
+    let mut parsed = Dotenv::default();
+    for (index, raw) in text.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_line(line) {
+            Ok(pair) => parsed.vars.push(pair),
+            Err(reason) => parsed.warnings.push(format!("line {}: {}", index + 1, reason)),
+        }
+    }
+    parsed
+}
+
+/// Load `path` into the environment and return how many variables it set. Variables that are
+/// already set are left alone and not counted. A missing file sets nothing and returns `Ok(0)`.
+/// Malformed lines are logged as warnings and skipped.
+pub fn load_dotenv(path: &Path) -> Result<usize, DotenvError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(source) => return Err(DotenvError { path: path.to_path_buf(), source }),
+    };
+    let parsed = parse_dotenv(&text);
+    let missing = missing_vars(&parsed, &ProcessEnv);
+    for (key, value) in &missing {
+        env::set_var(key, value);
+    }
+    // Logged after the variables are set, so `SQUIRE_LOG_FORMAT` from the file already applies.
+    for warning in &parsed.warnings {
+        LOG.warn("Skipping malformed line", &[("file", &path.display().to_string()), ("detail", warning)]);
+    }
+    Ok(missing.len())
+}
+
+/// The pairs of `parsed` that loading would set given `env`: keys `env` does not have yet, and
+/// only the first of a repeated key.
+pub fn missing_vars(parsed: &Dotenv, env: &dyn EnvSource) -> Vec<(String, String)> {
+    let mut missing: Vec<(String, String)> = Vec::new();
+    for (key, value) in &parsed.vars {
+        if env.var(key).is_none() && !missing.iter().any(|(seen, _)| seen == key) {
+            missing.push((key.clone(), value.clone()));
+        }
+    }
+    missing
+}
+
+/// `SQUIRE_ENV_FILE` if set, otherwise the first `.env` that exists beside the executable or in
+/// the current directory. `None` when there is nothing to load.
+pub fn default_dotenv_path() -> Option<PathBuf> {
+    default_dotenv_path_from(&ProcessEnv)
+}
+
+/// `default_dotenv_path` with `SQUIRE_ENV_FILE` read from `env`.
+pub fn default_dotenv_path_from(env: &dyn EnvSource) -> Option<PathBuf> {
+    if let Some(explicit) = env.var(ENV_FILE_ENV).filter(|value| !value.is_empty()) {
+        return Some(PathBuf::from(explicit));
+    }
+    let beside_exe = env::current_exe().ok().and_then(|exe| exe.parent().map(|dir| dir.join(DOTENV_FILE_NAME)));
+    let in_cwd = env::current_dir().ok().map(|dir| dir.join(DOTENV_FILE_NAME));
+    beside_exe.into_iter().chain(in_cwd).find(|path| path.is_file())
+}
+
+/// Load the file picked by `default_dotenv_path`. Problems are logged, never fatal: the program
+/// carries on with whatever the environment already holds.
+pub fn load_default_dotenv() {
+    let Some(path) = default_dotenv_path() else {
+        return;
+    };
+    let shown = path.display().to_string();
+    if !path.exists() {
+        LOG.warn("Environment file not found", &[("file", &shown), ("set_by", ENV_FILE_ENV)]);
+        return;
+    }
+    match load_dotenv(&path) {
+        Ok(set) => LOG.debug("Loaded environment file", &[("file", &shown), ("set", &set.to_string())]),
+        Err(err) => LOG.error("Could not load environment file", &[("error", &err.to_string())]),
+    }
+}
+
+/// One non-blank, non-comment line, already trimmed.
+fn parse_line(line: &str) -> Result<(String, String), String> {
+    let line = line.strip_prefix("export ").map(str::trim_start).unwrap_or(line);
+    let (key, rest) = line.split_once('=').ok_or("expected KEY=value")?;
+    let key = key.trim_end();
+    let valid_key = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_key {
+        return Err(format!("{key:?} is not a valid variable name"));
+    }
+    let rest = rest.trim_start();
+    let value = match rest.chars().next() {
+        Some('"') => parse_double_quoted(&rest[1..])?,
+        Some('\'') => {
+            let (value, after) = rest[1..].split_once('\'').ok_or("missing closing '")?;
+            check_after_quote(after)?;
+            value.to_string()
+        }
+        // An unquoted value ends at a ` #` comment.
+        _ => match rest.find(" #").or_else(|| rest.find("\t#")) {
+            Some(comment) => rest[..comment].trim_end().to_string(),
+            None => rest.trim_end().to_string(),
+        },
+    };
+    Ok((key.to_string(), value))
+}
+
+/// The text after an opening `"`, up to the closing one, with escapes decoded.
+fn parse_double_quoted(text: &str) -> Result<String, String> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => {
+                check_after_quote(&text[index + 1..])?;
+                return Ok(value);
+            }
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, 'r')) => value.push('\r'),
+                Some((_, '\\')) => value.push('\\'),
+                Some((_, '"')) => value.push('"'),
+                Some((_, other)) => return Err(format!("unknown escape \\{other}")),
+                None => break,
+            },
+            other => value.push(other),
+        }
+    }
+    Err("missing closing \"".to_string())
+}
+
+/// Only spaces or a comment may follow a closing quote.
+fn check_after_quote(after: &str) -> Result<(), String> {
+    let after = after.trim_start();
+    if after.is_empty() || after.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected text after the closing quote: {after:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::MapEnv;
+
+    fn pairs(parsed: &[(String, String)]) -> Vec<(&str, &str)> {
+        parsed.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect()
+    }
+
+    #[test]
+    fn variables_already_set_win_over_the_file() {
+        let parsed = parse_dotenv("SQUIRE_DISCORD_TOKEN=from-file\nSENTRY_RED_HOST=red.local\nSENTRY_RED_HOST=second\n");
+        let env = MapEnv::new().with("SQUIRE_DISCORD_TOKEN", "from-unit");
+        assert_eq!(pairs(&missing_vars(&parsed, &env)), [("SENTRY_RED_HOST", "red.local")]);
+        assert_eq!(pairs(&missing_vars(&parsed, &env.with("SENTRY_RED_HOST", ""))), []);
+    }
+
+    #[test]
+    fn quoting_and_escapes() {
+        let text = concat!(
+            "PLAIN=value with spaces   \n",
+            "COMMENTED=abc # not part of it\n",
+            "HASH=a#b\n",
+            "DOUBLE=\"line\\nnext\\t\\\"quoted\\\" \\\\ # kept\" # dropped\n",
+            "SINGLE='raw \\n # kept'\n",
+            "export EXPORTED = yes\n",
+            "EMPTY=\n",
+        );
+        let parsed = parse_dotenv(text);
+        assert_eq!(parsed.warnings, Vec::<String>::new());
+        assert_eq!(
+            pairs(&parsed.vars),
+            [
+                ("PLAIN", "value with spaces"),
+                ("COMMENTED", "abc"),
+                ("HASH", "a#b"),
+                ("DOUBLE", "line\nnext\t\"quoted\" \\ # kept"),
+                ("SINGLE", "raw \\n # kept"),
+                ("EXPORTED", "yes"),
+                ("EMPTY", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_blanks_and_bad_lines() {
+        let parsed = parse_dotenv("# settings\n\n   \n  # indented comment\nGOOD=1\nno equals sign\n9BAD=x\nOPEN=\"never closed\nESC=\"\\q\"\nTAIL='x' y\n");
+        assert_eq!(pairs(&parsed.vars), [("GOOD", "1")]);
+        let lines: Vec<&str> = parsed.warnings.iter().map(|warning| warning.split(':').next().unwrap()).collect();
+        assert_eq!(lines, ["line 6", "line 7", "line 8", "line 9", "line 10"]);
+        assert!(parsed.warnings[1].contains("\"9BAD\" is not a valid variable name"));
+    }
+
+    #[test]
+    fn a_missing_file_is_not_an_error() {
+        let path = env::temp_dir().join(format!("ecosystem-dotenv-{}-missing", std::process::id())).join(".env");
+        assert_eq!(load_dotenv(&path).unwrap(), 0);
+        assert_eq!(default_dotenv_path_from(&MapEnv::new().with(ENV_FILE_ENV, "/etc/squire/env")), Some(PathBuf::from("/etc/squire/env")));
+    }
+}
@@ -0,0 +1,407 @@
+//! A small JSON reader: Sentry loads its own status documents back in with it, and Squire's
+//! gateway reads `config.json`.
+//!
+//! Both write JSON by hand; this module goes the other way. It follows RFC 8259 closely enough
+//! for documents we produce and for typical hand-edited files, and reports the byte offset of the
+//! first problem instead of panicking. Objects keep their keys in file order, which also makes
+//! duplicate keys visible to callers that care.
+
+/// One parsed JSON value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// Look up `key` in an object. Returns `None` for missing keys and for non-objects.
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(flag) => Some(*flag),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            JsonValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// How much a document may hold before the parser gives up.
+///
+/// The parser calls itself once per `{` or `[`, so a file made of thousands of `{"a":` would use
+/// up the stack and crash the process before anything checked it. These limits turn hostile or
+/// runaway input into an ordinary error. The defaults fit every file Squire and Sentry write;
+/// a caller that really needs more builds its own and calls `parse_with`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParserLimits {
+    /// Objects and arrays inside each other. The document itself counts as level 1.
+    pub max_depth: usize,
+    /// Bytes in one string or key, after escapes are decoded.
+    pub max_string_bytes: usize,
+    /// Bytes in the whole document. Checked before parsing starts.
+    pub max_document_bytes: usize,
+}
+
+impl ParserLimits {
+    pub const DEFAULT: ParserLimits = ParserLimits { max_depth: 32, max_string_bytes: 1024 * 1024, max_document_bytes: 4 * 1024 * 1024 };
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        ParserLimits::DEFAULT
+    }
+}
+
+/// Parse a complete document with `ParserLimits::DEFAULT`. Trailing non-whitespace is an error.
+pub fn parse(text: &str) -> Result<JsonValue, String> {
+    parse_with(text, &ParserLimits::DEFAULT)
+}
+
+/// Parse a complete document with the given limits.
+pub fn parse_with(text: &str, limits: &ParserLimits) -> Result<JsonValue, String> {
+    if text.len() > limits.max_document_bytes {
+        return Err(format!("JSON error at byte 0: document is larger than the maximum of {} bytes", limits.max_document_bytes));
+    }
+    let mut parser = Parser { bytes: text.as_bytes(), position: 0, depth: 0, limits: *limits };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.position != parser.bytes.len() {
+        return Err(parser.error("unexpected text after the document"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    depth: usize,
+    limits: ParserLimits,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("JSON error at byte {}: {}", self.position, message)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => Ok(JsonValue::String(self.string()?)),
+            Some(b't') => self.literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.literal("null", JsonValue::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    /// Track nesting depth around objects and arrays.
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<JsonValue, String>) -> Result<JsonValue, String> {
+        self.depth += 1;
+        if self.depth > self.limits.max_depth {
+            return Err(self.error("maximum nesting depth exceeded"));
+        }
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn literal(&mut self, word: &str, value: JsonValue) -> Result<JsonValue, String> {
+        if self.bytes[self.position..].starts_with(word.as_bytes()) {
+            self.position += word.len();
+            Ok(value)
+        } else {
+            Err(self.error(&format!("expected {word}")))
+        }
+    }
+
+    fn object(&mut self) -> Result<JsonValue, String> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(JsonValue::Object(fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            let value = self.value()?;
+            fields.push((key, value));
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Object(fields));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<JsonValue, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(JsonValue::Array(items));
+        }
+
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut output = String::new();
+
+        loop {
+            // Copy a run of ordinary bytes in one go; the input is already valid UTF-8.
+            let start = self.position;
+            while let Some(byte) = self.peek() {
+                if byte == b'"' || byte == b'\\' || byte < 0x20 {
+                    break;
+                }
+                self.position += 1;
+            }
+            output.push_str(std::str::from_utf8(&self.bytes[start..self.position]).map_err(|_| self.error("invalid UTF-8"))?);
+            // Checked on every pass, so an escape just before the closing quote is counted too.
+            if output.len() > self.limits.max_string_bytes {
+                return Err(self.error(&format!("string is longer than the maximum of {} bytes", self.limits.max_string_bytes)));
+            }
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(output);
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    output.push(self.escape()?);
+                }
+                Some(_) => return Err(self.error("control character inside a string")),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, String> {
+        let Some(byte) = self.peek() else {
+            return Err(self.error("unterminated escape"));
+        };
+        self.position += 1;
+        Ok(match byte {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let first = self.hex4()?;
+                // Characters outside the Basic Multilingual Plane arrive as a surrogate pair.
+                if (0xD800..0xDC00).contains(&first) {
+                    if !self.bytes[self.position..].starts_with(b"\\u") {
+                        return Err(self.error("unpaired surrogate"));
+                    }
+                    self.position += 2;
+                    let second = self.hex4()?;
+                    if !(0xDC00..0xE000).contains(&second) {
+                        return Err(self.error("invalid low surrogate"));
+                    }
+                    let combined = 0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00);
+                    char::from_u32(combined).ok_or_else(|| self.error("invalid code point"))?
+                } else {
+                    char::from_u32(first).ok_or_else(|| self.error("invalid code point"))?
+                }
+            }
+            _ => return Err(self.error("unknown escape")),
+        })
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .bytes
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+            .ok_or_else(|| self.error("expected four hex digits"))?;
+        let text = std::str::from_utf8(digits).map_err(|_| self.error("expected four hex digits"))?;
+        let value = u32::from_str_radix(text, 16).map_err(|_| self.error("expected four hex digits"))?;
+        self.position += 4;
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<JsonValue, String> {
+        let start = self.position;
+        while matches!(self.peek(), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+            self.position += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.position]).map_err(|_| self.error("invalid number"))?;
+        text.parse::<f64>()
+            .map(JsonValue::Number)
+            .map_err(|_| format!("JSON error at byte {start}: invalid number {text}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nested_documents_in_key_order() {
+        let value = parse(r#" {"b": [1, 2.5, -3e2], "a": {"ok": true, "none": null}, "s": "x\"\u00e9\ud83d\ude00"} "#).unwrap();
+        let JsonValue::Object(fields) = &value else { panic!("not an object") };
+        assert_eq!(fields.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>(), ["b", "a", "s"]);
+        let numbers: Vec<f64> = value.get("b").and_then(JsonValue::as_array).unwrap().iter().filter_map(JsonValue::as_f64).collect();
+        assert_eq!(numbers, [1.0, 2.5, -300.0]);
+        assert_eq!(value.get("a").and_then(|a| a.get("ok")).and_then(JsonValue::as_bool), Some(true));
+        assert_eq!(value.get("a").and_then(|a| a.get("none")), Some(&JsonValue::Null));
+        assert_eq!(value.get("s").and_then(JsonValue::as_str), Some("x\"é😀"));
+    }
+
+    #[test]
+    fn reports_problems_instead_of_panicking() {
+        for text in ["", "{", "[1,]", "{\"a\" 1}", "\"\\ud83d\"", "1 2", "tru"] {
+            assert!(parse(text).is_err(), "{text:?} should not parse");
+        }
+        assert!(parse("{} x").unwrap_err().contains("unexpected text after the document"));
+    }
+
+    #[test]
+    fn limits_stop_deep_or_large_input() {
+        let limits = ParserLimits { max_depth: 3, max_string_bytes: 4, max_document_bytes: 64 };
+        assert!(parse_with("[[[1]]]", &limits).is_ok());
+        assert!(parse_with("[[[[1]]]]", &limits).is_err());
+        assert!(parse_with("\"abcd\"", &limits).is_ok());
+        assert!(parse_with("\"abcde\"", &limits).is_err());
+        assert!(parse_with(&format!("[{}]", "1,".repeat(40) + "1"), &limits).unwrap_err().contains("larger than the maximum"));
+        assert!(parse(&"[".repeat(10_000)).is_err());
+    }
+
+    /// `depth` objects, each holding the next under `"a"`.
+    fn nested_objects(depth: usize) -> String {
+        format!("{}1{}", "{\"a\":".repeat(depth), "}".repeat(depth))
+    }
+
+    #[test]
+    fn the_default_limits_give_each_problem_its_own_message() {
+        assert_eq!(ParserLimits::default(), ParserLimits { max_depth: 32, max_string_bytes: 1024 * 1024, max_document_bytes: 4 * 1024 * 1024 });
+        assert!(parse(&nested_objects(32)).is_ok());
+        let err = parse(&nested_objects(33)).unwrap_err();
+        assert!(err.ends_with("maximum nesting depth exceeded"), "{err}");
+        // Far past the limit is refused just as quickly, without using up the stack.
+        assert!(parse(&"{\"a\":".repeat(100_000)).unwrap_err().ends_with("maximum nesting depth exceeded"));
+        assert!(parse(&format!("{}{}", "[".repeat(32), "]".repeat(32))).is_ok());
+
+        let max = ParserLimits::DEFAULT.max_string_bytes;
+        assert!(parse(&format!("\"{}\"", "a".repeat(max))).is_ok());
+        let err = parse(&format!("\"{}\"", "a".repeat(max + 1))).unwrap_err();
+        assert!(err.ends_with("string is longer than the maximum of 1048576 bytes"), "{err}");
+        // Escapes count once decoded, including one right before the closing quote.
+        assert!(parse(&format!("\"{}\\n\"", "a".repeat(max - 1))).is_ok());
+        assert!(parse(&format!("\"{}\\n\"", "a".repeat(max))).is_err());
+        assert!(parse(&format!("{{\"{}\":1}}", "k".repeat(max + 1))).is_err(), "keys are strings too");
+
+        let document = format!("\"{}\"", " ".repeat(ParserLimits::DEFAULT.max_document_bytes));
+        assert_eq!(parse(&document).unwrap_err(), "JSON error at byte 0: document is larger than the maximum of 4194304 bytes");
+        let roomy = ParserLimits { max_depth: 64, ..ParserLimits::DEFAULT };
+        assert!(parse_with(&nested_objects(64), &roomy).is_ok());
+    }
+
+    #[test]
+    fn random_garbage_is_an_error_never_a_panic() {
+        // A small xorshift keeps the inputs the same on every run.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let alphabet = b"{}[]\",:\\/ -+.0123456789eEtrufalsn ubx\t\n";
+        let limits = ParserLimits { max_depth: 8, max_string_bytes: 16, max_document_bytes: 256 };
+        for _ in 0..20_000 {
+            let length = (next() % 64) as usize;
+            let text: String = (0..length)
+                .map(|_| match next() % 4 {
+                    0 => (b' ' + (next() % 95) as u8) as char,
+                    _ => alphabet[(next() % alphabet.len() as u64) as usize] as char,
+                })
+                .collect();
+            let _ = parse(&text);
+            let _ = parse_with(&text, &limits);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Code the hub, Squire, Sentry, and Bard all need, kept in one place.
+//!
+//! Each of these used to be copied into every crate that needed it, with a note asking to keep
+//! the copies identical. The crates now depend on this one instead and re-export the modules, so
+//! `crate::log::Logger` and friends still work inside each of them.
+//!
+//! - `log`: the shared logger (`SQUIRE_LOG_FORMAT`, `SQUIRE_LOG_LEVEL`).
+//! - `atomic`: whole-file replacement, so a crash never leaves half a file.
+//! - `lockfile`: the advisory lock every writer of a `Discovery/` file takes.
+//! - `queue_file`: line caps, rotation, and draining for the `Discovery/` queue files.
+//! - `runtime`: the clock, sleeping, and environment variables behind traits, with fakes.
+//! - `dotenv`: loads a `.env` file into the environment at startup.
+//! - `json`: a small JSON reader for config files and status documents.
+//! - `secret`: `SecretBytes`, which zeroes itself when dropped.
+//! - `self_verify`: checks the running binary against a Sentry manifest (`SQUIRE_MANIFEST`).
+//! - `sha256`: SHA-256 and HMAC-SHA256, written out by hand and checked against the published vectors.
+//! - `snowflake`: Discord ids, the creation time inside them, and local ids with the same layout.
+//! - `watchdog`: systemd's `WatchdogSec=` pings through `NOTIFY_SOCKET`.
+//! - `vault`: opens the Python vault's encrypted envelopes. Squire's `vault` and Sentry's
+//!   `vault-keys` features decide whether they use it.
+
+pub mod atomic;
+pub mod dotenv;
+pub mod json;
+pub mod lockfile;
+pub mod log;
+pub mod queue_file;
+pub mod runtime;
+pub mod secret;
+pub mod self_verify;
+pub mod sha256;
+pub mod snowflake;
+pub mod vault;
+pub mod watchdog;
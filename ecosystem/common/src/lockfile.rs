@@ -0,0 +1,321 @@
+//! Advisory lock files that keep several writers from mixing their lines in one Discovery file.
+//!
+//! The hub, Squire's gateway, and the Python modules all append to the same queue and log files.
+//! Two appends of long lines can interleave on some systems and leave a garbled line behind, so
+//! every writer first creates `<file>.lock` next to the file (for example
+//! `gateway_queue.log.lock`). Creating it uses "create only if it does not exist yet", which the
+//! operating system checks atomically: exactly one process wins, and the others wait their turn.
+//! The lock file is removed when the `FileLock` value is dropped.
+//!
+//! A process that crashes while holding a lock leaves the file behind. A lock older than
+//! `STALE_AFTER` (30 seconds) is treated as abandoned, removed, and reported in the log. Several
+//! waiters can notice the same stale lock at once, so none of them deletes `<file>.lock` by name:
+//! each renames it aside under a name only it uses, and deletes the renamed file only if it still
+//! holds the owner line (`pid=… at=… seq=…`) it judged stale. If another waiter got there first
+//! and the file moved aside is a fresh lock, it is linked back. Releasing works the same way, so a
+//! holder whose lock was reclaimed never deletes the next owner's lock.
+//!
+//! Readers take the same lock so they never see half of a line, but they only retry a few times
+//! (`READ_ATTEMPTS`) and then read anyway with a warning: routing late is better than routing
+//! never because one writer is slow.
+//!
+//! The hub, Squire, and Bard share this module through `ecosystem-common`. Python follows the
+//! same convention in `squire/python/core/file_lock.py`.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::log::Logger;
+
+/// A lock file older than this belongs to a writer that crashed and is reclaimed.
+pub const STALE_AFTER: Duration = Duration::from_secs(30);
+/// How many times a reader waits for a busy lock before reading without it.
+pub const READ_ATTEMPTS: u32 = 5;
+/// First wait between attempts; it doubles each time up to `MAX_BACKOFF`.
+const FIRST_BACKOFF: Duration = Duration::from_millis(2);
+const MAX_BACKOFF: Duration = Duration::from_millis(100);
+
+const LOG: Logger = Logger::new("lock");
+
+/// Numbers this process's locks and aside names, so two threads never write the same owner line.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Proof that this process holds `<file>.lock`. Dropping it releases the lock.
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf,
+    /// The line written into the lock file, which names this holder and no other.
+    owner: String,
+}
+
+impl FileLock {
+    /// Wait until the lock for `target` is ours. A stuck lock is reclaimed after `STALE_AFTER`,
+    /// so this never waits forever.
+    pub fn acquire(target: &Path) -> io::Result<FileLock> {
+        let path = lock_path(target);
+        let mut backoff = FIRST_BACKOFF;
+        loop {
+            if let Some(lock) = try_create(&path)? {
+                return Ok(lock);
+            }
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Try a few times to take the lock for reading `target`. `None` means a writer still holds
+    /// it; the caller reads anyway and a warning has been logged.
+    pub fn acquire_for_read(target: &Path) -> Option<FileLock> {
+        let path = lock_path(target);
+        let mut backoff = FIRST_BACKOFF;
+        for attempt in 1..=READ_ATTEMPTS {
+            match try_create(&path) {
+                Ok(Some(lock)) => return Some(lock),
+                Ok(None) if attempt < READ_ATTEMPTS => {
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    LOG.warn("Reading without lock", &[("file", &target.display().to_string()), ("error", &err.to_string())]);
+                    return None;
+                }
+            }
+        }
+        LOG.warn(
+            "Reading while another writer holds the lock",
+            &[("file", &target.display().to_string()), ("attempts", &READ_ATTEMPTS.to_string())],
+        );
+        None
+    }
+
+    /// Where this lock lives.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Someone may have reclaimed the lock while we were slow; their lock must stay.
+        if let Ok(false) = remove_if_owned(&self.path, &self.owner) {
+            LOG.warn("Lock was reclaimed by another writer before release", &[("lock", &self.path.display().to_string())]);
+        }
+    }
+}
+
+/// `<file>.lock` beside `target`.
+pub fn lock_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".lock");
+    target.with_file_name(name)
+}
+
+/// Append `line` plus a newline to `path` while holding its lock, creating the folder and file
+/// when needed.
+pub fn append_locked(path: &Path, line: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let _lock = FileLock::acquire(path)?;
+    let mut file = File::options().create(true).append(true).open(path)?;
+    // One write call per line so the whole line reaches the file together.
+    file.write_all(format!("{}\n", line).as_bytes())
+}
+
+/// Read all of `path` under its lock (or without it, after the read retries run out).
+pub fn read_locked(path: &Path) -> io::Result<Vec<u8>> {
+    let _lock = FileLock::acquire_for_read(path);
+    fs::read(path)
+}
+
+/// One attempt: create the lock, or reclaim it when stale and create it again. `Ok(None)` means
+/// someone else has it.
+fn try_create(path: &Path) -> io::Result<Option<FileLock>> {
+    if let Some(lock) = create(path)? {
+        return Ok(Some(lock));
+    }
+    if !is_stale(path) {
+        return Ok(None);
+    }
+    let Ok(stale_owner) = fs::read_to_string(path) else {
+        // Released between the two checks; the next attempt will find it free.
+        return Ok(None);
+    };
+    if remove_if_owned(path, &stale_owner)? {
+        LOG.warn("Reclaimed stale lock", &[("lock", &path.display().to_string()), ("owner", stale_owner.trim())]);
+        return create(path);
+    }
+    Ok(None)
+}
+
+/// Create the lock file if nobody has it, writing our owner line into it.
+fn create(path: &Path) -> io::Result<Option<FileLock>> {
+    match File::options().write(true).create_new(true).open(path) {
+        Ok(mut file) => {
+            // The process id and start time help when someone inspects a stuck lock; with the
+            // sequence number the line is ours alone.
+            let owner = format!("pid={} at={} seq={}\n", std::process::id(), now_millis(), SEQUENCE.fetch_add(1, Ordering::Relaxed));
+            file.write_all(owner.as_bytes())?;
+            Ok(Some(FileLock { path: path.to_path_buf(), owner }))
+        }
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Remove the lock at `path` only if it still holds `owner`. The file is first renamed to a name
+/// no one else uses, so the check and the removal see the same file; a lock that turns out to
+/// belong to someone else is linked back (a link never replaces a lock created meanwhile).
+/// `Ok(false)` means the lock was not `owner`'s, or was already gone.
+fn remove_if_owned(path: &Path, owner: &str) -> io::Result<bool> {
+    let mut aside = path.as_os_str().to_owned();
+    aside.push(format!(".aside-{}-{}", std::process::id(), SEQUENCE.fetch_add(1, Ordering::Relaxed)));
+    let aside = PathBuf::from(aside);
+    match fs::rename(path, &aside) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    }
+    if fs::read_to_string(&aside).is_ok_and(|found| found == owner) {
+        fs::remove_file(&aside)?;
+        return Ok(true);
+    }
+    if let Err(err) = fs::hard_link(&aside, path) {
+        LOG.warn("Could not put back another writer's lock", &[("lock", &path.display().to_string()), ("error", &err.to_string())]);
+    }
+    fs::remove_file(&aside)?;
+    Ok(false)
+}
+
+/// True when the lock file was last touched more than `STALE_AFTER` ago.
+fn is_stale(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > STALE_AFTER)
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ecosystem-lockfile-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Leave a lock behind as a crashed writer would, last touched a minute ago.
+    fn abandoned_lock(target: &Path) -> PathBuf {
+        let path = lock_path(target);
+        fs::write(&path, "pid=1 at=0 seq=0\n").unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(60)).unwrap();
+        path
+    }
+
+    #[test]
+    fn lock_is_exclusive_and_released_on_drop() {
+        let target = temp_dir("exclusive").join("queue.log");
+        let lock = FileLock::acquire(&target).unwrap();
+        assert!(lock.path().exists());
+        assert!(try_create(lock.path()).unwrap().is_none());
+        let path = lock.path().to_path_buf();
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn stale_lock_is_reclaimed() {
+        let target = temp_dir("stale").join("queue.log");
+        let path = abandoned_lock(&target);
+        let lock = try_create(&path).unwrap().expect("stale lock reclaimed");
+        assert!(fs::read_to_string(&path).unwrap().starts_with(&format!("pid={} ", std::process::id())));
+        drop(lock);
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 0, "no aside files left behind");
+    }
+
+    #[test]
+    fn fresh_lock_is_not_reclaimed() {
+        let target = temp_dir("fresh").join("queue.log");
+        let path = lock_path(&target);
+        fs::write(&path, "pid=1 at=0 seq=0\n").unwrap();
+        assert!(try_create(&path).unwrap().is_none());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "pid=1 at=0 seq=0\n");
+    }
+
+    #[test]
+    fn drop_leaves_a_lock_that_now_belongs_to_someone_else() {
+        let target = temp_dir("reclaimed").join("queue.log");
+        let lock = FileLock::acquire(&target).unwrap();
+        // Another writer judged us stale, took the lock, and wrote its own owner line.
+        fs::write(lock.path(), "pid=2 at=5 seq=9\n").unwrap();
+        let path = lock.path().to_path_buf();
+        drop(lock);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "pid=2 at=5 seq=9\n");
+    }
+
+    #[test]
+    fn remove_if_owned_puts_back_a_lock_it_does_not_own() {
+        let target = temp_dir("put-back").join("queue.log");
+        let path = lock_path(&target);
+        fs::write(&path, "pid=2 at=5 seq=9\n").unwrap();
+        assert!(!remove_if_owned(&path, "pid=1 at=0 seq=0\n").unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "pid=2 at=5 seq=9\n");
+        assert!(remove_if_owned(&path, "pid=2 at=5 seq=9\n").unwrap());
+        assert!(!path.exists());
+        assert!(!remove_if_owned(&path, "pid=2 at=5 seq=9\n").unwrap());
+    }
+
+    #[test]
+    fn waiters_racing_for_a_stale_lock_never_hold_it_together() {
+        let target = temp_dir("race").join("queue.log");
+        abandoned_lock(&target);
+        let holders = Arc::new(AtomicUsize::new(0));
+        let overlaps = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (target, holders, overlaps) = (target.clone(), Arc::clone(&holders), Arc::clone(&overlaps));
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        let _lock = FileLock::acquire(&target).unwrap();
+                        if holders.fetch_add(1, Ordering::SeqCst) != 0 {
+                            overlaps.fetch_add(1, Ordering::SeqCst);
+                        }
+                        thread::sleep(Duration::from_micros(200));
+                        holders.fetch_sub(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(overlaps.load(Ordering::SeqCst), 0);
+        assert!(!lock_path(&target).exists());
+    }
+
+    #[test]
+    fn append_locked_creates_folders_and_appends_whole_lines() {
+        let target = temp_dir("append").join("nested").join("queue.log");
+        append_locked(&target, "one").unwrap();
+        append_locked(&target, "two").unwrap();
+        assert_eq!(read_locked(&target).unwrap(), b"one\ntwo\n");
+        assert!(!lock_path(&target).exists());
+    }
+}
@@ -0,0 +1,348 @@
+//! Tiny logging facility: levels, named loggers, and one process-wide sink.
+//!
+//! Each component keeps a `Logger` constant (`const LOG: Logger = Logger::new("gateway");`) and
+//! calls `LOG.info("message", &[("key", "value")])`. Every line goes to one sink, standard error
+//! by default, in one of two formats chosen by `SQUIRE_LOG_FORMAT`:
+//! - `human` (default): `1767225600000 INFO  gateway: Flush done sent=3 failed=0`
+//! - `json`: `{"ts":1767225600000,"level":"info","component":"gateway","msg":"Flush done","fields":{"sent":"3","failed":"0"}}`
+//!
+//! `SQUIRE_LOG_LEVEL` (`debug`, `info`, `warn`, `error`; default `info`) hides quieter lines.
+//! Newlines inside messages and fields are escaped, so one call is always one line.
+//!
+//! Never pass a secret as a field. Wrap tokens in `redact()` first, which keeps only the fact
+//! that a value was there.
+//!
+//! The hub, Squire, Sentry, and Bard all log through this module (`ecosystem-common`), so their
+//! lines look the same.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::runtime::{EnvSource, ProcessEnv};
+
+/// Picks `human` or `json` output.
+const FORMAT_ENV: &str = "SQUIRE_LOG_FORMAT";
+/// Lowest level that is written.
+const LEVEL_ENV: &str = "SQUIRE_LOG_LEVEL";
+
+/// How important a line is. Lines below the configured level are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+
+    pub fn parse(raw: &str) -> Option<Level> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" | "warning" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Line layout written to the sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Plain text for people reading a terminal.
+    Human,
+    /// One JSON object per line for collectors and `grep`/`jq`.
+    Json,
+}
+
+impl Format {
+    pub fn parse(raw: &str) -> Option<Format> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "human" | "text" => Some(Format::Human),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// The shared settings and sink, created from the environment on first use.
+struct State {
+    format: Format,
+    min_level: Level,
+    sink: Box<dyn Write + Send>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+/// Run `action` with the shared state, creating it from the environment the first time.
+fn with_state<T>(action: impl FnOnce(&mut State) -> T) -> T {
+    // A panic while logging must not silence every later line, so a poisoned lock is reused.
+    let mut guard = STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let state = guard.get_or_insert_with(|| {
+        let (format, min_level) = settings_from(&ProcessEnv);
+        State { format, min_level, sink: Box::new(io::stderr()) }
+    });
+    action(state)
+}
+
+/// The format and level `SQUIRE_LOG_FORMAT` and `SQUIRE_LOG_LEVEL` ask for; unset or unknown
+/// values fall back to `human` and `info`.
+fn settings_from(env: &dyn EnvSource) -> (Format, Level) {
+    let format = env.var(FORMAT_ENV).and_then(|raw| Format::parse(&raw)).unwrap_or(Format::Human);
+    let min_level = env.var(LEVEL_ENV).and_then(|raw| Level::parse(&raw)).unwrap_or(Level::Info);
+    (format, min_level)
+}
+
+/// Read `SQUIRE_LOG_FORMAT` and `SQUIRE_LOG_LEVEL` again, from `env` instead of the process.
+pub fn configure(env: &dyn EnvSource) {
+    let (format, min_level) = settings_from(env);
+    with_state(|state| {
+        state.format = format;
+        state.min_level = min_level;
+    });
+}
+
+/// Send every later line to `sink` instead of standard error (tests pass a buffer).
+pub fn set_sink(sink: Box<dyn Write + Send>) {
+    with_state(|state| state.sink = sink);
+}
+
+/// Override `SQUIRE_LOG_FORMAT`.
+pub fn set_format(format: Format) {
+    with_state(|state| state.format = format);
+}
+
+/// Override `SQUIRE_LOG_LEVEL`.
+pub fn set_level(level: Level) {
+    with_state(|state| state.min_level = level);
+}
+
+/// Write an already formatted line (from `Logger::format`) to the sink.
+pub fn emit(line: &str) {
+    with_state(|state| {
+        let _ = writeln!(state.sink, "{}", line);
+        let _ = state.sink.flush();
+    });
+}
+
+/// Stand-in for a secret value: says that one was present without revealing any of it.
+pub fn redact(secret: &str) -> String {
+    if secret.is_empty() {
+        "[empty]".to_string()
+    } else {
+        "[redacted]".to_string()
+    }
+}
+
+/// A named source of log lines, such as `gateway` or `hub`.
+#[derive(Debug, Clone, Copy)]
+pub struct Logger {
+    component: &'static str,
+}
+
+impl Logger {
+    pub const fn new(component: &'static str) -> Self {
+        Self { component }
+    }
+
+    pub fn debug(&self, message: &str, fields: &[(&str, &str)]) {
+        self.log(Level::Debug, message, fields);
+    }
+
+    pub fn info(&self, message: &str, fields: &[(&str, &str)]) {
+        self.log(Level::Info, message, fields);
+    }
+
+    pub fn warn(&self, message: &str, fields: &[(&str, &str)]) {
+        self.log(Level::Warn, message, fields);
+    }
+
+    pub fn error(&self, message: &str, fields: &[(&str, &str)]) {
+        self.log(Level::Error, message, fields);
+    }
+
+    /// Format and write one line, unless `level` is below the configured minimum.
+    pub fn log(&self, level: Level, message: &str, fields: &[(&str, &str)]) {
+        if let Some(line) = self.format(level, message, fields) {
+            emit(&line);
+        }
+    }
+
+    /// The line `log` would write, or `None` when `level` is filtered out. Useful for callers
+    /// that also keep their own copy, such as the hub's `hub_queue.log`.
+    pub fn format(&self, level: Level, message: &str, fields: &[(&str, &str)]) -> Option<String> {
+        let (format, min_level) = with_state(|state| (state.format, state.min_level));
+        if level < min_level {
+            return None;
+        }
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+
+        let mut line = String::new();
+        match format {
+            Format::Human => {
+                let _ = write!(
+                    line,
+                    "{} {:<5} {}: {}",
+                    ts,
+                    level.as_str().to_ascii_uppercase(),
+                    self.component,
+                    escape_human(message)
+                );
+                for (key, value) in fields {
+                    let value = escape_human(value);
+                    if value.is_empty() || value.contains(' ') {
+                        let _ = write!(line, " {}=\"{}\"", key, value.replace('"', "\\\""));
+                    } else {
+                        let _ = write!(line, " {}={}", key, value);
+                    }
+                }
+            }
+            Format::Json => {
+                let _ = write!(
+                    line,
+                    "{{\"ts\":{},\"level\":\"{}\",\"component\":\"{}\",\"msg\":\"{}\",\"fields\":{{",
+                    ts,
+                    level.as_str(),
+                    escape_json(self.component),
+                    escape_json(message)
+                );
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        line.push(',');
+                    }
+                    let _ = write!(line, "\"{}\":\"{}\"", escape_json(key), escape_json(value));
+                }
+                line.push_str("}}");
+            }
+        }
+        Some(line)
+    }
+}
+
+/// Keep a human line on one line: newlines and tabs become visible escapes.
+fn escape_human(raw: &str) -> String {
+    raw.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r").replace('\t', "\\t")
+}
+
+/// Escape text for a JSON string literal.
+fn escape_json(raw: &str) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+    for ch in raw.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", ch as u32);
+            }
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+    use crate::runtime::MapEnv;
+    use std::sync::Arc;
+
+    /// The sink is shared by the whole process, so tests that swap it take turns.
+    static SINK_LOCK: Mutex<()> = Mutex::new(());
+
+    /// A sink the test can read back.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Run `action` with the sink captured and settings from `env`, and return the lines
+    /// `component` wrote. Other tests may log at the same time, so their lines are left out.
+    fn captured(env: &MapEnv, component: &str, action: impl FnOnce()) -> Vec<String> {
+        let _turn = SINK_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let capture = Capture::default();
+        set_sink(Box::new(capture.clone()));
+        configure(env);
+        action();
+        set_sink(Box::new(io::stderr()));
+        configure(&MapEnv::new());
+        let text = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        text.lines().filter(|line| line.contains(component)).map(str::to_string).collect()
+    }
+
+    #[test]
+    fn human_lines_escape_newlines_and_quote_spaced_fields() {
+        let log = Logger::new("log-test-human");
+        let lines = captured(&MapEnv::new().with(FORMAT_ENV, "human"), "log-test-human", || {
+            log.info("Flush done\nforged line", &[("sent", "3"), ("reason", "two words"), ("token", &redact("secret"))]);
+        });
+        assert_eq!(lines.len(), 1);
+        let (ts, rest) = lines[0].split_once(' ').unwrap();
+        assert!(ts.parse::<u128>().is_ok(), "{ts:?}");
+        assert_eq!(rest, "INFO  log-test-human: Flush done\\nforged line sent=3 reason=\"two words\" token=[redacted]");
+    }
+
+    #[test]
+    fn json_lines_parse_back_to_the_message_and_fields() {
+        let log = Logger::new("log-test-json");
+        let lines = captured(&MapEnv::new().with(FORMAT_ENV, "JSON"), "log-test-json", || {
+            log.warn("line one\nline \"two\"", &[("path", "C:\\bot"), ("bell", "\u{7}")]);
+        });
+        assert_eq!(lines.len(), 1);
+        let line = json::parse(&lines[0]).unwrap();
+        assert!(line.get("ts").and_then(|ts| ts.as_f64()).is_some());
+        assert_eq!(line.get("level").and_then(|level| level.as_str()), Some("warn"));
+        assert_eq!(line.get("component").and_then(|component| component.as_str()), Some("log-test-json"));
+        assert_eq!(line.get("msg").and_then(|msg| msg.as_str()), Some("line one\nline \"two\""));
+        let fields = line.get("fields").unwrap();
+        assert_eq!(fields.get("path").and_then(|path| path.as_str()), Some("C:\\bot"));
+        assert_eq!(fields.get("bell").and_then(|bell| bell.as_str()), Some("\u{7}"));
+    }
+
+    #[test]
+    fn level_from_the_environment_hides_quieter_lines() {
+        let log = Logger::new("log-test-level");
+        let lines = captured(&MapEnv::new().with(LEVEL_ENV, " Warning "), "log-test-level", || {
+            log.debug("hidden", &[]);
+            log.info("hidden", &[]);
+            log.warn("shown", &[]);
+            log.error("shown", &[]);
+        });
+        assert_eq!(lines.len(), 2, "{lines:?}");
+        assert!(lines[0].contains("WARN  log-test-level: shown"));
+        assert!(lines[1].contains("ERROR log-test-level: shown"));
+
+        // Unknown settings fall back to human lines at info.
+        assert_eq!(settings_from(&MapEnv::new().with(FORMAT_ENV, "xml").with(LEVEL_ENV, "loud")), (Format::Human, Level::Info));
+        assert_eq!(settings_from(&MapEnv::new().with(LEVEL_ENV, "debug")), (Format::Human, Level::Debug));
+    }
+
+    #[test]
+    fn redact_only_says_whether_there_was_a_value() {
+        assert_eq!(redact("hunter2"), "[redacted]");
+        assert_eq!(redact(""), "[empty]");
+    }
+}
@@ -0,0 +1,461 @@
+//! Size caps, rotation, and draining for the line-based queue files in `Discovery/`.
+//!
+//! `gateway_queue.log`, `hub_queue.log`, and `dead_letter.log` only ever grow. Left alone they
+//! fill the disk, and one enormous line (a Python traceback pasted into a queue, say) would be
+//! read into memory whole on every pass. A `QueueFile` wraps one such file and keeps it tidy:
+//!
+//! - **Line cap.** No line longer than `max_line_bytes` (16 KiB by default) is written or read.
+//!   The first part is kept and the rest is replaced by a marker such as
+//!   `...[truncated 48213 bytes]`, so a reader can still see that something was cut. Reading caps
+//!   a line while it streams through, so the long original is never held in memory.
+//! - **Rotation.** Once the file reaches `max_bytes` (1 MiB by default, or `SQUIRE_QUEUE_MAX_BYTES`)
+//!   it is renamed to `<name>.1`, the older `<name>.1` becomes `<name>.2`, and so on. At most
+//!   `generations` (3) old files are kept; the oldest is deleted. Each rotation also adds one to the
+//!   counter in `<name>.generation`.
+//! - **Cursors.** The hub and Squire's gateway both read the same `gateway_queue.log`, each for its
+//!   own lines, so neither may empty it. Each keeps a `QueueCursor` instead: the generation it was
+//!   reading plus a byte offset. When the counter has moved on, `read_from` first finishes the
+//!   rotated file the cursor points into, then reads the new file from the start, so a rotation
+//!   never skips a line.
+//! - **Drain.** A queue with a single consumer can use `drain()`, which reads every line and empties
+//!   the file in one step. Nothing is handled twice, and nothing is lost.
+//!
+//! All of this happens while holding the file's lock (see `lockfile`). Writers take the same lock,
+//! so no line can land between reading a file and rotating or emptying it.
+//!
+//! The hub, Squire, and Bard share this module through `ecosystem-common`.
+
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::atomic::atomic_write;
+use crate::lockfile::FileLock;
+use crate::log::Logger;
+use crate::runtime::{EnvSource, ProcessEnv};
+
+/// Overrides `DEFAULT_MAX_BYTES` for every queue file, in bytes.
+pub const MAX_BYTES_ENV: &str = "SQUIRE_QUEUE_MAX_BYTES";
+/// A queue file this large is rotated: 1 MiB.
+pub const DEFAULT_MAX_BYTES: u64 = 1024 * 1024;
+/// Longest line kept as it is, in bytes; longer ones are cut and marked.
+pub const DEFAULT_MAX_LINE_BYTES: usize = 16 * 1024;
+/// How many rotated files (`<name>.1` ... `<name>.3`) are kept.
+pub const DEFAULT_GENERATIONS: u64 = 3;
+/// Start of the marker that replaces the end of a cut line.
+pub const TRUNCATED_MARKER: &str = "...[truncated ";
+
+const LOG: Logger = Logger::new("queue");
+
+/// One line-based queue file and its limits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_line_bytes: usize,
+    generations: u64,
+}
+
+/// Where a reader stopped: the rotation generation it was reading and the byte offset in it.
+///
+/// Saved as `<offset>` while the generation is 0 (so offsets saved before rotation existed still
+/// load) and as `<generation>:<offset>` after that.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueCursor {
+    pub generation: u64,
+    pub offset: u64,
+}
+
+impl QueueCursor {
+    /// Read a saved cursor; `None` for anything that is not one.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        match raw.split_once(':') {
+            Some((generation, offset)) => {
+                Some(Self { generation: generation.parse().ok()?, offset: offset.parse().ok()? })
+            }
+            None => Some(Self { generation: 0, offset: raw.parse().ok()? }),
+        }
+    }
+
+    /// The text `parse` reads back.
+    pub fn to_state(&self) -> String {
+        if self.generation == 0 {
+            self.offset.to_string()
+        } else {
+            format!("{}:{}", self.generation, self.offset)
+        }
+    }
+}
+
+/// What one `read_from` found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueueBatch {
+    /// Complete lines, without their newlines, in the order they were written.
+    pub lines: Vec<String>,
+    /// Where the next read should start.
+    pub cursor: QueueCursor,
+    /// How many of `lines` were cut to the line cap.
+    pub truncated: usize,
+}
+
+impl QueueFile {
+    /// The queue at `path` with the default limits; `SQUIRE_QUEUE_MAX_BYTES` replaces the size limit
+    /// when it holds a positive number.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self::new_from(path, &ProcessEnv)
+    }
+
+    /// `new`, reading `SQUIRE_QUEUE_MAX_BYTES` from `env`.
+    pub fn new_from(path: impl Into<PathBuf>, env: &dyn EnvSource) -> Self {
+        let max_bytes = env
+            .var(MAX_BYTES_ENV)
+            .and_then(|raw| raw.trim().parse::<u64>().ok())
+            .filter(|bytes| *bytes > 0)
+            .unwrap_or(DEFAULT_MAX_BYTES);
+        Self { path: path.into(), max_bytes, max_line_bytes: DEFAULT_MAX_LINE_BYTES, generations: DEFAULT_GENERATIONS }
+    }
+
+    /// Rotate once the file reaches `bytes` (at least 1).
+    pub fn with_max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = bytes.max(1);
+        self
+    }
+
+    /// Cut lines longer than `bytes` (at least 1).
+    pub fn with_max_line_bytes(mut self, bytes: usize) -> Self {
+        self.max_line_bytes = bytes.max(1);
+        self
+    }
+
+    /// Keep this many rotated files (at least 1).
+    pub fn with_generations(mut self, generations: u64) -> Self {
+        self.generations = generations.max(1);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `<name>.<n>`, the file rotated out `n` rotations ago.
+    pub fn rotated_path(&self, n: u64) -> PathBuf {
+        self.sibling(&n.to_string())
+    }
+
+    /// `<name>.generation`, which counts the rotations so far.
+    pub fn generation_path(&self) -> PathBuf {
+        self.sibling("generation")
+    }
+
+    /// The rotation counter; 0 before the first rotation.
+    pub fn generation(&self) -> u64 {
+        fs::read_to_string(self.generation_path()).ok().and_then(|raw| raw.trim().parse().ok()).unwrap_or(0)
+    }
+
+    /// Append `line` plus a newline under the lock, cutting it to the line cap and rotating the file
+    /// first when it is already full. The folder and file are created when needed.
+    pub fn append(&self, line: &str) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let _lock = FileLock::acquire(&self.path)?;
+        self.rotate_when_full()?;
+        let mut file = File::options().create(true).append(true).open(&self.path)?;
+        // One write call per line so the whole line reaches the file together.
+        file.write_all(format!("{}\n", cap_line(line, self.max_line_bytes)).as_bytes())
+    }
+
+    /// Rotate the file when it has reached the size limit. Consumers call this after reading a file
+    /// that other programs (the Python modules) append to without rotating. Returns whether it
+    /// rotated.
+    pub fn rotate_if_full(&self) -> io::Result<bool> {
+        let _lock = FileLock::acquire(&self.path)?;
+        self.rotate_when_full()
+    }
+
+    /// Read every complete line after `cursor`, following it through rotated files when the file
+    /// has been rotated since. A last line without its newline is still being written and is left
+    /// for the next read. A file that shrank without a rotation (someone emptied it) is read from
+    /// the start again.
+    pub fn read_from(&self, cursor: QueueCursor) -> io::Result<QueueBatch> {
+        let _lock = FileLock::acquire_for_read(&self.path);
+        let generation = self.generation();
+        let mut batch = QueueBatch::default();
+
+        let mut offset = cursor.offset;
+        if cursor.generation < generation {
+            // The cursor points into a rotated file: finish it, then read any newer rotated files
+            // whole before the current one.
+            let behind = generation - cursor.generation;
+            if behind > self.generations {
+                LOG.warn(
+                    "Queue rotated past a reader; the oldest lines are gone",
+                    &[("file", &self.path.display().to_string()), ("rotations", &behind.to_string())],
+                );
+            }
+            for back in (1..=behind.min(self.generations)).rev() {
+                let start = if back == behind { cursor.offset } else { 0 };
+                self.read_lines(&self.rotated_path(back), start, true, &mut batch)?;
+            }
+            offset = 0;
+        } else if cursor.generation > generation {
+            // The counter went backwards (its file was deleted), so the cursor means nothing now.
+            offset = 0;
+        }
+        let end = self.read_lines(&self.path, offset, false, &mut batch)?;
+
+        // Without the lock a rotation may have happened part-way; try again on the next pass.
+        if self.generation() != generation {
+            return Ok(QueueBatch { lines: Vec::new(), cursor, truncated: 0 });
+        }
+        batch.cursor = QueueCursor { generation, offset: end };
+        Ok(batch)
+    }
+
+    /// Read every line (a last line without a newline included) and empty the file, all under
+    /// the lock. Use this only when one program is the file's sole reader.
+    pub fn drain(&self) -> io::Result<Vec<String>> {
+        let _lock = FileLock::acquire(&self.path)?;
+        let mut batch = QueueBatch::default();
+        self.read_lines(&self.path, 0, true, &mut batch)?;
+        match File::options().write(true).open(&self.path) {
+            Ok(file) => file.set_len(0)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(batch.lines)
+    }
+
+    /// Rotate while the lock is already held.
+    fn rotate_when_full(&self) -> io::Result<bool> {
+        let size = match fs::metadata(&self.path) {
+            Ok(meta) => meta.len(),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        if size < self.max_bytes {
+            return Ok(false);
+        }
+        // Oldest first: drop `<name>.N`, move every other generation up by one, then rotate the file.
+        match fs::remove_file(self.rotated_path(self.generations)) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        for n in (1..self.generations).rev() {
+            match fs::rename(self.rotated_path(n), self.rotated_path(n + 1)) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        atomic_write(&self.generation_path(), format!("{}\n", self.generation() + 1).as_bytes())?;
+        LOG.info("Queue rotated", &[("file", &self.path.display().to_string()), ("bytes", &size.to_string())]);
+        Ok(true)
+    }
+
+    /// Add the lines of `path` from byte `start` to `batch` and return the offset after the last
+    /// line taken. With `take_partial` a last line without a newline is taken too. A missing file
+    /// has no lines, and a `start` past the end reads from 0.
+    fn read_lines(&self, path: &Path, start: u64, take_partial: bool, batch: &mut QueueBatch) -> io::Result<u64> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        let mut offset = if start > file.metadata()?.len() { 0 } else { start };
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file);
+        let mut kept = Vec::new();
+        loop {
+            kept.clear();
+            let (length, ended) = read_capped_line(&mut reader, self.max_line_bytes, &mut kept)?;
+            if length == 0 || (!ended && !take_partial) {
+                return Ok(offset);
+            }
+            offset += length as u64;
+            let line_bytes = if ended { length - 1 } else { length };
+            if line_bytes > kept.len() {
+                // The cap may have split a character; drop its first half.
+                if let Err(err) = std::str::from_utf8(&kept) {
+                    if err.error_len().is_none() {
+                        kept.truncate(err.valid_up_to());
+                    }
+                }
+            }
+            let mut line = String::from_utf8_lossy(&kept).into_owned();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+            if line_bytes > kept.len() {
+                line = with_marker(line, line_bytes - kept.len());
+                batch.truncated += 1;
+            }
+            batch.lines.push(line);
+        }
+    }
+
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.file_name().map(OsString::from).unwrap_or_default();
+        name.push(".");
+        name.push(suffix);
+        self.path.with_file_name(name)
+    }
+}
+
+/// `line` cut to at most `max_bytes` (on a character boundary) plus the marker, or `line` itself
+/// when it fits.
+pub fn cap_line(line: &str, max_bytes: usize) -> Cow<'_, str> {
+    if line.len() <= max_bytes {
+        return Cow::Borrowed(line);
+    }
+    let mut cut = max_bytes;
+    while !line.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    Cow::Owned(with_marker(line[..cut].to_string(), line.len() - cut))
+}
+
+fn with_marker(mut kept: String, dropped: usize) -> String {
+    kept.push_str(&format!("{TRUNCATED_MARKER}{dropped} bytes]"));
+    kept
+}
+
+/// Read one line, keeping at most `max` bytes of it in `kept` and skipping the rest without
+/// storing it. Returns the bytes consumed (newline included) and whether a newline ended the line.
+fn read_capped_line(reader: &mut impl BufRead, max: usize, kept: &mut Vec<u8>) -> io::Result<(usize, bool)> {
+    let mut consumed = 0;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok((consumed, false));
+        }
+        let newline = buffer.iter().position(|byte| *byte == b'\n');
+        let content = newline.unwrap_or(buffer.len());
+        let room = max.saturating_sub(kept.len()).min(content);
+        kept.extend_from_slice(&buffer[..room]);
+        let used = newline.map_or(content, |at| at + 1);
+        reader.consume(used);
+        consumed += used;
+        if newline.is_some() {
+            return Ok((consumed, true));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::MapEnv;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ecosystem-queue-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn queue(dir: &Path) -> QueueFile {
+        QueueFile::new_from(dir.join("gateway_queue.log"), &MapEnv::new())
+    }
+
+    #[test]
+    fn the_size_limit_comes_from_the_environment() {
+        let dir = temp_dir("env");
+        assert_eq!(queue(&dir).max_bytes, DEFAULT_MAX_BYTES);
+        assert_eq!(QueueFile::new_from(dir.join("q"), &MapEnv::new().with(MAX_BYTES_ENV, " 4096 ")).max_bytes, 4096);
+        for bad in ["0", "-1", "lots"] {
+            assert_eq!(QueueFile::new_from(dir.join("q"), &MapEnv::new().with(MAX_BYTES_ENV, bad)).max_bytes, DEFAULT_MAX_BYTES, "{bad}");
+        }
+    }
+
+    #[test]
+    fn full_files_rotate_and_only_the_newest_generations_are_kept() {
+        let dir = temp_dir("rotate");
+        let queue = queue(&dir).with_max_bytes(10).with_generations(2);
+        for n in 1..=4 {
+            queue.append(&format!("message {n}")).unwrap();
+        }
+        // Every append after the first found a full file and rotated it away first.
+        assert_eq!(queue.generation(), 3);
+        assert_eq!(fs::read_to_string(queue.path()).unwrap(), "message 4\n");
+        assert_eq!(fs::read_to_string(queue.rotated_path(1)).unwrap(), "message 3\n");
+        assert_eq!(fs::read_to_string(queue.rotated_path(2)).unwrap(), "message 2\n");
+        assert!(!queue.rotated_path(3).exists());
+        // A consumer rotates a full file that others append to without rotating.
+        assert!(queue.rotate_if_full().unwrap());
+        assert_eq!((queue.generation(), queue.path().exists()), (4, false));
+        assert!(!queue.rotate_if_full().unwrap());
+    }
+
+    #[test]
+    fn cursors_follow_the_lines_through_a_rotation() {
+        let dir = temp_dir("cursor");
+        let queue = queue(&dir).with_max_bytes(12);
+        queue.append("first").unwrap();
+        let batch = queue.read_from(QueueCursor::default()).unwrap();
+        assert_eq!((batch.lines.as_slice(), batch.cursor), (["first".to_string()].as_slice(), QueueCursor { generation: 0, offset: 6 }));
+
+        for line in ["second", "third", "fourth"] {
+            queue.append(line).unwrap();
+        }
+        fs::write(queue.path(), format!("{}half", fs::read_to_string(queue.path()).unwrap())).unwrap();
+        let batch = queue.read_from(batch.cursor).unwrap();
+        assert_eq!(batch.lines, ["second", "third", "fourth"]);
+        assert_eq!(batch.cursor.generation, 1);
+        assert_eq!(queue.read_from(batch.cursor).unwrap().lines, Vec::<String>::new(), "the half line waits for its newline");
+
+        assert_eq!(QueueCursor::parse(&batch.cursor.to_state()), Some(batch.cursor));
+        assert_eq!(QueueCursor::parse(" 42 "), Some(QueueCursor { generation: 0, offset: 42 }));
+        assert_eq!(QueueCursor::parse("1:x"), None);
+    }
+
+    #[test]
+    fn long_lines_are_cut_with_a_marker_when_written_and_read() {
+        let dir = temp_dir("long");
+        let queue = queue(&dir).with_max_line_bytes(8);
+        queue.append("0123456789abcdef").unwrap();
+        assert_eq!(fs::read_to_string(queue.path()).unwrap(), "01234567...[truncated 8 bytes]\n");
+        assert_eq!(cap_line("ééééé", 5), "éé...[truncated 6 bytes]", "never splits a character");
+        assert_eq!(cap_line("short", 8), "short");
+
+        // Another program wrote a long line straight into the file.
+        fs::write(queue.path(), "ok\nthis line is far too long\nfine\n").unwrap();
+        let batch = queue.read_from(QueueCursor::default()).unwrap();
+        assert_eq!(batch.lines, ["ok", "this lin...[truncated 17 bytes]", "fine"]);
+        assert_eq!(batch.truncated, 1);
+    }
+
+    #[test]
+    fn drain_hands_out_every_line_exactly_once_while_a_writer_appends() {
+        let dir = temp_dir("drain");
+        let queue = queue(&dir);
+        let start = Arc::new(Barrier::new(2));
+        let writer = {
+            let (queue, start) = (queue.clone(), Arc::clone(&start));
+            thread::spawn(move || {
+                start.wait();
+                for n in 0..500 {
+                    queue.append(&format!("line {n}")).unwrap();
+                }
+            })
+        };
+        start.wait();
+        let mut seen = Vec::new();
+        while !writer.is_finished() {
+            seen.extend(queue.drain().unwrap());
+        }
+        writer.join().unwrap();
+        seen.extend(queue.drain().unwrap());
+        let expected: Vec<String> = (0..500).map(|n| format!("line {n}")).collect();
+        assert_eq!(seen, expected);
+        assert_eq!(fs::read_to_string(queue.path()).unwrap(), "");
+        assert_eq!(queue.drain().unwrap(), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,222 @@
+//! The outside world behind three small traits: the clock, sleeping, and environment variables.
+//!
+//! Code that calls `SystemTime::now`, `thread::sleep`, or `env::var` directly can only be tried
+//! out by really waiting and by changing the environment of the whole process, which other
+//! threads see too. Functions that care take a `Runtime` instead (often through a `*_with`
+//! variant next to the usual function), and the usual function passes `Runtime::system()`:
+//! - `Clock::now_millis` is milliseconds since 1970. `Clock::instant` is a monotonic `Instant`
+//!   for measuring intervals; the real clock returns `Instant::now()`.
+//! - `Sleeper::sleep` waits.
+//! - `EnvSource::var` reads one variable.
+//!
+//! `SystemClock`, `ThreadSleeper`, and `ProcessEnv` are the real ones. `ManualClock` and `MapEnv`
+//! are stand-ins: a `ManualClock` only moves when told to, and sleeping on it moves it forward
+//! instead of waiting, so a loop that "sleeps" for an hour finishes at once. `MapEnv` answers from
+//! its own list and never touches the process environment.
+//!
+//! The hub, Squire, Sentry, and Bard share these traits through `ecosystem-common`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::env;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Where the time comes from.
+pub trait Clock {
+    /// Milliseconds since 1970-01-01 UTC.
+    fn now_millis(&self) -> u128;
+
+    /// A point on a clock that never goes backwards, for measuring how long something took.
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// How to wait.
+pub trait Sleeper {
+    fn sleep(&self, duration: Duration);
+}
+
+/// Where environment variables come from.
+pub trait EnvSource {
+    /// The variable's value, or `None` when it is unset or not valid UTF-8.
+    fn var(&self, name: &str) -> Option<String>;
+}
+
+/// The computer's clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u128 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0)
+    }
+}
+
+/// Really waits, with `thread::sleep`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadSleeper;
+
+impl Sleeper for ThreadSleeper {
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// The process environment, with `env::var`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessEnv;
+
+impl EnvSource for ProcessEnv {
+    fn var(&self, name: &str) -> Option<String> {
+        env::var(name).ok()
+    }
+}
+
+/// A clock that stands still until `advance` or `sleep` moves it.
+#[derive(Debug)]
+pub struct ManualClock {
+    start_millis: u128,
+    base: Instant,
+    elapsed: Cell<Duration>,
+    slept: RefCell<Vec<Duration>>,
+}
+
+impl ManualClock {
+    /// A clock showing `start_millis` (milliseconds since 1970).
+    pub fn new(start_millis: u128) -> Self {
+        Self { start_millis, base: Instant::now(), elapsed: Cell::new(Duration::ZERO), slept: RefCell::new(Vec::new()) }
+    }
+
+    /// Move the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed.set(self.elapsed.get() + duration);
+    }
+
+    /// Every `sleep` asked of this clock so far, in order.
+    pub fn slept(&self) -> Vec<Duration> {
+        self.slept.borrow().clone()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u128 {
+        self.start_millis + self.elapsed.get().as_millis()
+    }
+
+    fn instant(&self) -> Instant {
+        self.base + self.elapsed.get()
+    }
+}
+
+impl Sleeper for ManualClock {
+    fn sleep(&self, duration: Duration) {
+        self.slept.borrow_mut().push(duration);
+        self.advance(duration);
+    }
+}
+
+/// Environment variables from a list instead of the process.
+#[derive(Clone, Debug, Default)]
+pub struct MapEnv {
+    vars: HashMap<String, String>,
+}
+
+impl MapEnv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace one variable, builder style: `MapEnv::new().with("NAME", "value")`.
+    pub fn with(mut self, name: &str, value: &str) -> Self {
+        self.set(name, value);
+        self
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.vars.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+}
+
+impl EnvSource for MapEnv {
+    fn var(&self, name: &str) -> Option<String> {
+        self.vars.get(name).cloned()
+    }
+}
+
+/// The three together, handed to code that needs any of them.
+#[derive(Clone, Copy)]
+pub struct Runtime<'a> {
+    pub clock: &'a dyn Clock,
+    pub sleeper: &'a dyn Sleeper,
+    pub env: &'a dyn EnvSource,
+}
+
+impl Runtime<'static> {
+    /// The real clock, real sleeping, and the process environment.
+    pub const fn system() -> Self {
+        Runtime { clock: &SystemClock, sleeper: &ThreadSleeper, env: &ProcessEnv }
+    }
+}
+
+impl Default for Runtime<'static> {
+    fn default() -> Self {
+        Self::system()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_manual_clock_moves_only_when_told() {
+        let clock = ManualClock::new(1_700_000_000_000);
+        let (millis, instant) = (clock.now_millis(), clock.instant());
+        assert_eq!((clock.now_millis(), clock.instant()), (millis, instant));
+
+        clock.advance(Duration::from_millis(1_500));
+        assert_eq!(clock.now_millis(), 1_700_000_001_500);
+        assert_eq!(clock.instant() - instant, Duration::from_millis(1_500));
+        assert!(clock.slept().is_empty(), "advancing is not sleeping");
+
+        // Sleeping on it returns at once and moves it forward.
+        let sleeper: &dyn Sleeper = &clock;
+        sleeper.sleep(Duration::from_secs(3_600));
+        sleeper.sleep(Duration::ZERO);
+        assert_eq!(clock.slept(), [Duration::from_secs(3_600), Duration::ZERO]);
+        assert_eq!(clock.now_millis(), 1_700_003_601_500);
+    }
+
+    #[test]
+    fn a_map_env_answers_from_its_own_list() {
+        let mut env = MapEnv::new().with("SQUIRE_TEST_NAME", "one").with("SQUIRE_TEST_NAME", "two").with("SQUIRE_TEST_EMPTY", "");
+        assert_eq!(env.var("SQUIRE_TEST_NAME").as_deref(), Some("two"));
+        assert_eq!(env.var("SQUIRE_TEST_EMPTY").as_deref(), Some(""), "set but empty is not unset");
+        env.remove("SQUIRE_TEST_NAME");
+        assert_eq!(env.var("SQUIRE_TEST_NAME"), None);
+        // `PATH` is set for the test process, but a `MapEnv` never looks there.
+        assert_eq!(MapEnv::new().var("PATH"), None);
+        assert_eq!(ProcessEnv.var("PATH"), env::var("PATH").ok());
+    }
+
+    #[test]
+    fn a_runtime_bundles_fakes_or_the_real_thing() {
+        let clock = ManualClock::new(42);
+        let env = MapEnv::new().with("SQUIRE_TEST_NAME", "fake");
+        let rt = Runtime { clock: &clock, sleeper: &clock, env: &env };
+        rt.sleeper.sleep(Duration::from_millis(8));
+        assert_eq!(rt.clock.now_millis(), 50);
+        assert_eq!(rt.env.var("SQUIRE_TEST_NAME").as_deref(), Some("fake"));
+
+        let system = Runtime::default();
+        let before = system.clock.instant();
+        assert!(system.clock.now_millis() > 1_600_000_000_000);
+        assert!(system.clock.instant() >= before);
+    }
+}
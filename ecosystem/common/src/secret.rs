@@ -0,0 +1,34 @@
+//! Bytes that are overwritten with zeros when dropped.
+//!
+//! Squire holds its bot token in one and Sentry its signing keys, so neither lingers in memory
+//! after use. The vault hands back every decrypted secret the same way.
+
+use std::fmt;
+
+/// Bytes that are zeroed when dropped. `Debug` never shows them.
+#[derive(Clone)]
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.0.fill(0);
+        // Tell the optimizer the zeros are used, so it cannot skip writing them.
+        std::hint::black_box(&self.0);
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes([redacted; {}])", self.0.len())
+    }
+}
@@ -0,0 +1,281 @@
+//! Startup self-check: does the running executable match the Sentry manifest for this deployment?
+//!
+//! Sentry verifies binaries from the outside, on a schedule. The moment that matters most is
+//! process start, so binaries that set `SQUIRE_MANIFEST` to a Sentry `manifest.txt` also check
+//! themselves before doing any work:
+//! 1. hash the file `std::env::current_exe()` points at, the same way `sentry-omega build` does;
+//! 2. find the manifest entries with the same file name (a trailing `.exe` is ignored, so a
+//!    Windows build matches a manifest written on Linux and the other way round; when entries in
+//!    different folders share the name, any of them may match);
+//! 3. report `Match`, `Mismatch`, or `NotListed`.
+//!
+//! `enforce_at_startup` turns that into a policy: a mismatch stops the process with exit code 7
+//! unless `SQUIRE_ALLOW_UNVERIFIED=1`, so a swapped or patched binary cannot quietly start. A
+//! binary that is not listed only logs a warning, because not every deployment ships every binary
+//! through Sentry.
+//!
+//! The hub, Squire, and Sentry share this module through `ecosystem-common`. Sentry records
+//! its manifest hashes with `manifest_hash` and `ManifestHasher` from here, so the two sides
+//! cannot drift apart.
+
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::process;
+
+use crate::log::Logger;
+use crate::runtime::{EnvSource, ProcessEnv};
+
+/// Path of the Sentry `manifest.txt` to check against. Unset means no self-check.
+pub const MANIFEST_ENV: &str = "SQUIRE_MANIFEST";
+/// Set to `1` to start anyway after a mismatch (for a deliberate hotfix, say).
+pub const ALLOW_UNVERIFIED_ENV: &str = "SQUIRE_ALLOW_UNVERIFIED";
+/// Exit code of a process that refused to start because it failed the self-check.
+pub const MISMATCH_EXIT_CODE: i32 = 7;
+
+const LOG: Logger = Logger::new("self-verify");
+
+/// What the self-check found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SelfVerify {
+    /// The manifest lists this file name with this hash.
+    Match { name: String, hash: String },
+    /// The manifest lists this file name with a different hash.
+    Mismatch { name: String, expected: String, observed: String },
+    /// No manifest entry has this file name.
+    NotListed { name: String, observed: String },
+}
+
+/// Check the running executable against the manifest at `manifest_path`.
+pub fn verify_self(manifest_path: &Path) -> Result<SelfVerify, String> {
+    let exe = env::current_exe().map_err(|err| format!("Unable to find the running executable: {err}"))?;
+    verify_exe(&exe, manifest_path)
+}
+
+/// Check any executable `exe` against the manifest; `verify_self` passes its own path.
+pub fn verify_exe(exe: &Path, manifest_path: &Path) -> Result<SelfVerify, String> {
+    let data = fs::read(exe).map_err(|err| format!("Unable to read {:?}: {err}", exe))?;
+    let observed = manifest_hash(&data);
+    let name = exe.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let manifest = fs::read_to_string(manifest_path).map_err(|err| format!("Unable to read manifest {:?}: {err}", manifest_path))?;
+
+    // Entries in different folders may share a file name; any of them matching is enough.
+    let wanted = strip_exe(&name);
+    let listed: Vec<&str> = manifest_entries(&manifest)
+        .filter(|(entry_name, _)| strip_exe(entry_name).eq_ignore_ascii_case(wanted))
+        .map(|(_, hash)| hash)
+        .collect();
+    Ok(match listed.first() {
+        None => SelfVerify::NotListed { name, observed },
+        Some(_) if listed.iter().any(|expected| expected.eq_ignore_ascii_case(&observed)) => SelfVerify::Match { name, hash: observed },
+        Some(expected) => SelfVerify::Mismatch { name, expected: expected.to_string(), observed },
+    })
+}
+
+/// Run the self-check when `SQUIRE_MANIFEST` is set, log the outcome, and exit with
+/// `MISMATCH_EXIT_CODE` on a mismatch (or an unreadable manifest) unless
+/// `SQUIRE_ALLOW_UNVERIFIED=1`. Returns normally whenever the process may go on.
+pub fn enforce_at_startup() {
+    if !may_start(&ProcessEnv, verify_self) {
+        process::exit(MISMATCH_EXIT_CODE);
+    }
+}
+
+/// The policy behind `enforce_at_startup`, with the variables read from `env` and the manifest
+/// handed to `check` (`verify_self` there). Logs the outcome and returns `false` when the process
+/// must stop.
+pub fn may_start(env: &dyn EnvSource, check: impl FnOnce(&Path) -> Result<SelfVerify, String>) -> bool {
+    let Some(manifest_path) = env.var(MANIFEST_ENV).filter(|path| !path.is_empty()) else {
+        return true;
+    };
+    let manifest_shown = Path::new(&manifest_path).display().to_string();
+    let problem = match check(Path::new(&manifest_path)) {
+        Ok(SelfVerify::Match { name, hash }) => {
+            LOG.info("Executable matches the manifest", &[("name", &name), ("hash", &hash), ("manifest", &manifest_shown)]);
+            return true;
+        }
+        Ok(SelfVerify::NotListed { name, observed }) => {
+            LOG.warn(
+                "Executable is not listed in the manifest; starting unverified",
+                &[("name", &name), ("hash", &observed), ("manifest", &manifest_shown)],
+            );
+            return true;
+        }
+        Ok(SelfVerify::Mismatch { name, expected, observed }) => {
+            LOG.error(
+                "Executable does not match the manifest",
+                &[("name", &name), ("expected", &expected), ("observed", &observed), ("manifest", &manifest_shown)],
+            );
+            "hash mismatch"
+        }
+        Err(err) => {
+            LOG.error("Self-check failed", &[("error", &err), ("manifest", &manifest_shown)]);
+            "self-check failed"
+        }
+    };
+
+    if env.var(ALLOW_UNVERIFIED_ENV).is_some_and(|value| value.trim() == "1") {
+        LOG.warn("Starting anyway because SQUIRE_ALLOW_UNVERIFIED=1", &[("problem", problem)]);
+        return true;
+    }
+    LOG.error(
+        "Refusing to start; set SQUIRE_ALLOW_UNVERIFIED=1 to override",
+        &[("problem", problem), ("exit_code", &MISMATCH_EXIT_CODE.to_string())],
+    );
+    false
+}
+
+/// The hash `sentry-omega build` records for a file's bytes. `DefaultHasher` is SipHash with
+/// fixed keys: not cryptographic, but the same for the same bytes within one toolchain, which is
+/// why every copy of this file must build with the workspace's toolchain.
+pub fn manifest_hash(data: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// `manifest_hash` fed in pieces, so a large file can be hashed through a small buffer. The hash
+/// of a byte slice starts with its length, so the total has to be known up front; `finish` gives
+/// `None` when the bytes fed do not add up to it (the file changed while it was read).
+pub struct ManifestHasher {
+    hasher: DefaultHasher,
+    expected_len: u64,
+    fed: u64,
+}
+
+impl ManifestHasher {
+    /// Start hashing a file of `len` bytes.
+    pub fn new(len: u64) -> Self {
+        let mut hasher = DefaultHasher::new();
+        // What `<[u8] as Hash>::hash` writes before the bytes themselves.
+        hasher.write_usize(len as usize);
+        Self { hasher, expected_len: len, fed: 0 }
+    }
+
+    /// Feed the next piece of the file.
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.write(data);
+        self.fed += data.len() as u64;
+    }
+
+    /// The same text `manifest_hash` gives for all the bytes fed, if there were `len` of them.
+    pub fn finish(self) -> Option<String> {
+        (self.fed == self.expected_len).then(|| format!("{:016x}", self.hasher.finish()))
+    }
+}
+
+/// `(name, hash)` of every entry line (`name|path|hash|size[|key=value...]`). Header lines are
+/// `key=value` with no `|` before the `=`, so they are skipped.
+fn manifest_entries(manifest: &str) -> impl Iterator<Item = (&str, &str)> {
+    manifest.lines().filter_map(|line| {
+        let bar = line.find('|')?;
+        if line.find('=').is_some_and(|equals| equals < bar) {
+            return None;
+        }
+        let mut parts = line.split('|');
+        let name = parts.next()?;
+        let _path = parts.next()?;
+        let hash = parts.next()?;
+        Some((name, hash))
+    })
+}
+
+/// `squire-gateway.exe` and `squire-gateway` name the same binary.
+fn strip_exe(name: &str) -> &str {
+    match name.len().checked_sub(4) {
+        Some(cut) if name.is_char_boundary(cut) && name[cut..].eq_ignore_ascii_case(".exe") => &name[..cut],
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::MapEnv;
+
+    #[test]
+    fn manifest_hasher_matches_manifest_hash_for_any_chunking() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        for len in [0, 1, 63, 4096, data.len()] {
+            let data = &data[..len];
+            for chunk in [1, 7, 1000, 65_536] {
+                let mut hasher = ManifestHasher::new(len as u64);
+                data.chunks(chunk).for_each(|piece| hasher.update(piece));
+                assert_eq!(hasher.finish().as_deref(), Some(manifest_hash(data).as_str()), "len {len} chunk {chunk}");
+            }
+        }
+    }
+
+    #[test]
+    fn manifest_hasher_refuses_a_length_that_does_not_add_up() {
+        let mut short = ManifestHasher::new(10);
+        short.update(b"123456789");
+        assert_eq!(short.finish(), None);
+        let mut long = ManifestHasher::new(2);
+        long.update(b"abc");
+        assert_eq!(long.finish(), None);
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("ecosystem-self-verify-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A copy of the test binary as `<dir>/squire-gateway`, and a manifest listing `listed_as`
+    /// with the copy's hash.
+    fn copy_and_manifest(dir: &Path, listed_as: &str) -> (std::path::PathBuf, std::path::PathBuf) {
+        let exe = dir.join("squire-gateway");
+        fs::copy(env::current_exe().unwrap(), &exe).unwrap();
+        let data = fs::read(&exe).unwrap();
+        let manifest = dir.join("manifest.txt");
+        let text = format!(
+            "release_id=r1\nmode=blue\nentries:\nbard|bins/bard|0000000000000000|4\n{listed_as}|bins/{listed_as}|{}|{}|mode=0755\nsignature_note=x\n",
+            manifest_hash(&data),
+            data.len()
+        );
+        fs::write(&manifest, text).unwrap();
+        (exe, manifest)
+    }
+
+    #[test]
+    fn a_copied_binary_matches_until_it_is_tampered_with() {
+        let dir = temp_dir("match");
+        let (exe, manifest) = copy_and_manifest(&dir, "squire-gateway.exe");
+        let hash = manifest_hash(&fs::read(&exe).unwrap());
+        assert_eq!(verify_exe(&exe, &manifest).unwrap(), SelfVerify::Match { name: "squire-gateway".to_string(), hash: hash.clone() });
+
+        let mut data = fs::read(&exe).unwrap();
+        data.push(0);
+        fs::write(&exe, &data).unwrap();
+        assert_eq!(
+            verify_exe(&exe, &manifest).unwrap(),
+            SelfVerify::Mismatch { name: "squire-gateway".to_string(), expected: hash, observed: manifest_hash(&data) }
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn an_unlisted_binary_starts_but_a_mismatch_needs_the_override() {
+        let dir = temp_dir("policy");
+        let (exe, manifest) = copy_and_manifest(&dir, "hub");
+        assert!(matches!(verify_exe(&exe, &manifest).unwrap(), SelfVerify::NotListed { .. }));
+        let with_manifest = MapEnv::new().with(MANIFEST_ENV, manifest.to_str().unwrap());
+        assert!(may_start(&with_manifest, |path| verify_exe(&exe, path)));
+
+        let (tampered, manifest) = copy_and_manifest(&dir, "squire-gateway");
+        fs::write(&tampered, b"patched").unwrap();
+        let with_manifest = MapEnv::new().with(MANIFEST_ENV, manifest.to_str().unwrap());
+        assert!(!may_start(&with_manifest, |path| verify_exe(&tampered, path)));
+        assert!(may_start(&with_manifest.clone().with(ALLOW_UNVERIFIED_ENV, "1"), |path| verify_exe(&tampered, path)));
+        // An unreadable manifest counts as a failed check too.
+        let missing = MapEnv::new().with(MANIFEST_ENV, dir.join("absent.txt").to_str().unwrap());
+        assert!(!may_start(&missing, |path| verify_exe(&tampered, path)));
+        // Without SQUIRE_MANIFEST nothing is checked.
+        assert!(may_start(&MapEnv::new(), |_| panic!("no manifest, no check")));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
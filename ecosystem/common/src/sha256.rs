@@ -0,0 +1,303 @@
+//! SHA-256 written out by hand so the crate keeps its "standard library only" promise.
+//!
+//! The algorithm follows FIPS 180-4 step by step: pad the message, split it into 64-byte blocks,
+//! expand each block into 64 words, and mix those words into eight running state values. The
+//! `Sha256` struct lets callers feed data in pieces (useful for large files), while `sha256` and
+//! `sha256_hex` cover the common "hash this slice" case. `hmac_sha256` builds the keyed variant
+//! used for per-file signatures on top of it.
+
+/// Round constants: the first 32 bits of the fractional parts of the cube roots of the first 64
+/// primes. They are fixed by the standard; every SHA-256 implementation uses the same table.
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Starting state: the first 32 bits of the fractional parts of the square roots of the first
+/// eight primes.
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Size of one SHA-256 block in bytes.
+pub const BLOCK_LEN: usize = 64;
+/// Size of a finished SHA-256 digest in bytes.
+pub const DIGEST_LEN: usize = 32;
+
+/// Incremental SHA-256 hasher. Call `update` as many times as needed, then `finalize` once.
+#[derive(Clone, Debug)]
+pub struct Sha256 {
+    state: [u32; 8],
+    /// Bytes waiting for a full 64-byte block.
+    buffer: [u8; BLOCK_LEN],
+    buffer_len: usize,
+    /// Total number of message bytes seen so far (needed for the final padding).
+    total_len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    /// Start a fresh hash.
+    pub fn new() -> Self {
+        Self {
+            state: INITIAL_STATE,
+            buffer: [0u8; BLOCK_LEN],
+            buffer_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Feed more bytes into the hash. Splitting data across calls gives the same result as one
+    /// big call.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+
+        // Top up a partially filled buffer first.
+        if self.buffer_len > 0 {
+            let take = (BLOCK_LEN - self.buffer_len).min(data.len());
+            self.buffer[self.buffer_len..self.buffer_len + take].copy_from_slice(&data[..take]);
+            self.buffer_len += take;
+            data = &data[take..];
+            if self.buffer_len == BLOCK_LEN {
+                let block = self.buffer;
+                self.compress(&block);
+                self.buffer_len = 0;
+            }
+        }
+
+        // Process whole blocks straight from the input.
+        while data.len() >= BLOCK_LEN {
+            let mut block = [0u8; BLOCK_LEN];
+            block.copy_from_slice(&data[..BLOCK_LEN]);
+            self.compress(&block);
+            data = &data[BLOCK_LEN..];
+        }
+
+        // Keep the leftovers for the next call.
+        if !data.is_empty() {
+            self.buffer[..data.len()].copy_from_slice(data);
+            self.buffer_len = data.len();
+        }
+    }
+
+    /// Finish the hash and return the 32-byte digest.
+    pub fn finalize(mut self) -> [u8; DIGEST_LEN] {
+        let bit_len = self.total_len.wrapping_mul(8);
+
+        // Padding: a single 1 bit, then zeros until 8 bytes remain in the block, then the
+        // message length in bits as a big-endian 64-bit number.
+        let mut padding = vec![0x80u8];
+        let used = (self.buffer_len + 1) % BLOCK_LEN;
+        let zeros = if used <= BLOCK_LEN - 8 { BLOCK_LEN - 8 - used } else { 2 * BLOCK_LEN - 8 - used };
+        padding.extend(std::iter::repeat_n(0u8, zeros));
+        padding.extend_from_slice(&bit_len.to_be_bytes());
+
+        // `update` would also bump total_len, which no longer matters once the length is fixed.
+        self.update(&padding);
+
+        let mut digest = [0u8; DIGEST_LEN];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    /// Mix one 64-byte block into the running state.
+    fn compress(&mut self, block: &[u8; BLOCK_LEN]) {
+        // Message schedule: 16 words straight from the block, 48 more derived from them.
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choose = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choose)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (slot, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *slot = slot.wrapping_add(value);
+        }
+    }
+}
+
+/// Hash a byte slice in one call.
+pub fn sha256(data: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Hash a byte slice and return lowercase hex, the form written into manifests and logs.
+pub fn sha256_hex(data: &[u8]) -> String {
+    to_hex(&sha256(data))
+}
+
+/// HMAC-SHA256 (RFC 2104): a keyed hash. Only someone holding `key` can produce the same tag, so
+/// a matching tag shows the data was signed by a key holder and not changed since.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; DIGEST_LEN] {
+    // HMAC pads the key to one block: longer keys are hashed first, shorter ones get zeros.
+    let mut block = [0u8; BLOCK_LEN];
+    if key.len() > BLOCK_LEN {
+        block[..DIGEST_LEN].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(&block.map(|byte| byte ^ 0x36));
+    inner.update(message);
+    let inner_digest = inner.finalize();
+
+    let mut outer = Sha256::new();
+    outer.update(&block.map(|byte| byte ^ 0x5c));
+    outer.update(&inner_digest);
+    outer.finalize()
+}
+
+/// Compare two byte strings in time that does not depend on where they first differ, so a
+/// caller checking a tag leaks nothing about how close a guess was.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Render bytes as lowercase hex.
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        output.push_str(&format!("{:02x}", byte));
+    }
+    output
+}
+
+/// Parse hex text (either case) back into bytes. Returns `None` for odd lengths or non-hex
+/// characters so callers can report a malformed value instead of panicking.
+pub fn from_hex(text: &str) -> Option<Vec<u8>> {
+    // `from_str_radix` would accept a leading `+`, so check the characters explicitly.
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(text.len() / 2);
+    for chunk in text.as_bytes().chunks(2) {
+        let pair = std::str::from_utf8(chunk).ok()?;
+        bytes.push(u8::from_str_radix(pair, 16).ok()?);
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        from_hex(text).expect("test vector is hex")
+    }
+
+    #[test]
+    fn sha256_matches_fips_180_examples() {
+        assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn streaming_in_uneven_pieces_matches_one_call() {
+        let data = vec![b'a'; 1_000_000];
+        let mut hasher = Sha256::new();
+        for piece in data.chunks(997) {
+            hasher.update(piece);
+        }
+        let digest = hasher.finalize();
+        assert_eq!(digest, sha256(&data));
+        assert_eq!(to_hex(&digest), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        let long_key = vec![0xaa; 131];
+        let cases: [(Vec<u8>, Vec<u8>, &str); 6] = [
+            (vec![0x0b; 20], b"Hi There".to_vec(), "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+            (
+                b"Jefe".to_vec(),
+                b"what do ya want for nothing?".to_vec(),
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (vec![0xaa; 20], vec![0xdd; 50], "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"),
+            (
+                hex("0102030405060708090a0b0c0d0e0f10111213141516171819"),
+                vec![0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            (
+                long_key.clone(),
+                b"Test Using Larger Than Block-Size Key - Hash Key First".to_vec(),
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                long_key,
+                b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.".to_vec(),
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+        for (key, message, expected) in cases {
+            assert_eq!(to_hex(&hmac_sha256(&key, &message)), expected);
+        }
+        // Test case 5 checks only the first 128 bits.
+        let truncated = hmac_sha256(&[0x0c; 20], b"Test With Truncation");
+        assert_eq!(to_hex(&truncated[..16]), "a3b6167473100ee06e0c796c2955552b");
+    }
+
+    #[test]
+    fn constant_time_eq_needs_same_length_and_bytes() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn hex_round_trips_and_rejects_bad_text() {
+        assert_eq!(from_hex("00ffA0"), Some(vec![0x00, 0xff, 0xa0]));
+        assert_eq!(to_hex(&[0x00, 0xff, 0xa0]), "00ffa0");
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("+1"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
@@ -0,0 +1,211 @@
+//! Discord ids ("snowflakes"), checked once instead of by hand in every module.
+//!
+//! A snowflake is a 64-bit number that Discord writes out as a decimal string, such as
+//! `175928847299117063`. Its bits are not random; from the highest to the lowest:
+//!
+//! ```text
+//! | 42 bits: milliseconds since DISCORD_EPOCH_MILLIS | 10 bits: worker | 12 bits: sequence |
+//! ```
+//!
+//! So the id alone says when the thing it names was created, and sorting ids sorts by creation
+//! time. `Snowflake::parse` accepts what Discord actually hands out: digits only, no leading zero,
+//! and a value between `MIN_DISCORD_ID` (the smallest 17-digit number; real ids have had 17 digits
+//! or more since 2015) and `u64::MAX` (20 digits). Anything else gets a `SnowflakeError` that says
+//! which rule it broke.
+//!
+//! `generate_local` makes ids for things that exist only here (queue entries, audit records) with
+//! the same bit layout, counted from `LOCAL_EPOCH_MILLIS` instead of Discord's epoch. Ids from one
+//! process always increase, even when several are made in the same millisecond. Local ids are
+//! never sent to Discord, so their timestamp is read with `local_timestamp_millis`.
+//!
+//! The hub and Squire share this module through `ecosystem-common`.
+
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::runtime::{Clock, SystemClock};
+
+/// Discord's epoch, 2015-01-01 00:00:00 UTC, in Unix milliseconds.
+pub const DISCORD_EPOCH_MILLIS: u64 = 1_420_070_400_000;
+/// The epoch of `generate_local`, 2026-01-01 00:00:00 UTC, in Unix milliseconds.
+pub const LOCAL_EPOCH_MILLIS: u64 = 1_767_225_600_000;
+/// The smallest id `parse` accepts: 17 digits.
+pub const MIN_DISCORD_ID: u64 = 10_000_000_000_000_000;
+/// Largest worker number that fits in the 10 worker bits.
+pub const MAX_WORKER_ID: u16 = 0x3FF;
+
+const TIMESTAMP_SHIFT: u32 = 22;
+const WORKER_SHIFT: u32 = 12;
+const SEQUENCE_BITS: u32 = 12;
+
+/// Why a string is not a Discord id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnowflakeError {
+    Empty,
+    /// Something other than `0`-`9`, including spaces and signs.
+    NotDigits,
+    /// `0123...`: Discord never writes ids that way, so it is probably a typo or a padded number.
+    LeadingZero,
+    /// Fewer than 17 digits, or more than fits in 64 bits.
+    OutOfRange,
+}
+
+impl fmt::Display for SnowflakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SnowflakeError::Empty => "is empty",
+            SnowflakeError::NotDigits => "must contain only the digits 0-9",
+            SnowflakeError::LeadingZero => "must not start with 0",
+            SnowflakeError::OutOfRange => "must be 17 to 20 digits and fit in 64 bits",
+        })
+    }
+}
+
+/// A Discord id, or a local id with the same layout. Ordered like the number, which is the order
+/// of creation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Snowflake(pub u64);
+
+impl Snowflake {
+    /// Read a Discord id written as a decimal string.
+    pub fn parse(text: &str) -> Result<Snowflake, SnowflakeError> {
+        if text.is_empty() {
+            return Err(SnowflakeError::Empty);
+        }
+        if !text.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(SnowflakeError::NotDigits);
+        }
+        if text.starts_with('0') {
+            return Err(SnowflakeError::LeadingZero);
+        }
+        // Only digits are left, so the only way `parse` fails is a value above `u64::MAX`.
+        match text.parse::<u64>() {
+            Ok(value) if value >= MIN_DISCORD_ID => Ok(Snowflake(value)),
+            _ => Err(SnowflakeError::OutOfRange),
+        }
+    }
+
+    /// The number itself.
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    /// When a Discord id was made, in Unix milliseconds.
+    pub fn timestamp_millis(self) -> u64 {
+        (self.0 >> TIMESTAMP_SHIFT) + DISCORD_EPOCH_MILLIS
+    }
+
+    /// When a Discord id was made, in Unix seconds.
+    pub fn created_at_unix(self) -> u64 {
+        self.timestamp_millis() / 1000
+    }
+
+    /// When an id from `generate_local` was made, in Unix milliseconds.
+    pub fn local_timestamp_millis(self) -> u64 {
+        (self.0 >> TIMESTAMP_SHIFT) + LOCAL_EPOCH_MILLIS
+    }
+
+    /// The 10 worker bits.
+    pub fn worker_id(self) -> u16 {
+        ((self.0 >> WORKER_SHIFT) & u64::from(MAX_WORKER_ID)) as u16
+    }
+}
+
+impl fmt::Display for Snowflake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Timestamp and sequence of the last local id, as one number: `(millis << 12) | sequence`.
+static LAST_LOCAL: Mutex<u64> = Mutex::new(0);
+
+/// A new local id for `worker_id` (only its lowest 10 bits are used), stamped with the clock.
+pub fn generate_local(worker_id: u16) -> Snowflake {
+    generate_local_with(worker_id, &SystemClock)
+}
+
+/// `generate_local` stamped with `clock` instead of the system clock.
+pub fn generate_local_with(worker_id: u16, clock: &dyn Clock) -> Snowflake {
+    generate_local_at(worker_id, u64::try_from(clock.now_millis()).unwrap_or(u64::MAX))
+}
+
+/// `generate_local` with the time given, in Unix milliseconds, so a test can hold the clock still.
+///
+/// Each id is larger than the one before it. Within one millisecond the 12-bit sequence counts
+/// up; if it runs out (4096 ids in one millisecond), or the clock steps backwards, the id borrows
+/// the next millisecond instead of repeating or going down.
+pub fn generate_local_at(worker_id: u16, now_unix_millis: u64) -> Snowflake {
+    let millis = now_unix_millis.saturating_sub(LOCAL_EPOCH_MILLIS);
+    // A poisoned lock only means another thread panicked mid-update; the number inside is still usable.
+    let mut last = LAST_LOCAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let candidate = millis << SEQUENCE_BITS;
+    *last = if candidate > *last { candidate } else { *last + 1 };
+    let (millis, sequence) = (*last >> SEQUENCE_BITS, *last & ((1 << SEQUENCE_BITS) - 1));
+    Snowflake((millis << TIMESTAMP_SHIFT) | (u64::from(worker_id & MAX_WORKER_ID) << WORKER_SHIFT) | sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::ManualClock;
+    use std::time::Duration;
+
+    #[test]
+    fn a_real_id_decodes_to_its_date() {
+        // Discord's documentation example: 2016-04-30 11:18:25.796 UTC, internal worker 1 and
+        // process 0, which the 10 worker bits hold as 1 << 5.
+        let id = Snowflake::parse("175928847299117063").unwrap();
+        assert_eq!(id.get(), 175_928_847_299_117_063);
+        assert_eq!(id.timestamp_millis(), 1_462_015_105_796);
+        assert_eq!(id.created_at_unix(), 1_462_015_105);
+        assert_eq!(id.worker_id(), 32);
+        assert_eq!(id.to_string(), "175928847299117063");
+        assert_eq!(Snowflake(0).timestamp_millis(), DISCORD_EPOCH_MILLIS);
+    }
+
+    #[test]
+    fn parse_names_the_rule_that_failed() {
+        assert_eq!(Snowflake::parse(""), Err(SnowflakeError::Empty));
+        for text in [" 175928847299117063", "175928847299117063\n", "-175928847299117063", "+175928847299117063", "1759288472991170e3", "１７５"] {
+            assert_eq!(Snowflake::parse(text), Err(SnowflakeError::NotDigits), "{text:?}");
+        }
+        assert_eq!(Snowflake::parse("0175928847299117063"), Err(SnowflakeError::LeadingZero));
+        assert_eq!(Snowflake::parse("0"), Err(SnowflakeError::LeadingZero));
+        assert_eq!(Snowflake::parse("9999999999999999"), Err(SnowflakeError::OutOfRange), "16 digits");
+        assert_eq!(Snowflake::parse("18446744073709551616"), Err(SnowflakeError::OutOfRange), "u64::MAX + 1");
+        assert_eq!(Snowflake::parse("10000000000000000"), Ok(Snowflake(MIN_DISCORD_ID)));
+        assert_eq!(Snowflake::parse("18446744073709551615"), Ok(Snowflake(u64::MAX)));
+        assert_eq!(SnowflakeError::OutOfRange.to_string(), "must be 17 to 20 digits and fit in 64 bits");
+    }
+
+    #[test]
+    fn ordering_follows_creation_time() {
+        let ids = ["175928847299117063", "81384788765712384", "1180000000000000000", "175928847299117064"];
+        let mut parsed: Vec<Snowflake> = ids.iter().map(|id| Snowflake::parse(id).unwrap()).collect();
+        parsed.sort();
+        let times: Vec<u64> = parsed.iter().map(|id| id.timestamp_millis()).collect();
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]), "{times:?}");
+        assert_eq!(parsed[0].to_string(), "81384788765712384");
+    }
+
+    #[test]
+    fn local_ids_keep_increasing_on_a_still_or_backward_clock() {
+        let clock = ManualClock::new(u128::from(LOCAL_EPOCH_MILLIS) + 86_400_000);
+        // Other tests share the process-wide counter, so only the order and bounds are checked.
+        let ids: Vec<Snowflake> = (0..5_000).map(|_| generate_local_with(7, &clock)).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]), "5000 ids in one millisecond overflow the sequence");
+        assert!(ids.iter().all(|id| id.worker_id() == 7));
+        assert!(ids[0].local_timestamp_millis() >= LOCAL_EPOCH_MILLIS + 86_400_000);
+
+        let last = *ids.last().unwrap();
+        let earlier = generate_local_at(7, LOCAL_EPOCH_MILLIS);
+        assert!(earlier > last, "a clock stepping back still gives a larger id");
+        clock.advance(Duration::from_secs(3_600));
+        let later = generate_local_with(MAX_WORKER_ID + 1, &clock);
+        assert!(later > earlier);
+        assert_eq!(later.worker_id(), 0, "only the lowest 10 worker bits are kept");
+        assert!(later.local_timestamp_millis() >= LOCAL_EPOCH_MILLIS + 86_400_000 + 3_600_000);
+        assert!(generate_local(1) > Snowflake(0));
+    }
+}
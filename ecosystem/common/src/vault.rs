@@ -0,0 +1,674 @@
+//! Opening vault envelopes in Rust.
+//!
+//! Squire's Python vault (`squire/python/crypto/secrets.py`) encrypts a secret into an
+//! `EncryptedSecret` envelope: a JSON object with base64 `nonce`, `ciphertext`, and `tag`. This
+//! module is the Rust reader for that format. Squire's gateway uses it to keep its bot token
+//! encrypted on disk (feature `vault`), and Sentry Blue its signing key (feature `vault-keys`);
+//! each decrypts only while it needs the secret.
+//!
+//! The steps mirror `decrypt_secret` in Python exactly:
+//! 1. `nonce` holds 16 bytes of salt followed by the 12-byte ChaCha20 nonce.
+//! 2. The per-envelope key is HKDF-SHA256 of the master key with that salt and the info string
+//!    `squire-aead-key` (`derive_key` in Python).
+//! 3. ChaCha20 block 0 gives the 32-byte Poly1305 one-time key; blocks 1 and up mask the text.
+//! 4. The tag is checked (in constant time) before anything is decrypted.
+//!
+//! The tag follows the Python vault's `_poly1305_aead_tag`, which feeds the padding and the
+//! length block to Poly1305 slightly differently from RFC 8439. The byte-for-byte copy keeps
+//! envelopes written by Python readable here; see the Squire TODO before changing either side.
+//!
+//! Secrets that older Python tooling sealed with libsodium's `crypto_secretbox` (XSalsa20-Poly1305,
+//! through PyNaCl) can be opened too, never written: their envelope says `"format": "secretbox"`,
+//! holds a 24-byte nonce, and uses the master key as it is (32 bytes, no HKDF). `reencrypt-config`
+//! in `config_loader.py` upgrades them to the native format.
+//!
+//! The master key comes from an environment variable as base64, the same way `config_loader.py`
+//! reads its `key_env` variable. Each crate names its own default (`SQUIRE_VAULT_KEY`,
+//! `SENTRY_VAULT_KEY`) in its `vault` module.
+
+use std::fs;
+use std::path::Path;
+
+use crate::json::{self, JsonValue};
+use crate::runtime::{EnvSource, ProcessEnv};
+use crate::secret::SecretBytes;
+use crate::sha256::{constant_time_eq, hmac_sha256};
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// Same info string as the Python vault, so both derive the same key.
+const HKDF_INFO: &[u8] = b"squire-aead-key";
+
+const XSALSA20_NONCE_LEN: usize = 24;
+
+/// Which cipher sealed an envelope: the envelope's `"format"` field.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SecretFormat {
+    /// `"chacha-poly"`, what the Python vault writes. Envelopes without `"format"` are this.
+    #[default]
+    ChaChaPoly,
+    /// `"secretbox"`: libsodium's XSalsa20-Poly1305, read only.
+    SecretboxCompat,
+}
+
+impl SecretFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SecretFormat::ChaChaPoly => "chacha-poly",
+            SecretFormat::SecretboxCompat => "secretbox",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "chacha-poly" => Some(SecretFormat::ChaChaPoly),
+            "secretbox" => Some(SecretFormat::SecretboxCompat),
+            _ => None,
+        }
+    }
+
+    /// How long `EncryptedSecret::nonce` is in this format.
+    fn nonce_len(self) -> usize {
+        match self {
+            SecretFormat::ChaChaPoly => SALT_LEN + NONCE_LEN,
+            SecretFormat::SecretboxCompat => XSALSA20_NONCE_LEN,
+        }
+    }
+}
+
+/// The fields of a Python `EncryptedSecret`, decoded.
+#[derive(Clone, Debug)]
+pub struct EncryptedSecret {
+    pub format: SecretFormat,
+    /// Salt (16 bytes) followed by the ChaCha20 nonce (12 bytes), or the 24-byte secretbox nonce.
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub tag: Vec<u8>,
+}
+
+impl EncryptedSecret {
+    /// Read an envelope file written by `EncryptedSecret.to_storable()`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Unable to read envelope {:?}: {err}", path))?;
+        Self::parse_named(&text, &format!(" {:?}", path))
+    }
+
+    /// `load` for envelope JSON that is already in memory, such as one handed over by `ffi`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        Self::parse_named(text, "")
+    }
+
+    /// `name` is put after "Envelope" in errors: `" <path>"`, or nothing.
+    fn parse_named(text: &str, name: &str) -> Result<Self, String> {
+        let document = json::parse(text).map_err(|err| format!("Envelope{name} is not JSON: {err}"))?;
+        let field = |field: &str| -> Result<Vec<u8>, String> {
+            let raw = document
+                .get(field)
+                .and_then(JsonValue::as_str)
+                .ok_or_else(|| format!("Envelope{name} is missing the \"{field}\" string"))?;
+            base64_decode(raw).ok_or_else(|| format!("Envelope{name} has invalid base64 in \"{field}\""))
+        };
+        let format = match document.get("format") {
+            None => SecretFormat::ChaChaPoly,
+            Some(value) => value.as_str().and_then(SecretFormat::parse).ok_or_else(|| {
+                format!("Envelope{name} has an unknown \"format\"; expected \"chacha-poly\" or \"secretbox\"")
+            })?,
+        };
+        Ok(Self { format, nonce: field("nonce")?, ciphertext: field("ciphertext")?, tag: field("tag")? })
+    }
+
+    /// Fail with a message naming both formats when the nonce does not fit `format`. A missing
+    /// `"format": "secretbox"` would otherwise look like a wrong key.
+    fn check_format(&self) -> Result<(), String> {
+        if self.nonce.len() == self.format.nonce_len() {
+            return Ok(());
+        }
+        let other = match self.format {
+            SecretFormat::ChaChaPoly => SecretFormat::SecretboxCompat,
+            SecretFormat::SecretboxCompat => SecretFormat::ChaChaPoly,
+        };
+        if self.nonce.len() == other.nonce_len() {
+            return Err(format!(
+                "Envelope is marked \"{}\" but its {}-byte nonce is that of a \"{}\" envelope; fix its \"format\" field",
+                self.format.as_str(),
+                self.nonce.len(),
+                other.as_str()
+            ));
+        }
+        Err(format!("Envelope nonce must be {} bytes for the \"{}\" format", self.format.nonce_len(), self.format.as_str()))
+    }
+}
+
+/// The master key, held only as long as the caller keeps it.
+pub struct SecretVault {
+    master: SecretBytes,
+}
+
+impl SecretVault {
+    /// Build a vault from the base64 master key in the environment variable `name`.
+    pub fn from_env_var(name: &str) -> Result<Self, String> {
+        Self::from_env(&ProcessEnv, name)
+    }
+
+    /// `from_env_var`, reading `name` from `env`.
+    pub fn from_env(env: &dyn EnvSource, name: &str) -> Result<Self, String> {
+        let raw = env.var(name).ok_or_else(|| format!("{name} is not set (base64 master key)"))?;
+        let mut master = raw.into_bytes();
+        let decoded = std::str::from_utf8(&master).ok().and_then(|text| base64_decode(text.trim()));
+        // The base64 text is as secret as the key itself, so it is wiped too.
+        drop(SecretBytes::new(std::mem::take(&mut master)));
+        Self::new(SecretBytes::new(decoded.ok_or_else(|| format!("{name} is not valid base64"))?))
+    }
+
+    pub fn new(master: SecretBytes) -> Result<Self, String> {
+        // Python refuses to encrypt with less than 128 bits, so a shorter key cannot be right.
+        if master.expose().len() < 16 {
+            return Err("Vault master key must be at least 16 bytes".to_string());
+        }
+        Ok(Self { master })
+    }
+
+    /// Check the tag and return the plaintext. A wrong master key and a tampered envelope both
+    /// fail here, before any plaintext exists. Both formats are handled; see the module notes.
+    pub fn decrypt(&self, secret: &EncryptedSecret) -> Result<SecretBytes, String> {
+        secret.check_format()?;
+        if secret.format == SecretFormat::SecretboxCompat {
+            return self.open_secretbox(secret);
+        }
+        let (salt, nonce) = secret.nonce.split_at(SALT_LEN);
+        let key = SecretBytes::new(derive_key(self.master.expose(), salt).to_vec());
+        let key: &[u8; 32] = key.expose().try_into().map_err(|_| "derived key has the wrong length".to_string())?;
+        let nonce: &[u8; NONCE_LEN] = nonce.try_into().map_err(|_| "nonce has the wrong length".to_string())?;
+
+        let mut block_zero = chacha20_block(key, 0, nonce);
+        let one_time_key: [u8; 32] = block_zero[..32].try_into().expect("block is 64 bytes");
+        block_zero.fill(0);
+        let expected = vault_tag(&[], &secret.ciphertext, &one_time_key);
+        if secret.tag.len() != TAG_LEN || !constant_time_eq(&expected, &secret.tag) {
+            return Err("Envelope failed authentication: wrong master key or a modified envelope".to_string());
+        }
+
+        let mut plaintext = secret.ciphertext.clone();
+        chacha20_xor(key, 1, nonce, &mut plaintext);
+        Ok(SecretBytes::new(plaintext))
+    }
+
+    /// libsodium's `crypto_secretbox_open`: XSalsa20 keystream bytes 0..32 are the Poly1305 key
+    /// for a plain Poly1305 over the ciphertext; the bytes after them mask the text.
+    fn open_secretbox(&self, secret: &EncryptedSecret) -> Result<SecretBytes, String> {
+        let key: &[u8; 32] = self.master.expose().try_into().map_err(|_| {
+            format!("A \"secretbox\" envelope needs the 32-byte key it was sealed with; this key is {} bytes", self.master.expose().len())
+        })?;
+        let nonce: &[u8; XSALSA20_NONCE_LEN] = secret.nonce.as_slice().try_into().map_err(|_| "nonce has the wrong length".to_string())?;
+        let subkey = SecretBytes::new(hsalsa20(key, nonce[..16].try_into().expect("16 bytes")).to_vec());
+        let subkey: &[u8; 32] = subkey.expose().try_into().expect("HSalsa20 gives 32 bytes");
+        let stream_nonce: &[u8; 8] = nonce[16..].try_into().expect("8 bytes");
+
+        let mut block_zero = salsa20_block(subkey, stream_nonce, 0);
+        let one_time_key: [u8; 32] = block_zero[..32].try_into().expect("block is 64 bytes");
+        let expected = poly1305_mac(&secret.ciphertext, &one_time_key);
+        if secret.tag.len() != TAG_LEN || !constant_time_eq(&expected, &secret.tag) {
+            block_zero.fill(0);
+            return Err("Envelope failed authentication: wrong master key or a modified envelope".to_string());
+        }
+
+        // The text starts 32 bytes into the keystream: the rest of block 0, then blocks 1 and up.
+        let mut plaintext = secret.ciphertext.clone();
+        let (head, tail) = plaintext.split_at_mut(secret.ciphertext.len().min(32));
+        for (byte, key_byte) in head.iter_mut().zip(&block_zero[32..]) {
+            *byte ^= key_byte;
+        }
+        block_zero.fill(0);
+        for (index, chunk) in tail.chunks_mut(64).enumerate() {
+            let mut block = salsa20_block(subkey, stream_nonce, index as u64 + 1);
+            for (byte, key_byte) in chunk.iter_mut().zip(block.iter()) {
+                *byte ^= key_byte;
+            }
+            block.fill(0);
+        }
+        Ok(SecretBytes::new(plaintext))
+    }
+}
+
+/// HKDF-SHA256 with one output block: `Extract(salt, master)` then `Expand(info || 0x01)`.
+fn derive_key(master: &[u8], salt: &[u8]) -> [u8; 32] {
+    let prk = hmac_sha256(salt, master);
+    let mut info = HKDF_INFO.to_vec();
+    info.push(1);
+    hmac_sha256(&prk, &info)
+}
+
+// -- ChaCha20 (RFC 8439 section 2.3) -------------------------------------------------------
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// One 64-byte keystream block: constants, key, counter, and nonce mixed for 20 rounds.
+fn chacha20_block(key: &[u8; 32], counter: u32, nonce: &[u8; NONCE_LEN]) -> [u8; 64] {
+    let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+    for i in 0..8 {
+        state[4 + i] = word(&key[i * 4..]);
+    }
+    state[12] = counter;
+    for i in 0..3 {
+        state[13 + i] = word(&nonce[i * 4..]);
+    }
+
+    let mut working = state;
+    for _ in 0..10 {
+        // Column rounds, then diagonal rounds.
+        quarter_round(&mut working, 0, 4, 8, 12);
+        quarter_round(&mut working, 1, 5, 9, 13);
+        quarter_round(&mut working, 2, 6, 10, 14);
+        quarter_round(&mut working, 3, 7, 11, 15);
+        quarter_round(&mut working, 0, 5, 10, 15);
+        quarter_round(&mut working, 1, 6, 11, 12);
+        quarter_round(&mut working, 2, 7, 8, 13);
+        quarter_round(&mut working, 3, 4, 9, 14);
+    }
+
+    let mut output = [0u8; 64];
+    for i in 0..16 {
+        output[i * 4..i * 4 + 4].copy_from_slice(&working[i].wrapping_add(state[i]).to_le_bytes());
+    }
+    output
+}
+
+/// XOR `data` with the keystream starting at block `counter`.
+fn chacha20_xor(key: &[u8; 32], counter: u32, nonce: &[u8; NONCE_LEN], data: &mut [u8]) {
+    for (index, chunk) in data.chunks_mut(64).enumerate() {
+        let mut block = chacha20_block(key, counter.wrapping_add(index as u32), nonce);
+        for (byte, key_byte) in chunk.iter_mut().zip(block.iter()) {
+            *byte ^= key_byte;
+        }
+        block.fill(0);
+    }
+}
+
+// -- XSalsa20 (libsodium secretbox, opening only) ----------------------------------------
+//
+// Salsa20 is ChaCha20's older sibling: the same add-rotate-XOR mixing, with the words laid out
+// differently (constants on the diagonal) and rotations of 7, 9, 13, and 18.
+
+/// Salsa20's 20 rounds (10 column + 10 row rounds) on a copy of `state`.
+fn salsa20_rounds(state: &[u32; 16]) -> [u32; 16] {
+    let mut x = *state;
+    let mut quarter = |a: usize, b: usize, c: usize, d: usize| {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    };
+    for _ in 0..10 {
+        quarter(0, 4, 8, 12);
+        quarter(5, 9, 13, 1);
+        quarter(10, 14, 2, 6);
+        quarter(15, 3, 7, 11);
+        quarter(0, 1, 2, 3);
+        quarter(5, 6, 7, 4);
+        quarter(10, 11, 8, 9);
+        quarter(15, 12, 13, 14);
+    }
+    x
+}
+
+/// The Salsa20 input: "expand 32-byte k" on the diagonal, the key around it, `middle` in the centre.
+fn salsa20_state(key: &[u8; 32], middle: &[u8; 16]) -> [u32; 16] {
+    let word = |bytes: &[u8], at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    let mut state = [0u32; 16];
+    state[0] = 0x6170_7865;
+    state[5] = 0x3320_646e;
+    state[10] = 0x7962_2d32;
+    state[15] = 0x6b20_6574;
+    for i in 0..4 {
+        state[1 + i] = word(key, i * 4);
+        state[11 + i] = word(key, 16 + i * 4);
+        state[6 + i] = word(middle, i * 4);
+    }
+    state
+}
+
+/// HSalsa20: the XSalsa20 subkey from the key and the first 16 nonce bytes. The rounds run
+/// without the final addition and eight of the words are kept.
+fn hsalsa20(key: &[u8; 32], nonce: &[u8; 16]) -> [u8; 32] {
+    let x = salsa20_rounds(&salsa20_state(key, nonce));
+    let mut output = [0u8; 32];
+    for (slot, index) in [0, 5, 10, 15, 6, 7, 8, 9].into_iter().enumerate() {
+        output[slot * 4..slot * 4 + 4].copy_from_slice(&x[index].to_le_bytes());
+    }
+    output
+}
+
+/// One 64-byte Salsa20 keystream block for an 8-byte nonce and a 64-bit block counter.
+fn salsa20_block(key: &[u8; 32], nonce: &[u8; 8], counter: u64) -> [u8; 64] {
+    let mut middle = [0u8; 16];
+    middle[..8].copy_from_slice(nonce);
+    middle[8..].copy_from_slice(&counter.to_le_bytes());
+    let state = salsa20_state(key, &middle);
+    let mixed = salsa20_rounds(&state);
+    let mut output = [0u8; 64];
+    for i in 0..16 {
+        output[i * 4..i * 4 + 4].copy_from_slice(&mixed[i].wrapping_add(state[i]).to_le_bytes());
+    }
+    output
+}
+
+// -- Poly1305 -------------------------------------------------------------------------------
+
+/// Poly1305 accumulator: numbers below 2^130 kept as five 26-bit pieces ("limbs") so every
+/// product fits in a `u64`.
+struct Poly1305 {
+    r: [u32; 5],
+    h: [u32; 5],
+    pad: [u32; 4],
+}
+
+const LIMB_MASK: u32 = 0x03ff_ffff;
+
+impl Poly1305 {
+    fn new(key: &[u8; 32]) -> Self {
+        let word = |at: usize| u32::from_le_bytes([key[at], key[at + 1], key[at + 2], key[at + 3]]);
+        // `r` is "clamped": fixed bits are cleared, as every Poly1305 does.
+        let r = [
+            word(0) & 0x03ff_ffff,
+            (word(3) >> 2) & 0x03ff_ff03,
+            (word(6) >> 4) & 0x03ff_c0ff,
+            (word(9) >> 6) & 0x03f0_3fff,
+            (word(12) >> 8) & 0x000f_ffff,
+        ];
+        Self { r, h: [0; 5], pad: [word(16), word(20), word(24), word(28)] }
+    }
+
+    /// Add `chunk` (at most 16 bytes, read little-endian) to the accumulator, with a 1 bit just
+    /// above its last byte when `high_bit` is set, then multiply by `r` modulo 2^130 - 5.
+    fn absorb(&mut self, chunk: &[u8], high_bit: bool) {
+        let mut bytes = [0u8; 17];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        if high_bit {
+            bytes[chunk.len()] = 1;
+        }
+        let wide = |at: usize| {
+            u64::from(bytes[at])
+                | u64::from(bytes[at + 1]) << 8
+                | u64::from(bytes[at + 2]) << 16
+                | u64::from(bytes[at + 3]) << 24
+                | u64::from(*bytes.get(at + 4).unwrap_or(&0)) << 32
+        };
+        self.h[0] += (wide(0) as u32) & LIMB_MASK;
+        self.h[1] += ((wide(3) >> 2) as u32) & LIMB_MASK;
+        self.h[2] += ((wide(6) >> 4) as u32) & LIMB_MASK;
+        self.h[3] += ((wide(9) >> 6) as u32) & LIMB_MASK;
+        self.h[4] += ((wide(12) >> 8) as u32) & LIMB_MASK;
+
+        let [r0, r1, r2, r3, r4] = self.r.map(u64::from);
+        let [s1, s2, s3, s4] = [r1 * 5, r2 * 5, r3 * 5, r4 * 5];
+        let [h0, h1, h2, h3, h4] = self.h.map(u64::from);
+
+        let d0 = h0 * r0 + h1 * s4 + h2 * s3 + h3 * s2 + h4 * s1;
+        let mut d1 = h0 * r1 + h1 * r0 + h2 * s4 + h3 * s3 + h4 * s2;
+        let mut d2 = h0 * r2 + h1 * r1 + h2 * r0 + h3 * s4 + h4 * s3;
+        let mut d3 = h0 * r3 + h1 * r2 + h2 * r1 + h3 * r0 + h4 * s4;
+        let mut d4 = h0 * r4 + h1 * r3 + h2 * r2 + h3 * r1 + h4 * r0;
+
+        // Carry each limb's overflow into the next; the top overflow wraps around times 5,
+        // because 2^130 is 5 more than the modulus.
+        let mut carry = d0 >> 26;
+        let mut h = [(d0 as u32) & LIMB_MASK, 0, 0, 0, 0];
+        d1 += carry;
+        carry = d1 >> 26;
+        h[1] = (d1 as u32) & LIMB_MASK;
+        d2 += carry;
+        carry = d2 >> 26;
+        h[2] = (d2 as u32) & LIMB_MASK;
+        d3 += carry;
+        carry = d3 >> 26;
+        h[3] = (d3 as u32) & LIMB_MASK;
+        d4 += carry;
+        carry = d4 >> 26;
+        h[4] = (d4 as u32) & LIMB_MASK;
+        h[0] += (carry as u32) * 5;
+        h[1] += h[0] >> 26;
+        h[0] &= LIMB_MASK;
+        self.h = h;
+    }
+
+    /// Reduce fully below 2^130 - 5, add the second half of the key, and keep the low 128 bits.
+    fn finish(self) -> [u8; 16] {
+        let mut h = self.h;
+        for i in 1..5 {
+            h[i] += h[i - 1] >> 26;
+            h[i - 1] &= LIMB_MASK;
+        }
+        h[0] += (h[4] >> 26) * 5;
+        h[4] &= LIMB_MASK;
+        h[1] += h[0] >> 26;
+        h[0] &= LIMB_MASK;
+
+        // Compute h - p; keep it when it did not go negative.
+        let mut g = [0u32; 5];
+        g[0] = h[0].wrapping_add(5);
+        for i in 1..5 {
+            g[i] = h[i].wrapping_add(g[i - 1] >> 26);
+            g[i - 1] &= LIMB_MASK;
+        }
+        g[4] = g[4].wrapping_sub(1 << 26);
+        let keep_g = (g[4] >> 31).wrapping_sub(1);
+        for i in 0..5 {
+            h[i] = (h[i] & !keep_g) | (g[i] & keep_g);
+        }
+
+        let words = [
+            h[0] | (h[1] << 26),
+            (h[1] >> 6) | (h[2] << 20),
+            (h[2] >> 12) | (h[3] << 14),
+            (h[3] >> 18) | (h[4] << 8),
+        ];
+        let mut tag = [0u8; 16];
+        let mut carry = 0u64;
+        for i in 0..4 {
+            let sum = u64::from(words[i]) + u64::from(self.pad[i]) + carry;
+            tag[i * 4..i * 4 + 4].copy_from_slice(&(sum as u32).to_le_bytes());
+            carry = sum >> 32;
+        }
+        tag
+    }
+}
+
+/// The Python vault's tag: AAD blocks, one padding block when AAD is present, ciphertext blocks,
+/// one padding block when ciphertext is present, then the two lengths without a high bit.
+fn vault_tag(aad: &[u8], ciphertext: &[u8], one_time_key: &[u8; 32]) -> [u8; 16] {
+    let padding = |data: &[u8]| vec![0u8; (16 - data.len() % 16) % 16];
+    let mut poly = Poly1305::new(one_time_key);
+    for chunk in aad.chunks(16) {
+        poly.absorb(chunk, true);
+    }
+    if !aad.is_empty() {
+        poly.absorb(&padding(aad), true);
+    }
+    for chunk in ciphertext.chunks(16) {
+        poly.absorb(chunk, true);
+    }
+    if !ciphertext.is_empty() {
+        poly.absorb(&padding(ciphertext), true);
+    }
+    let mut lengths = [0u8; 16];
+    lengths[..8].copy_from_slice(&(aad.len() as u64).to_le_bytes());
+    lengths[8..].copy_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly.absorb(&lengths, false);
+    poly.finish()
+}
+
+/// Plain Poly1305 over `message`, as secretbox uses it: 16-byte blocks with a 1 bit above each,
+/// the last block possibly shorter.
+fn poly1305_mac(message: &[u8], one_time_key: &[u8; 32]) -> [u8; 16] {
+    let mut poly = Poly1305::new(one_time_key);
+    for chunk in message.chunks(16) {
+        poly.absorb(chunk, true);
+    }
+    poly.finish()
+}
+
+/// Standard base64 (with `=` padding), as Python's `base64.b64encode` writes it.
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let value = |c: u8| -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some(u32::from(c - b'A')),
+            b'a'..=b'z' => Some(u32::from(c - b'a') + 26),
+            b'0'..=b'9' => Some(u32::from(c - b'0') + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    };
+    let clean: Vec<u8> = text.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+    if !clean.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut output = Vec::with_capacity(clean.len() / 4 * 3);
+    for (index, quad) in clean.chunks(4).enumerate() {
+        let last = index == clean.len() / 4 - 1;
+        let padding = quad.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut bits = 0u32;
+        for &c in &quad[..4 - padding] {
+            bits = (bits << 6) | value(c)?;
+        }
+        bits <<= 6 * padding as u32;
+        let bytes = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        output.extend_from_slice(&bytes[..3 - padding]);
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha256::{from_hex, to_hex};
+
+    /// Written by `encrypt_secret(bytes([0x42]) * 32, b"bot-token-123")` in the Python vault,
+    /// with `os.urandom` pinned so the output is fixed.
+    const PYTHON_ENVELOPE: &str = r#"{
+  "nonce": "AAECAwQFBgcICQoLDA0OD2RlZmdoaWprbG1ubw==",
+  "ciphertext": "TFm+XcV0JaAi1r4O9g==",
+  "tag": "F7UcgJS54mxd35h8QhgdTQ=="
+}"#;
+
+    fn vault() -> SecretVault {
+        SecretVault::new(SecretBytes::new(vec![0x42; 32])).unwrap()
+    }
+
+    #[test]
+    fn opens_an_envelope_written_by_python() {
+        let envelope = EncryptedSecret::parse(PYTHON_ENVELOPE).unwrap();
+        assert_eq!(envelope.format, SecretFormat::ChaChaPoly);
+        assert_eq!(vault().decrypt(&envelope).unwrap().expose(), b"bot-token-123");
+    }
+
+    #[test]
+    fn wrong_key_or_edited_envelope_fails_authentication() {
+        let envelope = EncryptedSecret::parse(PYTHON_ENVELOPE).unwrap();
+        let other = SecretVault::new(SecretBytes::new(vec![0x43; 32])).unwrap();
+        assert!(other.decrypt(&envelope).unwrap_err().contains("failed authentication"));
+
+        let mut edited = envelope.clone();
+        edited.ciphertext[0] ^= 1;
+        assert!(vault().decrypt(&edited).unwrap_err().contains("failed authentication"));
+        let mut edited = envelope;
+        *edited.tag.last_mut().unwrap() ^= 1;
+        assert!(vault().decrypt(&edited).unwrap_err().contains("failed authentication"));
+    }
+
+    #[test]
+    fn short_keys_and_bad_envelopes_are_refused() {
+        assert!(SecretVault::new(SecretBytes::new(vec![1; 15])).is_err());
+        assert!(EncryptedSecret::parse("{}").unwrap_err().contains("missing the \"nonce\" string"));
+        assert!(EncryptedSecret::parse(r#"{"nonce":"!!","ciphertext":"","tag":""}"#).unwrap_err().contains("invalid base64"));
+        let unknown = PYTHON_ENVELOPE.replacen('{', r#"{"format": "aes","#, 1);
+        assert!(EncryptedSecret::parse(&unknown).unwrap_err().contains("unknown \"format\""));
+        // A chacha-poly nonce marked as secretbox is reported as a format problem, not a bad key.
+        let mislabelled = PYTHON_ENVELOPE.replacen('{', r#"{"format": "secretbox","#, 1);
+        let envelope = EncryptedSecret::parse(&mislabelled).unwrap();
+        assert!(vault().decrypt(&envelope).unwrap_err().contains("is that of a \"chacha-poly\" envelope"));
+    }
+
+    #[test]
+    fn chacha20_block_matches_rfc_8439() {
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let nonce: [u8; NONCE_LEN] = from_hex("000000090000004a00000000").unwrap().try_into().unwrap();
+        assert_eq!(
+            to_hex(&chacha20_block(&key, 1, &nonce)),
+            "10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4e\
+             d2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e"
+        );
+    }
+
+    #[test]
+    fn poly1305_matches_rfc_8439() {
+        let key: [u8; 32] = from_hex("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b").unwrap().try_into().unwrap();
+        assert_eq!(to_hex(&poly1305_mac(b"Cryptographic Forum Research Group", &key)), "a8061dc1305136c6c22b8baf0c0127a9");
+    }
+
+    #[test]
+    fn base64_decodes_padding_and_rejects_junk() {
+        assert_eq!(base64_decode("aGk=").as_deref(), Some(&b"hi"[..]));
+        assert_eq!(base64_decode("aGV5\n").as_deref(), Some(&b"hey"[..]));
+        assert_eq!(base64_decode("").as_deref(), Some(&b""[..]));
+        assert_eq!(base64_decode("aGk"), None);
+        assert_eq!(base64_decode("a=Gk"), None);
+        assert_eq!(base64_decode("aG-="), None);
+    }
+
+    /// The libsodium fixture from `test_secrets.py`: `crypto_secretbox_easy` of
+    /// `b"legacy webhook signing token"` under `SECRETBOX_KEY` with the nonce 00 01 .. 17, its
+    /// leading 16-byte tag moved into `"tag"`.
+    const SECRETBOX_ENVELOPE: &str = r#"{
+  "format": "secretbox",
+  "nonce": "AAECAwQFBgcICQoLDA0ODxAREhMUFRYX",
+  "ciphertext": "HhG7HpHLvYQIuNko616z8ELhb+hjzGPgqap7lg==",
+  "tag": "vGimeN9nz+/eytyVXmVYfA=="
+}"#;
+    const SECRETBOX_KEY: &[u8; 32] = b"squire-secretbox-fixture-key-32b";
+
+    #[test]
+    fn opens_a_secretbox_envelope_sealed_by_libsodium() {
+        let envelope = EncryptedSecret::parse(SECRETBOX_ENVELOPE).unwrap();
+        assert_eq!(envelope.format, SecretFormat::SecretboxCompat);
+        let legacy = SecretVault::new(SecretBytes::new(SECRETBOX_KEY.to_vec())).unwrap();
+        assert_eq!(legacy.decrypt(&envelope).unwrap().expose(), b"legacy webhook signing token");
+
+        let mut edited = envelope.clone();
+        edited.ciphertext[0] ^= 1;
+        assert!(legacy.decrypt(&edited).unwrap_err().contains("failed authentication"));
+        assert!(vault().decrypt(&envelope).unwrap_err().contains("failed authentication"), "another 32-byte key");
+        let short = SecretVault::new(SecretBytes::new(SECRETBOX_KEY[..16].to_vec())).unwrap();
+        assert!(short.decrypt(&envelope).unwrap_err().contains("needs the 32-byte key it was sealed with; this key is 16 bytes"));
+    }
+
+    #[test]
+    fn a_format_label_that_does_not_fit_the_nonce_names_both_formats() {
+        let vault = SecretVault::new(SecretBytes::new(SECRETBOX_KEY.to_vec())).unwrap();
+        let unlabelled = EncryptedSecret::parse(&SECRETBOX_ENVELOPE.replacen(r#""format": "secretbox","#, "", 1)).unwrap();
+        assert_eq!(unlabelled.format, SecretFormat::ChaChaPoly);
+        let err = vault.decrypt(&unlabelled).unwrap_err();
+        assert_eq!(err, "Envelope is marked \"chacha-poly\" but its 24-byte nonce is that of a \"secretbox\" envelope; fix its \"format\" field");
+
+        let mut odd = EncryptedSecret::parse(SECRETBOX_ENVELOPE).unwrap();
+        odd.nonce.truncate(20);
+        assert_eq!(vault.decrypt(&odd).unwrap_err(), "Envelope nonce must be 24 bytes for the \"secretbox\" format");
+        assert_eq!(SecretFormat::parse("chacha-poly"), Some(SecretFormat::ChaChaPoly));
+        assert_eq!(SecretFormat::parse(SecretFormat::SecretboxCompat.as_str()), Some(SecretFormat::SecretboxCompat));
+    }
+}
@@ -0,0 +1,237 @@
+//! Telling systemd we are alive: a tiny `sd_notify` without libsystemd.
+//!
+//! A unit with `WatchdogSec=30` expects the service to say "still here" at least every 30 seconds
+//! and restarts it otherwise. That is how systemd notices a loop that hangs without crashing. The
+//! service talks to systemd by sending short text datagrams to the Unix socket named in
+//! `NOTIFY_SOCKET`:
+//! - `READY=1` once startup is done (needed for `Type=notify` units);
+//! - `WATCHDOG=1` at the top of every cycle;
+//! - `STOPPING=1` when it is shutting down on purpose.
+//!
+//! A path starting with `@` is a Linux "abstract" socket: it has a name but no file, and the `@`
+//! stands for the zero byte the kernel expects in front of the name.
+//!
+//! Without `NOTIFY_SOCKET` (run by hand, from cron, or on a system without systemd) every call does
+//! nothing. The socket send sits behind the `NotifySink` trait: `UnixSocketSink` is the real one,
+//! and `MemorySink` keeps the messages in a list instead, so a loop can be tried out without
+//! systemd.
+//!
+//! The hub, Squire, and Sentry share this module through `ecosystem-common`.
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use crate::runtime::EnvSource;
+
+/// The variable systemd sets to the socket it listens on.
+pub const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+
+/// Where notification messages go.
+pub trait NotifySink {
+    fn send(&self, message: &str) -> io::Result<()>;
+}
+
+/// Sends each message as one datagram to the socket at `address`.
+#[derive(Clone, Debug)]
+pub struct UnixSocketSink {
+    /// A file path, or `@name` for an abstract socket.
+    pub address: String,
+}
+
+impl NotifySink for UnixSocketSink {
+    /// A fresh socket per message: systemd may restart its end at any time, and a message per
+    /// cycle is far too rare for the extra socket to matter.
+    #[cfg(unix)]
+    fn send(&self, message: &str) -> io::Result<()> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        match self.address.strip_prefix('@') {
+            Some(name) => connect_abstract(&socket, name)?,
+            None => socket.connect(&self.address)?,
+        }
+        socket.send(message.as_bytes()).map(|_| ())
+    }
+
+    #[cfg(not(unix))]
+    fn send(&self, _message: &str) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are not available on this system"))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn connect_abstract(socket: &std::os::unix::net::UnixDatagram, name: &str) -> io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    let address = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+    socket.connect_addr(&address)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn connect_abstract(_socket: &std::os::unix::net::UnixDatagram, _name: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "abstract sockets (@name) only exist on Linux"))
+}
+
+/// Keeps every message in memory. Clones share one list, so keep a clone to read it back.
+#[derive(Clone, Debug, Default)]
+pub struct MemorySink {
+    sent: Rc<RefCell<Vec<String>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every message sent so far, oldest first.
+    pub fn sent(&self) -> Vec<String> {
+        self.sent.borrow().clone()
+    }
+}
+
+impl NotifySink for MemorySink {
+    fn send(&self, message: &str) -> io::Result<()> {
+        self.sent.borrow_mut().push(message.to_string());
+        Ok(())
+    }
+}
+
+/// The service's line to systemd. A disabled one ignores every call.
+pub struct Watchdog {
+    sink: Option<Box<dyn NotifySink>>,
+}
+
+impl Watchdog {
+    /// Send to `NOTIFY_SOCKET` when it is set and not empty; otherwise disabled.
+    pub fn from_env(env: &dyn EnvSource) -> Self {
+        let address = env.var(NOTIFY_SOCKET_ENV).filter(|address| !address.is_empty());
+        Watchdog { sink: address.map(|address| Box::new(UnixSocketSink { address }) as Box<dyn NotifySink>) }
+    }
+
+    /// A watchdog that never sends anything.
+    pub fn disabled() -> Self {
+        Watchdog { sink: None }
+    }
+
+    pub fn with_sink(sink: Box<dyn NotifySink>) -> Self {
+        Watchdog { sink: Some(sink) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    /// Send one message such as `STATUS=verifying`. `Ok` when disabled.
+    pub fn notify(&self, message: &str) -> io::Result<()> {
+        match &self.sink {
+            Some(sink) => sink.send(message),
+            None => Ok(()),
+        }
+    }
+
+    /// Startup is done.
+    pub fn ready(&self) -> io::Result<()> {
+        self.notify("READY=1")
+    }
+
+    /// Still alive; call at the top of every cycle.
+    pub fn ping(&self) -> io::Result<()> {
+        self.notify("WATCHDOG=1")
+    }
+
+    /// Shutting down on purpose, so a missing ping is not a hang.
+    pub fn stopping(&self) -> io::Result<()> {
+        self.notify("STOPPING=1")
+    }
+
+    /// Send `STOPPING=1` when the returned guard is dropped, for loops that can only be left
+    /// through an error.
+    pub fn stopping_on_drop(&self) -> StoppingGuard<'_> {
+        StoppingGuard { watchdog: self }
+    }
+}
+
+/// See `Watchdog::stopping_on_drop`.
+pub struct StoppingGuard<'a> {
+    watchdog: &'a Watchdog,
+}
+
+impl Drop for StoppingGuard<'_> {
+    fn drop(&mut self) {
+        // Nobody is left to tell about a failure here, and systemd notices the exit anyway.
+        let _ = self.watchdog.stopping();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::MapEnv;
+
+    /// Every datagram waiting on `socket`, oldest first.
+    #[cfg(unix)]
+    fn received(socket: &std::os::unix::net::UnixDatagram) -> Vec<String> {
+        socket.set_nonblocking(true).unwrap();
+        let mut buffer = [0u8; 256];
+        let mut messages = Vec::new();
+        while let Ok(length) = socket.recv(&mut buffer) {
+            messages.push(String::from_utf8_lossy(&buffer[..length]).into_owned());
+        }
+        messages
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn messages_reach_a_socket_file_in_order() {
+        let dir = std::env::temp_dir().join(format!("ecosystem-watchdog-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let socket = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        let watchdog = Watchdog::from_env(&MapEnv::new().with(NOTIFY_SOCKET_ENV, &path.to_string_lossy()));
+        assert!(watchdog.is_enabled());
+        {
+            let _stopping = watchdog.stopping_on_drop();
+            watchdog.ready().unwrap();
+            watchdog.ping().unwrap();
+            watchdog.ping().unwrap();
+            watchdog.notify("STATUS=verifying").unwrap();
+        }
+        assert_eq!(received(&socket), ["READY=1", "WATCHDOG=1", "WATCHDOG=1", "STATUS=verifying", "STOPPING=1"]);
+
+        // Nobody listening is an error the caller can log, never a panic.
+        drop(socket);
+        std::fs::remove_file(&path).unwrap();
+        assert!(watchdog.ping().is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn an_at_sign_names_an_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+        let name = format!("ecosystem-watchdog-test-{}", std::process::id());
+        let address = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let socket = std::os::unix::net::UnixDatagram::bind_addr(&address).unwrap();
+
+        let watchdog = Watchdog::from_env(&MapEnv::new().with(NOTIFY_SOCKET_ENV, &format!("@{name}")));
+        watchdog.ready().unwrap();
+        watchdog.stopping().unwrap();
+        assert_eq!(received(&socket), ["READY=1", "STOPPING=1"]);
+        assert!(!std::path::Path::new(&format!("@{name}")).exists(), "no file is created for it");
+    }
+
+    #[test]
+    fn without_the_variable_every_call_does_nothing() {
+        for env in [MapEnv::new(), MapEnv::new().with(NOTIFY_SOCKET_ENV, "")] {
+            let watchdog = Watchdog::from_env(&env);
+            assert!(!watchdog.is_enabled());
+            assert!(watchdog.ready().is_ok() && watchdog.ping().is_ok() && watchdog.stopping().is_ok());
+            drop(watchdog.stopping_on_drop());
+        }
+        assert!(!Watchdog::disabled().is_enabled());
+
+        let sink = MemorySink::new();
+        let watchdog = Watchdog::with_sink(Box::new(sink.clone()));
+        drop(watchdog.stopping_on_drop());
+        assert_eq!(sink.sent(), ["STOPPING=1"]);
+    }
+}
@@ -0,0 +1,1809 @@
+//! Central communications hub for the Course on Robot Recourse ecosystem.
+//!
+//! This module keeps all cross-bot communication inside the standard library.
+//! It scans for bot or ecosystem folders, drops a presence file inside each
+//! `Discovery/` directory to signal "safe to talk," and routes messages between
+//! the file-backed queues bots keep there. The `ecosystem-hub` binary calls these
+//! functions in a loop. No external crates are used so auditors can read
+//! everything in this repository.
+
+use std::collections::{HashSet, VecDeque};
+use std::env; // Standard-library access to the current working directory for clarity.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::atomic::{atomic_write, clean_stale_temps};
+use crate::hub_state::{self, HubState};
+use crate::lockfile::append_locked;
+use crate::log::{self, Level, Logger};
+use crate::queue_file::{QueueCursor, QueueFile};
+use crate::runtime::{EnvSource, ProcessEnv, Runtime};
+use crate::sha256::{constant_time_eq, from_hex, hmac_sha256, sha256, to_hex};
+
+/// Name of the presence file the hub writes inside each entity’s `Discovery/` directory.
+const PRESENCE_FILE: &str = "ecosystem_presence.txt";
+/// Name of the file where bots can drop messages for the hub to route.
+const BOT_QUEUE_FILE: &str = "gateway_queue.log";
+/// Name of the hub log stored inside the ecosystem’s own `Discovery/` folder.
+const HUB_QUEUE_FILE: &str = "hub_queue.log";
+/// Where the hub appends messages addressed to an entity.
+const INBOX_FILE: &str = "inbox.log";
+/// Where the hub confirms delivery back to the sending entity.
+const RECEIPTS_FILE: &str = "receipts.log";
+/// Hub-owned file for queue lines that could not be delivered.
+const DEAD_LETTER_FILE: &str = "dead_letter.log";
+/// Optional `key=value` descriptor an entity keeps in its `Discovery/` folder.
+const DESCRIPTOR_FILE: &str = "entity.toml";
+/// Consolidated list of every discovered entity, rewritten by the hub on each run.
+const REGISTRY_FILE: &str = "registry.json";
+/// Optional override for how many `Discovery/` levels the hub descends into.
+const MAX_DEPTH_ENV: &str = "ECOSYSTEM_DISCOVERY_MAX_DEPTH";
+/// Default nesting limit: deep enough for teaching layouts, shallow enough to stay quick.
+const DEFAULT_MAX_DEPTH: usize = 6;
+/// Set to `1` to let discovery walk into symlinked directories.
+const FOLLOW_SYMLINKS_ENV: &str = "ECOSYSTEM_FOLLOW_SYMLINKS";
+/// Hub-owned document with routing cursors and other state kept between cycles (see `hub_state`).
+const HUB_STATE_FILE: &str = "hub_state.json";
+/// Liveness file a bot rewrites on every flush or cycle: `pid=<pid> seq=<n> at=<unix millis>`.
+const HEARTBEAT_FILE: &str = "heartbeat.txt";
+/// How often bots are expected to beat, in seconds; overrides the default below.
+const HEARTBEAT_INTERVAL_ENV: &str = "ECOSYSTEM_HEARTBEAT_INTERVAL_SECS";
+const DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 60;
+/// A heartbeat older than this many expected intervals is stale.
+const HEARTBEAT_STALE_FACTOR: u64 = 3;
+/// Random nonce the hub asks an entity's gateway to sign: `nonce=`, `issued_at=`, `ttl_secs=`.
+const CHALLENGE_FILE: &str = "challenge.txt";
+/// The gateway's signed answer (see `check_attestations`).
+const CHALLENGE_RESPONSE_FILE: &str = "challenge_response.txt";
+/// How long a challenge (and an answer to it) stays good, in seconds; overrides the default below.
+const CHALLENGE_TTL_ENV: &str = "ECOSYSTEM_CHALLENGE_TTL_SECS";
+const DEFAULT_CHALLENGE_TTL_SECS: u64 = 10 * 60;
+/// Optional override (in seconds) for how long gateways accept a signed presence file.
+const PRESENCE_TTL_ENV: &str = "ECOSYSTEM_PRESENCE_TTL_SECS";
+/// Default presence lifetime, matching the gateways' default.
+const DEFAULT_PRESENCE_TTL_SECS: u64 = 15 * 60;
+/// Environment variable that carries the keyed material used to sign presence files.
+const PRESENCE_KEY_ENV: &str = "ECOSYSTEM_PRESENCE_KEY";
+
+/// Set to `1` to keep the old 16-byte SipHash presence scheme for one transition period.
+const PRESENCE_LEGACY_ENV: &str = "ECOSYSTEM_PRESENCE_LEGACY";
+/// `status=` value (and nonce suffix) of a marker that tells a gateway the hub said stop.
+const PRESENCE_REVOKED: &str = "revoked";
+
+/// Log lines from the hub carry the component name `hub`.
+const LOG: Logger = Logger::new("hub");
+
+/// The files inside one entity's `Discovery/` folder.
+///
+/// Every helper below asks a layout for its paths instead of joining `"Discovery"` and a file
+/// name itself, so the folder name and file names live in one place. The hub's own layout comes
+/// from `--root`, which the daemon resolves to an absolute path, so nothing depends on the
+/// directory the hub was started from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveryLayout {
+    /// The `Discovery/` folder itself. Files are created on demand inside it.
+    pub root: PathBuf,
+}
+
+impl DiscoveryLayout {
+    /// The layout of the entity (bot or ecosystem) whose folder is `entity`.
+    pub fn of(entity: &Path) -> Self {
+        Self { root: entity.join("Discovery") }
+    }
+
+    /// The signed marker that tells a bot the hub is live.
+    pub fn presence_file(&self) -> PathBuf {
+        self.root.join(PRESENCE_FILE)
+    }
+
+    /// Lines a bot writes for the hub to route (and for its gateway to forward).
+    pub fn dispatch_file(&self) -> PathBuf {
+        self.root.join(BOT_QUEUE_FILE)
+    }
+
+    /// The hub's own log, kept in the ecosystem's layout.
+    pub fn hub_log(&self) -> PathBuf {
+        self.root.join(HUB_QUEUE_FILE)
+    }
+
+    pub fn inbox_file(&self) -> PathBuf {
+        self.root.join(INBOX_FILE)
+    }
+
+    pub fn receipts_file(&self) -> PathBuf {
+        self.root.join(RECEIPTS_FILE)
+    }
+
+    pub fn dead_letter_file(&self) -> PathBuf {
+        self.root.join(DEAD_LETTER_FILE)
+    }
+
+    pub fn descriptor_file(&self) -> PathBuf {
+        self.root.join(DESCRIPTOR_FILE)
+    }
+
+    pub fn registry_file(&self) -> PathBuf {
+        self.root.join(REGISTRY_FILE)
+    }
+
+    /// The hub's own state between cycles, kept in the ecosystem's layout.
+    pub fn hub_state_file(&self) -> PathBuf {
+        self.root.join(HUB_STATE_FILE)
+    }
+
+    /// The liveness file the entity itself keeps fresh.
+    pub fn heartbeat_file(&self) -> PathBuf {
+        self.root.join(HEARTBEAT_FILE)
+    }
+
+    /// The hub's challenge to the entity's gateway.
+    pub fn challenge_file(&self) -> PathBuf {
+        self.root.join(CHALLENGE_FILE)
+    }
+
+    /// The gateway's answer.
+    pub fn challenge_response_file(&self) -> PathBuf {
+        self.root.join(CHALLENGE_RESPONSE_FILE)
+    }
+}
+
+/// The presence key, in whichever signing scheme is active.
+#[derive(Clone, Copy)]
+pub(crate) enum PresenceKey {
+    /// 32-byte HMAC-SHA256 key (64 hex characters).
+    Hmac([u8; 32]),
+    /// Old 16-byte SipHash key, only accepted while `ECOSYSTEM_PRESENCE_LEGACY=1`.
+    Legacy([u8; 16]),
+}
+
+/// Parse `ECOSYSTEM_PRESENCE_KEY`. A 64-hex key selects HMAC-SHA256; the old 32-hex key is only
+/// allowed in legacy mode, and otherwise produces an error that says how to upgrade.
+fn parse_presence_key(raw: &str, legacy_allowed: bool) -> Result<PresenceKey, String> {
+    let bytes = from_hex(raw).ok_or_else(|| format!("{} is not valid hex", PRESENCE_KEY_ENV))?;
+    match bytes.len() {
+        32 => {
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&bytes);
+            Ok(PresenceKey::Hmac(key))
+        }
+        16 if legacy_allowed => {
+            let mut key = [0u8; 16];
+            key.copy_from_slice(&bytes);
+            Ok(PresenceKey::Legacy(key))
+        }
+        16 => Err(format!(
+            "{} is 32 hex characters (16 bytes), the old SipHash key size; presence keys are now 64 hex characters (32 bytes). Generate one with `openssl rand -hex 32`, or set {}=1 to keep the old scheme during the transition",
+            PRESENCE_KEY_ENV, PRESENCE_LEGACY_ENV
+        )),
+        other => Err(format!(
+            "{} must be 64 hex characters (32 bytes), got {} bytes",
+            PRESENCE_KEY_ENV, other
+        )),
+    }
+}
+
+/// Load the presence key from the environment, honouring the legacy escape hatch.
+pub(crate) fn load_presence_key(env: &dyn EnvSource) -> Result<PresenceKey, String> {
+    let raw = env.var(PRESENCE_KEY_ENV).ok_or_else(|| format!("{} is unset", PRESENCE_KEY_ENV))?;
+    let legacy_allowed = env.var(PRESENCE_LEGACY_ENV).is_some_and(|v| v.trim() == "1");
+    parse_presence_key(raw.trim(), legacy_allowed)
+}
+
+/// Sign a nonce: 64 hex characters of HMAC-SHA256, or 16 of SipHash in legacy mode.
+fn sign_presence(key: &PresenceKey, nonce: &str) -> String {
+    match key {
+        PresenceKey::Hmac(key) => to_hex(&hmac_sha256(key, nonce.as_bytes())),
+        PresenceKey::Legacy(key) => sign_presence_legacy(key, nonce),
+    }
+}
+
+/// The pre-HMAC scheme, kept only for `ECOSYSTEM_PRESENCE_LEGACY=1`. `SipHasher` is deprecated,
+/// which is one of the reasons this path is on its way out.
+#[allow(deprecated)]
+fn sign_presence_legacy(key_bytes: &[u8; 16], nonce: &str) -> String {
+    use std::hash::{Hasher, SipHasher};
+
+    let mut k0 = 0u64;
+    let mut k1 = 0u64;
+    for (i, b) in key_bytes.iter().enumerate() {
+        if i < 8 {
+            k0 = (k0 << 8) | (*b as u64);
+        } else {
+            k1 = (k1 << 8) | (*b as u64);
+        }
+    }
+
+    let mut hasher = SipHasher::new_with_keys(k0, k1);
+    hasher.write(nonce.as_bytes());
+    format!("{:016x}", hasher.finish())
+}
+
+/// Compare a signature with the one we expect as bytes, in constant time, so how long a check
+/// takes says nothing about how much of a forged signature was right. Either case of hex is fine.
+fn signature_matches(expected_hex: &str, signature_hex: &str) -> bool {
+    match (from_hex(expected_hex), from_hex(signature_hex)) {
+        (Some(expected), Some(signature)) => constant_time_eq(&expected, &signature),
+        _ => false,
+    }
+}
+
+/// Derive one entity's key as `HMAC(master, entity_name)`. The hub keeps only the master key;
+/// each bot is given its own derived key, so a compromised bot can forge presence for itself but
+/// not for its neighbours.
+fn derive_entity_key(master: &[u8; 32], entity_name: &str) -> [u8; 32] {
+    hmac_sha256(master, entity_name.as_bytes())
+}
+
+/// Name an entity by its path relative to `base` (the folder holding the ecosystem), always with
+/// `/` separators, e.g. `ecosystem/Discovery/squire`. Keys are derived from this name, so it must
+/// not depend on where the checkout lives or which OS the hub runs on.
+pub(crate) fn entity_name(base: &Path, entity: &Path) -> String {
+    let relative = entity.strip_prefix(base).unwrap_or(entity);
+    relative
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Build a presence marker that includes a timestamped nonce and keyed signature, followed by
+/// `hub_*` lines describing the hub. Only the nonce is signed; the hub lines are informational.
+fn presence_payload(key: Option<&PresenceKey>, name: &str, hub: &EntityInfo, now_millis: u128) -> String {
+    let nonce = format!("{}|{}", name, now_millis);
+    let hub_lines = format!(
+        "\nhub_name={}\nhub_kind={}\nhub_capabilities={}",
+        hub.name,
+        hub.kind.as_str(),
+        hub.capabilities.join(",")
+    );
+
+    let signed = match key {
+        Some(PresenceKey::Hmac(master)) => {
+            let entity_key = PresenceKey::Hmac(derive_entity_key(master, name));
+            format!("nonce={}\nsignature={}", nonce, sign_presence(&entity_key, &nonce))
+        }
+        Some(legacy @ PresenceKey::Legacy(_)) => {
+            // The old scheme shares one key across every entity.
+            format!("nonce={}\nsignature={}", nonce, sign_presence(legacy, &nonce))
+        }
+        None => {
+            // Keep the marker explicit about the missing key so operators know why
+            // a gateway refuses to accept it.
+            format!(
+                "nonce={}\nsignature=missing-{}",
+                nonce, PRESENCE_KEY_ENV
+            )
+        }
+    };
+    signed + &hub_lines
+}
+
+/// Build a revocation marker: the same nonce and signature scheme as `presence_payload`, with
+/// `|revoked` at the end of the nonce and a `status=revoked` line. The word is inside the signed
+/// nonce, so only the hub can revoke; a `status=` line added by anyone else is ignored.
+fn revocation_payload(key: Option<&PresenceKey>, name: &str, now_millis: u128) -> String {
+    let nonce = format!("{}|{}|{}", name, now_millis, PRESENCE_REVOKED);
+    let signature = match key {
+        Some(PresenceKey::Hmac(master)) => sign_presence(&PresenceKey::Hmac(derive_entity_key(master, name)), &nonce),
+        Some(legacy @ PresenceKey::Legacy(_)) => sign_presence(legacy, &nonce),
+        None => format!("missing-{}", PRESENCE_KEY_ENV),
+    };
+    format!("nonce={}\nsignature={}\nstatus={}", nonce, signature, PRESENCE_REVOKED)
+}
+
+/// What a presence marker says once its signature checks out (see `check_marker`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MarkerState {
+    /// Signed within the TTL; the gateway will accept it.
+    Fresh,
+    /// Signed, but longer ago than the TTL: the hub has not refreshed it.
+    Stale { age_secs: u64 },
+    /// A signed revocation: the hub said stop.
+    Revoked,
+}
+
+/// Check the marker text `contents` the way a gateway would, but with the hub's key: the nonce
+/// must name `name`, the signature must match the key derived for it, and the time must be
+/// within `ttl_secs`. Clock skew is not allowed for here; `doctor` runs on the hub's own clock.
+pub(crate) fn check_marker(key: &PresenceKey, name: &str, contents: &str, now_millis: u128, ttl_secs: u64) -> Result<MarkerState, String> {
+    let field = |prefix: &str| contents.lines().find_map(|line| line.strip_prefix(prefix)).map(str::trim);
+    let nonce = field("nonce=").ok_or("no nonce= line")?;
+    let signature = field("signature=").ok_or("no signature= line")?;
+    if signature.starts_with("missing-") {
+        return Err(format!("unsigned: the hub had no {} when it wrote it", PRESENCE_KEY_ENV));
+    }
+    let expected = match key {
+        PresenceKey::Hmac(master) => sign_presence(&PresenceKey::Hmac(derive_entity_key(master, name)), nonce),
+        legacy @ PresenceKey::Legacy(_) => sign_presence(legacy, nonce),
+    };
+    if !signature_matches(&expected, signature) {
+        return Err("signature does not match this hub's key".to_string());
+    }
+    let mut parts = nonce.split('|');
+    if parts.next() != Some(name) {
+        return Err(format!("nonce names another entity ({nonce})"));
+    }
+    let signed_at = parts.next().and_then(|stamp| stamp.parse::<u128>().ok()).ok_or("nonce has no timestamp")?;
+    if parts.next() == Some(PRESENCE_REVOKED) {
+        return Ok(MarkerState::Revoked);
+    }
+    let age_secs = (now_millis.saturating_sub(signed_at) / 1000) as u64;
+    Ok(if age_secs > ttl_secs { MarkerState::Stale { age_secs } } else { MarkerState::Fresh })
+}
+
+/// What an entity says it is. Anything other than the three known kinds reads as `Unknown`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityKind {
+    Bot,
+    Ecosystem,
+    Sentinel,
+    Unknown,
+}
+
+impl EntityKind {
+    fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "bot" => Some(EntityKind::Bot),
+            "ecosystem" => Some(EntityKind::Ecosystem),
+            "sentinel" => Some(EntityKind::Sentinel),
+            "unknown" => Some(EntityKind::Unknown),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntityKind::Bot => "bot",
+            EntityKind::Ecosystem => "ecosystem",
+            EntityKind::Sentinel => "sentinel",
+            EntityKind::Unknown => "unknown",
+        }
+    }
+}
+
+/// One discovered entity plus whatever its `Discovery/entity.toml` advertises.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityInfo {
+    pub path: PathBuf,
+    /// `name=` from the descriptor, or the folder name when there is none.
+    pub name: String,
+    pub kind: EntityKind,
+    /// `capabilities=` split on commas, e.g. `logging`, `xp`, `moderation`.
+    pub capabilities: Vec<String>,
+}
+
+/// Everything one discovery pass found, plus problems worth logging.
+#[derive(Debug, Default)]
+pub struct DiscoveryScan {
+    pub entities: Vec<EntityInfo>,
+    /// Descriptor problems. Discovery carries on past them with default values.
+    pub warnings: Vec<String>,
+    /// How many directories were listed, for the hub log.
+    pub scanned_dirs: usize,
+    /// `Discovery/` folders left unread because they sit deeper than the depth limit.
+    pub truncated: Vec<PathBuf>,
+}
+
+/// Limits for one discovery pass.
+#[derive(Debug, Clone, Copy)]
+pub struct DiscoveryLimits {
+    /// Containers passed in are depth 0; each nested `Discovery/` adds one.
+    pub max_depth: usize,
+    /// Walk into symlinked directories. Off by default, because a link such as
+    /// `Discovery/loop -> ..` would otherwise point the scan back at itself.
+    pub follow_symlinks: bool,
+}
+
+impl DiscoveryLimits {
+    /// Read `ECOSYSTEM_DISCOVERY_MAX_DEPTH` and `ECOSYSTEM_FOLLOW_SYMLINKS`, with safe defaults.
+    pub fn from_env() -> Self {
+        Self {
+            max_depth: env::var(MAX_DEPTH_ENV)
+                .ok()
+                .and_then(|raw| raw.trim().parse().ok())
+                .unwrap_or(DEFAULT_MAX_DEPTH),
+            follow_symlinks: env::var(FOLLOW_SYMLINKS_ENV).map(|raw| raw.trim() == "1").unwrap_or(false),
+        }
+    }
+}
+
+/// Read `<entity>/Discovery/entity.toml`. The file is a small `key=value` list:
+/// ```text
+/// name=squire
+/// kind=bot
+/// capabilities=logging,xp,moderation
+/// ```
+/// Blank lines and `#` comments are skipped, and TOML-style quotes around values are tolerated.
+/// A missing file gives the folder name and `default_kind`; a bad line adds a warning and
+/// leaves that field at its default instead of failing discovery.
+fn load_entity_info(path: &Path, default_kind: EntityKind, warnings: &mut Vec<String>) -> EntityInfo {
+    let mut info = EntityInfo {
+        path: path.to_path_buf(),
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string()),
+        kind: default_kind,
+        capabilities: Vec::new(),
+    };
+
+    let descriptor = DiscoveryLayout::of(path).descriptor_file();
+    let Ok(contents) = fs::read_to_string(&descriptor) else {
+        return info;
+    };
+
+    for (number, raw_line) in contents.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            warnings.push(format!("{}:{}: expected key=value, got {:?}", descriptor.display(), number + 1, line));
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim() {
+            "name" if !value.is_empty() => info.name = value.to_string(),
+            "kind" => match EntityKind::parse(value) {
+                Some(kind) => info.kind = kind,
+                None => warnings.push(format!(
+                    "{}:{}: unknown kind {:?} (expected bot, ecosystem, or sentinel)",
+                    descriptor.display(),
+                    number + 1,
+                    value
+                )),
+            },
+            "capabilities" => {
+                info.capabilities = value
+                    .split(',')
+                    .map(|capability| capability.trim().trim_matches('"').to_string())
+                    .filter(|capability| !capability.is_empty())
+                    .collect();
+            }
+            other => warnings.push(format!("{}:{}: unknown key {:?}", descriptor.display(), number + 1, other)),
+        }
+    }
+
+    info
+}
+
+/// Determine whether a path represents a bot or ecosystem by checking for a `Discovery/` directory.
+fn is_entity(path: &Path) -> bool {
+    path.is_dir() && DiscoveryLayout::of(path).root.is_dir()
+}
+
+/// Collect entities breadth-first starting from the given container paths.
+///
+/// Three guards keep this finite: every directory is canonicalized and listed at most once (so
+/// even followed symlink loops end), symlinked directories are skipped unless the limits allow
+/// them, and nested `Discovery/` folders deeper than `max_depth` are recorded in
+/// `truncated` instead of being read.
+fn collect_entities(containers: Vec<PathBuf>, limits: DiscoveryLimits) -> DiscoveryScan {
+    let mut scan = DiscoveryScan::default();
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+    // Entities are deduplicated by real location too, so a followed link cannot list one twice.
+    let mut known_entities: HashSet<PathBuf> = HashSet::new();
+    let mut stack: VecDeque<(PathBuf, usize)> = containers.into_iter().map(|path| (path, 0)).collect();
+
+    while let Some((container, depth)) = stack.pop_front() {
+        let canonical = fs::canonicalize(&container).unwrap_or_else(|_| container.clone());
+        if !visited.insert(canonical) {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&container) else {
+            continue;
+        };
+        scan.scanned_dirs += 1;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_symlink = entry.file_type().map(|kind| kind.is_symlink()).unwrap_or(false);
+            if !path.is_dir() || (is_symlink && !limits.follow_symlinks) {
+                continue;
+            }
+
+            let real_path = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if is_entity(&path) && known_entities.insert(real_path) {
+                let info = load_entity_info(&path, EntityKind::Unknown, &mut scan.warnings);
+                scan.entities.push(info);
+            }
+            let discovery = DiscoveryLayout::of(&path).root;
+            if discovery.is_dir() {
+                if depth < limits.max_depth {
+                    stack.push_back((discovery, depth + 1));
+                } else {
+                    scan.truncated.push(discovery);
+                }
+            }
+        }
+    }
+
+    scan
+}
+
+/// Write `Discovery/registry.json` listing every entity, replacing the previous run's file.
+/// Entities with a matching entry in `heartbeats` also get a `heartbeat` object, and those in
+/// `attestations` an `attestation` object. The SHA-256 of what was written goes into `state` as
+/// `registry.last_hash`, so `dump-state` shows whether the file on disk is still the hub's.
+pub fn write_registry(
+    root: &Path,
+    hub: &EntityInfo,
+    entities: &[EntityInfo],
+    heartbeats: &[HeartbeatStatus],
+    attestations: &[AttestationStatus],
+    state: &mut HubState,
+) {
+    let base = root.parent().unwrap_or(root);
+    let describe = |info: &EntityInfo, heartbeats: &[HeartbeatStatus], attestations: &[AttestationStatus]| {
+        let name = entity_name(base, &info.path);
+        let capabilities = info
+            .capabilities
+            .iter()
+            .map(|capability| format!("\"{}\"", json_escape(capability)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let heartbeat = heartbeats
+            .iter()
+            .find(|status| status.name == name)
+            .map(|status| format!(",\"heartbeat\":{}", status.to_json()))
+            .unwrap_or_default();
+        let attestation = attestations
+            .iter()
+            .find(|status| status.name == name)
+            .map(|status| format!(",\"attestation\":{}", status.to_json()))
+            .unwrap_or_default();
+        format!(
+            "{{\"name\":\"{}\",\"path\":\"{}\",\"kind\":\"{}\",\"capabilities\":[{}]{}{}}}",
+            json_escape(&info.name),
+            json_escape(&name),
+            info.kind.as_str(),
+            capabilities,
+            heartbeat,
+            attestation
+        )
+    };
+    let listed = entities.iter().map(|info| describe(info, heartbeats, attestations)).collect::<Vec<_>>().join(",");
+    let document = format!(
+        "{{\"generated_at_ms\":{},\"hub\":{},\"entities\":[{}]}}\n",
+        now_millis(),
+        // The hub logs its own heartbeat in `hub_queue.log` instead.
+        describe(hub, &[], &[]),
+        listed
+    );
+
+    if atomic_write(&DiscoveryLayout::of(root).registry_file(), document.as_bytes()).is_ok() {
+        state.set(hub_state::REGISTRY_LAST_HASH, &to_hex(&sha256(document.as_bytes())));
+    }
+}
+
+/// Escape a string for a JSON string literal.
+pub(crate) fn json_escape(raw: &str) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+    for ch in raw.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Drop the presence file into each entity’s Discovery folder so the bot or ecosystem knows the hub is live.
+///
+/// Every call rewrites every marker with a fresh timestamped nonce. Gateways treat markers older
+/// than `ECOSYSTEM_PRESENCE_TTL_SECS` (15 minutes by default) as stale, so a hub that runs for
+/// longer than that must keep calling this, for example through `refresh_presence`.
+pub fn announce_presence(root: &Path, hub: &EntityInfo, entities: &[EntityInfo]) {
+    announce_presence_with(root, hub, entities, Runtime::system());
+}
+
+/// `announce_presence` with the key from `rt.env` and the nonce time from `rt.clock`.
+pub fn announce_presence_with(root: &Path, hub: &EntityInfo, entities: &[EntityInfo], rt: Runtime<'_>) {
+    let presence_key = match load_presence_key(rt.env) {
+        Ok(key) => Some(key),
+        Err(err) => {
+            append_hub_log(
+                root,
+                Level::Error,
+                "Presence files will be unsigned and gateways will ignore them",
+                &[("reason", &err)],
+            );
+            None
+        }
+    };
+    // Entity names are relative to the folder that holds the ecosystem, matching the
+    // containers `refresh_presence` scans.
+    let base = root.parent().unwrap_or(root);
+
+    for entity in entities {
+        let marker = DiscoveryLayout::of(&entity.path).presence_file();
+        if let Some(parent) = marker.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        // Written atomically: a marker cut short by a crash would look like a missing signature.
+        let payload = presence_payload(presence_key.as_ref(), &entity_name(base, &entity.path), hub, rt.clock.now_millis());
+        let _ = atomic_write(&marker, payload.as_bytes());
+    }
+
+    let hub_log = DiscoveryLayout::of(root).hub_log();
+    if let Some(parent) = hub_log.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+}
+
+/// Tell each entity the hub is going away, by overwriting its presence marker with a signed
+/// `status=revoked` marker.
+///
+/// Deleting the file would work too, but then a gateway cannot tell "the hub said stop" from "the
+/// hub never ran here". The revocation is signed like any marker, so a local process cannot forge
+/// one to cut a bot off. The hub calls this when it stops through its stop file; the next
+/// `announce_presence` replaces the revocation with a normal marker again.
+pub fn revoke_presence(root: &Path, entities: &[EntityInfo]) {
+    revoke_presence_with(root, entities, Runtime::system());
+}
+
+/// `revoke_presence` with the key from `rt.env` and the nonce time from `rt.clock`.
+pub fn revoke_presence_with(root: &Path, entities: &[EntityInfo], rt: Runtime<'_>) {
+    let presence_key = match load_presence_key(rt.env) {
+        Ok(key) => Some(key),
+        Err(err) => {
+            // An unsigned revocation is still not a valid marker, so the bot stops either way.
+            append_hub_log(root, Level::Error, "Revocations will be unsigned", &[("reason", &err)]);
+            None
+        }
+    };
+    let base = root.parent().unwrap_or(root);
+    for entity in entities {
+        let marker = DiscoveryLayout::of(&entity.path).presence_file();
+        // Only entities the hub announced to have a Discovery folder worth writing into.
+        if marker.parent().is_none_or(|parent| !parent.is_dir()) {
+            continue;
+        }
+        let payload = revocation_payload(presence_key.as_ref(), &entity_name(base, &entity.path), rt.clock.now_millis());
+        match atomic_write(&marker, payload.as_bytes()) {
+            Ok(()) => append_hub_log(root, Level::Info, "Presence revoked", &[("entity", &entity.name)]),
+            Err(err) => append_hub_log(
+                root,
+                Level::Warn,
+                "Could not revoke presence",
+                &[("entity", &entity.name), ("error", &err.to_string())],
+            ),
+        }
+    }
+}
+
+/// Revoke one entity's presence, for example when it is decommissioned while the hub keeps
+/// running. Same marker as `revoke_presence`.
+pub fn revoke_one(root: &Path, entity: &EntityInfo) {
+    revoke_presence(root, std::slice::from_ref(entity));
+}
+
+/// Find every entity under the usual containers (the ecosystem's parent folder and its own
+/// `Discovery/`) and describe the hub itself. Nothing is written; see `refresh_presence`.
+pub fn discover(root: &Path) -> (EntityInfo, DiscoveryScan) {
+    let mut containers = Vec::new();
+    if let Some(parent) = root.parent() {
+        containers.push(parent.to_path_buf());
+    }
+    containers.push(DiscoveryLayout::of(root).root);
+
+    let mut scan = collect_entities(containers, DiscoveryLimits::from_env());
+    // The hub describes itself with its own descriptor; without one it is still an ecosystem.
+    let hub = load_entity_info(root, EntityKind::Ecosystem, &mut scan.warnings);
+    (hub, scan)
+}
+
+/// Discover every entity and re-sign their presence markers. Call this on a timer shorter than the
+/// presence TTL (every 5 minutes is a comfortable choice for the 15-minute default) so gateways
+/// keep seeing the hub as alive. Also rewrites `Discovery/registry.json`. Returns the scan so
+/// the caller can log descriptor warnings and route messages.
+pub fn refresh_presence(root: &Path) -> DiscoveryScan {
+    refresh_presence_with(root, Runtime::system())
+}
+
+/// `refresh_presence` with the key, intervals, and time taken from `rt`.
+pub fn refresh_presence_with(root: &Path, rt: Runtime<'_>) -> DiscoveryScan {
+    let (hub, scan) = discover(root);
+    if !scan.entities.is_empty() {
+        announce_presence_with(root, &hub, &scan.entities, rt);
+    }
+    // A one-off call has no daemon holding the state, so it reads and saves the file itself.
+    let mut state = HubState::load(root);
+    let heartbeats = check_heartbeats_with(root, &scan.entities, &mut state, rt);
+    let attestations = check_attestations_with(root, &scan.entities, &mut state, rt);
+    write_registry(root, &hub, &scan.entities, &heartbeats, &attestations, &mut state);
+    let _ = state.flush();
+    scan
+}
+
+/// Write a scan's descriptor warnings, depth-limit skips, and directory count to the hub log.
+pub fn log_discovery(root: &Path, scan: &DiscoveryScan) {
+    for warning in &scan.warnings {
+        append_hub_log(root, Level::Warn, "Descriptor warning", &[("detail", warning)]);
+    }
+    for skipped in &scan.truncated {
+        append_hub_log(
+            root,
+            Level::Warn,
+            "Discovery depth limit reached",
+            &[("skipped", &skipped.display().to_string()), ("raise", MAX_DEPTH_ENV)],
+        );
+    }
+    append_hub_log(root, Level::Info, "Discovery scanned", &[("dirs", &scan.scanned_dirs.to_string())]);
+}
+
+/// How long gateways accept a presence marker: `ECOSYSTEM_PRESENCE_TTL_SECS`, or 15 minutes.
+pub fn presence_ttl_secs() -> u64 {
+    presence_ttl_secs_from(&ProcessEnv)
+}
+
+/// `presence_ttl_secs` read from `env`.
+pub fn presence_ttl_secs_from(env: &dyn EnvSource) -> u64 {
+    env.var(PRESENCE_TTL_ENV)
+        .and_then(|raw| raw.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_PRESENCE_TTL_SECS)
+}
+
+/// The hex key a bot should use as its own `ECOSYSTEM_PRESENCE_KEY`, derived from the hub's
+/// master key for the entity named `name` (e.g. `ecosystem/Discovery/squire`).
+pub fn derive_key_hex(name: &str) -> Result<String, String> {
+    match load_presence_key(&ProcessEnv)? {
+        PresenceKey::Hmac(master) => Ok(to_hex(&derive_entity_key(&master, name))),
+        PresenceKey::Legacy(_) => {
+            Err("Legacy SipHash keys are shared by every entity; there is nothing to derive.".to_string())
+        }
+    }
+}
+
+/// Remove temporary files that a crash left in the hub's and every entity's `Discovery/` folder
+/// (see `atomic::clean_stale_temps`). The hub runs this once at startup.
+pub fn clean_leftover_temps(root: &Path, entities: &[EntityInfo]) {
+    let folders = std::iter::once(DiscoveryLayout::of(root).root)
+        .chain(entities.iter().map(|entity| DiscoveryLayout::of(&entity.path).root));
+    let mut removed = 0;
+    for folder in folders {
+        removed += clean_stale_temps(&folder);
+    }
+    if removed > 0 {
+        append_hub_log(root, Level::Warn, "Removed temporary files left by a crash", &[("files", &removed.to_string())]);
+    }
+}
+
+/// Log a hub event: the line goes to the shared log sink (standard error by default) and is
+/// also appended to `Discovery/hub_queue.log`, so operators can audit the hub from its folder.
+/// Both copies use the format picked by `SQUIRE_LOG_FORMAT`, and lines below `SQUIRE_LOG_LEVEL`
+/// are skipped in both. The file is a `QueueFile`, so it rotates to `hub_queue.log.1` once full.
+pub fn append_hub_log(root: &Path, level: Level, message: &str, fields: &[(&str, &str)]) {
+    let Some(line) = LOG.format(level, message, fields) else {
+        return;
+    };
+    let _ = QueueFile::new(DiscoveryLayout::of(root).hub_log()).append(&line);
+    log::emit(&line);
+}
+
+/// One queue line in the routing format `to=<entity-name>|from=<entity-name>|body=<text>`.
+///
+/// Entity names are folder names (`squire`) or the relative names used for keys
+/// (`ecosystem/Discovery/squire`). The body is everything after `body=`, so it may contain `|`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutedMessage {
+    pub to: String,
+    pub from: String,
+    pub body: String,
+}
+
+/// Parse one queue line, explaining what is wrong when it is not a routable message.
+pub fn parse_routed_line(line: &str) -> Result<RoutedMessage, String> {
+    let mut parts = line.splitn(3, '|');
+    let to = parts
+        .next()
+        .and_then(|part| part.strip_prefix("to="))
+        .map(str::trim)
+        .filter(|to| !to.is_empty())
+        .ok_or_else(|| "missing to=".to_string())?;
+    let from = parts
+        .next()
+        .and_then(|part| part.strip_prefix("from="))
+        .map(str::trim)
+        .filter(|from| !from.is_empty())
+        .ok_or_else(|| "missing from=".to_string())?;
+    let body = parts
+        .next()
+        .and_then(|part| part.strip_prefix("body="))
+        .ok_or_else(|| "missing body=".to_string())?;
+
+    Ok(RoutedMessage { to: to.to_string(), from: from.to_string(), body: body.to_string() })
+}
+
+/// What one routing pass did, for the hub log.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RouteReport {
+    pub delivered: usize,
+    pub dead_lettered: usize,
+}
+
+/// Move new lines from every entity's `gateway_queue.log` into the recipients' `inbox.log`.
+///
+/// Each delivery appends the original line to `<recipient>/Discovery/inbox.log` and a receipt
+/// `delivered=<millis> id=<sha256 of the line>` to `<sender>/Discovery/receipts.log`. Only lines
+/// starting with `to=` are routed: the same file carries the bot's ordinary dispatch lines, which
+/// Squire's gateway sends and the hub leaves alone. A `to=` line that does not parse, names an
+/// unknown recipient, or has a `from=` that does not match the queue's owner goes to the hub's own
+/// `Discovery/dead_letter.log` instead.
+///
+/// Queues are append-only from the bots' side, so the hub remembers a `QueueCursor` (rotation
+/// generation and byte offset) per queue as `routing.offsets.<entity>` in `state` and only reads
+/// what was added since. Running the hub twice does not deliver anything twice. A half-written last line
+/// (no newline yet) is left for the next pass. Lines longer than the queue line cap arrive cut and
+/// marked, and a queue that has reached its size limit is rotated after it was read; Squire's
+/// gateway follows the rotation with its own cursor.
+pub fn route_messages(root: &Path, entities: &[EntityInfo], state: &mut HubState) -> RouteReport {
+    let base = root.parent().unwrap_or(root);
+    let mut report = RouteReport::default();
+
+    for source in entities {
+        let source_name = entity_name(base, &source.path);
+        let queue = QueueFile::new(DiscoveryLayout::of(&source.path).dispatch_file());
+        let cursor = state.get_in(hub_state::ROUTING_OFFSETS, &source_name).and_then(QueueCursor::parse).unwrap_or_default();
+        let Ok(batch) = queue.read_from(cursor) else {
+            continue;
+        };
+        if batch.truncated > 0 {
+            append_hub_log(
+                root,
+                Level::Warn,
+                "Cut over-long queue lines",
+                &[("entity", &source_name), ("lines", &batch.truncated.to_string())],
+            );
+        }
+
+        for raw_line in &batch.lines {
+            let line = raw_line.trim();
+            // Everything else is the gateway's to send (see `dispatch_messages` in Squire).
+            if !line.starts_with("to=") {
+                continue;
+            }
+
+            match resolve_recipient(line, source, base, entities) {
+                Ok(recipient) => {
+                    append_line(&DiscoveryLayout::of(&recipient).inbox_file(), line);
+                    append_line(
+                        &DiscoveryLayout::of(&source.path).receipts_file(),
+                        &format!("delivered={} id={}", now_millis(), to_hex(&sha256(line.as_bytes()))),
+                    );
+                    report.delivered += 1;
+                }
+                Err(reason) => {
+                    let _ = QueueFile::new(DiscoveryLayout::of(root).dead_letter_file()).append(&format!(
+                        "at={} source={} reason={} line={}",
+                        now_millis(),
+                        source_name,
+                        reason,
+                        line
+                    ));
+                    report.dead_lettered += 1;
+                }
+            }
+        }
+
+        // Save after every queue so a crash part-way through repeats at most one queue. A failed
+        // write is tried again by the next flush.
+        state.set_in(hub_state::ROUTING_OFFSETS, &source_name, &batch.cursor.to_state());
+        let _ = state.flush();
+        if let Err(err) = queue.rotate_if_full() {
+            append_hub_log(root, Level::Warn, "Could not rotate queue", &[("entity", &source_name), ("error", &err.to_string())]);
+        }
+    }
+
+    report
+}
+
+/// Check a queue line and find the entity it is addressed to.
+fn resolve_recipient(line: &str, source: &EntityInfo, base: &Path, entities: &[EntityInfo]) -> Result<PathBuf, String> {
+    let message = parse_routed_line(line)?;
+    if !names_entity(&message.from, source, base) {
+        return Err(format!("from={} does not match the queue owner", message.from));
+    }
+    entities
+        .iter()
+        .find(|entity| names_entity(&message.to, entity, base))
+        .map(|entity| entity.path.clone())
+        .ok_or_else(|| format!("unknown recipient {}", message.to))
+}
+
+/// True when `name` is the entity's advertised name, folder name, or full relative name.
+fn names_entity(name: &str, entity: &EntityInfo, base: &Path) -> bool {
+    entity.name == name
+        || entity.path.file_name().is_some_and(|folder| folder.to_string_lossy() == name)
+        || entity_name(base, &entity.path) == name
+}
+
+/// How an entity's heartbeat looked to the hub.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeartbeatState {
+    /// Rewritten within `HEARTBEAT_STALE_FACTOR` expected intervals.
+    Alive,
+    /// The file exists but is older than that (or cannot be parsed).
+    Stale,
+    /// No heartbeat file. Bots that do not write one yet always show up like this.
+    Missing,
+}
+
+impl HeartbeatState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HeartbeatState::Alive => "alive",
+            HeartbeatState::Stale => "stale",
+            HeartbeatState::Missing => "missing",
+        }
+    }
+}
+
+/// One entity's heartbeat, as `check_heartbeats` found it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeartbeatStatus {
+    /// The entity's relative name, as in the registry's `path`.
+    pub name: String,
+    pub state: HeartbeatState,
+    pub pid: Option<u32>,
+    pub seq: Option<u64>,
+    /// How long ago the heartbeat was written.
+    pub age_ms: Option<u64>,
+    /// The sequence number went down since the last check, so the process started over.
+    pub restarted: bool,
+}
+
+impl HeartbeatStatus {
+    /// The `heartbeat` object used in `registry.json`. Unknown numbers are `null`.
+    pub fn to_json(&self) -> String {
+        let number = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        format!(
+            "{{\"state\":\"{}\",\"pid\":{},\"seq\":{},\"age_ms\":{},\"restarted\":{}}}",
+            self.state.as_str(),
+            number(self.pid.map(|pid| pid.to_string())),
+            number(self.seq.map(|seq| seq.to_string())),
+            number(self.age_ms.map(|age| age.to_string())),
+            self.restarted
+        )
+    }
+}
+
+/// How often bots are expected to beat: `ECOSYSTEM_HEARTBEAT_INTERVAL_SECS`, or 60 seconds.
+pub fn heartbeat_interval_secs() -> u64 {
+    heartbeat_interval_secs_from(&ProcessEnv)
+}
+
+/// `heartbeat_interval_secs` read from `env`.
+pub fn heartbeat_interval_secs_from(env: &dyn EnvSource) -> u64 {
+    env.var(HEARTBEAT_INTERVAL_ENV)
+        .and_then(|raw| raw.trim().parse::<u64>().ok())
+        .filter(|seconds| *seconds > 0)
+        .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL_SECS)
+}
+
+/// Read every entity's `Discovery/heartbeat.txt` and sort it into alive, stale, or missing.
+///
+/// A heartbeat is alive while it is younger than three expected intervals, which forgives one or
+/// two slow cycles before anyone gets warned. The hub keeps the last sequence number it saw per
+/// entity in `state`, as `heartbeat.last_seq.<entity>`. Bots count up from 1 each time they start,
+/// so a number lower than the saved one means the bot restarted in between; that shows up as
+/// `restarted` once.
+pub fn check_heartbeats(root: &Path, entities: &[EntityInfo], state: &mut HubState) -> Vec<HeartbeatStatus> {
+    check_heartbeats_with(root, entities, state, Runtime::system())
+}
+
+/// `check_heartbeats` with the interval from `rt.env` and the current time from `rt.clock`.
+pub fn check_heartbeats_with(root: &Path, entities: &[EntityInfo], state: &mut HubState, rt: Runtime<'_>) -> Vec<HeartbeatStatus> {
+    let base = root.parent().unwrap_or(root);
+    let stale_after_ms = heartbeat_interval_secs_from(rt.env).saturating_mul(HEARTBEAT_STALE_FACTOR).saturating_mul(1000);
+    let now = rt.clock.now_millis();
+
+    let statuses: Vec<HeartbeatStatus> = entities
+        .iter()
+        .map(|entity| {
+            let name = entity_name(base, &entity.path);
+            let mut status =
+                HeartbeatStatus { name, state: HeartbeatState::Missing, pid: None, seq: None, age_ms: None, restarted: false };
+            let Ok(text) = fs::read_to_string(DiscoveryLayout::of(&entity.path).heartbeat_file()) else {
+                return status;
+            };
+            let Some((pid, seq, at)) = parse_heartbeat(&text) else {
+                // Present but unreadable: the bot is doing something, just not saying what.
+                status.state = HeartbeatState::Stale;
+                return status;
+            };
+            // A clock that runs slightly ahead on the bot's side counts as "just now".
+            let age = now.saturating_sub(at) as u64;
+            status.state = if age <= stale_after_ms { HeartbeatState::Alive } else { HeartbeatState::Stale };
+            status.restarted = state
+                .get_in(hub_state::HEARTBEAT_LAST_SEQ, &status.name)
+                .and_then(|previous| previous.parse::<u64>().ok())
+                .is_some_and(|previous| seq < previous);
+            state.set_in(hub_state::HEARTBEAT_LAST_SEQ, &status.name, &seq.to_string());
+            status.pid = Some(pid);
+            status.seq = Some(seq);
+            status.age_ms = Some(age);
+            status
+        })
+        .collect();
+    statuses
+}
+
+/// Parse `pid=<pid> seq=<n> at=<unix millis>`. The fields may come in any order.
+pub(crate) fn parse_heartbeat(text: &str) -> Option<(u32, u64, u128)> {
+    let (mut pid, mut seq, mut at) = (None, None, None);
+    for field in text.split_whitespace() {
+        match field.split_once('=')? {
+            ("pid", value) => pid = value.parse().ok(),
+            ("seq", value) => seq = value.parse().ok(),
+            ("at", value) => at = value.parse().ok(),
+            _ => {}
+        }
+    }
+    Some((pid?, seq?, at?))
+}
+
+/// Log one summary line for the cycle plus a warning for each stale or restarted entity.
+/// Missing heartbeats are only counted, since not every bot writes one.
+pub fn log_heartbeats(root: &Path, heartbeats: &[HeartbeatStatus]) {
+    let count = |state: HeartbeatState| heartbeats.iter().filter(|status| status.state == state).count().to_string();
+    append_hub_log(
+        root,
+        Level::Info,
+        "Heartbeats checked",
+        &[
+            ("alive", &count(HeartbeatState::Alive)),
+            ("stale", &count(HeartbeatState::Stale)),
+            ("missing", &count(HeartbeatState::Missing)),
+        ],
+    );
+    for status in heartbeats {
+        let age = status.age_ms.map(|age| age.to_string()).unwrap_or_else(|| "unknown".to_string());
+        if status.state == HeartbeatState::Stale {
+            append_hub_log(root, Level::Warn, "Heartbeat is stale", &[("entity", &status.name), ("age_ms", &age)]);
+        }
+        if status.restarted {
+            let pid = status.pid.map(|pid| pid.to_string()).unwrap_or_default();
+            append_hub_log(root, Level::Warn, "Entity restarted", &[("entity", &status.name), ("pid", &pid)]);
+        }
+    }
+}
+
+/// Whether an entity's gateway proved it holds the entity's key (see `check_attestations`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttestationState {
+    /// The answer is signed with the entity's derived key, names the current nonce, and is
+    /// younger than the challenge TTL.
+    Attested,
+    /// Correctly signed, but for an older nonce or longer ago than the TTL.
+    Stale,
+    /// Signed with another key, or not an answer at all. Logged as an ALERT.
+    Failed,
+    /// No answer yet. Entities without a gateway always show up like this.
+    Missing,
+}
+
+impl AttestationState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttestationState::Attested => "attested",
+            AttestationState::Stale => "stale",
+            AttestationState::Failed => "failed",
+            AttestationState::Missing => "missing",
+        }
+    }
+}
+
+/// One entity's answer to its challenge, as `check_attestations` judged it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttestationStatus {
+    /// The entity's relative name, as in the registry's `path`.
+    pub name: String,
+    pub state: AttestationState,
+    /// The SHA-256 of its binary that the gateway signed, once an answer checks out.
+    pub binary_sha256: Option<String>,
+    /// How long ago the answer was written.
+    pub age_ms: Option<u64>,
+    /// Why the state is not `attested`, in words.
+    pub reason: Option<String>,
+}
+
+impl AttestationStatus {
+    /// The `attestation` object used in `registry.json`. Unknown values are `null`.
+    pub fn to_json(&self) -> String {
+        let text = |value: &Option<String>| value.as_ref().map_or("null".to_string(), |value| format!("\"{}\"", json_escape(value)));
+        format!(
+            "{{\"state\":\"{}\",\"binary_sha256\":{},\"age_ms\":{},\"reason\":{}}}",
+            self.state.as_str(),
+            text(&self.binary_sha256),
+            self.age_ms.map_or("null".to_string(), |age| age.to_string()),
+            text(&self.reason)
+        )
+    }
+}
+
+/// How long a challenge stays good: `ECOSYSTEM_CHALLENGE_TTL_SECS`, or 10 minutes.
+pub fn challenge_ttl_secs_from(env: &dyn EnvSource) -> u64 {
+    env.var(CHALLENGE_TTL_ENV)
+        .and_then(|raw| raw.trim().parse::<u64>().ok())
+        .filter(|seconds| *seconds > 0)
+        .unwrap_or(DEFAULT_CHALLENGE_TTL_SECS)
+}
+
+/// Issue challenges and check every entity's answer.
+///
+/// The presence marker proves the hub spoke; this proves the other direction. The hub writes a
+/// random nonce to each entity's `Discovery/challenge.txt` and keeps its own copy in `state`
+/// (`attestation.nonce.<entity>`), so an entity cannot swap in an old nonce. The gateway
+/// answers in `Discovery/challenge_response.txt` with `nonce=`, `binary_sha256=`, `at=`, and
+/// `signature=`, the hex HMAC-SHA256 of the nonce bytes followed by the 32 hash bytes, keyed
+/// with the entity's derived key (`HMAC(master, entity name)`, the key its presence markers use).
+/// A process that only squats on the folder does not have that key.
+///
+/// A nonce older than the TTL is replaced by a new one, so an answer for the old nonce is stale
+/// until the gateway answers again on its next flush. Only HMAC presence keys can be derived per
+/// entity; without one nothing is issued and the list is empty.
+pub fn check_attestations(root: &Path, entities: &[EntityInfo], state: &mut HubState) -> Vec<AttestationStatus> {
+    check_attestations_with(root, entities, state, Runtime::system())
+}
+
+/// `check_attestations` with the key and TTL from `rt.env` and the current time from `rt.clock`.
+pub fn check_attestations_with(root: &Path, entities: &[EntityInfo], state: &mut HubState, rt: Runtime<'_>) -> Vec<AttestationStatus> {
+    let Ok(PresenceKey::Hmac(master)) = load_presence_key(rt.env) else {
+        return Vec::new();
+    };
+    let base = root.parent().unwrap_or(root);
+    let ttl_secs = challenge_ttl_secs_from(rt.env);
+    let ttl_ms = u128::from(ttl_secs) * 1000;
+    let now = rt.clock.now_millis();
+
+    let statuses = entities
+        .iter()
+        .map(|entity| {
+            let name = entity_name(base, &entity.path);
+            let layout = DiscoveryLayout::of(&entity.path);
+            let current = state.get_in(hub_state::ATTESTATION_NONCE, &name).and_then(|raw| {
+                let (nonce, at) = raw.split_once(':')?;
+                Some((nonce.to_string(), at.parse::<u128>().ok()?))
+            });
+            let nonce = match current {
+                Some((nonce, issued_at)) if now.saturating_sub(issued_at) < ttl_ms => nonce,
+                _ => {
+                    let nonce = fresh_nonce(&name, now);
+                    state.set_in(hub_state::ATTESTATION_NONCE, &name, &format!("{nonce}:{now}"));
+                    let challenge = format!("nonce={nonce}\nissued_at={now}\nttl_secs={ttl_secs}\n");
+                    if layout.root.is_dir() {
+                        if let Err(err) = atomic_write(&layout.challenge_file(), challenge.as_bytes()) {
+                            append_hub_log(root, Level::Warn, "Could not write challenge", &[("entity", &name), ("error", &err.to_string())]);
+                        }
+                    }
+                    nonce
+                }
+            };
+            let key = derive_entity_key(&master, &name);
+            let response = fs::read_to_string(layout.challenge_response_file()).ok();
+            judge_response(name, response.as_deref(), &nonce, &key, now, ttl_ms)
+        })
+        .collect();
+    statuses
+}
+
+/// Sort one answer (`None` when there is none) into the four states.
+fn judge_response(name: String, response: Option<&str>, nonce: &str, key: &[u8; 32], now: u128, ttl_ms: u128) -> AttestationStatus {
+    let mut status = AttestationStatus { name, state: AttestationState::Missing, binary_sha256: None, age_ms: None, reason: None };
+    let Some(text) = response else {
+        status.reason = Some("no challenge_response.txt yet".to_string());
+        return status;
+    };
+    let field = |prefix: &str| text.lines().find_map(|line| line.strip_prefix(prefix)).map(str::trim);
+    let parsed = (|| {
+        let answered = field("nonce=").ok_or("no nonce= line")?;
+        let hash = field("binary_sha256=").filter(|hash| hash.len() == 64).ok_or("no 64-character binary_sha256= line")?;
+        let at = field("at=").and_then(|at| at.parse::<u128>().ok()).ok_or("no at= time")?;
+        let signature = field("signature=").ok_or("no signature= line")?;
+        let mut message = from_hex(answered).ok_or("nonce is not hex")?;
+        message.extend(from_hex(hash).ok_or("binary_sha256 is not hex")?);
+        Ok::<_, &str>((answered, hash, at, signature, message))
+    })();
+    let (answered, hash, at, signature, message) = match parsed {
+        Ok(parsed) => parsed,
+        Err(problem) => {
+            status.state = AttestationState::Failed;
+            status.reason = Some(format!("malformed response: {problem}"));
+            return status;
+        }
+    };
+    if !from_hex(signature).is_some_and(|signature| constant_time_eq(&signature, &hmac_sha256(key, &message))) {
+        status.state = AttestationState::Failed;
+        status.reason = Some("signature does not match this entity's key".to_string());
+        return status;
+    }
+    let age = now.saturating_sub(at);
+    status.binary_sha256 = Some(hash.to_ascii_lowercase());
+    status.age_ms = Some(age as u64);
+    (status.state, status.reason) = if answered != nonce {
+        (AttestationState::Stale, Some("answers an older challenge".to_string()))
+    } else if age > ttl_ms {
+        (AttestationState::Stale, Some(format!("answered {}s ago, longer than the challenge TTL", age / 1000)))
+    } else {
+        (AttestationState::Attested, None)
+    };
+    status
+}
+
+/// 16 random bytes as hex. They come from `/dev/urandom` where it exists; elsewhere the
+/// standard library's randomly seeded `RandomState` hasher is mixed with the time and name
+/// through SHA-256. A nonce only has to be unpredictable, not secret.
+fn fresh_nonce(name: &str, now: u128) -> String {
+    use std::hash::{BuildHasher, Hasher};
+    use std::io::Read;
+
+    let mut bytes = [0u8; 16];
+    let from_os = fs::File::open("/dev/urandom").and_then(|mut file| file.read_exact(&mut bytes)).is_ok();
+    if !from_os {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write(name.as_bytes());
+        hasher.write_u128(now);
+        let seed = format!("{}|{}|{}|{}", hasher.finish(), std::process::id(), name, now);
+        bytes.copy_from_slice(&sha256(seed.as_bytes())[..16]);
+    }
+    to_hex(&bytes)
+}
+
+/// Log one summary line for the cycle, a warning for each stale answer, and an ALERT line for
+/// each failed one. Missing answers are only counted, since not every bot runs a gateway.
+pub fn log_attestations(root: &Path, attestations: &[AttestationStatus]) {
+    if attestations.is_empty() {
+        return;
+    }
+    let count = |state: AttestationState| attestations.iter().filter(|status| status.state == state).count().to_string();
+    append_hub_log(
+        root,
+        Level::Info,
+        "Attestations checked",
+        &[
+            ("attested", &count(AttestationState::Attested)),
+            ("stale", &count(AttestationState::Stale)),
+            ("failed", &count(AttestationState::Failed)),
+            ("missing", &count(AttestationState::Missing)),
+        ],
+    );
+    for status in attestations {
+        let reason = status.reason.as_deref().unwrap_or("");
+        match status.state {
+            AttestationState::Stale => append_hub_log(root, Level::Warn, "Attestation is stale", &[("entity", &status.name), ("reason", reason)]),
+            AttestationState::Failed => append_hub_log(
+                root,
+                Level::Error,
+                "ALERT attestation failed: a process without this entity's key answered its challenge",
+                &[("entity", &status.name), ("reason", reason)],
+            ),
+            AttestationState::Attested | AttestationState::Missing => {}
+        }
+    }
+}
+
+/// Append one line to a file under its lock, creating it (and its folder) when needed.
+fn append_line(path: &Path, line: &str) {
+    let _ = append_locked(path, line);
+}
+
+/// Milliseconds since 1970, used for nonces and receipts.
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Clock, ManualClock, MapEnv};
+
+    const MASTER: [u8; 32] = [7; 32];
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ecosystem-comm-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn bot(path: PathBuf) -> EntityInfo {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        EntityInfo { path, name, kind: EntityKind::Bot, capabilities: Vec::new() }
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path).unwrap_or_default().lines().map(str::to_string).collect()
+    }
+
+    /// Flip the last hex digit, so only the final byte of the signature differs.
+    fn tamper_last_byte(signature: &str) -> String {
+        let (head, last) = signature.split_at(signature.len() - 1);
+        format!("{head}{}", if last == "0" { "1" } else { "0" })
+    }
+
+    fn signed_marker(name: &str, signed_at: u128) -> (String, String) {
+        let nonce = format!("{name}|{signed_at}");
+        let signature = sign_presence(&PresenceKey::Hmac(derive_entity_key(&MASTER, name)), &nonce);
+        (nonce, signature)
+    }
+
+    #[test]
+    fn marker_with_our_signature_is_fresh_then_stale() {
+        let (nonce, signature) = signed_marker("bots/alpha", 1_000);
+        let contents = format!("nonce={nonce}\nsignature={}", signature.to_ascii_uppercase());
+        let key = PresenceKey::Hmac(MASTER);
+        assert_eq!(check_marker(&key, "bots/alpha", &contents, 5_000, 60), Ok(MarkerState::Fresh));
+        assert_eq!(check_marker(&key, "bots/alpha", &contents, 71_000, 60), Ok(MarkerState::Stale { age_secs: 70 }));
+    }
+
+    #[test]
+    fn marker_signature_differing_only_in_last_byte_is_rejected() {
+        let (nonce, signature) = signed_marker("bots/alpha", 1_000);
+        let contents = format!("nonce={nonce}\nsignature={}", tamper_last_byte(&signature));
+        let result = check_marker(&PresenceKey::Hmac(MASTER), "bots/alpha", &contents, 1_000, 60);
+        assert_eq!(result, Err("signature does not match this hub's key".to_string()));
+    }
+
+    #[test]
+    fn marker_signed_for_another_entity_is_rejected() {
+        let (nonce, signature) = signed_marker("bots/alpha", 1_000);
+        let contents = format!("nonce={nonce}\nsignature={signature}");
+        assert!(check_marker(&PresenceKey::Hmac(MASTER), "bots/beta", &contents, 1_000, 60).is_err());
+    }
+
+    #[test]
+    fn signature_matches_needs_hex_of_the_same_bytes() {
+        assert!(signature_matches("00ff", "00FF"));
+        assert!(!signature_matches("00ff", "00fe"));
+        assert!(!signature_matches("00ff", "00ff00"));
+        assert!(!signature_matches("00ff", "not-hex"));
+    }
+
+    fn attestation_response(key: &[u8; 32], nonce: &str, hash: &str, at: u128) -> (String, String) {
+        let mut message = from_hex(nonce).unwrap();
+        message.extend(from_hex(hash).unwrap());
+        let signature = to_hex(&hmac_sha256(key, &message));
+        (format!("nonce={nonce}\nbinary_sha256={hash}\nat={at}\n"), signature)
+    }
+
+    #[test]
+    fn attestation_with_last_byte_changed_fails() {
+        let key = [9u8; 32];
+        let nonce = "00112233445566778899aabbccddeeff";
+        let hash = "ab".repeat(32);
+        let (body, signature) = attestation_response(&key, nonce, &hash, 2_000);
+
+        let good = format!("{body}signature={signature}\n");
+        let status = judge_response("bots/alpha".to_string(), Some(&good), nonce, &key, 2_500, 60_000);
+        assert_eq!(status.state, AttestationState::Attested);
+
+        let forged = format!("{body}signature={}\n", tamper_last_byte(&signature));
+        let status = judge_response("bots/alpha".to_string(), Some(&forged), nonce, &key, 2_500, 60_000);
+        assert_eq!(status.state, AttestationState::Failed);
+        assert_eq!(status.reason.as_deref(), Some("signature does not match this entity's key"));
+    }
+
+    #[test]
+    fn routing_skips_dispatch_lines_and_dead_letters_only_bad_routed_ones() {
+        let base = temp_dir("route");
+        let root = base.join("ecosystem");
+        let (squire, bard) = (bot(base.join("squire")), bot(base.join("bard")));
+        fs::create_dir_all(DiscoveryLayout::of(&squire.path).dispatch_file().parent().unwrap()).unwrap();
+        fs::write(
+            DiscoveryLayout::of(&squire.path).dispatch_file(),
+            "Server restarted\n\
+             to=bard|from=squire|body=Quest board updated\n\
+             Nightly backup done | 3 files\n\
+             to=nobody|from=squire|body=lost\n\
+             to=bard|from=bard|body=spoofed\n\
+             to=bard\n",
+        )
+        .unwrap();
+        let entities = [squire.clone(), bard.clone()];
+        let mut state = HubState::in_memory();
+
+        let report = route_messages(&root, &entities, &mut state);
+        assert_eq!(report, RouteReport { delivered: 1, dead_lettered: 3 });
+        assert_eq!(lines(&DiscoveryLayout::of(&bard.path).inbox_file()), ["to=bard|from=squire|body=Quest board updated"]);
+        assert_eq!(lines(&DiscoveryLayout::of(&squire.path).receipts_file()).len(), 1);
+        let dead = lines(&DiscoveryLayout::of(&root).dead_letter_file());
+        assert_eq!(dead.len(), 3);
+        assert!(dead.iter().all(|line| line.contains(" line=to=")), "{dead:?}");
+        assert!(dead[0].contains("reason=unknown recipient nobody"));
+        assert!(dead[1].contains("reason=from=bard does not match the queue owner"));
+        assert!(dead[2].contains("reason=missing from="));
+
+        // Nothing new: a second pass neither delivers nor dead-letters again.
+        assert_eq!(route_messages(&root, &entities, &mut state), RouteReport::default());
+    }
+
+    #[test]
+    fn delivery_writes_a_receipt_and_resumes_after_a_restart() {
+        let base = temp_dir("route-receipts");
+        let root = base.join("ecosystem");
+        let (squire, bard) = (bot(base.join("squire")), bot(base.join("bard")));
+        let queue = DiscoveryLayout::of(&squire.path).dispatch_file();
+        fs::create_dir_all(queue.parent().unwrap()).unwrap();
+        let line = "to=bard|from=squire|body=Quest board updated";
+        fs::write(&queue, format!("{line}\nto=bard|from=squire|body=half wri")).unwrap();
+        let entities = [squire.clone(), bard.clone()];
+        // `announce_presence` creates the hub's own folder before the first routing pass.
+        fs::create_dir_all(DiscoveryLayout::of(&root).hub_state_file().parent().unwrap()).unwrap();
+
+        let mut state = HubState::load(&root);
+        assert_eq!(route_messages(&root, &entities, &mut state), RouteReport { delivered: 1, dead_lettered: 0 });
+        let receipts = lines(&DiscoveryLayout::of(&squire.path).receipts_file());
+        let (delivered, id) = receipts[0].split_once(' ').unwrap();
+        assert!(delivered.strip_prefix("delivered=").unwrap().parse::<u128>().is_ok(), "{delivered}");
+        assert_eq!(id, format!("id={}", to_hex(&sha256(line.as_bytes()))));
+
+        // The cursor was saved, so a hub started again only picks up the finished line.
+        let mut queue_file = fs::OpenOptions::new().append(true).open(&queue).unwrap();
+        std::io::Write::write_all(&mut queue_file, b"tten\n").unwrap();
+        let mut state = HubState::load(&root);
+        assert_eq!(route_messages(&root, &entities, &mut state), RouteReport { delivered: 1, dead_lettered: 0 });
+        assert_eq!(
+            lines(&DiscoveryLayout::of(&bard.path).inbox_file()),
+            [line, "to=bard|from=squire|body=half written"]
+        );
+        assert_eq!(lines(&DiscoveryLayout::of(&squire.path).receipts_file()).len(), 2);
+        assert_eq!(route_messages(&root, &entities, &mut HubState::load(&root)), RouteReport::default());
+    }
+
+    #[test]
+    fn routed_lines_need_to_from_and_body_in_order() {
+        let message = parse_routed_line("to=bard|from=squire|body=a|b").unwrap();
+        assert_eq!((message.to.as_str(), message.from.as_str(), message.body.as_str()), ("bard", "squire", "a|b"));
+        assert_eq!(parse_routed_line("to= |from=squire|body=x").unwrap_err(), "missing to=");
+        assert_eq!(parse_routed_line("to=bard|body=x").unwrap_err(), "missing from=");
+        assert_eq!(parse_routed_line("to=bard|from=squire").unwrap_err(), "missing body=");
+    }
+
+    fn entity_dir(parent: &Path, name: &str, descriptor: Option<&str>) -> PathBuf {
+        let path = parent.join(name);
+        fs::create_dir_all(DiscoveryLayout::of(&path).root).unwrap();
+        if let Some(text) = descriptor {
+            fs::write(DiscoveryLayout::of(&path).descriptor_file(), text).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn a_well_formed_descriptor_is_read_in_full() {
+        let base = temp_dir("descriptor-good");
+        let path = entity_dir(&base, "squire-folder", Some("# who we are\nname = \"squire\"\nkind=bot\n\ncapabilities=logging, \"xp\",,moderation\n"));
+        let mut warnings = Vec::new();
+        let info = load_entity_info(&path, EntityKind::Unknown, &mut warnings);
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(info.name, "squire");
+        assert_eq!(info.kind, EntityKind::Bot);
+        assert_eq!(info.capabilities, ["logging", "xp", "moderation"]);
+    }
+
+    #[test]
+    fn a_missing_descriptor_gives_the_folder_name_and_default_kind() {
+        let base = temp_dir("descriptor-missing");
+        let path = entity_dir(&base, "bard", None);
+        let mut warnings = Vec::new();
+        let info = load_entity_info(&path, EntityKind::Unknown, &mut warnings);
+        assert_eq!(info, EntityInfo { path: path.clone(), name: "bard".to_string(), kind: EntityKind::Unknown, capabilities: Vec::new() });
+        assert_eq!(load_entity_info(&path, EntityKind::Ecosystem, &mut warnings).kind, EntityKind::Ecosystem);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn a_malformed_descriptor_warns_and_keeps_the_good_lines() {
+        let base = temp_dir("descriptor-bad");
+        let path = entity_dir(&base, "odd", Some("name=odd-bot\nkind=robot\njust words\ncolour=blue\ncapabilities=xp\n"));
+        let mut warnings = Vec::new();
+        let info = load_entity_info(&path, EntityKind::Unknown, &mut warnings);
+        assert_eq!((info.name.as_str(), info.kind), ("odd-bot", EntityKind::Unknown));
+        assert_eq!(info.capabilities, ["xp"]);
+        assert_eq!(warnings.len(), 3, "{warnings:?}");
+        assert!(warnings[0].ends_with(":2: unknown kind \"robot\" (expected bot, ecosystem, or sentinel)"), "{}", warnings[0]);
+        assert!(warnings[1].ends_with(":3: expected key=value, got \"just words\""), "{}", warnings[1]);
+        assert!(warnings[2].ends_with(":4: unknown key \"colour\""), "{}", warnings[2]);
+    }
+
+    #[test]
+    fn the_registry_lists_the_hub_and_every_entity() {
+        let base = temp_dir("registry");
+        let root = entity_dir(&base, "ecosystem", None);
+        let squire = entity_dir(&base, "squire", Some("name=squire\nkind=bot\ncapabilities=logging,xp\n"));
+        entity_dir(&base, "bard", Some("kind=robot\n"));
+        fs::create_dir_all(base.join("not-an-entity")).unwrap();
+
+        let (hub, scan) = discover(&root);
+        assert_eq!(hub.kind, EntityKind::Ecosystem);
+        assert_eq!(scan.warnings.len(), 1, "{:?}", scan.warnings);
+        let mut state = HubState::in_memory();
+        write_registry(&root, &hub, &scan.entities, &[], &[], &mut state);
+
+        let text = fs::read_to_string(DiscoveryLayout::of(&root).registry_file()).unwrap();
+        let document = ecosystem_common::json::parse(&text).unwrap();
+        assert_eq!(document.get("hub").and_then(|hub| hub.get("kind")).and_then(|kind| kind.as_str()), Some("ecosystem"));
+        let entities = document.get("entities").and_then(|entities| entities.as_array()).unwrap();
+        let mut described: Vec<(String, String, Vec<String>)> = entities
+            .iter()
+            .map(|entity| {
+                let field = |name: &str| entity.get(name).and_then(|value| value.as_str()).unwrap().to_string();
+                let capabilities = entity.get("capabilities").and_then(|list| list.as_array()).unwrap();
+                (field("path"), field("kind"), capabilities.iter().map(|c| c.as_str().unwrap().to_string()).collect())
+            })
+            .collect();
+        described.sort();
+        assert_eq!(
+            described,
+            [
+                ("bard".to_string(), "unknown".to_string(), vec![]),
+                ("ecosystem".to_string(), "unknown".to_string(), vec![]),
+                ("squire".to_string(), "bot".to_string(), vec!["logging".to_string(), "xp".to_string()]),
+            ]
+        );
+        assert!(scan.entities.iter().any(|entity| entity.path == squire));
+        assert_eq!(state.get(hub_state::REGISTRY_LAST_HASH), Some(to_hex(&sha256(text.as_bytes()))).as_deref());
+    }
+
+    /// `levels` entities nested one inside the next's `Discovery/` folder, under `container`.
+    fn nested_entities(container: &Path, levels: usize) -> Vec<PathBuf> {
+        let mut parent = container.to_path_buf();
+        let mut made = Vec::new();
+        for level in 0..levels {
+            let path = entity_dir(&parent, &format!("level{level}"), None);
+            parent = DiscoveryLayout::of(&path).root;
+            made.push(path);
+        }
+        made
+    }
+
+    #[test]
+    fn discovery_finds_everything_within_the_depth_limit() {
+        let base = temp_dir("depth-within");
+        let made = nested_entities(&base, 4);
+        let scan = collect_entities(vec![base.clone()], DiscoveryLimits { max_depth: 6, follow_symlinks: false });
+        let found: Vec<&PathBuf> = scan.entities.iter().map(|entity| &entity.path).collect();
+        assert_eq!(found, made.iter().collect::<Vec<_>>());
+        assert!(scan.truncated.is_empty());
+        // The container plus the Discovery folder of every level.
+        assert_eq!(scan.scanned_dirs, 5);
+    }
+
+    #[test]
+    fn discovery_stops_at_the_depth_limit_and_logs_the_skipped_subtree() {
+        let base = temp_dir("depth-limit");
+        let made = nested_entities(&base, 5);
+        let scan = collect_entities(vec![base.clone()], DiscoveryLimits { max_depth: 2, follow_symlinks: false });
+        assert_eq!(scan.entities.len(), 3);
+        assert_eq!(scan.truncated, [DiscoveryLayout::of(&made[2]).root]);
+
+        let root = base.join("ecosystem");
+        log_discovery(&root, &scan);
+        let log = lines(&DiscoveryLayout::of(&root).hub_log()).join("\n");
+        assert!(log.contains("Discovery depth limit reached"), "{log}");
+        assert!(log.contains(&DiscoveryLayout::of(&made[2]).root.display().to_string()), "{log}");
+        assert!(log.contains(MAX_DEPTH_ENV));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_cycle_ends_whether_or_not_links_are_followed() {
+        let base = temp_dir("symlink-cycle");
+        let container = base.join("bots");
+        let bot = entity_dir(&container, "squire", None);
+        // `squire/Discovery/loop` points back at squire, whose Discovery folder holds the link.
+        std::os::unix::fs::symlink(&bot, DiscoveryLayout::of(&bot).root.join("loop")).unwrap();
+
+        for follow_symlinks in [false, true] {
+            let scan = collect_entities(vec![container.clone()], DiscoveryLimits { max_depth: 50, follow_symlinks });
+            let found: Vec<&PathBuf> = scan.entities.iter().map(|entity| &entity.path).collect();
+            assert_eq!(found, [&bot], "follow_symlinks={follow_symlinks}");
+            assert!(scan.truncated.is_empty());
+            assert!(scan.scanned_dirs <= 3, "follow_symlinks={follow_symlinks}: {}", scan.scanned_dirs);
+        }
+    }
+
+    fn write_beat(entity: &EntityInfo, text: &str) {
+        let path = DiscoveryLayout::of(&entity.path).heartbeat_file();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn heartbeats_are_alive_stale_or_missing_by_age() {
+        let base = temp_dir("heartbeats");
+        let root = base.join("ecosystem");
+        let entities = [bot(base.join("squire")), bot(base.join("bard")), bot(base.join("sentry")), bot(base.join("quiet"))];
+        let now = 1_700_000_000_000u128;
+        write_beat(&entities[0], &format!("pid=41 seq=3 at={}\n", now - 5_000));
+        // Ten seconds between beats: anything older than thirty seconds is stale.
+        write_beat(&entities[1], &format!("seq=9 at={} pid=42\n", now - 31_000));
+        write_beat(&entities[2], "pid=43 seq=oops at=1\n");
+        let clock = ManualClock::new(now);
+        let env = MapEnv::new().with(HEARTBEAT_INTERVAL_ENV, "10");
+        let rt = Runtime { clock: &clock, sleeper: &clock, env: &env };
+
+        let statuses = check_heartbeats_with(&root, &entities, &mut HubState::in_memory(), rt);
+        let states: Vec<(&str, HeartbeatState)> = statuses.iter().map(|status| (status.name.as_str(), status.state)).collect();
+        assert_eq!(
+            states,
+            [("squire", HeartbeatState::Alive), ("bard", HeartbeatState::Stale), ("sentry", HeartbeatState::Stale), ("quiet", HeartbeatState::Missing)]
+        );
+        assert_eq!((statuses[0].pid, statuses[0].seq, statuses[0].age_ms), (Some(41), Some(3), Some(5_000)));
+        assert_eq!(statuses[1].age_ms, Some(31_000));
+        assert_eq!(statuses[3].to_json(), "{\"state\":\"missing\",\"pid\":null,\"seq\":null,\"age_ms\":null,\"restarted\":false}");
+
+        // Exactly three intervals old still counts as alive; a bot clock running ahead is "just now".
+        write_beat(&entities[1], &format!("pid=42 seq=10 at={}\n", now - 30_000));
+        write_beat(&entities[2], &format!("pid=43 seq=1 at={}\n", now + 2_000));
+        let statuses = check_heartbeats_with(&root, &entities[1..3], &mut HubState::in_memory(), rt);
+        assert_eq!((statuses[0].state, statuses[1].state, statuses[1].age_ms), (HeartbeatState::Alive, HeartbeatState::Alive, Some(0)));
+        assert_eq!(heartbeat_interval_secs_from(&MapEnv::new().with(HEARTBEAT_INTERVAL_ENV, "0")), DEFAULT_HEARTBEAT_INTERVAL_SECS);
+    }
+
+    #[test]
+    fn a_lower_sequence_number_is_reported_as_a_restart_once() {
+        let base = temp_dir("heartbeat-restart");
+        let root = base.join("ecosystem");
+        let squire = bot(base.join("squire"));
+        let now = 1_700_000_000_000u128;
+        let clock = ManualClock::new(now);
+        let env = MapEnv::new();
+        let rt = Runtime { clock: &clock, sleeper: &clock, env: &env };
+        let mut state = HubState::in_memory();
+        let mut check = |seq: u64| {
+            write_beat(&squire, &format!("pid=7 seq={seq} at={now}\n"));
+            check_heartbeats_with(&root, std::slice::from_ref(&squire), &mut state, rt)[0].restarted
+        };
+
+        assert!(!check(40), "nothing to compare with yet");
+        assert!(!check(41));
+        assert!(check(1), "the count started over");
+        assert!(!check(2));
+        assert!(!check(2), "an unchanged beat is not a restart");
+    }
+
+    #[test]
+    fn a_revocation_is_signed_and_reads_back_as_revoked() {
+        let base = temp_dir("revoke");
+        let root = base.join("ecosystem");
+        let (alpha, beta) = (bot(base.join("alpha")), bot(base.join("beta")));
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&beta.path).unwrap();
+        let clock = ManualClock::new(1_700_000_000_000);
+        let env = MapEnv::new().with(PRESENCE_KEY_ENV, &"07".repeat(32));
+        let rt = Runtime { clock: &clock, sleeper: &clock, env: &env };
+        let hub = bot(root.clone());
+        announce_presence_with(&root, &hub, std::slice::from_ref(&alpha), rt);
+        let marker = DiscoveryLayout::of(&alpha.path).presence_file();
+        let key = PresenceKey::Hmac(MASTER);
+        let contents = fs::read_to_string(&marker).unwrap();
+        assert_eq!(check_marker(&key, "alpha", &contents, clock.now_millis(), 60), Ok(MarkerState::Fresh));
+
+        // A `status=revoked` line that the signed nonce does not back changes nothing.
+        let forged = format!("{contents}\nstatus=revoked");
+        assert_eq!(check_marker(&key, "alpha", &forged, clock.now_millis(), 60), Ok(MarkerState::Fresh));
+
+        clock.advance(std::time::Duration::from_secs(5));
+        revoke_presence_with(&root, &[alpha.clone(), beta.clone()], rt);
+        let revoked = fs::read_to_string(&marker).unwrap();
+        assert!(revoked.starts_with("nonce=alpha|1700000005000|revoked\n") && revoked.ends_with("\nstatus=revoked"), "{revoked}");
+        // Still revoked long after the TTL: only a new announcement undoes it.
+        assert_eq!(check_marker(&key, "alpha", &revoked, clock.now_millis() + 3_600_000, 60), Ok(MarkerState::Revoked));
+        assert!(!DiscoveryLayout::of(&beta.path).presence_file().exists(), "never announced to, so nothing to revoke");
+        assert!(lines(&DiscoveryLayout::of(&root).hub_log()).iter().any(|line| line.contains("Presence revoked")));
+
+        // A revocation signed for another entity, or by another key, is not accepted.
+        assert!(check_marker(&key, "beta", &revoked, clock.now_millis(), 60).is_err());
+        assert!(check_marker(&PresenceKey::Hmac([8; 32]), "alpha", &revoked, clock.now_millis(), 60).is_err());
+
+        announce_presence_with(&root, &hub, std::slice::from_ref(&alpha), rt);
+        let again = fs::read_to_string(&marker).unwrap();
+        assert_eq!(check_marker(&key, "alpha", &again, clock.now_millis(), 60), Ok(MarkerState::Fresh));
+    }
+
+    #[test]
+    fn a_revocation_without_a_key_is_unsigned() {
+        let base = temp_dir("revoke-unsigned");
+        let root = base.join("ecosystem");
+        let alpha = bot(base.join("alpha"));
+        fs::create_dir_all(DiscoveryLayout::of(&alpha.path).presence_file().parent().unwrap()).unwrap();
+        let clock = ManualClock::new(1_700_000_000_000);
+        let env = MapEnv::new();
+        revoke_presence_with(&root, std::slice::from_ref(&alpha), Runtime { clock: &clock, sleeper: &clock, env: &env });
+        let contents = fs::read_to_string(DiscoveryLayout::of(&alpha.path).presence_file()).unwrap();
+        assert!(contents.contains(&format!("signature=missing-{PRESENCE_KEY_ENV}")), "{contents}");
+        assert!(check_marker(&PresenceKey::Hmac(MASTER), "alpha", &contents, 0, 60).unwrap_err().starts_with("unsigned"));
+    }
+
+    /// The nonce in the entity's `challenge.txt`.
+    fn issued_nonce(entity: &EntityInfo) -> String {
+        let text = fs::read_to_string(DiscoveryLayout::of(&entity.path).challenge_file()).unwrap();
+        text.lines().find_map(|line| line.strip_prefix("nonce=")).unwrap().to_string()
+    }
+
+    /// Answer the entity's current challenge the way its gateway does, signed with `key`.
+    fn answer(entity: &EntityInfo, key: &[u8; 32], at: u128) {
+        let (body, signature) = attestation_response(key, &issued_nonce(entity), &"cd".repeat(32), at);
+        fs::write(DiscoveryLayout::of(&entity.path).challenge_response_file(), format!("{body}signature={signature}\n")).unwrap();
+    }
+
+    #[test]
+    fn gateways_that_answer_with_their_key_are_attested_and_others_are_not() {
+        let base = temp_dir("attest");
+        let root = base.join("ecosystem");
+        let entities = [bot(base.join("alpha")), bot(base.join("beta")), bot(base.join("gamma"))];
+        for entity in &entities {
+            fs::create_dir_all(DiscoveryLayout::of(&entity.path).root).unwrap();
+        }
+        let clock = ManualClock::new(1_700_000_000_000);
+        let env = MapEnv::new().with(PRESENCE_KEY_ENV, &"07".repeat(32)).with(CHALLENGE_TTL_ENV, "60");
+        let rt = Runtime { clock: &clock, sleeper: &clock, env: &env };
+        let mut state = HubState::in_memory();
+
+        let first = check_attestations_with(&root, &entities, &mut state, rt);
+        assert!(first.iter().all(|status| status.state == AttestationState::Missing));
+        let challenge = fs::read_to_string(DiscoveryLayout::of(&entities[0].path).challenge_file()).unwrap();
+        assert!(challenge.contains(&format!("issued_at={}\nttl_secs=60\n", clock.now_millis())), "{challenge}");
+        assert_ne!(issued_nonce(&entities[0]), issued_nonce(&entities[1]));
+
+        clock.advance(std::time::Duration::from_secs(5));
+        answer(&entities[0], &derive_entity_key(&MASTER, "alpha"), clock.now_millis());
+        // Beta's answer is signed with gamma's key: a squatter that took the wrong file.
+        answer(&entities[1], &derive_entity_key(&MASTER, "gamma"), clock.now_millis());
+        let statuses = check_attestations_with(&root, &entities, &mut state, rt);
+        let states: Vec<AttestationState> = statuses.iter().map(|status| status.state).collect();
+        assert_eq!(states, [AttestationState::Attested, AttestationState::Failed, AttestationState::Missing]);
+        assert_eq!(statuses[0].binary_sha256.as_deref(), Some("cd".repeat(32).as_str()));
+        assert_eq!(statuses[0].to_json(), format!("{{\"state\":\"attested\",\"binary_sha256\":\"{}\",\"age_ms\":0,\"reason\":null}}", "cd".repeat(32)));
+        assert_eq!(statuses[1].reason.as_deref(), Some("signature does not match this entity's key"));
+
+        log_attestations(&root, &statuses);
+        let log = lines(&DiscoveryLayout::of(&root).hub_log());
+        assert!(log.iter().any(|line| line.contains("ALERT attestation failed") && line.contains("beta")), "{log:?}");
+        assert!(log.iter().any(|line| line.contains("Attestations checked") && line.contains("attested=1") && line.contains("failed=1")), "{log:?}");
+
+        let hub = bot(root.clone());
+        write_registry(&root, &hub, &entities, &[], &statuses, &mut state);
+        let registry = fs::read_to_string(DiscoveryLayout::of(&root).registry_file()).unwrap();
+        assert!(registry.contains("\"path\":\"alpha\",\"kind\":\"bot\",\"capabilities\":[],\"attestation\":{\"state\":\"attested\""), "{registry}");
+    }
+
+    #[test]
+    fn a_rotated_nonce_leaves_the_old_answer_stale() {
+        let base = temp_dir("attest-rotate");
+        let root = base.join("ecosystem");
+        let alpha = bot(base.join("alpha"));
+        fs::create_dir_all(DiscoveryLayout::of(&alpha.path).root).unwrap();
+        let clock = ManualClock::new(1_700_000_000_000);
+        let env = MapEnv::new().with(PRESENCE_KEY_ENV, &"07".repeat(32)).with(CHALLENGE_TTL_ENV, "60");
+        let rt = Runtime { clock: &clock, sleeper: &clock, env: &env };
+        let mut state = HubState::in_memory();
+        let key = derive_entity_key(&MASTER, "alpha");
+        let check = |state: &mut HubState| check_attestations_with(&root, std::slice::from_ref(&alpha), state, rt).remove(0);
+
+        check(&mut state);
+        let old_nonce = issued_nonce(&alpha);
+        answer(&alpha, &key, clock.now_millis());
+        assert_eq!(check(&mut state).state, AttestationState::Attested);
+        clock.advance(std::time::Duration::from_secs(59));
+        assert_eq!(issued_nonce(&alpha), old_nonce, "a nonce younger than the TTL is kept");
+
+        clock.advance(std::time::Duration::from_secs(1));
+        let rotated = check(&mut state);
+        assert_ne!(issued_nonce(&alpha), old_nonce);
+        assert_eq!((rotated.state, rotated.reason.as_deref()), (AttestationState::Stale, Some("answers an older challenge")));
+        answer(&alpha, &key, clock.now_millis());
+        assert_eq!(check(&mut state).state, AttestationState::Attested);
+        assert!(check_attestations_with(&root, std::slice::from_ref(&alpha), &mut state, Runtime { clock: &clock, sleeper: &clock, env: &MapEnv::new() }).is_empty(), "no key, no challenges");
+    }
+}
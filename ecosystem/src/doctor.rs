@@ -0,0 +1,548 @@
+//! `ecosystem-hub doctor`: check that the hub, the bots, and Sentry are wired together.
+//!
+//! A half-working setup usually fails quietly: the hub runs without `ECOSYSTEM_PRESENCE_KEY`, so
+//! every marker is unsigned; a gateway runs from another folder than the one the hub announced
+//! to; a Sentry manifest points at a bins folder that was moved. `doctor` looks at each of these
+//! once and prints one line per check:
+//! - `PASS`: nothing to do;
+//! - `WARN`: works, but probably not the way you meant (a bot that is not running, say);
+//! - `FAIL`: this part cannot work until it is fixed.
+//!
+//! Each check is a plain function from a `DoctorContext` to one `CheckResult`, listed in
+//! `CHECKS`. Adding a check means writing one more such function and adding it to the list.
+//! Nothing is changed on disk, apart from a probe file that the writability check creates and
+//! removes straight away.
+//!
+//! Settings are read the way the hub reads them, after its `.env` is loaded. A bot started
+//! with a different `.env` may see different values; run `doctor` with that file too
+//! (`SQUIRE_ENV_FILE=...`) to check it.
+
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::comm::{self, DiscoveryLayout, EntityInfo, MarkerState, PresenceKey};
+use crate::dotenv;
+use crate::runtime::Runtime;
+use crate::self_verify::MANIFEST_ENV;
+
+/// Bot token variable read by Squire's gateway.
+const TOKEN_ENV: &str = "SQUIRE_DISCORD_TOKEN";
+/// Vault envelope holding the bot token instead (Squire's `vault` feature).
+const TOKEN_ENVELOPE_ENV: &str = "SQUIRE_TOKEN_ENVELOPE";
+/// Master key that opens the token envelope.
+const VAULT_KEY_ENV: &str = "SQUIRE_VAULT_KEY";
+
+/// How one check went. Ordered from best to worst, so the overall result is the maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CheckStatus::Pass => "pass",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+        }
+    }
+
+    /// Process exit code for this overall result: 0 for pass, 4 for warnings, 2 for a failure
+    /// (the same numbers Sentry's `status` uses).
+    pub fn exit_code(self) -> i32 {
+        match self {
+            CheckStatus::Pass => 0,
+            CheckStatus::Warn => 4,
+            CheckStatus::Fail => 2,
+        }
+    }
+}
+
+/// The outcome of one check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    /// Short, stable identifier such as `presence_key`, for scripts reading the JSON.
+    pub name: &'static str,
+    pub status: CheckStatus,
+    /// One sentence for a person: what was found, and for a warning or failure what to do.
+    pub detail: String,
+}
+
+impl CheckResult {
+    pub fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: CheckStatus::Pass, detail: detail.into() }
+    }
+
+    pub fn warn(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: CheckStatus::Warn, detail: detail.into() }
+    }
+
+    pub fn fail(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: CheckStatus::Fail, detail: detail.into() }
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"name\":\"{}\",\"status\":\"{}\",\"detail\":\"{}\"}}",
+            self.name,
+            self.status.as_str(),
+            comm::json_escape(&self.detail)
+        )
+    }
+}
+
+/// What the checks look at. `main` builds it from the real world; other code can point it at a
+/// temporary folder with a `ManualClock` and a `MapEnv`.
+pub struct DoctorContext<'a> {
+    /// The hub's folder (`--root`).
+    pub root: PathBuf,
+    /// Entities found by discovery under `root`.
+    pub entities: Vec<EntityInfo>,
+    /// The `.env` file the hub would load, if any (see `dotenv::default_dotenv_path`).
+    pub dotenv_path: Option<PathBuf>,
+    pub rt: Runtime<'a>,
+}
+
+impl<'a> DoctorContext<'a> {
+    /// Discover the entities under `root`; the `.env` path is left for the caller to fill in.
+    pub fn discover(root: &Path, rt: Runtime<'a>) -> Self {
+        let (_, scan) = comm::discover(root);
+        Self { root: root.to_path_buf(), entities: scan.entities, dotenv_path: None, rt }
+    }
+
+    /// The name keys are derived from, e.g. `ecosystem/Discovery/squire`.
+    fn name_of(&self, entity: &EntityInfo) -> String {
+        comm::entity_name(self.root.parent().unwrap_or(&self.root), &entity.path)
+    }
+}
+
+/// One check.
+pub type Check = fn(&DoctorContext<'_>) -> CheckResult;
+
+/// Every check, in the order they are printed.
+pub const CHECKS: &[Check] = &[
+    check_dotenv,
+    check_presence_key,
+    check_presence_markers,
+    check_heartbeats,
+    check_discovery_writable,
+    check_sentry_manifest,
+    check_discord_token,
+];
+
+/// Run every check in `CHECKS`.
+pub fn run_checks(ctx: &DoctorContext<'_>) -> Vec<CheckResult> {
+    CHECKS.iter().map(|check| check(ctx)).collect()
+}
+
+/// The worst status among `results` (`Pass` when there are none).
+pub fn overall(results: &[CheckResult]) -> CheckStatus {
+    results.iter().map(|result| result.status).max().unwrap_or(CheckStatus::Pass)
+}
+
+/// One line per check, then the overall result, for a terminal.
+pub fn render_text(results: &[CheckResult]) -> String {
+    let mut out = String::new();
+    for result in results {
+        out.push_str(&format!("{:<5}{:<20}{}\n", result.status.as_str().to_uppercase(), result.name, result.detail));
+    }
+    out.push_str(&format!("overall: {}\n", overall(results).as_str()));
+    out
+}
+
+/// `{"action":"doctor","overall":"warn","checks":[{"name":...,"status":...,"detail":...}]}`.
+pub fn render_json(results: &[CheckResult]) -> String {
+    let checks: Vec<String> = results.iter().map(CheckResult::to_json).collect();
+    format!("{{\"action\":\"doctor\",\"overall\":\"{}\",\"checks\":[{}]}}", overall(results).as_str(), checks.join(","))
+}
+
+/// Is there a `.env` to load, and does every line in it parse?
+pub fn check_dotenv(ctx: &DoctorContext<'_>) -> CheckResult {
+    const NAME: &str = "dotenv";
+    let Some(path) = &ctx.dotenv_path else {
+        return CheckResult::warn(
+            NAME,
+            "no .env beside the executable or in the current folder; settings come only from the environment",
+        );
+    };
+    match fs::read_to_string(path) {
+        Err(err) => CheckResult::fail(NAME, format!("{} cannot be read: {err} (check {})", path.display(), dotenv::ENV_FILE_ENV)),
+        Ok(text) => {
+            let parsed = dotenv::parse_dotenv(&text);
+            if parsed.warnings.is_empty() {
+                CheckResult::pass(NAME, format!("{} sets {} variable(s)", path.display(), parsed.vars.len()))
+            } else {
+                CheckResult::warn(NAME, format!("{} has lines that are skipped: {}", path.display(), parsed.warnings.join("; ")))
+            }
+        }
+    }
+}
+
+/// Is `ECOSYSTEM_PRESENCE_KEY` set, and the right length?
+pub fn check_presence_key(ctx: &DoctorContext<'_>) -> CheckResult {
+    const NAME: &str = "presence_key";
+    match comm::load_presence_key(ctx.rt.env) {
+        Ok(PresenceKey::Hmac(_)) => CheckResult::pass(NAME, "64 hex characters (HMAC-SHA256 master key)"),
+        Ok(PresenceKey::Legacy(_)) => {
+            CheckResult::warn(NAME, "legacy 32-hex-character SipHash key in use; move to a 64-hex-character key")
+        }
+        Err(err) => CheckResult::fail(NAME, format!("{err}; gateways will ignore every presence marker")),
+    }
+}
+
+/// Does every discovered entity have a marker signed with this hub's key, and is it fresh?
+pub fn check_presence_markers(ctx: &DoctorContext<'_>) -> CheckResult {
+    const NAME: &str = "presence_markers";
+    if ctx.entities.is_empty() {
+        return CheckResult::warn(NAME, "no entities discovered, so there are no markers to check");
+    }
+    let Ok(key) = comm::load_presence_key(ctx.rt.env) else {
+        return CheckResult::warn(NAME, "skipped: no usable presence key to check signatures with");
+    };
+    let ttl = comm::presence_ttl_secs_from(ctx.rt.env);
+    let now = ctx.rt.clock.now_millis();
+    let mut failures = Vec::new();
+    let mut warnings = Vec::new();
+    for entity in &ctx.entities {
+        let name = ctx.name_of(entity);
+        let marker = DiscoveryLayout::of(&entity.path).presence_file();
+        let Ok(contents) = fs::read_to_string(&marker) else {
+            failures.push(format!("{name}: missing ({})", marker.display()));
+            continue;
+        };
+        match comm::check_marker(&key, &name, &contents, now, ttl) {
+            Ok(MarkerState::Fresh) => {}
+            Ok(MarkerState::Stale { age_secs }) => warnings.push(format!("{name}: stale, signed {age_secs}s ago (TTL {ttl}s); is the hub running?")),
+            Ok(MarkerState::Revoked) => warnings.push(format!("{name}: revoked by the hub")),
+            Err(err) => failures.push(format!("{name}: {err}")),
+        }
+    }
+    let problems = failures.iter().chain(&warnings).cloned().collect::<Vec<_>>().join("; ");
+    if !failures.is_empty() {
+        CheckResult::fail(NAME, problems)
+    } else if !warnings.is_empty() {
+        CheckResult::warn(NAME, problems)
+    } else {
+        CheckResult::pass(NAME, format!("{} marker(s) signed and fresh", ctx.entities.len()))
+    }
+}
+
+/// Is every entity writing its heartbeat? Read-only: unlike `check_heartbeats`, no restart state
+/// is saved.
+pub fn check_heartbeats(ctx: &DoctorContext<'_>) -> CheckResult {
+    const NAME: &str = "heartbeats";
+    let stale_after_ms = u128::from(comm::heartbeat_interval_secs_from(ctx.rt.env)) * 3 * 1000;
+    let now = ctx.rt.clock.now_millis();
+    let mut problems = Vec::new();
+    for entity in &ctx.entities {
+        let name = ctx.name_of(entity);
+        let heartbeat = DiscoveryLayout::of(&entity.path).heartbeat_file();
+        match fs::read_to_string(&heartbeat).ok().as_deref().map(comm::parse_heartbeat) {
+            None => problems.push(format!("{name}: no heartbeat file (not running, or started from another folder?)")),
+            Some(None) => problems.push(format!("{name}: heartbeat file cannot be parsed")),
+            Some(Some((_, _, at))) if now.saturating_sub(at) > stale_after_ms => {
+                problems.push(format!("{name}: last beat {}s ago", now.saturating_sub(at) / 1000))
+            }
+            Some(Some(_)) => {}
+        }
+    }
+    // A bot that is simply not running is worth knowing, not a broken setup.
+    if problems.is_empty() {
+        CheckResult::pass(NAME, format!("{} heartbeat(s) fresh", ctx.entities.len()))
+    } else {
+        CheckResult::warn(NAME, problems.join("; "))
+    }
+}
+
+/// Can the hub write into its own and every entity's `Discovery/` folder?
+pub fn check_discovery_writable(ctx: &DoctorContext<'_>) -> CheckResult {
+    const NAME: &str = "discovery_writable";
+    // The hub's own folder is usually one of the entities too; a set checks it only once.
+    let folders: BTreeSet<PathBuf> = std::iter::once(DiscoveryLayout::of(&ctx.root).root)
+        .chain(ctx.entities.iter().map(|entity| DiscoveryLayout::of(&entity.path).root))
+        .collect();
+    let mut problems = Vec::new();
+    let mut checked = 0;
+    for folder in folders {
+        checked += 1;
+        if let Err(err) = probe_writable(&folder) {
+            problems.push(format!("{}: {err}", folder.display()));
+        }
+    }
+    if problems.is_empty() {
+        CheckResult::pass(NAME, format!("{checked} Discovery folder(s) writable"))
+    } else {
+        CheckResult::fail(NAME, problems.join("; "))
+    }
+}
+
+/// Create and remove a small file in `folder`. The name carries the process id, so two doctors
+/// never collide, and `create_new` never touches an existing file.
+fn probe_writable(folder: &Path) -> Result<(), String> {
+    if !folder.is_dir() {
+        return Err("not a folder".to_string());
+    }
+    let probe = folder.join(format!(".doctor-probe-{}", std::process::id()));
+    let result = OpenOptions::new().write(true).create_new(true).open(&probe).and_then(|mut file| file.write_all(b"probe\n"));
+    let _ = fs::remove_file(&probe);
+    result.map_err(|err| err.to_string())
+}
+
+/// When `SQUIRE_MANIFEST` is set: can the manifest be read, and do the folders its entries
+/// point at exist?
+pub fn check_sentry_manifest(ctx: &DoctorContext<'_>) -> CheckResult {
+    const NAME: &str = "sentry_manifest";
+    let Some(path) = ctx.rt.env.var(MANIFEST_ENV).filter(|path| !path.trim().is_empty()) else {
+        return CheckResult::pass(NAME, format!("{MANIFEST_ENV} is unset; no self-check configured"));
+    };
+    let path = PathBuf::from(path.trim());
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) => return CheckResult::fail(NAME, format!("{} cannot be read: {err}", path.display())),
+    };
+    if !text.lines().any(|line| line.strip_prefix("release_id=").is_some_and(|id| !id.trim().is_empty())) {
+        return CheckResult::fail(NAME, format!("{} has no release_id; it is not a Sentry manifest", path.display()));
+    }
+    // Entry lines are `name|path|hash|size[|...]` after `entries:`. Newer manifests keep the
+    // paths relative to a `root=` header line; older ones are relative to where `build` ran,
+    // which is usually where the services start too.
+    let root = text.lines().take_while(|line| line.trim() != "entries:").find_map(|line| line.strip_prefix("root=")).map(PathBuf::from);
+    let entries: Vec<PathBuf> = text
+        .lines()
+        .skip_while(|line| line.trim() != "entries:")
+        .skip(1)
+        .map(|line| line.split('|').collect::<Vec<_>>())
+        .filter(|fields| fields.len() >= 4)
+        .map(|fields| root.as_ref().map_or_else(|| PathBuf::from(fields[1]), |root| root.join(fields[1])))
+        .collect();
+    if entries.is_empty() {
+        return CheckResult::fail(NAME, format!("{} lists no entries", path.display()));
+    }
+    let mut folders: Vec<PathBuf> = entries
+        .iter()
+        .map(|entry| entry.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf())
+        .collect();
+    folders.sort();
+    folders.dedup();
+    let missing: Vec<String> = folders.iter().filter(|folder| !folder.is_dir()).map(|folder| folder.display().to_string()).collect();
+    if !missing.is_empty() {
+        return CheckResult::fail(NAME, format!("bins folder(s) named in the manifest do not exist (moved?): {}", missing.join(", ")));
+    }
+    let absent = entries.iter().filter(|entry| !entry.is_file()).count();
+    if absent > 0 {
+        return CheckResult::warn(NAME, format!("{absent} of {} listed file(s) are missing", entries.len()));
+    }
+    CheckResult::pass(NAME, format!("{} entries, all present", entries.len()))
+}
+
+/// Does Squire have a token to log in with: `SQUIRE_DISCORD_TOKEN`, or a vault envelope and the
+/// key to open it?
+pub fn check_discord_token(ctx: &DoctorContext<'_>) -> CheckResult {
+    const NAME: &str = "discord_token";
+    let set = |name: &str| ctx.rt.env.var(name).filter(|value| !value.trim().is_empty());
+    if let Some(envelope) = set(TOKEN_ENVELOPE_ENV) {
+        let envelope = PathBuf::from(envelope.trim());
+        if !envelope.is_file() {
+            return CheckResult::fail(NAME, format!("{TOKEN_ENVELOPE_ENV} points at {}, which does not exist", envelope.display()));
+        }
+        if set(VAULT_KEY_ENV).is_none() {
+            return CheckResult::fail(NAME, format!("{TOKEN_ENVELOPE_ENV} is set but {VAULT_KEY_ENV} is not, so it cannot be opened"));
+        }
+        return CheckResult::pass(NAME, format!("vault envelope {}", envelope.display()));
+    }
+    if set(TOKEN_ENV).is_some() {
+        return CheckResult::pass(NAME, format!("{TOKEN_ENV} is set"));
+    }
+    // The gateway still runs without a token; it only prepares messages in dry-run mode.
+    CheckResult::warn(NAME, format!("neither {TOKEN_ENV} nor {TOKEN_ENVELOPE_ENV} is set; Squire will run dry"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comm::EntityKind;
+    use crate::runtime::{Clock, ManualClock, MapEnv};
+    use std::time::Duration;
+
+    const PRESENCE_KEY_ENV: &str = "ECOSYSTEM_PRESENCE_KEY";
+    const KEY_HEX: &str = "0707070707070707070707070707070707070707070707070707070707070707";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ecosystem-doctor-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entity(path: PathBuf) -> EntityInfo {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        EntityInfo { path, name, kind: EntityKind::Bot, capabilities: Vec::new() }
+    }
+
+    /// A hub at `<base>/ecosystem` that announced to one bot at `<base>/squire`, a bot that beats,
+    /// a `.env`, and a manifest whose one entry exists.
+    struct Layout {
+        root: PathBuf,
+        bot: EntityInfo,
+        dotenv: PathBuf,
+        manifest: PathBuf,
+    }
+
+    fn healthy(name: &str, clock: &ManualClock) -> Layout {
+        let base = temp_dir(name);
+        let root = base.join("ecosystem");
+        let bot = entity(base.join("squire"));
+        fs::create_dir_all(DiscoveryLayout::of(&root).root).unwrap();
+        fs::create_dir_all(DiscoveryLayout::of(&bot.path).root).unwrap();
+        let env = MapEnv::new().with(PRESENCE_KEY_ENV, KEY_HEX);
+        comm::announce_presence_with(&root, &entity(root.clone()), std::slice::from_ref(&bot), Runtime { clock, sleeper: clock, env: &env });
+        fs::write(DiscoveryLayout::of(&bot.path).heartbeat_file(), format!("pid=7 seq=1 at={}\n", clock.now_millis())).unwrap();
+
+        let dotenv = base.join(".env");
+        fs::write(&dotenv, "ECOSYSTEM_PRESENCE_TTL_SECS=900\n").unwrap();
+        let bins = base.join("bins");
+        fs::create_dir_all(&bins).unwrap();
+        fs::write(bins.join("squire"), b"v1").unwrap();
+        let manifest = base.join("manifest.txt");
+        fs::write(&manifest, format!("release_id=r1\nroot={}\nentries:\nsquire|squire|0011223344556677|2\n", bins.display())).unwrap();
+        Layout { root, bot, dotenv, manifest }
+    }
+
+    fn healthy_env(layout: &Layout) -> MapEnv {
+        MapEnv::new()
+            .with(PRESENCE_KEY_ENV, KEY_HEX)
+            .with(TOKEN_ENV, "token")
+            .with(MANIFEST_ENV, &layout.manifest.to_string_lossy())
+    }
+
+    fn check(layout: &Layout, clock: &ManualClock, env: &MapEnv) -> Vec<CheckResult> {
+        let ctx = DoctorContext {
+            root: layout.root.clone(),
+            entities: vec![layout.bot.clone()],
+            dotenv_path: Some(layout.dotenv.clone()),
+            rt: Runtime { clock, sleeper: clock, env },
+        };
+        run_checks(&ctx)
+    }
+
+    fn result<'a>(results: &'a [CheckResult], name: &str) -> &'a CheckResult {
+        results.iter().find(|result| result.name == name).unwrap()
+    }
+
+    #[test]
+    fn a_healthy_layout_passes_every_check() {
+        let clock = ManualClock::new(1_700_000_000_000);
+        let layout = healthy("healthy", &clock);
+        let results = check(&layout, &clock, &healthy_env(&layout));
+        assert_eq!(results.len(), CHECKS.len());
+        assert!(results.iter().all(|result| result.status == CheckStatus::Pass), "{results:#?}");
+        assert_eq!(overall(&results).exit_code(), 0);
+        assert_eq!(result(&results, "presence_markers").detail, "1 marker(s) signed and fresh");
+        assert!(render_text(&results).ends_with("overall: pass\n"));
+        let json = render_json(&results);
+        assert!(json.starts_with("{\"action\":\"doctor\",\"overall\":\"pass\",\"checks\":[{\"name\":\"dotenv\",\"status\":\"pass\""), "{json}");
+        assert_eq!(overall(&[]), CheckStatus::Pass);
+    }
+
+    #[test]
+    fn a_presence_key_of_the_wrong_length_fails() {
+        let clock = ManualClock::new(1_700_000_000_000);
+        let layout = healthy("bad-key", &clock);
+        let env = healthy_env(&layout).with(PRESENCE_KEY_ENV, "abcd");
+        let results = check(&layout, &clock, &env);
+        assert_eq!(result(&results, "presence_key").status, CheckStatus::Fail);
+        assert!(result(&results, "presence_key").detail.contains("gateways will ignore every presence marker"));
+        assert_eq!(result(&results, "presence_markers").status, CheckStatus::Warn);
+        assert_eq!(overall(&results).exit_code(), 2);
+
+        let results = check(&layout, &clock, &healthy_env(&layout).with(PRESENCE_KEY_ENV, &"1".repeat(64)));
+        assert!(result(&results, "presence_markers").detail.contains("squire: signature does not match"));
+    }
+
+    #[test]
+    fn missing_stale_and_revoked_markers_are_told_apart() {
+        let clock = ManualClock::new(1_700_000_000_000);
+        let layout = healthy("markers", &clock);
+        let env = healthy_env(&layout);
+        let marker = DiscoveryLayout::of(&layout.bot.path).presence_file();
+
+        clock.advance(Duration::from_secs(comm::presence_ttl_secs_from(&env) + 1));
+        let stale = check(&layout, &clock, &env);
+        assert_eq!(result(&stale, "presence_markers").status, CheckStatus::Warn);
+        assert!(result(&stale, "presence_markers").detail.contains("squire: stale"));
+        assert!(result(&stale, "heartbeats").detail.contains("squire: last beat 901s ago"));
+
+        comm::revoke_presence_with(&layout.root, std::slice::from_ref(&layout.bot), Runtime { clock: &clock, sleeper: &clock, env: &env });
+        assert_eq!(result(&check(&layout, &clock, &env), "presence_markers").detail, "squire: revoked by the hub");
+
+        fs::remove_file(&marker).unwrap();
+        fs::remove_file(DiscoveryLayout::of(&layout.bot.path).heartbeat_file()).unwrap();
+        let missing = check(&layout, &clock, &env);
+        assert_eq!(result(&missing, "presence_markers").status, CheckStatus::Fail);
+        assert!(result(&missing, "presence_markers").detail.starts_with("squire: missing"));
+        assert!(result(&missing, "heartbeats").detail.contains("no heartbeat file"));
+        assert_eq!(overall(&missing), CheckStatus::Fail);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_discovery_folder_that_cannot_be_written_fails() {
+        use std::os::unix::fs::PermissionsExt;
+        let clock = ManualClock::new(1_700_000_000_000);
+        let layout = healthy("unwritable", &clock);
+        let env = healthy_env(&layout);
+        let folder = DiscoveryLayout::of(&layout.bot.path).root;
+
+        fs::set_permissions(&folder, fs::Permissions::from_mode(0o555)).unwrap();
+        let results = check(&layout, &clock, &env);
+        // Root can write anywhere, so the permission case only shows for ordinary users.
+        let privileged = probe_writable(&folder).is_ok();
+        fs::set_permissions(&folder, fs::Permissions::from_mode(0o755)).unwrap();
+        if !privileged {
+            let writable = result(&results, "discovery_writable");
+            assert_eq!(writable.status, CheckStatus::Fail);
+            assert!(writable.detail.starts_with(&folder.display().to_string()), "{}", writable.detail);
+        }
+
+        fs::remove_dir_all(&folder).unwrap();
+        fs::write(&folder, "not a folder").unwrap();
+        let writable = result(&check(&layout, &clock, &env), "discovery_writable").clone();
+        assert_eq!(writable.status, CheckStatus::Fail);
+        assert!(writable.detail.ends_with(": not a folder"), "{}", writable.detail);
+    }
+
+    #[test]
+    fn manifest_token_and_dotenv_problems_are_specific() {
+        let clock = ManualClock::new(1_700_000_000_000);
+        let layout = healthy("settings", &clock);
+        let env = healthy_env(&layout);
+
+        fs::write(&layout.manifest, "release_id=r1\nroot=/no/such/bins\nentries:\nsquire|squire|0011223344556677|2\n").unwrap();
+        let manifest = result(&check(&layout, &clock, &env), "sentry_manifest").clone();
+        assert_eq!(manifest.status, CheckStatus::Fail);
+        assert!(manifest.detail.contains("do not exist (moved?): /no/such/bins"), "{}", manifest.detail);
+        fs::write(&layout.manifest, "entries:\n").unwrap();
+        assert!(result(&check(&layout, &clock, &env), "sentry_manifest").detail.contains("has no release_id"));
+        let unset = env.clone().with(MANIFEST_ENV, " ");
+        assert_eq!(result(&check(&layout, &clock, &unset), "sentry_manifest").status, CheckStatus::Pass);
+
+        let no_token = MapEnv::new().with(PRESENCE_KEY_ENV, KEY_HEX);
+        assert_eq!(result(&check(&layout, &clock, &no_token), "discord_token").status, CheckStatus::Warn);
+        let envelope = layout.dotenv.with_file_name("token.envelope");
+        let locked = no_token.clone().with(TOKEN_ENVELOPE_ENV, &envelope.to_string_lossy());
+        assert!(result(&check(&layout, &clock, &locked), "discord_token").detail.contains("which does not exist"));
+        fs::write(&envelope, "sealed").unwrap();
+        assert!(result(&check(&layout, &clock, &locked), "discord_token").detail.contains(&format!("{VAULT_KEY_ENV} is not")));
+        assert_eq!(result(&check(&layout, &clock, &locked.with(VAULT_KEY_ENV, "k")), "discord_token").status, CheckStatus::Pass);
+
+        fs::write(&layout.dotenv, "GOOD=1\nthis line has no equals sign\n").unwrap();
+        assert_eq!(result(&check(&layout, &clock, &env), "dotenv").status, CheckStatus::Warn);
+        let ctx = DoctorContext { root: layout.root.clone(), entities: Vec::new(), dotenv_path: None, rt: Runtime { clock: &clock, sleeper: &clock, env: &env } };
+        assert_eq!(check_dotenv(&ctx).status, CheckStatus::Warn);
+        assert_eq!(check_presence_markers(&ctx).detail, "no entities discovered, so there are no markers to check");
+    }
+}
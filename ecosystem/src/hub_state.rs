@@ -0,0 +1,553 @@
+//! The hub's own memory between cycles and restarts: one `Discovery/hub_state.json` document.
+//!
+//! Routing cursors, heartbeat sequence numbers, and attestation nonces used to live in three
+//! small `name<TAB>value` files. Every new feature that needed to remember something added
+//! another one. Now they share one document of string keys, each starting with the namespace
+//! of the feature that owns it:
+//! - `routing.offsets.<entity>`: how far the hub has routed that entity's queue (`2:5120`);
+//! - `heartbeat.last_seq.<entity>`: the last heartbeat sequence number seen, to notice restarts;
+//! - `attestation.nonce.<entity>`: the challenge nonce last issued, as `<nonce>:<issued_at millis>`;
+//! - `registry.last_hash`: the SHA-256 of the `registry.json` the hub last wrote.
+//!
+//! The daemon loads the document once at startup, the cycle steps read and change it through
+//! `get`, `set`, and `remove`, and `flush` writes it back (through `atomic_write`, so never half a
+//! file) at the end of every cycle and on the way out. Routing also flushes after every queue, so
+//! a crash repeats at most one queue's deliveries.
+//!
+//! The file looks like this (keys sorted, so two dumps can be compared with `diff`):
+//!
+//! ```text
+//! {
+//!   "version": 1,
+//!   "values": {
+//!     "heartbeat.last_seq.ecosystem/Discovery/bard": "17",
+//!     "registry.last_hash": "9f86d081..."
+//!   }
+//! }
+//! ```
+//!
+//! A document that cannot be read (someone edited it by hand, say) is not fatal: it is renamed to
+//! `hub_state.json.corrupt-<unix millis>` for later inspection, the hub logs a warning and starts
+//! with an empty state. The cost is one cycle of "restarted" guesses and re-reading queues from
+//! the saved start, which is why the old file is kept rather than deleted.
+//!
+//! When there is no `hub_state.json` yet, `load` imports the old `route_state.txt`,
+//! `heartbeat_state.txt`, and `challenge_state.txt` once, so an upgrade does not deliver every
+//! queue a second time. The old files are removed after the first successful flush.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::atomic::atomic_write;
+use crate::comm::{append_hub_log, json_escape, DiscoveryLayout};
+use crate::log::Level;
+
+/// Routing cursor per entity queue.
+pub const ROUTING_OFFSETS: &str = "routing.offsets";
+/// Last heartbeat sequence number per entity.
+pub const HEARTBEAT_LAST_SEQ: &str = "heartbeat.last_seq";
+/// Last challenge nonce issued per entity.
+pub const ATTESTATION_NONCE: &str = "attestation.nonce";
+/// SHA-256 (hex) of the last `registry.json` written. A single key, not a namespace.
+pub const REGISTRY_LAST_HASH: &str = "registry.last_hash";
+
+/// Format version written as `"version"`. Documents with a higher one are treated as unreadable.
+const FORMAT_VERSION: u64 = 1;
+
+/// The key-value store behind `hub_state.json`.
+#[derive(Debug, Clone, Default)]
+pub struct HubState {
+    /// Where `flush` writes. `None` for a state that lives only in memory.
+    path: Option<PathBuf>,
+    values: BTreeMap<String, String>,
+    /// True when `values` differ from what is on disk, so quiet cycles do not rewrite the file.
+    dirty: bool,
+    /// Old per-feature state files imported by `load`, removed after the first flush.
+    imported: Vec<PathBuf>,
+}
+
+impl HubState {
+    /// A state that is never written anywhere.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Read the hub whose ecosystem folder is `root` (its `Discovery/hub_state.json`).
+    ///
+    /// Never fails: a missing file starts empty (after importing the old state files), and a
+    /// damaged one is set aside as described in the module notes.
+    pub fn load(root: &Path) -> Self {
+        let layout = DiscoveryLayout::of(root);
+        let path = layout.hub_state_file();
+        let mut state = HubState { path: Some(path.clone()), ..Self::default() };
+
+        match fs::read_to_string(&path) {
+            Ok(text) => match parse_document(&text) {
+                Ok(values) => state.values = values,
+                Err(reason) => {
+                    let kept = set_aside(&path);
+                    append_hub_log(
+                        root,
+                        Level::Warn,
+                        "Hub state was unreadable; starting fresh",
+                        &[
+                            ("reason", &reason),
+                            ("kept_as", &kept.map(|kept| kept.display().to_string()).unwrap_or_else(|err| format!("not renamed: {err}"))),
+                        ],
+                    );
+                    state.dirty = true;
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => state.import_legacy(&layout),
+            Err(err) => {
+                // Unreadable for another reason (permissions, say): leave the file alone, since
+                // overwriting it on the next flush would lose it for good.
+                append_hub_log(root, Level::Warn, "Could not read hub state; starting fresh", &[("error", &err.to_string())]);
+                state.path = None;
+            }
+        }
+        state
+    }
+
+    /// Read the document without changing anything on disk, for `ecosystem-hub dump-state`. A
+    /// missing file is an empty state; a damaged one is an error saying why.
+    pub fn read_only(root: &Path) -> Result<Self, String> {
+        let path = DiscoveryLayout::of(root).hub_state_file();
+        match fs::read_to_string(&path) {
+            Ok(text) => parse_document(&text)
+                .map(|values| HubState { values, ..Self::default() })
+                .map_err(|reason| format!("{} is unreadable: {reason}", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(format!("Could not read {}: {err}", path.display())),
+        }
+    }
+
+    /// The value stored under `key`, e.g. `get("registry.last_hash")`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Store `value` under `key`, replacing any previous value.
+    pub fn set(&mut self, key: &str, value: &str) {
+        if self.get(key) != Some(value) {
+            self.values.insert(key.to_string(), value.to_string());
+            self.dirty = true;
+        }
+    }
+
+    /// Forget `key`, returning the value it had.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let removed = self.values.remove(key);
+        self.dirty |= removed.is_some();
+        removed
+    }
+
+    /// `get` for one entity inside a namespace: `get_in(ROUTING_OFFSETS, "ecosystem/Discovery/bard")`.
+    pub fn get_in(&self, namespace: &str, name: &str) -> Option<&str> {
+        self.get(&namespaced(namespace, name))
+    }
+
+    /// `set` for one entity inside a namespace.
+    pub fn set_in(&mut self, namespace: &str, name: &str, value: &str) {
+        self.set(&namespaced(namespace, name), value);
+    }
+
+    /// Every `(entity, value)` pair in a namespace. Keys of other namespaces never show up here,
+    /// even ones that start with the same letters (`routing.offsets_old.x` is not in `routing.offsets`).
+    pub fn entries_in<'a>(&'a self, namespace: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        let prefix = format!("{namespace}.");
+        self.values
+            .range(prefix.clone()..)
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+            .map(move |(key, value)| (&key[namespace.len() + 1..], value.as_str()))
+    }
+
+    /// Every key and value, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// True when there is something `flush` has not written yet.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Write the document if anything changed since the last flush. The file is replaced whole,
+    /// so a crash leaves the previous version or this one.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+        atomic_write(path, self.to_json().as_bytes())?;
+        self.dirty = false;
+        // The imported values are safe in the new file now.
+        for old in self.imported.drain(..) {
+            let _ = fs::remove_file(old);
+        }
+        Ok(())
+    }
+
+    /// The document as `flush` writes it; also what `ecosystem-hub dump-state` prints.
+    pub fn to_json(&self) -> String {
+        let mut out = format!("{{\n  \"version\": {FORMAT_VERSION},\n  \"values\": {{");
+        for (index, (key, value)) in self.values.iter().enumerate() {
+            out.push_str(if index == 0 { "\n" } else { ",\n" });
+            out.push_str(&format!("    \"{}\": \"{}\"", json_escape(key), json_escape(value)));
+        }
+        out.push_str(if self.values.is_empty() { "}\n}\n" } else { "\n  }\n}\n" });
+        out
+    }
+
+    /// Copy the old `name<TAB>value` files into their namespaces.
+    fn import_legacy(&mut self, layout: &DiscoveryLayout) {
+        let legacy = [
+            (layout.root.join("route_state.txt"), ROUTING_OFFSETS),
+            (layout.root.join("heartbeat_state.txt"), HEARTBEAT_LAST_SEQ),
+            (layout.root.join("challenge_state.txt"), ATTESTATION_NONCE),
+        ];
+        for (path, namespace) in legacy {
+            let Ok(text) = fs::read_to_string(&path) else {
+                continue;
+            };
+            for (name, value) in text.lines().filter_map(|line| line.rsplit_once('\t')) {
+                self.set_in(namespace, name, value.trim());
+            }
+            self.imported.push(path);
+            self.dirty = true;
+        }
+    }
+}
+
+/// `<namespace>.<name>`.
+fn namespaced(namespace: &str, name: &str) -> String {
+    format!("{namespace}.{name}")
+}
+
+/// Rename a damaged document to `<name>.corrupt-<unix millis>` and return the new path.
+fn set_aside(path: &Path) -> io::Result<PathBuf> {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis()).unwrap_or(0);
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".corrupt-{millis}"));
+    let kept = path.with_file_name(name);
+    fs::rename(path, &kept)?;
+    Ok(kept)
+}
+
+/// Read `{"version": 1, "values": {"key": "value", ...}}`. Other top-level fields are skipped if
+/// they are strings or numbers, so a later version can add some without older hubs giving up.
+fn parse_document(text: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut reader = Reader { bytes: text.as_bytes(), pos: 0 };
+    let mut values = None;
+    reader.expect(b'{')?;
+    if !reader.eat(b'}') {
+        loop {
+            let field = reader.string()?;
+            reader.expect(b':')?;
+            match field.as_str() {
+                "version" => {
+                    let version = reader.number()?;
+                    if version > FORMAT_VERSION {
+                        return Err(format!("written by a newer hub (version {version})"));
+                    }
+                }
+                "values" => values = Some(reader.string_object()?),
+                _ if reader.peek() == Some(b'"') => {
+                    reader.string()?;
+                }
+                _ => {
+                    reader.number()?;
+                }
+            }
+            if reader.eat(b'}') {
+                break;
+            }
+            reader.expect(b',')?;
+        }
+    }
+    reader.skip_whitespace();
+    if reader.pos != reader.bytes.len() {
+        return Err(format!("unexpected text after the document at byte {}", reader.pos));
+    }
+    values.ok_or_else(|| "no \"values\" object".to_string())
+}
+
+/// Just enough of a JSON reader for `hub_state.json`: objects, strings, and whole numbers.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).copied()
+    }
+
+    /// Step over `byte` if it comes next.
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}' at byte {}", byte as char, self.pos))
+        }
+    }
+
+    fn number(&mut self) -> Result<u64, String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| format!("expected a whole number at byte {start}"))
+    }
+
+    /// `{"key": "value", ...}` with string values only.
+    fn string_object(&mut self) -> Result<BTreeMap<String, String>, String> {
+        let mut object = BTreeMap::new();
+        self.expect(b'{')?;
+        if self.eat(b'}') {
+            return Ok(object);
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(b':')?;
+            let value = self.string()?;
+            object.insert(key, value);
+            if self.eat(b'}') {
+                return Ok(object);
+            }
+            self.expect(b',')?;
+        }
+    }
+
+    /// A quoted string with the escapes `json_escape` writes (and the other standard ones).
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            let Some(&byte) = self.bytes.get(self.pos) else {
+                return Err("unterminated string".to_string());
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => return String::from_utf8(out).map_err(|_| "string is not UTF-8".to_string()),
+                b'\\' => {
+                    let escaped = self.bytes.get(self.pos).copied().ok_or("unterminated escape")?;
+                    self.pos += 1;
+                    let ch = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        other => return Err(format!("unknown escape '\\{}' at byte {}", other as char, self.pos - 1)),
+                    };
+                    let mut buffer = [0u8; 4];
+                    out.extend_from_slice(ch.encode_utf8(&mut buffer).as_bytes());
+                }
+                byte if byte < 0x20 => return Err(format!("control character in a string at byte {}", self.pos - 1)),
+                byte => out.push(byte),
+            }
+        }
+    }
+
+    /// The four hex digits after `\u`, joined with a following low surrogate when needed.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let first = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&first) {
+            if self.bytes.get(self.pos..self.pos + 2) != Some(b"\\u") {
+                return Err("unpaired surrogate".to_string());
+            }
+            self.pos += 2;
+            let second = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&second) {
+                return Err("unpaired surrogate".to_string());
+            }
+            0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00)
+        } else {
+            first
+        };
+        char::from_u32(code).ok_or_else(|| format!("invalid \\u escape at byte {}", self.pos))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .bytes
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| format!("bad \\u escape at byte {}", self.pos))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ecosystem folder with an empty `Discovery/`.
+    fn root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("ecosystem-hub-state-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(DiscoveryLayout::of(&root).root).unwrap();
+        root
+    }
+
+    fn discovery_files(root: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(DiscoveryLayout::of(root).root)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn values_survive_a_hub_restart() {
+        let root = root("restart");
+        let mut state = HubState::load(&root);
+        assert!(!state.is_dirty(), "a fresh hub with nothing to import writes nothing");
+        state.set(REGISTRY_LAST_HASH, "9f86d081");
+        state.set_in(HEARTBEAT_LAST_SEQ, "ecosystem/Discovery/bard", "17");
+        state.set_in(ROUTING_OFFSETS, "tab\tand \"quotes\" \u{1F600}", "2:5120");
+        state.flush().unwrap();
+        assert!(!state.is_dirty());
+
+        let reloaded = HubState::load(&root);
+        assert_eq!(reloaded.iter().collect::<Vec<_>>(), state.iter().collect::<Vec<_>>());
+        assert_eq!(reloaded.get_in(ROUTING_OFFSETS, "tab\tand \"quotes\" \u{1F600}"), Some("2:5120"));
+        assert_eq!(reloaded.to_json(), fs::read_to_string(DiscoveryLayout::of(&root).hub_state_file()).unwrap());
+        assert_eq!(HubState::read_only(&root).unwrap().get(REGISTRY_LAST_HASH), Some("9f86d081"));
+
+        // Setting the same value, or removing a missing key, leaves nothing to write.
+        let mut state = reloaded;
+        state.set(REGISTRY_LAST_HASH, "9f86d081");
+        assert_eq!(state.remove("registry.unknown"), None);
+        assert!(!state.is_dirty());
+        assert_eq!(state.remove(REGISTRY_LAST_HASH).as_deref(), Some("9f86d081"));
+        assert!(state.is_dirty());
+    }
+
+    #[test]
+    fn namespaces_only_list_their_own_keys() {
+        let mut state = HubState::in_memory();
+        state.set_in(ROUTING_OFFSETS, "squire", "1");
+        state.set_in(ROUTING_OFFSETS, "bard", "2");
+        state.set("routing.offsets_old.squire", "stale");
+        state.set("routing.offset", "no dot");
+        state.set_in(HEARTBEAT_LAST_SEQ, "squire", "9");
+        state.set(REGISTRY_LAST_HASH, "abc");
+
+        assert_eq!(state.entries_in(ROUTING_OFFSETS).collect::<Vec<_>>(), [("bard", "2"), ("squire", "1")]);
+        assert_eq!(state.entries_in(HEARTBEAT_LAST_SEQ).collect::<Vec<_>>(), [("squire", "9")]);
+        assert_eq!(state.entries_in(ATTESTATION_NONCE).count(), 0);
+        assert_eq!(state.get_in(HEARTBEAT_LAST_SEQ, "bard"), None);
+        // An in-memory state has nowhere to write.
+        state.flush().unwrap();
+        assert!(state.is_dirty());
+    }
+
+    #[test]
+    fn a_damaged_document_is_set_aside_and_the_hub_starts_fresh() {
+        let root = root("corrupt");
+        let path = DiscoveryLayout::of(&root).hub_state_file();
+        fs::write(&path, "{\"version\": 1, \"values\": {\"registry.last_hash\": ").unwrap();
+        assert!(HubState::read_only(&root).unwrap_err().contains("is unreadable"));
+        assert!(path.exists(), "dump-state changes nothing");
+
+        let mut state = HubState::load(&root);
+        assert_eq!(state.iter().count(), 0);
+        assert!(state.is_dirty(), "the fresh state replaces the damaged file on the next flush");
+        let kept: Vec<String> = discovery_files(&root).into_iter().filter(|name| name.starts_with("hub_state.json.corrupt-")).collect();
+        assert_eq!(kept.len(), 1);
+        let kept_path = DiscoveryLayout::of(&root).root.join(&kept[0]);
+        assert_eq!(fs::read_to_string(&kept_path).unwrap(), "{\"version\": 1, \"values\": {\"registry.last_hash\": ");
+        let log = fs::read_to_string(DiscoveryLayout::of(&root).hub_log()).unwrap();
+        assert!(log.contains("Hub state was unreadable; starting fresh"), "{log}");
+        assert!(log.contains(&kept[0]), "{log}");
+
+        state.flush().unwrap();
+        assert_eq!(HubState::read_only(&root).unwrap().iter().count(), 0);
+        assert!(kept_path.exists(), "the damaged copy is kept for inspection");
+    }
+
+    #[test]
+    fn documents_are_checked_before_they_are_trusted() {
+        let values = parse_document("{\"version\": 1, \"written_by\": \"hub 2.1\", \"written_at\": 5, \"values\": {\"a\": \"\\u00e9\\ud83d\\ude00\\n\"}}").unwrap();
+        assert_eq!(values.get("a").map(String::as_str), Some("\u{e9}\u{1F600}\n"));
+        assert_eq!(parse_document("{\"version\": 1, \"values\": {}}\n").unwrap().len(), 0);
+
+        assert_eq!(parse_document("{\"version\": 2, \"values\": {}}").unwrap_err(), "written by a newer hub (version 2)");
+        assert_eq!(parse_document("{\"version\": 1}").unwrap_err(), "no \"values\" object");
+        assert!(parse_document("{\"version\": 1, \"values\": {}} extra").unwrap_err().starts_with("unexpected text after the document"));
+        assert!(parse_document("{\"values\": {\"a\": 5}}").is_err(), "values must be strings");
+        assert_eq!(parse_document("{\"values\": {\"a\": \"\\ud83d\"}}").unwrap_err(), "unpaired surrogate");
+        assert_eq!(parse_document("{\"values\": {\"a\": \"open}}").unwrap_err(), "unterminated string");
+        assert!(parse_document("{\"values\": {\"a\": \"\\q\"}}").unwrap_err().starts_with("unknown escape '\\q'"));
+    }
+
+    #[test]
+    fn flushes_replace_the_file_whole_and_leave_nothing_behind() {
+        let root = root("atomic");
+        let path = DiscoveryLayout::of(&root).hub_state_file();
+        let mut state = HubState::load(&root);
+        for n in 0..20 {
+            state.set_in(ROUTING_OFFSETS, "squire", &format!("0:{n}"));
+            state.flush().unwrap();
+            assert_eq!(discovery_files(&root), ["hub_state.json"], "no temporary file is left after a flush");
+            assert_eq!(parse_document(&fs::read_to_string(&path).unwrap()).unwrap().len(), 1);
+        }
+
+        // A quiet cycle does not rewrite the file: a change made behind the hub's back survives.
+        fs::write(&path, "{\"version\": 1, \"values\": {}}").unwrap();
+        state.flush().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"version\": 1, \"values\": {}}");
+    }
+
+    #[test]
+    fn the_old_state_files_are_imported_once_and_removed_after_the_first_flush() {
+        let root = root("legacy");
+        let discovery = DiscoveryLayout::of(&root).root;
+        fs::write(discovery.join("route_state.txt"), "ecosystem/Discovery/bard\t2:5120\nnot a pair\n").unwrap();
+        fs::write(discovery.join("heartbeat_state.txt"), "ecosystem/Discovery/bard\t17 \n").unwrap();
+
+        let mut state = HubState::load(&root);
+        assert!(state.is_dirty());
+        assert_eq!(state.get_in(ROUTING_OFFSETS, "ecosystem/Discovery/bard"), Some("2:5120"));
+        assert_eq!(state.get_in(HEARTBEAT_LAST_SEQ, "ecosystem/Discovery/bard"), Some("17"));
+        assert_eq!(state.iter().count(), 2);
+        assert!(discovery.join("route_state.txt").exists(), "kept until the values are safe in the new file");
+
+        state.flush().unwrap();
+        assert_eq!(discovery_files(&root), ["hub_state.json"]);
+        assert_eq!(HubState::load(&root).get_in(ROUTING_OFFSETS, "ecosystem/Discovery/bard"), Some("2:5120"));
+    }
+}
@@ -0,0 +1,26 @@
+//! Ecosystem hub library.
+//!
+//! The hub's real work lives in `comm`: discovery, presence markers, the entity registry, and
+//! message routing between bot queues. The `ecosystem-hub` binary in `src/main.rs` schedules
+//! those steps; keeping them in a library lets other tools (and future tests) call them directly.
+//! `log`, `lockfile`, `atomic`, `dotenv`, `self_verify`, `queue_file`, `runtime`, `snowflake`, and
+//! `watchdog` are re-exported from the shared `ecosystem-common` crate (`ecosystem/common`).
+//! `log` is the logger Squire and Sentry use too, `lockfile` is the
+//! advisory lock (shared with Squire) that keeps concurrent writers from mixing lines, and
+//! `atomic` replaces whole files (markers, registry, hub state) so a crash never leaves half of one.
+//! `hub_state` is the hub's key-value memory between cycles and restarts (`Discovery/hub_state.json`).
+//! `dotenv` (also shared with Squire and Sentry) loads a `.env` file into the environment at startup.
+//! `self_verify` (shared the same way) checks the running binary against a Sentry manifest when
+//! `SQUIRE_MANIFEST` is set. `queue_file` (also shared with Squire) caps line length, rotates, and
+//! drains the `Discovery/` queue files so they cannot grow without end. `runtime` (shared with Squire and
+//! Sentry) puts the clock, sleeping, and environment variables behind traits, so the presence and
+//! heartbeat checks can run against a fake clock and environment. `snowflake` (shared with Squire) parses
+//! Discord ids, reads the creation time inside them, and makes local ids with the same layout.
+//! `doctor` checks that the hub, the bots, and Sentry are wired together (`ecosystem-hub doctor`).
+//! `watchdog` (shared with Squire and Sentry) pings systemd's `WatchdogSec=` through `NOTIFY_SOCKET`.
+
+pub mod comm;
+pub mod doctor;
+pub mod hub_state;
+
+pub use ecosystem_common::{atomic, dotenv, lockfile, log, queue_file, runtime, self_verify, sha256, snowflake, watchdog};
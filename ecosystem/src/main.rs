@@ -1,29 +1,486 @@
-//! Ecosystem hub placeholder binary.
-//! This crate keeps the same beginner-friendly tone as the handwritten `rust/central_comm.rs`
-//! file while giving Cargo a target to build during offline workspace builds.
+//! Ecosystem hub daemon.
+//!
+//! Every cycle the hub re-runs discovery, refreshes presence markers when needed, checks each
+//! bot's `Discovery/heartbeat.txt`, rewrites the registry, routes queued messages, and appends a
+//! heartbeat line of its own to `Discovery/hub_queue.log`.
+//! `--once` runs a single cycle for cron jobs and quick checks. Creating the stop file (by
+//! default `Discovery/hub.stop`) asks a running hub to finish its current cycle and exit; on the
+//! way out it revokes every presence marker it wrote, so gateways stop talking to each other.
+//! What the hub remembers between cycles (routing cursors and the like) is loaded from
+//! `Discovery/hub_state.json` at startup and saved at the end of every cycle and on the way out;
+//! `dump-state` prints it.
+//! Under systemd (`NOTIFY_SOCKET` set) the hub sends `READY=1` once it has loaded its state,
+//! `WATCHDOG=1` at the top of every cycle, and `STOPPING=1` on the way out (see `watchdog`).
 
+use std::collections::BTreeSet;
+use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use ecosystem_hub::comm::{self, DiscoveryLayout};
+use ecosystem_hub::doctor::{self, DoctorContext};
+use ecosystem_hub::dotenv;
+use ecosystem_hub::hub_state::HubState;
+use ecosystem_hub::log::Level;
+use ecosystem_hub::runtime::Runtime;
+use ecosystem_hub::self_verify;
+use ecosystem_hub::watchdog::Watchdog;
+
+/// Seconds between cycles unless `--interval-seconds` says otherwise.
+const DEFAULT_INTERVAL_SECONDS: u64 = 60;
+/// Stop file inside the hub's `Discovery/` folder, used when `--stop-file` is not given.
+const DEFAULT_STOP_FILE_NAME: &str = "hub.stop";
+/// How often the sleep between cycles checks for the stop file.
+const STOP_POLL: Duration = Duration::from_millis(500);
+
+const USAGE: &str = "usage: ecosystem-hub [--root <dir>] [--interval-seconds <n>] [--once] [--max-cycles <n>] [--stop-file <path>]
+       ecosystem-hub derive-key <entity path relative to the ecosystem's parent>
+       ecosystem-hub doctor [--root <dir>] [--format text|json]
+       ecosystem-hub dump-state [--root <dir>]";
+
+/// Parsed command line.
+struct Options {
+    root: PathBuf,
+    interval: Duration,
+    /// `Some(n)` stops after n cycles; `--once` is `Some(1)`.
+    max_cycles: Option<u64>,
+    stop_file: PathBuf,
+}
 
 fn main() {
-    // The hub is intentionally simple here: it only prepares the on-disk layout that other
-    // modules expect and prints guidance for readers following along. When you expand the hub
-    // you can replace these steps with the real discovery and presence-writing logic from the
-    // handwritten Rust stubs.
-    let working_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-    let discovery_path = working_dir.join("Discovery");
+    // Before anything reads `ECOSYSTEM_PRESENCE_KEY` and friends.
+    dotenv::load_default_dotenv();
+    // With `SQUIRE_MANIFEST` set, a binary that differs from the manifest exits here (code 7).
+    self_verify::enforce_at_startup();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // `derive-key <entity>` prints the key a bot should put in its own ECOSYSTEM_PRESENCE_KEY.
+    if args.first().map(String::as_str) == Some("derive-key") {
+        let Some(name) = args.get(1) else {
+            eprintln!("{USAGE}");
+            process::exit(1);
+        };
+        match comm::derive_key_hex(name) {
+            Ok(key) => println!("{key}"),
+            Err(err) => {
+                eprintln!("{err}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // `doctor` checks the wiring between the hub, the bots, and Sentry, then exits.
+    if args.first().map(String::as_str) == Some("doctor") {
+        match run_doctor(&args[1..]) {
+            Ok(code) => process::exit(code),
+            Err(err) => {
+                eprintln!("{err}\n{USAGE}");
+                process::exit(1);
+            }
+        }
+    }
+
+    // `dump-state` prints the saved hub state for debugging, without touching it.
+    if args.first().map(String::as_str) == Some("dump-state") {
+        match run_dump_state(&args[1..]) {
+            Ok(()) => return,
+            Err(err) => {
+                eprintln!("{err}\n{USAGE}");
+                process::exit(1);
+            }
+        }
+    }
+
+    let options = match parse_options(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            process::exit(1);
+        }
+    };
+    run(&options, Runtime::system());
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    // Use the current working directory by default so operators can run the hub from any
+    // level; normally this is the `ecosystem/` folder.
+    let current_dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let mut root = current_dir.clone();
+    let mut interval_seconds = DEFAULT_INTERVAL_SECONDS;
+    let mut max_cycles = None;
+    let mut stop_file = None;
+
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        let mut value = |name: &str| iter.next().cloned().ok_or_else(|| format!("{name} needs a value"));
+        match flag.as_str() {
+            "--root" => root = PathBuf::from(value("--root")?),
+            "--interval-seconds" => {
+                let raw = value("--interval-seconds")?;
+                interval_seconds = raw
+                    .parse()
+                    .ok()
+                    .filter(|seconds| *seconds > 0)
+                    .ok_or_else(|| format!("--interval-seconds expects a positive whole number, got {raw:?}"))?;
+            }
+            "--once" => max_cycles = Some(1),
+            "--max-cycles" => {
+                let raw = value("--max-cycles")?;
+                let cycles = raw
+                    .parse()
+                    .ok()
+                    .filter(|cycles| *cycles > 0)
+                    .ok_or_else(|| format!("--max-cycles expects a positive whole number, got {raw:?}"))?;
+                max_cycles = Some(cycles);
+            }
+            "--stop-file" => stop_file = Some(PathBuf::from(value("--stop-file")?)),
+            other => return Err(format!("Unknown argument {other:?}")),
+        }
+    }
+
+    // Make the root absolute once, so every path the comm helpers derive from it stays correct
+    // no matter which directory the hub was started from.
+    let root = if root.is_absolute() { root } else { current_dir.join(root) };
+    let stop_file = stop_file.unwrap_or_else(|| DiscoveryLayout::of(&root).root.join(DEFAULT_STOP_FILE_NAME));
+    Ok(Options { root, interval: Duration::from_secs(interval_seconds), max_cycles, stop_file })
+}
+
+/// `doctor [--root <dir>] [--format text|json]`: run every check and return the exit code.
+fn run_doctor(args: &[String]) -> Result<i32, String> {
+    let current_dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let mut root = current_dir.clone();
+    let mut json = false;
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        let mut value = |name: &str| iter.next().cloned().ok_or_else(|| format!("{name} needs a value"));
+        match flag.as_str() {
+            "--root" => root = PathBuf::from(value("--root")?),
+            "--format" => {
+                json = match value("--format")?.as_str() {
+                    "text" => false,
+                    "json" => true,
+                    other => return Err(format!("--format must be text or json, got {other:?}")),
+                }
+            }
+            other => return Err(format!("Unknown argument {other:?}")),
+        }
+    }
+    let root = if root.is_absolute() { root } else { current_dir.join(root) };
+
+    let mut ctx = DoctorContext::discover(&root, Runtime::system());
+    ctx.dotenv_path = dotenv::default_dotenv_path();
+    let results = doctor::run_checks(&ctx);
+    if json {
+        println!("{}", doctor::render_json(&results));
+    } else {
+        print!("{}", doctor::render_text(&results));
+    }
+    Ok(doctor::overall(&results).exit_code())
+}
+
+/// `dump-state [--root <dir>]`: print `Discovery/hub_state.json` as the hub would write it.
+fn run_dump_state(args: &[String]) -> Result<(), String> {
+    let current_dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let mut root = current_dir.clone();
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--root" => root = PathBuf::from(iter.next().ok_or("--root needs a value")?),
+            other => return Err(format!("Unknown argument {other:?}")),
+        }
+    }
+    let root = if root.is_absolute() { root } else { current_dir.join(root) };
+    print!("{}", HubState::read_only(&root)?.to_json());
+    Ok(())
+}
+
+/// Run cycles until `max_cycles` is reached or the stop file appears. Time, sleeping between
+/// cycles, and the presence key come from `rt`.
+fn run(options: &Options, rt: Runtime<'_>) {
+    let root = &options.root;
+    let mut state = HubState::load(root);
+    let ttl = Duration::from_secs(comm::presence_ttl_secs_from(rt.env));
+    // What we last announced to, and when. Markers are only rewritten when the set of entities
+    // changes or the last announcement is close to expiring, so quiet cycles touch nothing.
+    let mut announced: Option<(BTreeSet<PathBuf>, Instant)> = None;
+    // The entities of the last cycle, revoked when the hub is told to stop.
+    let mut known: Vec<comm::EntityInfo> = Vec::new();
+    let mut cycle = 0u64;
+    let mut stopped_by_file = false;
+    let watchdog = Watchdog::from_env(rt.env);
+    notify(root, watchdog.ready());
+
+    loop {
+        if stop_requested(options) {
+            stopped_by_file = true;
+            break;
+        }
+        cycle += 1;
+        // A cycle that hangs stops these, and systemd restarts the hub after `WatchdogSec=`.
+        notify(root, watchdog.ping());
+
+        let (hub, scan) = comm::discover(root);
+        if cycle == 1 {
+            comm::clean_leftover_temps(root, &scan.entities);
+        }
+        let current: BTreeSet<PathBuf> = scan.entities.iter().map(|entity| entity.path.clone()).collect();
+        let needs_announce = match &announced {
+            None => true,
+            Some((previous, at)) => *previous != current || near_expiry(rt.clock.instant().saturating_duration_since(*at), ttl, options.interval),
+        };
+
+        // An entity that dropped out of discovery (its descriptor was removed, say) but whose
+        // folder is still there gets a revocation instead of a marker that quietly expires.
+        for gone in known.iter().filter(|entity| !current.contains(&entity.path)) {
+            comm::revoke_presence_with(root, std::slice::from_ref(gone), rt);
+        }
+        known = scan.entities.clone();
+
+        if needs_announce {
+            // Descriptor warnings only matter when something changed, so they are not repeated
+            // every quiet cycle.
+            comm::log_discovery(root, &scan);
+            if scan.entities.is_empty() {
+                comm::append_hub_log(
+                    root,
+                    Level::Warn,
+                    "No bots discovered. Place bots or ecosystems beside this folder or inside Discovery/ so the hub can enroll them.",
+                    &[],
+                );
+            } else {
+                comm::announce_presence_with(root, &hub, &scan.entities, rt);
+            }
+            announced = Some((current, rt.clock.instant()));
+        }
+        let heartbeats = comm::check_heartbeats_with(root, &scan.entities, &mut state, rt);
+        comm::log_heartbeats(root, &heartbeats);
+        let attestations = comm::check_attestations_with(root, &scan.entities, &mut state, rt);
+        comm::log_attestations(root, &attestations);
+        comm::write_registry(root, &hub, &scan.entities, &heartbeats, &attestations, &mut state);
+        let routed = comm::route_messages(root, &scan.entities, &mut state);
+        flush_state(root, &mut state);
+
+        comm::append_hub_log(
+            root,
+            Level::Info,
+            "heartbeat",
+            &[
+                ("cycle", &cycle.to_string()),
+                ("entities", &scan.entities.len().to_string()),
+                ("announced", if needs_announce { "yes" } else { "no" }),
+                ("delivered", &routed.delivered.to_string()),
+                ("dead_lettered", &routed.dead_lettered.to_string()),
+            ],
+        );
+
+        if options.max_cycles.is_some_and(|max| cycle >= max) {
+            break;
+        }
+        if sleep_until_next_cycle(options, rt) {
+            stopped_by_file = true;
+            break;
+        }
+    }
+
+    notify(root, watchdog.stopping());
+    // `--once` and `--max-cycles` runs are meant to be repeated (from cron, for example), so only
+    // a stop request withdraws the markers.
+    if stopped_by_file {
+        comm::revoke_presence_with(root, &known, rt);
+    }
+    // Every cycle already flushed; this catches a flush that failed last time.
+    flush_state(root, &mut state);
+
+    comm::append_hub_log(root, Level::Info, "Hub stopped", &[("cycles", &cycle.to_string())]);
+}
 
-    if let Err(error) = fs::create_dir_all(&discovery_path) {
-        eprintln!("Failed to create Discovery directory: {error}");
-        std::process::exit(1);
+/// Save the hub state, logging (not stopping on) a failure; the next flush tries again.
+fn flush_state(root: &Path, state: &mut HubState) {
+    if let Err(err) = state.flush() {
+        comm::append_hub_log(root, Level::Warn, "Could not save hub state", &[("error", &err.to_string())]);
     }
+}
+
+/// Log (not stop on) a notification systemd did not get; it restarts the hub if pings stay away.
+fn notify(root: &Path, sent: std::io::Result<()>) {
+    if let Err(err) = sent {
+        comm::append_hub_log(root, Level::Warn, "Could not notify systemd", &[("error", &err.to_string())]);
+    }
+}
+
+/// True when the next cycle might come too late to refresh markers before gateways call them
+/// stale. Two intervals of headroom covers one slow cycle.
+fn near_expiry(elapsed: Duration, ttl: Duration, interval: Duration) -> bool {
+    elapsed + interval * 2 >= ttl
+}
+
+/// Sleep for one interval in short steps. Returns true if the stop file appeared meanwhile.
+fn sleep_until_next_cycle(options: &Options, rt: Runtime<'_>) -> bool {
+    let deadline = rt.clock.instant() + options.interval;
+    while rt.clock.instant() < deadline {
+        if stop_requested(options) {
+            return true;
+        }
+        rt.sleeper.sleep(STOP_POLL.min(deadline.saturating_duration_since(rt.clock.instant())));
+    }
+    false
+}
+
+/// Check for the stop file and remove it so the next start is not stopped straight away.
+fn stop_requested(options: &Options) -> bool {
+    if options.stop_file.exists() {
+        let _ = fs::remove_file(&options.stop_file);
+        comm::append_hub_log(
+            &options.root,
+            Level::Info,
+            "Stop file found",
+            &[("path", &options.stop_file.display().to_string())],
+        );
+        return true;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ecosystem_hub::runtime::{ManualClock, MapEnv};
+
+    const MASTER_HEX: &str = "0707070707070707070707070707070707070707070707070707070707070707";
+
+    /// `<base>/ecosystem` with its own `Discovery/`, and the bots `squire` and `bard` beside it.
+    fn layout(name: &str) -> PathBuf {
+        let base = env::temp_dir().join(format!("ecosystem-hub-main-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&base);
+        for entity in ["ecosystem", "squire", "bard"] {
+            fs::create_dir_all(DiscoveryLayout::of(&base.join(entity)).root).unwrap();
+        }
+        base
+    }
+
+    fn options(base: &Path, args: &[&str]) -> Options {
+        let root = base.join("ecosystem");
+        let mut words = vec!["--root".to_string(), root.display().to_string()];
+        words.extend(args.iter().map(|arg| arg.to_string()));
+        parse_options(&words).unwrap()
+    }
+
+    fn hub_log(base: &Path) -> String {
+        fs::read_to_string(DiscoveryLayout::of(&base.join("ecosystem")).hub_log()).unwrap_or_default()
+    }
+
+    #[test]
+    fn once_writes_signed_markers_and_one_heartbeat() {
+        let base = layout("once");
+        let clock = ManualClock::new(1_700_000_000_000);
+        let env = MapEnv::new().with("ECOSYSTEM_PRESENCE_KEY", MASTER_HEX);
+        run(&options(&base, &["--once"]), Runtime { clock: &clock, sleeper: &clock, env: &env });
+
+        for bot in ["squire", "bard"] {
+            let marker = fs::read_to_string(DiscoveryLayout::of(&base.join(bot)).presence_file()).unwrap();
+            assert!(marker.contains("nonce=") && marker.contains("1700000000000"), "{marker}");
+            assert!(!marker.contains("signature=missing-"), "{marker}");
+        }
+        let log = hub_log(&base);
+        assert_eq!(log.matches("heartbeat").count(), 1, "{log}");
+        assert!(log.contains("cycle=1") && log.contains("announced=yes"), "{log}");
+        assert!(log.contains("Hub stopped") && log.contains("cycles=1"), "{log}");
+        assert!(clock.slept().is_empty());
+    }
+
+    #[test]
+    fn max_cycles_sleeps_one_interval_between_cycles_and_exits() {
+        let base = layout("max-cycles");
+        let clock = ManualClock::new(1_700_000_000_000);
+        let env = MapEnv::new().with("ECOSYSTEM_PRESENCE_KEY", MASTER_HEX);
+        run(&options(&base, &["--max-cycles", "2", "--interval-seconds", "5"]), Runtime { clock: &clock, sleeper: &clock, env: &env });
 
-    let marker_path = discovery_path.join("ecosystem_presence.txt");
-    if let Err(error) = fs::write(&marker_path, "ecosystem hub staged by Cargo build\n") {
-        eprintln!("Failed to write presence marker: {error}");
-        std::process::exit(1);
+        assert_eq!(clock.slept().iter().sum::<Duration>(), Duration::from_secs(5));
+        assert!(clock.slept().iter().all(|step| *step <= STOP_POLL));
+        let log = hub_log(&base);
+        assert!(log.contains("cycle=2"), "{log}");
+        // Nothing changed and the markers are far from expiry, so the second cycle left them alone.
+        assert_eq!(log.matches("announced=no").count(), 1, "{log}");
+        assert!(log.contains("cycles=2"), "{log}");
+        // Only a stop file withdraws the markers.
+        let marker = fs::read_to_string(DiscoveryLayout::of(&base.join("squire")).presence_file()).unwrap();
+        assert!(!marker.contains("revoked"), "{marker}");
     }
 
-    println!("Ecosystem hub stub ready. Discovery marker placed at {:?}", marker_path);
-    println!("Replace this stub with the full central coordinator when wiring real Discord flows.");
+    #[test]
+    fn messages_are_routed_inside_the_loop() {
+        let base = layout("routing");
+        fs::write(DiscoveryLayout::of(&base.join("squire")).dispatch_file(), "to=bard|from=squire|body=hello\n").unwrap();
+        let clock = ManualClock::new(1_700_000_000_000);
+        let env = MapEnv::new().with("ECOSYSTEM_PRESENCE_KEY", MASTER_HEX);
+        run(&options(&base, &["--once"]), Runtime { clock: &clock, sleeper: &clock, env: &env });
+
+        let inbox = fs::read_to_string(DiscoveryLayout::of(&base.join("bard")).inbox_file()).unwrap();
+        assert_eq!(inbox, "to=bard|from=squire|body=hello\n");
+        assert!(hub_log(&base).contains("delivered=1"));
+    }
+
+    #[test]
+    fn a_stop_file_ends_the_run_and_is_removed() {
+        let base = layout("stop-file");
+        let options = options(&base, &["--interval-seconds", "5"]);
+        fs::write(&options.stop_file, "").unwrap();
+        let clock = ManualClock::new(1_700_000_000_000);
+        let env = MapEnv::new().with("ECOSYSTEM_PRESENCE_KEY", MASTER_HEX);
+        run(&options, Runtime { clock: &clock, sleeper: &clock, env: &env });
+
+        assert!(!options.stop_file.exists());
+        let log = hub_log(&base);
+        assert!(log.contains("Stop file found") && log.contains("cycles=0"), "{log}");
+    }
+
+    #[test]
+    fn options_need_positive_numbers_and_known_flags() {
+        let base = Path::new("/srv/ecosystem");
+        let parse = |words: &[&str]| parse_options(&words.iter().map(|word| word.to_string()).collect::<Vec<_>>());
+        let parsed = parse(&["--root", "/srv/ecosystem", "--once"]).unwrap();
+        assert_eq!(parsed.max_cycles, Some(1));
+        assert_eq!(parsed.interval, Duration::from_secs(DEFAULT_INTERVAL_SECONDS));
+        assert_eq!(parsed.stop_file, DiscoveryLayout::of(base).root.join(DEFAULT_STOP_FILE_NAME));
+
+        assert!(parse(&["--interval-seconds", "0"]).err().unwrap().contains("positive whole number"));
+        assert!(parse(&["--max-cycles", "two"]).err().unwrap().contains("positive whole number"));
+        assert_eq!(parse(&["--root"]).err().unwrap(), "--root needs a value");
+        assert_eq!(parse(&["--forever"]).err().unwrap(), "Unknown argument \"--forever\"");
+    }
+
+    #[test]
+    fn markers_are_refreshed_two_intervals_before_they_expire() {
+        let (ttl, interval) = (Duration::from_secs(900), Duration::from_secs(60));
+        assert!(!near_expiry(Duration::from_secs(779), ttl, interval));
+        assert!(near_expiry(Duration::from_secs(780), ttl, interval));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn systemd_hears_ready_a_ping_per_cycle_and_stopping() {
+        let base = layout("watchdog");
+        let path = base.join("notify.sock");
+        let socket = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let clock = ManualClock::new(1_700_000_000_000);
+        let env = MapEnv::new().with("ECOSYSTEM_PRESENCE_KEY", MASTER_HEX).with("NOTIFY_SOCKET", &path.display().to_string());
+        run(&options(&base, &["--max-cycles", "3", "--interval-seconds", "5"]), Runtime { clock: &clock, sleeper: &clock, env: &env });
+
+        let mut buffer = [0u8; 64];
+        let mut sent = Vec::new();
+        while let Ok(length) = socket.recv(&mut buffer) {
+            sent.push(String::from_utf8_lossy(&buffer[..length]).into_owned());
+        }
+        assert_eq!(sent, ["READY=1", "WATCHDOG=1", "WATCHDOG=1", "WATCHDOG=1", "STOPPING=1"]);
+
+        // A socket nobody listens on is logged, and the hub carries on.
+        drop(socket);
+        run(&options(&base, &["--once"]), Runtime { clock: &clock, sleeper: &clock, env: &env });
+        let log = hub_log(&base);
+        assert!(log.contains("Could not notify systemd") && log.contains("Hub stopped"), "{log}");
+    }
 }